@@ -47,7 +47,7 @@
 };
 use pageserver_api::shard::{DEFAULT_STRIPE_SIZE, ShardCount, ShardStripeSize, TenantShardId};
 use postgres_backend::AuthType;
-use safekeeper_api::membership::{SafekeeperGeneration, SafekeeperId};
+use safekeeper_api::membership::{SafekeeperGeneration, SafekeeperId, SafekeeperRole};
 use safekeeper_api::{
     DEFAULT_HTTP_LISTEN_PORT as DEFAULT_SAFEKEEPER_HTTP_PORT,
     DEFAULT_PG_LISTEN_PORT as DEFAULT_SAFEKEEPER_PG_PORT, PgMajorVersion, PgVersionId,
@@ -1253,6 +1253,7 @@ async fn handle_timeline(cmd: &TimelineCmd, env: &mut local_env::LocalEnv) -> Re
                             host: default_host,
                             id: default_sk.conf.id,
                             pg_port: default_sk.conf.pg_port,
+                            role: SafekeeperRole::Full,
                         }],
                     },
                     new_members: None,
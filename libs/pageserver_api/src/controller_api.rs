@@ -549,6 +549,9 @@ pub struct SafekeeperDescribeResponse {
     pub https_port: Option<i32>,
     pub availability_zone_id: String,
     pub scheduling_policy: SkSchedulingPolicy,
+    /// Witness safekeepers vote but don't store WAL.
+    #[serde(default)]
+    pub is_witness: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
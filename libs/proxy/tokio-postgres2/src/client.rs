@@ -18,7 +18,7 @@
 use crate::connection::gc_bytesmut;
 use crate::query::RowStream;
 use crate::simple_query::SimpleQueryStream;
-use crate::types::{Oid, Type};
+use crate::types::{Format, Oid, Type};
 use crate::{
     CancelToken, Error, ReadyForQueryStatus, SimpleQueryMessage, Transaction, TransactionBuilder,
     query, simple_query,
@@ -235,6 +235,25 @@ pub async fn query_raw_txt<S, I>(
         statement: &str,
         params: I,
     ) -> Result<RowStream<'_>, Error>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = Option<S>>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.query_raw_typed_txt(statement, &[], params, Format::Text)
+            .await
+    }
+
+    /// Like `query_raw_txt`, but parameters are parsed as the declared types
+    /// and rows are returned in `output_format`. Types not declared, or
+    /// declared with OID 0, are inferred by the backend.
+    pub async fn query_raw_typed_txt<S, I>(
+        &mut self,
+        statement: &str,
+        param_types: &[Oid],
+        params: I,
+        output_format: Format,
+    ) -> Result<RowStream<'_>, Error>
     where
         S: AsRef<str>,
         I: IntoIterator<Item = Option<S>>,
@@ -244,7 +263,9 @@ pub async fn query_raw_txt<S, I>(
             &mut self.inner,
             &mut self.cached_typeinfo,
             statement,
+            param_types,
             params,
+            output_format,
         )
         .await
     }
@@ -1,6 +1,7 @@
 #![allow(async_fn_in_trait)]
 
 use crate::query::RowStream;
+use crate::types::{Format, Oid};
 use crate::{Client, Error, Transaction};
 
 mod private {
@@ -21,6 +22,19 @@ async fn query_raw_txt<S, I>(
         S: AsRef<str> + Sync + Send,
         I: IntoIterator<Item = Option<S>> + Sync + Send,
         I::IntoIter: ExactSizeIterator + Sync + Send;
+
+    /// Like `Client::query_raw_typed_txt`.
+    async fn query_raw_typed_txt<S, I>(
+        &mut self,
+        statement: &str,
+        param_types: &[Oid],
+        params: I,
+        output_format: Format,
+    ) -> Result<RowStream<'_>, Error>
+    where
+        S: AsRef<str> + Sync + Send,
+        I: IntoIterator<Item = Option<S>> + Sync + Send,
+        I::IntoIter: ExactSizeIterator + Sync + Send;
 }
 
 impl private::Sealed for Client {}
@@ -38,6 +52,22 @@ async fn query_raw_txt<S, I>(
     {
         self.query_raw_txt(statement, params).await
     }
+
+    async fn query_raw_typed_txt<S, I>(
+        &mut self,
+        statement: &str,
+        param_types: &[Oid],
+        params: I,
+        output_format: Format,
+    ) -> Result<RowStream<'_>, Error>
+    where
+        S: AsRef<str> + Sync + Send,
+        I: IntoIterator<Item = Option<S>> + Sync + Send,
+        I::IntoIter: ExactSizeIterator + Sync + Send,
+    {
+        self.query_raw_typed_txt(statement, param_types, params, output_format)
+            .await
+    }
 }
 
 impl private::Sealed for Transaction<'_> {}
@@ -55,4 +85,20 @@ async fn query_raw_txt<S, I>(
     {
         self.query_raw_txt(statement, params).await
     }
+
+    async fn query_raw_typed_txt<S, I>(
+        &mut self,
+        statement: &str,
+        param_types: &[Oid],
+        params: I,
+        output_format: Format,
+    ) -> Result<RowStream<'_>, Error>
+    where
+        S: AsRef<str> + Sync + Send,
+        I: IntoIterator<Item = Option<S>> + Sync + Send,
+        I::IntoIter: ExactSizeIterator + Sync + Send,
+    {
+        self.query_raw_typed_txt(statement, param_types, params, output_format)
+            .await
+    }
 }
@@ -5,7 +5,7 @@
 use futures_util::{Stream, ready};
 use postgres_protocol2::message::backend::Message;
 use postgres_protocol2::message::frontend;
-use postgres_types2::Format;
+use postgres_types2::{Format, Oid};
 
 use crate::client::{CachedTypeInfo, InnerClient, Responses};
 use crate::{Error, ReadyForQueryStatus, Row, Statement};
@@ -14,7 +14,9 @@ pub async fn query_txt<'a, S, I>(
     client: &'a mut InnerClient,
     typecache: &mut CachedTypeInfo,
     query: &str,
+    param_types: &[Oid],
     params: I,
+    output_format: Format,
 ) -> Result<RowStream<'a>, Error>
 where
     S: AsRef<str>,
@@ -39,9 +41,9 @@ pub async fn query_txt<'a, S, I>(
     // parse the query and get type info
     let responses = client.send_with_flush(|buf| {
         frontend::parse(
-            "",                 // unnamed prepared statement
-            query,              // query to parse
-            std::iter::empty(), // give no type info
+            "",                          // unnamed prepared statement
+            query,                       // query to parse
+            param_types.iter().copied(), // types the client declared, if any
             buf,
         )
         .map_err(Error::encode)?;
@@ -69,7 +71,7 @@ pub async fn query_txt<'a, S, I>(
         crate::prepare::parse_row_description(&mut client, typecache, row_description).await?;
 
     let responses = client.send_with_sync(|buf| {
-        // Bind, pass params as text, retrieve as text
+        // Bind, pass params as text, retrieve in the requested format
         match frontend::bind(
             "",                 // empty string selects the unnamed portal
             "",                 // unnamed prepared statement
@@ -82,7 +84,10 @@ pub async fn query_txt<'a, S, I>(
                 }
                 None => Ok(postgres_protocol2::IsNull::Yes),
             },
-            Some(0), // all text
+            Some(match output_format {
+                Format::Text => 0,
+                Format::Binary => 1,
+            }), // same format for all columns
             buf,
         ) {
             Ok(()) => Ok(()),
@@ -106,7 +111,7 @@ pub async fn query_txt<'a, S, I>(
         statement: Statement::new("", columns),
         command_tag: None,
         status: ReadyForQueryStatus::Unknown,
-        output_format: Format::Text,
+        output_format,
     })
 }
 
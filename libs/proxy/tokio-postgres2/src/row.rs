@@ -212,6 +212,17 @@ pub fn as_text(&self, idx: usize) -> Result<Option<&str>, Error> {
         }
     }
 
+    /// Get the raw binary representation of the column at the given index
+    ///
+    /// Useful when using query_raw_typed_txt() with binary output format
+    pub fn as_binary(&self, idx: usize) -> Result<Option<&[u8]>, Error> {
+        if self.output_format == Format::Binary {
+            Ok(self.col_buffer(idx))
+        } else {
+            Err(Error::from_sql(Box::new(WrongFormat {}), idx))
+        }
+    }
+
     /// Row byte size
     pub fn body_len(&self) -> usize {
         self.body.buffer().len()
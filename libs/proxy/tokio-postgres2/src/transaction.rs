@@ -1,4 +1,5 @@
 use crate::query::RowStream;
+use crate::types::{Format, Oid};
 use crate::{CancelToken, Client, Error, ReadyForQueryStatus};
 
 /// A representation of a PostgreSQL database transaction.
@@ -42,6 +43,12 @@ pub async fn rollback(mut self) -> Result<ReadyForQueryStatus, Error> {
         self.client.batch_execute("ROLLBACK").await
     }
 
+    /// Consumes the transaction leaving it open on the connection. The caller
+    /// is responsible for finishing it with `COMMIT` or `ROLLBACK` queries.
+    pub fn detach(mut self) {
+        self.done = true;
+    }
+
     /// Like `Client::query_raw_txt`.
     pub async fn query_raw_txt<S, I>(
         &mut self,
@@ -56,6 +63,24 @@ pub async fn query_raw_txt<S, I>(
         self.client.query_raw_txt(statement, params).await
     }
 
+    /// Like `Client::query_raw_typed_txt`.
+    pub async fn query_raw_typed_txt<S, I>(
+        &mut self,
+        statement: &str,
+        param_types: &[Oid],
+        params: I,
+        output_format: Format,
+    ) -> Result<RowStream<'_>, Error>
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = Option<S>>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        self.client
+            .query_raw_typed_txt(statement, param_types, params, output_format)
+            .await
+    }
+
     /// Like `Client::cancel_token`.
     pub fn cancel_token(&self) -> CancelToken {
         self.client.cancel_token()
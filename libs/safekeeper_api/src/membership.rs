@@ -48,6 +48,30 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
+/// Role of a member in the configuration.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafekeeperRole {
+    /// Regular member storing full WAL copy.
+    #[default]
+    Full,
+    /// Member which votes and acknowledges flush positions, but doesn't store
+    /// WAL: it keeps only term history and LSNs of the WAL it has seen. Such
+    /// a member can't serve WAL to computes, pageservers or peers, so a
+    /// quorum must never consist of witnesses only; see
+    /// [`MemberSet::validate_witnesses`].
+    Witness,
+}
+
+impl Display for SafekeeperRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SafekeeperRole::Full => write!(f, "full"),
+            SafekeeperRole::Witness => write!(f, "witness"),
+        }
+    }
+}
+
 /// Membership is defined by ids so e.g. walproposer uses them to figure out
 /// quorums, but we also carry host and port to give wp idea where to connect.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -57,11 +81,24 @@ pub struct SafekeeperId {
     /// We include here only port for computes -- that is, pg protocol tenant
     /// only port, or wide pg protocol port if the former is not configured.
     pub pg_port: u16,
+    /// Absent in configurations created before witnesses were introduced.
+    #[serde(default)]
+    pub role: SafekeeperRole,
+}
+
+impl SafekeeperId {
+    pub fn is_witness(&self) -> bool {
+        self.role == SafekeeperRole::Witness
+    }
 }
 
 impl Display for SafekeeperId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[id={}, ep={}:{}]", self.id, self.host, self.pg_port)
+        write!(f, "[id={}, ep={}:{}", self.id, self.host, self.pg_port)?;
+        if self.is_witness() {
+            write!(f, ", witness")?;
+        }
+        write!(f, "]")
     }
 }
 
@@ -89,6 +126,39 @@ pub fn contains(&self, sk: NodeId) -> bool {
         self.m.iter().any(|m| m.id == sk)
     }
 
+    /// Get member by id.
+    pub fn get(&self, sk: NodeId) -> Option<&SafekeeperId> {
+        self.m.iter().find(|m| m.id == sk)
+    }
+
+    /// Number of members required to form a quorum.
+    pub fn quorum(&self) -> usize {
+        self.m.len() / 2 + 1
+    }
+
+    /// Number of witness members.
+    pub fn witnesses_count(&self) -> usize {
+        self.m.iter().filter(|m| m.is_witness()).count()
+    }
+
+    /// Check that any quorum of the set includes at least one full member,
+    /// otherwise committed WAL might end up not being stored anywhere. E.g.
+    /// two full members with one witness is fine, but one full member with two
+    /// witnesses is not.
+    pub fn validate_witnesses(&self) -> anyhow::Result<()> {
+        let witnesses = self.witnesses_count();
+        if witnesses >= self.quorum() {
+            bail!(
+                "{} witnesses in the set {} of {} members, must be less than quorum size {}",
+                witnesses,
+                self,
+                self.m.len(),
+                self.quorum()
+            );
+        }
+        Ok(())
+    }
+
     pub fn add(&mut self, sk: SafekeeperId) -> anyhow::Result<()> {
         if self.contains(sk.id) {
             bail!(format!(
@@ -143,6 +213,76 @@ pub fn new(members: MemberSet) -> Self {
     pub fn contains(&self, sk_id: NodeId) -> bool {
         self.members.contains(sk_id) || self.new_members.as_ref().is_some_and(|m| m.contains(sk_id))
     }
+
+    /// Role of `sk_id` in the configuration, None if it is not a member.
+    pub fn role(&self, sk_id: NodeId) -> Option<SafekeeperRole> {
+        self.members
+            .get(sk_id)
+            .or_else(|| self.new_members.as_ref().and_then(|m| m.get(sk_id)))
+            .map(|m| m.role)
+    }
+
+    /// Is `sk_id` a witness member of the configuration?
+    pub fn is_witness(&self, sk_id: NodeId) -> bool {
+        self.role(sk_id) == Some(SafekeeperRole::Witness)
+    }
+
+    /// Does the configuration have witness members at all?
+    pub fn has_witnesses(&self) -> bool {
+        self.members.witnesses_count() > 0
+            || self
+                .new_members
+                .as_ref()
+                .is_some_and(|m| m.witnesses_count() > 0)
+    }
+
+    /// Check that witnesses in both member sets can't form a quorum on their
+    /// own and that no safekeeper has different roles in the two sets.
+    pub fn validate_witnesses(&self) -> anyhow::Result<()> {
+        self.members.validate_witnesses()?;
+        if let Some(new_members) = &self.new_members {
+            new_members.validate_witnesses()?;
+            for sk in &new_members.m {
+                if let Some(old) = self.members.get(sk.id) {
+                    if old.role != sk.role {
+                        bail!(
+                            "sk {} has role {} in members, but {} in new_members",
+                            sk.id,
+                            old.role,
+                            sk.role
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that switching to `to` doesn't change role of any safekeeper
+    /// which is a member of both configurations. Witness doesn't have WAL, so
+    /// it can't become a full member in place; and the other way around is
+    /// not supported for simplicity. Such a safekeeper should be removed and
+    /// added back instead.
+    pub fn validate_role_change(&self, to: &Configuration) -> anyhow::Result<()> {
+        let members = self
+            .members
+            .m
+            .iter()
+            .chain(self.new_members.iter().flat_map(|m| &m.m));
+        for sk in members {
+            if let Some(new_role) = to.role(sk.id) {
+                if new_role != sk.role {
+                    bail!(
+                        "sk {} can't change role from {} to {} in place",
+                        sk.id,
+                        sk.role,
+                        new_role
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Display for Configuration {
@@ -164,7 +304,16 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 mod tests {
     use utils::id::NodeId;
 
-    use super::{MemberSet, SafekeeperId};
+    use super::{Configuration, MemberSet, SafekeeperId, SafekeeperRole};
+
+    fn sk(id: u64, role: SafekeeperRole) -> SafekeeperId {
+        SafekeeperId {
+            id: NodeId(id),
+            host: format!("sk-{id}.org"),
+            pg_port: 5432,
+            role,
+        }
+    }
 
     #[test]
     fn test_member_set() {
@@ -174,6 +323,7 @@ fn test_member_set() {
                 id: NodeId(42),
                 host: String::from("lala.org"),
                 pg_port: 5432,
+                role: SafekeeperRole::Full,
             })
             .unwrap();
 
@@ -182,6 +332,7 @@ fn test_member_set() {
                 id: NodeId(42),
                 host: String::from("lala.org"),
                 pg_port: 5432,
+                role: SafekeeperRole::Full,
             })
             .expect_err("duplicate must not be allowed");
 
@@ -190,6 +341,7 @@ fn test_member_set() {
                 id: NodeId(43),
                 host: String::from("bubu.org"),
                 pg_port: 5432,
+                role: SafekeeperRole::Witness,
             })
             .unwrap();
 
@@ -199,7 +351,48 @@ fn test_member_set() {
         println!("members json: {j}");
         assert_eq!(
             j,
-            r#"[{"id":42,"host":"lala.org","pg_port":5432},{"id":43,"host":"bubu.org","pg_port":5432}]"#
+            r#"[{"id":42,"host":"lala.org","pg_port":5432,"role":"full"},{"id":43,"host":"bubu.org","pg_port":5432,"role":"witness"}]"#
         );
+
+        // Configurations stored before roles were introduced have full members only.
+        let legacy: MemberSet =
+            serde_json::from_str(r#"[{"id":42,"host":"lala.org","pg_port":5432}]"#).unwrap();
+        assert_eq!(legacy.m[0].role, SafekeeperRole::Full);
+    }
+
+    #[test]
+    fn test_witnesses_validation() {
+        use SafekeeperRole::*;
+
+        let ok = MemberSet::new(vec![sk(1, Full), sk(2, Full), sk(3, Witness)]).unwrap();
+        ok.validate_witnesses().unwrap();
+
+        let too_many = MemberSet::new(vec![sk(1, Full), sk(2, Witness), sk(3, Witness)]).unwrap();
+        too_many
+            .validate_witnesses()
+            .expect_err("witnesses must not form a quorum");
+
+        let mut conf = Configuration::new(ok.clone());
+        conf.validate_witnesses().unwrap();
+        assert!(conf.has_witnesses());
+        assert!(conf.is_witness(NodeId(3)));
+        assert!(!conf.is_witness(NodeId(2)));
+        assert_eq!(conf.role(NodeId(4)), None);
+
+        // The same safekeeper can't be full in one set and witness in another.
+        conf.new_members =
+            Some(MemberSet::new(vec![sk(1, Full), sk(2, Witness), sk(4, Full)]).unwrap());
+        conf.validate_witnesses()
+            .expect_err("role mismatch in joint conf must be rejected");
+
+        // Role can't be changed in place, but a new witness can be added.
+        let from = Configuration::new(ok);
+        let mut to = from.clone();
+        to.generation = from.generation.next();
+        to.members = MemberSet::new(vec![sk(1, Witness), sk(2, Full), sk(4, Full)]).unwrap();
+        from.validate_role_change(&to)
+            .expect_err("role change must be rejected");
+        to.members = MemberSet::new(vec![sk(1, Full), sk(2, Full), sk(4, Witness)]).unwrap();
+        from.validate_role_change(&to).unwrap();
     }
 }
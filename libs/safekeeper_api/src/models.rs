@@ -317,6 +317,39 @@ pub struct PullTimelineResponse {
     // TODO: add more fields?
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullTimelineStatus {
+    InProgress,
+    Finished,
+    /// Pull gave up; data verified so far is kept and the next pull from the
+    /// same donor continues from it.
+    Failed,
+}
+
+/// Progress of pulling timeline from a peer, reported by the receiving
+/// safekeeper.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullTimelineProgress {
+    pub tenant_id: TenantId,
+    pub timeline_id: TimelineId,
+    /// Donor safekeeper host.
+    pub safekeeper_host: String,
+    pub status: PullTimelineStatus,
+    /// Number of snapshot requests made so far; each next one resumes from
+    /// the last verified segment.
+    pub attempts: u32,
+    /// Number of WAL segments in the donor snapshot.
+    pub segments_total: u64,
+    /// Number of WAL segments received and verified, including ones kept from
+    /// previous attempts.
+    pub segments_done: u64,
+    /// Bytes of WAL received over all attempts.
+    pub bytes_received: u64,
+    /// Error of the last failed attempt.
+    pub last_error: Option<String>,
+}
+
 /// Response to a timeline locate request.
 /// Storcon-only API.
 #[derive(Serialize, Deserialize, Clone, Debug)]
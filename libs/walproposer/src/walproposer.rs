@@ -198,6 +198,8 @@ pub struct Config {
     /// walproposer mode, finish when all safekeepers are synced or subscribe
     /// to WAL streaming
     pub sync_safekeepers: bool,
+    /// Version of compute <-> safekeeper protocol
+    pub proto_version: u32,
 }
 
 /// WalProposer main struct. C methods are reexported as Rust functions.
@@ -237,7 +239,9 @@ pub fn new(api: Box<dyn ApiImpl>, config: Config) -> Wrapper {
             syncSafekeepers: config.sync_safekeepers,
             systemId: 0,
             pgTimeline: 1,
-            proto_version: 3,
+            proto_version: config.proto_version as i32,
+            commit_mode: 0,
+            shard_count: 1,
             callback_data,
         };
         let c_config = Box::into_raw(Box::new(c_config));
@@ -599,6 +603,7 @@ fn test_simple_sync_safekeepers() -> anyhow::Result<()> {
             safekeeper_reconnect_timeout: 1000,
             safekeeper_connection_timeout: 10000,
             sync_safekeepers: true,
+            proto_version: 3,
         };
 
         let wp = Wrapper::new(my_impl, config);
@@ -180,10 +180,12 @@ WalProposerCreate(WalProposerConfig *config, walproposer_api api)
 	}
 	wp->quorum = wp->n_safekeepers / 2 + 1;
 
-	if (wp->config->proto_version != 2 && wp->config->proto_version != 3)
+	if (wp->config->proto_version < 2 || wp->config->proto_version > 5)
 		wp_log(FATAL, "unsupported safekeeper protocol version %d", wp->config->proto_version);
 	if (wp->safekeepers_generation > INVALID_GENERATION && wp->config->proto_version < 3)
 		wp_log(FATAL, "enabling generations requires protocol version 3");
+	if (wp->config->commit_mode != WP_COMMIT_MODE_FLUSH && wp->config->proto_version < 5)
+		wp_log(FATAL, "commit mode other than flush requires protocol version 5");
 	wp_log(LOG, "using safekeeper protocol version %d", wp->config->proto_version);
 	
 	/* BEGIN_HADRON */
@@ -201,6 +203,10 @@ WalProposerCreate(WalProposerConfig *config, walproposer_api api)
 	wp->greetRequest.pg_version = PG_VERSION_NUM;
 	wp->greetRequest.system_id = wp->config->systemId;
 	wp->greetRequest.wal_seg_size = wp->config->wal_segment_size;
+	/* sync-safekeepers has no backends waiting for commit */
+	wp->greetRequest.commit_mode = wp->config->syncSafekeepers ?
+		WP_COMMIT_MODE_FLUSH : wp->config->commit_mode;
+	wp->greetRequest.shard_count = Max(1, wp->config->shard_count);
 
 	wp->api.init_event_set(wp);
 
@@ -828,6 +834,26 @@ ResetMemberSafekeeperPtrs(WalProposer *wp)
 	}
 }
 
+/*
+ * Is sk a witness in current mconf? Witness can't serve WAL, so must never be
+ * used as a donor.
+ */
+static bool
+SafekeeperIsWitness(WalProposer *wp, Safekeeper *sk)
+{
+	for (uint32 i = 0; i < wp->mconf.members.len; i++)
+	{
+		if (wp->members_safekeepers[i] == sk)
+			return wp->mconf.members.m[i].is_witness;
+	}
+	for (uint32 i = 0; i < wp->mconf.new_members.len; i++)
+	{
+		if (wp->new_members_safekeepers[i] == sk)
+			return wp->mconf.new_members.m[i].is_witness;
+	}
+	return false;
+}
+
 static uint32
 MsetQuorum(MemberSet *mset)
 {
@@ -937,6 +963,11 @@ RecvAcceptorGreeting(Safekeeper *sk)
 		   sk->host, sk->port, sk->greetResponse.nodeId, mconf_toml, sk->greetResponse.term);
 	pfree(mconf_toml);
 
+	/* Witness safekeepers follow flush mode instead of remote_apply. */
+	if (sk->greetResponse.commitMode != wp->greetRequest.commit_mode)
+		wp_log(LOG, "safekeeper %s:%s uses commit mode %d instead of requested %d",
+			   sk->host, sk->port, sk->greetResponse.commitMode, wp->greetRequest.commit_mode);
+
 	/*
 	 * Adopt mconf of safekeepers if it is higher.
 	 */
@@ -1130,13 +1161,25 @@ VotesCollectedMset(WalProposer *wp, MemberSet *mset, Safekeeper **msk, StringInf
 		{
 			Assert(sk->voteResponse.voteGiven);
 
+			if (mset->m[i].is_witness)
+			{
+				/* witness has no WAL, only remember its position */
+				if (GetLastLogTerm(sk) > wp->witnessLastLogTerm ||
+					(GetLastLogTerm(sk) == wp->witnessLastLogTerm &&
+					 sk->voteResponse.flushLsn > wp->witnessFlushLsn))
+				{
+					wp->witnessLastLogTerm = GetLastLogTerm(sk);
+					wp->witnessFlushLsn = sk->voteResponse.flushLsn;
+				}
+			}
+
 			/*
 			 * Find the highest vote. NULL check is for the legacy case where
 			 * safekeeper might be not initialized with LSN at all and return
 			 * 0 LSN in the vote response; we still want to set donor to
 			 * something in this case.
 			 */
-			if (GetLastLogTerm(sk) > wp->donorLastLogTerm ||
+			else if (GetLastLogTerm(sk) > wp->donorLastLogTerm ||
 				(GetLastLogTerm(sk) == wp->donorLastLogTerm &&
 				 sk->voteResponse.flushLsn > wp->propTermStartLsn) ||
 				wp->donor == NULL)
@@ -1149,7 +1192,8 @@ VotesCollectedMset(WalProposer *wp, MemberSet *mset, Safekeeper **msk, StringInf
 
 			if (n_votes > 0)
 				appendStringInfoString(s, ", ");
-			appendStringInfo(s, "{id = %lu, ep = %s:%s}", sk->greetResponse.nodeId, sk->host, sk->port);
+			appendStringInfo(s, "{id = %lu, ep = %s:%s%s}", sk->greetResponse.nodeId, sk->host, sk->port,
+							 mset->m[i].is_witness ? ", witness" : "");
 			n_votes++;
 		}
 	}
@@ -1174,6 +1218,9 @@ VotesCollected(WalProposer *wp)
 
 	wp->propTermStartLsn = InvalidXLogRecPtr;
 	wp->donorLastLogTerm = 0;
+	wp->donor = NULL;
+	wp->witnessLastLogTerm = 0;
+	wp->witnessFlushLsn = InvalidXLogRecPtr;
 	wp->truncateLsn = InvalidXLogRecPtr;
 
 	/* legacy: generations disabled */
@@ -1226,6 +1273,22 @@ VotesCollected(WalProposer *wp)
 		if (!VotesCollectedMset(wp, &wp->mconf.new_members, wp->new_members_safekeepers, &s))
 			goto res;
 	}
+
+	/*
+	 * WAL which is only on a witness among the voters might be committed
+	 * (witness and some absent full member could form the quorum), but we
+	 * can't fetch it from the witness. Wait for more full members then.
+	 */
+	if (wp->donor == NULL ||
+		wp->witnessLastLogTerm > wp->donorLastLogTerm ||
+		(wp->witnessLastLogTerm == wp->donorLastLogTerm &&
+		 wp->witnessFlushLsn > wp->propTermStartLsn))
+	{
+		wp_log(LOG, "quorum collected, but witness position (%lu, %X/%X) is ahead of all voted full members (%lu, %X/%X), waiting for more votes; %s",
+			   wp->witnessLastLogTerm, LSN_FORMAT_ARGS(wp->witnessFlushLsn),
+			   wp->donorLastLogTerm, LSN_FORMAT_ARGS(wp->propTermStartLsn), s.data);
+		goto res;
+	}
 	wp_log(LOG, "walproposer elected, %s", s.data);
 	collected = true;
 
@@ -2073,7 +2136,8 @@ UpdateDonorShmem(WalProposer *wp)
 	{
 		Safekeeper *sk = &wp->safekeeper[i];
 
-		if (sk->state == SS_ACTIVE && sk->appendResponse.flushLsn > donor_lsn)
+		if (sk->state == SS_ACTIVE && sk->appendResponse.flushLsn > donor_lsn &&
+			!SafekeeperIsWitness(wp, sk))
 		{
 			donor = sk;
 			donor_lsn = sk->appendResponse.flushLsn;
@@ -2175,33 +2239,33 @@ HandleSafekeeperResponse(WalProposer *wp, Safekeeper *fromsk)
 	}
 }
 
-/* Serialize MembershipConfiguration into buf. */
+/* Serialize MemberSet into buf. Since v4 each member is followed by role. */
 static void
-MembershipConfigurationSerialize(MembershipConfiguration *mconf, StringInfo buf)
+MemberSetSerialize(MemberSet *mset, StringInfo buf, int proto_version)
 {
-	uint32		i;
-
-	pq_sendint32(buf, mconf->generation);
-
-	pq_sendint32(buf, mconf->members.len);
-	for (i = 0; i < mconf->members.len; i++)
+	pq_sendint32(buf, mset->len);
+	for (uint32 i = 0; i < mset->len; i++)
 	{
-		pq_sendint64(buf, mconf->members.m[i].node_id);
-		pq_send_ascii_string(buf, mconf->members.m[i].host);
-		pq_sendint16(buf, mconf->members.m[i].port);
+		pq_sendint64(buf, mset->m[i].node_id);
+		pq_send_ascii_string(buf, mset->m[i].host);
+		pq_sendint16(buf, mset->m[i].port);
+		if (proto_version >= 4)
+			pq_sendint8(buf, mset->m[i].is_witness ? 1 : 0);
 	}
+}
+
+/* Serialize MembershipConfiguration into buf. */
+static void
+MembershipConfigurationSerialize(MembershipConfiguration *mconf, StringInfo buf, int proto_version)
+{
+	pq_sendint32(buf, mconf->generation);
+	MemberSetSerialize(&mconf->members, buf, proto_version);
 
 	/*
 	 * There is no special mark for absent new_members; zero members in
 	 * invalid, so zero len means absent.
 	 */
-	pq_sendint32(buf, mconf->new_members.len);
-	for (i = 0; i < mconf->new_members.len; i++)
-	{
-		pq_sendint64(buf, mconf->new_members.m[i].node_id);
-		pq_send_ascii_string(buf, mconf->new_members.m[i].host);
-		pq_sendint16(buf, mconf->new_members.m[i].port);
-	}
+	MemberSetSerialize(&mconf->new_members, buf, proto_version);
 }
 
 /* Serialize proposer -> acceptor message into buf using specified version */
@@ -2209,11 +2273,11 @@ static void
 PAMessageSerialize(WalProposer *wp, ProposerAcceptorMessage *msg, StringInfo buf, int proto_version)
 {
 	/* both version are supported currently until we fully migrate to 3 */
-	Assert(proto_version == 3 || proto_version == 2);
+	Assert(proto_version >= 2 && proto_version <= 5);
 
 	resetStringInfo(buf);
 
-	if (proto_version == 3)
+	if (proto_version >= 3)
 	{
 		/*
 		 * v2 sends structs for some messages as is, so commonly send tag only
@@ -2229,10 +2293,15 @@ PAMessageSerialize(WalProposer *wp, ProposerAcceptorMessage *msg, StringInfo buf
 
 					pq_send_ascii_string(buf, m->tenant_id);
 					pq_send_ascii_string(buf, m->timeline_id);
-					MembershipConfigurationSerialize(&m->mconf, buf);
+					MembershipConfigurationSerialize(&m->mconf, buf, proto_version);
 					pq_sendint32(buf, m->pg_version);
 					pq_sendint64(buf, m->system_id);
 					pq_sendint32(buf, m->wal_seg_size);
+					if (proto_version >= 5)
+					{
+						pq_sendint8(buf, m->commit_mode);
+						pq_sendint8(buf, m->shard_count);
+					}
 					break;
 				}
 			case 'v':
@@ -2412,35 +2481,32 @@ AsyncRead(Safekeeper *sk, char **buf, int *buf_size)
 	return false;
 }
 
-/* Deserialize membership configuration from buf to mconf. */
+/* Deserialize MemberSet from buf to mset. */
 static void
-MembershipConfigurationDeserialize(MembershipConfiguration *mconf, StringInfo buf)
+MemberSetDeserialize(MemberSet *mset, StringInfo buf, int proto_version)
 {
-	uint32		i;
-
-	mconf->generation = pq_getmsgint32(buf);
-	mconf->members.len = pq_getmsgint32(buf);
-	mconf->members.m = palloc0(sizeof(SafekeeperId) * mconf->members.len);
-	for (i = 0; i < mconf->members.len; i++)
+	mset->len = pq_getmsgint32(buf);
+	mset->m = palloc0(sizeof(SafekeeperId) * mset->len);
+	for (uint32 i = 0; i < mset->len; i++)
 	{
 		const char *buf_host;
 
-		mconf->members.m[i].node_id = pq_getmsgint64(buf);
+		mset->m[i].node_id = pq_getmsgint64(buf);
 		buf_host = pq_getmsgrawstring(buf);
-		strlcpy(mconf->members.m[i].host, buf_host, sizeof(mconf->members.m[i].host));
-		mconf->members.m[i].port = pq_getmsgint16(buf);
+		strlcpy(mset->m[i].host, buf_host, sizeof(mset->m[i].host));
+		mset->m[i].port = pq_getmsgint16(buf);
+		if (proto_version >= 4)
+			mset->m[i].is_witness = pq_getmsgbyte(buf) == 1;
 	}
-	mconf->new_members.len = pq_getmsgint32(buf);
-	mconf->new_members.m = palloc0(sizeof(SafekeeperId) * mconf->new_members.len);
-	for (i = 0; i < mconf->new_members.len; i++)
-	{
-		const char *buf_host;
+}
 
-		mconf->new_members.m[i].node_id = pq_getmsgint64(buf);
-		buf_host = pq_getmsgrawstring(buf);
-		strlcpy(mconf->new_members.m[i].host, buf_host, sizeof(mconf->new_members.m[i].host));
-		mconf->new_members.m[i].port = pq_getmsgint16(buf);
-	}
+/* Deserialize membership configuration from buf to mconf. */
+static void
+MembershipConfigurationDeserialize(MembershipConfiguration *mconf, StringInfo buf, int proto_version)
+{
+	mconf->generation = pq_getmsgint32(buf);
+	MemberSetDeserialize(&mconf->members, buf, proto_version);
+	MemberSetDeserialize(&mconf->new_members, buf, proto_version);
 }
 
 /*
@@ -2474,7 +2540,7 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 	s.maxlen = buf_size;
 	s.cursor = 0;
 
-	if (wp->config->proto_version == 3)
+	if (wp->config->proto_version >= 3)
 	{
 		tag = pq_getmsgbyte(&s);
 		if (tag != anymsg->tag)
@@ -2491,8 +2557,12 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 					AcceptorGreeting *msg = (AcceptorGreeting *) anymsg;
 
 					msg->nodeId = pq_getmsgint64(&s);
-					MembershipConfigurationDeserialize(&msg->mconf, &s);
+					MembershipConfigurationDeserialize(&msg->mconf, &s, wp->config->proto_version);
 					msg->term = pq_getmsgint64(&s);
+					if (wp->config->proto_version >= 5)
+						msg->commitMode = pq_getmsgbyte(&s);
+					else
+						msg->commitMode = WP_COMMIT_MODE_FLUSH;
 					pq_getmsgend(&s);
 					return true;
 				}
@@ -2523,6 +2593,10 @@ AsyncReadMessage(Safekeeper *sk, AcceptorProposerMessage *anymsg)
 					msg->term = pq_getmsgint64(&s);
 					msg->flushLsn = pq_getmsgint64(&s);
 					msg->commitLsn = pq_getmsgint64(&s);
+					if (wp->config->proto_version >= 5)
+						msg->applyLsn = pq_getmsgint64(&s);
+					else
+						msg->applyLsn = InvalidXLogRecPtr;
 					msg->hs.ts = pq_getmsgint64(&s);
 					msg->hs.xmin.value = pq_getmsgint64(&s);
 					msg->hs.catalog_xmin.value = pq_getmsgint64(&s);
@@ -3001,7 +3075,8 @@ MembershipConfigurationToString(MembershipConfiguration *mconf)
 			appendStringInfoString(&s, ", ");
 		appendStringInfo(&s, "{node_id = %lu", mconf->members.m[i].node_id);
 		appendStringInfo(&s, ", host = %s", mconf->members.m[i].host);
-		appendStringInfo(&s, ", port = %u }", mconf->members.m[i].port);
+		appendStringInfo(&s, ", port = %u", mconf->members.m[i].port);
+		appendStringInfo(&s, ", witness = %s }", mconf->members.m[i].is_witness ? "true" : "false");
 	}
 	appendStringInfo(&s, "], new_members = [");
 	for (i = 0; i < mconf->new_members.len; i++)
@@ -3010,7 +3085,8 @@ MembershipConfigurationToString(MembershipConfiguration *mconf)
 			appendStringInfoString(&s, ", ");
 		appendStringInfo(&s, "{node_id = %lu", mconf->new_members.m[i].node_id);
 		appendStringInfo(&s, ", host = %s", mconf->new_members.m[i].host);
-		appendStringInfo(&s, ", port = %u }", mconf->new_members.m[i].port);
+		appendStringInfo(&s, ", port = %u", mconf->new_members.m[i].port);
+		appendStringInfo(&s, ", witness = %s }", mconf->new_members.m[i].is_witness ? "true" : "false");
 	}
 	appendStringInfoString(&s, "]}");
 	return s.data;
@@ -152,6 +152,8 @@ typedef struct SafekeeperId
 	NNodeId		node_id;
 	char		host[MAXCONNINFO];
 	uint16		port;
+	/* witness votes and acks WAL but doesn't store it; since proto v4 */
+	bool		is_witness;
 } SafekeeperId;
 
 /* Set of safekeepers. */
@@ -183,6 +185,23 @@ typedef struct ProposerAcceptorMessage
 } ProposerAcceptorMessage;
 
 /* Initial Proposer -> Acceptor message */
+/*
+ * What safekeeper waits for before acknowledging WAL, requested in greeting
+ * since proto v5. Values must match CommitMode in safekeeper.rs.
+ */
+typedef enum
+{
+	/* ack WAL once it is fsynced */
+	WP_COMMIT_MODE_FLUSH = 0,
+	/* ack WAL once it is written, without waiting for fsync */
+	WP_COMMIT_MODE_WRITE = 1,
+	/*
+	 * ack like flush, and report how far pageservers ingested WAL; commits are
+	 * confirmed to backends only after that
+	 */
+	WP_COMMIT_MODE_REMOTE_APPLY = 2,
+} WalProposerCommitMode;
+
 typedef struct ProposerGreeting
 {
 	ProposerAcceptorMessage pam;	/* message tag */
@@ -205,6 +224,8 @@ typedef struct ProposerGreeting
 	uint32		pg_version;		/* in PG_VERSION_NUM format */
 	uint64		system_id;		/* Postgres system identifier. */
 	uint32		wal_seg_size;
+	uint8		commit_mode;	/* WalProposerCommitMode, since proto v5 */
+	uint8		shard_count;	/* number of pageserver shards, since proto v5 */
 } ProposerGreeting;
 
 /* protocol v2 variant, kept while wp supports it */
@@ -236,6 +257,8 @@ typedef struct AcceptorGreeting
 	NNodeId		nodeId;
 	MembershipConfiguration mconf;
 	term_t		term;
+	/* commit mode safekeeper follows, might differ from requested one */
+	uint8		commitMode;
 } AcceptorGreeting;
 
 /*
@@ -463,6 +486,12 @@ typedef struct AppendResponse
 	/* Safekeeper reports back his awareness about which WAL is committed, as */
 	/* this is a criterion for walproposer --sync mode exit */
 	XLogRecPtr	commitLsn;
+
+	/*
+	 * In remote_apply commit mode, position up to which WAL is flushed on the
+	 * safekeeper and ingested by pageservers; 0 otherwise. Since proto v5.
+	 */
+	XLogRecPtr	applyLsn;
 	HotStandbyFeedback hs;
 	/* Feedback received from pageserver includes standby_status_update fields */
 	/* and custom neon feedback. */
@@ -814,6 +843,15 @@ typedef struct WalProposerConfig
 
 	int			proto_version;
 
+	/* Requested WalProposerCommitMode; other than flush needs proto v5. */
+	int			commit_mode;
+
+	/*
+	 * Number of pageserver shards of the tenant. In remote_apply mode
+	 * safekeepers report apply position only once all of them sent feedback.
+	 */
+	int			shard_count;
+
 #ifdef WALPROPOSER_LIB
 	void	   *callback_data;
 #endif
@@ -912,6 +950,14 @@ typedef struct WalProposer
 	/* Most advanced acceptor */
 	Safekeeper *donor;
 
+	/*
+	 * Most advanced <last log term, flush lsn> among voted witnesses. Witness
+	 * can't be a donor as it has no WAL, so elections wait until some full
+	 * member has this position.
+	 */
+	term_t		witnessLastLogTerm;
+	XLogRecPtr	witnessFlushLsn;
+
 	/* timeline globally starts at this LSN */
 	XLogRecPtr	timelineStartLsn;
 
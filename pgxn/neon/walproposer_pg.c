@@ -66,7 +66,15 @@ char	   *wal_acceptors_list = "";
 int			wal_acceptor_reconnect_timeout = 1000;
 int			wal_acceptor_connection_timeout = 10000;
 int			safekeeper_proto_version = 3;
+int			safekeeper_commit_mode = WP_COMMIT_MODE_FLUSH;
 char	   *safekeeper_conninfo_options = "";
+
+static const struct config_enum_entry safekeeper_commit_modes[] = {
+	{"flush", WP_COMMIT_MODE_FLUSH, false},
+	{"write", WP_COMMIT_MODE_WRITE, false},
+	{"remote_apply", WP_COMMIT_MODE_REMOTE_APPLY, false},
+	{NULL, 0, false}
+};
 /* BEGIN_HADRON */
 int         databricks_max_wal_mb_per_second = -1;
 // during throttling, we will limit the effective WAL write rate to 10KB.
@@ -154,6 +162,8 @@ init_walprop_config(bool syncSafekeepers)
 		walprop_config.systemId = 0;
 	walprop_config.pgTimeline = walprop_pg_get_timeline_id();
 	walprop_config.proto_version = safekeeper_proto_version;
+	walprop_config.commit_mode = safekeeper_commit_mode;
+	walprop_config.shard_count = syncSafekeepers ? 1 : get_num_shards();
 }
 
 /*
@@ -266,13 +276,24 @@ nwp_register_gucs(void)
 	DefineCustomIntVariable(
 							"neon.safekeeper_proto_version",
 							"Version of compute <-> safekeeper protocol.",
-							"Used while migrating from 2 to 3.",
+							"Used while migrating from 2 to 3. Version 4 is required for timelines with witness safekeepers.",
 							&safekeeper_proto_version,
 							3, 0, INT_MAX,
 							PGC_POSTMASTER,
 							0,
 							NULL, NULL, NULL);
 
+	DefineCustomEnumVariable(
+							 "neon.safekeeper_commit_mode",
+							 "What safekeepers wait for before acknowledging WAL.",
+							 "flush waits for fsync, write doesn't, remote_apply additionally confirms commits only after pageservers ingested them. Other than flush requires protocol version 5.",
+							 &safekeeper_commit_mode,
+							 WP_COMMIT_MODE_FLUSH,
+							 safekeeper_commit_modes,
+							 PGC_POSTMASTER,
+							 0,
+							 NULL, NULL, NULL);
+
     /* BEGIN_HADRON */
     DefineCustomIntVariable(
                             "databricks.max_wal_mb_per_second",
@@ -2114,6 +2135,27 @@ CombineHotStanbyFeedbacks(HotStandbyFeedback *hs, WalProposer *wp)
 	}
 }
 
+/*
+ * LSN up to which commits can be confirmed to backends. That's commitLsn,
+ * except in remote_apply mode where WAL must also be ingested by pageservers,
+ * which any safekeeper streaming to them reports as applyLsn.
+ */
+static XLogRecPtr
+GetConfirmedLsn(WalProposer *wp)
+{
+	XLogRecPtr	applyLsn = InvalidXLogRecPtr;
+
+	if (wp->config->commit_mode != WP_COMMIT_MODE_REMOTE_APPLY)
+		return wp->commitLsn;
+
+	for (int i = 0; i < wp->n_safekeepers; i++)
+	{
+		if (wp->safekeeper[i].state == SS_ACTIVE)
+			applyLsn = Max(applyLsn, wp->safekeeper[i].appendResponse.applyLsn);
+	}
+	return Min(wp->commitLsn, applyLsn);
+}
+
 /*
  * Based on commitLsn and safekeeper responses including pageserver feedback,
  * 1) Propagate cluster size received from ps to ensure the limit.
@@ -2172,9 +2214,9 @@ walprop_pg_process_safekeeper_feedback(WalProposer *wp, Safekeeper *sk)
 		}
 	}
 
-	if (wp->commitLsn > standby_flush_lsn)
+	if (GetConfirmedLsn(wp) > standby_flush_lsn)
 	{
-		standby_flush_lsn = wp->commitLsn;
+		standby_flush_lsn = GetConfirmedLsn(wp);
 		needToAdvanceSlot = true;
 	}
 
@@ -216,6 +216,7 @@ async fn authenticate(
         NodeInfo {
             conn_info,
             aux: db_info.aux,
+            replica: None,
         },
         auth_info,
         user_info,
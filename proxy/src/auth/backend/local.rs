@@ -44,6 +44,7 @@ pub fn new(postgres_addr: SocketAddr, compute_ctl: ApiUrl) -> Self {
                     compute_id: "local".into(),
                     cold_start_info: ColdStartInfo::WarmCached,
                 },
+                replica: None,
             },
         }
     }
@@ -8,6 +8,7 @@
 
 pub use console_redirect::ConsoleRedirectBackend;
 pub(crate) use console_redirect::ConsoleRedirectError;
+use jwt::FetchAuthRules;
 use local::LocalBackend;
 use postgres_client::config::AuthKeys;
 use serde::{Deserialize, Serialize};
@@ -74,6 +75,10 @@ fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                     .debug_tuple("ControlPlane::ProxyV1")
                     .field(&endpoint.url())
                     .finish(),
+                ControlPlaneClient::File(api) => fmt
+                    .debug_tuple("ControlPlane::File")
+                    .field(&api.path())
+                    .finish(),
                 #[cfg(any(test, feature = "testing"))]
                 ControlPlaneClient::PostgresMock(endpoint) => {
                     let url = endpoint.url();
@@ -198,7 +203,7 @@ fn try_from(user_info: ComputeUserInfoMaybeEndpoint) -> Result<Self, Self::Error
 /// All authentication flows will emit an AuthenticationOk message if successful.
 async fn auth_quirks(
     ctx: &RequestContext,
-    api: &impl control_plane::ControlPlaneApi,
+    api: &(impl control_plane::ControlPlaneApi + FetchAuthRules),
     user_info: ComputeUserInfoMaybeEndpoint,
     client: &mut stream::PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
     allow_cleartext: bool,
@@ -236,6 +241,27 @@ async fn auth_quirks(
         .get_role_access_control(ctx, &info.endpoint, &info.user)
         .await?;
 
+    // A client certificate is only used if the endpoint accepts them, otherwise
+    // we fall back to the other flows.
+    if let Some(cert_auth) = &access_controls.client_cert_auth
+        && let Some(chain) = client.get_ref().client_certificates()
+    {
+        cert_auth.authenticate(chain, &info.user)?;
+        let secret = match &role_access.secret {
+            Some(AuthSecret::Scram(secret)) => Some(secret),
+            None => None,
+        };
+        let keys = auth::compute_keys_without_password(
+            &info.user,
+            secret,
+            role_access.client_key.as_ref(),
+        )?;
+        ctx.set_auth_method(crate::context::AuthMethod::ClientCertificate);
+        client.write_message(BeMessage::AuthenticationOk);
+
+        return Ok(ComputeCredentials { info, keys });
+    }
+
     let secret = if let Some(secret) = role_access.secret {
         secret
     } else {
@@ -248,7 +274,9 @@ async fn auth_quirks(
 
     match authenticate_with_secret(
         ctx,
+        api,
         secret,
+        role_access.client_key,
         info,
         client,
         unauthenticated_password,
@@ -262,9 +290,12 @@ async fn auth_quirks(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn authenticate_with_secret(
     ctx: &RequestContext,
+    fetch: &impl FetchAuthRules,
     secret: AuthSecret,
+    client_key: Option<scram::ScramKey>,
     info: ComputeUserInfo,
     client: &mut stream::PqStream<Stream<impl AsyncRead + AsyncWrite + Unpin>>,
     unauthenticated_password: Option<Vec<u8>>,
@@ -302,7 +333,7 @@ async fn authenticate_with_secret(
     }
 
     // Finally, proceed with the main auth flow (SCRAM-based).
-    classic::authenticate(ctx, info, client, config, secret).await
+    classic::authenticate(ctx, fetch, info, client, config, secret, client_key).await
 }
 
 impl<'a> Backend<'a, ComputeUserInfoMaybeEndpoint> {
@@ -381,7 +412,10 @@ pub(crate) async fn get_role_secret(
                 api.get_role_access_control(ctx, &user_info.endpoint, &user_info.user)
                     .await
             }
-            Self::Local(_) => Ok(RoleAccessControl { secret: None }),
+            Self::Local(_) => Ok(RoleAccessControl {
+                secret: None,
+                client_key: None,
+            }),
         }
     }
 
@@ -399,6 +433,8 @@ pub(crate) async fn get_endpoint_access_control(
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: self.client_cert_auth.clone(),
             }),
         }
     }
@@ -411,7 +447,14 @@ async fn wake_compute(
         ctx: &RequestContext,
     ) -> Result<CachedNodeInfo, control_plane::errors::WakeComputeError> {
         match self {
-            Self::ControlPlane(api, info) => api.wake_compute(ctx, info).await,
+            Self::ControlPlane(api, info) => {
+                let mut node = api.wake_compute(ctx, info).await?;
+                if info.options.is_read_only() {
+                    node.route_read_only();
+                    ctx.set_project(node.aux.clone());
+                }
+                Ok(node)
+            }
             Self::Local(local) => Ok(Cached::new_uncached(local.node_info.clone())),
         }
     }
@@ -427,32 +470,46 @@ mod tests {
     use control_plane::AuthSecret;
     use fallible_iterator::FallibleIterator;
     use once_cell::sync::Lazy;
+    use postgres_client::config::AuthKeys;
     use postgres_protocol::authentication::sasl::{ChannelBinding, ScramSha256};
     use postgres_protocol::message::backend::Message as PgMessage;
     use postgres_protocol::message::frontend;
+    use rustls::crypto::ring;
+    use rustls::pki_types::{PrivateKeyDer, ServerName};
     use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 
-    use super::auth_quirks;
-    use super::jwt::JwkCache;
+    use super::jwt::tests::{jwks_server, new_ec_jwk, new_ec_jwt};
+    use super::jwt::{AuthRule, FetchAuthRules, JwkCache};
+    use super::{ComputeCredentialKeys, ComputeCredentials, auth_quirks};
     use crate::auth::{ComputeUserInfoMaybeEndpoint, IpPattern};
     use crate::cache::node_info::CachedNodeInfo;
     use crate::config::AuthenticationConfig;
     use crate::context::RequestContext;
-    use crate::control_plane::messages::EndpointRateLimitConfig;
+    use crate::control_plane::messages::{
+        ClientCertAuthConfig, ClientCertRoleMapping, EndpointRateLimitConfig,
+    };
     use crate::control_plane::{
         self, AccessBlockerFlags, EndpointAccessControl, RoleAccessControl,
     };
+    use crate::intern::RoleNameInt;
+    use crate::pglb::handshake::{HandshakeData, handshake};
     use crate::proxy::NeonOptions;
     use crate::rate_limiter::EndpointRateLimiter;
-    use crate::scram::ServerSecret;
     use crate::scram::threadpool::ThreadPool;
+    use crate::scram::{ScramKey, ServerSecret};
     use crate::stream::{PqStream, Stream};
+    use crate::tls::client_cert::{ClientCertAuth, DeferredClientCertVerifier};
+    use crate::tls::server_config::{CertResolver, TlsConfig};
 
+    #[derive(Clone)]
     struct Auth {
         ips: Vec<IpPattern>,
         vpc_endpoint_ids: Vec<String>,
         access_blocker_flags: AccessBlockerFlags,
         secret: AuthSecret,
+        client_key: Option<ScramKey>,
+        client_cert_auth: Option<Arc<ClientCertAuth>>,
+        auth_rules: Vec<AuthRule>,
     }
 
     impl control_plane::ControlPlaneApi for Auth {
@@ -464,6 +521,7 @@ async fn get_role_access_control(
         ) -> Result<RoleAccessControl, control_plane::errors::GetAuthInfoError> {
             Ok(RoleAccessControl {
                 secret: Some(self.secret.clone()),
+                client_key: self.client_key.clone(),
             })
         }
 
@@ -478,6 +536,8 @@ async fn get_endpoint_access_control(
                 allowed_vpce: Arc::new(self.vpc_endpoint_ids.clone()),
                 flags: self.access_blocker_flags,
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: None,
             })
         }
 
@@ -499,6 +559,16 @@ async fn wake_compute(
         }
     }
 
+    impl FetchAuthRules for Auth {
+        async fn fetch_auth_rules(
+            &self,
+            _ctx: &RequestContext,
+            _endpoint: crate::types::EndpointId,
+        ) -> Result<Vec<AuthRule>, super::jwt::FetchAuthRulesError> {
+            Ok(self.auth_rules.clone())
+        }
+    }
+
     static CONFIG: Lazy<AuthenticationConfig> = Lazy::new(|| AuthenticationConfig {
         jwks_cache: JwkCache::default(),
         scram_thread_pool: ThreadPool::new(1),
@@ -507,6 +577,19 @@ async fn wake_compute(
         is_vpc_acccess_proxy: false,
         is_auth_broker: false,
         accept_jwts: false,
+        accept_oauthbearer: false,
+        console_redirect_confirmation_timeout: std::time::Duration::from_secs(5),
+    });
+
+    static OAUTHBEARER_CONFIG: Lazy<AuthenticationConfig> = Lazy::new(|| AuthenticationConfig {
+        jwks_cache: JwkCache::default(),
+        scram_thread_pool: ThreadPool::new(1),
+        scram_protocol_timeout: std::time::Duration::from_secs(5),
+        ip_allowlist_check_enabled: true,
+        is_vpc_acccess_proxy: false,
+        is_auth_broker: false,
+        accept_jwts: false,
+        accept_oauthbearer: true,
         console_redirect_confirmation_timeout: std::time::Duration::from_secs(5),
     });
 
@@ -530,6 +613,9 @@ async fn auth_quirks_scram() {
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
+            client_key: None,
+            client_cert_auth: None,
+            auth_rules: vec![],
         };
 
         let user_info = ComputeUserInfoMaybeEndpoint {
@@ -601,6 +687,181 @@ async fn auth_quirks_scram() {
         handle.await.unwrap();
     }
 
+    #[tokio::test]
+    async fn auth_quirks_oauthbearer_invalid_token() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = PqStream::new_skip_handshake(Stream::from_raw(server));
+
+        let ctx = RequestContext::test();
+        let api = Auth {
+            ips: vec![],
+            vpc_endpoint_ids: vec![],
+            access_blocker_flags: AccessBlockerFlags::default(),
+            secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
+            client_key: None,
+            client_cert_auth: None,
+            auth_rules: vec![],
+        };
+
+        let user_info = ComputeUserInfoMaybeEndpoint {
+            user: "conrad".into(),
+            endpoint_id: Some("endpoint".into()),
+            options: NeonOptions::default(),
+        };
+
+        let handle = tokio::spawn(async move {
+            let mut read = BytesMut::new();
+
+            // server should offer oauthbearer alongside scram
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationSasl(a) => {
+                    let options: Vec<&str> = a.mechanisms().collect().unwrap();
+                    assert_eq!(options, ["SCRAM-SHA-256", "OAUTHBEARER"]);
+                }
+                _ => panic!("wrong message"),
+            }
+
+            // client sends a token which is not a jwt
+            let mut write = BytesMut::new();
+            frontend::sasl_initial_response(
+                "OAUTHBEARER",
+                b"n,,\x01auth=Bearer not-a-jwt\x01\x01",
+                &mut write,
+            )
+            .unwrap();
+            client.write_all(&write).await.unwrap();
+
+            // server rejects the token
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationSaslContinue(a) => {
+                    assert_eq!(a.data(), br#"{"status":"invalid_token"}"#);
+                }
+                _ => panic!("wrong message"),
+            }
+
+            // client acknowledges the rejection
+            write.clear();
+            frontend::sasl_response(b"\x01", &mut write).unwrap();
+            client.write_all(&write).await.unwrap();
+        });
+        let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
+            EndpointRateLimiter::DEFAULT,
+            64,
+        ));
+
+        let err = auth_quirks(
+            &ctx,
+            &api,
+            user_info,
+            &mut stream,
+            false,
+            &OAUTHBEARER_CONFIG,
+            endpoint_rate_limiter,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, crate::auth::AuthError::Jwt(_)), "{err:?}");
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn auth_quirks_oauthbearer() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let mut stream = PqStream::new_skip_handshake(Stream::from_raw(server));
+
+        let (sk, jwk) = new_ec_jwk("1".into());
+        let jwks = jose_jwk::JwkSet { keys: vec![jwk] };
+        let jwks_addr = jwks_server(move |path| match path {
+            "/" => Some(serde_json::to_vec(&jwks).unwrap()),
+            _ => None,
+        })
+        .await;
+
+        let client_key = ScramKey::from([1; 32]);
+        let secret = ServerSecret {
+            cached_at: tokio::time::Instant::now(),
+            iterations: 4096,
+            salt_base64: "c2FsdA==".into(),
+            stored_key: client_key.sha256(),
+            server_key: ScramKey::from([2; 32]),
+            doomed: false,
+        };
+
+        let ctx = RequestContext::test();
+        let api = Auth {
+            ips: vec![],
+            vpc_endpoint_ids: vec![],
+            access_blocker_flags: AccessBlockerFlags::default(),
+            secret: AuthSecret::Scram(secret),
+            client_key: Some(client_key),
+            client_cert_auth: None,
+            auth_rules: vec![AuthRule {
+                id: "foo".to_owned(),
+                jwks_url: format!("http://{jwks_addr}/").parse().unwrap(),
+                audience: None,
+                role_names: vec![RoleNameInt::from(&crate::types::RoleName::from("conrad"))],
+            }],
+        };
+
+        let user_info = ComputeUserInfoMaybeEndpoint {
+            user: "conrad".into(),
+            endpoint_id: Some("endpoint".into()),
+            options: NeonOptions::default(),
+        };
+
+        let jwt = new_ec_jwt("1".into(), &sk);
+        let handle = tokio::spawn(async move {
+            let mut read = BytesMut::new();
+
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationSasl(_) => {}
+                _ => panic!("wrong message"),
+            }
+
+            // client sends a valid token
+            let mut write = BytesMut::new();
+            let message = format!("n,,\x01auth=Bearer {jwt}\x01\x01");
+            frontend::sasl_initial_response("OAUTHBEARER", message.as_bytes(), &mut write).unwrap();
+            client.write_all(&write).await.unwrap();
+
+            // server accepts the token
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationOk => {}
+                _ => panic!("wrong message"),
+            }
+        });
+        let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
+            EndpointRateLimiter::DEFAULT,
+            64,
+        ));
+
+        let creds = auth_quirks(
+            &ctx,
+            &api,
+            user_info,
+            &mut stream,
+            false,
+            &OAUTHBEARER_CONFIG,
+            endpoint_rate_limiter,
+        )
+        .await
+        .unwrap();
+
+        // flush the final server message
+        stream.flush().await.unwrap();
+        handle.await.unwrap();
+
+        // proxy logs in to compute with the keys of the role, not the token
+        match creds.keys {
+            ComputeCredentialKeys::AuthKeys(AuthKeys::ScramSha256(keys)) => {
+                assert_eq!(keys.client_key, [1; 32]);
+                assert_eq!(keys.server_key, [2; 32]);
+            }
+            _ => panic!("wrong keys"),
+        }
+    }
+
     #[tokio::test]
     async fn auth_quirks_cleartext() {
         let (mut client, server) = tokio::io::duplex(1024);
@@ -612,6 +873,9 @@ async fn auth_quirks_cleartext() {
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
+            client_key: None,
+            client_cert_auth: None,
+            auth_rules: vec![],
         };
 
         let user_info = ComputeUserInfoMaybeEndpoint {
@@ -666,6 +930,9 @@ async fn auth_quirks_password_hack() {
             vpc_endpoint_ids: vec![],
             access_blocker_flags: AccessBlockerFlags::default(),
             secret: AuthSecret::Scram(ServerSecret::build("my-secret-password").await.unwrap()),
+            client_key: None,
+            client_cert_auth: None,
+            auth_rules: vec![],
         };
 
         let user_info = ComputeUserInfoMaybeEndpoint {
@@ -711,4 +978,205 @@ async fn auth_quirks_password_hack() {
 
         handle.await.unwrap();
     }
+
+    /// Keys of the client which logs in with a certificate, and the CA which
+    /// issues both the client and the server certificates.
+    struct ClientCertSetup {
+        tls: TlsConfig,
+        client_config: Arc<rustls::ClientConfig>,
+        cert_auth: Arc<ClientCertAuth>,
+    }
+
+    fn client_cert_setup() -> ClientCertSetup {
+        let provider = Arc::new(ring::default_provider());
+
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let server_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["endpoint.localhost".into()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "localhost");
+        let server_cert = params.signed_by(&server_key, &ca, &ca_key).unwrap();
+        let server_key = PrivateKeyDer::Pkcs8(server_key.serialize_der().into());
+
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "billing-service");
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+        let client_key = PrivateKeyDer::Pkcs8(client_key.serialize_der().into());
+
+        let pg_config = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(Arc::new(DeferredClientCertVerifier::new(&provider)))
+            .with_single_cert(vec![server_cert.der().clone()], server_key.clone_key())
+            .unwrap();
+        let cert_resolver = CertResolver::new(server_key, vec![server_cert.der().clone()]).unwrap();
+        let pg_config = Arc::new(pg_config);
+        let tls = TlsConfig {
+            http_config: pg_config.clone(),
+            pg_config,
+            common_names: cert_resolver.get_common_names(),
+            cert_resolver: Arc::new(cert_resolver),
+        };
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(ca.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(vec![client_cert.der().clone()], client_key)
+            .unwrap();
+
+        let cert_auth = ClientCertAuth::new(ClientCertAuthConfig {
+            trusted_ca_bundle: ca.pem(),
+            role_mapping: vec![ClientCertRoleMapping {
+                common_name: "billing-service".to_owned(),
+                role_name: RoleNameInt::from(&crate::types::RoleName::from("conrad")),
+            }],
+        })
+        .unwrap();
+
+        ClientCertSetup {
+            tls,
+            client_config: Arc::new(client_config),
+            cert_auth: Arc::new(cert_auth),
+        }
+    }
+
+    /// Connect with the client certificate over TLS and authenticate.
+    async fn auth_quirks_client_cert(
+        setup: &ClientCertSetup,
+        api: Auth,
+    ) -> crate::auth::Result<ComputeCredentials> {
+        let (client, server) = tokio::io::duplex(8192);
+
+        let client_config = setup.client_config.clone();
+        let handle = tokio::spawn(async move {
+            let mut client = client;
+            let mut write = BytesMut::new();
+            frontend::ssl_request(&mut write);
+            client.write_all(&write).await.unwrap();
+            assert_eq!(client.read_u8().await.unwrap(), b'S');
+
+            let server_name = ServerName::try_from("endpoint.localhost").unwrap();
+            let mut client = tokio_rustls::TlsConnector::from(client_config)
+                .connect(server_name, client)
+                .await
+                .unwrap();
+
+            let mut params = frontend::StartupMessageParams::default();
+            params.insert("user", "conrad");
+            params.insert("database", "postgres");
+            write.clear();
+            frontend::startup_message(&params, &mut write).unwrap();
+            client.write_all(&write).await.unwrap();
+
+            let mut read = BytesMut::new();
+            match read_message(&mut client, &mut read).await {
+                PgMessage::AuthenticationOk => {}
+                _ => panic!("wrong message"),
+            }
+        });
+
+        let ctx = RequestContext::test();
+        let mut stream = match handshake(&ctx, server, Some(&setup.tls), false)
+            .await
+            .unwrap()
+        {
+            HandshakeData::Startup(stream, _) => stream,
+            HandshakeData::Cancel(_) => panic!("unexpected cancel request"),
+        };
+
+        let user_info = ComputeUserInfoMaybeEndpoint {
+            user: "conrad".into(),
+            endpoint_id: Some("endpoint".into()),
+            options: NeonOptions::default(),
+        };
+        let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
+            EndpointRateLimiter::DEFAULT,
+            64,
+        ));
+
+        let res = auth_quirks(
+            &ctx,
+            &api,
+            user_info,
+            &mut stream,
+            false,
+            &CONFIG,
+            endpoint_rate_limiter,
+        )
+        .await;
+
+        if res.is_ok() {
+            stream.flush().await.unwrap();
+            handle.await.unwrap();
+        } else {
+            handle.abort();
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn auth_quirks_client_certificate() {
+        let setup = client_cert_setup();
+
+        let client_key = ScramKey::from([1; 32]);
+        let secret = ServerSecret {
+            cached_at: tokio::time::Instant::now(),
+            iterations: 4096,
+            salt_base64: "c2FsdA==".into(),
+            stored_key: client_key.sha256(),
+            server_key: ScramKey::from([2; 32]),
+            doomed: false,
+        };
+        let mut api = Auth {
+            ips: vec![],
+            vpc_endpoint_ids: vec![],
+            access_blocker_flags: AccessBlockerFlags::default(),
+            secret: AuthSecret::Scram(secret),
+            client_key: Some(client_key),
+            client_cert_auth: Some(setup.cert_auth.clone()),
+            auth_rules: vec![],
+        };
+
+        // proxy logs in to compute with the keys of the role
+        let creds = auth_quirks_client_cert(&setup, api.clone()).await.unwrap();
+        match creds.keys {
+            ComputeCredentialKeys::AuthKeys(AuthKeys::ScramSha256(keys)) => {
+                assert_eq!(keys.client_key, [1; 32]);
+                assert_eq!(keys.server_key, [2; 32]);
+            }
+            _ => panic!("wrong keys"),
+        }
+
+        // the client key is stale after the password change
+        api.client_key = Some(ScramKey::from([3; 32]));
+        let err = auth_quirks_client_cert(&setup, api.clone())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, crate::auth::AuthError::PasswordRequired(_)),
+            "{err:?}"
+        );
+
+        // the allowlist is still checked
+        api.ips = vec![IpPattern::Single([10, 0, 0, 1].into())];
+        let err = auth_quirks_client_cert(&setup, api).await.unwrap_err();
+        assert!(
+            matches!(err, crate::auth::AuthError::IpAddressNotAllowed(_)),
+            "{err:?}"
+        );
+    }
 }
@@ -456,6 +456,41 @@ fn parse_neon_options() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn parse_read_only_session() -> anyhow::Result<()> {
+        let sni = Some("project.localhost");
+        let common_names = Some(["localhost".into()].into());
+        let ctx = RequestContext::test();
+
+        for params in [
+            [
+                ("user", "john_doe"),
+                ("options", "neon_proxy_read_only:true"),
+            ],
+            [("user", "john_doe"), ("target_session_attrs", "read-only")],
+        ] {
+            let options = StartupMessageParams::new(params);
+            let user_info =
+                ComputeUserInfoMaybeEndpoint::parse(&ctx, &options, sni, common_names.as_ref())?;
+            assert!(user_info.options.is_read_only());
+            assert!(!user_info.options.is_ephemeral());
+            assert_eq!(
+                user_info.options.get_cache_key("project"),
+                "project proxy_read_only:true"
+            );
+        }
+
+        let options = StartupMessageParams::new([
+            ("user", "john_doe"),
+            ("target_session_attrs", "read-write"),
+        ]);
+        let user_info =
+            ComputeUserInfoMaybeEndpoint::parse(&ctx, &options, sni, common_names.as_ref())?;
+        assert!(!user_info.options.is_read_only());
+
+        Ok(())
+    }
+
     #[test]
     fn test_check_peer_addr_is_in_list() {
         fn check(v: serde_json::Value) -> bool {
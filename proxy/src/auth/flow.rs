@@ -4,19 +4,22 @@
 
 use postgres_protocol::authentication::sasl::{SCRAM_SHA_256, SCRAM_SHA_256_PLUS};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tracing::info;
+use tracing::{info, warn};
 
-use super::backend::ComputeCredentialKeys;
+use super::backend::jwt::{FetchAuthRules, JwkCache};
+use super::backend::{ComputeCredentialKeys, ComputeUserInfo};
 use super::{AuthError, PasswordHackPayload};
 use crate::context::RequestContext;
 use crate::control_plane::AuthSecret;
 use crate::intern::{EndpointIdInt, RoleNameInt};
 use crate::pqproto::{BeAuthenticationSaslMessage, BeMessage};
 use crate::sasl;
+use crate::sasl::oauthbearer::{self, OAUTHBEARER};
 use crate::scram::threadpool::ThreadPool;
 use crate::scram::{self};
 use crate::stream::{PqStream, Stream};
 use crate::tls::TlsServerEndPoint;
+use crate::types::RoleName;
 
 /// Use [SCRAM](crate::scram)-based auth in [`AuthFlow`].
 pub(crate) struct Scram<'a>(
@@ -37,6 +40,33 @@ fn first_message(&self, channel_binding: bool) -> BeMessage<'_> {
     }
 }
 
+/// Use [SCRAM](crate::scram)-based auth in [`AuthFlow`], but also let the client
+/// log in with a JWT via `OAUTHBEARER`.
+pub(crate) struct ScramOrOAuthBearer<'a, F> {
+    pub(crate) scram: Scram<'a>,
+    pub(crate) jwks: &'a JwkCache,
+    pub(crate) fetch: &'a F,
+    pub(crate) user_info: &'a ComputeUserInfo,
+    /// Client key of the role, to log in to compute after a token.
+    pub(crate) client_key: Option<&'a scram::ScramKey>,
+}
+
+impl<F> ScramOrOAuthBearer<'_, F> {
+    #[inline(always)]
+    fn first_message(&self, channel_binding: bool) -> BeMessage<'_> {
+        const METHODS: &[&str] = &[SCRAM_SHA_256_PLUS, SCRAM_SHA_256, OAUTHBEARER];
+        const METHODS_WITHOUT_PLUS: &[&str] = &[SCRAM_SHA_256, OAUTHBEARER];
+
+        if channel_binding {
+            BeMessage::AuthenticationSasl(BeAuthenticationSaslMessage::Methods(METHODS))
+        } else {
+            BeMessage::AuthenticationSasl(BeAuthenticationSaslMessage::Methods(
+                METHODS_WITHOUT_PLUS,
+            ))
+        }
+    }
+}
+
 /// Use an ad hoc auth flow (for clients which don't support SNI) proposed in
 /// <https://github.com/neondatabase/cloud/issues/1620#issuecomment-1165332290>.
 pub(crate) struct PasswordHack;
@@ -164,6 +194,144 @@ pub(crate) async fn authenticate(self) -> super::Result<sasl::Outcome<scram::Scr
     }
 }
 
+/// Stream wrapper for handling [SCRAM](crate::scram) and `OAUTHBEARER` auth.
+impl<S: AsyncRead + AsyncWrite + Unpin, F: FetchAuthRules>
+    AuthFlow<'_, S, ScramOrOAuthBearer<'_, F>>
+{
+    /// Perform user authentication. Raise an error in case authentication failed.
+    pub(crate) async fn authenticate(self) -> super::Result<sasl::Outcome<ComputeCredentialKeys>> {
+        let ScramOrOAuthBearer {
+            scram: Scram(secret, ctx),
+            jwks,
+            fetch,
+            user_info,
+            client_key,
+        } = self.state;
+        let channel_binding = self.tls_server_end_point;
+
+        // pause the timer while we communicate with the client
+        let paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+
+        // send sasl message.
+        let methods = self.state.first_message(channel_binding.supported());
+        self.stream.write_message(methods);
+        self.stream.flush().await?;
+
+        // Initial client message contains the chosen auth method's name.
+        let msg = self.stream.read_password_message().await?;
+        let sasl = sasl::FirstMessage::parse(msg)
+            .ok_or(sasl::Error::BadClientMessage("bad sasl message"))?;
+
+        // TODO: make this a metric instead
+        info!("client chooses {}", sasl.method);
+
+        let method = match sasl.method {
+            SCRAM_SHA_256 => crate::context::AuthMethod::ScramSha256,
+            SCRAM_SHA_256_PLUS => crate::context::AuthMethod::ScramSha256Plus,
+            OAUTHBEARER => crate::context::AuthMethod::Jwt,
+            method => return Err(sasl::Error::BadAuthMethod(method.into()).into()),
+        };
+        ctx.set_auth_method(method);
+
+        if sasl.method != OAUTHBEARER {
+            let exchange = scram::Exchange::new(secret, rand::random, channel_binding);
+            let step = sasl::Mechanism::exchange(exchange, sasl.message);
+            drop(paused);
+
+            let outcome = sasl::exchange(ctx, self.stream, step).await?;
+            return Ok(match outcome {
+                sasl::Outcome::Success(client_key) => {
+                    sasl::Outcome::Success(scram_credential_keys(secret, &client_key))
+                }
+                sasl::Outcome::Failure(reason) => sasl::Outcome::Failure(reason),
+            });
+        }
+
+        let token = oauthbearer::parse_client_response(sasl.message)?.map(str::to_owned);
+        drop(paused);
+
+        let Some(token) = token else {
+            // The client is asking for discovery information, which we don't provide.
+            reject_bearer_token(ctx, self.stream).await?;
+            return Ok(sasl::Outcome::Failure("missing bearer token"));
+        };
+
+        let res = jwks
+            .check_jwt(
+                ctx,
+                user_info.endpoint.clone(),
+                &user_info.user,
+                fetch,
+                &token,
+            )
+            .await;
+
+        if let Err(error) = res {
+            reject_bearer_token(ctx, self.stream).await?;
+            return Err(error.into());
+        }
+
+        let keys = compute_keys_without_password(&user_info.user, Some(secret), client_key)?;
+        self.stream.write_message(BeMessage::AuthenticationOk);
+        Ok(sasl::Outcome::Success(keys))
+    }
+}
+
+/// Tell the client that its bearer token is invalid, as required by
+/// <https://datatracker.ietf.org/doc/html/rfc7628#section-3.2.2>.
+/// The caller is expected to report the error afterwards.
+async fn reject_bearer_token<S: AsyncRead + AsyncWrite + Unpin>(
+    ctx: &RequestContext,
+    stream: &mut PqStream<Stream<S>>,
+) -> super::Result<()> {
+    // pause the timer while we communicate with the client
+    let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+
+    let sasl_msg = BeAuthenticationSaslMessage::Continue(oauthbearer::INVALID_TOKEN.as_bytes());
+    stream.write_message(BeMessage::AuthenticationSasl(sasl_msg));
+    stream.flush().await?;
+
+    // the client must acknowledge the error before we can send it.
+    let msg = stream.read_password_message().await?;
+    if msg != b"\x01" {
+        return Err(sasl::Error::BadClientMessage("expected an empty OAUTHBEARER response").into());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn scram_credential_keys(
+    secret: &scram::ServerSecret,
+    client_key: &scram::ScramKey,
+) -> ComputeCredentialKeys {
+    let keys = crate::compute::ScramKeys {
+        client_key: client_key.as_bytes(),
+        server_key: secret.server_key.as_bytes(),
+    };
+
+    ComputeCredentialKeys::AuthKeys(postgres_client::config::AuthKeys::ScramSha256(keys))
+}
+
+/// Keys to log in to compute as a role whose client has authenticated
+/// without the password, e.g. with a client certificate or a bearer token.
+/// Compute only accepts SCRAM, so the control plane has to provide the
+/// client key of the role.
+pub(crate) fn compute_keys_without_password(
+    user: &RoleName,
+    secret: Option<&scram::ServerSecret>,
+    client_key: Option<&scram::ScramKey>,
+) -> super::Result<ComputeCredentialKeys> {
+    let (Some(secret), Some(client_key)) = (secret, client_key) else {
+        return Err(AuthError::PasswordRequired(user.as_str().into()));
+    };
+    // the key is stale if the password has changed since
+    if secret.is_password_invalid(client_key).into() {
+        warn!("client key doesn't match the role secret");
+        return Err(AuthError::PasswordRequired(user.as_str().into()));
+    }
+    Ok(scram_credential_keys(secret, client_key))
+}
+
 pub(crate) async fn validate_password_and_exchange(
     pool: &ThreadPool,
     endpoint: EndpointIdInt,
@@ -182,13 +350,9 @@ pub(crate) async fn validate_password_and_exchange(
                 sasl::Outcome::Failure(reason) => return Ok(sasl::Outcome::Failure(reason)),
             };
 
-            let keys = crate::compute::ScramKeys {
-                client_key: client_key.as_bytes(),
-                server_key: scram_secret.server_key.as_bytes(),
-            };
-
-            Ok(sasl::Outcome::Success(ComputeCredentialKeys::AuthKeys(
-                postgres_client::config::AuthKeys::ScramSha256(keys),
+            Ok(sasl::Outcome::Success(scram_credential_keys(
+                &scram_secret,
+                &client_key,
             )))
         }
     }
@@ -24,6 +24,7 @@
 use crate::auth::backend::jwt::JwtError;
 use crate::control_plane;
 use crate::error::{ReportableError, UserFacingError};
+use crate::tls::client_cert::ClientCertError;
 
 /// Convenience wrapper for the authentication error.
 pub(crate) type Result<T> = std::result::Result<T, AuthError>;
@@ -64,6 +65,9 @@ pub(crate) enum AuthError {
     #[error("password authentication failed for user '{0}'")]
     PasswordFailed(Box<str>),
 
+    #[error("user '{0}' can only log in with the password")]
+    PasswordRequired(Box<str>),
+
     /// Errors produced by e.g. [`crate::stream::PqStream`].
     #[error(transparent)]
     Io(#[from] io::Error),
@@ -95,6 +99,9 @@ pub(crate) enum AuthError {
 
     #[error(transparent)]
     Jwt(#[from] JwtError),
+
+    #[error(transparent)]
+    ClientCertificate(#[from] ClientCertError),
 }
 
 impl AuthError {
@@ -138,6 +145,7 @@ fn to_string_client(&self) -> String {
             Self::GetAuthInfo(e) => e.to_string_client(),
             Self::Sasl(e) => e.to_string_client(),
             Self::PasswordFailed(_) => self.to_string(),
+            Self::PasswordRequired(_) => self.to_string(),
             Self::BadAuthMethod(_) => self.to_string(),
             Self::MalformedPassword(_) => self.to_string(),
             Self::MissingEndpointName => self.to_string(),
@@ -150,6 +158,7 @@ fn to_string_client(&self) -> String {
             Self::UserTimeout(_) => self.to_string(),
             Self::ConfirmationTimeout(_) => self.to_string(),
             Self::Jwt(_) => self.to_string(),
+            Self::ClientCertificate(e) => e.to_string_client(),
         }
     }
 }
@@ -161,6 +170,7 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
             Self::GetAuthInfo(e) => e.get_error_kind(),
             Self::Sasl(e) => e.get_error_kind(),
             Self::PasswordFailed(_) => crate::error::ErrorKind::User,
+            Self::PasswordRequired(_) => crate::error::ErrorKind::User,
             Self::BadAuthMethod(_) => crate::error::ErrorKind::User,
             Self::MalformedPassword(_) => crate::error::ErrorKind::User,
             Self::MissingEndpointName => crate::error::ErrorKind::User,
@@ -173,6 +183,7 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
             Self::UserTimeout(_) => crate::error::ErrorKind::User,
             Self::ConfirmationTimeout(_) => crate::error::ErrorKind::User,
             Self::Jwt(_) => crate::error::ErrorKind::User,
+            Self::ClientCertificate(e) => e.get_error_kind(),
         }
     }
 }
@@ -0,0 +1,7 @@
+//! Replays a session captured by the proxy against a compute, to reproduce the
+//! protocol issues of the clients.
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    proxy::binary::capture_replay::run().await
+}
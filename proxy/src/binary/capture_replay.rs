@@ -0,0 +1,351 @@
+//! Replays a session captured by the proxy against a compute, to reproduce the
+//! protocol issues of the clients.
+//!
+//! The captures are the parquet files the proxy uploads with
+//! `--parquet-upload-capture-remote-storage`. The messages the client sent are
+//! sent to the compute in the captured order, and the responses of the compute
+//! are compared to the ones the client received.
+
+use std::fs::File;
+use std::time::Duration;
+
+use anyhow::{Context, bail, ensure};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use futures::TryStreamExt;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, Row};
+use postgres_client::config::SslMode;
+use postgres_client::connect_raw::StartupStream;
+use postgres_client::maybe_tls_stream::MaybeTlsStream;
+use postgres_client::tls::NoTlsStream;
+use postgres_protocol::message::backend::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tracing::{info, warn};
+use utils::project_git_version;
+use uuid::Uuid;
+
+use crate::context::capture::{DIRECTION_BACKEND, DIRECTION_FRONTEND};
+use crate::pglb::audit::error_code;
+use crate::pqproto::{
+    BE_BIND_COMPLETE, BE_CLOSE_COMPLETE, BE_COMMAND_COMPLETE, BE_EMPTY_QUERY_RESPONSE,
+    BE_ERROR_RESPONSE, BE_NO_DATA, BE_NOTICE_RESPONSE, BE_NOTIFICATION_RESPONSE,
+    BE_PARAMETER_STATUS, BE_PARSE_COMPLETE, BE_PORTAL_SUSPENDED, BE_READY_FOR_QUERY,
+};
+
+project_git_version!(GIT_VERSION);
+
+type ComputeStream = MaybeTlsStream<TcpStream, NoTlsStream>;
+
+/// Replay a captured session against a compute
+#[derive(Parser)]
+#[command(version = GIT_VERSION, about)]
+struct CaptureReplayArgs {
+    /// Parquet files with the captured messages.
+    #[clap(required = true)]
+    files: Vec<Utf8PathBuf>,
+    /// Session to replay. Can be omitted if the files have one session only.
+    #[clap(long)]
+    session_id: Option<Uuid>,
+    /// Host of the postgres server to replay the session against
+    #[clap(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Port of the postgres server to replay the session against
+    #[clap(long, default_value_t = 5432)]
+    port: u16,
+    /// Role to connect with. Defaults to the role of the captured session.
+    #[clap(long)]
+    user: Option<String>,
+    /// Database to connect to. Defaults to the database of the captured session.
+    #[clap(long)]
+    dbname: Option<String>,
+    /// Password of the role, if the server asks for one.
+    #[clap(long, env = "PGPASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    /// Wait between the messages of the client as long as the client did.
+    #[clap(long)]
+    keep_timing: bool,
+    /// How long to wait for each response of the server.
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    response_timeout: Duration,
+}
+
+pub async fn run() -> anyhow::Result<()> {
+    let _logging_guard = crate::logging::init()?;
+
+    let args = CaptureReplayArgs::parse();
+    let session = read_capture(&args.files, args.session_id)?;
+    info!(
+        session_id = %session.session_id,
+        messages = session.messages.len(),
+        "replaying the captured session"
+    );
+
+    let mut stream = connect(&args, &session).await?;
+    let mismatches = replay(&mut stream, &session.messages, &args).await?;
+    ensure!(
+        mismatches == 0,
+        "{mismatches} responses differ from the captured ones"
+    );
+
+    info!("the responses match the captured ones");
+    Ok(())
+}
+
+struct CapturedSession {
+    session_id: Uuid,
+    username: Option<String>,
+    database: Option<String>,
+    messages: Vec<CapturedMessage>,
+}
+
+struct CapturedMessage {
+    sequence: u64,
+    offset: Duration,
+    frontend: bool,
+    /// The message, with its tag and length.
+    message: Vec<u8>,
+    truncated: bool,
+}
+
+/// Read the messages of the session from the capture files, in their order in the session.
+fn read_capture(
+    files: &[Utf8PathBuf],
+    session_id: Option<Uuid>,
+) -> anyhow::Result<CapturedSession> {
+    let mut session: Option<CapturedSession> = None;
+
+    for path in files {
+        let file = File::open(path).with_context(|| format!("failed to open {path}"))?;
+        let reader = SerializedFileReader::new(file)
+            .with_context(|| format!("failed to read the capture from {path}"))?;
+
+        for row in reader.get_row_iter(None)? {
+            let row = parse_row(&row?).with_context(|| format!("invalid capture in {path}"))?;
+            if session_id.is_some_and(|id| id != row.session_id) {
+                continue;
+            }
+
+            let session = session.get_or_insert_with(|| CapturedSession {
+                session_id: row.session_id,
+                username: row.username.clone(),
+                database: row.database.clone(),
+                messages: vec![],
+            });
+            ensure!(
+                session.session_id == row.session_id,
+                "the capture has several sessions, pick one with --session-id"
+            );
+            session.messages.push(row.message);
+        }
+    }
+
+    let Some(mut session) = session else {
+        bail!("the capture has no messages of the session");
+    };
+    session.messages.sort_by_key(|message| message.sequence);
+    Ok(session)
+}
+
+struct CaptureRow {
+    session_id: Uuid,
+    username: Option<String>,
+    database: Option<String>,
+    message: CapturedMessage,
+}
+
+fn parse_row(row: &Row) -> anyhow::Result<CaptureRow> {
+    let mut session_id = None;
+    let mut username = None;
+    let mut database = None;
+    let mut sequence = None;
+    let mut offset_us = None;
+    let mut frontend = None;
+    let mut message = None;
+    let mut truncated = false;
+
+    for (name, field) in row.get_column_iter() {
+        match (name.as_str(), field) {
+            ("session_id", Field::Str(id)) => session_id = Some(id.parse()?),
+            ("session_id", Field::Bytes(id)) => session_id = Some(Uuid::from_slice(id.data())?),
+            ("username", Field::Str(user)) => username = Some(user.clone()),
+            ("database", Field::Str(dbname)) => database = Some(dbname.clone()),
+            ("sequence", Field::ULong(n)) => sequence = Some(*n),
+            ("sequence", Field::Long(n)) => sequence = Some(*n as u64),
+            ("offset_us", Field::ULong(n)) => offset_us = Some(*n),
+            ("offset_us", Field::Long(n)) => offset_us = Some(*n as u64),
+            ("direction", Field::Str(direction)) => match direction.as_str() {
+                DIRECTION_FRONTEND => frontend = Some(true),
+                DIRECTION_BACKEND => frontend = Some(false),
+                direction => bail!("unknown direction {direction}"),
+            },
+            ("message", Field::Bytes(bytes)) => message = Some(bytes.data().to_vec()),
+            ("truncated", Field::Bool(b)) => truncated = *b,
+            _ => {}
+        }
+    }
+
+    let message = message.context("missing message")?;
+    ensure!(message.len() >= 5, "message is too short");
+    Ok(CaptureRow {
+        session_id: session_id.context("missing session_id")?,
+        username,
+        database,
+        message: CapturedMessage {
+            sequence: sequence.context("missing sequence")?,
+            offset: Duration::from_micros(offset_us.unwrap_or_default()),
+            frontend: frontend.context("missing direction")?,
+            message,
+            truncated,
+        },
+    })
+}
+
+/// Connect and authenticate to the compute, like the proxy did for the captured session.
+async fn connect(
+    args: &CaptureReplayArgs,
+    session: &CapturedSession,
+) -> anyhow::Result<ComputeStream> {
+    let user = args
+        .user
+        .as_deref()
+        .or(session.username.as_deref())
+        .context("the capture has no role, set one with --user")?;
+    let dbname = args
+        .dbname
+        .as_deref()
+        .or(session.database.as_deref())
+        .unwrap_or(user);
+
+    let mut config = postgres_client::Config::new(args.host.clone(), args.port);
+    config.ssl_mode(SslMode::Disable);
+    config.user(user);
+    config.dbname(dbname);
+    if let Some(password) = &args.password {
+        config.password(password);
+    }
+
+    let socket = TcpStream::connect((args.host.as_str(), args.port))
+        .await
+        .with_context(|| format!("failed to connect to {}:{}", args.host, args.port))?;
+    socket.set_nodelay(true)?;
+
+    let mut stream = StartupStream::new(MaybeTlsStream::Raw(socket));
+    config.authenticate(&mut stream).await?;
+
+    // the server reports its parameters before it's ready for the session.
+    loop {
+        match stream.try_next().await? {
+            Some(Message::ReadyForQuery(_)) => break,
+            Some(Message::ErrorResponse(_)) => bail!("the server rejected the session"),
+            Some(_) => {}
+            None => bail!("the server closed the connection"),
+        }
+    }
+    info!(user, dbname, "connected to {}:{}", args.host, args.port);
+
+    Ok(stream.into_framed().into_inner())
+}
+
+/// Send the messages of the client to the compute, and compare its responses to
+/// the captured ones. Returns the number of responses which differ.
+async fn replay(
+    stream: &mut ComputeStream,
+    messages: &[CapturedMessage],
+    args: &CaptureReplayArgs,
+) -> anyhow::Result<usize> {
+    let started = Instant::now();
+    let mut mismatches = 0;
+
+    for captured in messages {
+        let tag = captured.message[0] as char;
+        if captured.frontend {
+            ensure!(
+                !captured.truncated,
+                "message #{} ({tag}) was truncated by the capture and can't be sent",
+                captured.sequence,
+            );
+            if args.keep_timing {
+                tokio::time::sleep_until(started + captured.offset).await;
+            }
+            stream.write_all(&captured.message).await?;
+            continue;
+        }
+
+        // the asynchronous messages don't follow the order of the session.
+        if is_asynchronous(captured.message[0]) {
+            continue;
+        }
+
+        stream.flush().await?;
+        let response = tokio::time::timeout(args.response_timeout, read_response(stream))
+            .await
+            .with_context(|| format!("no response for message #{}", captured.sequence))??;
+
+        ensure!(
+            response[0] == captured.message[0],
+            "the session diverged at message #{}: expected {tag}, got {}",
+            captured.sequence,
+            response[0] as char,
+        );
+        if let Some(difference) = compare(&captured.message, &response) {
+            warn!(sequence = captured.sequence, "{tag} differs: {difference}");
+            mismatches += 1;
+        }
+    }
+
+    Ok(mismatches)
+}
+
+fn is_asynchronous(tag: u8) -> bool {
+    matches!(
+        tag,
+        BE_NOTICE_RESPONSE | BE_NOTIFICATION_RESPONSE | BE_PARAMETER_STATUS
+    )
+}
+
+/// Read the next message of the compute which isn't asynchronous.
+async fn read_response(stream: &mut ComputeStream) -> anyhow::Result<Vec<u8>> {
+    loop {
+        let mut message = vec![0; 5];
+        stream.read_exact(&mut message).await?;
+        // the length includes itself
+        let len = u32::from_be_bytes(message[1..5].try_into()?) as usize;
+        ensure!(len >= 4, "invalid message length {len}");
+        message.resize(len + 1, 0);
+        stream.read_exact(&mut message[5..]).await?;
+
+        if !is_asynchronous(message[0]) {
+            return Ok(message);
+        }
+    }
+}
+
+/// Describe how the response differs from the captured one. Only the parts of
+/// the responses which don't depend on the data are compared.
+fn compare(captured: &[u8], response: &[u8]) -> Option<String> {
+    match captured[0] {
+        BE_ERROR_RESPONSE => {
+            let captured = error_code(&captured[5..]);
+            let response = error_code(&response[5..]);
+            (captured != response).then(|| format!("SQLSTATE {response} instead of {captured}"))
+        }
+        BE_COMMAND_COMPLETE
+        | BE_EMPTY_QUERY_RESPONSE
+        | BE_PARSE_COMPLETE
+        | BE_BIND_COMPLETE
+        | BE_CLOSE_COMPLETE
+        | BE_NO_DATA
+        | BE_PORTAL_SUSPENDED
+        | BE_READY_FOR_QUERY => (captured != response).then(|| {
+            format!(
+                "{:?} instead of {:?}",
+                String::from_utf8_lossy(&response[5..]),
+                String::from_utf8_lossy(&captured[5..]),
+            )
+        }),
+        _ => None,
+    }
+}
@@ -21,16 +21,17 @@
 use crate::auth::backend::local::LocalBackend;
 use crate::auth::{self};
 use crate::cancellation::CancellationHandler;
-#[cfg(feature = "rest_broker")]
-use crate::config::RestConfig;
 use crate::config::{
     self, AuthenticationConfig, ComputeConfig, HttpConfig, ProxyConfig, RetryConfig,
     refresh_config_loop,
 };
+#[cfg(feature = "rest_broker")]
+use crate::config::{EndpointSet, RestConfig};
 use crate::control_plane::locks::ApiLocks;
 use crate::http::health_server::AppMetrics;
 use crate::metrics::{Metrics, ServiceInfo};
-use crate::rate_limiter::{EndpointRateLimiter, LeakyBucketConfig, RateBucketInfo};
+use crate::pglb::drain::Drain;
+use crate::rate_limiter::{EndpointRateLimiter, LeakyBucketConfig, RateBucketInfo, SessionLimiter};
 use crate::scram::threadpool::ThreadPool;
 use crate::serverless::cancel_set::CancelSet;
 use crate::serverless::{self, GlobalConnPoolOptions};
@@ -107,6 +108,10 @@ struct SqlOverHttpArgs {
 
     #[clap(long, default_value_t = 10 * 1024 * 1024)] // 10 MiB
     sql_over_http_max_response_size_bytes: usize,
+
+    /// How long an interactive transaction may stay idle between requests before it is rolled back
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    sql_over_http_txn_session_idle_timeout: tokio::time::Duration,
 }
 
 pub async fn run() -> anyhow::Result<()> {
@@ -189,11 +194,13 @@ pub async fn run() -> anyhow::Result<()> {
 
     maintenance_tasks.spawn(crate::http::health_server::task_main(
         metrics_listener,
+        shutdown.clone(),
         AppMetrics {
             jemalloc,
             neon_metrics,
             proxy: crate::metrics::Metrics::get(),
         },
+        None,
     ));
 
     let task = serverless::task_main(
@@ -247,6 +254,8 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
 
     let http_config = HttpConfig {
         accept_websockets: false,
+        websocket_compression: false,
+        websocket_multiplexing: false,
         pool_options: GlobalConnPoolOptions {
             gc_epoch: Duration::from_secs(60),
             pool_shards: 2,
@@ -260,6 +269,7 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
         client_conn_threshold: args.sql_over_http.sql_over_http_client_conn_threshold,
         max_request_size_bytes: args.sql_over_http.sql_over_http_max_request_size_bytes,
         max_response_size_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+        txn_session_idle_timeout: args.sql_over_http.sql_over_http_txn_session_idle_timeout,
     };
 
     let compute_config = ComputeConfig {
@@ -288,6 +298,7 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
             is_vpc_acccess_proxy: false,
             is_auth_broker: false,
             accept_jwts: true,
+            accept_oauthbearer: false,
             console_redirect_confirmation_timeout: Duration::ZERO,
         },
         #[cfg(feature = "rest_broker")]
@@ -296,12 +307,18 @@ fn build_config(args: &LocalProxyCliArgs) -> anyhow::Result<&'static ProxyConfig
             db_schema_cache: None,
             max_schema_size: 0,
             hostname_prefix: String::new(),
+            graphql_endpoints: EndpointSet::None,
+            query_cache: None,
+            query_cache_endpoints: EndpointSet::None,
         },
         proxy_protocol_v2: config::ProxyProtocolV2::Rejected,
         handshake_timeout: Duration::from_secs(10),
         wake_compute_retry_config: RetryConfig::parse(RetryConfig::WAKE_COMPUTE_DEFAULT_VALUES)?,
         connect_compute_locks,
         connect_to_compute: compute_config,
+        session_limiter: SessionLimiter::new_with_shards(16),
+        transaction_pool: None,
+        drain: Drain::new(None),
         greetings,
         #[cfg(feature = "testing")]
         disable_pg_session_jwt: args.disable_pg_session_jwt,
@@ -2,6 +2,7 @@
 //! is also covered by code style configs in lib.rs and the unused-code check is
 //! more effective when practically all modules are private to the lib.
 
+pub mod capture_replay;
 pub mod local_proxy;
 pub mod pg_sni_router;
 pub mod proxy;
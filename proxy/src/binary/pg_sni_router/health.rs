@@ -0,0 +1,196 @@
+//! Active health checks of the targets of the routes.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use clashmap::ClashMap;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::SSL_REQUEST;
+use super::routes::RouteTable;
+use crate::protocol2::{ConnectionInfo, encode_proxy_protocol};
+use crate::util::run_until_cancelled;
+
+/// Consecutive failed checks after which a target gets no new connections.
+const UNHEALTHY_THRESHOLD: u32 = 2;
+
+/// Consecutive failed checks of the targets. The targets which were not checked yet are healthy.
+#[derive(Default)]
+pub(crate) struct TargetHealth {
+    failures: ClashMap<String, u32>,
+}
+
+impl TargetHealth {
+    pub(crate) fn is_healthy(&self, addr: &str) -> bool {
+        self.failures
+            .get(addr)
+            .is_none_or(|failures| *failures < UNHEALTHY_THRESHOLD)
+    }
+
+    pub(crate) fn record(&self, addr: &str, success: bool) {
+        let mut failures = self.failures.entry(addr.to_owned()).or_default();
+        if success {
+            if *failures >= UNHEALTHY_THRESHOLD {
+                info!(addr, "target is healthy again");
+            }
+            *failures = 0;
+        } else {
+            *failures += 1;
+            if *failures == UNHEALTHY_THRESHOLD {
+                warn!(addr, "target is unhealthy");
+            }
+        }
+    }
+
+    fn retain(&self, f: impl Fn(&str) -> bool) {
+        self.failures.retain(|addr, _| f(addr));
+    }
+}
+
+/// Check the targets of the routes periodically, until cancelled.
+pub(crate) async fn task_main(
+    table: Arc<RouteTable>,
+    interval: Duration,
+    timeout: Duration,
+    cancellation_token: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    while run_until_cancelled(interval.tick(), &cancellation_token)
+        .await
+        .is_some()
+    {
+        let targets = table.targets();
+        table.health.retain(|addr| targets.contains_key(addr));
+
+        let table = &table;
+        let checks = targets.iter().map(|(addr, &proxy_protocol)| async move {
+            let res = tokio::time::timeout(timeout, check(addr, proxy_protocol)).await;
+            let success = match res {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    warn!(%addr, "health check failed: {e}");
+                    false
+                }
+                Err(_) => {
+                    warn!(%addr, "health check timed out");
+                    false
+                }
+            };
+            table.health.record(addr, success);
+        });
+        futures::future::join_all(checks).await;
+    }
+
+    info!("health checks have stopped");
+}
+
+/// The targets must answer an `SSLRequest`, like postgres. The ones which expect
+/// a PROXY protocol header get one with the addresses of the router before it:
+/// with a LOCAL header, the proxies close the connection without answering.
+async fn check(addr: &str, proxy_protocol: bool) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+
+    if proxy_protocol {
+        let info = ConnectionInfo {
+            addr: stream.local_addr()?,
+            extra: None,
+        };
+        let header = encode_proxy_protocol(&info, stream.peer_addr()?);
+        stream.write_all(&header).await?;
+    }
+    stream.write_all(&SSL_REQUEST).await?;
+    match stream.read_u8().await? {
+        b'S' | b'N' => Ok(()),
+        resp => Err(std::io::Error::other(format!(
+            "unexpected response to SSLRequest: {resp:#x}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::crypto::ring;
+    use rustls::pki_types::PrivateKeyDer;
+    use tokio::net::TcpListener;
+
+    use super::super::Router;
+    use super::*;
+    use crate::config::ProxyProtocolV2;
+
+    #[test]
+    fn target_health() {
+        let health = TargetHealth::default();
+        assert!(health.is_healthy("backend:5432"));
+
+        health.record("backend:5432", false);
+        assert!(health.is_healthy("backend:5432"));
+        health.record("backend:5432", false);
+        assert!(!health.is_healthy("backend:5432"));
+
+        health.record("backend:5432", true);
+        assert!(health.is_healthy("backend:5432"));
+    }
+
+    #[tokio::test]
+    async fn check_postgres() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let server = tokio::spawn(async move {
+            for resp in [b'N', b'E'] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 8];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request, SSL_REQUEST);
+                stream.write_all(&[resp]).await.unwrap();
+            }
+        });
+
+        check(&addr, false).await.unwrap();
+        check(&addr, false).await.unwrap_err();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_proxy_protocol() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let tls_config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(cert.key_pair.serialize_der().into()),
+                )
+                .unwrap();
+
+        // the router itself, which expects the PROXY protocol header.
+        let router = Router::new(
+            None,
+            Arc::new(RouteTable::default()),
+            ProxyProtocolV2::Required,
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let cancellation_token = CancellationToken::new();
+        let router = tokio::spawn(super::super::task_main(
+            Arc::new(router),
+            Arc::new(tls_config),
+            None,
+            listener,
+            cancellation_token.clone(),
+        ));
+
+        // the router answers, instead of closing the connection as after a LOCAL header.
+        check(&addr, true).await.unwrap();
+
+        cancellation_token.cancel();
+        router.await.unwrap().unwrap();
+    }
+}
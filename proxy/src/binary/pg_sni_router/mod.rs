@@ -3,14 +3,24 @@
 //!
 //! This allows connecting to pods/services running in the same Kubernetes cluster from
 //! the outside. Similar to an ingress controller for HTTPS.
+//!
+//! The connections can also be routed with a table of routes, by their SNI hostname,
+//! ALPN protocol, or the private link metadata of the PROXY protocol header. The routes
+//! can be changed at runtime over HTTP, which lets the router front several proxy
+//! clusters, e.g. while endpoints are migrated between them.
+
+mod health;
+mod routes;
 
 use std::io;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, anyhow, bail, ensure};
 use clap::Arg;
+use clap::builder::EnumValueParser;
 use futures::future::Either;
 use futures::{FutureExt, TryFutureExt};
 use itertools::Itertools;
@@ -21,21 +31,27 @@
 use tokio_rustls::TlsConnector;
 use tokio_rustls::server::TlsStream;
 use tokio_util::sync::CancellationToken;
-use tracing::{Instrument, error, info};
+use tracing::{Instrument, debug, error, info};
+use utils::auth::{JwtAuth, SwappableJwtAuth};
 use utils::project_git_version;
 use utils::sentry_init::init_sentry;
 
+use self::routes::{ClientInfo, Destination, RouteTable};
+use crate::config::ProxyProtocolV2;
 use crate::context::RequestContext;
 use crate::metrics::{Metrics, ServiceInfo};
 use crate::pglb::TlsRequired;
 use crate::pqproto::FeStartupPacket;
-use crate::protocol2::ConnectionInfo;
+use crate::protocol2::{ConnectHeader, ConnectionInfo, encode_proxy_protocol, read_proxy_protocol};
 use crate::proxy::{ErrorSource, copy_bidirectional_client_compute};
 use crate::stream::{PqStream, Stream};
 use crate::util::run_until_cancelled;
 
 project_git_version!(GIT_VERSION);
 
+/// `SSLRequest` startup message.
+const SSL_REQUEST: [u8; 8] = *b"\x00\x00\x00\x08\x04\xd2\x16\x2f";
+
 fn cli() -> clap::Command {
     clap::Command::new("Neon proxy/router")
         .version(GIT_VERSION)
@@ -71,7 +87,53 @@ fn cli() -> clap::Command {
                 .short('d')
                 .long("destination")
                 .help("append this domain zone to the SNI hostname to get the destination address")
-                .required(true),
+                .required_unless_present_any(["routes", "routes-listen"]),
+        )
+        .arg(
+            Arg::new("routes")
+                .long("routes")
+                .help("path to a JSON file with the routes by id, updated by the routes API"),
+        )
+        .arg(
+            Arg::new("routes-listen")
+                .long("routes-listen")
+                .help("listen for requests to the routes API on ip:port"),
+        )
+        .arg(
+            Arg::new("routes-auth-public-key-path")
+                .long("routes-auth-public-key-path")
+                .help(
+                    "path to the public key to verify the JWTs of the routes API requests, \
+                     which need the admin scope; required unless the API listens on loopback",
+                ),
+        )
+        .arg(
+            Arg::new("proxy-protocol-v2")
+                .long("proxy-protocol-v2")
+                .help("whether the client connections start with a PROXY protocol v2 header")
+                .value_parser(EnumValueParser::<ProxyProtocolV2>::new())
+                .default_value("rejected"),
+        )
+        .arg(
+            Arg::new("alpn-protocols")
+                .long("alpn-protocols")
+                .help("comma-separated ALPN protocols to offer to the clients, to route by")
+                .value_delimiter(',')
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("health-check-interval")
+                .long("health-check-interval")
+                .help("how often to check the health of the targets of the routes")
+                .value_parser(humantime::parse_duration)
+                .default_value("10s"),
+        )
+        .arg(
+            Arg::new("health-check-timeout")
+                .long("health-check-timeout")
+                .help("how long a health check of a target may take")
+                .value_parser(humantime::parse_duration)
+                .default_value("2s"),
         )
 }
 
@@ -81,23 +143,35 @@ pub async fn run() -> anyhow::Result<()> {
     let _sentry_guard = init_sentry(Some(GIT_VERSION.into()), &[]);
 
     let args = cli().get_matches();
-    let destination: String = args
-        .get_one::<String>("dest")
-        .expect("string argument defined")
-        .parse()?;
+    let destination = args.get_one::<String>("dest").cloned();
 
     // Configure TLS
-    let tls_config = match (
+    let mut tls_config = match (
         args.get_one::<String>("tls-key"),
         args.get_one::<String>("tls-cert"),
     ) {
         (Some(key_path), Some(cert_path)) => parse_tls(key_path.as_ref(), cert_path.as_ref())?,
         _ => bail!("tls-key and tls-cert must be specified"),
     };
+    if let Some(protocols) = args.get_many::<String>("alpn-protocols") {
+        let mut config = rustls::ServerConfig::clone(&tls_config);
+        config.alpn_protocols = protocols.map(|p| p.as_bytes().to_vec()).collect();
+        tls_config = Arc::new(config);
+    }
 
     let compute_tls_config =
         Arc::new(crate::tls::client_config::compute_client_config_with_root_certs()?);
 
+    let routes = match args.get_one::<String>("routes") {
+        Some(path) => RouteTable::load(path.as_ref())?,
+        None => RouteTable::default(),
+    };
+    let routes = Arc::new(routes);
+    let proxy_protocol_v2 = *args
+        .get_one::<ProxyProtocolV2>("proxy-protocol-v2")
+        .expect("proxy-protocol-v2 argument defined");
+    let router = Arc::new(Router::new(destination, routes.clone(), proxy_protocol_v2));
+
     // Start listening for incoming client connections
     let proxy_address: SocketAddr = args
         .get_one::<String>("listen")
@@ -114,10 +188,45 @@ pub async fn run() -> anyhow::Result<()> {
     let proxy_listener_compute_tls = TcpListener::bind(proxy_address_compute_tls).await?;
 
     let cancellation_token = CancellationToken::new();
-    let dest = Arc::new(destination);
+
+    if let Some(routes_address) = args.get_one::<String>("routes-listen") {
+        let routes_address: SocketAddr = routes_address.parse()?;
+        let auth = match args.get_one::<String>("routes-auth-public-key-path") {
+            Some(key_path) => {
+                let jwt_auth = JwtAuth::from_key_path(key_path.as_ref())?;
+                Some(Arc::new(SwappableJwtAuth::new(jwt_auth)))
+            }
+            None => {
+                ensure!(
+                    routes_address.ip().is_loopback(),
+                    "routes api on {routes_address} requires --routes-auth-public-key-path"
+                );
+                None
+            }
+        };
+        info!("Starting routes api on {routes_address}");
+        let routes_listener = std::net::TcpListener::bind(routes_address)?;
+        tokio::spawn(
+            routes::task_main(routes_listener, routes.clone(), auth)
+                .map_err(|e| error!("routes api has failed: {e:#}")),
+        );
+    }
+
+    let health_check_interval = *args
+        .get_one::<Duration>("health-check-interval")
+        .expect("health-check-interval argument defined");
+    let health_check_timeout = *args
+        .get_one::<Duration>("health-check-timeout")
+        .expect("health-check-timeout argument defined");
+    tokio::spawn(health::task_main(
+        routes,
+        health_check_interval,
+        health_check_timeout,
+        cancellation_token.clone(),
+    ));
 
     let main = tokio::spawn(task_main(
-        dest.clone(),
+        router.clone(),
         tls_config.clone(),
         None,
         proxy_listener,
@@ -126,14 +235,13 @@ pub async fn run() -> anyhow::Result<()> {
     .map(crate::error::flatten_err);
 
     let main_tls = tokio::spawn(task_main(
-        dest,
+        router,
         tls_config,
         Some(compute_tls_config),
         proxy_listener_compute_tls,
         cancellation_token.clone(),
     ))
     .map(crate::error::flatten_err);
-
     Metrics::get()
         .service
         .info
@@ -205,8 +313,60 @@ pub(super) fn parse_tls(
     Ok(tls_config)
 }
 
+/// Where the router sends the connections.
+pub(super) struct Router {
+    /// Domain zone of the destinations encoded in the SNI hostnames, for the
+    /// connections which don't match any of the routes.
+    dest_suffix: Option<String>,
+    routes: Arc<RouteTable>,
+    proxy_protocol_v2: ProxyProtocolV2,
+}
+
+impl Router {
+    pub(super) fn new(
+        dest_suffix: Option<String>,
+        routes: Arc<RouteTable>,
+        proxy_protocol_v2: ProxyProtocolV2,
+    ) -> Self {
+        Self {
+            dest_suffix,
+            routes,
+            proxy_protocol_v2,
+        }
+    }
+
+    fn destination(&self, client: &ClientInfo<'_>) -> anyhow::Result<Destination> {
+        if let Some(dest) = self.routes.resolve(client) {
+            return Ok(dest);
+        }
+        let Some(dest_suffix) = &self.dest_suffix else {
+            bail!("no route for the connection");
+        };
+
+        // Cut off first part of the SNI domain
+        // We receive required destination details in the format of
+        //   `{k8s_service_name}--{k8s_namespace}--{port}.non-sni-domain`
+        let sni = client.sni.ok_or(anyhow!("SNI missing"))?;
+        let dest: Vec<&str> = sni
+            .split_once('.')
+            .context("invalid SNI")?
+            .0
+            .splitn(3, "--")
+            .collect();
+        let port = dest[2].parse::<u16>().context("invalid port")?;
+        let destination = format!("{}.{}.{}:{}", dest[0], dest[1], dest_suffix, port);
+
+        Ok(Destination {
+            route_id: None,
+            addr: destination.clone(),
+            server_name: destination,
+            proxy_protocol: false,
+        })
+    }
+}
+
 pub(super) async fn task_main(
-    dest_suffix: Arc<String>,
+    router: Arc<Router>,
     tls_config: Arc<rustls::ServerConfig>,
     compute_tls_config: Option<Arc<rustls::ClientConfig>>,
     listener: tokio::net::TcpListener,
@@ -225,24 +385,49 @@ pub(super) async fn task_main(
 
         let session_id = uuid::Uuid::new_v4();
         let tls_config = Arc::clone(&tls_config);
-        let dest_suffix = Arc::clone(&dest_suffix);
+        let router = Arc::clone(&router);
         let compute_tls_config = compute_tls_config.clone();
 
         connections.spawn(
             async move {
+                let local_addr = socket.local_addr()?;
+                let (socket, conn_info) = match router.proxy_protocol_v2 {
+                    ProxyProtocolV2::Required => match read_proxy_protocol(socket).await? {
+                        // the load balancers will not send any more data.
+                        (_socket, ConnectHeader::Local) => {
+                            debug!("healthcheck received");
+                            return Ok(());
+                        }
+                        (socket, ConnectHeader::Proxy(info)) => (socket, info),
+                    },
+                    ProxyProtocolV2::Rejected => (
+                        socket,
+                        ConnectionInfo {
+                            addr: peer_addr,
+                            extra: None,
+                        },
+                    ),
+                };
+
                 socket
                     .set_nodelay(true)
                     .context("failed to set socket option")?;
 
                 let ctx = RequestContext::new(
                     session_id,
-                    ConnectionInfo {
-                        addr: peer_addr,
-                        extra: None,
-                    },
+                    conn_info.clone(),
                     crate::metrics::Protocol::SniRouter,
                 );
-                handle_client(ctx, dest_suffix, tls_config, compute_tls_config, socket).await
+                handle_client(
+                    ctx,
+                    &router,
+                    conn_info,
+                    local_addr,
+                    tls_config,
+                    compute_tls_config,
+                    socket,
+                )
+                .await
             }
             .unwrap_or_else(|e| {
                 if let Some(FirstMessage(io_error)) = e.downcast_ref() {
@@ -302,41 +487,39 @@ async fn ssl_handshake<S: AsyncRead + AsyncWrite + Unpin>(
 
 async fn handle_client(
     ctx: RequestContext,
-    dest_suffix: Arc<String>,
+    router: &Router,
+    conn_info: ConnectionInfo,
+    local_addr: SocketAddr,
     tls_config: Arc<rustls::ServerConfig>,
     compute_tls_config: Option<Arc<rustls::ClientConfig>>,
     stream: impl AsyncRead + AsyncWrite + Unpin,
 ) -> anyhow::Result<()> {
     let mut tls_stream = ssl_handshake(&ctx, stream, tls_config).await?;
 
-    // Cut off first part of the SNI domain
-    // We receive required destination details in the format of
-    //   `{k8s_service_name}--{k8s_namespace}--{port}.non-sni-domain`
-    let sni = tls_stream
-        .get_ref()
-        .1
-        .server_name()
-        .ok_or(anyhow!("SNI missing"))?;
-    let dest: Vec<&str> = sni
-        .split_once('.')
-        .context("invalid SNI")?
-        .0
-        .splitn(3, "--")
-        .collect();
-    let port = dest[2].parse::<u16>().context("invalid port")?;
-    let destination = format!("{}.{}.{}:{}", dest[0], dest[1], dest_suffix, port);
-
-    info!("destination: {}", destination);
-
-    let mut client = tokio::net::TcpStream::connect(&destination).await?;
+    let conn = &tls_stream.get_ref().1;
+    let dest = router.destination(&ClientInfo {
+        sni: conn.server_name(),
+        alpn: conn.alpn_protocol(),
+        extra: conn_info.extra.as_ref(),
+    })?;
+
+    info!(
+        route_id = dest.route_id.as_deref(),
+        "destination: {}", dest.addr
+    );
+
+    let mut client = tokio::net::TcpStream::connect(&dest.addr).await?;
+
+    if dest.proxy_protocol {
+        let header = encode_proxy_protocol(&conn_info, local_addr);
+        client.write_all(&header).await?;
+    }
 
     let client = if let Some(compute_tls_config) = compute_tls_config {
         info!("upgrading TLS");
 
         // send SslRequest
-        client
-            .write_all(b"\x00\x00\x00\x08\x04\xd2\x16\x2f")
-            .await?;
+        client.write_all(&SSL_REQUEST).await?;
 
         // wait for S/N respons
         let mut resp = b'N';
@@ -346,7 +529,7 @@ async fn handle_client(
         ensure!(resp == b'S', "compute refused TLS");
 
         // upgrade to TLS.
-        let domain = DnsName::try_from(destination)?;
+        let domain = DnsName::try_from(dest.server_name)?;
         let domain = rustls::pki_types::ServerName::DnsName(domain);
         let client = TlsConnector::from(compute_tls_config)
             .connect(domain, client)
@@ -0,0 +1,672 @@
+//! Dynamic routes of the router, managed over HTTP.
+//!
+//! A route matches the connections by their SNI hostname, the ALPN protocol
+//! negotiated with the client, and the private link metadata from the PROXY
+//! protocol v2 header. The connections are sent to one of the targets of the
+//! most specific route that matches, picked by weight among the healthy ones.
+//!
+//! The routes changed over HTTP are written back to the routes file, if the
+//! table was loaded from one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use anyhow::{Context, anyhow, bail, ensure};
+use arc_swap::ArcSwap;
+use camino::{Utf8Path, Utf8PathBuf};
+use http_utils::endpoint::{self, auth_middleware, check_permission_with, request_span};
+use http_utils::error::ApiError;
+use http_utils::json::{json_request, json_response};
+use http_utils::request::get_request_param;
+use http_utils::{RequestExt, RouterBuilder, RouterService};
+use hyper0::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utils::auth::{AuthError, Scope, SwappableJwtAuth};
+use utils::crashsafe;
+
+use super::health::TargetHealth;
+use crate::protocol2::ConnectionInfoExtra;
+
+const MAX_ROUTE_ID_LEN: usize = 128;
+
+/// SNI hostname of a route. `*.domain` matches the hostnames with one more label than `domain`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) enum SniPattern {
+    Exact(String),
+    Wildcard(String),
+}
+
+impl SniPattern {
+    fn matches(&self, sni: &str) -> bool {
+        match self {
+            SniPattern::Exact(hostname) => hostname.eq_ignore_ascii_case(sni),
+            SniPattern::Wildcard(domain) => sni.split_once('.').is_some_and(|(label, rest)| {
+                !label.is_empty() && domain.eq_ignore_ascii_case(rest)
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for SniPattern {
+    type Error = anyhow::Error;
+
+    fn try_from(pattern: String) -> anyhow::Result<Self> {
+        let pattern = pattern.to_ascii_lowercase();
+        let (wildcard, hostname) = match pattern.strip_prefix("*.") {
+            Some(domain) => (true, domain),
+            None => (false, pattern.as_str()),
+        };
+        ensure!(
+            !hostname.is_empty()
+                && hostname
+                    .split('.')
+                    .all(|label| !label.is_empty() && !label.contains('*')),
+            "invalid SNI pattern {pattern:?}"
+        );
+
+        let hostname = hostname.to_owned();
+        Ok(if wildcard {
+            SniPattern::Wildcard(hostname)
+        } else {
+            SniPattern::Exact(hostname)
+        })
+    }
+}
+
+impl From<SniPattern> for String {
+    fn from(pattern: SniPattern) -> Self {
+        match pattern {
+            SniPattern::Exact(hostname) => hostname,
+            SniPattern::Wildcard(domain) => format!("*.{domain}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Route {
+    /// Match the SNI hostname, exactly or with a `*.` wildcard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sni: Option<SniPattern>,
+    /// Match the ALPN protocol negotiated with the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alpn: Option<String>,
+    /// Match the AWS VPC endpoint id from the PROXY protocol header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    vpce_id: Option<String>,
+    /// Match the Azure private endpoint link id from the PROXY protocol header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    azure_link_id: Option<u32>,
+    targets: Vec<Target>,
+    /// Relay the address of the client to the targets with a PROXY protocol v2 header.
+    #[serde(default)]
+    proxy_protocol: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Target {
+    /// `host:port` of the backend.
+    addr: String,
+    /// Share of the connections, relative to the other targets of the route.
+    /// The targets without weight get no new connections.
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// What the router knows about a client connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ClientInfo<'a> {
+    pub(crate) sni: Option<&'a str>,
+    pub(crate) alpn: Option<&'a [u8]>,
+    pub(crate) extra: Option<&'a ConnectionInfoExtra>,
+}
+
+impl Route {
+    fn validate(&self) -> anyhow::Result<()> {
+        ensure!(!self.targets.is_empty(), "route has no targets");
+        ensure!(
+            self.targets.iter().any(|target| target.weight > 0),
+            "route has no targets with weight"
+        );
+        for target in &self.targets {
+            let port = target
+                .addr
+                .rsplit_once(':')
+                .and_then(|(host, port)| (!host.is_empty()).then_some(port));
+            ensure!(
+                port.is_some_and(|port| port.parse::<u16>().is_ok()),
+                "invalid target address {:?}, expected host:port",
+                target.addr
+            );
+        }
+        Ok(())
+    }
+
+    fn matches(&self, client: &ClientInfo<'_>) -> bool {
+        let sni = match &self.sni {
+            None => true,
+            Some(pattern) => client.sni.is_some_and(|sni| pattern.matches(sni)),
+        };
+        let alpn = match &self.alpn {
+            None => true,
+            Some(alpn) => client.alpn == Some(alpn.as_bytes()),
+        };
+        let vpce_id = match &self.vpce_id {
+            None => true,
+            Some(id) => matches!(
+                client.extra,
+                Some(ConnectionInfoExtra::Aws { vpce_id }) if vpce_id == id
+            ),
+        };
+        let azure_link_id = match self.azure_link_id {
+            None => true,
+            Some(id) => matches!(
+                client.extra,
+                Some(ConnectionInfoExtra::Azure { link_id }) if *link_id == id
+            ),
+        };
+        sni && alpn && vpce_id && azure_link_id
+    }
+
+    /// Exact SNI hostnames win over wildcards, and then the routes with more conditions.
+    fn specificity(&self) -> (u8, usize) {
+        let sni = match &self.sni {
+            Some(SniPattern::Exact(_)) => 2,
+            Some(SniPattern::Wildcard(_)) => 1,
+            None => 0,
+        };
+        let conditions = [
+            self.alpn.is_some(),
+            self.vpce_id.is_some(),
+            self.azure_link_id.is_some(),
+        ];
+        (sni, conditions.into_iter().filter(|c| *c).count())
+    }
+}
+
+/// Where a connection is sent.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Destination {
+    pub(crate) route_id: Option<String>,
+    pub(crate) addr: String,
+    /// Name of the destination for TLS.
+    pub(crate) server_name: String,
+    pub(crate) proxy_protocol: bool,
+}
+
+pub(crate) type Routes = BTreeMap<String, Route>;
+
+#[derive(Default)]
+pub(crate) struct RouteTable {
+    routes: ArcSwap<Routes>,
+    /// The file the routes were loaded from, which keeps them across restarts.
+    path: Option<Utf8PathBuf>,
+    /// Serializes the updates, so that the file gets the latest routes.
+    updates: tokio::sync::Mutex<()>,
+    pub(crate) health: TargetHealth,
+}
+
+impl RouteTable {
+    pub(crate) fn new(routes: Routes) -> anyhow::Result<Self> {
+        for (id, route) in &routes {
+            validate_id(id)?;
+            route
+                .validate()
+                .with_context(|| format!("invalid route {id}"))?;
+        }
+        Ok(Self {
+            routes: ArcSwap::from_pointee(routes),
+            ..Self::default()
+        })
+    }
+
+    /// Read the routes from a JSON file, which maps the ids of the routes to them.
+    /// The updates of the routes are written back to the file.
+    pub(crate) fn load(path: &Utf8Path) -> anyhow::Result<Self> {
+        let routes = std::fs::read(path)
+            .with_context(|| format!("failed to read the routes from {path}"))?;
+        let routes = serde_json::from_slice(&routes)
+            .with_context(|| format!("failed to parse the routes from {path}"))?;
+        Ok(Self {
+            path: Some(path.to_owned()),
+            ..Self::new(routes)?
+        })
+    }
+
+    /// Pick the destination of the client from the most specific route that matches.
+    pub(crate) fn resolve(&self, client: &ClientInfo<'_>) -> Option<Destination> {
+        let routes = self.routes.load();
+        // the first of the routes with the same specificity, by id.
+        let (id, route) = routes
+            .iter()
+            .filter(|(_, route)| route.matches(client))
+            .rev()
+            .max_by_key(|(_, route)| route.specificity())?;
+
+        let target = self.pick_target(&route.targets)?;
+        let server_name = match client.sni {
+            // backends like proxies need the hostname the client connected to.
+            Some(sni) => sni.to_owned(),
+            None => target
+                .addr
+                .rsplit_once(':')
+                .map_or(target.addr.as_str(), |(host, _)| host)
+                .to_owned(),
+        };
+        Some(Destination {
+            route_id: Some(id.clone()),
+            addr: target.addr.clone(),
+            server_name,
+            proxy_protocol: route.proxy_protocol,
+        })
+    }
+
+    /// Pick a target by weight among the healthy ones, or among all of them if
+    /// none of them is healthy.
+    fn pick_target<'a>(&self, targets: &'a [Target]) -> Option<&'a Target> {
+        let mut candidates: Vec<_> = targets
+            .iter()
+            .filter(|target| target.weight > 0 && self.health.is_healthy(&target.addr))
+            .collect();
+        if candidates.is_empty() {
+            warn!("none of the targets of the route are healthy");
+            candidates = targets.iter().filter(|target| target.weight > 0).collect();
+        }
+
+        let total: u64 = candidates
+            .iter()
+            .map(|target| u64::from(target.weight))
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let mut point = rand::random_range(0..total);
+        candidates.into_iter().find(|target| {
+            let weight = u64::from(target.weight);
+            if point < weight {
+                return true;
+            }
+            point -= weight;
+            false
+        })
+    }
+
+    /// The addresses of all the targets, and whether they get a PROXY protocol header.
+    pub(crate) fn targets(&self) -> HashMap<String, bool> {
+        let mut targets = HashMap::new();
+        for route in self.routes.load().values() {
+            for target in &route.targets {
+                *targets.entry(target.addr.clone()).or_default() |= route.proxy_protocol;
+            }
+        }
+        targets
+    }
+
+    async fn upsert(&self, id: String, route: Route) -> anyhow::Result<()> {
+        self.update(|routes| {
+            routes.insert(id, route);
+        })
+        .await
+    }
+
+    async fn remove(&self, id: &str) -> anyhow::Result<bool> {
+        let mut removed = false;
+        self.update(|routes| removed = routes.remove(id).is_some())
+            .await?;
+        Ok(removed)
+    }
+
+    /// Change the routes, after writing them to the routes file, if any.
+    async fn update(&self, f: impl FnOnce(&mut Routes)) -> anyhow::Result<()> {
+        let _guard = self.updates.lock().await;
+        let mut routes = Routes::clone(&self.routes.load());
+        f(&mut routes);
+
+        if let Some(path) = &self.path {
+            let content = serde_json::to_vec_pretty(&routes)?;
+            let tmp_path = crashsafe::path_with_suffix_extension(path, "tmp");
+            let final_path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                crashsafe::overwrite(&final_path, &tmp_path, &content)
+            })
+            .await?
+            .with_context(|| format!("failed to write the routes to {path}"))?;
+        }
+
+        self.routes.store(Arc::new(routes));
+        Ok(())
+    }
+}
+
+fn validate_id(id: &str) -> anyhow::Result<()> {
+    if id.is_empty()
+        || id.len() > MAX_ROUTE_ID_LEN
+        || !id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+    {
+        bail!("invalid route id {id:?}");
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TargetStatus {
+    healthy: bool,
+}
+
+/// The routes API needs a token with the admin scope, if the auth is enabled.
+fn check_permission(req: &Request<Body>) -> Result<(), ApiError> {
+    check_permission_with(req, |claims| match claims.scope {
+        Scope::Admin => Ok(()),
+        _ => Err(AuthError("routes API requires the admin scope".into())),
+    })
+}
+
+async fn list_routes_handler(
+    req: Request<Body>,
+    table: Arc<RouteTable>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&req)?;
+    json_response(StatusCode::OK, &**table.routes.load())
+}
+
+async fn put_route_handler(
+    mut req: Request<Body>,
+    table: Arc<RouteTable>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&req)?;
+    let id = get_request_param(&req, "route_id")?.to_owned();
+    validate_id(&id).map_err(ApiError::BadRequest)?;
+    let route: Route = json_request(&mut req).await?;
+    route.validate().map_err(ApiError::BadRequest)?;
+
+    info!(%id, ?route, "updating route");
+    table
+        .upsert(id, route.clone())
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    json_response(StatusCode::OK, route)
+}
+
+async fn delete_route_handler(
+    req: Request<Body>,
+    table: Arc<RouteTable>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&req)?;
+    let id = get_request_param(&req, "route_id")?;
+    let removed = table
+        .remove(id)
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    if !removed {
+        return Err(ApiError::NotFound(anyhow!("route {id} not found").into()));
+    }
+
+    info!(id, "removed route");
+    json_response(StatusCode::OK, ())
+}
+
+async fn list_targets_handler(
+    req: Request<Body>,
+    table: Arc<RouteTable>,
+) -> Result<Response<Body>, ApiError> {
+    check_permission(&req)?;
+    let targets: BTreeMap<_, _> = table
+        .targets()
+        .into_keys()
+        .map(|addr| {
+            let healthy = table.health.is_healthy(&addr);
+            (addr, TargetStatus { healthy })
+        })
+        .collect();
+    json_response(StatusCode::OK, targets)
+}
+
+fn make_router(
+    table: Arc<RouteTable>,
+    auth: Option<Arc<SwappableJwtAuth>>,
+) -> RouterBuilder<Body, ApiError> {
+    let mut router = endpoint::make_router();
+    if auth.is_some() {
+        router = router.middleware(auth_middleware(|request| {
+            // Option<Arc<SwappableJwtAuth>> is always provided as data below, hence unwrap().
+            request
+                .data::<Option<Arc<SwappableJwtAuth>>>()
+                .unwrap()
+                .as_deref()
+        }));
+    }
+
+    let list = table.clone();
+    let put = table.clone();
+    let delete = table.clone();
+    router
+        .data(auth)
+        .get("/v1/routes", move |r| {
+            let table = list.clone();
+            request_span(r, move |r| list_routes_handler(r, table))
+        })
+        .put("/v1/routes/:route_id", move |r| {
+            let table = put.clone();
+            request_span(r, move |r| put_route_handler(r, table))
+        })
+        .delete("/v1/routes/:route_id", move |r| {
+            let table = delete.clone();
+            request_span(r, move |r| delete_route_handler(r, table))
+        })
+        .get("/v1/targets", move |r| {
+            let table = table.clone();
+            request_span(r, move |r| list_targets_handler(r, table))
+        })
+}
+
+/// Serve the API which manages the routes. With `auth`, the requests need a
+/// JWT with the admin scope.
+pub(crate) async fn task_main(
+    listener: TcpListener,
+    table: Arc<RouteTable>,
+    auth: Option<Arc<SwappableJwtAuth>>,
+) -> anyhow::Result<Infallible> {
+    scopeguard::defer! {
+        info!("routes api has shut down");
+    }
+
+    let service = || RouterService::new(make_router(table, auth).build()?);
+
+    hyper0::Server::from_tcp(listener)?
+        .serve(service().map_err(|e| anyhow!(e))?)
+        .await?;
+
+    bail!("hyper server without shutdown handling cannot shutdown successfully");
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn table(routes: serde_json::Value) -> RouteTable {
+        RouteTable::new(serde_json::from_value(routes).unwrap()).unwrap()
+    }
+
+    fn resolve(table: &RouteTable, client: ClientInfo<'_>) -> Option<String> {
+        table.resolve(&client).map(|dest| dest.addr)
+    }
+
+    #[test]
+    fn sni_patterns() {
+        let exact = SniPattern::try_from("Ep-1.Example.com".to_owned()).unwrap();
+        assert!(exact.matches("ep-1.example.COM"));
+        assert!(!exact.matches("ep-2.example.com"));
+
+        let wildcard = SniPattern::try_from("*.example.com".to_owned()).unwrap();
+        assert!(wildcard.matches("ep-1.example.com"));
+        assert!(!wildcard.matches("example.com"));
+        assert!(!wildcard.matches("a.ep-1.example.com"));
+        assert!(!wildcard.matches(".example.com"));
+        assert_eq!(String::from(wildcard), "*.example.com");
+
+        for invalid in ["", "*", "*.", "a.*.com", "a..com", "*example.com"] {
+            SniPattern::try_from(invalid.to_owned()).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn invalid_routes() {
+        for routes in [
+            json!({ "r": { "targets": [] } }),
+            json!({ "r": { "targets": [{ "addr": "backend" }] } }),
+            json!({ "r": { "targets": [{ "addr": ":5432" }] } }),
+            json!({ "r": { "targets": [{ "addr": "backend:5432", "weight": 0 }] } }),
+            json!({ "r/1": { "targets": [{ "addr": "backend:5432" }] } }),
+        ] {
+            let routes = serde_json::from_value(routes).unwrap();
+            RouteTable::new(routes).unwrap_err();
+        }
+
+        serde_json::from_value::<Routes>(json!({
+            "r": { "targets": [{ "addr": "backend:5432" }], "unknown": true }
+        }))
+        .unwrap_err();
+    }
+
+    #[test]
+    fn most_specific_route() {
+        let table = table(json!({
+            "default": { "targets": [{ "addr": "default:5432" }] },
+            "wildcard": {
+                "sni": "*.eu-west-1.example.com",
+                "targets": [{ "addr": "old-cluster:5432" }],
+            },
+            "exact": {
+                "sni": "ep-1.eu-west-1.example.com",
+                "targets": [{ "addr": "new-cluster:5432" }],
+            },
+            "vpce": {
+                "sni": "*.eu-west-1.example.com",
+                "vpce_id": "vpce-1",
+                "targets": [{ "addr": "private:5432" }],
+            },
+            "alpn": {
+                "alpn": "custom",
+                "targets": [{ "addr": "custom:5432" }],
+            },
+        }));
+
+        let sni = |sni| ClientInfo {
+            sni: Some(sni),
+            ..ClientInfo::default()
+        };
+        assert_eq!(
+            resolve(&table, sni("ep-1.eu-west-1.example.com")).unwrap(),
+            "new-cluster:5432"
+        );
+        assert_eq!(
+            resolve(&table, sni("ep-2.eu-west-1.example.com")).unwrap(),
+            "old-cluster:5432"
+        );
+        assert_eq!(
+            resolve(&table, sni("ep-2.us-east-1.example.com")).unwrap(),
+            "default:5432"
+        );
+
+        let vpce = ConnectionInfoExtra::Aws {
+            vpce_id: "vpce-1".into(),
+        };
+        let client = ClientInfo {
+            extra: Some(&vpce),
+            ..sni("ep-2.eu-west-1.example.com")
+        };
+        assert_eq!(resolve(&table, client).unwrap(), "private:5432");
+
+        let client = ClientInfo {
+            alpn: Some(b"custom"),
+            ..ClientInfo::default()
+        };
+        assert_eq!(resolve(&table, client).unwrap(), "custom:5432");
+
+        let dest = table.resolve(&sni("ep-1.eu-west-1.example.com")).unwrap();
+        assert_eq!(dest.route_id.as_deref(), Some("exact"));
+        assert_eq!(dest.server_name, "ep-1.eu-west-1.example.com");
+    }
+
+    #[test]
+    fn weighted_targets() {
+        let table = table(json!({
+            "r": {
+                "targets": [
+                    { "addr": "drained:5432", "weight": 0 },
+                    { "addr": "unhealthy:5432", "weight": 10 },
+                    { "addr": "healthy:5432", "weight": 1 },
+                ],
+            },
+        }));
+        assert_eq!(table.targets().len(), 3);
+
+        for _ in 0..5 {
+            table.health.record("unhealthy:5432", false);
+        }
+        for _ in 0..10 {
+            let dest = resolve(&table, ClientInfo::default()).unwrap();
+            assert_eq!(dest, "healthy:5432");
+        }
+
+        // the unhealthy targets are used if there is nothing else.
+        table.health.record("healthy:5432", false);
+        table.health.record("healthy:5432", false);
+        for _ in 0..10 {
+            let dest = resolve(&table, ClientInfo::default()).unwrap();
+            assert_ne!(dest, "drained:5432");
+        }
+    }
+
+    #[tokio::test]
+    async fn update_routes() {
+        let table = RouteTable::default();
+        assert_eq!(resolve(&table, ClientInfo::default()), None);
+
+        let route: Route =
+            serde_json::from_value(json!({ "targets": [{ "addr": "backend:5432" }] })).unwrap();
+        table.upsert("r".to_owned(), route).await.unwrap();
+        assert_eq!(
+            resolve(&table, ClientInfo::default()).unwrap(),
+            "backend:5432"
+        );
+
+        assert!(table.remove("r").await.unwrap());
+        assert!(!table.remove("r").await.unwrap());
+        assert_eq!(resolve(&table, ClientInfo::default()), None);
+    }
+
+    #[tokio::test]
+    async fn persist_routes() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let path = dir.path().join("routes.json");
+        std::fs::write(
+            &path,
+            json!({ "a": { "targets": [{ "addr": "a:5432" }] } }).to_string(),
+        )
+        .unwrap();
+
+        let table = RouteTable::load(&path).unwrap();
+        let route: Route =
+            serde_json::from_value(json!({ "targets": [{ "addr": "b:5432" }] })).unwrap();
+        table.upsert("b".to_owned(), route).await.unwrap();
+        table.remove("a").await.unwrap();
+
+        let reloaded = RouteTable::load(&path).unwrap();
+        assert_eq!(**reloaded.routes.load(), **table.routes.load());
+        assert_eq!(resolve(&reloaded, ClientInfo::default()).unwrap(), "b:5432");
+    }
+}
@@ -5,11 +5,8 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-#[cfg(any(test, feature = "testing"))]
-use anyhow::Context;
-use anyhow::{bail, ensure};
+use anyhow::{Context, bail, ensure};
 use arc_swap::ArcSwapOption;
-#[cfg(any(test, feature = "testing"))]
 use camino::Utf8PathBuf;
 use futures::future::Either;
 use itertools::{Itertools, Position};
@@ -21,6 +18,7 @@
 use tokio::task::JoinSet;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
+use utils::auth::{JwtAuth, SwappableJwtAuth};
 use utils::sentry_init::init_sentry;
 use utils::{project_build_tag, project_git_version};
 
@@ -29,19 +27,25 @@
 use crate::auth::backend::local::LocalBackend;
 use crate::auth::backend::{ConsoleRedirectBackend, MaybeOwned};
 use crate::batch::BatchQueue;
-use crate::cancellation::{CancellationHandler, CancellationProcessor};
-#[cfg(feature = "rest_broker")]
-use crate::config::RestConfig;
+use crate::cache::query::QueryCache;
+use crate::cancellation::forward::{CancelForwardPeer, CancelForwarder};
+use crate::cancellation::{CancellationHandler, CancellationProcessor, MAX_CLUSTER_ID};
 #[cfg(any(test, feature = "testing"))]
 use crate::config::refresh_config_loop;
 use crate::config::{
     self, AuthenticationConfig, CacheOptions, ComputeConfig, HttpConfig, ProjectInfoCacheOptions,
     ProxyConfig, ProxyProtocolV2, remote_storage_from_toml,
 };
+#[cfg(feature = "rest_broker")]
+use crate::config::{EndpointSet, RestConfig};
 use crate::context::parquet::ParquetUploadArgs;
 use crate::http::health_server::AppMetrics;
 use crate::metrics::{Metrics, ServiceInfo};
-use crate::rate_limiter::{EndpointRateLimiter, RateBucketInfo, WakeComputeRateLimiter};
+use crate::pglb::drain::Drain;
+use crate::pglb::transaction_pool::TransactionPool;
+use crate::rate_limiter::{
+    EndpointRateLimiter, RateBucketInfo, SessionLimiter, WakeComputeRateLimiter,
+};
 use crate::redis::connection_with_credentials_provider::ConnectionWithCredentialsProvider;
 use crate::redis::kv_ops::RedisKVClient;
 use crate::redis::{elasticache, notifications};
@@ -69,6 +73,9 @@ enum AuthBackendType {
     #[clap(alias("link"))]
     ConsoleRedirect,
 
+    /// Endpoints are served from `--control-plane-file`.
+    File,
+
     #[cfg(any(test, feature = "testing"))]
     Postgres,
 
@@ -92,12 +99,22 @@ struct ProxyCliArgs {
     #[clap(long, default_value = "./local_proxy.json")]
     #[cfg(any(test, feature = "testing"))]
     config_path: Utf8PathBuf,
+    /// Path of the endpoints file (used for file auth backend), in TOML or JSON format
+    #[clap(long)]
+    control_plane_file: Option<Utf8PathBuf>,
+    /// How often to check the endpoints file for changes
+    #[clap(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    control_plane_file_poll_interval: Duration,
     /// listen for management callback connection on ip:port
     #[clap(short, long, default_value = "127.0.0.1:7000")]
     mgmt: SocketAddr,
     /// listen for incoming http connections (metrics, etc) on ip:port
     #[clap(long, default_value = "127.0.0.1:7001")]
     http: SocketAddr,
+    /// path to the public key to verify the JWTs of the drain requests, which need the admin
+    /// scope; without it, draining over http is only served on loopback
+    #[clap(long)]
+    http_auth_public_key_path: Option<Utf8PathBuf>,
     /// listen for incoming wss connections on ip:port
     #[clap(long)]
     wss: Option<SocketAddr>,
@@ -122,6 +139,9 @@ struct ProxyCliArgs {
     /// if this is not local proxy, this toggles whether we accept jwt or passwords for http
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     is_auth_broker: bool,
+    /// offer SASL OAUTHBEARER alongside SCRAM for postgres connections, accepting jwts as credentials
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    accept_oauthbearer: bool,
     /// path to TLS key for client postgres connections
     ///
     /// tls-key and tls-cert are for backwards compatibility, we can put all certs in one dir
@@ -135,6 +155,9 @@ struct ProxyCliArgs {
     /// Allow writing TLS session keys to the given file pointed to by the environment variable `SSLKEYLOGFILE`.
     #[clap(long, alias = "allow-ssl-keylogfile")]
     allow_tls_keylogfile: bool,
+    /// Ask postgres clients for a TLS certificate, which endpoints may accept in place of a password.
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    tls_client_cert_auth: bool,
     /// path to directory with TLS certificates for client postgres connections
     #[clap(long)]
     certs_dir: Option<PathBuf>,
@@ -173,6 +196,20 @@ struct ProxyCliArgs {
     /// Cancellation ops batch size for redis
     #[clap(long, default_value_t = 8)]
     cancellation_batch_size: usize,
+    /// Id of this proxy cluster, encoded into the cancel keys so that the cancel
+    /// requests can be forwarded to the cluster which issued the key
+    #[clap(long, value_parser = clap::value_parser!(u8).range(..=i64::from(MAX_CLUSTER_ID)))]
+    cancel_cluster_id: Option<u8>,
+    /// Another proxy cluster to forward the cancel requests for its keys to,
+    /// in the form `<cluster id>=<url>`. Can be given multiple times
+    #[clap(long)]
+    cancel_forward_peer: Vec<CancelForwardPeer>,
+    /// Secret shared by the proxy clusters to sign the forwarded cancel requests
+    #[clap(long, env = "NEON_PROXY_CANCEL_FORWARD_SECRET", hide_env_values = true)]
+    cancel_forward_secret: Option<String>,
+    /// listen for the cancel requests forwarded by other proxy clusters on ip:port
+    #[clap(long)]
+    cancel_forward_listen: Option<SocketAddr>,
     /// redis url for plain authentication
     #[clap(long, alias("redis-notifications"))]
     redis_plain: Option<String>,
@@ -231,6 +268,13 @@ struct ProxyCliArgs {
     #[clap(long, default_value = config::RetryConfig::WAKE_COMPUTE_DEFAULT_VALUES)]
     wake_compute_retry: String,
 
+    /// accept the permessage-deflate extension of websockets
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    websocket_compression: bool,
+    /// accept the `neon-mux-v1` websocket subprotocol, which carries several postgres sessions over one websocket
+    #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
+    websocket_multiplexing: bool,
+
     /// Configure if this is a private access proxy for the POC: In that case the proxy will ignore the IP allowlist
     #[clap(long, default_value_t = false, value_parser = clap::builder::BoolishValueParser::new(), action = clap::ArgAction::Set)]
     is_private_access_proxy: bool,
@@ -239,6 +283,18 @@ struct ProxyCliArgs {
     #[clap(value_enum, long, default_value_t = ProxyProtocolV2::Rejected)]
     proxy_protocol_v2: ProxyProtocolV2,
 
+    /// How many idle compute connections to keep for each endpoint, role and database,
+    /// for the clients which opt in to transaction pooling. 0 disables transaction pooling.
+    #[clap(long, default_value_t = 0)]
+    transaction_pool_max_idle_conns: usize,
+    /// How long compute connections of the transaction pool should remain idle for before closing
+    #[clap(long, default_value = "1m", value_parser = humantime::parse_duration)]
+    transaction_pool_idle_timeout: tokio::time::Duration,
+    /// On shutdown, how long to wait before closing the remaining client sessions
+    /// once they are idle between transactions. Without it, the sessions are left to finish on their own
+    #[clap(long, value_parser = humantime::parse_duration)]
+    drain_timeout: Option<Duration>,
+
     /// Time the proxy waits for the webauth session to be confirmed by the control plane.
     // TODO: rename to `console_redirect_confirmation_timeout`.
     #[clap(long, default_value = "2m", value_parser = humantime::parse_duration)]
@@ -266,6 +322,24 @@ struct ProxyCliArgs {
     #[clap(long, default_value = "apirest.")]
     #[cfg(feature = "rest_broker")]
     hostname_prefix: String,
+
+    /// Endpoints for which the GraphQL API of the rest broker is enabled: `*` for all of them,
+    /// or a comma separated list of endpoint ids
+    #[clap(long, default_value = "")]
+    #[cfg(feature = "rest_broker")]
+    rest_graphql_endpoints: String,
+
+    /// cache for the results of REST GET requests, the ttl being the longest a result can be
+    /// kept for (use `size=0` to disable)
+    #[clap(long, default_value = "size=10000,ttl=5m")]
+    #[cfg(feature = "rest_broker")]
+    rest_query_cache: String,
+
+    /// Endpoints for which the results of REST GET requests are cached: `*` for all of them,
+    /// or a comma separated list of endpoint ids
+    #[clap(long, default_value = "")]
+    #[cfg(feature = "rest_broker")]
+    rest_query_cache_endpoints: String,
 }
 
 #[derive(clap::Args, Clone, Copy, Debug)]
@@ -312,6 +386,10 @@ struct SqlOverHttpArgs {
 
     #[clap(long, default_value_t = 10 * 1024 * 1024)] // 10 MiB
     sql_over_http_max_response_size_bytes: usize,
+
+    /// How long an interactive transaction may stay idle between requests before it is rolled back
+    #[clap(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    sql_over_http_txn_session_idle_timeout: tokio::time::Duration,
 }
 
 #[derive(clap::Args, Clone, Debug)]
@@ -368,10 +446,24 @@ pub async fn run() -> anyhow::Result<()> {
     // Check that we can bind to address before further initialization
     info!("Starting http on {}", args.http);
     let http_listener = TcpListener::bind(args.http).await?.into_std()?;
+    let http_auth = match &args.http_auth_public_key_path {
+        Some(key_path) => {
+            let jwt_auth = JwtAuth::from_key_path(key_path)?;
+            Some(Arc::new(SwappableJwtAuth::new(jwt_auth)))
+        }
+        None => None,
+    };
 
     info!("Starting mgmt on {}", args.mgmt);
     let mgmt_listener = TcpListener::bind(args.mgmt).await?;
 
+    let cancel_forward_listener = if let Some(addr) = args.cancel_forward_listen {
+        info!("Starting cancel forwarding on {addr}");
+        Some(TcpListener::bind(addr).await?.into_std()?)
+    } else {
+        None
+    };
+
     let proxy_listener = if args.is_auth_broker {
         None
     } else {
@@ -418,7 +510,7 @@ pub async fn run() -> anyhow::Result<()> {
 
     let cancellation_token = CancellationToken::new();
 
-    let cancellation_handler = Arc::new(CancellationHandler::new(&config.connect_to_compute));
+    let cancellation_handler = Arc::new(build_cancellation_handler(&args, config)?);
 
     let endpoint_rate_limiter = Arc::new(EndpointRateLimiter::new_with_shards(
         RateBucketInfo::to_leaky_bucket(&args.endpoint_rps_limit)
@@ -455,6 +547,16 @@ pub async fn run() -> anyhow::Result<()> {
                 ));
             }
 
+            // reload the endpoints of the file auth backend when the file changes.
+            if let auth::Backend::ControlPlane(api, ()) = auth_backend
+                && let control_plane::client::ControlPlaneClient::File(api) = &**api
+            {
+                client_tasks.spawn(api.clone().watch(
+                    args.control_plane_file_poll_interval,
+                    cancellation_token.clone(),
+                ));
+            }
+
             // if auth backend is local, we need to load the config file
             #[cfg(any(test, feature = "testing"))]
             if let auth::Backend::Local(_) = &auth_backend {
@@ -488,10 +590,14 @@ pub async fn run() -> anyhow::Result<()> {
 
         let tls_config = super::pg_sni_router::parse_tls(&key_path, &cert_path)?;
 
-        let dest = Arc::new(dest);
+        let router = Arc::new(super::pg_sni_router::Router::new(
+            Some(dest),
+            Arc::default(),
+            ProxyProtocolV2::Rejected,
+        ));
 
         client_tasks.spawn(super::pg_sni_router::task_main(
-            dest.clone(),
+            router.clone(),
             tls_config.clone(),
             None,
             listen,
@@ -499,7 +605,7 @@ pub async fn run() -> anyhow::Result<()> {
         ));
 
         client_tasks.spawn(super::pg_sni_router::task_main(
-            dest,
+            router,
             tls_config,
             Some(config.connect_to_compute.tls.clone()),
             listen_tls,
@@ -513,6 +619,16 @@ pub async fn run() -> anyhow::Result<()> {
         args.region,
     ));
 
+    // drain the client sessions once we stop accepting new ones,
+    // either on SIGTERM or when requested through the http server.
+    tokio::spawn({
+        let cancellation_token = cancellation_token.clone();
+        async move {
+            cancellation_token.cancelled().await;
+            config.drain.start();
+        }
+    });
+
     // maintenance tasks. these never return unless there's an error
     let mut maintenance_tasks = JoinSet::new();
 
@@ -524,14 +640,28 @@ pub async fn run() -> anyhow::Result<()> {
     }));
     maintenance_tasks.spawn(http::health_server::task_main(
         http_listener,
+        cancellation_token.clone(),
         AppMetrics {
             jemalloc,
             neon_metrics,
             proxy: crate::metrics::Metrics::get(),
         },
+        http_auth,
     ));
     maintenance_tasks.spawn(control_plane::mgmt::task_main(mgmt_listener));
 
+    if let Some(listener) = cancel_forward_listener {
+        let Either::Left(auth_backend) = auth_backend else {
+            bail!("cancel forwarding is not supported by the console redirect auth backend");
+        };
+        maintenance_tasks.spawn(crate::cancellation::forward::task_main(
+            listener,
+            config,
+            auth_backend,
+            cancellation_handler.clone(),
+        ));
+    }
+
     // add a task to flush the db_schema cache every 10 minutes
     #[cfg(feature = "rest_broker")]
     if let Some(db_schema_cache) = &config.rest_config.db_schema_cache {
@@ -574,14 +704,38 @@ pub async fn run() -> anyhow::Result<()> {
             }
         }
 
+        let mut project_info_cache = None;
         #[allow(irrefutable_let_patterns)]
         if let Either::Left(auth::Backend::ControlPlane(api, ())) = &auth_backend
             && let crate::control_plane::client::ControlPlaneClient::ProxyV1(api) = &**api
         {
             // project info cache and invalidation of that cache.
             let cache = api.caches.project_info.clone();
-            maintenance_tasks.spawn(notifications::task_main(client, cache.clone()));
-            maintenance_tasks.spawn(async move { cache.gc_worker().await });
+            maintenance_tasks.spawn({
+                let cache = cache.clone();
+                async move { cache.gc_worker().await }
+            });
+            project_info_cache = Some(cache);
+        }
+
+        // query cache and purges of that cache.
+        #[cfg(feature = "rest_broker")]
+        let query_cache = config.rest_config.query_cache.clone();
+        #[cfg(not(feature = "rest_broker"))]
+        let query_cache: Option<Arc<QueryCache>> = None;
+        if let Some(purges) = query_cache.as_ref().and_then(|c| c.publish_purges()) {
+            maintenance_tasks.spawn(notifications::publish_query_cache_purges(
+                client.clone(),
+                purges,
+            ));
+        }
+
+        if project_info_cache.is_some() || query_cache.is_some() {
+            maintenance_tasks.spawn(notifications::task_main(
+                client,
+                project_info_cache,
+                query_cache,
+            ));
         }
     }
 
@@ -615,6 +769,37 @@ pub async fn run() -> anyhow::Result<()> {
 }
 
 /// ProxyConfig is created at proxy startup, and lives forever.
+fn build_cancellation_handler(
+    args: &ProxyCliArgs,
+    config: &'static ProxyConfig,
+) -> anyhow::Result<CancellationHandler> {
+    let handler = CancellationHandler::new(&config.connect_to_compute);
+
+    let Some(cluster_id) = args.cancel_cluster_id else {
+        ensure!(
+            args.cancel_forward_peer.is_empty() && args.cancel_forward_listen.is_none(),
+            "cancel forwarding requires cancel-cluster-id to be set"
+        );
+        return Ok(handler);
+    };
+
+    let forwarder = if args.cancel_forward_peer.is_empty() && args.cancel_forward_listen.is_none() {
+        None
+    } else {
+        let secret = args
+            .cancel_forward_secret
+            .as_deref()
+            .context("cancel forwarding requires cancel-forward-secret to be set")?;
+        Some(CancelForwarder::new(
+            args.cancel_forward_peer.clone(),
+            secret,
+        )?)
+    };
+
+    info!(cluster_id, "cancel keys are encoded with the cluster id");
+    Ok(handler.with_forwarding(cluster_id, forwarder))
+}
+
 fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
     let thread_pool = ThreadPool::new(args.scram_thread_pool_size);
     Metrics::get()
@@ -630,6 +815,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             cert_path,
             args.certs_dir.as_deref(),
             args.allow_tls_keylogfile,
+            args.tls_client_cert_auth,
         )?),
         (None, None) => None,
         _ => bail!("either both or neither tls-key and tls-cert must be specified"),
@@ -680,6 +866,8 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
 
     let http_config = HttpConfig {
         accept_websockets: !args.is_auth_broker,
+        websocket_compression: args.websocket_compression,
+        websocket_multiplexing: args.websocket_multiplexing,
         pool_options: GlobalConnPoolOptions {
             max_conns_per_endpoint: args.sql_over_http.sql_over_http_pool_max_conns_per_endpoint,
             gc_epoch: args.sql_over_http.sql_over_http_pool_gc_epoch,
@@ -692,6 +880,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         client_conn_threshold: args.sql_over_http.sql_over_http_client_conn_threshold,
         max_request_size_bytes: args.sql_over_http.sql_over_http_max_request_size_bytes,
         max_response_size_bytes: args.sql_over_http.sql_over_http_max_response_size_bytes,
+        txn_session_idle_timeout: args.sql_over_http.sql_over_http_txn_session_idle_timeout,
     };
     let authentication_config = AuthenticationConfig {
         jwks_cache: JwkCache::default(),
@@ -704,6 +893,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         accept_jwts: args.is_auth_broker,
         #[cfg(feature = "rest_broker")]
         accept_jwts: args.is_auth_broker || args.is_rest_broker,
+        accept_oauthbearer: args.accept_oauthbearer,
         console_redirect_confirmation_timeout: args.webauth_confirmation_timeout,
     };
 
@@ -724,11 +914,26 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
             None
         };
 
+        let query_cache_endpoints: EndpointSet = args.rest_query_cache_endpoints.parse()?;
+        let query_cache_config: CacheOptions = args.rest_query_cache.parse()?;
+        let query_cache = if args.is_rest_broker
+            && query_cache_endpoints != EndpointSet::None
+            && query_cache_config.size != Some(0)
+        {
+            info!("Using QueryCache with options={query_cache_config:?}");
+            Some(Arc::new(QueryCache::new(&query_cache_config)))
+        } else {
+            None
+        };
+
         RestConfig {
             is_rest_broker: args.is_rest_broker,
             db_schema_cache,
             max_schema_size: args.max_schema_size,
             hostname_prefix: args.hostname_prefix.clone(),
+            graphql_endpoints: args.rest_graphql_endpoints.parse()?,
+            query_cache,
+            query_cache_endpoints,
         }
     };
 
@@ -741,7 +946,7 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
     });
 
     match &args.auth_backend {
-        AuthBackendType::ControlPlane => {}
+        AuthBackendType::ControlPlane | AuthBackendType::File => {}
         #[cfg(any(test, feature = "testing"))]
         AuthBackendType::Postgres => {}
         #[cfg(any(test, feature = "testing"))]
@@ -761,6 +966,14 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
         wake_compute_retry_config: config::RetryConfig::parse(&args.wake_compute_retry)?,
         connect_compute_locks,
         connect_to_compute: compute_config,
+        session_limiter: SessionLimiter::new_with_shards(64),
+        transaction_pool: (args.transaction_pool_max_idle_conns > 0).then(|| {
+            TransactionPool::new(
+                args.transaction_pool_max_idle_conns,
+                args.transaction_pool_idle_timeout,
+            )
+        }),
+        drain: Drain::new(args.drain_timeout),
         greetings,
         #[cfg(feature = "testing")]
         disable_pg_session_jwt: false,
@@ -771,6 +984,9 @@ fn build_config(args: &ProxyCliArgs) -> anyhow::Result<&'static ProxyConfig> {
     let config = Box::leak(Box::new(config));
 
     tokio::spawn(config.connect_compute_locks.garbage_collect_worker());
+    if let Some(pool) = &config.transaction_pool {
+        tokio::spawn(pool.gc_worker());
+    }
 
     Ok(config)
 }
@@ -858,6 +1074,20 @@ fn build_auth_backend(
             Ok(Either::Left(config))
         }
 
+        AuthBackendType::File => {
+            let path = args
+                .control_plane_file
+                .clone()
+                .context("file auth backend requires --control-plane-file")?;
+            let api = control_plane::client::file::FileControlPlane::load(path)?;
+
+            let api = control_plane::client::ControlPlaneClient::File(api);
+            let auth_backend = auth::Backend::ControlPlane(MaybeOwned::Owned(api), ());
+            let config = Box::leak(Box::new(auth_backend));
+
+            Ok(Either::Left(config))
+        }
+
         AuthBackendType::ConsoleRedirect => {
             let wake_compute_cache_config: CacheOptions = args.wake_compute_cache.parse()?;
             let project_info_cache_config: ProjectInfoCacheOptions =
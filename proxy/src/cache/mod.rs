@@ -1,5 +1,6 @@
 pub(crate) mod common;
 pub(crate) mod node_info;
 pub(crate) mod project_info;
+pub(crate) mod query;
 
 pub(crate) use common::{Cached, ControlPlaneResult, CplaneExpiry};
@@ -285,9 +285,12 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: None,
             },
             RoleAccessControl {
                 secret: secret1.clone(),
+                client_key: None,
             },
         );
 
@@ -301,9 +304,12 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: None,
             },
             RoleAccessControl {
                 secret: secret2.clone(),
+                client_key: None,
             },
         );
 
@@ -328,9 +334,12 @@ async fn test_project_info_cache_settings() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: None,
             },
             RoleAccessControl {
                 secret: secret3.clone(),
+                client_key: None,
             },
         );
 
@@ -426,9 +435,12 @@ async fn test_caching_project_info_errors() {
                 allowed_vpce: Arc::new(vec![]),
                 flags: AccessBlockerFlags::default(),
                 rate_limits: EndpointRateLimitConfig::default(),
+                audit_log: None,
+                client_cert_auth: None,
             },
             RoleAccessControl {
                 secret: secret.clone(),
+                client_key: None,
             },
         );
         assert!(get_role_secret(&endpoint_id, &user1).is_err());
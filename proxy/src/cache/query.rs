@@ -0,0 +1,286 @@
+//! Cache of query results for read heavy endpoints.
+//!
+//! Entries are scoped to an endpoint so that they can all be purged when the
+//! endpoint is written to, either through this proxy or through another one
+//! (see [`crate::redis::notifications`]).
+//!
+//! The purges come from the REST writes and from the SQL over HTTP requests
+//! which are not read-only, as long as they reach a rest broker. The writes over
+//! postgres connections, or through the proxies without this cache, are only
+//! seen once the entries expire, so the TTL bounds how stale a result can be.
+
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http::HeaderMap;
+use http::header::CACHE_CONTROL;
+use moka::Expiry;
+use tokio::sync::mpsc;
+
+use crate::cache::common::{count_cache_insert, count_cache_outcome, eviction_listener};
+use crate::config::CacheOptions;
+use crate::intern::EndpointIdInt;
+use crate::metrics::{CacheKind, Metrics};
+
+/// Purges waiting to be published, dropped if the publisher lags behind.
+const PURGE_QUEUE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct QueryCacheKey {
+    pub(crate) endpoint: EndpointIdInt,
+    pub(crate) role: String,
+    /// Normalized query, along with everything that might change its result
+    /// for the same role, e.g. the jwt claims used by RLS policies.
+    pub(crate) query: String,
+    /// Query parameters, serialized.
+    pub(crate) params: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct CachedQueryResult {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Bytes,
+    ttl: Duration,
+}
+
+/// How a request wants the cache to be used, from its `Cache-Control` header.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CacheDirectives {
+    /// Whether a cached result can be returned (no `no-cache`).
+    pub(crate) lookup: bool,
+    /// How long to keep the result for, if it can be stored (no `no-store`).
+    pub(crate) ttl: Option<Duration>,
+}
+
+impl CacheDirectives {
+    /// Parse the `Cache-Control` directives of the request. `max-age` sets
+    /// the ttl of the stored result, capped to `max_ttl`.
+    pub(crate) fn from_headers(headers: &HeaderMap, max_ttl: Duration) -> Self {
+        let mut directives = Self {
+            lookup: true,
+            ttl: Some(max_ttl),
+        };
+
+        let values = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok());
+        for directive in values.flat_map(|v| v.split(',')).map(str::trim) {
+            match directive.split_once('=') {
+                Some((name, value)) if name.eq_ignore_ascii_case("max-age") => {
+                    if let (Some(ttl), Ok(max_age)) = (directives.ttl, value.trim().parse()) {
+                        directives.ttl = Some(ttl.min(Duration::from_secs(max_age)));
+                    }
+                }
+                None if directive.eq_ignore_ascii_case("no-cache") => directives.lookup = false,
+                None if directive.eq_ignore_ascii_case("no-store") => directives.ttl = None,
+                _ => {}
+            }
+        }
+
+        if directives.ttl == Some(Duration::ZERO) {
+            directives.ttl = None;
+        }
+        directives
+    }
+}
+
+struct QueryResultExpiry;
+
+impl Expiry<QueryCacheKey, Arc<CachedQueryResult>> for QueryResultExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &QueryCacheKey,
+        value: &Arc<CachedQueryResult>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &QueryCacheKey,
+        value: &Arc<CachedQueryResult>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+pub(crate) struct QueryCache {
+    cache: moka::sync::Cache<QueryCacheKey, Arc<CachedQueryResult>>,
+    /// Upper bound of the entries ttl.
+    max_ttl: Duration,
+    /// Purges to publish to the other proxies, if there is redis.
+    purges: OnceLock<mpsc::Sender<EndpointIdInt>>,
+}
+
+impl QueryCache {
+    pub(crate) fn new(config: &CacheOptions) -> Self {
+        let builder = moka::sync::Cache::builder()
+            .name("query")
+            .expire_after(QueryResultExpiry)
+            .support_invalidation_closures();
+        let builder = config.moka(builder);
+
+        if let Some(size) = config.size {
+            Metrics::get()
+                .cache
+                .capacity
+                .set(CacheKind::QueryResult, size as i64);
+        }
+
+        let builder = builder
+            .eviction_listener(|_k, _v, cause| eviction_listener(CacheKind::QueryResult, cause));
+
+        Self {
+            cache: builder.build(),
+            max_ttl: config.absolute_ttl.unwrap_or(Duration::from_secs(60)),
+            purges: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn max_ttl(&self) -> Duration {
+        self.max_ttl
+    }
+
+    pub(crate) fn get(&self, key: &QueryCacheKey) -> Option<Arc<CachedQueryResult>> {
+        count_cache_outcome(CacheKind::QueryResult, self.cache.get(key))
+    }
+
+    pub(crate) fn insert(
+        &self,
+        key: QueryCacheKey,
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Bytes,
+        ttl: Duration,
+    ) {
+        count_cache_insert(CacheKind::QueryResult);
+        let value = CachedQueryResult {
+            status,
+            headers,
+            body,
+            ttl: ttl.min(self.max_ttl),
+        };
+        self.cache.insert(key, Arc::new(value));
+    }
+
+    /// Drop the cached results of the endpoint, on this proxy only.
+    pub(crate) fn invalidate_endpoint(&self, endpoint: EndpointIdInt) {
+        if let Err(e) = self
+            .cache
+            .invalidate_entries_if(move |key, _| key.endpoint == endpoint)
+        {
+            tracing::error!("failed to invalidate query cache for endpoint {endpoint}: {e}");
+        }
+    }
+
+    /// Drop the cached results of the endpoint after a write, and tell the
+    /// other proxies to do the same.
+    pub(crate) fn purge_endpoint(&self, endpoint: EndpointIdInt) {
+        self.invalidate_endpoint(endpoint);
+        if let Some(purges) = self.purges.get()
+            && purges.try_send(endpoint).is_err()
+        {
+            tracing::warn!("query cache purge queue is full, not publishing purge of {endpoint}");
+        }
+    }
+
+    /// Start publishing purges, returning the queue to publish from.
+    pub(crate) fn publish_purges(&self) -> Option<mpsc::Receiver<EndpointIdInt>> {
+        let (tx, rx) = mpsc::channel(PURGE_QUEUE_SIZE);
+        self.purges.set(tx).ok()?;
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn directives(cache_control: &[&'static str]) -> CacheDirectives {
+        let mut headers = HeaderMap::new();
+        for value in cache_control {
+            headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        CacheDirectives::from_headers(&headers, Duration::from_secs(300))
+    }
+
+    #[test]
+    fn test_cache_directives() {
+        assert_eq!(
+            directives(&[]),
+            CacheDirectives {
+                lookup: true,
+                ttl: Some(Duration::from_secs(300)),
+            }
+        );
+        assert_eq!(
+            directives(&["max-age=10"]),
+            CacheDirectives {
+                lookup: true,
+                ttl: Some(Duration::from_secs(10)),
+            }
+        );
+        assert_eq!(
+            directives(&["max-age=3600"]),
+            CacheDirectives {
+                lookup: true,
+                ttl: Some(Duration::from_secs(300)),
+            }
+        );
+        assert_eq!(
+            directives(&["no-cache, max-age=5"]),
+            CacheDirectives {
+                lookup: false,
+                ttl: Some(Duration::from_secs(5)),
+            }
+        );
+        assert_eq!(
+            directives(&["no-store", "max-age=5"]),
+            CacheDirectives {
+                lookup: true,
+                ttl: None,
+            }
+        );
+        assert_eq!(
+            directives(&["max-age=0"]),
+            CacheDirectives {
+                lookup: true,
+                ttl: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalidate_endpoint() {
+        let cache = QueryCache::new(&"size=1000,ttl=1m".parse().unwrap());
+        let key = |endpoint: &str| QueryCacheKey {
+            endpoint: (&crate::types::EndpointId::from(endpoint)).into(),
+            role: "anon".to_string(),
+            query: "select 1".to_string(),
+            params: "[]".to_string(),
+        };
+
+        for endpoint in ["ep-foo", "ep-bar"] {
+            cache.insert(
+                key(endpoint),
+                200,
+                vec![],
+                Bytes::from_static(b"[]"),
+                Duration::from_secs(60),
+            );
+        }
+
+        cache.invalidate_endpoint(key("ep-foo").endpoint);
+        cache.cache.run_pending_tasks();
+        assert!(cache.get(&key("ep-foo")).is_none());
+        assert!(cache.get(&key("ep-bar")).is_some());
+    }
+}
@@ -0,0 +1,480 @@
+//! Forwarding of cancel requests between proxy clusters.
+//!
+//! The cancel keys are stored in the Redis of the proxy cluster which issued
+//! them, and carry the id of that cluster. A cancel request that lands in
+//! another cluster is relayed over HTTP to the issuing cluster, which checks
+//! the client against the access controls of the endpoint and cancels the query.
+//!
+//! The receiving cluster trusts the client address in the request, so the
+//! requests are signed with a secret shared by the clusters. Each request carries
+//! a timestamp and a random nonce, and is accepted once within `MAX_REQUEST_AGE`.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, anyhow, bail};
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
+use hmac::Mac as _;
+use http_utils::endpoint::{self, request_span};
+use http_utils::error::ApiError;
+use http_utils::json::json_response;
+use http_utils::{RouterBuilder, RouterService};
+use hyper0::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{CancelError, CancellationHandler};
+use crate::auth;
+use crate::config::ProxyConfig;
+use crate::context::RequestContext;
+use crate::ext::LockExt;
+use crate::http::ClientWithMiddleware;
+use crate::metrics::Protocol;
+use crate::pqproto::{CancelKeyData, id_to_cancel_key};
+use crate::protocol2::{ConnectionInfo, ConnectionInfoExtra};
+use crate::url::ApiUrl;
+
+const SIGNATURE_HEADER: &str = "neon-cancel-signature";
+/// Forwarded requests older than this are rejected, to limit replays.
+const MAX_REQUEST_AGE: Duration = Duration::from_secs(30);
+const MAX_REQUEST_SIZE: u64 = 1024;
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(5);
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+#[derive(Serialize, Deserialize)]
+struct ForwardedCancelRequest {
+    key: u64,
+    /// Address of the client which sent the cancel request.
+    peer_addr: IpAddr,
+    extra: Option<ConnectionInfoExtra>,
+    /// Unix timestamp of the request, in seconds.
+    timestamp: u64,
+    /// Tells apart the requests for the same key within a second, so that replays can be detected.
+    nonce: u64,
+}
+
+/// Another proxy cluster, in the format of `<cluster id>=<url>`.
+#[derive(Clone, Debug)]
+pub struct CancelForwardPeer {
+    cluster_id: u8,
+    url: ApiUrl,
+}
+
+impl FromStr for CancelForwardPeer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (cluster_id, url) = s.split_once('=').context("expected <cluster id>=<url>")?;
+        Ok(Self {
+            cluster_id: cluster_id.parse().context("invalid cluster id")?,
+            url: url.parse()?,
+        })
+    }
+}
+
+/// Relays the cancel requests for the sessions of other proxy clusters.
+pub struct CancelForwarder {
+    client: ClientWithMiddleware,
+    peers: HashMap<u8, ApiUrl>,
+    secret: Box<[u8]>,
+}
+
+impl CancelForwarder {
+    pub fn new(peers: Vec<CancelForwardPeer>, secret: &str) -> anyhow::Result<Self> {
+        if secret.is_empty() {
+            bail!("cancel forwarding requires a shared secret");
+        }
+        Ok(Self {
+            client: crate::http::new_client(),
+            peers: peers
+                .into_iter()
+                .map(|peer| (peer.cluster_id, peer.url))
+                .collect(),
+            secret: secret.as_bytes().into(),
+        })
+    }
+
+    pub(super) fn has_peer(&self, cluster_id: u8) -> bool {
+        self.peers.contains_key(&cluster_id)
+    }
+
+    fn sign(&self, body: &[u8]) -> String {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(body);
+        BASE64_STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    fn verify(&self, body: &[u8], signature: &str) -> bool {
+        let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+            return false;
+        };
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+
+    /// Relay the cancel request to the proxy cluster which issued the key.
+    pub(super) async fn forward(
+        &self,
+        cluster_id: u8,
+        key: CancelKeyData,
+        ctx: &RequestContext,
+    ) -> Result<(), CancelError> {
+        let Some(url) = self.peers.get(&cluster_id) else {
+            return Err(CancelError::NotFound);
+        };
+
+        let request = ForwardedCancelRequest {
+            key: key.0.get(),
+            peer_addr: ctx.peer_addr(),
+            extra: ctx.extra(),
+            timestamp: unix_timestamp(),
+            nonce: rand::random(),
+        };
+        let body = serde_json::to_vec(&request).expect("serialising to json should not fail");
+
+        let mut url = url.clone();
+        url.path_segments_mut()
+            .pop_if_empty()
+            .extend(["v1", "cancel"]);
+
+        info!(cluster_id, "forwarding cancel request for key {key}");
+        let response = self
+            .client
+            .post(url.into_inner())
+            .timeout(FORWARD_TIMEOUT)
+            .header(SIGNATURE_HEADER, self.sign(&body))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| {
+                warn!(cluster_id, "failed to forward cancel request: {e}");
+                CancelError::InternalError
+            })?;
+
+        match response.status() {
+            http::StatusCode::OK => Ok(()),
+            http::StatusCode::NOT_FOUND => Err(CancelError::NotFound),
+            http::StatusCode::TOO_MANY_REQUESTS => Err(CancelError::RateLimit),
+            http::StatusCode::FORBIDDEN => Err(CancelError::Rejected),
+            status => {
+                warn!(cluster_id, %status, "forwarded cancel request failed");
+                Err(CancelError::InternalError)
+            }
+        }
+    }
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+struct ForwardedCancelState {
+    check_ip_allowed: bool,
+    check_vpc_allowed: bool,
+    auth_backend: &'static auth::Backend<'static, ()>,
+    cancellation_handler: Arc<CancellationHandler>,
+    /// Signatures of the requests accepted within `MAX_REQUEST_AGE`, with their timestamps.
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ForwardedCancelState {
+    /// Whether the request was not accepted before.
+    fn first_seen(&self, signature: String, timestamp: u64) -> bool {
+        let now = unix_timestamp();
+        let mut seen = self.seen.lock_propagate_poison();
+        seen.retain(|_, t| now.abs_diff(*t) <= MAX_REQUEST_AGE.as_secs());
+        seen.insert(signature, timestamp).is_none()
+    }
+}
+
+async fn cancel_handler(
+    req: Request<Body>,
+    state: Arc<ForwardedCancelState>,
+) -> Result<Response<Body>, ApiError> {
+    let Some(forwarder) = &state.cancellation_handler.forwarder else {
+        return Err(ApiError::NotFound(
+            anyhow!("cancel forwarding is disabled").into(),
+        ));
+    };
+
+    let signature = req
+        .headers()
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| ApiError::Unauthorized("missing signature".to_owned()))?
+        .to_owned();
+
+    if hyper0::body::HttpBody::size_hint(req.body())
+        .upper()
+        .is_none_or(|size| size > MAX_REQUEST_SIZE)
+    {
+        return Err(ApiError::BadRequest(anyhow!("request is too large")));
+    }
+    let body = hyper0::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| ApiError::BadRequest(e.into()))?;
+
+    if !forwarder.verify(&body, &signature) {
+        return Err(ApiError::Unauthorized("invalid signature".to_owned()));
+    }
+
+    let request: ForwardedCancelRequest =
+        serde_json::from_slice(&body).map_err(|e| ApiError::BadRequest(e.into()))?;
+    if unix_timestamp().abs_diff(request.timestamp) > MAX_REQUEST_AGE.as_secs() {
+        return Err(ApiError::Unauthorized("request has expired".to_owned()));
+    }
+    if !state.first_seen(signature, request.timestamp) {
+        return Err(ApiError::Unauthorized("request was replayed".to_owned()));
+    }
+
+    let ctx = RequestContext::new(
+        uuid::Uuid::new_v4(),
+        ConnectionInfo {
+            addr: SocketAddr::new(request.peer_addr, 0),
+            extra: request.extra,
+        },
+        Protocol::Tcp,
+    );
+
+    let res = state
+        .cancellation_handler
+        .cancel_session_inner(
+            id_to_cancel_key(request.key),
+            ctx,
+            state.check_ip_allowed,
+            state.check_vpc_allowed,
+            state.auth_backend.get_api(),
+            false,
+        )
+        .await;
+
+    match res {
+        Ok(()) => json_response(StatusCode::OK, ()),
+        Err(CancelError::NotFound) => Err(ApiError::NotFound(anyhow!("key not found").into())),
+        Err(CancelError::RateLimit) => Err(ApiError::TooManyRequests("rate limit exceeded".into())),
+        Err(CancelError::AuthError(_) | CancelError::Rejected) => {
+            Err(ApiError::Forbidden("not allowed".to_owned()))
+        }
+        Err(e) => Err(ApiError::InternalServerError(e.into())),
+    }
+}
+
+fn make_router(state: Arc<ForwardedCancelState>) -> RouterBuilder<Body, ApiError> {
+    endpoint::make_router().post("/v1/cancel", move |r| {
+        let state = state.clone();
+        request_span(r, move |r| cancel_handler(r, state))
+    })
+}
+
+/// Serve the cancel requests forwarded by the other proxy clusters.
+pub async fn task_main(
+    listener: TcpListener,
+    config: &'static ProxyConfig,
+    auth_backend: &'static auth::Backend<'static, ()>,
+    cancellation_handler: Arc<CancellationHandler>,
+) -> anyhow::Result<Infallible> {
+    scopeguard::defer! {
+        info!("cancel forwarding server has shut down");
+    }
+
+    let auth_config = &config.authentication_config;
+    let state = Arc::new(ForwardedCancelState {
+        check_ip_allowed: auth_config.ip_allowlist_check_enabled,
+        check_vpc_allowed: auth_config.is_vpc_acccess_proxy,
+        auth_backend,
+        cancellation_handler,
+        seen: Mutex::default(),
+    });
+    serve(listener, state).await
+}
+
+async fn serve(
+    listener: TcpListener,
+    state: Arc<ForwardedCancelState>,
+) -> anyhow::Result<Infallible> {
+    let service = || RouterService::new(make_router(state).build()?);
+
+    hyper0::Server::from_tcp(listener)?
+        .serve(service().map_err(|e| anyhow!(e))?)
+        .await?;
+
+    bail!("hyper server without shutdown handling cannot shutdown successfully");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::backend::MaybeOwned;
+    use crate::cancellation::tests::{
+        accept_cancel, cancel_closure, cancellation_handler, control_plane, wait_for_key,
+    };
+    use crate::cancellation::{CancelError, key_with_cluster_id};
+    use crate::control_plane::client::ControlPlaneClient;
+    use crate::control_plane::client::file::FileControlPlane;
+    use crate::redis::mock;
+
+    /// Serve the cancel requests forwarded to the cluster of `cancellation_handler`.
+    fn serve_forwarded(
+        cancellation_handler: Arc<CancellationHandler>,
+        api: FileControlPlane,
+    ) -> ApiUrl {
+        let auth_backend = Box::leak(Box::new(auth::Backend::ControlPlane(
+            MaybeOwned::Owned(ControlPlaneClient::File(api)),
+            (),
+        )));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        let state = Arc::new(ForwardedCancelState {
+            check_ip_allowed: true,
+            check_vpc_allowed: false,
+            auth_backend,
+            cancellation_handler,
+            seen: Mutex::default(),
+        });
+        tokio::spawn(serve(listener, state));
+        url.parse().unwrap()
+    }
+
+    /// A cluster with id 1, which serves the forwarded cancel requests.
+    async fn issuing_cluster(api: FileControlPlane) -> (Arc<CancellationHandler>, ApiUrl) {
+        let forwarder = CancelForwarder::new(vec![], "secret").unwrap();
+        let handler = cancellation_handler(mock::serve().await).await;
+        let handler = Arc::new(handler.with_forwarding(1, Some(forwarder)));
+        let url = serve_forwarded(handler.clone(), api);
+        (handler, url)
+    }
+
+    async fn send(
+        url: &ApiUrl,
+        signer: &CancelForwarder,
+        request: &ForwardedCancelRequest,
+    ) -> http::StatusCode {
+        let body = serde_json::to_vec(request).unwrap();
+        let mut url = url.clone();
+        url.path_segments_mut()
+            .pop_if_empty()
+            .extend(["v1", "cancel"]);
+
+        signer
+            .client
+            .post(url.into_inner())
+            .header(SIGNATURE_HEADER, signer.sign(&body))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn parse_peer() {
+        let peer: CancelForwardPeer = "3=https://proxy.eu-west-1.internal:7002/".parse().unwrap();
+        assert_eq!(peer.cluster_id, 3);
+        assert_eq!(peer.url.as_str(), "https://proxy.eu-west-1.internal:7002/");
+
+        "https://proxy.eu-west-1.internal"
+            .parse::<CancelForwardPeer>()
+            .unwrap_err();
+        "300=https://proxy.eu-west-1.internal"
+            .parse::<CancelForwardPeer>()
+            .unwrap_err();
+    }
+
+    #[test]
+    fn signatures() {
+        let forwarder = CancelForwarder::new(vec![], "secret").unwrap();
+        let other = CancelForwarder::new(vec![], "other secret").unwrap();
+
+        let body = br#"{"key":1,"peer_addr":"127.0.0.1","extra":null,"timestamp":0}"#;
+        let signature = forwarder.sign(body);
+        assert!(forwarder.verify(body, &signature));
+        assert!(!other.verify(body, &signature));
+        assert!(!forwarder.verify(br#"{"key":2}"#, &signature));
+        assert!(!forwarder.verify(body, "not base64"));
+
+        CancelForwarder::new(vec![], "").err().unwrap();
+    }
+
+    #[tokio::test]
+    async fn forward_to_issuing_cluster() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let api = control_plane(dir.path());
+        let compute = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (issuer, url) = issuing_cluster(api.clone()).await;
+
+        // the cluster which receives the cancel request, with a redis of its own.
+        let peer = CancelForwardPeer { cluster_id: 1, url };
+        let forwarder = CancelForwarder::new(vec![peer], "secret").unwrap();
+        let receiver = cancellation_handler(mock::serve().await).await;
+        let receiver = receiver.with_forwarding(2, Some(forwarder));
+
+        let session = issuer.clone().get_key();
+        let key = *session.key();
+        let closure = cancel_closure(compute.local_addr().unwrap(), 1);
+        let (_target, targets) = tokio::sync::watch::channel(Some(closure));
+        tokio::spawn(async move { session.maintain_pooled_cancel_key(targets).await });
+        wait_for_key(&issuer, key, Some(1)).await;
+
+        let cancel = |key| receiver.cancel_session(key, RequestContext::test(), false, false, &api);
+
+        let (res, pid) = tokio::join!(cancel(key), accept_cancel(&compute));
+        res.unwrap();
+        assert_eq!(pid, 1);
+
+        // the issuing cluster does not know about the key either.
+        let unknown = key_with_cluster_id(rand::random(), 1);
+        assert!(matches!(cancel(unknown).await, Err(CancelError::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn reject_replayed_and_forged_requests() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let (_issuer, url) = issuing_cluster(control_plane(dir.path())).await;
+        let forwarder = CancelForwarder::new(vec![], "secret").unwrap();
+        let forger = CancelForwarder::new(vec![], "other secret").unwrap();
+
+        let key = key_with_cluster_id(rand::random(), 1).0.get();
+        let request = |timestamp, nonce| ForwardedCancelRequest {
+            key,
+            peer_addr: IpAddr::from([127, 0, 0, 1]),
+            extra: None,
+            timestamp,
+            nonce,
+        };
+        let now = unix_timestamp();
+
+        // the request is accepted, but the key is unknown.
+        let status = send(&url, &forwarder, &request(now, 1)).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+
+        // the same request again.
+        let status = send(&url, &forwarder, &request(now, 1)).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        let status = send(&url, &forger, &request(now, 2)).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        let expired = now - 2 * MAX_REQUEST_AGE.as_secs();
+        let status = send(&url, &forwarder, &request(expired, 3)).await;
+        assert_eq!(status, http::StatusCode::UNAUTHORIZED);
+
+        // another request for the same key is not a replay.
+        let status = send(&url, &forwarder, &request(now, 4)).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+    }
+}
@@ -23,13 +23,17 @@
 use crate::control_plane::ControlPlaneApi;
 use crate::error::ReportableError;
 use crate::ext::LockExt;
-use crate::metrics::{CancelChannelSizeGuard, CancellationRequest, Metrics, RedisMsgKind};
-use crate::pqproto::CancelKeyData;
+use crate::metrics::{
+    CancelChannelSizeGuard, CancellationOutcome, CancellationRequest, Metrics, RedisMsgKind,
+};
+use crate::pqproto::{CancelKeyData, id_to_cancel_key};
 use crate::rate_limiter::LeakyBucketRateLimiter;
 use crate::redis::keys::KeyPrefix;
 use crate::redis::kv_ops::{RedisKVClient, RedisKVClientError};
 use crate::util::run_until;
 
+pub mod forward;
+
 type IpSubnetKey = IpNet;
 
 /// Initial period and TTL is shorter to clear keys of short-lived connections faster.
@@ -38,6 +42,10 @@
 /// `CANCEL_KEY_TTL_SLACK` is added to the periods to determine the actual TTL.
 const CANCEL_KEY_TTL_SLACK: Duration = Duration::from_secs(30);
 
+/// The cluster id is stored in the top byte of the cancel key, without the
+/// sign bit of the backend pid.
+pub const MAX_CLUSTER_ID: u8 = 0x7f;
+
 // Message types for sending through mpsc channel
 pub enum CancelKeyOp {
     Store {
@@ -55,6 +63,9 @@ pub enum CancelKeyOp {
     GetOld {
         key: CancelKeyData,
     },
+    Delete {
+        key: CancelKeyData,
+    },
 }
 
 impl CancelKeyOp {
@@ -64,6 +75,7 @@ const fn redis_msg_kind(&self) -> RedisMsgKind {
             CancelKeyOp::Refresh { .. } => RedisMsgKind::Expire,
             CancelKeyOp::Get { .. } => RedisMsgKind::Get,
             CancelKeyOp::GetOld { .. } => RedisMsgKind::HGet,
+            CancelKeyOp::Delete { .. } => RedisMsgKind::Del,
         }
     }
 
@@ -153,6 +165,10 @@ fn register(&self, pipe: &mut Pipeline) {
                 let key = KeyPrefix::Cancel(*key).build_redis_key();
                 pipe.add_command(Cmd::get(key));
             }
+            CancelKeyOp::Delete { key } => {
+                let key = KeyPrefix::Cancel(*key).build_redis_key();
+                pipe.add_command(Cmd::del(key));
+            }
         }
     }
 }
@@ -201,6 +217,9 @@ pub struct CancellationHandler {
     // rate limiter of cancellation requests
     limiter: Arc<std::sync::Mutex<LeakyBucketRateLimiter<IpSubnetKey>>>,
     tx: OnceLock<BatchQueue<CancellationProcessor>>, // send messages to the redis KV client task
+    /// Id of this proxy cluster, encoded into the cancel keys.
+    cluster_id: Option<u8>,
+    forwarder: Option<forward::CancelForwarder>,
 }
 
 #[derive(Debug, Error)]
@@ -220,6 +239,9 @@ pub(crate) enum CancelError {
     #[error("key not found")]
     NotFound,
 
+    #[error("cancel request was rejected")]
+    Rejected,
+
     #[error("proxy service error")]
     InternalError,
 }
@@ -233,7 +255,9 @@ fn get_error_kind(&self) -> crate::error::ErrorKind {
             }
             CancelError::Postgres(_) => crate::error::ErrorKind::Compute,
             CancelError::RateLimit => crate::error::ErrorKind::RateLimit,
-            CancelError::NotFound | CancelError::AuthError(_) => crate::error::ErrorKind::User,
+            CancelError::NotFound | CancelError::Rejected | CancelError::AuthError(_) => {
+                crate::error::ErrorKind::User
+            }
             CancelError::InternalError => crate::error::ErrorKind::Service,
         }
     }
@@ -250,9 +274,25 @@ pub fn new(compute_config: &'static ComputeConfig) -> Self {
                     64,
                 ),
             )),
+            cluster_id: None,
+            forwarder: None,
         }
     }
 
+    /// Encode the cluster id into the cancel keys, and forward the cancel
+    /// requests for the keys of other clusters.
+    #[must_use]
+    pub fn with_forwarding(
+        mut self,
+        cluster_id: u8,
+        forwarder: Option<forward::CancelForwarder>,
+    ) -> Self {
+        assert!(cluster_id <= MAX_CLUSTER_ID, "invalid cluster id");
+        self.cluster_id = Some(cluster_id);
+        self.forwarder = forwarder;
+        self
+    }
+
     pub fn init_tx(&self, queue: BatchQueue<CancellationProcessor>) {
         self.tx
             .set(queue)
@@ -268,7 +308,10 @@ pub(crate) fn get_key(self: Arc<Self>) -> Session {
         // if we forwarded the backend_pid from postgres to the client, there would be a lot
         // of overlap between our computes as most pids are small (~100).
 
-        let key: CancelKeyData = rand::random();
+        let mut key: CancelKeyData = rand::random();
+        if let Some(cluster_id) = self.cluster_id {
+            key = key_with_cluster_id(key, cluster_id);
+        }
 
         debug!("registered new query cancellation key {key}");
         Session {
@@ -337,6 +380,10 @@ async fn get_cancel_key(
             CancelError::InternalError
         })?;
 
+        if result == Value::Nil {
+            return Ok(None);
+        }
+
         let cancel_state_str = String::from_owned_redis_value(result).map_err(|e| {
             tracing::warn!("failed to receive GetCancelData response: {e}");
             CancelError::InternalError
@@ -366,6 +413,29 @@ pub(crate) async fn cancel_session<T: ControlPlaneApi>(
         check_ip_allowed: bool,
         check_vpc_allowed: bool,
         auth_backend: &T,
+    ) -> Result<(), CancelError> {
+        self.cancel_session_inner(
+            key,
+            ctx,
+            check_ip_allowed,
+            check_vpc_allowed,
+            auth_backend,
+            true,
+        )
+        .await
+    }
+
+    /// Cancel the query of a session of this cluster. The cancel requests for
+    /// the keys of other clusters are forwarded to them if `allow_forwarding`,
+    /// which is not the case for the requests that were forwarded already.
+    pub(super) async fn cancel_session_inner<T: ControlPlaneApi>(
+        &self,
+        key: CancelKeyData,
+        ctx: RequestContext,
+        check_ip_allowed: bool,
+        check_vpc_allowed: bool,
+        auth_backend: &T,
+        allow_forwarding: bool,
     ) -> Result<(), CancelError> {
         let subnet_key = match ctx.peer_addr() {
             IpAddr::V4(ip) => IpNet::V4(Ipv4Net::new_assert(ip, 24).trunc()), // use defaut mask here
@@ -384,7 +454,7 @@ pub(crate) async fn cancel_session<T: ControlPlaneApi>(
                 .proxy
                 .cancellation_requests_total
                 .inc(CancellationRequest {
-                    kind: crate::metrics::CancellationOutcome::RateLimitExceeded,
+                    kind: CancellationOutcome::RateLimitExceeded,
                 });
             return Err(CancelError::RateLimit);
         }
@@ -395,12 +465,28 @@ pub(crate) async fn cancel_session<T: ControlPlaneApi>(
         })?;
 
         let Some(cancel_closure) = cancel_state else {
+            // the key might have been issued by another proxy cluster.
+            let cluster_id = key_cluster_id(key);
+            if allow_forwarding
+                && let Some(forwarder) = &self.forwarder
+                && self.cluster_id != Some(cluster_id)
+                && forwarder.has_peer(cluster_id)
+            {
+                Metrics::get()
+                    .proxy
+                    .cancellation_requests_total
+                    .inc(CancellationRequest {
+                        kind: CancellationOutcome::Forwarded,
+                    });
+                return forwarder.forward(cluster_id, key, &ctx).await;
+            }
+
             tracing::warn!("query cancellation key not found: {key}");
             Metrics::get()
                 .proxy
                 .cancellation_requests_total
                 .inc(CancellationRequest {
-                    kind: crate::metrics::CancellationOutcome::NotFound,
+                    kind: CancellationOutcome::NotFound,
                 });
             return Err(CancelError::NotFound);
         };
@@ -417,13 +503,22 @@ pub(crate) async fn cancel_session<T: ControlPlaneApi>(
             .proxy
             .cancellation_requests_total
             .inc(CancellationRequest {
-                kind: crate::metrics::CancellationOutcome::Found,
+                kind: CancellationOutcome::Found,
             });
         info!("cancelling query per user's request using key {key}");
         cancel_closure.try_cancel_query(self.compute_config).await
     }
 }
 
+fn key_with_cluster_id(key: CancelKeyData, cluster_id: u8) -> CancelKeyData {
+    let id = key.0.get() & !(0xff << 56);
+    id_to_cancel_key(id | (u64::from(cluster_id & MAX_CLUSTER_ID) << 56))
+}
+
+fn key_cluster_id(key: CancelKeyData) -> u8 {
+    (key.0.get() >> 56) as u8
+}
+
 /// This should've been a [`std::future::Future`], but
 /// it's impossible to name a type of an unboxed future
 /// (we'd need something like `#![feature(type_alias_impl_trait)]`).
@@ -592,4 +687,235 @@ enum State {
             );
         }
     }
+
+    /// Keep the cancel key of a client in transaction pooling mode pointed at the
+    /// compute connection which serves its current transaction. The key is removed
+    /// in between transactions, and once the channel is dropped.
+    ///
+    /// This is not cancel safe
+    pub(crate) async fn maintain_pooled_cancel_key(
+        &self,
+        mut targets: tokio::sync::watch::Receiver<Option<CancelClosure>>,
+    ) {
+        let Some(tx) = self.cancellation_handler.tx.get() else {
+            tracing::warn!("cancellation handler is not available");
+            return;
+        };
+
+        let mut registered = false;
+        loop {
+            let target = targets.borrow_and_update().clone();
+            let op = match &target {
+                Some(cancel_closure) => {
+                    tracing::debug!(
+                        src=%self.key,
+                        dest=?cancel_closure.cancel_token,
+                        "registering cancellation key"
+                    );
+                    let closure_json = serde_json::to_string(cancel_closure)
+                        .expect("serialising to json string should not fail")
+                        .into_boxed_str();
+                    Some(CancelKeyOp::Store {
+                        key: self.key,
+                        value: closure_json,
+                        expire: CANCEL_KEY_REFRESH_PERIOD + CANCEL_KEY_TTL_SLACK,
+                    })
+                }
+                None if registered => Some(CancelKeyOp::Delete { key: self.key }),
+                None => None,
+            };
+
+            if let Some(op) = op {
+                let guard = op.cancel_channel_metric_guard();
+                match tx
+                    .call((guard, op), std::future::pending::<Infallible>())
+                    .await
+                {
+                    Ok(_) => registered = target.is_some(),
+                    Err(error) => {
+                        // the key might be left behind, try to remove it next time.
+                        tracing::warn!("error updating cancellation key: {error}");
+                        registered = true;
+                    }
+                }
+            }
+
+            // store the key again before it expires, for long running transactions.
+            match timeout(CANCEL_KEY_REFRESH_PERIOD, targets.changed()).await {
+                Ok(Ok(())) | Err(_) => {}
+                Ok(Err(_closed)) => break,
+            }
+        }
+
+        if registered {
+            let op = CancelKeyOp::Delete { key: self.key };
+            let guard = op.cancel_channel_metric_guard();
+            if let Err(error) = tx
+                .call((guard, op), std::future::pending::<Infallible>())
+                .await
+            {
+                tracing::warn!("error removing cancellation key: {error}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+    use postgres_client::config::SslMode;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::RetryConfig;
+    use crate::control_plane::client::file::FileControlPlane;
+    use crate::proxy::NeonOptions;
+    use crate::tls::client_config::compute_client_config_with_certs;
+
+    /// A cancellation handler which keeps the keys in the Redis at `redis`.
+    pub(super) async fn cancellation_handler(redis: SocketAddr) -> CancellationHandler {
+        let compute_config = Box::leak(Box::new(ComputeConfig {
+            retry: RetryConfig {
+                base_delay: Duration::from_secs(1),
+                max_retries: 5,
+                backoff_factor: 2.0,
+            },
+            tls: Arc::new(compute_client_config_with_certs(std::iter::empty())),
+            timeout: Duration::from_secs(2),
+        }));
+
+        let handler = CancellationHandler::new(compute_config);
+        handler.init_tx(BatchQueue::new(CancellationProcessor {
+            client: crate::redis::mock::client(redis).await,
+            batch_size: 8,
+        }));
+        handler
+    }
+
+    /// A control plane with the endpoint of [`cancel_closure`], open to all addresses.
+    pub(super) fn control_plane(dir: &Utf8Path) -> FileControlPlane {
+        let path = dir.join("endpoints.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[endpoints]]
+            id = "endpoint"
+            compute = { host = "127.0.0.1" }
+            "#,
+        )
+        .unwrap();
+        FileControlPlane::load(path).unwrap()
+    }
+
+    pub(super) fn cancel_closure(compute: SocketAddr, process_id: i32) -> CancelClosure {
+        CancelClosure {
+            socket_addr: compute,
+            cancel_token: RawCancelToken {
+                ssl_mode: SslMode::Disable,
+                process_id,
+                secret_key: process_id + 1,
+            },
+            hostname: compute.ip().to_string(),
+            user_info: ComputeUserInfo {
+                endpoint: "endpoint".into(),
+                user: "user".into(),
+                options: NeonOptions::parse_options_raw(""),
+            },
+        }
+    }
+
+    /// Accept a `CancelRequest` on the compute, and return the backend pid it is for.
+    pub(super) async fn accept_cancel(compute: &TcpListener) -> i32 {
+        let (mut stream, _) = compute.accept().await.unwrap();
+        let mut request = [0; 16];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..8], [0, 0, 0, 16, 0x04, 0xd2, 0x16, 0x2e]);
+
+        let process_id = i32::from_be_bytes(request[8..12].try_into().unwrap());
+        let secret_key = i32::from_be_bytes(request[12..].try_into().unwrap());
+        assert_eq!(secret_key, process_id + 1);
+        process_id
+    }
+
+    #[test]
+    fn cluster_id_in_key() {
+        for cluster_id in [0, 1, 42, MAX_CLUSTER_ID] {
+            for _ in 0..100 {
+                let key = key_with_cluster_id(rand::random(), cluster_id);
+                assert_eq!(key_cluster_id(key), cluster_id);
+
+                // the backend pid stays positive.
+                let pid = (key.0.get() >> 32) as i32;
+                assert!(pid >= 0);
+            }
+        }
+    }
+
+    /// Wait for the key to point at the backend `process_id`, or to be gone.
+    pub(super) async fn wait_for_key(
+        handler: &CancellationHandler,
+        key: CancelKeyData,
+        pid: Option<i32>,
+    ) {
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let closure = handler.get_cancel_key(key).await.unwrap();
+                if closure.map(|c| c.cancel_token.process_id) == pid {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn pooled_cancel_key() {
+        let handler = Arc::new(cancellation_handler(crate::redis::mock::serve().await).await);
+        let dir = camino_tempfile::tempdir().unwrap();
+        let control_plane = control_plane(dir.path());
+        let compute = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let compute_addr = compute.local_addr().unwrap();
+
+        let session = handler.clone().get_key();
+        let key = *session.key();
+        let (targets, rx) = tokio::sync::watch::channel(None);
+        let task = tokio::spawn(async move { session.maintain_pooled_cancel_key(rx).await });
+
+        let cancel =
+            || handler.cancel_session(key, RequestContext::test(), false, false, &control_plane);
+
+        // the key is registered for the connection of the transaction.
+        targets.send_replace(Some(cancel_closure(compute_addr, 1)));
+        wait_for_key(&handler, key, Some(1)).await;
+        let (res, pid) = tokio::join!(cancel(), accept_cancel(&compute));
+        res.unwrap();
+        assert_eq!(pid, 1);
+
+        // the next transaction is served by another connection.
+        targets.send_replace(Some(cancel_closure(compute_addr, 2)));
+        wait_for_key(&handler, key, Some(2)).await;
+        let (res, pid) = tokio::join!(cancel(), accept_cancel(&compute));
+        res.unwrap();
+        assert_eq!(pid, 2);
+
+        // in between transactions, there is nothing to cancel.
+        targets.send_replace(None);
+        wait_for_key(&handler, key, None).await;
+        assert!(matches!(cancel().await, Err(CancelError::NotFound)));
+
+        // the key is removed when the client goes away, without cancelling anything.
+        targets.send_replace(Some(cancel_closure(compute_addr, 3)));
+        wait_for_key(&handler, key, Some(3)).await;
+        drop(targets);
+        task.await.unwrap();
+        assert!(handler.get_cancel_key(key).await.unwrap().is_none());
+        assert!(
+            timeout(Duration::from_millis(100), compute.accept())
+                .await
+                .is_err()
+        );
+    }
 }
@@ -135,6 +135,7 @@ pub enum Auth {
 }
 
 /// A config for authenticating to the compute node.
+#[derive(Clone)]
 pub(crate) struct AuthInfo {
     /// None for local-proxy, as we use trust-based localhost auth.
     /// Some for sql-over-http, ws, tcp, and in most cases for console-redirect.
@@ -234,6 +235,8 @@ pub(crate) fn set_startup_params(
                 "user" | "database" | "application_name" | "replication" => {
                     self.server_params.insert(k, v);
                 }
+                // the proxy routes the session with it, postgres does not know this parameter.
+                "target_session_attrs" => {}
 
                 // if we allow arbitrary params, then we forward them through.
                 // this is a flag for a period of backwards compatibility
@@ -245,6 +248,11 @@ pub(crate) fn set_startup_params(
         }
     }
 
+    /// The startup params the compute node will be connected with.
+    pub(crate) fn server_params(&self) -> &StartupMessageParams {
+        &self.server_params
+    }
+
     pub async fn authenticate(
         &self,
         ctx: &RequestContext,
@@ -14,17 +14,23 @@
 
 use crate::auth::backend::jwt::JwkCache;
 use crate::auth::backend::local::JWKS_ROLE_MAP;
+#[cfg(feature = "rest_broker")]
+use crate::cache::query::QueryCache;
 use crate::control_plane::locks::ApiLocks;
 use crate::control_plane::messages::{EndpointJwksResponse, JwksSettings};
 use crate::ext::TaskExt;
 use crate::intern::RoleNameInt;
-use crate::rate_limiter::{RateLimitAlgorithm, RateLimiterConfig};
+use crate::pglb::drain::Drain;
+use crate::pglb::transaction_pool::TransactionPool;
+use crate::rate_limiter::{RateLimitAlgorithm, RateLimiterConfig, SessionLimiter};
 use crate::scram;
 use crate::serverless::GlobalConnPoolOptions;
 use crate::serverless::cancel_set::CancelSet;
 #[cfg(feature = "rest_broker")]
 use crate::serverless::rest::DbSchemaCache;
 pub use crate::tls::server_config::{TlsConfig, configure_tls};
+#[cfg(feature = "rest_broker")]
+use crate::types::EndpointId;
 use crate::types::{Host, RoleName};
 
 pub struct ProxyConfig {
@@ -39,6 +45,11 @@ pub struct ProxyConfig {
     pub wake_compute_retry_config: RetryConfig,
     pub connect_compute_locks: ApiLocks<Host>,
     pub connect_to_compute: ComputeConfig,
+    pub session_limiter: SessionLimiter,
+    /// Pool of compute connections for the clients in transaction pooling mode, if enabled.
+    pub transaction_pool: Option<TransactionPool>,
+    /// Closes the client sessions within a deadline once the proxy shuts down.
+    pub drain: Drain,
     pub greetings: String, // Greeting message sent to the client after connection establishment and contains session_id.
     #[cfg(feature = "testing")]
     pub disable_pg_session_jwt: bool,
@@ -67,11 +78,17 @@ pub struct MetricCollectionConfig {
 
 pub struct HttpConfig {
     pub accept_websockets: bool,
+    /// Accept the permessage-deflate extension of websockets.
+    pub websocket_compression: bool,
+    /// Accept the subprotocol which multiplexes several sessions over one websocket.
+    pub websocket_multiplexing: bool,
     pub pool_options: GlobalConnPoolOptions,
     pub cancel_set: CancelSet,
     pub client_conn_threshold: u64,
     pub max_request_size_bytes: usize,
     pub max_response_size_bytes: usize,
+    /// How long an interactive transaction may stay idle between requests.
+    pub txn_session_idle_timeout: Duration,
 }
 
 pub struct AuthenticationConfig {
@@ -82,6 +99,8 @@ pub struct AuthenticationConfig {
     pub jwks_cache: JwkCache,
     pub is_auth_broker: bool,
     pub accept_jwts: bool,
+    /// Offer `OAUTHBEARER` to postgres clients, validating the token like a JWT.
+    pub accept_oauthbearer: bool,
     pub console_redirect_confirmation_timeout: tokio::time::Duration,
 }
 
@@ -91,6 +110,53 @@ pub struct RestConfig {
     pub db_schema_cache: Option<DbSchemaCache>,
     pub max_schema_size: usize,
     pub hostname_prefix: String,
+    pub graphql_endpoints: EndpointSet,
+    pub query_cache: Option<Arc<QueryCache>>,
+    pub query_cache_endpoints: EndpointSet,
+}
+
+/// Endpoints for which an opt-in feature of the rest broker is enabled.
+#[cfg(feature = "rest_broker")]
+#[derive(Debug, Default, PartialEq)]
+pub enum EndpointSet {
+    #[default]
+    None,
+    All,
+    List(std::collections::HashSet<EndpointId>),
+}
+
+#[cfg(feature = "rest_broker")]
+impl EndpointSet {
+    pub fn is_enabled(&self, endpoint: &EndpointId) -> bool {
+        match self {
+            EndpointSet::None => false,
+            EndpointSet::All => true,
+            EndpointSet::List(endpoints) => endpoints.contains(endpoint),
+        }
+    }
+}
+
+/// Parses `*` for all endpoints, or a comma separated list of endpoint ids.
+#[cfg(feature = "rest_broker")]
+impl FromStr for EndpointSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(EndpointSet::None);
+        }
+        if s == "*" {
+            return Ok(EndpointSet::All);
+        }
+        let endpoints = s
+            .split(',')
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+            .map(EndpointId::from)
+            .collect();
+        Ok(EndpointSet::List(endpoints))
+    }
 }
 
 #[derive(Debug)]
@@ -494,6 +560,7 @@ fn parse_jwks_settings(jwks: compute_api::spec::JwksSettings) -> anyhow::Result<
                 tls_config.cert_path.as_ref(),
                 None,
                 false,
+                false,
             )
         })
         .await
@@ -547,6 +614,25 @@ fn test_parse_cache_options() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[cfg(feature = "rest_broker")]
+    #[test]
+    fn test_parse_endpoint_set() -> anyhow::Result<()> {
+        let ep = EndpointId::from("ep-foo-123");
+        let other = EndpointId::from("ep-bar-456");
+
+        let endpoints: EndpointSet = "".parse()?;
+        assert!(!endpoints.is_enabled(&ep));
+
+        let endpoints: EndpointSet = "*".parse()?;
+        assert!(endpoints.is_enabled(&ep));
+
+        let endpoints: EndpointSet = "ep-foo-123, ep-baz-789".parse()?;
+        assert!(endpoints.is_enabled(&ep));
+        assert!(!endpoints.is_enabled(&other));
+
+        Ok(())
+    }
+
     #[test]
     fn test_parse_lock_options() -> anyhow::Result<()> {
         let ConcurrencyLockOptions {
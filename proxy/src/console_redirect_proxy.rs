@@ -10,11 +10,12 @@
 use crate::cancellation::{CancelClosure, CancellationHandler};
 use crate::config::{ProxyConfig, ProxyProtocolV2};
 use crate::context::RequestContext;
+use crate::context::audit::QueryAudit;
 use crate::error::ReportableError;
 use crate::metrics::{Metrics, NumClientConnectionsGuard};
 use crate::pglb::ClientRequestError;
 use crate::pglb::handshake::{HandshakeData, handshake};
-use crate::pglb::passthrough::ProxyPassthrough;
+use crate::pglb::passthrough::{Compute, ProxyPassthrough};
 use crate::protocol2::{ConnectHeader, ConnectionInfo, read_proxy_protocol};
 use crate::proxy::{
     ErrorSource, connect_compute, forward_compute_params_to_client, send_client_greeting,
@@ -263,15 +264,23 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
     Ok(Some(ProxyPassthrough {
         client: stream,
-        compute: node.stream.into_framed().into_inner(),
+        compute: Compute::Dedicated {
+            stream: node.stream.into_framed().into_inner(),
+            cancel_on_shutdown,
+            db_conn: node.guage,
+        },
 
         aux: node.aux,
         private_link_id: None,
 
-        _cancel_on_shutdown: cancel_on_shutdown,
+        // the console redirect flow has no endpoint access control to limit sessions with.
+        session: None,
+        // nor the audit log mode.
+        audit: QueryAudit::default(),
+        capture: ctx.session_capture(),
+        drain: &config.drain,
 
         _req: request_gauge,
         _conn: conn_gauge,
-        _db_conn: node.guage,
     }))
 }
@@ -0,0 +1,393 @@
+//! Audit log of the statements run through the proxy.
+//!
+//! Endpoints opt in to the audit log through the control plane. Each query
+//! becomes a record, uploaded as parquet files like the request logs.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
+use smol_str::SmolStr;
+use tokio::sync::mpsc;
+
+use super::parquet::ParquetRow;
+use crate::control_plane::messages::AuditLogMode;
+
+#[derive(parquet_derive::ParquetRecordWriter, Clone)]
+pub(crate) struct AuditRecord {
+    pub(crate) region: String,
+    pub(crate) protocol: &'static str,
+    /// When the query started. Must be UTC.
+    pub(crate) timestamp: chrono::NaiveDateTime,
+    pub(crate) session_id: uuid::Uuid,
+    pub(crate) endpoint_id: Option<String>,
+    pub(crate) project: Option<String>,
+    pub(crate) branch: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) database: Option<String>,
+    /// The statement, as much of it as the audit log mode of the endpoint keeps.
+    pub(crate) query: Option<String>,
+    /// Name of the command from the command tag, e.g. `SELECT`.
+    pub(crate) command: Option<String>,
+    pub(crate) rows: Option<u64>,
+    /// SQLSTATE of the error, or `aborted` if the query didn't run to the end.
+    pub(crate) error: Option<String>,
+    pub(crate) duration_us: u64,
+}
+
+impl ParquetRow for AuditRecord {
+    const FILE_NAME: &'static str = "audit";
+
+    fn set_region(&mut self, region: &str) {
+        region.clone_into(&mut self.region);
+    }
+}
+
+struct AuditSession {
+    mode: AuditLogMode,
+    sender: mpsc::UnboundedSender<AuditRecord>,
+    /// The fields shared by all the records of the session.
+    record: AuditRecord,
+}
+
+/// Records the queries of a session, if the endpoint has the audit log enabled.
+#[derive(Clone, Default)]
+pub(crate) struct QueryAudit(Option<Arc<AuditSession>>);
+
+impl QueryAudit {
+    pub(super) fn new(
+        mode: AuditLogMode,
+        sender: mpsc::UnboundedSender<AuditRecord>,
+        record: AuditRecord,
+    ) -> Self {
+        Self(Some(Arc::new(AuditSession {
+            mode,
+            sender,
+            record,
+        })))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn test(mode: AuditLogMode) -> (Self, mpsc::UnboundedReceiver<AuditRecord>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let record = AuditRecord {
+            region: String::new(),
+            protocol: "tcp",
+            timestamp: Utc::now().naive_utc(),
+            session_id: uuid::Uuid::nil(),
+            endpoint_id: Some("endpoint".to_owned()),
+            project: None,
+            branch: None,
+            username: Some("user".to_owned()),
+            database: None,
+            query: None,
+            command: None,
+            rows: None,
+            error: None,
+            duration_us: 0,
+        };
+        (Self::new(mode, tx, record), rx)
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Start auditing the query. It is recorded when the returned guard is dropped.
+    pub(crate) fn start(&self, query: &str) -> AuditedQuery {
+        let query = self.0.as_ref().and_then(|session| match session.mode {
+            AuditLogMode::Full => Some(query.to_owned()),
+            AuditLogMode::Normalized => Some(normalize(query)),
+            AuditLogMode::Redacted => None,
+        });
+
+        AuditedQuery {
+            session: self.0.clone(),
+            query,
+            started_at: Utc::now(),
+            started: Instant::now(),
+            command: None,
+            rows: None,
+            error: None,
+            finished: false,
+        }
+    }
+}
+
+/// A query in progress. It's recorded as aborted unless it's finished
+/// before it's dropped.
+pub(crate) struct AuditedQuery {
+    session: Option<Arc<AuditSession>>,
+    query: Option<String>,
+    started_at: chrono::DateTime<Utc>,
+    started: Instant,
+    command: Option<SmolStr>,
+    rows: Option<u64>,
+    error: Option<SmolStr>,
+    finished: bool,
+}
+
+impl AuditedQuery {
+    /// A command of the query completed. Simple queries can run several commands.
+    pub(crate) fn complete(&mut self, command: &str, rows: Option<u64>) {
+        self.command = Some(command.into());
+        if let Some(rows) = rows {
+            self.rows = Some(self.rows.unwrap_or_default() + rows);
+        }
+    }
+
+    /// The query failed with the given SQLSTATE.
+    pub(crate) fn fail(&mut self, code: &str) {
+        self.error.get_or_insert_with(|| code.into());
+    }
+
+    /// The query ran to the end, successfully or not.
+    pub(crate) fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for AuditedQuery {
+    fn drop(&mut self) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+
+        let error = match self.error.take() {
+            Some(error) => Some(error.to_string()),
+            None if !self.finished => Some("aborted".to_owned()),
+            None => None,
+        };
+
+        let record = AuditRecord {
+            timestamp: self.started_at.naive_utc(),
+            query: self.query.take(),
+            command: self.command.as_deref().map(String::from),
+            rows: self.rows,
+            error,
+            duration_us: self.started.elapsed().as_micros() as u64,
+            ..session.record.clone()
+        };
+
+        // the worker is gone when the proxy shuts down.
+        let _ = session.sender.send(record);
+    }
+}
+
+/// Split the command tag into the command name and number of rows affected.
+pub(crate) fn parse_command_tag(command_tag: &str) -> (&str, Option<u64>) {
+    let mut words = command_tag.split(' ');
+    let command = words.next().unwrap_or_default();
+    let rows = if command == "INSERT" {
+        // INSERT returns OID first and then number of rows
+        words.nth(1)
+    } else {
+        // other commands return number of rows (if any)
+        words.next()
+    };
+    (command, rows.and_then(|rows| rows.parse().ok()))
+}
+
+/// Replace the literals of the statement with `?` and drop the comments,
+/// so that the values in the statement don't end up in the audit log.
+pub(crate) fn normalize(query: &str) -> String {
+    let bytes = query.as_bytes();
+    let mut normalized = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let after_ident = i > 0 && is_ident(bytes[i - 1]);
+        match (bytes[i], bytes.get(i + 1).copied()) {
+            (b'\'', _) => {
+                i = skip_string(bytes, i, false);
+                normalized.push(b'?');
+            }
+            (b'e' | b'E', Some(b'\'')) if !after_ident => {
+                i = skip_string(bytes, i + 1, true);
+                normalized.push(b'?');
+            }
+            (b'"', _) => {
+                // quoted identifiers are kept as they are.
+                let end = skip_quoted(bytes, i, b'"');
+                normalized.extend_from_slice(&bytes[i..end]);
+                i = end;
+            }
+            (b'-', Some(b'-')) => {
+                i = bytes[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map_or(bytes.len(), |n| i + n);
+            }
+            (b'/', Some(b'*')) => {
+                i = skip_comment(bytes, i);
+                normalized.push(b' ');
+            }
+            (b'$', Some(b'0'..=b'9')) => {
+                // parameters are kept as they are.
+                let end = skip_while(bytes, i + 1, |b| b.is_ascii_digit());
+                normalized.extend_from_slice(&bytes[i..end]);
+                i = end;
+            }
+            (b'$', _) if !after_ident => match skip_dollar_quoted(query, i) {
+                Some(end) => {
+                    i = end;
+                    normalized.push(b'?');
+                }
+                None => {
+                    normalized.push(b'$');
+                    i += 1;
+                }
+            },
+            (b'0'..=b'9', _) | (b'.', Some(b'0'..=b'9')) if !after_ident => {
+                i = skip_number(bytes, i);
+                normalized.push(b'?');
+            }
+            (b, _) => {
+                normalized.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    // only ascii characters are replaced, so the rest is still utf8.
+    String::from_utf8(normalized).expect("normalized query should be utf8")
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || !b.is_ascii()
+}
+
+fn skip_while(bytes: &[u8], start: usize, f: impl Fn(u8) -> bool) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|&b| !f(b))
+        .map_or(bytes.len(), |n| start + n)
+}
+
+/// Skip the string literal starting with the quote at `start`.
+fn skip_string(bytes: &[u8], start: usize, backslash_escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if backslash_escapes => i += 2,
+            b'\'' if bytes.get(i + 1) == Some(&b'\'') => i += 2,
+            b'\'' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip the quoted identifier starting with the quote at `start`.
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) != Some(&quote) {
+                return i + 1;
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Skip the block comment starting at `start`. They can be nested.
+fn skip_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skip the dollar-quoted string starting at `start`, if there is one.
+fn skip_dollar_quoted(query: &str, start: usize) -> Option<usize> {
+    let bytes = query.as_bytes();
+    let tag_end = skip_while(bytes, start + 1, |b| {
+        b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii()
+    });
+    if bytes.get(tag_end) != Some(&b'$') {
+        return None;
+    }
+
+    let tag = &query[start..=tag_end];
+    let body = tag_end + 1;
+    Some(
+        query[body..]
+            .find(tag)
+            .map_or(query.len(), |n| body + n + tag.len()),
+    )
+}
+
+/// Skip the numeric literal starting at `start`, e.g. `42`, `1.5e-3` or `0x1F`.
+fn skip_number(bytes: &[u8], start: usize) -> usize {
+    let radix_prefix = bytes[start] == b'0'
+        && matches!(
+            bytes.get(start + 1),
+            Some(b'x' | b'X' | b'o' | b'O' | b'b' | b'B')
+        );
+
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' => i += 1,
+            b'+' | b'-' if !radix_prefix && matches!(bytes[i - 1], b'e' | b'E') => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn normalize_literals() {
+        let cases = [
+            ("select 1", "select ?"),
+            (
+                "select * from t where id = $1",
+                "select * from t where id = $1",
+            ),
+            (
+                "insert into t2 (a, b) values ('it''s', 1.5e-3)",
+                "insert into t2 (a, b) values (?, ?)",
+            ),
+            (r"select E'\'secret\''", "select ?"),
+            ("select $$secret$$, $tag$ $$ $tag$", "select ?, ?"),
+            (
+                "select \"col'1\" from \"t\"\"2\"",
+                "select \"col'1\" from \"t\"\"2\"",
+            ),
+            ("select 'a'::text -- secret\n, .5", "select ?::text \n, ?"),
+            ("select /* secret /* nested */ */ 0x1F", "select   ?"),
+            (
+                "select col1 from t1 limit 10",
+                "select col1 from t1 limit ?",
+            ),
+            ("select 0.5e-3, 0xE-1", "select ?, ?-?"),
+            ("select 'ünïcode', 'tëst'", "select ?, ?"),
+        ];
+
+        for (query, expected) in cases {
+            assert_eq!(normalize(query), expected, "{query}");
+        }
+    }
+}
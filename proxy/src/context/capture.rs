@@ -0,0 +1,116 @@
+//! Capture of the wire traffic of sessions, to reproduce protocol bugs.
+//!
+//! The operator opts endpoints in with `--parquet-upload-capture-endpoints`.
+//! Each message of their passthrough sessions after the authentication becomes
+//! a record, uploaded as parquet files like the request logs. The `capture_replay`
+//! binary plays the captured sessions back against a compute.
+
+use std::time::Instant;
+
+use tokio::sync::mpsc;
+
+use super::parquet::ParquetRow;
+
+pub(crate) const DIRECTION_FRONTEND: &str = "frontend";
+pub(crate) const DIRECTION_BACKEND: &str = "backend";
+
+#[derive(parquet_derive::ParquetRecordWriter, Clone)]
+pub(crate) struct CaptureRecord {
+    pub(crate) region: String,
+    pub(crate) session_id: uuid::Uuid,
+    pub(crate) endpoint_id: Option<String>,
+    pub(crate) username: Option<String>,
+    pub(crate) database: Option<String>,
+    /// Position of the message in the session, counting both directions.
+    pub(crate) sequence: u64,
+    /// Time since the start of the capture.
+    pub(crate) offset_us: u64,
+    /// `frontend` for the messages of the client, `backend` for the ones of the compute.
+    pub(crate) direction: &'static str,
+    /// The message, with its tag and length.
+    pub(crate) message: Vec<u8>,
+    /// Whether the body of the message was cut short.
+    pub(crate) truncated: bool,
+}
+
+impl ParquetRow for CaptureRecord {
+    const FILE_NAME: &'static str = "capture";
+
+    fn set_region(&mut self, region: &str) {
+        region.clone_into(&mut self.region);
+    }
+}
+
+struct CaptureSession {
+    sender: mpsc::UnboundedSender<CaptureRecord>,
+    /// The fields shared by all the records of the session.
+    record: CaptureRecord,
+    started: Instant,
+    sequence: u64,
+}
+
+/// Records the messages of a session, if its endpoint is opted in to the capture.
+#[derive(Default)]
+pub(crate) struct SessionCapture(Option<Box<CaptureSession>>);
+
+impl SessionCapture {
+    pub(super) fn new(sender: mpsc::UnboundedSender<CaptureRecord>, record: CaptureRecord) -> Self {
+        Self(Some(Box::new(CaptureSession {
+            sender,
+            record,
+            started: Instant::now(),
+            sequence: 0,
+        })))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn test() -> (Self, mpsc::UnboundedReceiver<CaptureRecord>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let record = CaptureRecord {
+            region: String::new(),
+            session_id: uuid::Uuid::nil(),
+            endpoint_id: Some("endpoint".to_owned()),
+            username: Some("user".to_owned()),
+            database: None,
+            sequence: 0,
+            offset_us: 0,
+            direction: "",
+            message: vec![],
+            truncated: false,
+        };
+        (Self::new(tx, record), rx)
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Record a message the client sent.
+    pub(crate) fn frontend(&mut self, message: Vec<u8>, truncated: bool) {
+        self.record(DIRECTION_FRONTEND, message, truncated);
+    }
+
+    /// Record a message the client received.
+    pub(crate) fn backend(&mut self, message: Vec<u8>, truncated: bool) {
+        self.record(DIRECTION_BACKEND, message, truncated);
+    }
+
+    fn record(&mut self, direction: &'static str, message: Vec<u8>, truncated: bool) {
+        let Some(session) = &mut self.0 else {
+            return;
+        };
+
+        let record = CaptureRecord {
+            sequence: session.sequence,
+            offset_us: session.started.elapsed().as_micros() as u64,
+            direction,
+            message,
+            truncated,
+            ..session.record.clone()
+        };
+        session.sequence += 1;
+
+        // the worker is gone when the proxy shuts down.
+        let _ = session.sender.send(record);
+    }
+}
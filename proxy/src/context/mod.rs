@@ -1,5 +1,6 @@
 //! Connection request monitoring contexts
 
+use std::collections::HashSet;
 use std::net::IpAddr;
 
 use chrono::Utc;
@@ -11,8 +12,10 @@
 use try_lock::TryLock;
 use uuid::Uuid;
 
+use self::audit::{AuditRecord, QueryAudit};
+use self::capture::{CaptureRecord, SessionCapture};
 use self::parquet::RequestData;
-use crate::control_plane::messages::{ColdStartInfo, MetricsAuxInfo};
+use crate::control_plane::messages::{AuditLogMode, ColdStartInfo, MetricsAuxInfo};
 use crate::error::ErrorKind;
 use crate::intern::{BranchIdInt, ProjectIdInt};
 use crate::metrics::{LatencyAccumulated, LatencyTimer, Metrics, Protocol, Waiting};
@@ -20,11 +23,18 @@
 use crate::protocol2::{ConnectionInfo, ConnectionInfoExtra};
 use crate::types::{DbName, EndpointId, RoleName};
 
+pub(crate) mod audit;
+pub(crate) mod capture;
 pub mod parquet;
 
 pub(crate) static LOG_CHAN: OnceCell<mpsc::WeakUnboundedSender<RequestData>> = OnceCell::new();
 pub(crate) static LOG_CHAN_DISCONNECT: OnceCell<mpsc::WeakUnboundedSender<RequestData>> =
     OnceCell::new();
+pub(crate) static AUDIT_CHAN: OnceCell<mpsc::WeakUnboundedSender<AuditRecord>> = OnceCell::new();
+pub(crate) static CAPTURE_CHAN: OnceCell<mpsc::WeakUnboundedSender<CaptureRecord>> =
+    OnceCell::new();
+/// Endpoints whose sessions are captured.
+pub(crate) static CAPTURE_ENDPOINTS: OnceCell<HashSet<EndpointId>> = OnceCell::new();
 
 /// Context data for a single request to connect to a database.
 ///
@@ -60,6 +70,7 @@ struct RequestContextInner {
     pub(crate) cold_start_info: ColdStartInfo,
     pg_options: Option<StartupMessageParams>,
     testodrome_query_id: Option<SmolStr>,
+    audit_log: Option<AuditLogMode>,
 
     // extra
     // This sender is here to keep the request monitoring channel open while requests are taking place.
@@ -78,6 +89,7 @@ pub(crate) enum AuthMethod {
     ScramSha256Plus,
     Cleartext,
     Jwt,
+    ClientCertificate,
 }
 
 impl Clone for RequestContext {
@@ -104,6 +116,7 @@ fn clone(&self) -> Self {
             cold_start_info: inner.cold_start_info,
             pg_options: inner.pg_options.clone(),
             testodrome_query_id: inner.testodrome_query_id.clone(),
+            audit_log: inner.audit_log,
 
             sender: None,
             disconnect_sender: None,
@@ -148,6 +161,7 @@ pub fn new(session_id: Uuid, conn_info: ConnectionInfo, protocol: Protocol) -> S
             cold_start_info: ColdStartInfo::Unknown,
             pg_options: None,
             testodrome_query_id: None,
+            audit_log: None,
 
             sender: LOG_CHAN.get().and_then(|tx| tx.upgrade()),
             disconnect_sender: LOG_CHAN_DISCONNECT.get().and_then(|tx| tx.upgrade()),
@@ -158,6 +172,15 @@ pub fn new(session_id: Uuid, conn_info: ConnectionInfo, protocol: Protocol) -> S
         Self(TryLock::new(inner))
     }
 
+    /// Context of another session from the same client connection, such as the
+    /// sessions multiplexed over one websocket.
+    pub(crate) fn new_session(&self) -> Self {
+        let this = self.0.try_lock().expect("should not deadlock");
+        let ctx = Self::new(Uuid::new_v4(), this.conn_info.clone(), this.protocol);
+        ctx.set_user_agent(this.user_agent.clone());
+        ctx
+    }
+
     #[cfg(test)]
     pub(crate) fn test() -> Self {
         use std::net::SocketAddr;
@@ -267,6 +290,76 @@ pub(crate) fn set_jwt_issuer(&self, jwt_issuer: String) {
         this.jwt_issuer = Some(jwt_issuer);
     }
 
+    pub(crate) fn set_audit_log(&self, audit_log: Option<AuditLogMode>) {
+        let mut this = self.0.try_lock().expect("should not deadlock");
+        this.audit_log = audit_log;
+    }
+
+    /// Audit of the queries of the session, if the endpoint has it enabled.
+    pub(crate) fn query_audit(&self) -> QueryAudit {
+        let this = self.0.try_lock().expect("should not deadlock");
+        let Some(mode) = this.audit_log else {
+            return QueryAudit::default();
+        };
+        let Some(sender) = AUDIT_CHAN.get().and_then(|tx| tx.upgrade()) else {
+            return QueryAudit::default();
+        };
+
+        QueryAudit::new(
+            mode,
+            sender,
+            AuditRecord {
+                region: String::new(),
+                protocol: this.protocol.as_str(),
+                timestamp: this.first_packet.naive_utc(),
+                session_id: this.session_id,
+                endpoint_id: this.endpoint_id.as_deref().map(String::from),
+                project: this.project.as_deref().map(String::from),
+                branch: this.branch.as_deref().map(String::from),
+                username: this.user.as_deref().map(String::from),
+                database: this.dbname.as_deref().map(String::from),
+                query: None,
+                command: None,
+                rows: None,
+                error: None,
+                duration_us: 0,
+            },
+        )
+    }
+
+    /// Capture of the traffic of the session, if the endpoint is opted in.
+    pub(crate) fn session_capture(&self) -> SessionCapture {
+        let this = self.0.try_lock().expect("should not deadlock");
+        let Some(endpoint_id) = &this.endpoint_id else {
+            return SessionCapture::default();
+        };
+        if !CAPTURE_ENDPOINTS
+            .get()
+            .is_some_and(|endpoints| endpoints.contains(&endpoint_id.normalize()))
+        {
+            return SessionCapture::default();
+        }
+        let Some(sender) = CAPTURE_CHAN.get().and_then(|tx| tx.upgrade()) else {
+            return SessionCapture::default();
+        };
+
+        SessionCapture::new(
+            sender,
+            CaptureRecord {
+                region: String::new(),
+                session_id: this.session_id,
+                endpoint_id: Some(endpoint_id.to_string()),
+                username: this.user.as_deref().map(String::from),
+                database: this.dbname.as_deref().map(String::from),
+                sequence: 0,
+                offset_us: 0,
+                direction: "",
+                message: vec![],
+                truncated: false,
+            },
+        )
+    }
+
     pub fn has_private_peer_addr(&self) -> bool {
         self.0
             .try_lock()
@@ -5,7 +5,8 @@
 use bytes::buf::Writer;
 use bytes::{BufMut, BytesMut};
 use chrono::{Datelike, Timelike};
-use futures::{Stream, StreamExt};
+use futures::{FutureExt, Stream, StreamExt};
+use once_cell::sync::OnceCell;
 use parquet::basic::Compression;
 use parquet::file::metadata::RowGroupMetaDataPtr;
 use parquet::file::properties::{DEFAULT_PAGE_SIZE, WriterProperties, WriterPropertiesPtr};
@@ -19,11 +20,13 @@
 use tracing::{Span, debug, info};
 use utils::backoff;
 
-use super::{LOG_CHAN, RequestContextInner};
+use super::{
+    AUDIT_CHAN, CAPTURE_CHAN, CAPTURE_ENDPOINTS, LOG_CHAN, LOG_CHAN_DISCONNECT, RequestContextInner,
+};
 use crate::config::remote_storage_from_toml;
-use crate::context::LOG_CHAN_DISCONNECT;
 use crate::ext::TaskExt;
 use crate::pqproto::StartupMessageParams;
+use crate::types::EndpointId;
 
 #[derive(clap::Args, Clone, Debug)]
 pub struct ParquetUploadArgs {
@@ -36,6 +39,20 @@ pub struct ParquetUploadArgs {
     #[clap(long, value_parser = remote_storage_from_toml)]
     parquet_upload_disconnect_events_remote_storage: Option<RemoteStorageConfig>,
 
+    /// Storage location to upload the audit log of the statements to.
+    /// Only the endpoints with the audit log enabled are recorded.
+    #[clap(long, value_parser = remote_storage_from_toml)]
+    parquet_upload_audit_remote_storage: Option<RemoteStorageConfig>,
+
+    /// Storage location to upload the captured traffic of the sessions to.
+    /// Only the sessions of `parquet_upload_capture_endpoints` are captured.
+    #[clap(long, value_parser = remote_storage_from_toml)]
+    parquet_upload_capture_remote_storage: Option<RemoteStorageConfig>,
+
+    /// Endpoints to capture the post-auth traffic of, with the secrets stripped.
+    #[clap(long, value_delimiter = ',')]
+    parquet_upload_capture_endpoints: Vec<String>,
+
     /// How many rows to include in a row group
     #[clap(long, default_value_t = 8192)]
     parquet_upload_row_group_size: usize,
@@ -104,6 +121,22 @@ pub(crate) struct RequestData {
     disconnect_timestamp: Option<chrono::NaiveDateTime>,
 }
 
+/// Rows which are uploaded to the remote storage as parquet files.
+pub(crate) trait ParquetRow: Send + 'static {
+    /// Prefix of the names of the uploaded files.
+    const FILE_NAME: &'static str;
+
+    fn set_region(&mut self, region: &str);
+}
+
+impl ParquetRow for RequestData {
+    const FILE_NAME: &'static str = "requests";
+
+    fn set_region(&mut self, region: &str) {
+        region.clone_into(&mut self.region);
+    }
+}
+
 struct Options<'a> {
     options: &'a StartupMessageParams,
 }
@@ -144,6 +177,7 @@ fn from(value: &RequestContextInner) -> Self {
                 super::AuthMethod::ScramSha256Plus => "scram_sha_256_plus",
                 super::AuthMethod::Cleartext => "cleartext",
                 super::AuthMethod::Jwt => "jwt",
+                super::AuthMethod::ClientCertificate => "client_certificate",
             }),
             jwt_issuer: value.jwt_issuer.clone(),
             protocol: value.protocol.as_str(),
@@ -174,21 +208,7 @@ pub async fn worker(
         return Ok(());
     };
 
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    LOG_CHAN
-        .set(tx.downgrade())
-        .expect("only one worker should set the channel");
-
-    // setup row stream that will close on cancellation
-    let cancellation_token2 = cancellation_token.clone();
-    tokio::spawn(async move {
-        cancellation_token2.cancelled().await;
-        // dropping this sender will cause the channel to close only once
-        // all the remaining inflight requests have been completed.
-        drop(tx);
-    });
-    let rx = futures::stream::poll_fn(move |cx| rx.poll_recv(cx));
-    let rx = rx.map(RequestData::from);
+    let rx = channel_until_cancelled(&LOG_CHAN, &cancellation_token).map(RequestData::from);
 
     let storage = GenericRemoteStorage::from_config(&remote_storage_config)
         .await
@@ -208,43 +228,76 @@ pub async fn worker(
         test_remote_failures: 0,
     };
 
-    // TODO(anna): consider moving this to a separate function.
+    let mut uploads = vec![worker_inner(storage, rx, parquet_config.clone(), &region).boxed()];
+
     if let Some(disconnect_events_storage_config) =
         config.parquet_upload_disconnect_events_remote_storage
     {
-        let (tx_disconnect, mut rx_disconnect) = mpsc::unbounded_channel();
-        LOG_CHAN_DISCONNECT
-            .set(tx_disconnect.downgrade())
-            .expect("only one worker should set the channel");
-
-        // setup row stream that will close on cancellation
-        tokio::spawn(async move {
-            cancellation_token.cancelled().await;
-            // dropping this sender will cause the channel to close only once
-            // all the remaining inflight requests have been completed.
-            drop(tx_disconnect);
-        });
-        let rx_disconnect = futures::stream::poll_fn(move |cx| rx_disconnect.poll_recv(cx));
-        let rx_disconnect = rx_disconnect.map(RequestData::from);
+        let rx_disconnect = channel_until_cancelled(&LOG_CHAN_DISCONNECT, &cancellation_token)
+            .map(RequestData::from);
 
         let storage_disconnect =
             GenericRemoteStorage::from_config(&disconnect_events_storage_config)
                 .await
                 .context("remote storage for disconnect events init")?;
-        let parquet_config_disconnect = parquet_config.clone();
-        tokio::try_join!(
-            worker_inner(storage, rx, parquet_config, &region),
+        uploads.push(
             worker_inner(
                 storage_disconnect,
                 rx_disconnect,
-                parquet_config_disconnect,
-                &region
+                parquet_config.clone(),
+                &region,
             )
-        )
-        .map(|_| ())
-    } else {
-        worker_inner(storage, rx, parquet_config, &region).await
+            .boxed(),
+        );
+    }
+
+    if let Some(audit_storage_config) = config.parquet_upload_audit_remote_storage {
+        let rx_audit = channel_until_cancelled(&AUDIT_CHAN, &cancellation_token);
+
+        let storage_audit = GenericRemoteStorage::from_config(&audit_storage_config)
+            .await
+            .context("remote storage for audit log init")?;
+        uploads
+            .push(worker_inner(storage_audit, rx_audit, parquet_config.clone(), &region).boxed());
+    }
+
+    if let Some(capture_storage_config) = config.parquet_upload_capture_remote_storage {
+        let endpoints = config
+            .parquet_upload_capture_endpoints
+            .iter()
+            .map(|endpoint| EndpointId::from(endpoint.as_str()).normalize())
+            .collect();
+        CAPTURE_ENDPOINTS
+            .set(endpoints)
+            .expect("only one worker should set the capture endpoints");
+        let rx_capture = channel_until_cancelled(&CAPTURE_CHAN, &cancellation_token);
+
+        let storage_capture = GenericRemoteStorage::from_config(&capture_storage_config)
+            .await
+            .context("remote storage for session captures init")?;
+        uploads.push(worker_inner(storage_capture, rx_capture, parquet_config, &region).boxed());
     }
+
+    futures::future::try_join_all(uploads).await.map(|_| ())
+}
+
+/// Set up the channel for the rows, which will close on cancellation.
+fn channel_until_cancelled<T: Send + 'static>(
+    chan: &OnceCell<mpsc::WeakUnboundedSender<T>>,
+    cancellation_token: &CancellationToken,
+) -> impl Stream<Item = T> + use<T> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    chan.set(tx.downgrade())
+        .expect("only one worker should set the channel");
+
+    let cancellation_token = cancellation_token.clone();
+    tokio::spawn(async move {
+        cancellation_token.cancelled().await;
+        // dropping this sender will cause the channel to close only once
+        // all the remaining inflight requests have been completed.
+        drop(tx);
+    });
+    futures::stream::poll_fn(move |cx| rx.poll_recv(cx))
 }
 
 #[derive(Clone, Debug)]
@@ -259,12 +312,15 @@ struct ParquetConfig {
     test_remote_failures: u64,
 }
 
-async fn worker_inner(
+async fn worker_inner<R: ParquetRow>(
     storage: GenericRemoteStorage,
-    rx: impl Stream<Item = RequestData>,
+    rx: impl Stream<Item = R>,
     config: ParquetConfig,
     region: &str,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<()>
+where
+    for<'a> &'a [R]: RecordWriter<R>,
+{
     #[cfg(any(test, feature = "testing"))]
     let storage = if config.test_remote_failures > 0 {
         GenericRemoteStorage::unreliable_wrapper(storage, config.test_remote_failures, 100)
@@ -285,7 +341,7 @@ async fn worker_inner(
 
     let mut len = 0;
     while let Some(mut row) = rx.next().await {
-        region.clone_into(&mut row.region);
+        row.set_region(region);
         rows.push(row);
         let force = last_upload.elapsed() > config.max_duration;
         if rows.len() == config.rows_per_group || force {
@@ -295,7 +351,7 @@ async fn worker_inner(
         }
         if len > config.file_size || force {
             last_upload = time::Instant::now();
-            let file = upload_parquet(w, len, &storage).await?;
+            let file = upload_parquet(w, len, &storage, R::FILE_NAME).await?;
             w = SerializedFileWriter::new(file, schema.clone(), config.propeties.clone())?;
             len = 0;
         }
@@ -308,21 +364,19 @@ async fn worker_inner(
     }
 
     if !w.flushed_row_groups().is_empty() {
-        let _rtchk: Writer<BytesMut> = upload_parquet(w, len, &storage).await?;
+        let _rtchk: Writer<BytesMut> = upload_parquet(w, len, &storage, R::FILE_NAME).await?;
     }
 
     Ok(())
 }
 
-async fn flush_rows<W>(
-    rows: Vec<RequestData>,
+async fn flush_rows<R, W>(
+    rows: Vec<R>,
     mut w: SerializedFileWriter<W>,
-) -> anyhow::Result<(
-    Vec<RequestData>,
-    SerializedFileWriter<W>,
-    RowGroupMetaDataPtr,
-)>
+) -> anyhow::Result<(Vec<R>, SerializedFileWriter<W>, RowGroupMetaDataPtr)>
 where
+    R: ParquetRow,
+    for<'a> &'a [R]: RecordWriter<R>,
     W: std::io::Write + Send + 'static,
 {
     let span = Span::current();
@@ -351,6 +405,7 @@ async fn upload_parquet(
     mut w: SerializedFileWriter<Writer<BytesMut>>,
     len: i64,
     storage: &GenericRemoteStorage,
+    name: &str,
 ) -> anyhow::Result<Writer<BytesMut>> {
     let len_uncompressed = w
         .flushed_row_groups()
@@ -384,7 +439,7 @@ async fn upload_parquet(
     info!(
         %id,
         rows = metadata.num_rows,
-        size, compression, "uploading request parquet file"
+        size, compression, name, "uploading request parquet file"
     );
 
     let year = now.year();
@@ -393,7 +448,7 @@ async fn upload_parquet(
     let hour = now.hour();
     // segment files by time for S3 performance
     let path = RemotePath::from_string(&format!(
-        "{year:04}/{month:02}/{day:02}/{hour:02}/requests_{id}.parquet"
+        "{year:04}/{month:02}/{day:02}/{hour:02}/{name}_{id}.parquet"
     ))?;
     let cancel = CancellationToken::new();
     let maybe_err = backoff::retry(
@@ -31,6 +31,7 @@
 use crate::metrics::Metrics;
 use crate::proxy::retry::CouldRetry;
 use crate::rate_limiter::WakeComputeRateLimiter;
+use crate::tls::client_cert::ClientCertAuth;
 use crate::types::{EndpointCacheKey, EndpointId, RoleName};
 use crate::{compute, http, scram};
 
@@ -83,9 +84,12 @@ async fn get_and_cache_auth_info<T>(
                     allowed_vpce: Arc::new(auth_info.allowed_vpc_endpoint_ids),
                     flags: auth_info.access_blocker_flags,
                     rate_limits: auth_info.rate_limits,
+                    audit_log: auth_info.audit_log,
+                    client_cert_auth: auth_info.client_cert_auth,
                 };
                 let role_control = RoleAccessControl {
                     secret: auth_info.secret,
+                    client_key: auth_info.client_key,
                 };
                 let res = extract(&control, &role_control);
 
@@ -184,6 +188,10 @@ async fn do_get_auth_req(
                     .ok_or(GetAuthInfoError::BadSecret)?;
                 Some(secret)
             };
+            let client_key = body
+                .role_client_key
+                .map(|key| scram::ScramKey::parse(&key).ok_or(GetAuthInfoError::BadSecret))
+                .transpose()?;
             let allowed_ips = body.allowed_ips.unwrap_or_default();
             Metrics::get()
                 .proxy
@@ -196,8 +204,14 @@ async fn do_get_auth_req(
                 .observe(allowed_vpc_endpoint_ids.len() as f64);
             let block_public_connections = body.block_public_connections.unwrap_or_default();
             let block_vpc_connections = body.block_vpc_connections.unwrap_or_default();
+            let client_cert_auth = body.client_cert_auth.and_then(|config| {
+                ClientCertAuth::new(config)
+                    .inspect_err(|e| warn!("invalid client certificate settings: {e:#}"))
+                    .ok()
+            });
             Ok(AuthInfo {
                 secret,
+                client_key,
                 allowed_ips,
                 allowed_vpc_endpoint_ids,
                 project_id: body.project_id,
@@ -207,6 +221,8 @@ async fn do_get_auth_req(
                     vpc_access_blocked: block_vpc_connections,
                 },
                 rate_limits: body.rate_limits,
+                audit_log: body.audit_log,
+                client_cert_auth: client_cert_auth.map(Arc::new),
             })
         }
         .inspect_err(|e| tracing::debug!(error = ?e))
@@ -303,32 +319,7 @@ async fn do_wake_compute(
             info!(duration = ?start.elapsed(), "received http response");
             let body = parse_body::<WakeCompute>(response.status(), response.bytes().await?)?;
 
-            let Some((host, port)) = parse_host_port(&body.address) else {
-                return Err(WakeComputeError::BadComputeAddress(body.address));
-            };
-
-            let host_addr = IpAddr::from_str(host).ok();
-
-            let ssl_mode = match &body.server_name {
-                Some(_) => SslMode::Require,
-                None => SslMode::Disable,
-            };
-            let host = match body.server_name {
-                Some(host) => host.into(),
-                None => host.into(),
-            };
-
-            let node = NodeInfo {
-                conn_info: compute::ConnectInfo {
-                    host_addr,
-                    host,
-                    port,
-                    ssl_mode,
-                },
-                aux: body.aux,
-            };
-
-            Ok(node)
+            node_info(body)
         }
         .inspect_err(|e| tracing::debug!(error = ?e))
         .instrument(info_span!("do_wake_compute"))
@@ -466,6 +457,9 @@ macro_rules! check_cache {
                 let mut stored_node = node.clone();
                 // store the cached node as 'warm_cached'
                 stored_node.aux.cold_start_info = ColdStartInfo::WarmCached;
+                if let Some(replica) = &mut stored_node.replica {
+                    replica.aux.cold_start_info = ColdStartInfo::WarmCached;
+                }
                 self.caches.node_info.insert(key.clone(), Ok(stored_node));
 
                 Ok(Cached {
@@ -498,6 +492,40 @@ macro_rules! check_cache {
     }
 }
 
+/// Connection info of the compute described by the `wake_compute` response.
+fn node_info(body: WakeCompute) -> Result<NodeInfo, WakeComputeError> {
+    let Some((host, port)) = parse_host_port(&body.address) else {
+        return Err(WakeComputeError::BadComputeAddress(body.address));
+    };
+
+    let host_addr = IpAddr::from_str(host).ok();
+
+    let ssl_mode = match &body.server_name {
+        Some(_) => SslMode::Require,
+        None => SslMode::Disable,
+    };
+    let host = match body.server_name {
+        Some(host) => host.into(),
+        None => host.into(),
+    };
+
+    let replica = match body.replica {
+        Some(replica) => Some(Box::new(node_info(*replica)?)),
+        None => None,
+    };
+
+    Ok(NodeInfo {
+        conn_info: compute::ConnectInfo {
+            host_addr,
+            host,
+            port,
+            ssl_mode,
+        },
+        aux: body.aux,
+        replica,
+    })
+}
+
 /// Parse http response body, taking status code into account.
 fn parse_body<T: for<'a> serde::Deserialize<'a>>(
     status: StatusCode,
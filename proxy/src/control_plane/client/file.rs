@@ -0,0 +1,442 @@
+//! Control plane backend which serves endpoints from a local configuration file.
+//!
+//! This lets the proxy run without the Neon console, e.g. when self-hosting
+//! or with `neon_local`. The file is polled for changes and reloaded; if the
+//! new contents are invalid, the previously loaded endpoints are kept.
+//!
+//! Example in TOML (a file with the `.json` extension is parsed as JSON):
+//!
+//! ```toml
+//! [[endpoints]]
+//! id = "ep-local"
+//! allowed_ips = ["127.0.0.1", "10.0.0.0/8"]
+//! compute = { host = "127.0.0.1", port = 55432 }
+//!
+//! [[endpoints.roles]]
+//! name = "cloud_admin"
+//! secret = "SCRAM-SHA-256$4096:...$...:..."
+//! ```
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use arc_swap::ArcSwap;
+use camino::{Utf8Path, Utf8PathBuf};
+use postgres_client::config::SslMode;
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::auth::IpPattern;
+use crate::auth::backend::ComputeUserInfo;
+use crate::auth::backend::jwt::AuthRule;
+use crate::cache::Cached;
+use crate::cache::node_info::CachedNodeInfo;
+use crate::compute::ConnectInfo;
+use crate::context::RequestContext;
+use crate::control_plane::errors::{
+    ControlPlaneError, GetAuthInfoError, GetEndpointJwksError, WakeComputeError,
+};
+use crate::control_plane::messages::{
+    ColdStartInfo, ControlPlaneErrorMessage, Details, EndpointRateLimitConfig, ErrorInfo,
+    MetricsAuxInfo, Reason, Status,
+};
+use crate::control_plane::{
+    AccessBlockerFlags, AuthSecret, EndpointAccessControl, NodeInfo, RoleAccessControl,
+};
+use crate::scram;
+use crate::types::{BranchId, EndpointId, ProjectId, RoleName};
+use crate::util::run_until_cancelled;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    endpoints: Vec<EndpointConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointConfig {
+    id: EndpointId,
+    /// Defaults to the endpoint id. Only used for metrics and logs.
+    project_id: Option<ProjectId>,
+    /// Defaults to the endpoint id. Only used for metrics and logs.
+    branch_id: Option<BranchId>,
+    /// Empty list means that all addresses are allowed.
+    #[serde(default)]
+    allowed_ips: Vec<IpPattern>,
+    #[serde(default)]
+    rate_limits: EndpointRateLimitConfig,
+    compute: ComputeConfig,
+    #[serde(default)]
+    roles: Vec<RoleConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ComputeConfig {
+    host: String,
+    #[serde(default = "default_compute_port")]
+    port: u16,
+    /// Require TLS for the connections to the compute.
+    #[serde(default)]
+    tls: bool,
+}
+
+fn default_compute_port() -> u16 {
+    5432
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleConfig {
+    name: RoleName,
+    /// SCRAM secret in the format of `pg_authid.rolpassword`.
+    secret: String,
+}
+
+struct Endpoint {
+    access_control: EndpointAccessControl,
+    roles: HashMap<RoleName, AuthSecret>,
+    node: NodeInfo,
+}
+
+type Endpoints = HashMap<EndpointId, Endpoint>;
+
+fn parse_endpoints(path: &Utf8Path, contents: &[u8]) -> anyhow::Result<Endpoints> {
+    let config: FileConfig = if path.extension() == Some("json") {
+        serde_json::from_slice(contents)?
+    } else {
+        toml::from_str(std::str::from_utf8(contents)?)?
+    };
+
+    let mut endpoints = HashMap::with_capacity(config.endpoints.len());
+    for endpoint in config.endpoints {
+        let mut roles = HashMap::with_capacity(endpoint.roles.len());
+        for role in endpoint.roles {
+            let secret = scram::ServerSecret::parse(&role.secret).with_context(|| {
+                format!(
+                    "invalid SCRAM secret for role '{}' of endpoint '{}'",
+                    role.name, endpoint.id
+                )
+            })?;
+            if roles.insert(role.name, AuthSecret::Scram(secret)).is_some() {
+                bail!("duplicate role in endpoint '{}'", endpoint.id);
+            }
+        }
+
+        let access_control = EndpointAccessControl {
+            allowed_ips: Arc::new(endpoint.allowed_ips),
+            allowed_vpce: Arc::new(vec![]),
+            flags: AccessBlockerFlags::default(),
+            rate_limits: endpoint.rate_limits,
+            audit_log: None,
+            client_cert_auth: None,
+        };
+
+        let compute = endpoint.compute;
+        let node = NodeInfo {
+            conn_info: ConnectInfo {
+                host_addr: IpAddr::from_str(&compute.host).ok(),
+                host: compute.host.into(),
+                port: compute.port,
+                ssl_mode: if compute.tls {
+                    SslMode::Require
+                } else {
+                    SslMode::Disable
+                },
+            },
+            aux: MetricsAuxInfo {
+                endpoint_id: (&endpoint.id).into(),
+                project_id: (&endpoint
+                    .project_id
+                    .unwrap_or_else(|| endpoint.id.as_str().into()))
+                    .into(),
+                branch_id: (&endpoint
+                    .branch_id
+                    .unwrap_or_else(|| endpoint.id.as_str().into()))
+                    .into(),
+                compute_id: endpoint.id.as_str().into(),
+                cold_start_info: ColdStartInfo::Warm,
+            },
+            replica: None,
+        };
+
+        let id = endpoint.id.clone();
+        let endpoint = Endpoint {
+            access_control,
+            roles,
+            node,
+        };
+        if endpoints.insert(id.clone(), endpoint).is_some() {
+            bail!("duplicate endpoint '{id}'");
+        }
+    }
+
+    Ok(endpoints)
+}
+
+fn endpoint_not_found() -> ControlPlaneError {
+    ControlPlaneError::Message(Box::new(ControlPlaneErrorMessage {
+        error: "endpoint not found".into(),
+        http_status_code: http::StatusCode::NOT_FOUND,
+        status: Some(Status {
+            code: "NOT_FOUND".into(),
+            message: "endpoint not found".into(),
+            details: Details {
+                error_info: Some(ErrorInfo {
+                    reason: Reason::EndpointNotFound,
+                }),
+                retry_info: None,
+                user_facing_message: None,
+            },
+        }),
+    }))
+}
+
+#[derive(Clone)]
+pub struct FileControlPlane {
+    path: Utf8PathBuf,
+    endpoints: Arc<ArcSwap<Endpoints>>,
+}
+
+impl FileControlPlane {
+    /// Load the endpoints from `path`. Unlike the later reloads,
+    /// failing to load the file at startup is an error.
+    pub fn load(path: Utf8PathBuf) -> anyhow::Result<Self> {
+        let contents = std::fs::read(&path).with_context(|| format!("failed to read {path}"))?;
+        let endpoints =
+            parse_endpoints(&path, &contents).with_context(|| format!("failed to parse {path}"))?;
+        info!(%path, endpoints = endpoints.len(), "loaded control plane file");
+
+        Ok(Self {
+            path,
+            endpoints: Arc::new(ArcSwap::from_pointee(endpoints)),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Poll the file every `interval` and reload the endpoints when its contents change,
+    /// until the proxy shuts down.
+    pub async fn watch(
+        self,
+        interval: Duration,
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut last = std::fs::read(&self.path).ok();
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        while run_until_cancelled(interval.tick(), &cancellation_token)
+            .await
+            .is_some()
+        {
+            let contents = match tokio::fs::read(&self.path).await {
+                Ok(contents) => contents,
+                Err(e) => {
+                    error!(path = %self.path, "failed to read control plane file: {e}");
+                    continue;
+                }
+            };
+            if last.as_ref() == Some(&contents) {
+                continue;
+            }
+
+            match parse_endpoints(&self.path, &contents) {
+                Ok(endpoints) => {
+                    info!(path = %self.path, endpoints = endpoints.len(), "reloaded control plane file");
+                    self.endpoints.store(Arc::new(endpoints));
+                }
+                Err(e) => {
+                    error!(path = %self.path, "failed to parse control plane file, keeping the previous endpoints: {e:#}");
+                }
+            }
+            last = Some(contents);
+        }
+
+        info!(path = %self.path, "stopped watching control plane file");
+        Ok(())
+    }
+}
+
+impl super::ControlPlaneApi for FileControlPlane {
+    async fn get_endpoint_access_control(
+        &self,
+        _ctx: &RequestContext,
+        endpoint: &EndpointId,
+        _role: &RoleName,
+    ) -> Result<EndpointAccessControl, GetAuthInfoError> {
+        let endpoints = self.endpoints.load();
+        let endpoint = endpoints.get(endpoint).ok_or_else(endpoint_not_found)?;
+        Ok(endpoint.access_control.clone())
+    }
+
+    async fn get_role_access_control(
+        &self,
+        _ctx: &RequestContext,
+        endpoint: &EndpointId,
+        role: &RoleName,
+    ) -> Result<RoleAccessControl, GetAuthInfoError> {
+        let endpoints = self.endpoints.load();
+        let endpoint = endpoints.get(endpoint).ok_or_else(endpoint_not_found)?;
+        Ok(RoleAccessControl {
+            secret: endpoint.roles.get(role).cloned(),
+            client_key: None,
+        })
+    }
+
+    async fn get_endpoint_jwks(
+        &self,
+        _ctx: &RequestContext,
+        _endpoint: &EndpointId,
+    ) -> Result<Vec<AuthRule>, GetEndpointJwksError> {
+        Ok(vec![])
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn wake_compute(
+        &self,
+        _ctx: &RequestContext,
+        user_info: &ComputeUserInfo,
+    ) -> Result<CachedNodeInfo, WakeComputeError> {
+        let endpoints = self.endpoints.load();
+        let endpoint = endpoints
+            .get(&user_info.endpoint)
+            .ok_or_else(endpoint_not_found)?;
+        Ok(Cached::new_uncached(endpoint.node.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "SCRAM-SHA-256$4096:+/tQQax7twvwTj64mjBsxQ==$\
+        D5h6KTMBlUvDJk2Y8ELfC1Sjtc6k9YHjRyuRZyBNJns=:\
+        Pi3QHbcluX//NDfVkKlFl88GGzlJ5LkyPwcdlN/QBvI=";
+
+    #[test]
+    fn parse_toml() {
+        let config = format!(
+            r#"
+            [[endpoints]]
+            id = "ep-local"
+            allowed_ips = ["127.0.0.1"]
+            compute = {{ host = "127.0.0.1", port = 55432 }}
+
+            [[endpoints.roles]]
+            name = "cloud_admin"
+            secret = "{SECRET}"
+
+            [[endpoints]]
+            id = "ep-remote"
+            compute = {{ host = "compute.local", tls = true }}
+            "#
+        );
+        let endpoints = parse_endpoints(Utf8Path::new("proxy.toml"), config.as_bytes()).unwrap();
+
+        let local = &endpoints[&EndpointId::from("ep-local")];
+        assert_eq!(local.access_control.allowed_ips.len(), 1);
+        assert!(local.roles.contains_key(&RoleName::from("cloud_admin")));
+        assert_eq!(local.node.conn_info.port, 55432);
+        assert_eq!(
+            local.node.conn_info.host_addr,
+            Some(IpAddr::from([127, 0, 0, 1]))
+        );
+
+        let remote = &endpoints[&EndpointId::from("ep-remote")];
+        assert!(remote.roles.is_empty());
+        assert_eq!(remote.node.conn_info.port, 5432);
+        assert_eq!(remote.node.conn_info.host_addr, None);
+        assert!(matches!(remote.node.conn_info.ssl_mode, SslMode::Require));
+    }
+
+    #[test]
+    fn parse_json() {
+        let config = format!(
+            r#"{{"endpoints": [{{
+                "id": "ep-local",
+                "compute": {{"host": "localhost"}},
+                "roles": [{{"name": "cloud_admin", "secret": "{SECRET}"}}]
+            }}]}}"#
+        );
+        let endpoints = parse_endpoints(Utf8Path::new("proxy.json"), config.as_bytes()).unwrap();
+        assert!(endpoints.contains_key(&EndpointId::from("ep-local")));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for config in [
+            // bad secret
+            r#"
+            [[endpoints]]
+            id = "ep"
+            compute = { host = "localhost" }
+            roles = [{ name = "a", secret = "md5abc" }]
+            "#,
+            // duplicate endpoint
+            r#"
+            [[endpoints]]
+            id = "ep"
+            compute = { host = "localhost" }
+            [[endpoints]]
+            id = "ep"
+            compute = { host = "localhost" }
+            "#,
+            // unknown field
+            r#"
+            [[endpoints]]
+            id = "ep"
+            compute = { host = "localhost" }
+            password = "hunter2"
+            "#,
+        ] {
+            assert!(parse_endpoints(Utf8Path::new("proxy.toml"), config.as_bytes()).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let path = dir.path().join("proxy.toml");
+        let endpoint = |id: &str| {
+            format!("[[endpoints]]\nid = \"{id}\"\ncompute = {{ host = \"localhost\" }}\n")
+        };
+        let has_endpoint = |api: &FileControlPlane, id: &str| {
+            api.endpoints.load().contains_key(&EndpointId::from(id))
+        };
+
+        std::fs::write(&path, endpoint("ep-1")).unwrap();
+        let api = FileControlPlane::load(path.clone()).unwrap();
+        let cancellation_token = CancellationToken::new();
+        let watcher = tokio::spawn(
+            api.clone()
+                .watch(Duration::from_millis(10), cancellation_token.clone()),
+        );
+
+        std::fs::write(&path, endpoint("ep-2")).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !has_endpoint(&api, "ep-2") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(!has_endpoint(&api, "ep-1"));
+
+        // the previous endpoints are kept if the file becomes invalid.
+        std::fs::write(&path, "[[endpoints]]").unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(has_endpoint(&api, "ep-2"));
+
+        cancellation_token.cancel();
+        watcher.await.unwrap().unwrap();
+    }
+}
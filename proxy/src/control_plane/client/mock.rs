@@ -125,12 +125,15 @@ async fn do_get_auth_info(
         .await?;
         Ok(AuthInfo {
             secret,
+            client_key: None,
             allowed_ips,
             allowed_vpc_endpoint_ids: vec![],
             project_id: None,
             account_id: None,
             access_blocker_flags: AccessBlockerFlags::default(),
             rate_limits: EndpointRateLimitConfig::default(),
+            audit_log: None,
+            client_cert_auth: None,
         })
     }
 
@@ -196,6 +199,7 @@ async fn do_wake_compute(&self) -> Result<NodeInfo, WakeComputeError> {
                 compute_id: "compute".into(),
                 cold_start_info: crate::control_plane::messages::ColdStartInfo::Warm,
             },
+            replica: None,
         };
 
         Ok(node)
@@ -235,6 +239,8 @@ async fn get_endpoint_access_control(
             allowed_vpce: Arc::new(info.allowed_vpc_endpoint_ids),
             flags: info.access_blocker_flags,
             rate_limits: info.rate_limits,
+            audit_log: info.audit_log,
+            client_cert_auth: info.client_cert_auth,
         })
     }
 
@@ -247,6 +253,7 @@ async fn get_role_access_control(
         let info = self.do_get_auth_info(endpoint, role).await?;
         Ok(RoleAccessControl {
             secret: info.secret,
+            client_key: info.client_key,
         })
     }
 
@@ -252,6 +252,10 @@ pub(crate) struct UserFacingMessage {
 #[derive(Deserialize)]
 pub(crate) struct GetEndpointAccessControl {
     pub(crate) role_secret: Box<str>,
+    /// Base64 SCRAM `ClientKey` of the role, if it may log in without the
+    /// password, e.g. with a client certificate or a bearer token.
+    #[serde(default)]
+    pub(crate) role_client_key: Option<Box<str>>,
 
     pub(crate) project_id: Option<ProjectIdInt>,
     pub(crate) account_id: Option<AccountIdInt>,
@@ -263,11 +267,106 @@ pub(crate) struct GetEndpointAccessControl {
 
     #[serde(default)]
     pub(crate) rate_limits: EndpointRateLimitConfig,
+
+    /// Statements run against the endpoint are recorded in the audit log, if set.
+    #[serde(default)]
+    pub(crate) audit_log: Option<AuditLogMode>,
+
+    /// Clients may authenticate with a TLS client certificate, if set.
+    #[serde(default)]
+    pub(crate) client_cert_auth: Option<ClientCertAuthConfig>,
+}
+
+/// Which client certificates can be used to log in to an endpoint.
+#[derive(Clone, Deserialize, Debug)]
+pub(crate) struct ClientCertAuthConfig {
+    /// PEM bundle of the CAs which issue the client certificates.
+    pub(crate) trusted_ca_bundle: String,
+    /// Which roles the certificate subjects may log in as.
+    pub(crate) role_mapping: Vec<ClientCertRoleMapping>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub(crate) struct ClientCertRoleMapping {
+    /// Common name of the certificate subject.
+    pub(crate) common_name: String,
+    pub(crate) role_name: RoleNameInt,
+}
+
+/// How much of the SQL text the audit log of an endpoint keeps.
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditLogMode {
+    /// The statements as they were sent.
+    Full,
+    /// The statements with the literals replaced by `?`.
+    Normalized,
+    /// No statement text, only the command, row count and outcome.
+    Redacted,
 }
 
 #[derive(Copy, Clone, Deserialize, Default, Debug)]
 pub struct EndpointRateLimitConfig {
     pub connection_attempts: ConnectionAttemptsLimit,
+    #[serde(default)]
+    pub sessions: SessionLimits,
+}
+
+/// Limits on the established sessions of an endpoint, across all the
+/// protocols. Unset limits are not enforced.
+#[derive(Copy, Clone, Deserialize, Default, Debug)]
+pub struct SessionLimits {
+    /// Maximum number of concurrent sessions to the endpoint.
+    pub max_connections: Option<u32>,
+    /// Maximum number of concurrent sessions of a single role to the endpoint.
+    pub max_role_connections: Option<u32>,
+    /// Rate of queries of a single role to the endpoint.
+    pub role_queries: Option<LeakyBucketSetting>,
+    #[serde(flatten)]
+    pub timeouts: SessionTimeouts,
+}
+
+/// How long the sessions of an endpoint can stay open, so that a leaked
+/// connection does not keep the compute running. Unset or zero timeouts are not enforced.
+#[derive(Copy, Clone, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct SessionTimeouts {
+    /// Close the sessions which are idle outside of a transaction for longer.
+    #[serde(
+        default,
+        rename = "idle_session_timeout_ms",
+        deserialize_with = "optional_milliseconds"
+    )]
+    pub idle_session: Option<Duration>,
+    /// Close the sessions which are idle inside of a transaction for longer.
+    #[serde(
+        default,
+        rename = "idle_in_transaction_session_timeout_ms",
+        deserialize_with = "optional_milliseconds"
+    )]
+    pub idle_in_transaction: Option<Duration>,
+    /// Close the sessions which are open for longer, once they are idle
+    /// outside of a transaction.
+    #[serde(
+        default,
+        rename = "max_session_lifetime_ms",
+        deserialize_with = "optional_milliseconds"
+    )]
+    pub max_lifetime: Option<Duration>,
+}
+
+impl SessionTimeouts {
+    pub(crate) fn is_enforced(&self) -> bool {
+        self.idle_session.is_some()
+            || self.idle_in_transaction.is_some()
+            || self.max_lifetime.is_some()
+    }
+}
+
+fn optional_milliseconds<'de, D: serde::Deserializer<'de>>(
+    d: D,
+) -> Result<Option<Duration>, D::Error> {
+    let millis = Option::<u64>::deserialize(d)?;
+    Ok(millis.filter(|&ms| ms > 0).map(Duration::from_millis))
 }
 
 #[derive(Copy, Clone, Deserialize, Default, Debug)]
@@ -290,6 +389,9 @@ pub(crate) struct WakeCompute {
     pub(crate) address: Box<str>,
     pub(crate) server_name: Option<String>,
     pub(crate) aux: MetricsAuxInfo,
+    /// Read replica compute of the same branch, if there is one running.
+    #[serde(default)]
+    pub(crate) replica: Option<Box<WakeCompute>>,
 }
 
 /// Async response which concludes the console redirect auth flow.
@@ -515,6 +617,22 @@ fn parse_wake_compute() -> anyhow::Result<()> {
             "aux": dummy_aux(),
         });
         serde_json::from_str::<WakeCompute>(&json.to_string())?;
+
+        // with a read replica
+        let json = json!({
+            "address": "0.0.0.0",
+            "aux": dummy_aux(),
+            "replica": {
+                "address": "0.0.0.1",
+                "server_name": "replica.localhost",
+                "aux": dummy_aux(),
+            },
+        });
+        let wake = serde_json::from_str::<WakeCompute>(&json.to_string())?;
+        let replica = wake.replica.expect("replica should be parsed");
+        assert_eq!(&*replica.address, "0.0.0.1");
+        assert!(replica.replica.is_none());
+
         Ok(())
     }
 
@@ -551,4 +669,71 @@ fn parse_get_role_secret() -> anyhow::Result<()> {
 
         Ok(())
     }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn parse_session_limits() -> anyhow::Result<()> {
+        let json = json!({
+            "role_secret": "secret",
+            "rate_limits": {
+                "connection_attempts": {},
+                "sessions": {
+                    "max_connections": 100,
+                    "role_queries": { "rps": 50.0, "burst": 100.0 },
+                },
+            },
+        });
+        let access = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        let sessions = access.rate_limits.sessions;
+        assert_eq!(sessions.max_connections, Some(100));
+        assert_eq!(sessions.max_role_connections, None);
+        assert!(sessions.role_queries.is_some_and(|q| q.rps == 50.0));
+
+        // older control planes do not send the session limits
+        let json = json!({
+            "role_secret": "secret",
+            "rate_limits": { "connection_attempts": {} },
+        });
+        let access = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        assert!(access.rate_limits.sessions.max_connections.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_session_timeouts() -> anyhow::Result<()> {
+        let json = json!({
+            "role_secret": "secret",
+            "rate_limits": {
+                "connection_attempts": {},
+                "sessions": {
+                    "idle_in_transaction_session_timeout_ms": 300_000,
+                    "max_session_lifetime_ms": 86_400_000,
+                    "idle_session_timeout_ms": 0,
+                },
+            },
+        });
+        let access = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        let timeouts = access.rate_limits.sessions.timeouts;
+        assert_eq!(
+            timeouts.idle_in_transaction,
+            Some(Duration::from_secs(5 * 60))
+        );
+        assert_eq!(
+            timeouts.max_lifetime,
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        // zero disables the timeout, like in postgres
+        assert_eq!(timeouts.idle_session, None);
+        assert!(timeouts.is_enforced());
+
+        let json = json!({
+            "role_secret": "secret",
+            "rate_limits": { "connection_attempts": {}, "sessions": {} },
+        });
+        let access = serde_json::from_str::<GetEndpointAccessControl>(&json.to_string())?;
+        assert!(!access.rate_limits.sessions.timeouts.is_enforced());
+
+        Ok(())
+    }
 }
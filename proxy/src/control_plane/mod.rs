@@ -11,7 +11,7 @@
 
 use std::sync::Arc;
 
-use messages::EndpointRateLimitConfig;
+use messages::{AuditLogMode, EndpointRateLimitConfig};
 
 use crate::auth::backend::ComputeUserInfo;
 use crate::auth::backend::jwt::AuthRule;
@@ -19,9 +19,12 @@
 use crate::cache::node_info::CachedNodeInfo;
 use crate::context::RequestContext;
 use crate::control_plane::messages::MetricsAuxInfo;
-use crate::intern::{AccountIdInt, EndpointIdInt, ProjectIdInt};
+use crate::intern::{AccountIdInt, EndpointIdInt, ProjectIdInt, RoleNameInt};
 use crate::protocol2::ConnectionInfoExtra;
-use crate::rate_limiter::{EndpointRateLimiter, LeakyBucketConfig};
+use crate::rate_limiter::{
+    EndpointRateLimiter, LeakyBucketConfig, SessionGuard, SessionLimitError, SessionLimiter,
+};
+use crate::tls::client_cert::ClientCertAuth;
 use crate::types::{EndpointId, RoleName};
 use crate::{compute, scram};
 
@@ -48,6 +51,8 @@ pub(crate) enum AuthSecret {
 #[derive(Default)]
 pub(crate) struct AuthInfo {
     pub(crate) secret: Option<AuthSecret>,
+    /// SCRAM `ClientKey` of the role, see [`RoleAccessControl::client_key`].
+    pub(crate) client_key: Option<scram::ScramKey>,
     /// List of IP addresses allowed for the autorization.
     pub(crate) allowed_ips: Vec<IpPattern>,
     /// List of VPC endpoints allowed for the autorization.
@@ -60,6 +65,10 @@ pub(crate) struct AuthInfo {
     pub(crate) access_blocker_flags: AccessBlockerFlags,
     /// The rate limits for this endpoint.
     pub(crate) rate_limits: EndpointRateLimitConfig,
+    /// The audit log mode of this endpoint.
+    pub(crate) audit_log: Option<AuditLogMode>,
+    /// Client certificate authentication settings of this endpoint.
+    pub(crate) client_cert_auth: Option<Arc<ClientCertAuth>>,
 }
 
 /// Info for establishing a connection to a compute node.
@@ -69,6 +78,19 @@ pub(crate) struct NodeInfo {
 
     /// Labels for proxy's metrics.
     pub(crate) aux: MetricsAuxInfo,
+
+    /// Read replica of the same branch, which serves the read-only sessions.
+    pub(crate) replica: Option<Box<NodeInfo>>,
+}
+
+impl NodeInfo {
+    /// Connect to the read replica instead, if the branch has one.
+    /// Otherwise, the session falls back to the primary compute.
+    pub(crate) fn route_read_only(&mut self) {
+        if let Some(replica) = self.replica.take() {
+            *self = *replica;
+        }
+    }
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -80,6 +102,10 @@ pub(crate) struct AccessBlockerFlags {
 #[derive(Clone, Debug)]
 pub struct RoleAccessControl {
     pub secret: Option<AuthSecret>,
+    /// SCRAM `ClientKey` matching the secret. Compute only accepts SCRAM, so
+    /// proxy logs in with it when the client authenticates with a client
+    /// certificate or a bearer token instead of the password.
+    pub client_key: Option<scram::ScramKey>,
 }
 
 #[derive(Clone, Debug)]
@@ -89,6 +115,8 @@ pub struct EndpointAccessControl {
     pub flags: AccessBlockerFlags,
 
     pub rate_limits: EndpointRateLimitConfig,
+    pub audit_log: Option<AuditLogMode>,
+    pub client_cert_auth: Option<Arc<ClientCertAuth>>,
 }
 
 impl EndpointAccessControl {
@@ -157,6 +185,23 @@ pub fn connection_attempt_rate_limit(
 
         Ok(())
     }
+
+    /// Count the session against the concurrent sessions limits of the
+    /// endpoint and the role, for as long as the returned guard is held.
+    pub(crate) fn acquire_session(
+        &self,
+        ctx: &RequestContext,
+        endpoint: &EndpointId,
+        role: &RoleName,
+        session_limiter: &'static SessionLimiter,
+    ) -> Result<SessionGuard, SessionLimitError> {
+        session_limiter.acquire(
+            ctx.protocol(),
+            EndpointIdInt::from(endpoint),
+            RoleNameInt::from(role),
+            &self.rate_limits.sessions,
+        )
+    }
 }
 
 /// This will allocate per each call, but the http requests alone
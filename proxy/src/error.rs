@@ -4,6 +4,8 @@
 use measured::FixedCardinalityLabel;
 use tokio::task::JoinError;
 
+use crate::pqproto::{ErrorCode, SQLSTATE_INTERNAL_ERROR};
+
 /// Marks errors that may be safely shown to a client.
 /// This trait can be seen as a specialized version of [`ToString`].
 ///
@@ -20,6 +22,12 @@ pub(crate) trait UserFacingError: ReportableError {
     fn to_string_client(&self) -> String {
         self.to_string()
     }
+
+    /// SQLSTATE code reported to the client along with the error.
+    #[inline(always)]
+    fn sql_state(&self) -> ErrorCode {
+        SQLSTATE_INTERNAL_ERROR
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, FixedCardinalityLabel)]
@@ -3,36 +3,93 @@
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, bail};
-use http_utils::endpoint::{self, profile_cpu_handler, profile_heap_handler, request_span};
+use http_utils::endpoint::{
+    self, auth_middleware, check_permission_with, profile_cpu_handler, profile_heap_handler,
+    request_span,
+};
 use http_utils::error::ApiError;
 use http_utils::json::json_response;
-use http_utils::{RouterBuilder, RouterService};
+use http_utils::{RequestExt, RouterBuilder, RouterService};
 use hyper0::header::CONTENT_TYPE;
 use hyper0::{Body, Request, Response, StatusCode};
 use measured::MetricGroup;
 use measured::text::BufferedTextEncoder;
 use metrics::NeonMetrics;
-use tracing::{info, info_span};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, info_span, warn};
+use utils::auth::{AuthError, Scope, SwappableJwtAuth};
 
 use crate::ext::{LockExt, TaskExt};
 use crate::jemalloc;
+use crate::metrics::{Metrics, ServiceInfo};
 
 async fn status_handler(_: Request<Body>) -> Result<Response<Body>, ApiError> {
     json_response(StatusCode::OK, "")
 }
 
-fn make_router(metrics: AppMetrics) -> RouterBuilder<hyper0::Body, ApiError> {
+/// Stop accepting new clients and drain the existing sessions, as on SIGTERM.
+/// Used for rolling deploys, where the proxy should hand off to the new instance.
+/// Needs a token with the admin scope, if the auth is enabled.
+async fn drain_handler(
+    req: Request<Body>,
+    shutdown: CancellationToken,
+) -> Result<Response<Body>, ApiError> {
+    check_permission_with(&req, |claims| match claims.scope {
+        Scope::Admin => Ok(()),
+        _ => Err(AuthError("draining requires the admin scope".into())),
+    })?;
+
+    if !shutdown.is_cancelled() {
+        warn!("drain requested, shutting down once all existing connections have closed");
+        Metrics::get()
+            .service
+            .info
+            .set_label(ServiceInfo::terminating());
+        shutdown.cancel();
+    }
+    json_response(StatusCode::ACCEPTED, "")
+}
+
+fn make_router(
+    metrics: AppMetrics,
+    shutdown: CancellationToken,
+    auth: Option<Arc<SwappableJwtAuth>>,
+    allow_drain: bool,
+) -> RouterBuilder<hyper0::Body, ApiError> {
     let state = Arc::new(Mutex::new(PrometheusHandler {
         encoder: BufferedTextEncoder::new(),
         metrics,
     }));
 
-    endpoint::make_router()
+    let mut router = endpoint::make_router();
+    if auth.is_some() {
+        router = router.middleware(auth_middleware(|request| {
+            // the metrics and the status stay open to the monitoring
+            if request.uri().path() != "/v1/drain" {
+                return None;
+            }
+            // Option<Arc<SwappableJwtAuth>> is always provided as data below, hence unwrap().
+            request
+                .data::<Option<Arc<SwappableJwtAuth>>>()
+                .unwrap()
+                .as_deref()
+        }));
+    }
+
+    router = router
+        .data(auth)
         .get("/metrics", move |r| {
             let state = state.clone();
             request_span(r, move |b| prometheus_metrics_handler(b, state))
         })
-        .get("/v1/status", status_handler)
+        .get("/v1/status", status_handler);
+    if allow_drain {
+        router = router.post("/v1/drain", move |r| {
+            let shutdown = shutdown.clone();
+            request_span(r, move |b| drain_handler(b, shutdown))
+        });
+    }
+    router
         .get("/profile/cpu", move |r| {
             request_span(r, profile_cpu_handler)
         })
@@ -41,15 +98,25 @@ fn make_router(metrics: AppMetrics) -> RouterBuilder<hyper0::Body, ApiError> {
         })
 }
 
+/// Serve the metrics and the status. Draining the proxy needs a JWT with the
+/// admin scope if `auth` is set, and is only served on loopback otherwise.
 pub async fn task_main(
     http_listener: TcpListener,
+    shutdown: CancellationToken,
     metrics: AppMetrics,
+    auth: Option<Arc<SwappableJwtAuth>>,
 ) -> anyhow::Result<Infallible> {
     scopeguard::defer! {
         info!("http has shut down");
     }
 
-    let service = || RouterService::new(make_router(metrics).build()?);
+    let addr = http_listener.local_addr()?;
+    let allow_drain = auth.is_some() || addr.ip().is_loopback();
+    if !allow_drain {
+        warn!("/v1/drain is disabled, http on {addr} requires --http-auth-public-key-path for it");
+    }
+
+    let service = || RouterService::new(make_router(metrics, shutdown, auth, allow_drain).build()?);
 
     hyper0::Server::from_tcp(http_listener)?
         .serve(service().map_err(|e| anyhow!(e))?)
@@ -111,3 +178,75 @@ async fn prometheus_metrics_handler(
 
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use metrics::BuildInfo;
+    use utils::auth::JwtAuth;
+
+    use super::*;
+
+    // from libs/utils/src/auth.rs
+    const TEST_PUB_KEY_ED25519: &str = r#"
+-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEARYwaNBayR+eGI0iXB4s3QxE3Nl2g1iWbr6KtLWeVD/w=
+-----END PUBLIC KEY-----
+"#;
+
+    #[tokio::test]
+    async fn drain_requires_auth() {
+        let dir = camino_tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("auth_public_key.pem");
+        std::fs::write(&key_path, TEST_PUB_KEY_ED25519).unwrap();
+        let auth = JwtAuth::from_key_path(&key_path).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .into_std()
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let metrics = AppMetrics {
+            jemalloc: None,
+            neon_metrics: NeonMetrics::new(BuildInfo {
+                revision: "test",
+                build_tag: "test",
+            }),
+            proxy: Metrics::get(),
+        };
+        tokio::spawn(task_main(
+            listener,
+            shutdown.clone(),
+            metrics,
+            Some(Arc::new(SwappableJwtAuth::new(auth))),
+        ));
+
+        let client = reqwest::Client::new();
+
+        // the status needs no token
+        let res = client
+            .get(format!("http://{addr}/v1/status"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+
+        let res = client
+            .post(format!("http://{addr}/v1/drain"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 401);
+
+        let res = client
+            .post(format!("http://{addr}/v1/drain"))
+            .bearer_auth("not-a-token")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+
+        assert!(!shutdown.is_cancelled());
+    }
+}
@@ -48,6 +48,7 @@ pub fn get() -> &'static Self {
             metrics.proxy.redis_events_count.init_all_dense();
             metrics.proxy.retries_metric.init_all_dense();
             metrics.proxy.connection_failures_total.init_all_dense();
+            metrics.proxy.client_sessions_ended_total.init_all_dense();
 
             metrics
         })
@@ -93,6 +94,9 @@ pub struct ProxyMetrics {
     /// Number of opened connections to a database.
     pub http_pool_opened_connections: Gauge,
 
+    /// Number of connections pinned to interactive transactions over http.
+    pub http_txn_sessions: Gauge,
+
     /// Number of allowed ips
     #[metric(metadata = Thresholds::with_buckets([0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 10.0, 20.0, 50.0, 100.0]))]
     pub allowed_ips_number: Histogram<10>,
@@ -144,6 +148,12 @@ pub struct ProxyMetrics {
     /// Number of events consumed from redis (per event type).
     pub redis_events_count: CounterVec<StaticLabelSet<RedisEventsCount>>,
 
+    /// Number of sessions rejected and queries held back by the session limits of endpoints.
+    pub session_limits_exceeded: CounterVec<SessionLimitSet>,
+
+    /// Number of client sessions that ended after the proxy pass started, by why they ended.
+    pub client_sessions_ended_total: CounterVec<StaticLabelSet<SessionEndReason>>,
+
     #[metric(namespace = "connect_compute_lock")]
     pub connect_compute_lock: ApiLockMetrics,
 
@@ -345,6 +355,40 @@ pub enum SniKind {
     PasswordHack,
 }
 
+#[derive(LabelGroup)]
+#[label(set = SessionLimitSet)]
+pub struct SessionLimitGroup {
+    pub protocol: Protocol,
+    pub limit: SessionLimitKind,
+}
+
+#[derive(FixedCardinalityLabel, Copy, Clone)]
+pub enum SessionLimitKind {
+    /// Concurrent sessions to the endpoint.
+    EndpointConnections,
+    /// Concurrent sessions of a role to the endpoint.
+    RoleConnections,
+    /// Rate of queries of a role to the endpoint.
+    Queries,
+}
+
+#[derive(FixedCardinalityLabel, Copy, Clone, Debug, PartialEq, Eq)]
+#[label(singleton = "reason")]
+pub enum SessionEndReason {
+    /// The client closed the connection.
+    ClientDisconnect,
+    /// The compute closed the connection.
+    ComputeDisconnect,
+    ClientError,
+    ComputeError,
+    /// The session was idle outside of a transaction for too long.
+    IdleSessionTimeout,
+    /// The session was idle inside of a transaction for too long.
+    IdleInTransactionTimeout,
+    /// The session was open for longer than the endpoint allows.
+    MaxLifetime,
+}
+
 #[derive(FixedCardinalityLabel, Copy, Clone)]
 #[label(singleton = "kind")]
 pub enum ConnectionFailureKind {
@@ -371,6 +415,7 @@ pub enum CancellationOutcome {
     NotFound,
     Found,
     RateLimitExceeded,
+    Forwarded,
 }
 
 #[derive(LabelGroup)]
@@ -395,6 +440,7 @@ pub enum RedisMsgKind {
     Get,
     Expire,
     HGet,
+    Del,
 }
 
 #[derive(Default, Clone)]
@@ -592,6 +638,7 @@ pub enum RedisEventsCount {
     InvalidateProject,
     InvalidateProjects,
     InvalidateOrg,
+    PurgeQueryCache,
 }
 
 pub struct ThreadPoolWorkers(usize);
@@ -733,6 +780,8 @@ pub enum CacheKind {
     ProjectInfoEndpoints,
     ProjectInfoRoles,
     Schema,
+    OpenApi,
+    QueryResult,
     Pbkdf2,
 }
 
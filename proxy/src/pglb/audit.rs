@@ -0,0 +1,467 @@
+//! Audit log of the queries of passthrough sessions.
+//!
+//! The passthrough only forwards bytes, so the audit follows the messages in
+//! both directions of the client stream: the queries the client sends, and the
+//! command completions and errors it gets back from the compute.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use smol_str::SmolStr;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::context::audit::{AuditedQuery, QueryAudit, parse_command_tag};
+use crate::pqproto::{
+    BE_COMMAND_COMPLETE, BE_EMPTY_QUERY_RESPONSE, BE_ERROR_RESPONSE, BE_PORTAL_SUSPENDED,
+    BE_READY_FOR_QUERY, FE_BIND, FE_CLOSE, FE_EXECUTE, FE_FUNCTION_CALL, FE_PARSE, FE_QUERY,
+    FE_SYNC, read_cstr,
+};
+
+/// Longer message bodies are truncated, along with the statements in them.
+const MAX_AUDITED_LEN: usize = 64 * 1024;
+
+/// Client stream which records the queries of the session in the audit log.
+pub(crate) struct QueryAuditStream<S> {
+    inner: S,
+    /// Not set if the endpoint doesn't have the audit log enabled.
+    state: Option<Box<AuditState>>,
+}
+
+impl<S> QueryAuditStream<S> {
+    pub(crate) fn new(inner: S, audit: QueryAudit) -> Self {
+        let state = audit.is_enabled().then(|| {
+            Box::new(AuditState {
+                frontend: MessageReader::new(MAX_AUDITED_LEN),
+                backend: MessageReader::new(MAX_AUDITED_LEN),
+                queries: QueryTracker::new(audit),
+            })
+        });
+        Self { inner, state }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for QueryAuditStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(state) = &mut this.state {
+            let AuditState {
+                frontend, queries, ..
+            } = &mut **state;
+            frontend.read(
+                &buf.filled()[filled..],
+                is_audited_frontend,
+                |tag, body, _| {
+                    queries.frontend(tag, body);
+                },
+            );
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for QueryAuditStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(state) = &mut this.state {
+            let AuditState {
+                backend, queries, ..
+            } = &mut **state;
+            backend.read(&buf[..n], is_audited_backend, |tag, body, _| {
+                queries.backend(tag, body);
+            });
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+struct AuditState {
+    frontend: MessageReader,
+    backend: MessageReader,
+    queries: QueryTracker,
+}
+
+fn is_audited_frontend(tag: u8) -> bool {
+    matches!(
+        tag,
+        FE_QUERY | FE_PARSE | FE_BIND | FE_EXECUTE | FE_CLOSE | FE_SYNC | FE_FUNCTION_CALL
+    )
+}
+
+fn is_audited_backend(tag: u8) -> bool {
+    matches!(
+        tag,
+        BE_COMMAND_COMPLETE
+            | BE_EMPTY_QUERY_RESPONSE
+            | BE_PORTAL_SUSPENDED
+            | BE_ERROR_RESPONSE
+            | BE_READY_FOR_QUERY
+    )
+}
+
+/// Follows the messages in one direction of the byte stream, keeping the
+/// bodies of the messages the caller is interested in.
+///
+/// <https://www.postgresql.org/docs/current/protocol-message-formats.html>
+pub(super) struct MessageReader {
+    /// Tag and length of the current message.
+    header: [u8; 5],
+    header_len: usize,
+    /// Remaining bytes of the body of the current message.
+    body_len: usize,
+    /// Body of the current message, if it's kept.
+    body: Option<Vec<u8>>,
+    /// Longer bodies are truncated.
+    max_len: usize,
+}
+
+impl MessageReader {
+    pub(super) fn new(max_len: usize) -> Self {
+        Self {
+            header: [0; 5],
+            header_len: 0,
+            body_len: 0,
+            body: None,
+            max_len,
+        }
+    }
+
+    /// Consume the next bytes of the stream, passing the messages with the
+    /// `kept` tags to `on_message` once they are read entirely, along with
+    /// whether their bodies were truncated.
+    pub(super) fn read(
+        &mut self,
+        mut bytes: &[u8],
+        kept: fn(u8) -> bool,
+        mut on_message: impl FnMut(u8, &[u8], bool),
+    ) {
+        loop {
+            if self.header_len < self.header.len() {
+                let n = (self.header.len() - self.header_len).min(bytes.len());
+                self.header[self.header_len..self.header_len + n].copy_from_slice(&bytes[..n]);
+                self.header_len += n;
+                bytes = &bytes[n..];
+                if self.header_len < self.header.len() {
+                    return;
+                }
+
+                let [tag, len @ ..] = self.header;
+                // the length includes itself
+                self.body_len = (u32::from_be_bytes(len) as usize).saturating_sub(4);
+                self.body = kept(tag).then(Vec::new);
+            }
+
+            let n = self.body_len.min(bytes.len());
+            if let Some(body) = &mut self.body {
+                let kept_len = n.min(self.max_len.saturating_sub(body.len()));
+                body.extend_from_slice(&bytes[..kept_len]);
+            }
+            self.body_len -= n;
+            bytes = &bytes[n..];
+
+            if self.body_len > 0 {
+                return;
+            }
+            if let Some(body) = self.body.take() {
+                let [tag, len @ ..] = self.header;
+                let truncated = body.len() + 4 < u32::from_be_bytes(len) as usize;
+                on_message(tag, &body, truncated);
+            }
+            self.header_len = 0;
+
+            if bytes.is_empty() {
+                return;
+            }
+        }
+    }
+}
+
+/// A request of the client which the compute responds to.
+enum Pending {
+    /// Simple query, which ends with `ReadyForQuery`.
+    Query(AuditedQuery),
+    /// Execution of a portal.
+    Execute(AuditedQuery),
+    /// Sync or function call, which ends with `ReadyForQuery`.
+    Sync,
+}
+
+/// Matches the responses of the compute to the queries of the client.
+struct QueryTracker {
+    audit: QueryAudit,
+    /// Text of the prepared statements, by name.
+    statements: HashMap<SmolStr, Arc<str>>,
+    /// Text of the statements of the portals, by name.
+    portals: HashMap<SmolStr, Arc<str>>,
+    /// Requests sent to the compute which haven't been responded to yet.
+    pending: VecDeque<Pending>,
+    /// The first error since the last `ReadyForQuery`. The compute skips the
+    /// executions after an error, until the next sync.
+    error: Option<SmolStr>,
+}
+
+impl QueryTracker {
+    fn new(audit: QueryAudit) -> Self {
+        Self {
+            audit,
+            statements: HashMap::new(),
+            portals: HashMap::new(),
+            pending: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    fn frontend(&mut self, tag: u8, body: &[u8]) {
+        match tag {
+            FE_QUERY => {
+                let (query, _) = read_str(body);
+                let query = self.audit.start(&query);
+                self.pending.push_back(Pending::Query(query));
+            }
+            FE_PARSE => {
+                let (name, rest) = read_str(body);
+                let (query, _) = read_str(rest);
+                self.statements.insert(name.into(), query.into());
+            }
+            FE_BIND => {
+                let (portal, rest) = read_str(body);
+                let (statement, _) = read_str(rest);
+                match self.statements.get(&*statement) {
+                    Some(query) => self.portals.insert(portal.into(), Arc::clone(query)),
+                    None => self.portals.remove(&*portal),
+                };
+            }
+            FE_EXECUTE => {
+                let (portal, _) = read_str(body);
+                let query = self.portals.get(&*portal).map_or("", |query| &**query);
+                let query = self.audit.start(query);
+                self.pending.push_back(Pending::Execute(query));
+            }
+            FE_CLOSE => match body.split_first() {
+                Some((b'S', name)) => {
+                    self.statements.remove(&*read_str(name).0);
+                }
+                Some((b'P', name)) => {
+                    self.portals.remove(&*read_str(name).0);
+                }
+                _ => {}
+            },
+            FE_SYNC | FE_FUNCTION_CALL => self.pending.push_back(Pending::Sync),
+            _ => {}
+        }
+    }
+
+    fn backend(&mut self, tag: u8, body: &[u8]) {
+        match tag {
+            BE_COMMAND_COMPLETE => {
+                let (command_tag, _) = read_str(body);
+                let (command, rows) = parse_command_tag(&command_tag);
+                match self.pending.front_mut() {
+                    Some(Pending::Query(query)) => query.complete(command, rows),
+                    Some(Pending::Execute(query)) => {
+                        query.complete(command, rows);
+                        self.finish_execute();
+                    }
+                    Some(Pending::Sync) | None => {}
+                }
+            }
+            BE_EMPTY_QUERY_RESPONSE | BE_PORTAL_SUSPENDED => {
+                if let Some(Pending::Execute(_)) = self.pending.front() {
+                    self.finish_execute();
+                }
+            }
+            BE_ERROR_RESPONSE => {
+                let code = error_code(body);
+                match self.pending.front_mut() {
+                    Some(Pending::Query(query)) => query.fail(&code),
+                    Some(Pending::Execute(query)) => {
+                        query.fail(&code);
+                        self.finish_execute();
+                    }
+                    Some(Pending::Sync) | None => {}
+                }
+                self.error.get_or_insert(code);
+            }
+            BE_READY_FOR_QUERY => {
+                // the executions which are still pending were skipped because of an error.
+                let error = self.error.take();
+                while let Some(pending) = self.pending.pop_front() {
+                    match pending {
+                        Pending::Query(query) => {
+                            query.finish();
+                            break;
+                        }
+                        Pending::Execute(mut query) => {
+                            if let Some(error) = &error {
+                                query.fail(error);
+                            }
+                            query.finish();
+                        }
+                        Pending::Sync => break,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish_execute(&mut self) {
+        if let Some(Pending::Execute(query)) = self.pending.pop_front() {
+            query.finish();
+        }
+    }
+}
+
+/// Read a null-terminated string from the front of a message body. The string
+/// is not terminated if the body was truncated.
+fn read_str(body: &[u8]) -> (Cow<'_, str>, &[u8]) {
+    match read_cstr(body) {
+        Ok((s, rest)) => (String::from_utf8_lossy(s), rest),
+        Err(_) => (String::from_utf8_lossy(body), &[]),
+    }
+}
+
+/// SQLSTATE from the fields of an `ErrorResponse`.
+pub(crate) fn error_code(mut body: &[u8]) -> SmolStr {
+    while let Some((&field, rest)) = body.split_first()
+        && field != 0
+    {
+        let (value, rest) = read_str(rest);
+        if field == b'C' {
+            return value.into();
+        }
+        body = rest;
+    }
+    SmolStr::new_static("XX000")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_plane::messages::AuditLogMode;
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn read_messages() {
+        let mut stream = message(b'Q', b"select 1\0");
+        stream.extend(message(b'd', b"QE"));
+        stream.extend(message(b'S', b""));
+        stream.extend(message(b'P', b"s1\0select $1\0\0\0"));
+
+        let expected = vec![
+            (b'Q', b"select 1\0".to_vec()),
+            (b'S', vec![]),
+            (b'P', b"s1\0select $1\0\0\0".to_vec()),
+        ];
+
+        // the messages can be split anywhere between reads
+        for chunk in [1, 2, 3, 5, 7, stream.len()] {
+            let mut reader = MessageReader::new(MAX_AUDITED_LEN);
+            let mut messages = vec![];
+            for c in stream.chunks(chunk) {
+                reader.read(c, is_audited_frontend, |tag, body, _| {
+                    messages.push((tag, body.to_vec()));
+                });
+            }
+            assert_eq!(messages, expected, "chunks of {chunk} bytes");
+        }
+    }
+
+    #[test]
+    fn track_queries() {
+        let (audit, mut records) = QueryAudit::test(AuditLogMode::Full);
+        let mut tracker = QueryTracker::new(audit);
+
+        // simple query, with two commands
+        tracker.frontend(FE_QUERY, b"insert into t values (1); select 1\0");
+        tracker.backend(BE_COMMAND_COMPLETE, b"INSERT 0 1\0");
+        tracker.backend(BE_COMMAND_COMPLETE, b"SELECT 1\0");
+        assert!(records.try_recv().is_err());
+        tracker.backend(BE_READY_FOR_QUERY, b"I");
+
+        let record = records.try_recv().expect("query should be recorded");
+        assert_eq!(
+            record.query.as_deref(),
+            Some("insert into t values (1); select 1")
+        );
+        assert_eq!(record.command.as_deref(), Some("SELECT"));
+        assert_eq!(record.rows, Some(2));
+        assert_eq!(record.error, None);
+
+        // extended query, with a prepared statement executed twice
+        tracker.frontend(FE_PARSE, b"s1\0select $1\0\0\0");
+        for _ in 0..2 {
+            tracker.frontend(FE_BIND, b"\0s1\0\0\0\0\0\0\0");
+            tracker.frontend(FE_EXECUTE, b"\0\0\0\0\0");
+        }
+        tracker.frontend(FE_SYNC, b"");
+        tracker.backend(BE_COMMAND_COMPLETE, b"SELECT 1\0");
+        tracker.backend(BE_ERROR_RESPONSE, b"SERROR\0C57014\0\0");
+        tracker.backend(BE_READY_FOR_QUERY, b"I");
+
+        let record = records.try_recv().expect("execution should be recorded");
+        assert_eq!(record.query.as_deref(), Some("select $1"));
+        assert_eq!(record.rows, Some(1));
+        assert_eq!(record.error, None);
+        let record = records.try_recv().expect("execution should be recorded");
+        assert_eq!(record.query.as_deref(), Some("select $1"));
+        assert_eq!(record.error.as_deref(), Some("57014"));
+
+        // executions after an error are skipped
+        tracker.frontend(FE_PARSE, b"\0selec\0\0\0");
+        tracker.frontend(FE_BIND, b"\0\0\0\0\0\0\0\0");
+        tracker.frontend(FE_EXECUTE, b"\0\0\0\0\0");
+        tracker.frontend(FE_SYNC, b"");
+        // pipelined after the sync
+        tracker.frontend(FE_QUERY, b"select 2\0");
+        tracker.backend(BE_ERROR_RESPONSE, b"SERROR\0C42601\0\0");
+        tracker.backend(BE_READY_FOR_QUERY, b"I");
+
+        let record = records.try_recv().expect("execution should be recorded");
+        assert_eq!(record.query.as_deref(), Some("selec"));
+        assert_eq!(record.error.as_deref(), Some("42601"));
+        assert!(records.try_recv().is_err());
+
+        // the session ends before the query completes
+        drop(tracker);
+        let record = records.try_recv().expect("query should be recorded");
+        assert_eq!(record.query.as_deref(), Some("select 2"));
+        assert_eq!(record.error.as_deref(), Some("aborted"));
+    }
+
+    #[test]
+    fn error_codes() {
+        assert_eq!(error_code(b"SERROR\0C42P01\0Mmissing\0\0"), "42P01");
+        assert_eq!(error_code(b"SERROR\0\0"), "XX000");
+    }
+}
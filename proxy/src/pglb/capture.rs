@@ -0,0 +1,277 @@
+//! Capture of the messages of passthrough sessions.
+//!
+//! The capture follows the messages in both directions of the client stream,
+//! and strips the secrets from them before they are recorded: the password and
+//! SASL messages are dropped, the literals of the statements which mention
+//! passwords are replaced like in the normalized audit log, and the parameter
+//! values of the extended protocol are recorded as nulls.
+
+use std::borrow::Cow;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::audit::MessageReader;
+use crate::context::audit::normalize;
+use crate::context::capture::SessionCapture;
+use crate::pqproto::{
+    BE_AUTHENTICATION, BE_BACKEND_KEY_DATA, FE_BIND, FE_PARSE, FE_PASSWORD_MESSAGE, FE_QUERY,
+    read_cstr,
+};
+
+/// Longer message bodies are truncated. The truncated messages can't be replayed.
+const MAX_CAPTURED_LEN: usize = 1024 * 1024;
+
+/// Client stream which records the messages of the session.
+pub(crate) struct CaptureStream<S> {
+    inner: S,
+    /// Not set if the endpoint is not captured.
+    state: Option<Box<CaptureState>>,
+}
+
+impl<S> CaptureStream<S> {
+    pub(crate) fn new(inner: S, capture: SessionCapture) -> Self {
+        let state = capture.is_enabled().then(|| {
+            Box::new(CaptureState {
+                frontend: MessageReader::new(MAX_CAPTURED_LEN),
+                backend: MessageReader::new(MAX_CAPTURED_LEN),
+                capture,
+            })
+        });
+        Self { inner, state }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CaptureStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(state) = &mut this.state {
+            let CaptureState {
+                frontend, capture, ..
+            } = &mut **state;
+            frontend.read(
+                &buf.filled()[filled..],
+                |_| true,
+                |tag, body, truncated| {
+                    if let Some(body) = strip_frontend(tag, body) {
+                        capture.frontend(message(tag, &body), truncated);
+                    }
+                },
+            );
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CaptureStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(state) = &mut this.state {
+            let CaptureState {
+                backend, capture, ..
+            } = &mut **state;
+            backend.read(
+                &buf[..n],
+                |_| true,
+                |tag, body, truncated| {
+                    if let Some(body) = strip_backend(tag, body) {
+                        capture.backend(message(tag, body), truncated);
+                    }
+                },
+            );
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+struct CaptureState {
+    frontend: MessageReader,
+    backend: MessageReader,
+    capture: SessionCapture,
+}
+
+/// The body to record for the message of the client, if any.
+fn strip_frontend(tag: u8, body: &[u8]) -> Option<Cow<'_, [u8]>> {
+    match tag {
+        // also used for the SASL and GSSAPI responses.
+        FE_PASSWORD_MESSAGE => None,
+        // the body is the statement, ending with the null terminator.
+        FE_QUERY => Some(strip_query(body).map_or(Cow::Borrowed(body), Cow::Owned)),
+        FE_PARSE => {
+            let Ok((name, rest)) = read_cstr(body) else {
+                return Some(Cow::Borrowed(body));
+            };
+            // the statement is cut short if the message is truncated.
+            let (query, rest) = read_cstr(rest).unwrap_or((rest, &[]));
+            Some(strip_query(query).map_or(Cow::Borrowed(body), |query| {
+                Cow::Owned([name, &query[..], rest].join(&0))
+            }))
+        }
+        // the parameters can hold the secrets of the statement, e.g. a password
+        // passed as `$1`. Malformed messages are dropped rather than recorded as-is.
+        FE_BIND => strip_bind(body).map(Cow::Owned),
+        _ => Some(Cow::Borrowed(body)),
+    }
+}
+
+/// Replace the values of the parameters of a Bind message with nulls, keeping
+/// the message well-formed.
+fn strip_bind(body: &[u8]) -> Option<Vec<u8>> {
+    let (portal, rest) = read_cstr(body).ok()?;
+    let (statement, mut rest) = read_cstr(rest).ok()?;
+    let format_count = read_count(&mut rest)?;
+    let (formats, mut rest) = rest.split_at_checked(format_count * 2)?;
+    let params = read_count(&mut rest)?;
+    for _ in 0..params {
+        let (len, tail) = rest.split_first_chunk::<4>()?;
+        rest = tail;
+        // -1 for a null value.
+        if let Ok(len) = usize::try_from(i32::from_be_bytes(*len)) {
+            rest = rest.get(len..)?;
+        }
+    }
+
+    let mut stripped = Vec::with_capacity(body.len());
+    for name in [portal, statement] {
+        stripped.extend_from_slice(name);
+        stripped.push(0);
+    }
+    stripped.extend_from_slice(&(format_count as u16).to_be_bytes());
+    stripped.extend_from_slice(formats);
+    stripped.extend_from_slice(&(params as u16).to_be_bytes());
+    for _ in 0..params {
+        stripped.extend_from_slice(&(-1i32).to_be_bytes());
+    }
+    // the result column format codes.
+    stripped.extend_from_slice(rest);
+    Some(stripped)
+}
+
+fn read_count(buf: &mut &[u8]) -> Option<usize> {
+    let (count, rest) = buf.split_first_chunk::<2>()?;
+    *buf = rest;
+    Some(u16::from_be_bytes(*count).into())
+}
+
+/// The body to record for the message the client received, if any.
+fn strip_backend(tag: u8, body: &[u8]) -> Option<&[u8]> {
+    match tag {
+        // not expected after the authentication, but they must not leak either.
+        BE_AUTHENTICATION | BE_BACKEND_KEY_DATA => None,
+        _ => Some(body),
+    }
+}
+
+/// Replace the literals of the statement if it could set a password,
+/// e.g. `ALTER ROLE ... PASSWORD '...'`.
+fn strip_query(query: &[u8]) -> Option<Vec<u8>> {
+    if !query
+        .windows(b"password".len())
+        .any(|w| w.eq_ignore_ascii_case(b"password"))
+    {
+        return None;
+    }
+    Some(normalize(&String::from_utf8_lossy(query)).into_bytes())
+}
+
+fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(body.len() + 5);
+    message.push(tag);
+    // the length includes itself
+    message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn capture_without_secrets() {
+        let (capture, mut records) = SessionCapture::test();
+        let (client, mut peer) = duplex(1024);
+        let mut client = CaptureStream::new(client, capture);
+
+        let frontend = [
+            message(b'p', b"hunter2\0"),
+            message(b'Q', b"select 1\0"),
+            message(b'Q', b"alter role foo password 'hunter2'\0"),
+            message(b'P', b"s1\0create user bar with PASSWORD 'hunter2'\0\0\0"),
+            message(b'P', b"s2\0alter role foo password $1\0\0\0"),
+            message(
+                b'B',
+                b"\0s2\0\0\x01\0\0\0\x02\0\0\0\x07hunter2\xff\xff\xff\xff\0\x01\0\0",
+            ),
+            // malformed, the parameter is cut short.
+            message(b'B', b"\0s2\0\0\0\0\x01\0\0\0\x07hunter"),
+            message(b'S', b""),
+        ]
+        .concat();
+        peer.write_all(&frontend).await.unwrap();
+        let mut buf = vec![0; frontend.len()];
+        client.read_exact(&mut buf).await.unwrap();
+
+        let backend = [message(b'K', &[0; 8]), message(b'Z', b"I")].concat();
+        client.write_all(&backend).await.unwrap();
+
+        drop(client);
+        let mut captured = vec![];
+        while let Ok(record) = records.try_recv() {
+            assert!(!record.truncated);
+            captured.push((record.sequence, record.direction, record.message));
+        }
+        assert_eq!(
+            captured,
+            [
+                (0, "frontend", message(b'Q', b"select 1\0")),
+                (1, "frontend", message(b'Q', b"alter role foo password ?\0")),
+                (
+                    2,
+                    "frontend",
+                    message(b'P', b"s1\0create user bar with PASSWORD ?\0\0\0")
+                ),
+                (
+                    3,
+                    "frontend",
+                    message(b'P', b"s2\0alter role foo password $1\0\0\0")
+                ),
+                (
+                    4,
+                    "frontend",
+                    message(
+                        b'B',
+                        b"\0s2\0\0\x01\0\0\0\x02\xff\xff\xff\xff\xff\xff\xff\xff\0\x01\0\0"
+                    )
+                ),
+                (5, "frontend", message(b'S', b"")),
+                (6, "backend", message(b'Z', b"I")),
+            ]
+        );
+    }
+}
@@ -2,10 +2,21 @@
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
+use std::time::Duration;
 
+use bytes::Buf;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
 use tracing::info;
 
+use super::drain::MessageScanner;
+use crate::control_plane::messages::SessionTimeouts;
+use crate::metrics::SessionEndReason;
+use crate::pqproto::{
+    BE_READY_FOR_QUERY, FE_FUNCTION_CALL, FE_QUERY, FE_SYNC, SQLSTATE_ADMIN_SHUTDOWN,
+    SQLSTATE_IDLE_IN_TRANSACTION_SESSION_TIMEOUT, SQLSTATE_IDLE_SESSION_TIMEOUT, WriteBuf,
+};
+
 #[derive(Debug)]
 enum TransferState {
     Running(CopyBuffer),
@@ -13,6 +24,15 @@ enum TransferState {
     Done(u64),
 }
 
+impl TransferState {
+    fn amount(&self) -> u64 {
+        match self {
+            TransferState::Running(buf) => buf.amt,
+            TransferState::ShuttingDown(count) | TransferState::Done(count) => *count,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum ErrorDirection {
     Read(io::Error),
@@ -76,50 +96,397 @@ pub async fn copy_bidirectional_client_compute<Client, Compute>(
     Client: AsyncRead + AsyncWrite + Unpin + ?Sized,
     Compute: AsyncRead + AsyncWrite + Unpin + ?Sized,
 {
+    let (client_to_compute, compute_to_client, _) =
+        copy_bidirectional(client, compute, SessionTimeouts::default()).await?;
+    Ok((client_to_compute, compute_to_client))
+}
+
+/// Like [`copy_bidirectional_client_compute`], for a postgres session which is
+/// closed by the proxy once it exceeds the `timeouts`. Returns why the session ended.
+#[tracing::instrument(skip_all)]
+pub(crate) async fn copy_bidirectional_with_timeouts<Client, Compute>(
+    client: &mut Client,
+    compute: &mut Compute,
+    timeouts: SessionTimeouts,
+) -> Result<SessionEndReason, ErrorSource>
+where
+    Client: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    Compute: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let (_, _, reason) = copy_bidirectional(client, compute, timeouts).await?;
+    Ok(reason)
+}
+
+async fn copy_bidirectional<Client, Compute>(
+    client: &mut Client,
+    compute: &mut Compute,
+    timeouts: SessionTimeouts,
+) -> Result<(u64, u64, SessionEndReason), ErrorSource>
+where
+    Client: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    Compute: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut client = SessionClient::new(client, timeouts.is_enforced());
+    let mut timer = SessionTimer::new(timeouts);
     let mut client_to_compute = TransferState::Running(CopyBuffer::new());
     let mut compute_to_client = TransferState::Running(CopyBuffer::new());
+    let mut ended_by = SessionEndReason::ClientDisconnect;
+    let mut terminating = None;
 
     poll_fn(|cx| {
-        let mut client_to_compute_result =
-            transfer_one_direction(cx, &mut client_to_compute, client, compute)
-                .map_err(ErrorSource::from_client)?;
-        let mut compute_to_client_result =
-            transfer_one_direction(cx, &mut compute_to_client, compute, client)
-                .map_err(ErrorSource::from_compute)?;
-
-        // TODO: 1 info log, with a enum label for close direction.
-
-        // Early termination checks from compute to client.
-        if let TransferState::Done(_) = compute_to_client
-            && let TransferState::Running(buf) = &client_to_compute
-        {
-            info!("Compute is done, terminate client");
-            // Initiate shutdown
-            client_to_compute = TransferState::ShuttingDown(buf.amt);
-            client_to_compute_result =
-                transfer_one_direction(cx, &mut client_to_compute, client, compute)
+        loop {
+            if let Some(reason) = terminating {
+                ready!(client.poll_terminate(cx)).map_err(ErrorSource::Client)?;
+                ready!(Pin::new(&mut *compute).poll_shutdown(cx)).map_err(ErrorSource::Compute)?;
+                return Poll::Ready(Ok((
+                    client_to_compute.amount(),
+                    compute_to_client.amount(),
+                    reason,
+                )));
+            }
+
+            let mut client_to_compute_result =
+                transfer_one_direction(cx, &mut client_to_compute, &mut client, compute)
                     .map_err(ErrorSource::from_client)?;
+            let mut compute_to_client_result =
+                transfer_one_direction(cx, &mut compute_to_client, compute, &mut client)
+                    .map_err(ErrorSource::from_compute)?;
+
+            // TODO: 1 info log, with a enum label for close direction.
+
+            // Early termination checks from compute to client.
+            if let TransferState::Done(_) = compute_to_client
+                && let TransferState::Running(buf) = &client_to_compute
+            {
+                info!("Compute is done, terminate client");
+                ended_by = SessionEndReason::ComputeDisconnect;
+                // Initiate shutdown
+                client_to_compute = TransferState::ShuttingDown(buf.amt);
+                client_to_compute_result =
+                    transfer_one_direction(cx, &mut client_to_compute, &mut client, compute)
+                        .map_err(ErrorSource::from_client)?;
+            }
+
+            // Early termination checks from client to compute.
+            if let TransferState::Done(_) = client_to_compute
+                && let TransferState::Running(buf) = &compute_to_client
+            {
+                info!("Client is done, terminate compute");
+                // Initiate shutdown
+                compute_to_client = TransferState::ShuttingDown(buf.amt);
+                compute_to_client_result =
+                    transfer_one_direction(cx, &mut compute_to_client, compute, &mut client)
+                        .map_err(ErrorSource::from_compute)?;
+            }
+
+            // The timers are checked once the messages that are ready were
+            // forwarded, as they might have changed whether the session is idle.
+            if let Some(reason) = timer.poll_expired(cx, &mut client) {
+                info!(?reason, "session timed out, terminate client and compute");
+                client.write_termination(reason);
+                terminating = Some(reason);
+                continue;
+            }
+
+            // It is not a problem if ready! returns early ... (comment remains the same)
+            let client_to_compute = ready!(client_to_compute_result);
+            let compute_to_client = ready!(compute_to_client_result);
+
+            return Poll::Ready(Ok((client_to_compute, compute_to_client, ended_by)));
         }
+    })
+    .await
+}
+
+/// The client side of a postgres session, which follows the messages in both
+/// directions to find out when the session is idle.
+struct SessionClient<'a, C: ?Sized> {
+    inner: &'a mut C,
+    /// Set if any timeouts are enforced.
+    protocol: Option<ProtocolState>,
+    /// Written to the client when the proxy closes the session.
+    error: WriteBuf,
+    need_flush: bool,
+}
+
+struct ProtocolState {
+    frontend: MessageScanner,
+    backend: MessageScanner,
+    /// Number of `ReadyForQuery` messages the client is waiting for.
+    pending: usize,
+    /// Transaction status of the compute while the session is idle: the compute
+    /// sent the last `ReadyForQuery` and the client has not sent anything since.
+    idle: Option<u8>,
+    /// Whether the session went idle or stopped being idle since the timer was last checked.
+    changed: bool,
+}
+
+impl<'a, C: ?Sized> SessionClient<'a, C> {
+    fn new(inner: &'a mut C, enforced: bool) -> Self {
+        let protocol = enforced.then(|| ProtocolState {
+            frontend: MessageScanner::default(),
+            backend: MessageScanner::default(),
+            pending: 0,
+            // the compute is ready for the first query once the proxy pass starts.
+            idle: Some(b'I'),
+            changed: true,
+        });
+        Self {
+            inner,
+            protocol,
+            error: WriteBuf::new(),
+            need_flush: false,
+        }
+    }
+
+    fn write_termination(&mut self, reason: SessionEndReason) {
+        let (message, code) = match reason {
+            SessionEndReason::IdleInTransactionTimeout => (
+                "terminating connection due to idle-in-transaction timeout",
+                SQLSTATE_IDLE_IN_TRANSACTION_SESSION_TIMEOUT,
+            ),
+            SessionEndReason::IdleSessionTimeout => (
+                "terminating connection due to idle-session timeout",
+                SQLSTATE_IDLE_SESSION_TIMEOUT,
+            ),
+            _ => (
+                "terminating connection due to maximum session lifetime",
+                SQLSTATE_ADMIN_SHUTDOWN,
+            ),
+        };
+        self.error.write_error(message, code);
+    }
+}
+
+impl<C: AsyncWrite + Unpin + ?Sized> SessionClient<'_, C> {
+    /// Let the client know why the session ends, and close the connection.
+    fn poll_terminate(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.error.has_remaining() {
+            let n = ready!(Pin::new(&mut *self.inner).poll_write(cx, self.error.chunk()))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.error.advance(n);
+            self.need_flush = true;
+        }
+        if self.need_flush {
+            ready!(Pin::new(&mut *self.inner).poll_flush(cx))?;
+            self.need_flush = false;
+        }
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
 
-        // Early termination checks from client to compute.
-        if let TransferState::Done(_) = client_to_compute
-            && let TransferState::Running(buf) = &compute_to_client
+impl<C: AsyncRead + Unpin + ?Sized> AsyncRead for SessionClient<'_, C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut *this.inner).poll_read(cx, buf))?;
+
+        if let Some(p) = &mut this.protocol
+            && buf.filled().len() > filled
         {
-            info!("Client is done, terminate compute");
-            // Initiate shutdown
-            compute_to_client = TransferState::ShuttingDown(buf.amt);
-            compute_to_client_result =
-                transfer_one_direction(cx, &mut compute_to_client, compute, client)
-                    .map_err(ErrorSource::from_compute)?;
+            p.frontend.advance(
+                &buf.filled()[filled..],
+                |tag| {
+                    if matches!(tag, FE_QUERY | FE_SYNC | FE_FUNCTION_CALL) {
+                        p.pending += 1;
+                    }
+                },
+                |_, _| {},
+            );
+            if p.idle.take().is_some() {
+                p.changed = true;
+            }
         }
+        Poll::Ready(Ok(()))
+    }
+}
 
-        // It is not a problem if ready! returns early ... (comment remains the same)
-        let client_to_compute = ready!(client_to_compute_result);
-        let compute_to_client = ready!(compute_to_client_result);
+impl<C: AsyncWrite + Unpin + ?Sized> AsyncWrite for SessionClient<'_, C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = ready!(Pin::new(&mut *this.inner).poll_write(cx, buf))?;
+
+        if let Some(p) = &mut this.protocol {
+            let mut status = None;
+            p.backend.advance(
+                &buf[..n],
+                |tag| {
+                    if tag == BE_READY_FOR_QUERY {
+                        p.pending = p.pending.saturating_sub(1);
+                    }
+                },
+                |tag, body| {
+                    if tag == BE_READY_FOR_QUERY {
+                        status = Some(body[0]);
+                    }
+                },
+            );
+            // the client might have sent more queries already.
+            if let Some(status) = status
+                && p.pending == 0
+            {
+                p.idle = Some(status);
+                p.changed = true;
+            }
+        }
+        Poll::Ready(Ok(n))
+    }
 
-        Poll::Ready(Ok((client_to_compute, compute_to_client)))
-    })
-    .await
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Checks the session against its timeouts.
+struct SessionTimer {
+    timeouts: SessionTimeouts,
+    /// Deadline of the session while it's idle.
+    idle: Option<(SessionEndReason, Pin<Box<Sleep>>)>,
+    lifetime: Option<Pin<Box<Sleep>>>,
+    /// The session ends once it's idle outside of a transaction.
+    lifetime_expired: bool,
+}
+
+impl SessionTimer {
+    fn new(timeouts: SessionTimeouts) -> Self {
+        Self {
+            timeouts,
+            idle: None,
+            lifetime: timeouts
+                .max_lifetime
+                .map(|lifetime| Box::pin(tokio::time::sleep(lifetime))),
+            lifetime_expired: false,
+        }
+    }
+
+    fn poll_expired<C: ?Sized>(
+        &mut self,
+        cx: &mut Context<'_>,
+        client: &mut SessionClient<'_, C>,
+    ) -> Option<SessionEndReason> {
+        let p = client.protocol.as_mut()?;
+
+        if let Some(lifetime) = &mut self.lifetime
+            && lifetime.as_mut().poll(cx).is_ready()
+        {
+            self.lifetime = None;
+            self.lifetime_expired = true;
+        }
+
+        if p.changed {
+            p.changed = false;
+            let deadline = |reason, timeout: Option<Duration>| {
+                timeout.map(|timeout| (reason, Box::pin(tokio::time::sleep(timeout))))
+            };
+            self.idle = match p.idle {
+                None => None,
+                Some(b'I') => deadline(
+                    SessionEndReason::IdleSessionTimeout,
+                    self.timeouts.idle_session,
+                ),
+                // in a transaction, or in a failed one.
+                Some(_) => deadline(
+                    SessionEndReason::IdleInTransactionTimeout,
+                    self.timeouts.idle_in_transaction,
+                ),
+            };
+        }
+
+        let idle_expired = match &mut self.idle {
+            Some((reason, deadline)) if deadline.as_mut().poll(cx).is_ready() => Some(*reason),
+            _ => None,
+        };
+
+        // the compute might be sending a notification while the session is idle.
+        if !p.backend.at_boundary() {
+            return None;
+        }
+        if self.lifetime_expired && p.idle == Some(b'I') {
+            return Some(SessionEndReason::MaxLifetime);
+        }
+        idle_expired
+    }
+}
+
+/// A session client which enforces the timeouts by itself, for the sessions which are
+/// not forwarded by [`copy_bidirectional`], like the ones in transaction pooling mode.
+///
+/// The timers are checked whenever the client is read from, and the client reads as
+/// closed once the proxy terminated the session.
+pub(super) struct TimedClient<'a, C: ?Sized> {
+    client: SessionClient<'a, C>,
+    timer: SessionTimer,
+    ended_by: Option<SessionEndReason>,
+}
+
+impl<'a, C: ?Sized> TimedClient<'a, C> {
+    pub(super) fn new(inner: &'a mut C, timeouts: SessionTimeouts) -> Self {
+        Self {
+            client: SessionClient::new(inner, timeouts.is_enforced()),
+            timer: SessionTimer::new(timeouts),
+            ended_by: None,
+        }
+    }
+
+    /// Why the proxy ended the session, if it did.
+    pub(super) fn ended_by(&self) -> Option<SessionEndReason> {
+        self.ended_by
+    }
+}
+
+impl<C: AsyncRead + AsyncWrite + Unpin + ?Sized> AsyncRead for TimedClient<'_, C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.ended_by.is_none()
+            && let Some(reason) = this.timer.poll_expired(cx, &mut this.client)
+        {
+            info!(?reason, "session timed out, terminate client");
+            this.client.write_termination(reason);
+            this.ended_by = Some(reason);
+        }
+
+        if this.ended_by.is_some() {
+            ready!(this.client.poll_terminate(cx))?;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.client).poll_read(cx, buf)
+    }
+}
+
+impl<C: AsyncWrite + Unpin + ?Sized> AsyncWrite for TimedClient<'_, C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().client).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().client).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().client).poll_shutdown(cx)
+    }
 }
 
 #[derive(Debug)]
@@ -263,10 +630,118 @@ pub(super) fn poll_copy<R, W>(
 
 #[cfg(test)]
 mod tests {
-    use tokio::io::AsyncWriteExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+    use tokio::task::JoinHandle;
 
     use super::*;
 
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    /// Start a session, returning the client and the compute ends of it.
+    fn session(
+        timeouts: SessionTimeouts,
+    ) -> (
+        DuplexStream,
+        DuplexStream,
+        JoinHandle<Result<SessionEndReason, ErrorSource>>,
+    ) {
+        let (client, mut client_proxy) = duplex(1024);
+        let (mut compute_proxy, compute) = duplex(1024);
+        let task = tokio::spawn(async move {
+            copy_bidirectional_with_timeouts(&mut client_proxy, &mut compute_proxy, timeouts).await
+        });
+        (client, compute, task)
+    }
+
+    /// Run a query and return the status of the compute after it.
+    async fn query(client: &mut DuplexStream, compute: &mut DuplexStream, sql: &[u8], status: u8) {
+        let query = message(b'Q', sql);
+        client.write_all(&query).await.unwrap();
+        compute.read_exact(&mut vec![0; query.len()]).await.unwrap();
+        compute.write_all(&message(b'Z', &[status])).await.unwrap();
+        client.read_exact(&mut [0; 6]).await.unwrap();
+    }
+
+    async fn read_termination(client: &mut DuplexStream) {
+        let mut header = [0; 5];
+        client.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], b'E');
+        let len = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+        client.read_exact(&mut vec![0; len - 4]).await.unwrap();
+        assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_in_transaction_timeout() {
+        let timeouts = SessionTimeouts {
+            idle_in_transaction: Some(Duration::from_secs(60)),
+            ..SessionTimeouts::default()
+        };
+        let (mut client, mut compute, task) = session(timeouts);
+
+        // idle sessions outside of a transaction are left open.
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert!(!task.is_finished());
+
+        query(&mut client, &mut compute, b"begin\0", b'T').await;
+        let reason = task.await.unwrap().unwrap();
+        assert_eq!(reason, SessionEndReason::IdleInTransactionTimeout);
+        read_termination(&mut client).await;
+        assert_eq!(compute.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_lifetime_waits_for_transaction() {
+        let timeouts = SessionTimeouts {
+            max_lifetime: Some(Duration::from_secs(60)),
+            ..SessionTimeouts::default()
+        };
+        let (mut client, mut compute, task) = session(timeouts);
+
+        query(&mut client, &mut compute, b"begin\0", b'T').await;
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert!(!task.is_finished());
+
+        query(&mut client, &mut compute, b"commit\0", b'I').await;
+        let reason = task.await.unwrap().unwrap();
+        assert_eq!(reason, SessionEndReason::MaxLifetime);
+        read_termination(&mut client).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pipelined_queries_are_not_idle() {
+        let timeouts = SessionTimeouts {
+            idle_session: Some(Duration::from_secs(60)),
+            ..SessionTimeouts::default()
+        };
+        let (mut client, mut compute, task) = session(timeouts);
+
+        // the client sends a second query before the first one completes.
+        let queries = [message(b'Q', b"select 1\0"), message(b'Q', b"select 2\0")].concat();
+        client.write_all(&queries).await.unwrap();
+        compute
+            .read_exact(&mut vec![0; queries.len()])
+            .await
+            .unwrap();
+        compute.write_all(&message(b'Z', b"I")).await.unwrap();
+        client.read_exact(&mut [0; 6]).await.unwrap();
+
+        // the second query takes longer than the idle timeout.
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert!(!task.is_finished());
+
+        compute.write_all(&message(b'Z', b"I")).await.unwrap();
+        client.read_exact(&mut [0; 6]).await.unwrap();
+        let reason = task.await.unwrap().unwrap();
+        assert_eq!(reason, SessionEndReason::IdleSessionTimeout);
+        read_termination(&mut client).await;
+    }
+
     #[tokio::test]
     async fn test_client_to_compute() {
         let (mut client_client, mut client_proxy) = tokio::io::duplex(8); // Create a mock duplex stream
@@ -0,0 +1,366 @@
+//! Draining of the passthrough sessions when the proxy shuts down.
+//!
+//! Once the drain starts, the clients are sent a notice asking them to
+//! reconnect. The sessions which are still open at the deadline are closed
+//! as soon as the compute is idle between transactions, so that no
+//! transaction is cut off midway.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::Buf;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use tracing::info;
+
+use crate::pqproto::{BE_READY_FOR_QUERY, BeMessage, SQLSTATE_ADMIN_SHUTDOWN, WriteBuf};
+
+const DRAIN_NOTICE: &str =
+    "the proxy is shutting down, please reconnect once the current transaction is over";
+const DRAIN_TERMINATED: &str = "terminating connection due to proxy shutdown";
+
+/// Drains the sessions within a deadline once the proxy starts shutting down.
+pub struct Drain {
+    timeout: Option<Duration>,
+    started: CancellationToken,
+}
+
+impl Drain {
+    /// Without a timeout, the sessions are left to finish on their own.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            started: CancellationToken::new(),
+        }
+    }
+
+    pub fn start(&self) {
+        if let Some(timeout) = self.timeout
+            && !self.started.is_cancelled()
+        {
+            info!(?timeout, "draining client sessions");
+            self.started.cancel();
+        }
+    }
+}
+
+enum DrainState {
+    Disabled,
+    Waiting {
+        started: Pin<Box<WaitForCancellationFutureOwned>>,
+        timeout: Duration,
+    },
+    Draining {
+        deadline: Pin<Box<Sleep>>,
+    },
+    /// The deadline has passed, the session ends once the compute is idle.
+    Terminating,
+    /// The client was told that the session ends.
+    Terminated,
+}
+
+/// Client stream of a passthrough session, which closes the session when draining.
+pub(crate) struct DrainStream<S> {
+    inner: S,
+    state: DrainState,
+    /// Follows the backend messages written to the client.
+    messages: MessageScanner,
+    /// Whether the compute is idle outside of a transaction: its last message
+    /// was `ReadyForQuery` and the client has not sent anything since.
+    idle: bool,
+    /// Messages from the proxy that are yet to be written to the client.
+    injected: WriteBuf,
+    need_flush: bool,
+}
+
+impl<S> DrainStream<S> {
+    pub(crate) fn new(inner: S, drain: &Drain) -> Self {
+        let state = match drain.timeout {
+            None => DrainState::Disabled,
+            Some(timeout) => DrainState::Waiting {
+                started: Box::pin(drain.started.clone().cancelled_owned()),
+                timeout,
+            },
+        };
+        Self {
+            inner,
+            state,
+            messages: MessageScanner::default(),
+            // the session starts once the compute is ready for the first query.
+            idle: true,
+            injected: WriteBuf::new(),
+            need_flush: false,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DrainStream<S> {
+    /// Advance the drain of the session. Returns whether the session is over.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<bool>> {
+        if let DrainState::Waiting { started, timeout } = &mut self.state
+            && started.as_mut().poll(cx).is_ready()
+        {
+            BeMessage::NoticeResponse(DRAIN_NOTICE).write_message(&mut self.injected);
+            self.state = DrainState::Draining {
+                deadline: Box::pin(tokio::time::sleep(*timeout)),
+            };
+        }
+
+        if let DrainState::Draining { deadline } = &mut self.state
+            && deadline.as_mut().poll(cx).is_ready()
+        {
+            self.state = DrainState::Terminating;
+        }
+
+        if matches!(self.state, DrainState::Terminating) && self.idle && self.messages.at_boundary()
+        {
+            info!("closing the idle session of a draining proxy");
+            self.injected
+                .write_error(DRAIN_TERMINATED, SQLSTATE_ADMIN_SHUTDOWN);
+            self.state = DrainState::Terminated;
+        }
+
+        ready!(self.poll_injected(cx))?;
+        Poll::Ready(Ok(matches!(self.state, DrainState::Terminated)))
+    }
+
+    /// Write out our own messages, in between the messages from the compute.
+    fn poll_injected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.messages.at_boundary() {
+            return Poll::Ready(Ok(()));
+        }
+
+        while self.injected.has_remaining() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, self.injected.chunk()))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.injected.advance(n);
+            self.need_flush = true;
+        }
+
+        if self.need_flush {
+            ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+            self.need_flush = false;
+            self.injected.reset();
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for DrainStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // end the session as if the client disconnected.
+        if ready!(this.poll_drain(cx))? {
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if buf.filled().len() > filled {
+            this.idle = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DrainStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_injected(cx))?;
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        if let Some(status) = this.messages.scan(&buf[..n]) {
+            this.idle = status == b'I';
+        }
+
+        // the drain only makes progress when the client is read from,
+        // which might be waiting for the client indefinitely.
+        let terminate = this.idle && matches!(this.state, DrainState::Terminating);
+        if this.messages.at_boundary() && (terminate || this.injected.has_remaining()) {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_injected(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Follows the messages in the byte stream, to find the transaction boundaries.
+///
+/// <https://www.postgresql.org/docs/current/protocol-message-formats.html>
+#[derive(Default)]
+pub(super) struct MessageScanner {
+    /// Tag and length of the current message, if not read entirely yet.
+    header: [u8; 5],
+    header_len: usize,
+    /// Remaining bytes of the body of the current message.
+    body_len: usize,
+}
+
+impl MessageScanner {
+    pub(super) fn at_boundary(&self) -> bool {
+        self.header_len == 0 && self.body_len == 0
+    }
+
+    /// Consume the next bytes of the stream, returning the transaction
+    /// status of the last `ReadyForQuery` message in them.
+    fn scan(&mut self, bytes: &[u8]) -> Option<u8> {
+        let mut status = None;
+        self.advance(
+            bytes,
+            |_| {},
+            |tag, body| {
+                if tag == BE_READY_FOR_QUERY {
+                    status = Some(body[0]);
+                }
+            },
+        );
+        status
+    }
+
+    /// Consume the next bytes of the stream, calling `on_message` with the tag of
+    /// each message once its header is complete, and `on_body` with the parts of
+    /// the bodies of the messages.
+    pub(super) fn advance(
+        &mut self,
+        mut bytes: &[u8],
+        mut on_message: impl FnMut(u8),
+        mut on_body: impl FnMut(u8, &[u8]),
+    ) {
+        while !bytes.is_empty() {
+            if self.body_len > 0 {
+                let n = self.body_len.min(bytes.len());
+                on_body(self.header[0], &bytes[..n]);
+                self.body_len -= n;
+                bytes = &bytes[n..];
+                continue;
+            }
+
+            let n = (self.header.len() - self.header_len).min(bytes.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&bytes[..n]);
+            self.header_len += n;
+            bytes = &bytes[n..];
+
+            if self.header_len == self.header.len() {
+                let [tag, len @ ..] = self.header;
+                // the length includes itself
+                self.body_len = (u32::from_be_bytes(len) as usize).saturating_sub(4);
+                self.header_len = 0;
+                on_message(tag);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::*;
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn find_ready_for_query() {
+        let mut stream = message(b'C', b"SELECT 1\0");
+        stream.extend(message(b'Z', b"T"));
+        stream.extend(message(b'C', b"COMMIT\0"));
+        // the status is part of the body, not the start of a message
+        stream.extend(message(b'D', b"\0\x01Z\0\0\0\x05T"));
+        stream.extend(message(b'Z', b"I"));
+
+        for chunk in [1, 2, 3, 5, 7, stream.len()] {
+            let mut scanner = MessageScanner::default();
+            let statuses: Vec<_> = stream
+                .chunks(chunk)
+                .filter_map(|c| scanner.scan(c))
+                .collect();
+            assert_eq!(statuses.last(), Some(&b'I'), "chunks of {chunk} bytes");
+            assert!(scanner.at_boundary());
+        }
+
+        let mut scanner = MessageScanner::default();
+        assert_eq!(scanner.scan(&stream[..stream.len() - 1]), Some(b'T'));
+        assert!(!scanner.at_boundary());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_at_transaction_boundary() {
+        let drain = Drain::new(Some(Duration::from_secs(60)));
+        let (client, proxy) = duplex(1024);
+        let (mut client_read, mut client_write) = tokio::io::split(client);
+        let mut proxy = DrainStream::new(proxy, &drain);
+
+        // the client starts a transaction.
+        client_write
+            .write_all(&message(b'Q', b"begin\0"))
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let n = proxy.read(&mut buf).await.unwrap();
+        assert_eq!(n, 11);
+        proxy.write_all(&message(b'Z', b"T")).await.unwrap();
+        client_read.read_exact(&mut [0; 6]).await.unwrap();
+
+        // the notice is sent once the drain starts.
+        drain.start();
+        let read = tokio::time::timeout(Duration::from_secs(1), proxy.read(&mut buf)).await;
+        assert!(read.is_err());
+        let mut notice = [0; 5];
+        client_read.read_exact(&mut notice).await.unwrap();
+        assert_eq!(notice[0], b'N');
+        let len = u32::from_be_bytes(notice[1..].try_into().unwrap()) as usize;
+        client_read.read_exact(&mut vec![0; len - 4]).await.unwrap();
+
+        // the session is not closed inside of the transaction.
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let read = tokio::time::timeout(Duration::from_secs(1), proxy.read(&mut buf)).await;
+        assert!(read.is_err());
+
+        // once the transaction is over, the client is told and the session ends.
+        client_write
+            .write_all(&message(b'Q', b"commit\0"))
+            .await
+            .unwrap();
+        let n = proxy.read(&mut buf).await.unwrap();
+        assert_eq!(n, 12);
+        proxy.write_all(&message(b'Z', b"I")).await.unwrap();
+        assert_eq!(proxy.read(&mut buf).await.unwrap(), 0);
+
+        let mut ready = [0; 6];
+        client_read.read_exact(&mut ready).await.unwrap();
+        assert_eq!(ready[0], b'Z');
+        let mut error = [0; 1];
+        client_read.read_exact(&mut error).await.unwrap();
+        assert_eq!(error[0], b'E');
+    }
+}
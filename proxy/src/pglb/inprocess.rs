@@ -1,5 +1,3 @@
-#![allow(dead_code, reason = "TODO: work in progress")]
-
 use std::pin::{Pin, pin};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
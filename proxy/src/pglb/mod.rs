@@ -1,7 +1,12 @@
+pub mod audit;
+pub mod capture;
 pub mod copy_bidirectional;
+pub mod drain;
 pub mod handshake;
 pub mod inprocess;
 pub mod passthrough;
+pub mod throttle;
+pub mod transaction_pool;
 
 use std::sync::Arc;
 
@@ -296,7 +301,7 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
     let common_names = tls.map(|tls| &tls.common_names);
 
-    let (node, cancel_on_shutdown) = handle_client(
+    let (compute, aux, session) = handle_client(
         config,
         auth_backend,
         ctx,
@@ -319,15 +324,17 @@ pub(crate) async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin + Send>(
 
     Ok(Some(ProxyPassthrough {
         client,
-        compute: node.stream.into_framed().into_inner(),
+        compute,
 
-        aux: node.aux,
+        aux,
         private_link_id,
 
-        _cancel_on_shutdown: cancel_on_shutdown,
+        session: Some(session),
+        audit: ctx.query_audit(),
+        capture: ctx.session_capture(),
+        drain: &config.drain,
 
         _req: request_gauge,
         _conn: conn_gauge,
-        _db_conn: node.guage,
     }))
 }
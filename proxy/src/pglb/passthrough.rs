@@ -5,24 +5,34 @@
 use tracing::debug;
 use utils::measured_stream::MeasuredStream;
 
-use super::copy_bidirectional::ErrorSource;
+use super::audit::QueryAuditStream;
+use super::capture::CaptureStream;
+use super::copy_bidirectional::{ErrorSource, copy_bidirectional_with_timeouts};
+use super::drain::{Drain, DrainStream};
+use super::throttle::QueryThrottle;
+use super::transaction_pool::PooledClient;
 use crate::compute::MaybeRustlsStream;
-use crate::control_plane::messages::MetricsAuxInfo;
+use crate::context::audit::QueryAudit;
+use crate::context::capture::SessionCapture;
+use crate::control_plane::messages::{MetricsAuxInfo, SessionTimeouts};
 use crate::metrics::{
     Direction, Metrics, NumClientConnectionsGuard, NumConnectionRequestsGuard,
-    NumDbConnectionsGuard,
+    NumDbConnectionsGuard, SessionEndReason,
 };
+use crate::rate_limiter::SessionGuard;
 use crate::stream::Stream;
 use crate::usage_metrics::{Ids, MetricCounterRecorder, USAGE_METRICS};
 
-/// Forward bytes in both directions (client <-> compute).
+/// Forward bytes in both directions (client <-> compute), until the session
+/// ends or exceeds the `timeouts`.
 #[tracing::instrument(skip_all)]
 pub(crate) async fn proxy_pass(
     client: impl AsyncRead + AsyncWrite + Unpin,
     compute: impl AsyncRead + AsyncWrite + Unpin,
     aux: MetricsAuxInfo,
     private_link_id: Option<SmolStr>,
-) -> Result<(), ErrorSource> {
+    timeouts: SessionTimeouts,
+) -> Result<SessionEndReason, ErrorSource> {
     // we will report ingress at a later date
     let usage_tx = USAGE_METRICS.register(Ids {
         endpoint_id: aux.endpoint_id,
@@ -55,31 +65,98 @@ pub(crate) async fn proxy_pass(
 
     // Starting from here we only proxy the client's traffic.
     debug!("performing the proxy pass...");
-    let _ = crate::pglb::copy_bidirectional::copy_bidirectional_client_compute(
-        &mut client,
-        &mut compute,
-    )
-    .await?;
-
-    Ok(())
+    copy_bidirectional_with_timeouts(&mut client, &mut compute, timeouts).await
 }
 
 pub(crate) struct ProxyPassthrough<S> {
     pub(crate) client: Stream<S>,
-    pub(crate) compute: MaybeRustlsStream,
+    pub(crate) compute: Compute,
 
     pub(crate) aux: MetricsAuxInfo,
     pub(crate) private_link_id: Option<SmolStr>,
 
-    pub(crate) _cancel_on_shutdown: tokio::sync::oneshot::Sender<Infallible>,
+    /// Counts against the session limits of the endpoint until the session ends.
+    pub(crate) session: Option<SessionGuard>,
+    /// Records the queries of the session, if the endpoint has the audit log enabled.
+    pub(crate) audit: QueryAudit,
+    /// Records the messages of the session, if the endpoint is captured.
+    pub(crate) capture: SessionCapture,
+    /// Closes the session when the proxy shuts down.
+    pub(crate) drain: &'static Drain,
 
     pub(crate) _req: NumConnectionRequestsGuard<'static>,
     pub(crate) _conn: NumClientConnectionsGuard<'static>,
-    pub(crate) _db_conn: NumDbConnectionsGuard<'static>,
+}
+
+pub(crate) enum Compute {
+    /// The client has a compute connection of its own for the whole session.
+    Dedicated {
+        stream: MaybeRustlsStream,
+        cancel_on_shutdown: tokio::sync::oneshot::Sender<Infallible>,
+        db_conn: NumDbConnectionsGuard<'static>,
+    },
+    /// The client borrows a compute connection from the pool for each transaction.
+    Pooled(PooledClient),
 }
 
 impl<S: AsyncRead + AsyncWrite + Unpin> ProxyPassthrough<S> {
     pub(crate) async fn proxy_pass(self) -> Result<(), ErrorSource> {
-        proxy_pass(self.client, self.compute, self.aux, self.private_link_id).await
+        let Self {
+            client,
+            compute,
+            aux,
+            private_link_id,
+            session,
+            audit,
+            capture,
+            drain,
+            ..
+        } = self;
+
+        // the capture sees the messages the proxy injects in the session too.
+        let client = CaptureStream::new(client, capture);
+        let client = DrainStream::new(client, drain);
+        let client = QueryAuditStream::new(client, audit);
+        let timeouts = session
+            .as_ref()
+            .map(|session| *session.timeouts())
+            .unwrap_or_default();
+
+        let res = match compute {
+            Compute::Dedicated {
+                stream,
+                cancel_on_shutdown: _cancel_on_shutdown,
+                db_conn: _db_conn,
+            } => match session {
+                Some(session) if session.has_query_limit() => {
+                    let client = QueryThrottle::new(client, session);
+                    proxy_pass(client, stream, aux, private_link_id, timeouts).await
+                }
+                _session => proxy_pass(client, stream, aux, private_link_id, timeouts).await,
+            },
+            // pooled clients only hold on to a compute connection during a transaction.
+            Compute::Pooled(pooled) => match session {
+                Some(session) if session.has_query_limit() => {
+                    let client = QueryThrottle::new(client, session);
+                    pooled
+                        .proxy_pass(client, aux, private_link_id, timeouts)
+                        .await
+                }
+                _session => {
+                    pooled
+                        .proxy_pass(client, aux, private_link_id, timeouts)
+                        .await
+                }
+            },
+        };
+
+        let reason = match &res {
+            Ok(reason) => *reason,
+            Err(ErrorSource::Client(_)) => SessionEndReason::ClientError,
+            Err(ErrorSource::Compute(_)) => SessionEndReason::ComputeError,
+        };
+        Metrics::get().proxy.client_sessions_ended_total.inc(reason);
+
+        res.map(|_| ())
     }
 }
@@ -0,0 +1,161 @@
+//! Query rate limiting of passthrough sessions.
+//!
+//! The passthrough only forwards bytes, so queries cannot be rejected without
+//! breaking the protocol state between the client and the compute. Instead,
+//! once the role runs more queries than allowed, we stop reading from the
+//! client until the rate limit lets the queries through.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+use crate::rate_limiter::SessionGuard;
+
+/// Client stream which holds back the queries over the rate limit of the session.
+pub(crate) struct QueryThrottle<S> {
+    inner: S,
+    session: SessionGuard,
+    messages: MessageScanner,
+    /// Queries read from the client that are not accounted for yet.
+    pending: u32,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> QueryThrottle<S> {
+    pub(crate) fn new(inner: S, session: SessionGuard) -> Self {
+        Self {
+            inner,
+            session,
+            messages: MessageScanner::default(),
+            pending: 0,
+            sleep: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for QueryThrottle<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // the queries we already forwarded have to be accounted for before
+        // we read any more from the client.
+        while this.pending > 0 {
+            if let Some(sleep) = &mut this.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+            match this.session.check_queries(1) {
+                Ok(()) => this.pending -= 1,
+                Err(ready_at) => this.sleep = Some(Box::pin(tokio::time::sleep_until(ready_at))),
+            }
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.pending = this.messages.scan(&buf.filled()[filled..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for QueryThrottle<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Follows the frontend messages in the byte stream, to count the queries.
+///
+/// <https://www.postgresql.org/docs/current/protocol-message-formats.html>
+#[derive(Default)]
+struct MessageScanner {
+    /// Tag and length of the current message, if not read entirely yet.
+    header: [u8; 5],
+    header_len: usize,
+    /// Remaining bytes of the body of the current message.
+    body_len: usize,
+}
+
+impl MessageScanner {
+    /// Consume the next bytes of the stream, returning how many queries
+    /// (simple queries or executions of portals) started in them.
+    fn scan(&mut self, mut bytes: &[u8]) -> u32 {
+        let mut queries = 0;
+        while !bytes.is_empty() {
+            if self.body_len > 0 {
+                let n = self.body_len.min(bytes.len());
+                self.body_len -= n;
+                bytes = &bytes[n..];
+                continue;
+            }
+
+            let n = (self.header.len() - self.header_len).min(bytes.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&bytes[..n]);
+            self.header_len += n;
+            bytes = &bytes[n..];
+
+            if self.header_len == self.header.len() {
+                let [tag, len @ ..] = self.header;
+                // the length includes itself
+                self.body_len = (u32::from_be_bytes(len) as usize).saturating_sub(4);
+                self.header_len = 0;
+                if matches!(tag, b'Q' | b'E') {
+                    queries += 1;
+                }
+            }
+        }
+        queries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![tag];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn count_queries() {
+        let mut stream = message(b'Q', b"select 1\0");
+        stream.extend(message(b'P', b"\0select $1\0\0\0"));
+        stream.extend(message(b'B', b"\0\0\0\0\0\0\0\0"));
+        stream.extend(message(b'E', b"\0\0\0\0\0"));
+        stream.extend(message(b'S', b""));
+        // the tag of the query is part of the body, not the start of a message
+        stream.extend(message(b'd', b"QE"));
+        stream.extend(message(b'Q', b"select 2\0"));
+
+        let mut scanner = MessageScanner::default();
+        assert_eq!(scanner.scan(&stream), 3);
+
+        // the messages can be split anywhere between reads
+        for chunk in [1, 2, 3, 5, 7] {
+            let mut scanner = MessageScanner::default();
+            let queries: u32 = stream.chunks(chunk).map(|c| scanner.scan(c)).sum();
+            assert_eq!(queries, 3, "chunks of {chunk} bytes");
+        }
+    }
+}
@@ -0,0 +1,1069 @@
+//! Transaction-mode pooling of compute connections, for the Postgres clients
+//! which opt in with `options=neon_proxy_pool_mode:transaction`.
+//!
+//! A pooled client only holds on to a compute connection for the duration of a
+//! transaction. Once the compute reports that it's idle with `ReadyForQuery`, the
+//! connection goes back to the pool, to serve the next transaction of any client
+//! of the endpoint with the same role, database and startup parameters.
+//!
+//! Like with pgbouncer, session state (`SET`, `LISTEN`, advisory locks, temporary
+//! tables...) does not carry over from one transaction to the next.
+//!
+//! The cancel key of a pooled client is registered for the compute connection of
+//! its current transaction, and removed once the transaction is over. A cancel
+//! request which races with the end of the transaction can still reach the
+//! connection after it went back to the pool, as with pgbouncer.
+//!
+//! Named prepared statements are renamed after a hash of their query, so that the
+//! clients of a compute connection can share them, and are prepared again whenever
+//! a client uses them on a connection which does not know about them yet.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ahash::RandomState;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use clashmap::ClashMap;
+use futures::TryStreamExt;
+use postgres_client::RawCancelToken;
+use postgres_protocol::message::backend::Message;
+use sha2::{Digest, Sha256};
+use smol_str::{SmolStr, format_smolstr};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::debug;
+use utils::measured_stream::MeasuredStream;
+
+use super::copy_bidirectional::{ErrorSource, TimedClient};
+use crate::auth::Backend;
+use crate::auth::backend::ComputeUserInfo;
+use crate::cancellation::CancelClosure;
+use crate::compute::{AuthInfo, ComputeConnection, MaybeRustlsStream, PostgresError};
+use crate::config::ProxyConfig;
+use crate::context::RequestContext;
+use crate::control_plane::messages::{MetricsAuxInfo, SessionTimeouts};
+use crate::error::UserFacingError;
+use crate::metrics::{Direction, Metrics, NumDbConnectionsGuard, SessionEndReason};
+use crate::pqproto::{
+    BE_BIND_COMPLETE, BE_CLOSE_COMPLETE, BE_COMMAND_COMPLETE, BE_EMPTY_QUERY_RESPONSE,
+    BE_ERROR_RESPONSE, BE_NO_DATA, BE_NOTICE_RESPONSE, BE_NOTIFICATION_RESPONSE,
+    BE_PARAMETER_STATUS, BE_PARSE_COMPLETE, BE_PORTAL_SUSPENDED, BE_READY_FOR_QUERY,
+    BE_ROW_DESCRIPTION, BeMessage, CancelKeyData, FE_BIND, FE_CLOSE, FE_DESCRIBE, FE_EXECUTE,
+    FE_FUNCTION_CALL, FE_PARSE, FE_QUERY, FE_SYNC, FE_TERMINATE, RawMessage, WriteBuf, read_cstr,
+    split_message,
+};
+use crate::proxy::connect_auth::{AuthError, connect_to_compute_and_auth};
+use crate::proxy::connect_compute::TlsNegotiation;
+use crate::stream::PqStream;
+use crate::usage_metrics::{Ids, MetricCounterRecorder, USAGE_METRICS};
+
+/// Postgres does not allow for messages larger than this.
+const MAX_MESSAGE_LEN: u32 = 1 << 30;
+
+/// We stop reading from one side once this many bytes are waiting to be sent to the other.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+/// Idle compute connections, waiting for the next transaction of a pooled client.
+pub struct TransactionPool {
+    idle: ClashMap<PoolKey, Vec<IdleConnection>, RandomState>,
+    max_idle: usize,
+    idle_timeout: Duration,
+}
+
+/// Compute connections are only shared by the clients that would have set them up the same way.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PoolKey {
+    endpoint: crate::types::EndpointCacheKey,
+    /// The startup params sent to the compute, including the role and the database.
+    params: SmolStr,
+}
+
+struct IdleConnection {
+    conn: PooledConnection,
+    since: Instant,
+}
+
+impl TransactionPool {
+    pub fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self {
+            idle: ClashMap::with_hasher_and_shard_amount(RandomState::new(), 64),
+            max_idle,
+            idle_timeout,
+        }
+    }
+
+    fn checkout(&self, key: &PoolKey) -> Option<PooledConnection> {
+        let mut idle = self.idle.get_mut(key)?;
+        // the most recently used connections are the least likely to have expired.
+        while let Some(IdleConnection { mut conn, since }) = idle.pop() {
+            if since.elapsed() >= self.idle_timeout {
+                idle.clear();
+                break;
+            }
+            if !conn.is_closed() {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, key: &PoolKey, conn: PooledConnection) {
+        let mut idle = self.idle.entry(key.clone()).or_default();
+        if idle.len() < self.max_idle {
+            idle.push(IdleConnection {
+                conn,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Close the connections that have been idle for too long, so that computes can suspend.
+    pub async fn gc_worker(&self) {
+        let mut interval =
+            tokio::time::interval((self.idle_timeout / 2).max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            self.idle.retain(|_, idle| {
+                idle.retain(|c| now.duration_since(c.since) < self.idle_timeout);
+                !idle.is_empty()
+            });
+        }
+    }
+}
+
+/// A compute connection which can serve the transactions of any client of its pool.
+pub(crate) struct PooledConnection {
+    stream: MessageStream<MaybeRustlsStream>,
+    /// Parameters reported by the compute, to greet new clients with.
+    parameters: Vec<(String, String)>,
+    /// Names of the statements prepared on the connection.
+    prepared: HashSet<SmolStr>,
+    /// Where to send the cancel requests for the queries of the connection.
+    cancel: Option<CancelClosure>,
+    aux: MetricsAuxInfo,
+    _db_conn: NumDbConnectionsGuard<'static>,
+}
+
+impl PooledConnection {
+    /// Wait for the compute to be ready for queries, like
+    /// [`forward_compute_params_to_client`](crate::proxy::forward_compute_params_to_client)
+    /// does for dedicated connections.
+    async fn start(
+        mut node: ComputeConnection,
+        user_info: &ComputeUserInfo,
+    ) -> Result<Self, PostgresError> {
+        let mut parameters = vec![];
+        let mut cancel = None;
+
+        let err = loop {
+            let msg = match node.stream.try_next().await {
+                Ok(msg) => msg,
+                Err(e) => break postgres_client::Error::io(e),
+            };
+
+            match msg {
+                Some(Message::ParameterStatus(body)) => {
+                    if let Ok(name) = body.name()
+                        && let Ok(value) = body.value()
+                    {
+                        parameters.push((name.to_owned(), value.to_owned()));
+                    }
+                }
+                Some(Message::BackendKeyData(body)) => {
+                    cancel = Some(CancelClosure {
+                        socket_addr: node.socket_addr,
+                        cancel_token: RawCancelToken {
+                            ssl_mode: node.ssl_mode,
+                            process_id: body.process_id(),
+                            secret_key: body.secret_key(),
+                        },
+                        hostname: node.hostname.to_string(),
+                        user_info: user_info.clone(),
+                    });
+                }
+                Some(Message::NoticeResponse(_)) => {}
+                Some(Message::ReadyForQuery(_)) => {
+                    return Ok(Self {
+                        stream: MessageStream::new(node.stream.into_framed().into_inner()),
+                        parameters,
+                        prepared: HashSet::new(),
+                        cancel,
+                        aux: node.aux,
+                        _db_conn: node.guage,
+                    });
+                }
+                Some(Message::ErrorResponse(body)) => break postgres_client::Error::db(body),
+                Some(_) => break postgres_client::Error::unexpected_message(),
+                None => break postgres_client::Error::closed(),
+            }
+        };
+
+        Err(PostgresError::Postgres(err))
+    }
+
+    pub(crate) fn aux(&self) -> &MetricsAuxInfo {
+        &self.aux
+    }
+
+    /// Finish the startup of a new client, as the compute would have.
+    pub(crate) fn greet(
+        &self,
+        client: &mut PqStream<impl AsyncRead + AsyncWrite + Unpin>,
+        cancel_key_data: CancelKeyData,
+    ) {
+        for (name, value) in &self.parameters {
+            client.write_message(BeMessage::ParameterStatus {
+                name: name.as_bytes(),
+                value: value.as_bytes(),
+            });
+        }
+        client.write_message(BeMessage::BackendKeyData(cancel_key_data));
+        client.write_message(BeMessage::ReadyForQuery);
+    }
+
+    /// Whether the compute closed the connection while it was idle.
+    fn is_closed(&mut self) -> bool {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        matches!(self.stream.poll_io(&mut cx, true), Poll::Ready(Err(_))) || self.stream.eof
+    }
+
+    fn set_parameter(&mut self, body: &[u8]) {
+        let Ok((name, rest)) = read_cstr(body) else {
+            return;
+        };
+        let Ok((value, _)) = read_cstr(rest) else {
+            return;
+        };
+        let (name, value) = (
+            String::from_utf8_lossy(name).into_owned(),
+            String::from_utf8_lossy(value).into_owned(),
+        );
+        match self.parameters.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.parameters.push((name, value)),
+        }
+    }
+}
+
+/// A client in transaction pooling mode.
+pub(crate) struct PooledClient {
+    pool: &'static TransactionPool,
+    key: PoolKey,
+
+    ctx: RequestContext,
+    config: &'static ProxyConfig,
+    backend: Backend<'static, ComputeUserInfo>,
+    auth_info: AuthInfo,
+
+    /// The compute connection of the current transaction, for the cancel key of the client.
+    cancel: watch::Sender<Option<CancelClosure>>,
+
+    /// Statements prepared by the client, by the name it gave them.
+    statements: HashMap<Bytes, Statement>,
+}
+
+struct Statement {
+    /// The name of the statement on the compute connections.
+    name: SmolStr,
+    /// The `Parse` message which prepares the statement under that name.
+    parse: RawMessage,
+}
+
+impl Statement {
+    fn new(parse: &[u8]) -> Self {
+        // statements are shared between clients, so the name needs to identify the
+        // query and the types of its parameters unambiguously.
+        let hash = Sha256::digest(parse);
+        let name = format_smolstr!("neon_{}", hex::encode(&hash[..16]));
+
+        let mut body = Vec::with_capacity(name.len() + 1 + parse.len());
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        body.extend_from_slice(parse);
+
+        Self {
+            name,
+            parse: RawMessage::new(FE_PARSE, &body),
+        }
+    }
+}
+
+impl PooledClient {
+    pub(crate) fn new(
+        pool: &'static TransactionPool,
+        ctx: &RequestContext,
+        config: &'static ProxyConfig,
+        backend: Backend<'static, ComputeUserInfo>,
+        auth_info: AuthInfo,
+        cancel: watch::Sender<Option<CancelClosure>>,
+    ) -> Self {
+        let Backend::ControlPlane(_, user_info) = &backend else {
+            unreachable!("local proxy does not run tcp proxy service");
+        };
+        let key = PoolKey {
+            endpoint: user_info.endpoint_cache_key(),
+            params: auth_info.server_params().params.as_str().into(),
+        };
+
+        Self {
+            pool,
+            key,
+            ctx: ctx.clone(),
+            config,
+            backend,
+            auth_info,
+            cancel,
+            statements: HashMap::new(),
+        }
+    }
+
+    /// Take a connection from the pool, or connect to the compute if there are none left.
+    pub(crate) async fn connect(&self) -> Result<PooledConnection, AuthError> {
+        if let Some(conn) = self.pool.checkout(&self.key) {
+            debug!("reusing pooled compute connection");
+            return Ok(conn);
+        }
+
+        let node = connect_to_compute_and_auth(
+            &self.ctx,
+            self.config,
+            &self.backend,
+            self.auth_info.clone(),
+            TlsNegotiation::Postgres,
+        )
+        .await?;
+
+        let Backend::ControlPlane(_, user_info) = &self.backend else {
+            unreachable!("local proxy does not run tcp proxy service");
+        };
+        Ok(PooledConnection::start(node, user_info).await?)
+    }
+
+    pub(crate) fn release(&self, conn: PooledConnection) {
+        self.pool.checkin(&self.key, conn);
+    }
+
+    /// Forward the transactions of the client to the computes connections of the pool,
+    /// until the client disconnects or exceeds the `timeouts`. Returns why the session ended.
+    pub(crate) async fn proxy_pass(
+        mut self,
+        client: impl AsyncRead + AsyncWrite + Unpin,
+        aux: MetricsAuxInfo,
+        private_link_id: Option<SmolStr>,
+        timeouts: SessionTimeouts,
+    ) -> Result<SessionEndReason, ErrorSource> {
+        let usage_tx = USAGE_METRICS.register(Ids {
+            endpoint_id: aux.endpoint_id,
+            branch_id: aux.branch_id,
+            private_link_id,
+        });
+
+        let metrics = &Metrics::get().proxy.io_bytes;
+        let m_sent = metrics.with_labels(Direction::Tx);
+        let m_recv = metrics.with_labels(Direction::Rx);
+        let mut client = MeasuredStream::new(
+            client,
+            |cnt| {
+                // Number of bytes the client sent to the compute nodes (inbound).
+                metrics.get_metric(m_recv).inc_by(cnt as u64);
+                usage_tx.record_ingress(cnt as u64);
+            },
+            |cnt| {
+                // Number of bytes we sent to the client (outbound).
+                metrics.get_metric(m_sent).inc_by(cnt as u64);
+                usage_tx.record_egress(cnt as u64);
+            },
+        );
+
+        debug!("performing the pooled proxy pass...");
+        let mut client = TimedClient::new(&mut client, timeouts);
+        let mut stream = MessageStream::new(&mut client);
+        // a connection that is dropped in the middle of a transaction is closed, not reused.
+        let mut txn = None;
+        self.run(&mut stream, &mut txn).await?;
+        Ok(client
+            .ended_by()
+            .unwrap_or(SessionEndReason::ClientDisconnect))
+    }
+
+    async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        client: &mut MessageStream<S>,
+        txn: &mut Option<Transaction>,
+    ) -> Result<(), ErrorSource> {
+        loop {
+            // forward the messages of the client, as long as the compute keeps up.
+            while txn.as_ref().is_none_or(|t| !t.conn.stream.is_congested()) {
+                let Some(msg) = client.next_message().map_err(ErrorSource::Client)? else {
+                    break;
+                };
+                if msg.tag() == FE_TERMINATE {
+                    return Ok(());
+                }
+                let t = match txn {
+                    Some(t) => t,
+                    None => txn.insert(self.begin(client).await?),
+                };
+                self.forward_frontend(msg, t, client)
+                    .map_err(ErrorSource::Client)?;
+            }
+
+            if client.eof {
+                return Ok(());
+            }
+
+            let mut finished = false;
+            if let Some(t) = txn {
+                while !client.is_congested() {
+                    let Some(msg) = t.conn.stream.next_message().map_err(ErrorSource::Compute)?
+                    else {
+                        break;
+                    };
+                    if t.forward_backend(msg, client) {
+                        finished = true;
+                        break;
+                    }
+                }
+            }
+            if finished && let Some(t) = txn.take() {
+                self.cancel.send_replace(None);
+                self.release(t.conn);
+                continue;
+            }
+
+            futures::future::poll_fn(|cx| -> Poll<Result<(), ErrorSource>> {
+                let compute_congested = txn.as_ref().is_some_and(|t| t.conn.stream.is_congested());
+                let client_ready = client
+                    .poll_io(cx, !compute_congested)
+                    .map_err(ErrorSource::Client)?;
+
+                let compute_ready = match txn {
+                    Some(t) => t
+                        .conn
+                        .stream
+                        .poll_io(cx, !client.is_congested())
+                        .map_err(ErrorSource::Compute)?,
+                    None => Poll::Pending,
+                };
+
+                if client_ready.is_ready() || compute_ready.is_ready() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await?;
+
+            if let Some(t) = txn
+                && t.conn.stream.eof
+            {
+                return Err(ErrorSource::Compute(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+
+    /// Get a compute connection for the next transaction of the client.
+    async fn begin<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        client: &mut MessageStream<S>,
+    ) -> Result<Transaction, ErrorSource> {
+        match self.connect().await {
+            Ok(conn) => {
+                self.cancel.send_replace(conn.cancel.clone());
+                Ok(Transaction {
+                    conn,
+                    pending: VecDeque::new(),
+                    skipping: false,
+                })
+            }
+            Err(e) => {
+                // the client is waiting on a response, let it know what happened.
+                let mut buf = WriteBuf::new();
+                buf.write_error(&e.to_string_client(), e.sql_state());
+                client.write.put(buf);
+                client
+                    .stream
+                    .write_all(&client.write)
+                    .await
+                    .map_err(ErrorSource::Client)?;
+                client.stream.flush().await.map_err(ErrorSource::Client)?;
+                Err(ErrorSource::Compute(io::Error::other(e)))
+            }
+        }
+    }
+
+    /// Forward a message of the client to the compute, renaming the prepared statements.
+    fn forward_frontend<S>(
+        &mut self,
+        msg: RawMessage,
+        txn: &mut Transaction,
+        client: &mut MessageStream<S>,
+    ) -> io::Result<()> {
+        let request = match msg.tag() {
+            FE_PARSE => {
+                let (name, parse) = read_cstr(msg.body())?;
+                if name.is_empty() {
+                    Request::Parse
+                } else {
+                    let statement = Statement::new(parse);
+                    txn.prepare(&statement, true);
+                    self.statements
+                        .insert(Bytes::copy_from_slice(name), statement);
+                    txn.synthesize(client);
+                    return Ok(());
+                }
+            }
+            FE_BIND => {
+                let (portal, rest) = read_cstr(msg.body())?;
+                let (name, rest) = read_cstr(rest)?;
+                if let Some(statement) = self.statements.get(name) {
+                    txn.prepare(statement, false);
+                    let msg = rename(FE_BIND, portal, &statement.name, rest);
+                    txn.send(Request::Bind, &msg);
+                    return Ok(());
+                }
+                Request::Bind
+            }
+            FE_DESCRIBE => {
+                if let Some((&b'S', body)) = msg.body().split_first() {
+                    let (name, _) = read_cstr(body)?;
+                    if let Some(statement) = self.statements.get(name) {
+                        txn.prepare(statement, false);
+                        let msg = rename(FE_DESCRIBE, b"S", &statement.name, b"");
+                        txn.send(Request::Describe, &msg);
+                        return Ok(());
+                    }
+                }
+                Request::Describe
+            }
+            FE_CLOSE => {
+                if let Some((&b'S', body)) = msg.body().split_first() {
+                    let (name, _) = read_cstr(body)?;
+                    // other clients might still be using the statement, so it stays
+                    // prepared on the compute connections.
+                    if self.statements.remove(name).is_some() {
+                        txn.respond(Request::Close, BE_CLOSE_COMPLETE);
+                        txn.synthesize(client);
+                        return Ok(());
+                    }
+                }
+                Request::Close
+            }
+            FE_EXECUTE => Request::Execute,
+            FE_SYNC => {
+                txn.skipping = false;
+                Request::Sync
+            }
+            FE_QUERY | FE_FUNCTION_CALL => Request::Query,
+            // flush and copy data messages have no response of their own.
+            _ => {
+                txn.conn.stream.write_message(msg.as_bytes());
+                return Ok(());
+            }
+        };
+
+        txn.send(request, &msg);
+        Ok(())
+    }
+}
+
+/// Build a message whose first null-terminated string is followed by a statement name.
+fn rename(tag: u8, prefix: &[u8], name: &str, rest: &[u8]) -> RawMessage {
+    let mut body = Vec::with_capacity(prefix.len() + name.len() + rest.len() + 2);
+    body.extend_from_slice(prefix);
+    // the describe message prefixes the name with its type, rather than a portal name.
+    if tag != FE_DESCRIBE {
+        body.push(0);
+    }
+    body.extend_from_slice(name.as_bytes());
+    body.push(0);
+    body.extend_from_slice(rest);
+    RawMessage::new(tag, &body)
+}
+
+/// A compute connection lent to a client for the duration of a transaction.
+struct Transaction {
+    conn: PooledConnection,
+    /// Requests of the client the compute has not responded to yet.
+    pending: VecDeque<Pending>,
+    /// After an error, the compute ignores all messages until the next sync.
+    skipping: bool,
+}
+
+struct Pending {
+    request: Request,
+    response: Response,
+    /// The statement prepared on the connection by this request, if it succeeds.
+    prepared: Option<SmolStr>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Request {
+    Parse,
+    Bind,
+    Close,
+    Describe,
+    Execute,
+    Sync,
+    /// Simple queries and function calls.
+    Query,
+}
+
+impl Request {
+    /// Whether the message of the compute is the last of its response to the request.
+    fn is_complete(self, tag: u8) -> bool {
+        match self {
+            Request::Parse => tag == BE_PARSE_COMPLETE,
+            Request::Bind => tag == BE_BIND_COMPLETE,
+            Request::Close => tag == BE_CLOSE_COMPLETE,
+            Request::Describe => matches!(tag, BE_ROW_DESCRIPTION | BE_NO_DATA),
+            Request::Execute => matches!(
+                tag,
+                BE_COMMAND_COMPLETE | BE_EMPTY_QUERY_RESPONSE | BE_PORTAL_SUSPENDED
+            ),
+            Request::Sync | Request::Query => tag == BE_READY_FOR_QUERY,
+        }
+    }
+}
+
+enum Response {
+    Forward,
+    /// The request was made by the proxy, the client does not expect a response.
+    Discard,
+    /// The request was not sent to the compute, the proxy responds with this message instead.
+    Synthesize(u8),
+}
+
+impl Transaction {
+    fn send(&mut self, request: Request, msg: &RawMessage) {
+        self.conn.stream.write_message(msg.as_bytes());
+        if !self.skipping {
+            self.pending.push_back(Pending {
+                request,
+                response: Response::Forward,
+                prepared: None,
+            });
+        }
+    }
+
+    fn respond(&mut self, request: Request, tag: u8) {
+        if !self.skipping {
+            self.pending.push_back(Pending {
+                request,
+                response: Response::Synthesize(tag),
+                prepared: None,
+            });
+        }
+    }
+
+    /// Make sure the statement is prepared on the compute connection, responding to
+    /// the client if it asked for it to be.
+    fn prepare(&mut self, statement: &Statement, requested: bool) {
+        if self.skipping {
+            return;
+        }
+        if self.conn.prepared.contains(&statement.name) {
+            if requested {
+                self.respond(Request::Parse, BE_PARSE_COMPLETE);
+            }
+            return;
+        }
+
+        self.conn.stream.write_message(statement.parse.as_bytes());
+        self.conn.prepared.insert(statement.name.clone());
+        self.pending.push_back(Pending {
+            request: Request::Parse,
+            response: if requested {
+                Response::Forward
+            } else {
+                Response::Discard
+            },
+            prepared: Some(statement.name.clone()),
+        });
+    }
+
+    /// Respond in place of the compute, once the previous requests are responded to.
+    fn synthesize<S>(&mut self, client: &mut MessageStream<S>) {
+        while let Some(Pending {
+            response: Response::Synthesize(tag),
+            ..
+        }) = self.pending.front()
+        {
+            client.write_message(RawMessage::new(*tag, &[]).as_bytes());
+            self.pending.pop_front();
+        }
+    }
+
+    /// Forward a message of the compute to the client.
+    ///
+    /// Returns true once the transaction is over and the connection can be released.
+    fn forward_backend<S>(&mut self, msg: RawMessage, client: &mut MessageStream<S>) -> bool {
+        match msg.tag() {
+            // asynchronous messages can be sent at any point.
+            BE_NOTICE_RESPONSE | BE_NOTIFICATION_RESPONSE => {
+                client.write_message(msg.as_bytes());
+                return false;
+            }
+            BE_PARAMETER_STATUS => {
+                self.conn.set_parameter(msg.body());
+                client.write_message(msg.as_bytes());
+                return false;
+            }
+            BE_ERROR_RESPONSE => {
+                client.write_message(msg.as_bytes());
+                self.error();
+                self.synthesize(client);
+                return false;
+            }
+            _ => {}
+        }
+
+        let Some(pending) = self.pending.front() else {
+            client.write_message(msg.as_bytes());
+            return false;
+        };
+
+        if !matches!(pending.response, Response::Discard) {
+            client.write_message(msg.as_bytes());
+        }
+        if !pending.request.is_complete(msg.tag()) {
+            return false;
+        }
+
+        self.pending.pop_front();
+        self.synthesize(client);
+
+        msg.tag() == BE_READY_FOR_QUERY
+            && msg.body() == [b'I']
+            && self.pending.is_empty()
+            && !self.skipping
+            && self.conn.stream.write.is_empty()
+    }
+
+    /// The request at the front failed. Unless it was a sync, the compute
+    /// skips all the requests up until the next sync.
+    fn error(&mut self) {
+        if self
+            .pending
+            .front()
+            .is_some_and(|p| matches!(p.request, Request::Sync | Request::Query))
+        {
+            return;
+        }
+
+        while self
+            .pending
+            .front()
+            .is_some_and(|p| p.request != Request::Sync)
+        {
+            if let Some(Pending {
+                prepared: Some(name),
+                ..
+            }) = self.pending.pop_front()
+            {
+                self.conn.prepared.remove(&name);
+            }
+        }
+        self.skipping = self.pending.is_empty();
+    }
+}
+
+/// Buffers the messages read from and written to a stream, so that we can wait
+/// on both sides of the connection without ever cancelling a read or write midway.
+struct MessageStream<S> {
+    stream: S,
+    read: BytesMut,
+    write: BytesMut,
+    flush: bool,
+    eof: bool,
+}
+
+impl<S> MessageStream<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            read: BytesMut::new(),
+            write: BytesMut::new(),
+            flush: false,
+            eof: false,
+        }
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<RawMessage>> {
+        split_message(&mut self.read, MAX_MESSAGE_LEN)
+    }
+
+    fn write_message(&mut self, msg: &[u8]) {
+        self.write.extend_from_slice(msg);
+    }
+
+    fn is_congested(&self) -> bool {
+        self.write.len() >= MAX_BUFFERED
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MessageStream<S> {
+    /// Write out the buffered messages, and read in more if `read` is set.
+    ///
+    /// Ready once any progress was made.
+    fn poll_io(&mut self, cx: &mut Context<'_>, read: bool) -> Poll<io::Result<()>> {
+        let mut progress = false;
+
+        while !self.write.is_empty() {
+            match Pin::new(&mut self.stream).poll_write(cx, &self.write)? {
+                Poll::Ready(0) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(n) => {
+                    self.write.advance(n);
+                    self.flush = true;
+                    progress = true;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        if self.write.is_empty()
+            && self.flush
+            && Pin::new(&mut self.stream).poll_flush(cx)?.is_ready()
+        {
+            self.flush = false;
+        }
+
+        if read && !self.eof {
+            self.read.reserve(8 * 1024);
+            if let Poll::Ready(n) =
+                tokio_util::io::poll_read_buf(Pin::new(&mut self.stream), cx, &mut self.read)?
+            {
+                self.eof = n == 0;
+                progress = true;
+            }
+        }
+
+        if progress {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use postgres_client::maybe_tls_stream::MaybeTlsStream;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::control_plane::messages::ColdStartInfo;
+    use crate::types::{BranchId, EndpointId, ProjectId};
+
+    fn message(tag: u8, body: &[u8]) -> RawMessage {
+        RawMessage::new(tag, body)
+    }
+
+    async fn transaction() -> (Transaction, MessageStream<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let conn = PooledConnection {
+            stream: MessageStream::new(MaybeTlsStream::Raw(stream)),
+            parameters: vec![],
+            prepared: HashSet::new(),
+            cancel: None,
+            aux: MetricsAuxInfo {
+                endpoint_id: (&EndpointId::from("endpoint")).into(),
+                project_id: (&ProjectId::from("project")).into(),
+                branch_id: (&BranchId::from("branch")).into(),
+                compute_id: "compute".into(),
+                cold_start_info: ColdStartInfo::Warm,
+            },
+            _db_conn: Metrics::get()
+                .proxy
+                .db_connections
+                .guard(crate::metrics::Protocol::Tcp),
+        };
+        let txn = Transaction {
+            conn,
+            pending: VecDeque::new(),
+            skipping: false,
+        };
+        (txn, MessageStream::new(()))
+    }
+
+    impl<S> MessageStream<S> {
+        /// Take the messages written to the stream, as if the other end read them.
+        fn sent(&mut self) -> Vec<RawMessage> {
+            let mut read = std::mem::take(&mut self.write);
+            let mut messages = vec![];
+            while let Some(msg) = split_message(&mut read, MAX_MESSAGE_LEN).unwrap() {
+                messages.push(msg);
+            }
+            messages
+        }
+
+        fn sent_tags(&mut self) -> Vec<u8> {
+            self.sent().iter().map(RawMessage::tag).collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn statements_are_prepared_once_per_connection() {
+        let statement = Statement::new(b"select $1\0\0\0");
+        assert!(statement.name.starts_with("neon_"));
+        assert!(statement.name.len() < 64, "must fit in NAMEDATALEN");
+
+        let (mut txn, mut client) = transaction().await;
+
+        // the first client to prepare the statement gets the response of the compute.
+        txn.prepare(&statement, true);
+        let sent = txn.conn.stream.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].as_bytes(), statement.parse.as_bytes());
+        assert!(!txn.forward_backend(message(BE_PARSE_COMPLETE, b""), &mut client));
+        assert_eq!(client.sent_tags(), [BE_PARSE_COMPLETE]);
+
+        // the next one is responded to by the proxy.
+        txn.prepare(&statement, true);
+        txn.synthesize(&mut client);
+        assert!(txn.conn.stream.sent().is_empty());
+        assert_eq!(client.sent_tags(), [BE_PARSE_COMPLETE]);
+        assert!(txn.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn injected_parse_is_not_forwarded() {
+        let statement = Statement::new(b"select 1\0\0\0");
+        let (mut txn, mut client) = transaction().await;
+
+        // a bind of a statement the connection does not know about yet.
+        txn.prepare(&statement, false);
+        txn.send(Request::Bind, &message(FE_BIND, b""));
+        txn.send(Request::Execute, &message(FE_EXECUTE, b""));
+        txn.send(Request::Sync, &message(FE_SYNC, b""));
+        assert_eq!(
+            txn.conn.stream.sent_tags(),
+            [FE_PARSE, FE_BIND, FE_EXECUTE, FE_SYNC]
+        );
+
+        for msg in [
+            message(BE_PARSE_COMPLETE, b""),
+            message(BE_BIND_COMPLETE, b""),
+            message(b'D', b"\0\0"),
+            message(BE_COMMAND_COMPLETE, b"SELECT 1\0"),
+        ] {
+            assert!(!txn.forward_backend(msg, &mut client));
+        }
+        // the transaction is over once the compute is idle.
+        assert!(txn.forward_backend(message(BE_READY_FOR_QUERY, b"I"), &mut client));
+
+        assert_eq!(
+            client.sent_tags(),
+            [
+                BE_BIND_COMPLETE,
+                b'D',
+                BE_COMMAND_COMPLETE,
+                BE_READY_FOR_QUERY
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_skip_to_sync() {
+        let statement = Statement::new(b"select oops\0\0\0");
+        let (mut txn, mut client) = transaction().await;
+
+        txn.prepare(&statement, true);
+        txn.send(Request::Describe, &message(FE_DESCRIBE, b""));
+
+        // the statement fails to parse, and the describe is skipped.
+        assert!(!txn.forward_backend(message(BE_ERROR_RESPONSE, b"\0"), &mut client));
+        assert!(txn.skipping);
+        assert!(txn.pending.is_empty());
+        assert!(!txn.conn.prepared.contains(&statement.name));
+
+        // nothing is expected of the compute until the sync.
+        txn.prepare(&statement, true);
+        txn.synthesize(&mut client);
+        assert!(txn.pending.is_empty());
+
+        txn.skipping = false;
+        txn.send(Request::Sync, &message(FE_SYNC, b""));
+        txn.conn.stream.sent();
+        assert!(txn.forward_backend(message(BE_READY_FOR_QUERY, b"I"), &mut client));
+        assert_eq!(client.sent_tags(), [BE_ERROR_RESPONSE, BE_READY_FOR_QUERY]);
+    }
+
+    #[tokio::test]
+    async fn transactions_hold_on_to_the_connection() {
+        let (mut txn, mut client) = transaction().await;
+
+        txn.send(Request::Query, &message(FE_QUERY, b"begin\0"));
+        txn.conn.stream.sent();
+        assert!(!txn.forward_backend(message(BE_COMMAND_COMPLETE, b"BEGIN\0"), &mut client));
+        assert!(!txn.forward_backend(message(BE_READY_FOR_QUERY, b"T"), &mut client));
+
+        txn.send(Request::Query, &message(FE_QUERY, b"commit\0"));
+        txn.conn.stream.sent();
+        assert!(!txn.forward_backend(message(BE_COMMAND_COMPLETE, b"COMMIT\0"), &mut client));
+        assert!(txn.forward_backend(message(BE_READY_FOR_QUERY, b"I"), &mut client));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pooled_sessions_time_out() {
+        let timeouts = SessionTimeouts {
+            idle_in_transaction: Some(Duration::from_secs(60)),
+            ..SessionTimeouts::default()
+        };
+        let (mut client, mut proxy) = tokio::io::duplex(1024);
+        let mut session = TimedClient::new(&mut proxy, timeouts);
+        let mut stream = MessageStream::new(&mut session);
+
+        // the client starts a transaction, and goes idle in it.
+        let begin = message(FE_QUERY, b"begin\0");
+        client.write_all(begin.as_bytes()).await.unwrap();
+        while stream.read.len() < begin.as_bytes().len() {
+            futures::future::poll_fn(|cx| stream.poll_io(cx, true))
+                .await
+                .unwrap();
+        }
+        assert_eq!(stream.next_message().unwrap().unwrap().tag(), FE_QUERY);
+        stream.write_message(message(BE_READY_FOR_QUERY, b"T").as_bytes());
+
+        let start = Instant::now();
+        while !stream.eof {
+            futures::future::poll_fn(|cx| stream.poll_io(cx, true))
+                .await
+                .unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_secs(60));
+        drop(stream);
+        assert_eq!(
+            session.ended_by(),
+            Some(SessionEndReason::IdleInTransactionTimeout)
+        );
+
+        let mut received = vec![];
+        client.read_to_end(&mut received).await.unwrap();
+        let mut received = BytesMut::from(&received[..]);
+        let tags: Vec<u8> =
+            std::iter::from_fn(|| split_message(&mut received, MAX_MESSAGE_LEN).unwrap())
+                .map(|msg| msg.tag())
+                .collect();
+        assert_eq!(tags, [BE_READY_FOR_QUERY, BE_ERROR_RESPONSE]);
+    }
+
+    #[test]
+    fn rename_statements() {
+        let msg = rename(FE_BIND, b"portal", "neon_1", b"\0\0");
+        assert_eq!(msg.body(), b"portal\0neon_1\0\0\0");
+
+        let msg = rename(FE_DESCRIBE, b"S", "neon_1", b"");
+        assert_eq!(msg.body(), b"Sneon_1\0");
+    }
+}
@@ -5,7 +5,7 @@
 use std::fmt;
 use std::io::{self, Cursor};
 
-use bytes::{Buf, BufMut};
+use bytes::{Buf, BufMut, BytesMut};
 use itertools::Itertools;
 use rand::distr::{Distribution, StandardUniform};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -14,8 +14,38 @@
 pub type ErrorCode = [u8; 5];
 
 pub const FE_PASSWORD_MESSAGE: u8 = b'p';
+pub const FE_BIND: u8 = b'B';
+pub const FE_CLOSE: u8 = b'C';
+pub const FE_DESCRIBE: u8 = b'D';
+pub const FE_EXECUTE: u8 = b'E';
+pub const FE_FUNCTION_CALL: u8 = b'F';
+pub const FE_PARSE: u8 = b'P';
+pub const FE_QUERY: u8 = b'Q';
+pub const FE_SYNC: u8 = b'S';
+pub const FE_TERMINATE: u8 = b'X';
+
+pub const BE_PARSE_COMPLETE: u8 = b'1';
+pub const BE_BIND_COMPLETE: u8 = b'2';
+pub const BE_CLOSE_COMPLETE: u8 = b'3';
+pub const BE_NOTIFICATION_RESPONSE: u8 = b'A';
+pub const BE_COMMAND_COMPLETE: u8 = b'C';
+pub const BE_ERROR_RESPONSE: u8 = b'E';
+pub const BE_EMPTY_QUERY_RESPONSE: u8 = b'I';
+pub const BE_BACKEND_KEY_DATA: u8 = b'K';
+pub const BE_NOTICE_RESPONSE: u8 = b'N';
+pub const BE_AUTHENTICATION: u8 = b'R';
+pub const BE_PARAMETER_STATUS: u8 = b'S';
+pub const BE_ROW_DESCRIPTION: u8 = b'T';
+pub const BE_READY_FOR_QUERY: u8 = b'Z';
+pub const BE_NO_DATA: u8 = b'n';
+pub const BE_PORTAL_SUSPENDED: u8 = b's';
 
 pub const SQLSTATE_INTERNAL_ERROR: [u8; 5] = *b"XX000";
+pub const SQLSTATE_TOO_MANY_CONNECTIONS: [u8; 5] = *b"53300";
+pub const SQLSTATE_CONFIGURATION_LIMIT_EXCEEDED: [u8; 5] = *b"53400";
+pub const SQLSTATE_ADMIN_SHUTDOWN: [u8; 5] = *b"57P01";
+pub const SQLSTATE_IDLE_SESSION_TIMEOUT: [u8; 5] = *b"57P05";
+pub const SQLSTATE_IDLE_IN_TRANSACTION_SESSION_TIMEOUT: [u8; 5] = *b"25P03";
 
 /// The protocol version number.
 ///
@@ -256,6 +286,72 @@ struct Header {
     Ok((header.tag, buf))
 }
 
+/// A raw postgres message, including its tag and length.
+pub struct RawMessage(BytesMut);
+
+impl RawMessage {
+    pub fn new(tag: u8, body: &[u8]) -> Self {
+        let mut buf = BytesMut::with_capacity(5 + body.len());
+        buf.put_u8(tag);
+        buf.put_u32(body.len() as u32 + 4);
+        buf.put_slice(body);
+        Self(buf)
+    }
+
+    pub fn tag(&self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.0[5..]
+    }
+
+    /// The message as it is sent on the wire.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Split the next postgres message off the front of `buf`, once it has been read entirely.
+///
+/// Unlike [`read_message`], reading from the stream is left to the caller, which
+/// makes it possible to read messages in a cancel safe way.
+pub fn split_message(buf: &mut BytesMut, max: u32) -> io::Result<Option<RawMessage>> {
+    let Some(&[_tag, ref len @ ..]) = buf.first_chunk::<5>() else {
+        return Ok(None);
+    };
+    let len = u32::from_be_bytes(*len);
+
+    // the length is inclusive of itself, so it must be at least 4.
+    let Some(len) = len.checked_sub(4) else {
+        return Err(io::Error::other(format!(
+            "invalid message length {len}, must be at least 4."
+        )));
+    };
+
+    if len > max {
+        tracing::warn!("large postgres message detected: {len} bytes");
+        return Err(io::Error::other(format!("invalid message length {len}")));
+    }
+
+    let total = 5 + len as usize;
+    if buf.len() < total {
+        buf.reserve(total - buf.len());
+        return Ok(None);
+    }
+
+    Ok(Some(RawMessage(buf.split_to(total))))
+}
+
+/// Read a null-terminated string from the front of a message body,
+/// returning the string and the rest of the body.
+pub fn read_cstr(buf: &[u8]) -> io::Result<(&[u8], &[u8])> {
+    let Some(end) = buf.iter().position(|&b| b == 0) else {
+        return Err(io::Error::other("missing null terminator in message"));
+    };
+    Ok((&buf[..end], &buf[end + 1..]))
+}
+
 pub struct WriteBuf(Cursor<Vec<u8>>);
 
 impl Buf for WriteBuf {
@@ -601,7 +697,7 @@ mod tests {
     use zerocopy::IntoBytes;
 
     use super::ProtocolVersion;
-    use crate::pqproto::{FeStartupPacket, read_message, read_startup};
+    use crate::pqproto::{FeStartupPacket, read_message, read_startup, split_message};
 
     #[tokio::test]
     async fn reject_large_startup() {
@@ -642,6 +738,35 @@ async fn reject_large_password() {
         assert_eq!(client.to_string(), "broken pipe");
     }
 
+    #[test]
+    fn split_partial_messages() {
+        let mut payload = vec![];
+        payload.push(b'Q');
+        payload.extend_from_slice(&13_u32.to_be_bytes());
+        payload.extend_from_slice(b"select 1\0");
+        payload.push(b'S');
+        payload.extend_from_slice(&4_u32.to_be_bytes());
+
+        let mut buf = bytes::BytesMut::new();
+        let mut messages = vec![];
+        for &b in &payload {
+            buf.extend_from_slice(&[b]);
+            while let Some(msg) = split_message(&mut buf, 512).unwrap() {
+                messages.push((msg.tag(), msg.body().to_vec()));
+            }
+        }
+
+        assert_eq!(
+            messages,
+            [(b'Q', b"select 1\0".to_vec()), (b'S', b"".to_vec())]
+        );
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"Q\0\0\x02\x05");
+        let err = split_message(&mut buf, 512).unwrap_err();
+        assert_eq!(err.to_string(), "invalid message length 513");
+    }
+
     #[tokio::test]
     async fn read_startup_message() {
         let mut payload = vec![];
@@ -3,9 +3,10 @@
 
 use core::fmt;
 use std::io;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 use bytes::Buf;
+use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 use strum_macros::FromRepr;
 use tokio::io::{AsyncRead, AsyncReadExt};
@@ -50,7 +51,8 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ConnectionInfoExtra {
     Aws { vpce_id: SmolStr },
     Azure { link_id: u32 },
@@ -73,6 +75,54 @@ pub(crate) async fn read_proxy_protocol<T: AsyncRead + Unpin>(
     Ok((read, res))
 }
 
+/// Encode the header which relays the connection of the client to another server.
+/// `local_addr` is the address the client connected to.
+pub(crate) fn encode_proxy_protocol(info: &ConnectionInfo, local_addr: SocketAddr) -> Vec<u8> {
+    let mut payload = vec![];
+    let family = if let (IpAddr::V4(src), IpAddr::V4(dst)) = (info.addr.ip(), local_addr.ip()) {
+        payload.extend_from_slice(&src.octets());
+        payload.extend_from_slice(&dst.octets());
+        TCP_OVER_IPV4
+    } else {
+        let ipv6 = |ip: IpAddr| match ip {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        payload.extend_from_slice(&ipv6(info.addr.ip()).octets());
+        payload.extend_from_slice(&ipv6(local_addr.ip()).octets());
+        TCP_OVER_IPV6
+    };
+    payload.extend_from_slice(&info.addr.port().to_be_bytes());
+    payload.extend_from_slice(&local_addr.port().to_be_bytes());
+
+    let (kind, value) = match &info.extra {
+        None => (None, vec![]),
+        Some(ConnectionInfoExtra::Aws { vpce_id }) => (
+            Some(Pp2Kind::Aws),
+            [&[Pp2AwsType::VpceId as u8], vpce_id.as_bytes()].concat(),
+        ),
+        Some(ConnectionInfoExtra::Azure { link_id }) => (
+            Some(Pp2Kind::Azure),
+            [
+                &[Pp2AzureType::PrivateEndpointLinkId as u8],
+                &link_id.to_le_bytes()[..],
+            ]
+            .concat(),
+        ),
+    };
+    if let Some(kind) = kind {
+        payload.push(kind as u8);
+        payload.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&value);
+    }
+
+    let mut header = SIGNATURE.to_vec();
+    header.extend_from_slice(&[PROXY_V2, family]);
+    header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    header.extend_from_slice(&payload);
+    header
+}
+
 fn process_proxy_payload(
     header: ProxyProtocolV2Header,
     mut payload: &[u8],
@@ -295,7 +345,8 @@ mod tests {
     use tokio::io::AsyncReadExt;
 
     use crate::protocol2::{
-        ConnectHeader, LOCAL_V2, PROXY_V2, TCP_OVER_IPV4, UDP_OVER_IPV6, read_proxy_protocol,
+        ConnectHeader, ConnectionInfo, ConnectionInfoExtra, LOCAL_V2, PROXY_V2, TCP_OVER_IPV4,
+        UDP_OVER_IPV6, encode_proxy_protocol, read_proxy_protocol,
     };
 
     #[tokio::test]
@@ -448,4 +499,35 @@ async fn test_local() {
 
         let ConnectHeader::Local = info else { panic!() };
     }
+
+    #[tokio::test]
+    async fn test_encode() {
+        let cases = [
+            ConnectionInfo {
+                addr: ([10, 0, 0, 1], 5432).into(),
+                extra: None,
+            },
+            ConnectionInfo {
+                addr: ([10, 0, 0, 1], 5432).into(),
+                extra: Some(ConnectionInfoExtra::Aws {
+                    vpce_id: "vpce-0123456789abcdef".into(),
+                }),
+            },
+            ConnectionInfo {
+                addr: ([0, 0, 0, 0, 0, 0, 0, 1], 5432).into(),
+                extra: Some(ConnectionInfoExtra::Azure { link_id: 42 }),
+            },
+        ];
+        for info in cases {
+            let header = encode_proxy_protocol(&info, ([192, 168, 0, 1], 4432).into());
+            let (_, decoded) = read_proxy_protocol(header.as_slice()).await.unwrap();
+            // the addresses of other families are mapped to ipv6.
+            let ConnectHeader::Proxy(decoded) = decoded else {
+                panic!()
+            };
+            assert_eq!(decoded.addr.ip().to_canonical(), info.addr.ip());
+            assert_eq!(decoded.addr.port(), info.addr.port());
+            assert_eq!(decoded.extra, info.extra);
+        }
+    }
 }
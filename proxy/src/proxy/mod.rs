@@ -7,7 +7,6 @@
 pub(crate) mod wake_compute;
 
 use std::collections::HashSet;
-use std::convert::Infallible;
 use std::sync::Arc;
 
 use futures::TryStreamExt;
@@ -21,17 +20,21 @@
 use smol_str::{SmolStr, format_smolstr};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tracing::Instrument;
 
 use crate::cancellation::{CancelClosure, CancellationHandler};
-use crate::compute::{ComputeConnection, PostgresError, RustlsStream};
+use crate::compute::{PostgresError, RustlsStream};
 use crate::config::ProxyConfig;
 use crate::context::RequestContext;
+use crate::control_plane::ControlPlaneApi;
+use crate::control_plane::messages::MetricsAuxInfo;
 pub use crate::pglb::copy_bidirectional::{ErrorSource, copy_bidirectional_client_compute};
+use crate::pglb::passthrough::Compute;
+use crate::pglb::transaction_pool::PooledClient;
 use crate::pglb::{ClientMode, ClientRequestError};
 use crate::pqproto::{BeMessage, CancelKeyData, StartupMessageParams};
-use crate::rate_limiter::EndpointRateLimiter;
+use crate::rate_limiter::{EndpointRateLimiter, SessionGuard};
 use crate::stream::{PqStream, Stream};
 use crate::types::EndpointCacheKey;
 use crate::{auth, compute};
@@ -47,7 +50,7 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
     endpoint_rate_limiter: Arc<EndpointRateLimiter>,
     common_names: Option<&HashSet<String>>,
     params: &StartupMessageParams,
-) -> Result<(ComputeConnection, oneshot::Sender<Infallible>), ClientRequestError> {
+) -> Result<(Compute, MetricsAuxInfo, SessionGuard), ClientRequestError> {
     let hostname = mode.hostname(client.get_ref());
     // Extract credentials which we're going to use for auth.
     let result = auth_backend
@@ -88,12 +91,58 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
         auth::Backend::ControlPlane(cplane, creds) => (cplane, creds),
         auth::Backend::Local(_) => unreachable!("local proxy does not run tcp proxy service"),
     };
+
+    // count the session against the limits of the endpoint before connecting to it.
+    // the access control was fetched during authentication, so this hits the cache.
+    let limits = match cplane
+        .get_endpoint_access_control(ctx, &creds.info.endpoint, &creds.info.user)
+        .await
+    {
+        Ok(access_control) => {
+            ctx.set_audit_log(access_control.audit_log);
+            access_control.acquire_session(
+                ctx,
+                &creds.info.endpoint,
+                &creds.info.user,
+                &config.session_limiter,
+            )
+        }
+        Err(e) => Err(client.throw_error(e, Some(ctx)).await)?,
+    };
+    let session_guard = match limits {
+        Ok(session_guard) => session_guard,
+        Err(e) => Err(client.throw_error(e, Some(ctx)).await)?,
+    };
     let params_compat = creds.info.options.get(NeonOptions::PARAMS_COMPAT).is_some();
+    let transaction_pool = config.transaction_pool.as_ref().filter(|_| {
+        creds.info.options.get(NeonOptions::POOL_MODE).as_deref() == Some("transaction")
+    });
     let mut auth_info = compute::AuthInfo::with_auth_keys(creds.keys);
     auth_info.set_startup_params(params, params_compat);
 
     let backend = auth::Backend::ControlPlane(cplane, creds.info);
 
+    if let Some(pool) = transaction_pool {
+        let (cancel_target, cancel_targets) = watch::channel(None);
+        let pooled = PooledClient::new(pool, ctx, config, backend, auth_info, cancel_target);
+        let conn = match pooled.connect().await {
+            Ok(conn) => conn,
+            Err(e) => Err(client.throw_error(e, Some(ctx)).await)?,
+        };
+
+        send_client_greeting(ctx, &config.greetings, client);
+
+        // the key follows the compute connection of the current transaction.
+        let session = cancellation_handler.get_key();
+        conn.greet(client, *session.key());
+        let aux = conn.aux().clone();
+        pooled.release(conn);
+
+        tokio::spawn(async move { session.maintain_pooled_cancel_key(cancel_targets).await });
+
+        return Ok((Compute::Pooled(pooled), aux, session_guard));
+    }
+
     // TODO: callback to pglb
     let res = connect_auth::connect_to_compute_and_auth(
         ctx,
@@ -143,7 +192,12 @@ pub(crate) async fn handle_client<S: AsyncRead + AsyncWrite + Unpin + Send>(
             .await;
     });
 
-    Ok((node, cancel_on_shutdown))
+    let compute = Compute::Dedicated {
+        stream: node.stream.into_framed().into_inner(),
+        cancel_on_shutdown,
+        db_conn: node.guage,
+    };
+    Ok((compute, node.aux, session_guard))
 }
 
 /// Greet the client with any useful information.
@@ -255,6 +309,13 @@ impl NeonOptions {
     /// `PARAMS_COMPAT` allows opting in to forwarding all startup parameters from client to compute.
     pub const PARAMS_COMPAT: &'static str = "proxy_params_compat";
 
+    /// `POOL_MODE` allows opting in to sharing compute connections with other clients,
+    /// with the `transaction` value.
+    pub const POOL_MODE: &'static str = "proxy_pool_mode";
+
+    /// `READ_ONLY` allows opting in to connecting to a read replica of the branch, if there is one.
+    pub const READ_ONLY: &'static str = "proxy_read_only";
+
     // cplane options:
 
     /// `LSN` allows provisioning an ephemeral compute with time-travel to the provided LSN.
@@ -267,10 +328,16 @@ impl NeonOptions {
     const ENDPOINT_TYPE: &'static str = "endpoint_type";
 
     pub(crate) fn parse_params(params: &StartupMessageParams) -> Self {
-        params
+        let options = params
             .options_raw()
             .map(Self::parse_from_iter)
-            .unwrap_or_default()
+            .unwrap_or_default();
+
+        // drivers which know about standbys ask for one with `target_session_attrs`.
+        match params.get("target_session_attrs") {
+            Some("read-only" | "standby" | "prefer-standby") => options.with_read_only(),
+            _ => options,
+        }
     }
 
     pub(crate) fn parse_options_raw(options: &str) -> Self {
@@ -284,10 +351,26 @@ pub(crate) fn get(&self, key: &str) -> Option<SmolStr> {
             .cloned()
     }
 
+    /// Whether the session should be routed to a read replica.
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.get(Self::READ_ONLY).as_deref() == Some("true")
+    }
+
+    pub(crate) fn with_read_only(mut self) -> Self {
+        if !self.is_read_only() {
+            self.0.retain(|(k, _)| k != Self::READ_ONLY);
+            self.0.push((Self::READ_ONLY.into(), "true".into()));
+            self.0.sort();
+        }
+        self
+    }
+
     pub(crate) fn is_ephemeral(&self) -> bool {
         self.0.iter().any(|(k, _)| match &**k {
-            // This is not a cplane option, we know it does not create ephemeral computes.
+            // These are not cplane options, we know they do not create ephemeral computes.
             Self::PARAMS_COMPAT => false,
+            Self::POOL_MODE => false,
+            Self::READ_ONLY => false,
             Self::LSN => true,
             Self::TIMESTAMP => true,
             Self::ENDPOINT_TYPE => true,
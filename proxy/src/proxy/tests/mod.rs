@@ -542,6 +542,7 @@ fn helper_create_uncached_node_info() -> NodeInfo {
             compute_id: "compute".into(),
             cold_start_info: crate::control_plane::messages::ColdStartInfo::Warm,
         },
+        replica: None,
     }
 }
 
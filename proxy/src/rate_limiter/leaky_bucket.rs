@@ -35,6 +35,17 @@ pub fn new_with_shards(config: LeakyBucketConfig, shards: usize) -> Self {
 
     /// Check that number of connections to the endpoint is below `max_rps` rps.
     pub(crate) fn check(&self, key: K, config: Option<LeakyBucketConfig>, n: u32) -> bool {
+        self.check_ready_at(key, config, n).is_ok()
+    }
+
+    /// Like [`Self::check`], but returns when the tokens could be added if
+    /// the bucket is full.
+    pub(crate) fn check_ready_at(
+        &self,
+        key: K,
+        config: Option<LeakyBucketConfig>,
+        n: u32,
+    ) -> Result<(), Instant> {
         let now = Instant::now();
 
         let config = config.map_or(self.default_config, Into::into);
@@ -52,7 +63,7 @@ pub(crate) fn check(&self, key: K, config: Option<LeakyBucketConfig>, n: u32) ->
             .entry(key)
             .or_insert_with(|| LeakyBucketState { empty_at: now });
 
-        entry.add_tokens(&config, now, n as f64).is_ok()
+        entry.add_tokens(&config, now, n as f64)
     }
 
     fn do_gc(&self, now: Instant) {
@@ -1,6 +1,7 @@
 mod leaky_bucket;
 mod limit_algorithm;
 mod limiter;
+mod session;
 
 pub use leaky_bucket::{EndpointRateLimiter, LeakyBucketConfig, LeakyBucketRateLimiter};
 #[cfg(test)]
@@ -9,3 +10,5 @@
     DynamicLimiter, Outcome, RateLimitAlgorithm, RateLimiterConfig, Token,
 };
 pub use limiter::{RateBucketInfo, WakeComputeRateLimiter};
+pub use session::SessionLimiter;
+pub(crate) use session::{SessionGuard, SessionLimitError};
@@ -0,0 +1,277 @@
+use std::hash::Hash;
+
+use ahash::RandomState;
+use clashmap::ClashMap;
+use clashmap::mapref::entry::Entry;
+use thiserror::Error;
+use tokio::time::Instant;
+
+use super::{LeakyBucketConfig, LeakyBucketRateLimiter};
+use crate::control_plane::messages::{LeakyBucketSetting, SessionLimits, SessionTimeouts};
+use crate::error::{ErrorKind, ReportableError, UserFacingError};
+use crate::intern::{EndpointIdInt, RoleNameInt};
+use crate::metrics::{Metrics, Protocol, SessionLimitGroup, SessionLimitKind};
+use crate::pqproto::{
+    ErrorCode, SQLSTATE_CONFIGURATION_LIMIT_EXCEEDED, SQLSTATE_TOO_MANY_CONNECTIONS,
+};
+
+// Limits on the established sessions of each endpoint, as set by the control
+// plane in `SessionLimits`: concurrent sessions per endpoint and per role, and
+// the rate of queries per role. The session timeouts are enforced by the
+// proxy pass, see `pglb::copy_bidirectional`.
+pub struct SessionLimiter {
+    endpoints: ClashMap<EndpointIdInt, u32, RandomState>,
+    roles: ClashMap<(EndpointIdInt, RoleNameInt), u32, RandomState>,
+    queries: LeakyBucketRateLimiter<(EndpointIdInt, RoleNameInt)>,
+}
+
+impl SessionLimiter {
+    pub fn new_with_shards(shards: usize) -> Self {
+        Self {
+            endpoints: ClashMap::with_hasher_and_shard_amount(RandomState::new(), shards),
+            roles: ClashMap::with_hasher_and_shard_amount(RandomState::new(), shards),
+            queries: LeakyBucketRateLimiter::new_with_shards(
+                LeakyBucketRateLimiter::<()>::DEFAULT,
+                shards,
+            ),
+        }
+    }
+
+    /// Register a new session of the role to the endpoint, unless either of
+    /// them already has as many sessions as allowed.
+    pub(crate) fn acquire(
+        &'static self,
+        protocol: Protocol,
+        endpoint: EndpointIdInt,
+        role: RoleNameInt,
+        limits: &SessionLimits,
+    ) -> Result<SessionGuard, SessionLimitError> {
+        if !increment(&self.endpoints, endpoint, limits.max_connections) {
+            record_exceeded(protocol, SessionLimitKind::EndpointConnections);
+            return Err(SessionLimitError::TooManyConnections);
+        }
+        if !increment(&self.roles, (endpoint, role), limits.max_role_connections) {
+            decrement(&self.endpoints, endpoint);
+            record_exceeded(protocol, SessionLimitKind::RoleConnections);
+            return Err(SessionLimitError::TooManyRoleConnections);
+        }
+
+        Ok(SessionGuard {
+            limiter: self,
+            protocol,
+            endpoint,
+            role,
+            role_queries: limits
+                .role_queries
+                .filter(|config| config.rps > 0.0 && config.burst > 0.0),
+            timeouts: limits.timeouts,
+        })
+    }
+
+    #[cfg(test)]
+    fn sessions(&self, endpoint: EndpointIdInt, role: RoleNameInt) -> (u32, u32) {
+        (
+            self.endpoints.get(&endpoint).map_or(0, |c| *c),
+            self.roles.get(&(endpoint, role)).map_or(0, |c| *c),
+        )
+    }
+}
+
+/// Increment the number of sessions of `key`, unless it reached `max`.
+fn increment<K: Hash + Eq>(map: &ClashMap<K, u32, RandomState>, key: K, max: Option<u32>) -> bool {
+    match map.entry(key) {
+        Entry::Occupied(mut count) => {
+            if max.is_some_and(|max| *count.get() >= max) {
+                return false;
+            }
+            *count.get_mut() += 1;
+        }
+        Entry::Vacant(count) => {
+            if max == Some(0) {
+                return false;
+            }
+            count.insert(1);
+        }
+    }
+    true
+}
+
+fn decrement<K: Hash + Eq>(map: &ClashMap<K, u32, RandomState>, key: K) {
+    if let Entry::Occupied(mut count) = map.entry(key) {
+        *count.get_mut() -= 1;
+        if *count.get() == 0 {
+            count.remove();
+        }
+    }
+}
+
+fn record_exceeded(protocol: Protocol, limit: SessionLimitKind) {
+    Metrics::get()
+        .proxy
+        .session_limits_exceeded
+        .inc(SessionLimitGroup { protocol, limit });
+}
+
+/// A session counted against the limits of its endpoint and role, until dropped.
+pub(crate) struct SessionGuard {
+    limiter: &'static SessionLimiter,
+    protocol: Protocol,
+    endpoint: EndpointIdInt,
+    role: RoleNameInt,
+    role_queries: Option<LeakyBucketSetting>,
+    timeouts: SessionTimeouts,
+}
+
+impl SessionGuard {
+    pub(crate) fn timeouts(&self) -> &SessionTimeouts {
+        &self.timeouts
+    }
+
+    /// Whether the queries of the session are rate limited.
+    pub(crate) fn has_query_limit(&self) -> bool {
+        self.role_queries.is_some()
+    }
+
+    /// Account for `n` queries of the session. If the role ran too many
+    /// queries, returns when they can run instead.
+    pub(crate) fn check_queries(&self, n: u32) -> Result<(), Instant> {
+        let Some(config) = self.role_queries else {
+            return Ok(());
+        };
+
+        let config = LeakyBucketConfig::new(config.rps, config.burst);
+        self.limiter
+            .queries
+            .check_ready_at((self.endpoint, self.role), Some(config), n)
+            .inspect_err(|_| record_exceeded(self.protocol, SessionLimitKind::Queries))
+    }
+
+    /// Like [`Self::check_queries`], for the callers that reject the queries
+    /// instead of waiting.
+    pub(crate) fn try_queries(&self, n: u32) -> Result<(), SessionLimitError> {
+        self.check_queries(n)
+            .map_err(|_| SessionLimitError::TooManyQueries)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        decrement(&self.limiter.roles, (self.endpoint, self.role));
+        decrement(&self.limiter.endpoints, self.endpoint);
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SessionLimitError {
+    #[error("Too many connections to this endpoint. Please try again later.")]
+    TooManyConnections,
+
+    #[error("Too many connections for this role. Please try again later.")]
+    TooManyRoleConnections,
+
+    #[error("Too many queries for this role. Please try again later.")]
+    TooManyQueries,
+}
+
+impl ReportableError for SessionLimitError {
+    fn get_error_kind(&self) -> ErrorKind {
+        ErrorKind::RateLimit
+    }
+}
+
+impl UserFacingError for SessionLimitError {
+    fn sql_state(&self) -> ErrorCode {
+        match self {
+            SessionLimitError::TooManyConnections => SQLSTATE_TOO_MANY_CONNECTIONS,
+            SessionLimitError::TooManyRoleConnections => SQLSTATE_TOO_MANY_CONNECTIONS,
+            SessionLimitError::TooManyQueries => SQLSTATE_CONFIGURATION_LIMIT_EXCEEDED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{EndpointId, RoleName};
+
+    fn limiter() -> &'static SessionLimiter {
+        Box::leak(Box::new(SessionLimiter::new_with_shards(4)))
+    }
+
+    #[test]
+    fn concurrent_sessions() {
+        let limiter = limiter();
+        let endpoint = EndpointIdInt::from(&EndpointId::from("ep-session-limits"));
+        let alice = RoleNameInt::from(&RoleName::from("alice"));
+        let bob = RoleNameInt::from(&RoleName::from("bob"));
+        let limits = SessionLimits {
+            max_connections: Some(3),
+            max_role_connections: Some(2),
+            ..SessionLimits::default()
+        };
+
+        let a1 = limiter
+            .acquire(Protocol::Tcp, endpoint, alice, &limits)
+            .unwrap();
+        let _a2 = limiter
+            .acquire(Protocol::Tcp, endpoint, alice, &limits)
+            .unwrap();
+        let err = limiter.acquire(Protocol::Tcp, endpoint, alice, &limits);
+        assert!(matches!(
+            err,
+            Err(SessionLimitError::TooManyRoleConnections)
+        ));
+        assert_eq!(limiter.sessions(endpoint, alice), (2, 2));
+
+        let _b1 = limiter
+            .acquire(Protocol::Http, endpoint, bob, &limits)
+            .unwrap();
+        let err = limiter.acquire(Protocol::Http, endpoint, bob, &limits);
+        assert!(matches!(err, Err(SessionLimitError::TooManyConnections)));
+        assert_eq!(limiter.sessions(endpoint, bob), (3, 1));
+
+        drop(a1);
+        assert_eq!(limiter.sessions(endpoint, alice), (2, 1));
+        let _b2 = limiter
+            .acquire(Protocol::Http, endpoint, bob, &limits)
+            .unwrap();
+    }
+
+    #[test]
+    fn sessions_are_released() {
+        let limiter = limiter();
+        let endpoint = EndpointIdInt::from(&EndpointId::from("ep-session-release"));
+        let role = RoleNameInt::from(&RoleName::from("alice"));
+
+        let session = limiter
+            .acquire(Protocol::Ws, endpoint, role, &SessionLimits::default())
+            .unwrap();
+        assert_eq!(limiter.sessions(endpoint, role), (1, 1));
+        drop(session);
+        assert!(limiter.endpoints.is_empty());
+        assert!(limiter.roles.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn query_rate() {
+        let limiter = limiter();
+        let endpoint = EndpointIdInt::from(&EndpointId::from("ep-query-rate"));
+        let role = RoleNameInt::from(&RoleName::from("alice"));
+        let limits = SessionLimits {
+            role_queries: Some(LeakyBucketSetting {
+                rps: 10.0,
+                burst: 5.0,
+            }),
+            ..SessionLimits::default()
+        };
+
+        let session = limiter
+            .acquire(Protocol::Tcp, endpoint, role, &limits)
+            .unwrap();
+        assert!(session.has_query_limit());
+        session.check_queries(5).unwrap();
+        let ready_at = session.check_queries(1).unwrap_err();
+        tokio::time::advance(ready_at - Instant::now()).await;
+        session.check_queries(1).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! An in-memory stand-in for Redis, for the tests of the features which keep their
+//! state there. Only the commands used by the proxy are supported, the others are
+//! acknowledged without doing anything.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+
+use super::connection_with_credentials_provider::ConnectionWithCredentialsProvider;
+use super::kv_ops::RedisKVClient;
+
+type Keys = Arc<Mutex<HashMap<Vec<u8>, (Vec<u8>, Option<Instant>)>>>;
+
+/// Serve on a local port, for as long as the runtime is running.
+pub(crate) async fn serve() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let keys = Keys::default();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle(stream, keys.clone()));
+        }
+    });
+
+    addr
+}
+
+/// A client connected to the server at `addr`.
+pub(crate) async fn client(addr: SocketAddr) -> RedisKVClient {
+    let mut client = RedisKVClient::new(
+        ConnectionWithCredentialsProvider::new_with_static_credentials(format!("redis://{addr}")),
+    );
+    client.try_connect().await.unwrap();
+    client
+}
+
+async fn handle(stream: TcpStream, keys: Keys) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    while let Some(command) = read_command(&mut read).await? {
+        let reply = run(&keys, &command);
+        write.write_all(&reply).await?;
+    }
+    Ok(())
+}
+
+/// Read a command, sent as an array of bulk strings.
+async fn read_command(read: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(len) = read_len(read, '*').await? else {
+        return Ok(None);
+    };
+
+    let mut args = Vec::with_capacity(len);
+    for _ in 0..len {
+        let len = read_len(read, '$')
+            .await?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        let mut arg = vec![0; len + 2];
+        read.read_exact(&mut arg).await?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+async fn read_len(
+    read: &mut (impl AsyncBufRead + Unpin),
+    prefix: char,
+) -> io::Result<Option<usize>> {
+    let mut line = String::new();
+    if read.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    line.trim_end()
+        .strip_prefix(prefix)
+        .and_then(|len| len.parse().ok())
+        .map(Some)
+        .ok_or_else(|| io::Error::other(format!("unexpected line: {line:?}")))
+}
+
+fn run(keys: &Keys, command: &[Vec<u8>]) -> Vec<u8> {
+    let mut keys = keys.lock().unwrap();
+    let now = Instant::now();
+    keys.retain(|_, (_, expires)| expires.is_none_or(|at| at > now));
+
+    let Some((name, args)) = command.split_first() else {
+        return b"-ERR empty command\r\n".to_vec();
+    };
+    match (name.to_ascii_uppercase().as_slice(), args) {
+        (b"PING", _) => b"+PONG\r\n".to_vec(),
+        (b"SET", [key, value, options @ ..]) => {
+            let expires = match options {
+                [ex, secs] if ex.eq_ignore_ascii_case(b"EX") => Some(now + seconds(secs)),
+                _ => None,
+            };
+            keys.insert(key.clone(), (value.clone(), expires));
+            b"+OK\r\n".to_vec()
+        }
+        (b"GET", [key]) => match keys.get(key) {
+            Some((value, _)) => {
+                let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                reply.extend_from_slice(value);
+                reply.extend_from_slice(b"\r\n");
+                reply
+            }
+            None => b"$-1\r\n".to_vec(),
+        },
+        (b"EXPIRE", [key, secs]) => match keys.get_mut(key) {
+            Some((_, expires)) => {
+                *expires = Some(now + seconds(secs));
+                b":1\r\n".to_vec()
+            }
+            None => b":0\r\n".to_vec(),
+        },
+        (b"DEL", args) => {
+            let removed = args
+                .iter()
+                .filter(|key| keys.remove(*key).is_some())
+                .count();
+            format!(":{removed}\r\n").into_bytes()
+        }
+        // e.g. `CLIENT SETINFO` while connecting.
+        _ => b"+OK\r\n".to_vec(),
+    }
+}
+
+fn seconds(arg: &[u8]) -> Duration {
+    let secs = std::str::from_utf8(arg).ok().and_then(|s| s.parse().ok());
+    Duration::from_secs(secs.unwrap_or_default())
+}
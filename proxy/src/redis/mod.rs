@@ -2,4 +2,6 @@
 pub mod elasticache;
 pub mod keys;
 pub mod kv_ops;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod notifications;
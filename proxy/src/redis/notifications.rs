@@ -4,10 +4,13 @@
 use futures::StreamExt;
 use redis::aio::PubSub;
 use serde::Deserialize;
+use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
 use super::connection_with_credentials_provider::ConnectionWithCredentialsProvider;
+use super::kv_ops::RedisKVClient;
 use crate::cache::project_info::ProjectInfoCache;
+use crate::cache::query::QueryCache;
 use crate::intern::{AccountIdInt, EndpointIdInt, ProjectIdInt, RoleNameInt};
 use crate::metrics::{Metrics, RedisErrors, RedisEventsCount};
 use crate::util::deserialize_json_string;
@@ -15,6 +18,7 @@
 const CPLANE_CHANNEL_NAME: &str = "neondb-proxy-ws-updates";
 const RECONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
 const INVALIDATION_LAG: std::time::Duration = std::time::Duration::from_secs(20);
+const QUERY_CACHE_PURGE_TOPIC: &str = "/query_cache_purge";
 
 async fn try_connect(client: &ConnectionWithCredentialsProvider) -> anyhow::Result<PubSub> {
     let mut conn = client.get_async_pubsub().await?;
@@ -60,6 +64,13 @@ enum Notification {
     )]
     RoleSettingUpdate(InvalidateRole),
 
+    /// Sent by proxies after writes to an endpoint, or to purge its query cache explicitly.
+    #[serde(
+        rename = "/query_cache_purge",
+        deserialize_with = "deserialize_json_string"
+    )]
+    QueryCachePurge(InvalidateEndpoint),
+
     #[serde(
         other,
         deserialize_with = "deserialize_unknown_topic",
@@ -132,20 +143,25 @@ fn deserialize_unknown_topic<'de, D>(deserializer: D) -> Result<(), D::Error>
 }
 
 struct MessageHandler<C: Send + Sync + 'static> {
-    cache: Arc<C>,
+    cache: Option<Arc<C>>,
+    query_cache: Option<Arc<QueryCache>>,
 }
 
 impl<C: Send + Sync + 'static> Clone for MessageHandler<C> {
     fn clone(&self) -> Self {
         Self {
             cache: self.cache.clone(),
+            query_cache: self.query_cache.clone(),
         }
     }
 }
 
 impl MessageHandler<ProjectInfoCache> {
-    pub(crate) fn new(cache: Arc<ProjectInfoCache>) -> Self {
-        Self { cache }
+    pub(crate) fn new(
+        cache: Option<Arc<ProjectInfoCache>>,
+        query_cache: Option<Arc<QueryCache>>,
+    ) -> Self {
+        Self { cache, query_cache }
     }
 
     #[tracing::instrument(skip(self, msg), fields(session_id = tracing::field::Empty))]
@@ -185,8 +201,9 @@ async fn handle_message(&self, msg: redis::Msg) -> anyhow::Result<()> {
             Notification::RoleSettingUpdate { .. }
             | Notification::EndpointSettingsUpdate { .. }
             | Notification::ProjectSettingsUpdate { .. }
-            | Notification::AccountSettingsUpdate { .. } => {
-                invalidate_cache(self.cache.clone(), msg.clone());
+            | Notification::AccountSettingsUpdate { .. }
+            | Notification::QueryCachePurge { .. } => {
+                self.invalidate_cache(&msg);
 
                 let m = &Metrics::get().proxy.redis_events_count;
                 match msg {
@@ -202,6 +219,9 @@ async fn handle_message(&self, msg: redis::Msg) -> anyhow::Result<()> {
                     Notification::AccountSettingsUpdate { .. } => {
                         m.inc(RedisEventsCount::InvalidateOrg);
                     }
+                    Notification::QueryCachePurge { .. } => {
+                        m.inc(RedisEventsCount::PurgeQueryCache);
+                    }
                     Notification::UnknownTopic => {}
                 }
 
@@ -210,10 +230,10 @@ async fn handle_message(&self, msg: redis::Msg) -> anyhow::Result<()> {
                 // It might happen that the invalid entry is on the way to be cached.
                 // To make sure that the entry is invalidated, let's repeat the invalidation in INVALIDATION_LAG seconds.
                 // TODO: include the version (or the timestamp) in the message and invalidate only if the entry is cached before the message.
-                let cache = self.cache.clone();
+                let handler = self.clone();
                 tokio::spawn(async move {
                     tokio::time::sleep(INVALIDATION_LAG).await;
-                    invalidate_cache(cache, msg);
+                    handler.invalidate_cache(&msg);
                 });
             }
 
@@ -222,9 +242,20 @@ async fn handle_message(&self, msg: redis::Msg) -> anyhow::Result<()> {
 
         Ok(())
     }
+
+    fn invalidate_cache(&self, msg: &Notification) {
+        if let Notification::QueryCachePurge(ids) = msg {
+            if let Some(query_cache) = &self.query_cache {
+                ids.iter()
+                    .for_each(|&id| query_cache.invalidate_endpoint(id));
+            }
+        } else if let Some(cache) = &self.cache {
+            invalidate_cache(cache, msg);
+        }
+    }
 }
 
-fn invalidate_cache(cache: Arc<ProjectInfoCache>, msg: Notification) {
+fn invalidate_cache(cache: &ProjectInfoCache, msg: &Notification) {
     match msg {
         Notification::EndpointSettingsUpdate(ids) => ids
             .iter()
@@ -241,7 +272,10 @@ fn invalidate_cache(cache: Arc<ProjectInfoCache>, msg: Notification) {
         Notification::RoleSettingUpdate(InvalidateRole {
             project_id,
             role_name,
-        }) => cache.invalidate_role_secret_for_project(project_id, role_name),
+        }) => cache.invalidate_role_secret_for_project(*project_id, *role_name),
+
+        // query cache is not a part of the project info cache.
+        Notification::QueryCachePurge(_) => {}
 
         Notification::UnknownTopic => unreachable!(),
     }
@@ -282,13 +316,14 @@ async fn handle_messages(
     }
 }
 
-/// Handle console's invalidation messages.
+/// Handle console's invalidation messages, and query cache purges from the other proxies.
 #[tracing::instrument(name = "redis_notifications", skip_all)]
 pub async fn task_main(
     redis: ConnectionWithCredentialsProvider,
-    cache: Arc<ProjectInfoCache>,
+    cache: Option<Arc<ProjectInfoCache>>,
+    query_cache: Option<Arc<QueryCache>>,
 ) -> anyhow::Result<Infallible> {
-    let handler = MessageHandler::new(cache);
+    let handler = MessageHandler::new(cache, query_cache);
     // 6h - 1m.
     // There will be 1 minute overlap between two tasks. But at least we can be sure that no message is lost.
     let mut interval = tokio::time::interval(std::time::Duration::from_secs(6 * 60 * 60 - 60));
@@ -308,12 +343,44 @@ pub async fn task_main(
     }
 }
 
+/// Publish the purges of the query cache, so that the other proxies drop their cached results
+/// for the endpoints written to through this one.
+#[tracing::instrument(name = "redis_query_cache_purges", skip_all)]
+pub async fn publish_query_cache_purges(
+    redis: ConnectionWithCredentialsProvider,
+    mut purges: mpsc::Receiver<EndpointIdInt>,
+) -> anyhow::Result<Infallible> {
+    let mut client = RedisKVClient::new(redis);
+    if let Err(e) = client.try_connect().await {
+        tracing::error!("failed to connect to redis: {e}, will retry on the first purge");
+    }
+
+    while let Some(endpoint) = purges.recv().await {
+        let data = serde_json::json!({ "endpoint_id": endpoint.as_str() }).to_string();
+        let payload = serde_json::json!({
+            "topic": QUERY_CACHE_PURGE_TOPIC,
+            "data": data,
+        })
+        .to_string();
+
+        let publish = redis::cmd("PUBLISH")
+            .arg(CPLANE_CHANNEL_NAME)
+            .arg(payload)
+            .to_owned();
+        if let Err(e) = client.query::<()>(&publish).await {
+            tracing::error!(%endpoint, "failed to publish query cache purge: {e}");
+        }
+    }
+
+    anyhow::bail!("query cache purges queue closed")
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
 
     use super::*;
-    use crate::types::{ProjectId, RoleName};
+    use crate::types::{EndpointId, ProjectId, RoleName};
 
     #[test]
     fn parse_allowed_ips() -> anyhow::Result<()> {
@@ -386,6 +453,26 @@ fn parse_password_updated() -> anyhow::Result<()> {
         Ok(())
     }
 
+    #[test]
+    fn parse_query_cache_purge() -> anyhow::Result<()> {
+        let endpoint_id: EndpointId = "ep-foo-123".into();
+        let data = format!("{{\"endpoint_id\": \"{endpoint_id}\"}}");
+        let text = json!({
+            "type": "message",
+            "topic": QUERY_CACHE_PURGE_TOPIC,
+            "data": data,
+        })
+        .to_string();
+
+        let result: Notification = serde_json::from_str(&text)?;
+        assert_eq!(
+            result,
+            Notification::QueryCachePurge(InvalidateEndpoint::EndpointId((&endpoint_id).into()))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn parse_unknown_topic() -> anyhow::Result<()> {
         let with_data = json!({
@@ -0,0 +1,112 @@
+//! Parser for the `OAUTHBEARER` SASL mechanism.
+//!
+//! RFC: <https://datatracker.ietf.org/doc/html/rfc7628>.
+
+use super::{ChannelBinding, Error, Result};
+
+/// Name of the mechanism as advertised to the client.
+pub(crate) const OAUTHBEARER: &str = "OAUTHBEARER";
+
+/// Server challenge which tells the client that its token was rejected.
+/// The client must acknowledge it with a lone `\x01` before we report the error.
+pub(crate) const INVALID_TOKEN: &str = r#"{"status":"invalid_token"}"#;
+
+/// Separator between the key-value pairs of a client message.
+const KVSEP: char = '\x01';
+
+/// Extract the bearer token from the client's initial response, e.g.
+/// `n,,\x01auth=Bearer <token>\x01\x01`.
+///
+/// Returns `None` if the client has sent an empty `auth` value,
+/// which is how clients ask for the server's discovery information.
+pub(crate) fn parse_client_response(input: &str) -> Result<Option<&str>> {
+    let mut parts = input.splitn(3, ',');
+    let (Some(cbind_flag), Some(authzid), Some(kvpairs)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::BadClientMessage("missing gs2 header"));
+    };
+
+    match ChannelBinding::parse(cbind_flag) {
+        Some(ChannelBinding::NotSupportedClient | ChannelBinding::NotSupportedServer) => {}
+        Some(ChannelBinding::Required(_)) => {
+            return Err(Error::ChannelBindingFailed(
+                "OAUTHBEARER does not support channel binding",
+            ));
+        }
+        None => return Err(Error::BadClientMessage("invalid channel binding flag")),
+    }
+
+    if !authzid.is_empty() {
+        return Err(Error::BadClientMessage("authzid is not supported"));
+    }
+
+    let kvpairs = kvpairs
+        .strip_prefix(KVSEP)
+        .and_then(|kvpairs| kvpairs.strip_suffix(KVSEP))
+        // every key-value pair is terminated by a separator
+        .filter(|kvpairs| kvpairs.is_empty() || kvpairs.ends_with(KVSEP))
+        .ok_or(Error::BadClientMessage("malformed key-value pairs"))?;
+
+    let mut auth = None;
+    for kvpair in kvpairs.split_terminator(KVSEP) {
+        let (key, value) = kvpair
+            .split_once('=')
+            .ok_or(Error::BadClientMessage("malformed key-value pair"))?;
+        if key == "auth" {
+            auth = Some(value);
+        }
+    }
+
+    let auth = auth.ok_or(Error::BadClientMessage("missing auth value"))?;
+    if auth.is_empty() {
+        return Ok(None);
+    }
+
+    // The scheme is case-insensitive, see RFC 6750.
+    let token = auth
+        .split_at_checked("Bearer ".len())
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer "))
+        .map(|(_, token)| token.trim_start_matches(' '))
+        .filter(|token| !token.is_empty())
+        .ok_or(Error::BadClientMessage("expected a bearer token"))?;
+
+    Ok(Some(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bearer_token() {
+        let token = parse_client_response("n,,\x01auth=Bearer abc.def.ghi\x01\x01").unwrap();
+        assert_eq!(token, Some("abc.def.ghi"));
+
+        let token =
+            parse_client_response("y,,\x01host=localhost\x01auth=bearer  abc\x01\x01").unwrap();
+        assert_eq!(token, Some("abc"));
+
+        // discovery request
+        let token = parse_client_response("n,,\x01auth=\x01\x01").unwrap();
+        assert_eq!(token, None);
+    }
+
+    #[test]
+    fn parse_bearer_token_errors() {
+        for input in [
+            "",
+            "n,,",
+            "n,,\x01\x01",
+            "n,,\x01auth=Bearer abc\x01",
+            "n,,auth=Bearer abc\x01\x01",
+            "n,a=user,\x01auth=Bearer abc\x01\x01",
+            "p=tls-server-end-point,,\x01auth=Bearer abc\x01\x01",
+            "n,,\x01auth=Basic abc\x01\x01",
+            "n,,\x01auth=Bearer \x01\x01",
+            "n,,\x01auth\x01\x01",
+        ] {
+            assert!(parse_client_response(input).is_err(), "{input:?}");
+        }
+    }
+}
@@ -30,9 +30,9 @@ pub async fn authenticate<S, F, M>(
     F: FnOnce(&str) -> super::Result<M>,
     M: Mechanism,
 {
-    let (mut mechanism, mut input) = {
+    let step = {
         // pause the timer while we communicate with the client
-        let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
+        let paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
 
         // Initial client message contains the chosen auth method's name.
         let msg = stream.read_password_message().await?;
@@ -40,18 +40,35 @@ pub async fn authenticate<S, F, M>(
         let sasl = super::FirstMessage::parse(msg)
             .ok_or(super::Error::BadClientMessage("bad sasl message"))?;
 
-        (mechanism(sasl.method)?, sasl.message)
+        let mechanism = mechanism(sasl.method)?;
+        drop(paused);
+
+        mechanism.exchange(sasl.message)
     };
 
-    loop {
-        match mechanism.exchange(input) {
-            Ok(Step::Continue(moved_mechanism, reply)) => {
-                mechanism = moved_mechanism;
+    exchange(ctx, stream, step).await
+}
 
+/// Complete the SASL exchange, starting from the mechanism's reply to the
+/// initial client message.
+pub(crate) async fn exchange<S, M>(
+    ctx: &RequestContext,
+    stream: &mut PqStream<S>,
+    mut step: super::Result<Step<M, M::Output>>,
+) -> super::Result<Outcome<M::Output>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    M: Mechanism,
+{
+    loop {
+        let mechanism = match step {
+            Ok(Step::Continue(mechanism, reply)) => {
                 // write reply
                 let sasl_msg = BeAuthenticationSaslMessage::Continue(reply.as_bytes());
                 stream.write_message(BeMessage::AuthenticationSasl(sasl_msg));
                 drop(reply);
+
+                mechanism
             }
             Ok(Step::Success(result, reply)) => {
                 // write reply
@@ -60,15 +77,15 @@ pub async fn authenticate<S, F, M>(
                 stream.write_message(BeMessage::AuthenticationOk);
 
                 // exit with success
-                break Ok(Outcome::Success(result));
+                return Ok(Outcome::Success(result));
             }
             // exit with failure
-            Ok(Step::Failure(reason)) => break Ok(Outcome::Failure(reason)),
+            Ok(Step::Failure(reason)) => return Ok(Outcome::Failure(reason)),
             Err(error) => {
                 tracing::info!(?error, "error during SASL exchange");
                 return Err(error);
             }
-        }
+        };
 
         // pause the timer while we communicate with the client
         let _paused = ctx.latency_timer_pause(crate::metrics::Waiting::Client);
@@ -76,7 +93,9 @@ pub async fn authenticate<S, F, M>(
         // get next input
         stream.flush().await?;
         let msg = stream.read_password_message().await?;
-        input = std::str::from_utf8(msg)
+        let input = std::str::from_utf8(msg)
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad encoding"))?;
+
+        step = mechanism.exchange(input);
     }
 }
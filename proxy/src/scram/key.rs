@@ -46,6 +46,11 @@ pub(crate) fn sha256(&self) -> Self {
         }
     }
 
+    /// Parse a base64 encoded key.
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        super::base64_decode_array(input).map(Self::from)
+    }
+
     pub(crate) fn as_bytes(&self) -> [u8; SCRAM_KEY_LEN] {
         self.bytes
     }
@@ -15,6 +15,7 @@
 use super::conn_pool_lib::{Client, ConnInfo, EndpointConnPool, GlobalConnPool};
 use super::http_conn_pool::{self, HttpConnPool, LocalProxyClient, poll_http2_client};
 use super::local_conn_pool::{self, EXT_NAME, EXT_SCHEMA, EXT_VERSION, LocalConnPool};
+use super::txn_session::TxnSessions;
 use crate::auth::backend::local::StaticAuthRules;
 use crate::auth::backend::{ComputeCredentials, ComputeUserInfo};
 use crate::auth::{self, AuthError};
@@ -30,7 +31,7 @@
 use crate::intern::{EndpointIdInt, RoleNameInt};
 use crate::pqproto::StartupMessageParams;
 use crate::proxy::{connect_auth, connect_compute};
-use crate::rate_limiter::EndpointRateLimiter;
+use crate::rate_limiter::{EndpointRateLimiter, SessionGuard, SessionLimitError};
 use crate::types::{EndpointId, LOCAL_PROXY_SUFFIX};
 
 pub(crate) struct PoolingBackend {
@@ -39,6 +40,7 @@ pub(crate) struct PoolingBackend {
     pub(crate) local_pool: Arc<LocalConnPool<postgres_client::Client>>,
     pub(crate) pool:
         Arc<GlobalConnPool<postgres_client::Client, EndpointConnPool<postgres_client::Client>>>,
+    pub(crate) txn_sessions: Arc<TxnSessions>,
 
     pub(crate) config: &'static ProxyConfig,
     pub(crate) auth_backend: &'static crate::auth::Backend<'static, ()>,
@@ -102,6 +104,26 @@ pub(crate) async fn authenticate_with_password(
         })
     }
 
+    /// Count the request against the session limits of the endpoint, for as
+    /// long as the returned guard is held. Also picks up the audit log mode of
+    /// the endpoint.
+    pub(crate) async fn acquire_session(
+        &self,
+        ctx: &RequestContext,
+        user_info: &ComputeUserInfo,
+    ) -> Result<SessionGuard, HttpConnError> {
+        let backend = self.auth_backend.as_ref().map(|()| user_info.clone());
+        let access_control = backend.get_endpoint_access_control(ctx).await?;
+        ctx.set_audit_log(access_control.audit_log);
+        let session = access_control.acquire_session(
+            ctx,
+            &user_info.endpoint,
+            &user_info.user,
+            &self.config.session_limiter,
+        )?;
+        Ok(session)
+    }
+
     pub(crate) async fn authenticate_with_jwt(
         &self,
         ctx: &RequestContext,
@@ -436,6 +458,8 @@ pub(crate) enum HttpConnError {
     WakeCompute(#[from] WakeComputeError),
     #[error("error acquiring resource permit: {0}")]
     TooManyConnectionAttempts(#[from] ApiLockError),
+    #[error("{0}")]
+    SessionLimit(#[from] SessionLimitError),
 }
 
 impl From<connect_auth::AuthError> for HttpConnError {
@@ -475,6 +499,7 @@ fn get_error_kind(&self) -> ErrorKind {
             HttpConnError::AuthError(a) => a.get_error_kind(),
             HttpConnError::WakeCompute(w) => w.get_error_kind(),
             HttpConnError::TooManyConnectionAttempts(w) => w.get_error_kind(),
+            HttpConnError::SessionLimit(s) => s.get_error_kind(),
         }
     }
 }
@@ -494,6 +519,7 @@ fn to_string_client(&self) -> String {
             HttpConnError::TooManyConnectionAttempts(_) => {
                 "Failed to acquire permit to connect to the database. Too many database connection attempts are currently ongoing.".to_owned()
             }
+            HttpConnError::SessionLimit(s) => s.to_string_client(),
         }
     }
 }
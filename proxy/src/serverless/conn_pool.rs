@@ -222,6 +222,8 @@ async fn test_pool() {
         let _ = env_logger::try_init();
         let config = Box::leak(Box::new(crate::config::HttpConfig {
             accept_websockets: false,
+            websocket_compression: false,
+            websocket_multiplexing: false,
             pool_options: GlobalConnPoolOptions {
                 max_conns_per_endpoint: 2,
                 gc_epoch: Duration::from_secs(1),
@@ -234,6 +236,7 @@ async fn test_pool() {
             client_conn_threshold: u64::MAX,
             max_request_size_bytes: usize::MAX,
             max_response_size_bytes: usize::MAX,
+            txn_session_idle_timeout: Duration::from_secs(1),
         }));
         let pool = GlobalConnPool::new(config);
         let conn_info = ConnInfo {
@@ -0,0 +1,864 @@
+//! GraphQL queries on top of the REST API.
+//!
+//! Only the part of GraphQL needed to read tables is supported: queries made
+//! of fields with arguments and selection sets, and variables. Fragments,
+//! directives, mutations and introspection are refused.
+//!
+//! Every top level field of the query is turned into a PostgREST request:
+//!
+//! ```graphql
+//! query ($min: Int) {
+//!   films(filter: {year: {gte: $min}}, order: {title: asc}, limit: 10) {
+//!     title
+//!     director: directors(filter: {country: {eq: "FR"}}) { name }
+//!   }
+//! }
+//! ```
+//!
+//! becomes `GET /films?select=title,director:directors(name)&year=gte.<min>&order=title.asc&limit=10&director.country=eq.FR`,
+//! which then goes through the same SQL generation as the REST API, so RLS
+//! and permissions apply the same way.
+
+use serde_json::{Map, Value as JsonValue};
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub(crate) enum GraphqlError {
+    #[error("syntax error at {pos}: {message}")]
+    Syntax { pos: usize, message: String },
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("{0} are not supported")]
+    Unsupported(&'static str),
+    #[error("operation {0} not found")]
+    UnknownOperation(String),
+    #[error("operation name is required when the document has several operations")]
+    OperationNameRequired,
+    #[error("variable ${0} is not provided")]
+    MissingVariable(String),
+    #[error("field {field}: {message}")]
+    InvalidField { field: String, message: String },
+}
+
+/// A field of a selection set.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Field {
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Value)>,
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    /// Key of the field in the response.
+    pub(crate) fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Variable(String),
+    Null,
+    Bool(bool),
+    /// Ints and floats, as written.
+    Number(String),
+    String(String),
+    Enum(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[derive(Debug, PartialEq)]
+struct Operation {
+    name: Option<String>,
+    /// Variable names and their default values.
+    variables: Vec<(String, Option<Value>)>,
+    selection: Vec<Field>,
+}
+
+/// Parse the document and return the top level fields of the operation to
+/// run, with variables substituted.
+pub(crate) fn parse_query(
+    document: &str,
+    operation_name: Option<&str>,
+    variables: &Map<String, JsonValue>,
+) -> Result<Vec<Field>, GraphqlError> {
+    let mut parser = Parser::new(document);
+    let mut operations = vec![];
+    while parser.peek()?.is_some() {
+        operations.push(parser.operation()?);
+    }
+
+    let operation = match operation_name {
+        Some(name) => operations
+            .into_iter()
+            .find(|op| op.name.as_deref() == Some(name))
+            .ok_or_else(|| GraphqlError::UnknownOperation(name.to_owned()))?,
+        None if operations.len() == 1 => operations.pop().expect("checked the length"),
+        None if operations.is_empty() => {
+            return Err(GraphqlError::Syntax {
+                pos: 0,
+                message: "document has no operations".to_owned(),
+            });
+        }
+        None => return Err(GraphqlError::OperationNameRequired),
+    };
+
+    let mut resolved = Map::new();
+    for (name, default) in operation.variables {
+        let value = match (variables.get(&name), default) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default)) => to_json(default, &Map::new())?,
+            (None, None) => JsonValue::Null,
+        };
+        resolved.insert(name, value);
+    }
+
+    operation
+        .selection
+        .into_iter()
+        .map(|field| substitute(field, &resolved))
+        .collect()
+}
+
+fn substitute(field: Field, variables: &Map<String, JsonValue>) -> Result<Field, GraphqlError> {
+    let arguments = field
+        .arguments
+        .into_iter()
+        .map(|(name, value)| Ok((name, from_json(to_json(value, variables)?))))
+        .collect::<Result<_, GraphqlError>>()?;
+    let selection = field
+        .selection
+        .into_iter()
+        .map(|field| substitute(field, variables))
+        .collect::<Result<_, _>>()?;
+    Ok(Field {
+        arguments,
+        selection,
+        ..field
+    })
+}
+
+fn to_json(value: Value, variables: &Map<String, JsonValue>) -> Result<JsonValue, GraphqlError> {
+    Ok(match value {
+        Value::Variable(name) => variables
+            .get(&name)
+            .cloned()
+            .ok_or(GraphqlError::MissingVariable(name))?,
+        Value::Null => JsonValue::Null,
+        Value::Bool(b) => JsonValue::Bool(b),
+        Value::Number(n) => serde_json::from_str(&n).map_err(|_| GraphqlError::Syntax {
+            pos: 0,
+            message: format!("invalid number {n}"),
+        })?,
+        Value::String(s) | Value::Enum(s) => JsonValue::String(s),
+        Value::List(items) => JsonValue::Array(
+            items
+                .into_iter()
+                .map(|v| to_json(v, variables))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => JsonValue::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| Ok((k, to_json(v, variables)?)))
+                .collect::<Result<_, GraphqlError>>()?,
+        ),
+    })
+}
+
+fn from_json(value: JsonValue) -> Value {
+    match value {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => Value::Bool(b),
+        JsonValue::Number(n) => Value::Number(n.to_string()),
+        JsonValue::String(s) => Value::String(s),
+        JsonValue::Array(items) => Value::List(items.into_iter().map(from_json).collect()),
+        JsonValue::Object(fields) => {
+            Value::Object(fields.into_iter().map(|(k, v)| (k, from_json(v))).collect())
+        }
+    }
+}
+
+/// PostgREST request for a top level field: the table and query parameters.
+#[derive(Debug, PartialEq)]
+pub(crate) struct TableQuery {
+    pub table: String,
+    pub params: Vec<(String, String)>,
+}
+
+/// Translate a top level field into a PostgREST request.
+pub(crate) fn to_table_query(field: &Field) -> Result<TableQuery, GraphqlError> {
+    if field.name.starts_with("__") {
+        return Err(GraphqlError::Unsupported("introspection queries"));
+    }
+    if field.selection.is_empty() {
+        return Err(invalid(field, "selection set is required"));
+    }
+
+    let mut params = vec![("select".to_owned(), select_list(&field.selection)?)];
+    arguments_to_params(field, "", &mut params)?;
+    embedded_params(&field.selection, "", &mut params)?;
+    Ok(TableQuery {
+        table: field.name.clone(),
+        params,
+    })
+}
+
+fn select_list(selection: &[Field]) -> Result<String, GraphqlError> {
+    let mut items = Vec::with_capacity(selection.len());
+    for field in selection {
+        if field.name.starts_with("__") {
+            return Err(GraphqlError::Unsupported("introspection queries"));
+        }
+        let mut item = match &field.alias {
+            Some(alias) if *alias != field.name => format!("{alias}:{}", field.name),
+            _ => field.name.clone(),
+        };
+        if !field.selection.is_empty() {
+            item = format!("{item}({})", select_list(&field.selection)?);
+        } else if !field.arguments.is_empty() {
+            return Err(invalid(field, "only embedded tables accept arguments"));
+        }
+        items.push(item);
+    }
+    Ok(items.join(","))
+}
+
+/// Arguments of embedded tables are passed with their path as prefix, e.g.
+/// `directors.limit=1`.
+fn embedded_params(
+    selection: &[Field],
+    prefix: &str,
+    params: &mut Vec<(String, String)>,
+) -> Result<(), GraphqlError> {
+    for field in selection.iter().filter(|f| !f.selection.is_empty()) {
+        let prefix = format!("{prefix}{}.", field.response_key());
+        arguments_to_params(field, &prefix, params)?;
+        embedded_params(&field.selection, &prefix, params)?;
+    }
+    Ok(())
+}
+
+fn arguments_to_params(
+    field: &Field,
+    prefix: &str,
+    params: &mut Vec<(String, String)>,
+) -> Result<(), GraphqlError> {
+    for (name, value) in &field.arguments {
+        match (name.as_str(), value) {
+            (_, Value::Null) => {}
+            ("limit" | "offset", Value::Number(n)) if n.parse::<u64>().is_ok() => {
+                params.push((format!("{prefix}{name}"), n.clone()));
+            }
+            ("limit" | "offset", _) => {
+                return Err(invalid(
+                    field,
+                    &format!("{name} must be a non-negative integer"),
+                ));
+            }
+            ("order", value) => {
+                params.push((format!("{prefix}order"), order(field, value)?));
+            }
+            ("filter", Value::Object(columns)) => {
+                for (column, condition) in columns {
+                    params.push((format!("{prefix}{column}"), filter(field, condition)?));
+                }
+            }
+            ("filter", _) => return Err(invalid(field, "filter must be an object")),
+            (name, _) => return Err(invalid(field, &format!("unknown argument {name}"))),
+        }
+    }
+    Ok(())
+}
+
+/// `{title: asc}` or `[{year: desc_nulls_last}, {title: asc}]` into
+/// `year.desc.nullslast,title.asc`.
+fn order(field: &Field, value: &Value) -> Result<String, GraphqlError> {
+    let objects = match value {
+        Value::List(items) => items.iter().collect(),
+        value => vec![value],
+    };
+    let mut terms = vec![];
+    for object in objects {
+        let Value::Object(columns) = object else {
+            return Err(invalid(
+                field,
+                "order must be an object or a list of objects",
+            ));
+        };
+        for (column, direction) in columns {
+            let (Value::Enum(direction) | Value::String(direction)) = direction else {
+                return Err(invalid(field, "order direction must be an enum value"));
+            };
+            let direction = match direction.as_str() {
+                "asc" => "asc",
+                "desc" => "desc",
+                "asc_nulls_first" => "asc.nullsfirst",
+                "asc_nulls_last" => "asc.nullslast",
+                "desc_nulls_first" => "desc.nullsfirst",
+                "desc_nulls_last" => "desc.nullslast",
+                other => {
+                    return Err(invalid(field, &format!("unknown order direction {other}")));
+                }
+            };
+            terms.push(format!("{column}.{direction}"));
+        }
+    }
+    Ok(terms.join(","))
+}
+
+/// `{gte: 1}` into `gte.1`. Several operators on the same column are not
+/// supported, as PostgREST takes a single filter per query parameter.
+fn filter(field: &Field, condition: &Value) -> Result<String, GraphqlError> {
+    let Value::Object(ops) = condition else {
+        return Err(invalid(field, "column filter must be an object"));
+    };
+    let [(op, operand)] = ops.as_slice() else {
+        return Err(invalid(
+            field,
+            "column filter must have exactly one operator",
+        ));
+    };
+    match op.as_str() {
+        "eq" | "neq" | "gt" | "gte" | "lt" | "lte" | "like" | "ilike" | "match" | "imatch" => {
+            Ok(format!("{op}.{}", scalar(field, operand)?))
+        }
+        "is" => match operand {
+            Value::Null => Ok("is.null".to_owned()),
+            Value::Bool(b) => Ok(format!("is.{b}")),
+            _ => Err(invalid(field, "is accepts only null, true or false")),
+        },
+        "in" => {
+            let Value::List(items) = operand else {
+                return Err(invalid(field, "in accepts only lists"));
+            };
+            let items = items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(quote(s)),
+                    item => scalar(field, item),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("in.({})", items.join(",")))
+        }
+        op => Err(invalid(field, &format!("unknown filter operator {op}"))),
+    }
+}
+
+fn scalar(field: &Field, value: &Value) -> Result<String, GraphqlError> {
+    match value {
+        Value::Null => Ok("null".to_owned()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.clone()),
+        Value::String(s) | Value::Enum(s) => Ok(s.clone()),
+        _ => Err(invalid(field, "filter operand must be a scalar")),
+    }
+}
+
+/// Quote a string in a PostgREST list, so that commas and parentheses in it
+/// are not taken for separators.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn invalid(field: &Field, message: &str) -> GraphqlError {
+    GraphqlError::InvalidField {
+        field: field.response_key().to_owned(),
+        message: message.to_owned(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Punct(char),
+    Spread,
+    Name(&'a str),
+    Number(&'a str),
+    String(String),
+}
+
+/// How deep selection sets, values and types may be nested, so that a
+/// small document can't exhaust the stack of the parser.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    peeked: Option<(usize, Token<'a>)>,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            peeked: None,
+            depth: 0,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> GraphqlError {
+        GraphqlError::Syntax {
+            pos: self.pos,
+            message: message.into(),
+        }
+    }
+
+    /// Run `f` one nesting level deeper.
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, GraphqlError>,
+    ) -> Result<T, GraphqlError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!("document is nested deeper than {MAX_DEPTH} levels")));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token<'a>>, GraphqlError> {
+        if self.peeked.is_none() {
+            let start = self.pos;
+            if let Some(token) = self.lex()? {
+                self.peeked = Some((start, token));
+            }
+        }
+        Ok(self.peeked.as_ref().map(|(_, t)| t))
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, GraphqlError> {
+        self.peek()?;
+        match self.peeked.take() {
+            Some((_, token)) => Ok(token),
+            None => Err(self.error("unexpected end of document")),
+        }
+    }
+
+    fn eat(&mut self, punct: char) -> Result<bool, GraphqlError> {
+        if self.peek()? == Some(&Token::Punct(punct)) {
+            self.peeked = None;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), GraphqlError> {
+        match self.next()? {
+            Token::Punct(p) if p == punct => Ok(()),
+            token => Err(self.error(format!("expected {punct}, got {token:?}"))),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, GraphqlError> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(self.error(format!("expected a name, got {token:?}"))),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Operation, GraphqlError> {
+        let mut operation = Operation {
+            name: None,
+            variables: vec![],
+            selection: vec![],
+        };
+        match self.peek()? {
+            Some(Token::Punct('{')) => {}
+            Some(Token::Name("query")) => {
+                self.next()?;
+                if let Some(Token::Name(_)) = self.peek()? {
+                    operation.name = Some(self.name()?.to_owned());
+                }
+                if self.eat('(')? {
+                    while !self.eat(')')? {
+                        operation.variables.push(self.variable_definition()?);
+                    }
+                }
+                if let Some(Token::Punct('@')) = self.peek()? {
+                    return Err(GraphqlError::Unsupported("directives"));
+                }
+            }
+            Some(Token::Name("mutation")) => return Err(GraphqlError::Unsupported("mutations")),
+            Some(Token::Name("subscription")) => {
+                return Err(GraphqlError::Unsupported("subscriptions"));
+            }
+            Some(Token::Name("fragment")) => return Err(GraphqlError::Unsupported("fragments")),
+            _ => return Err(self.error("expected an operation")),
+        }
+        operation.selection = self.selection_set()?;
+        Ok(operation)
+    }
+
+    fn variable_definition(&mut self) -> Result<(String, Option<Value>), GraphqlError> {
+        self.expect('$')?;
+        let name = self.name()?.to_owned();
+        self.expect(':')?;
+        // types are not checked, Postgres does that
+        self.skip_type()?;
+        let default = if self.eat('=')? {
+            Some(self.value(true)?)
+        } else {
+            None
+        };
+        Ok((name, default))
+    }
+
+    fn skip_type(&mut self) -> Result<(), GraphqlError> {
+        if self.eat('[')? {
+            self.nested(Self::skip_type)?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.eat('!')?;
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, GraphqlError> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        while !self.eat('}')? {
+            fields.push(self.field()?);
+        }
+        if fields.is_empty() {
+            return Err(self.error("selection set is empty"));
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, GraphqlError> {
+        if let Some(Token::Spread) = self.peek()? {
+            return Err(GraphqlError::Unsupported("fragments"));
+        }
+        let mut name = self.name()?.to_owned();
+        let mut alias = None;
+        if self.eat(':')? {
+            alias = Some(name);
+            name = self.name()?.to_owned();
+        }
+        let mut arguments = vec![];
+        if self.eat('(')? {
+            while !self.eat(')')? {
+                let name = self.name()?.to_owned();
+                self.expect(':')?;
+                arguments.push((name, self.value(false)?));
+            }
+        }
+        if let Some(Token::Punct('@')) = self.peek()? {
+            return Err(GraphqlError::Unsupported("directives"));
+        }
+        let selection = if let Some(Token::Punct('{')) = self.peek()? {
+            self.nested(Self::selection_set)?
+        } else {
+            vec![]
+        };
+        Ok(Field {
+            alias,
+            name,
+            arguments,
+            selection,
+        })
+    }
+
+    fn value(&mut self, constant: bool) -> Result<Value, GraphqlError> {
+        match self.next()? {
+            Token::Punct('$') if !constant => Ok(Value::Variable(self.name()?.to_owned())),
+            Token::Punct('[') => {
+                let mut items = vec![];
+                while !self.eat(']')? {
+                    items.push(self.nested(|p| p.value(constant))?);
+                }
+                Ok(Value::List(items))
+            }
+            Token::Punct('{') => {
+                let mut fields = vec![];
+                while !self.eat('}')? {
+                    let name = self.name()?.to_owned();
+                    self.expect(':')?;
+                    fields.push((name, self.nested(|p| p.value(constant))?));
+                }
+                Ok(Value::Object(fields))
+            }
+            Token::Name("null") => Ok(Value::Null),
+            Token::Name("true") => Ok(Value::Bool(true)),
+            Token::Name("false") => Ok(Value::Bool(false)),
+            Token::Name(name) => Ok(Value::Enum(name.to_owned())),
+            Token::Number(n) => Ok(Value::Number(n.to_owned())),
+            Token::String(s) => Ok(Value::String(s)),
+            token => Err(self.error(format!("expected a value, got {token:?}"))),
+        }
+    }
+
+    /// Read the next token, skipping whitespace, commas and comments.
+    fn lex(&mut self) -> Result<Option<Token<'a>>, GraphqlError> {
+        let bytes = self.src.as_bytes();
+        loop {
+            match bytes.get(self.pos) {
+                None => return Ok(None),
+                Some(b' ' | b'\t' | b'\n' | b'\r' | b',') => self.pos += 1,
+                // byte order mark
+                Some(0xef) if self.src[self.pos..].starts_with('\u{feff}') => self.pos += 3,
+                Some(b'#') => {
+                    while !matches!(bytes.get(self.pos), None | Some(b'\n' | b'\r')) {
+                        self.pos += 1;
+                    }
+                }
+                Some(_) => break,
+            }
+        }
+
+        let start = self.pos;
+        let c = bytes[start];
+        let token = match c {
+            b'!' | b'$' | b'(' | b')' | b':' | b'=' | b'@' | b'[' | b']' | b'{' | b'|' | b'}' => {
+                self.pos += 1;
+                Token::Punct(c as char)
+            }
+            b'.' if self.src[start..].starts_with("...") => {
+                self.pos += 3;
+                Token::Spread
+            }
+            b'_' | b'a'..=b'z' | b'A'..=b'Z' => {
+                while matches!(
+                    bytes.get(self.pos),
+                    Some(b'_' | b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9')
+                ) {
+                    self.pos += 1;
+                }
+                Token::Name(&self.src[start..self.pos])
+            }
+            b'-' | b'0'..=b'9' => {
+                self.pos += 1;
+                while matches!(
+                    bytes.get(self.pos),
+                    Some(b'0'..=b'9' | b'.' | b'e' | b'E' | b'+' | b'-')
+                ) {
+                    self.pos += 1;
+                }
+                Token::Number(&self.src[start..self.pos])
+            }
+            b'"' if self.src[start..].starts_with("\"\"\"") => {
+                self.pos += 3;
+                let Some(end) = self.src[self.pos..].find("\"\"\"") else {
+                    return Err(self.error("unterminated block string"));
+                };
+                let s = self.src[self.pos..self.pos + end].replace("\\\"\"\"", "\"\"\"");
+                self.pos += end + 3;
+                Token::String(s)
+            }
+            b'"' => {
+                self.pos += 1;
+                Token::String(self.string()?)
+            }
+            _ => return Err(self.error(format!("unexpected character {:?}", c as char))),
+        };
+        Ok(Some(token))
+    }
+
+    fn string(&mut self) -> Result<String, GraphqlError> {
+        let mut s = String::new();
+        let mut chars = self.src[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(s);
+                }
+                '\n' | '\r' => break,
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'u')) => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn query(document: &str, variables: JsonValue) -> Result<Vec<TableQuery>, GraphqlError> {
+        let JsonValue::Object(variables) = variables else {
+            panic!("variables must be an object");
+        };
+        parse_query(document, None, &variables)?
+            .iter()
+            .map(to_table_query)
+            .collect()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn test_table_query() {
+        let queries = query(
+            r#"
+            # comment
+            query Films($min: Int = 1990, $country: String) {
+              films(filter: {year: {gte: $min}, genre: {in: ["drama", "film, noir"]}},
+                    order: [{year: desc_nulls_last}, {title: asc}], limit: 10) {
+                title
+                released: year
+                director: directors(filter: {country: {eq: $country}}, limit: 1) { name }
+              }
+              actors { name }
+            }"#,
+            json!({"country": "FR"}),
+        )
+        .unwrap();
+        assert_eq!(
+            queries,
+            vec![
+                TableQuery {
+                    table: "films".to_owned(),
+                    params: params(&[
+                        ("select", "title,released:year,director:directors(name)"),
+                        ("year", "gte.1990"),
+                        ("genre", r#"in.("drama","film, noir")"#),
+                        ("order", "year.desc.nullslast,title.asc"),
+                        ("limit", "10"),
+                        ("director.country", "eq.FR"),
+                        ("director.limit", "1"),
+                    ]),
+                },
+                TableQuery {
+                    table: "actors".to_owned(),
+                    params: params(&[("select", "name")]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operation_selection() {
+        let document = "query A { a { x } } query B { b { y } }";
+        let variables = Map::new();
+        assert_eq!(
+            parse_query(document, None, &variables),
+            Err(GraphqlError::OperationNameRequired)
+        );
+        let fields = parse_query(document, Some("B"), &variables).unwrap();
+        assert_eq!(fields[0].name, "b");
+        assert_eq!(
+            parse_query(document, Some("C"), &variables),
+            Err(GraphqlError::UnknownOperation("C".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            query("mutation { a { x } }", json!({})),
+            Err(GraphqlError::Unsupported("mutations"))
+        );
+        assert_eq!(
+            query("{ a { ...F } }", json!({})),
+            Err(GraphqlError::Unsupported("fragments"))
+        );
+        assert_eq!(
+            query("{ __schema { types { name } } }", json!({})),
+            Err(GraphqlError::Unsupported("introspection queries"))
+        );
+        assert_eq!(
+            query("query ($x: Int) { a(limit: $y) { x } }", json!({})),
+            Err(GraphqlError::MissingVariable("y".to_owned()))
+        );
+        assert!(matches!(
+            query("{ a(where: {x: 1}) { x } }", json!({})),
+            Err(GraphqlError::InvalidField { .. })
+        ));
+        assert!(matches!(
+            query("{ a { x ", json!({})),
+            Err(GraphqlError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let selection = |depth: usize| {
+            let document = "{ a ".to_owned() + &"{ b ".repeat(depth) + &"}".repeat(depth + 1);
+            parse_query(&document, None, &Map::new())
+        };
+        assert!(selection(MAX_DEPTH).is_ok());
+        assert!(matches!(
+            selection(MAX_DEPTH + 1),
+            Err(GraphqlError::Syntax { .. })
+        ));
+
+        let list = |depth: usize| {
+            let document = format!(
+                "{{ a(x: {}1{}) {{ b }} }}",
+                "[".repeat(depth),
+                "]".repeat(depth)
+            );
+            parse_query(&document, None, &Map::new())
+        };
+        assert!(list(MAX_DEPTH).is_ok());
+        assert!(matches!(
+            list(MAX_DEPTH + 1),
+            Err(GraphqlError::Syntax { .. })
+        ));
+
+        // deep enough to overflow the stack without the limit
+        let document = "{ a(x: ".to_owned() + &"{y: ".repeat(100_000);
+        assert!(matches!(
+            parse_query(&document, None, &Map::new()),
+            Err(GraphqlError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn test_strings() {
+        let fields = parse_query(
+            r#"{ a(filter: {x: {eq: "q\"\\\u00e9\n"}, y: {eq: """bl"ock"""}}) { x } }"#,
+            None,
+            &Map::new(),
+        )
+        .unwrap();
+        let Value::Object(filter) = &fields[0].arguments[0].1 else {
+            panic!("filter must be an object");
+        };
+        assert_eq!(
+            filter[0].1,
+            Value::Object(vec![(
+                "eq".to_owned(),
+                Value::String("q\"\\é\n".to_owned())
+            )])
+        );
+        assert_eq!(
+            filter[1].1,
+            Value::Object(vec![("eq".to_owned(), Value::String("bl\"ock".to_owned()))])
+        );
+    }
+}
@@ -28,11 +28,15 @@
 pub(super) static CONN_STRING: HeaderName = HeaderName::from_static("neon-connection-string");
 pub(super) static RAW_TEXT_OUTPUT: HeaderName = HeaderName::from_static("neon-raw-text-output");
 pub(super) static ARRAY_MODE: HeaderName = HeaderName::from_static("neon-array-mode");
+pub(super) static TYPED_MODE: HeaderName = HeaderName::from_static("neon-typed-mode");
+pub(super) static BINARY_OUTPUT: HeaderName = HeaderName::from_static("neon-binary-output");
+pub(super) static STREAM_OUTPUT: HeaderName = HeaderName::from_static("neon-stream-output");
 pub(super) static ALLOW_POOL: HeaderName = HeaderName::from_static("neon-pool-opt-in");
 pub(super) static TXN_ISOLATION_LEVEL: HeaderName =
     HeaderName::from_static("neon-batch-isolation-level");
 pub(super) static TXN_READ_ONLY: HeaderName = HeaderName::from_static("neon-batch-read-only");
 pub(super) static TXN_DEFERRABLE: HeaderName = HeaderName::from_static("neon-batch-deferrable");
+pub(super) static TXN_SESSION: HeaderName = HeaderName::from_static("neon-txn-session");
 
 pub(crate) fn uuid_to_header_value(id: Uuid) -> HeaderValue {
     let mut uuid = [0; uuid::fmt::Hyphenated::LENGTH];
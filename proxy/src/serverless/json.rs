@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use base64::Engine as _;
+use base64::prelude::BASE64_STANDARD;
 use json::{ListSer, ObjectSer, ValueSer};
 use postgres_client::Row;
-use postgres_client::types::{Kind, Type};
+use postgres_client::types::{Kind, Oid, Type};
 use serde_json::Value;
 
 //
@@ -59,6 +64,113 @@ fn json_array_to_pg_array(value: &Value) -> Option<String> {
     }
 }
 
+/// Parameter type declared by the client in typed mode, either by OID or by
+/// name of a builtin type.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ParamType {
+    Oid(Oid),
+    Name(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TypedParamsError {
+    #[error("unknown parameter type {0}")]
+    UnknownType(String),
+    #[error("parameter ${0} is declared as bytea, but is not valid base64")]
+    InvalidBytea(usize),
+    #[error("parameter types can only be declared in typed mode")]
+    NotTypedMode,
+}
+
+/// SQL names of builtin types which differ from their `pg_type` names.
+const TYPE_ALIASES: &[(&str, Type)] = &[
+    ("boolean", Type::BOOL),
+    ("smallint", Type::INT2),
+    ("integer", Type::INT4),
+    ("int", Type::INT4),
+    ("bigint", Type::INT8),
+    ("real", Type::FLOAT4),
+    ("double precision", Type::FLOAT8),
+    ("decimal", Type::NUMERIC),
+    ("character varying", Type::VARCHAR),
+    ("timestamp without time zone", Type::TIMESTAMP),
+    ("timestamp with time zone", Type::TIMESTAMPTZ),
+    ("time without time zone", Type::TIME),
+    ("time with time zone", Type::TIMETZ),
+];
+
+/// Builtin types by name. Arrays are named as in `pg_type`, e.g. `_int4`.
+static TYPES_BY_NAME: LazyLock<HashMap<String, Type>> = LazyLock::new(|| {
+    let mut types: HashMap<String, Type> = (0..=Oid::from(u16::MAX))
+        .filter_map(Type::from_oid)
+        .map(|ty| (ty.name().to_owned(), ty))
+        .collect();
+    for (alias, ty) in TYPE_ALIASES {
+        types.insert((*alias).to_owned(), ty.clone());
+    }
+    types
+});
+
+/// Resolve declared parameter types to OIDs to be sent with the query. Types
+/// can be given by OID, which works for custom types as well, or by name of a
+/// builtin type. `int4[]` is accepted for `_int4`.
+pub(crate) fn param_types_to_oids(types: &[ParamType]) -> Result<Vec<Oid>, TypedParamsError> {
+    types
+        .iter()
+        .map(|ty| match ty {
+            ParamType::Oid(oid) => Ok(*oid),
+            ParamType::Name(name) => {
+                let name = name.trim().to_ascii_lowercase();
+                let lookup = match name.strip_suffix("[]") {
+                    Some(elem) => TYPES_BY_NAME.get(elem.trim_end()).and_then(array_type),
+                    None => TYPES_BY_NAME.get(&name).cloned(),
+                };
+                lookup
+                    .map(|ty| ty.oid())
+                    .ok_or(TypedParamsError::UnknownType(name))
+            }
+        })
+        .collect()
+}
+
+fn array_type(elem: &Type) -> Option<Type> {
+    TYPES_BY_NAME.get(&format!("_{}", elem.name())).cloned()
+}
+
+/// In typed mode bytea parameters are passed as base64. Convert them to the
+/// hex text format, so that they can be sent as text like other parameters.
+pub(crate) fn typed_params_to_pg_text(
+    param_types: &[Oid],
+    params: &mut [Option<String>],
+) -> Result<(), TypedParamsError> {
+    for (i, (oid, param)) in param_types.iter().zip(params.iter_mut()).enumerate() {
+        if *oid != Type::BYTEA.oid() {
+            continue;
+        }
+        if let Some(param) = param {
+            let bytes = BASE64_STANDARD
+                .decode(param.as_bytes())
+                .map_err(|_| TypedParamsError::InvalidBytea(i + 1))?;
+            *param = format!("\\x{}", hex::encode(bytes));
+        }
+    }
+    Ok(())
+}
+
+/// How column values are written to JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueFormat {
+    /// Values are converted to the closest JSON type.
+    Json,
+    /// Postgres text representation is returned as is.
+    RawText,
+    /// Like [`ValueFormat::Json`], but bytea is returned as base64.
+    Typed,
+    /// Postgres binary representation, base64 encoded.
+    Binary,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum JsonConversionError {
     #[error("internal error compute returned invalid data: {0}")]
@@ -69,6 +181,8 @@ pub(crate) enum JsonConversionError {
     ParseFloatError(#[from] std::num::ParseFloatError),
     #[error("parse json error: {0}")]
     ParseJsonError(#[from] serde_json::Error),
+    #[error("parse bytea error: {0}")]
+    ParseByteaError(#[from] hex::FromHexError),
     #[error("unbalanced array")]
     UnbalancedArray,
     #[error("unbalanced quoted string")]
@@ -97,12 +211,12 @@ fn finish(self) {
 }
 
 //
-// Convert postgres row with text-encoded values to JSON object
+// Convert postgres row to JSON object
 //
-pub(crate) fn pg_text_row_to_json(
+pub(crate) fn pg_row_to_json(
     output: ValueSer,
     row: &Row,
-    raw_output: bool,
+    format: ValueFormat,
     array_mode: bool,
 ) -> Result<(), JsonConversionError> {
     let mut entries = if array_mode {
@@ -112,13 +226,21 @@ pub(crate) fn pg_text_row_to_json(
     };
 
     for (i, column) in row.columns().iter().enumerate() {
-        let pg_value = row.as_text(i).map_err(JsonConversionError::AsTextError)?;
-
         let value = entries.key(column.name());
 
+        if format == ValueFormat::Binary {
+            match row.as_binary(i).map_err(JsonConversionError::AsTextError)? {
+                Some(v) => value.value(BASE64_STANDARD.encode(v)),
+                None => value.value(json::Null),
+            }
+            continue;
+        }
+
+        let pg_value = row.as_text(i).map_err(JsonConversionError::AsTextError)?;
+        let typed = format == ValueFormat::Typed;
         match pg_value {
-            Some(v) if raw_output => value.value(v),
-            Some(v) => pg_text_to_json(value, v, column.type_())?,
+            Some(v) if format == ValueFormat::RawText => value.value(v),
+            Some(v) => pg_text_to_json(value, v, column.type_(), typed)?,
             None => value.value(json::Null),
         }
     }
@@ -130,12 +252,17 @@ pub(crate) fn pg_text_row_to_json(
 //
 // Convert postgres text-encoded value to JSON value
 //
-fn pg_text_to_json(output: ValueSer, val: &str, pg_type: &Type) -> Result<(), JsonConversionError> {
+fn pg_text_to_json(
+    output: ValueSer,
+    val: &str,
+    pg_type: &Type,
+    typed: bool,
+) -> Result<(), JsonConversionError> {
     if let Kind::Array(elem_type) = pg_type.kind() {
         // todo: we should fetch this from postgres.
         let delimiter = ',';
 
-        json::value_as_list!(|output| pg_array_parse(output, val, elem_type, delimiter)?);
+        json::value_as_list!(|output| pg_array_parse(output, val, elem_type, delimiter, typed)?);
         return Ok(());
     }
 
@@ -158,6 +285,12 @@ fn pg_text_to_json(output: ValueSer, val: &str, pg_type: &Type) -> Result<(), Js
         }
         // we assume that the string value is valid json.
         Type::JSON | Type::JSONB => output.write_raw_json(val.as_bytes()),
+        // bytea is returned in the hex format, e.g. `\xdeadbeef`
+        Type::BYTEA if typed => {
+            let hex = val.strip_prefix("\\x").unwrap_or(val);
+            output.value(BASE64_STANDARD.encode(hex::decode(hex)?));
+        }
+        // int8 and numeric are strings too, so that they keep their precision
         _ => output.value(val),
     }
 
@@ -188,6 +321,7 @@ fn pg_array_parse(
     mut pg_array: &str,
     elem: &Type,
     delim: char,
+    typed: bool,
 ) -> Result<(), JsonConversionError> {
     // skip bounds decoration, eg:
     // `[1:1][-2:-1][3:5]={{{1,2,3},{4,5,6}}}`
@@ -202,7 +336,7 @@ fn pg_array_parse(
     // whitespace might preceed a `{`.
     let pg_array = pg_array.trim_start();
 
-    let rest = pg_array_parse_inner(elements, pg_array, elem, delim)?;
+    let rest = pg_array_parse_inner(elements, pg_array, elem, delim, typed)?;
     if !rest.is_empty() {
         return Err(JsonConversionError::UnbalancedArray);
     }
@@ -217,6 +351,7 @@ fn pg_array_parse_inner<'a>(
     mut pg_array: &'a str,
     elem: &Type,
     delim: char,
+    typed: bool,
 ) -> Result<&'a str, JsonConversionError> {
     // array should have a `{` prefix.
     pg_array = pg_array
@@ -227,7 +362,7 @@ fn pg_array_parse_inner<'a>(
 
     loop {
         let value = elements.entry();
-        pg_array = pg_array_parse_item(value, &mut q, pg_array, elem, delim)?;
+        pg_array = pg_array_parse_item(value, &mut q, pg_array, elem, delim, typed)?;
 
         // check for separator.
         if let Some(next) = pg_array.strip_prefix(delim) {
@@ -257,6 +392,7 @@ fn pg_array_parse_item<'a>(
     mut pg_array: &'a str,
     elem: &Type,
     delim: char,
+    typed: bool,
 ) -> Result<&'a str, JsonConversionError> {
     // We are trying to parse an array item.
     // This could be a new array, if this is a multi-dimentional array.
@@ -268,8 +404,9 @@ fn pg_array_parse_item<'a>(
 
     if pg_array.starts_with('{') {
         // nested array.
-        pg_array =
-            json::value_as_list!(|output| pg_array_parse_inner(output, pg_array, elem, delim))?;
+        pg_array = json::value_as_list!(|output| pg_array_parse_inner(
+            output, pg_array, elem, delim, typed
+        ))?;
         return Ok(pg_array);
     }
 
@@ -278,7 +415,7 @@ fn pg_array_parse_item<'a>(
         pg_array = pg_array_parse_quoted(quoted, pg_array)?;
 
         // we have un-escaped the string, parse it as pgtext.
-        pg_text_to_json(output, quoted, elem)?;
+        pg_text_to_json(output, quoted, elem, typed)?;
 
         return Ok(pg_array);
     }
@@ -299,7 +436,7 @@ fn pg_array_parse_item<'a>(
     if item == "NULL" {
         output.value(json::Null);
     } else {
-        pg_text_to_json(output, item, elem)?;
+        pg_text_to_json(output, item, elem, typed)?;
     }
 
     Ok(pg_array)
@@ -430,14 +567,87 @@ fn test_json_array_to_pg_array() {
         );
     }
 
+    #[test]
+    fn test_param_types_to_oids() {
+        let types: Vec<ParamType> =
+            serde_json::from_str(r#"[20, "int8", "Numeric", "bytea", "integer[]", "_text"]"#)
+                .unwrap();
+        assert_eq!(
+            param_types_to_oids(&types).unwrap(),
+            vec![
+                Type::INT8.oid(),
+                Type::INT8.oid(),
+                Type::NUMERIC.oid(),
+                Type::BYTEA.oid(),
+                Type::INT4_ARRAY.oid(),
+                Type::TEXT_ARRAY.oid(),
+            ]
+        );
+
+        let types = vec![ParamType::Name("no_such_type".to_owned())];
+        assert!(matches!(
+            param_types_to_oids(&types),
+            Err(TypedParamsError::UnknownType(_))
+        ));
+    }
+
+    #[test]
+    fn test_typed_bytea_params() {
+        let types = [Type::BYTEA.oid(), Type::TEXT.oid(), Type::BYTEA.oid()];
+        let mut params = vec![
+            Some("3q2+7w==".to_owned()),
+            Some("3q2+7w==".to_owned()),
+            None,
+        ];
+        typed_params_to_pg_text(&types, &mut params).unwrap();
+        assert_eq!(
+            params,
+            vec![
+                Some("\\xdeadbeef".to_owned()),
+                Some("3q2+7w==".to_owned()),
+                None
+            ]
+        );
+
+        let mut params = vec![Some("not base64".to_owned())];
+        assert!(matches!(
+            typed_params_to_pg_text(&types, &mut params),
+            Err(TypedParamsError::InvalidBytea(1))
+        ));
+    }
+
+    #[test]
+    fn test_typed_bytea_output() {
+        fn typed(val: &str, pg_type: &Type) -> Value {
+            let output =
+                json::value_to_string!(|v| super::pg_text_to_json(v, val, pg_type, true).unwrap());
+            serde_json::from_str(&output).unwrap()
+        }
+        assert_eq!(typed("\\xdeadbeef", &Type::BYTEA), json!("3q2+7w=="));
+        assert_eq!(typed("\\xdeadbeef", &Type::TEXT), json!("\\xdeadbeef"));
+        assert_eq!(
+            typed(r#"{"\\x00ff",NULL}"#, &Type::BYTEA_ARRAY),
+            json!(["AP8=", null])
+        );
+        assert_eq!(
+            typed("123456789012345678901234567890.5", &Type::NUMERIC),
+            json!("123456789012345678901234567890.5")
+        );
+        assert_eq!(
+            pg_text_to_json("\\xdeadbeef", &Type::BYTEA),
+            json!("\\xdeadbeef")
+        );
+    }
+
     fn pg_text_to_json(val: &str, pg_type: &Type) -> Value {
-        let output = json::value_to_string!(|v| super::pg_text_to_json(v, val, pg_type).unwrap());
+        let output =
+            json::value_to_string!(|v| super::pg_text_to_json(v, val, pg_type, false).unwrap());
         serde_json::from_str(&output).unwrap()
     }
 
     fn pg_array_parse(pg_array: &str, pg_type: &Type) -> Value {
         let output = json::value_to_string!(|v| json::value_as_list!(|v| {
-            super::pg_array_parse(v, pg_array, pg_type, ',').unwrap();
+            super::pg_array_parse(v, pg_array, pg_type, ',', false).unwrap();
         }));
         serde_json::from_str(&output).unwrap()
     }
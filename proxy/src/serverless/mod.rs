@@ -7,13 +7,18 @@
 mod conn_pool;
 mod conn_pool_lib;
 mod error;
+#[cfg(feature = "rest_broker")]
+mod graphql;
 mod http_conn_pool;
 mod http_util;
 mod json;
 mod local_conn_pool;
 #[cfg(feature = "rest_broker")]
+mod openapi;
+#[cfg(feature = "rest_broker")]
 pub mod rest;
 mod sql_over_http;
+mod txn_session;
 mod websocket;
 
 use std::net::{IpAddr, SocketAddr};
@@ -113,10 +118,31 @@ pub async fn task_main(
         }
     });
 
+    let txn_sessions = Arc::new(txn_session::TxnSessions::new(&config.http_config));
+    {
+        let txn_sessions = Arc::clone(&txn_sessions);
+        tokio::spawn(async move {
+            txn_sessions.gc_worker().await;
+        });
+    }
+
+    // roll back interactive transactions on shutdown
+    tokio::spawn({
+        let cancellation_token = cancellation_token.clone();
+        let txn_sessions = txn_sessions.clone();
+        async move {
+            cancellation_token.cancelled().await;
+            tokio::task::spawn_blocking(move || txn_sessions.shutdown())
+                .await
+                .propagate_task_panic();
+        }
+    });
+
     let backend = Arc::new(PoolingBackend {
         http_conn_pool: Arc::clone(&http_conn_pool),
         local_pool,
         pool: Arc::clone(&conn_pool),
+        txn_sessions,
         config,
         auth_backend,
         endpoint_rate_limiter: Arc::clone(&endpoint_rate_limiter),
@@ -432,8 +458,11 @@ async fn request_handler(
         let span = ctx.span();
         info!(parent: &span, "performing websocket upgrade");
 
-        let (response, websocket) = framed_websockets::upgrade::upgrade(&mut request)
+        let options =
+            websocket::WebSocketOptions::negotiate(&config.http_config, request.headers());
+        let (mut response, websocket) = framed_websockets::upgrade::upgrade(&mut request)
             .map_err(|e| ApiError::BadRequest(e.into()))?;
+        options.response_headers(response.headers_mut());
 
         let cancellations = cancellations.clone();
         ws_connections.spawn(
@@ -443,6 +472,7 @@ async fn request_handler(
                     backend.auth_backend,
                     ctx,
                     websocket,
+                    options,
                     cancellation_handler,
                     endpoint_rate_limiter,
                     host,
@@ -482,7 +512,7 @@ async fn request_handler(
             .header("Access-Control-Allow-Origin", "*")
             .header(
                 "Access-Control-Allow-Headers",
-                "Authorization, Neon-Connection-String, Neon-Raw-Text-Output, Neon-Array-Mode, Neon-Pool-Opt-In, Neon-Batch-Read-Only, Neon-Batch-Isolation-Level",
+                "Authorization, Neon-Connection-String, Neon-Raw-Text-Output, Neon-Array-Mode, Neon-Pool-Opt-In, Neon-Batch-Read-Only, Neon-Batch-Isolation-Level, Neon-Stream-Output",
             )
             .header("Access-Control-Max-Age", "86400" /* 24 hours */)
             .status(StatusCode::OK) // 204 is also valid, but see: https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods/OPTIONS#status_code
@@ -0,0 +1,313 @@
+//! OpenAPI description of the REST API, served on `GET /` like PostgREST does.
+//!
+//! The document is generated from the introspected [`DbSchema`] and the
+//! privileges of the requesting role, so it only lists the tables, views and
+//! functions the role can use, with the methods it is allowed to call.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue, json};
+use subzero_core::schema::{DbSchema, ObjectType, ProcVolatility};
+
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// Privileges of the current role on the objects of a schema, the schema
+/// name being the first parameter.
+///
+/// Ran after the env query, so that `current_user` is the requesting role.
+pub(crate) const PRIVILEGES_SQL: &str = r#"
+select
+  (select coalesce(json_object_agg(c.relname, array_remove(array[
+      case when has_table_privilege(c.oid, 'SELECT') then 'get' end,
+      case when has_table_privilege(c.oid, 'INSERT') then 'post' end,
+      case when has_table_privilege(c.oid, 'UPDATE') then 'patch' end,
+      case when has_table_privilege(c.oid, 'DELETE') then 'delete' end
+    ], null)), '{}')
+   from pg_class c
+   join pg_namespace n on n.oid = c.relnamespace
+   where n.nspname = $1 and c.relkind in ('r', 'v', 'm', 'f', 'p')) as tables,
+  (select coalesce(json_agg(distinct p.proname), '[]')
+   from pg_proc p
+   join pg_namespace n on n.oid = p.pronamespace
+   where n.nspname = $1 and has_function_privilege(p.oid, 'EXECUTE')) as functions
+"#;
+
+/// What the requesting role is allowed to do, as returned by [`PRIVILEGES_SQL`].
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Privileges {
+    /// Allowed methods for each table and view, e.g. `["get", "post"]`.
+    pub tables: HashMap<String, Vec<String>>,
+    /// Functions the role can execute.
+    pub functions: HashSet<String>,
+}
+
+/// Generate the OpenAPI document for `schema_name`, served at `server_url`.
+pub(crate) fn openapi_document(
+    db_schema: &DbSchema<'_>,
+    schema_name: &str,
+    privileges: &Privileges,
+    server_url: &str,
+) -> JsonValue {
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+
+    paths.insert(
+        "/".to_string(),
+        json!({
+            "get": {
+                "tags": ["Introspection"],
+                "summary": "OpenAPI description (this document)",
+                "responses": { "200": { "description": "OK" } },
+            }
+        }),
+    );
+
+    let mut objects = db_schema
+        .schemas
+        .get(schema_name)
+        .map(|schema| schema.objects.values().collect::<Vec<_>>())
+        .unwrap_or_default();
+    // keep the document stable between generations
+    objects.sort_by_key(|object| object.name);
+
+    for object in objects {
+        let name = object.name;
+        match &object.kind {
+            ObjectType::Table | ObjectType::View => {
+                let Some(methods) = privileges.tables.get(name).filter(|m| !m.is_empty()) else {
+                    continue;
+                };
+
+                let mut columns = object.columns.values().collect::<Vec<_>>();
+                columns.sort_by_key(|column| column.name);
+
+                let mut properties = Map::new();
+                for column in &columns {
+                    let mut property = pg_type_to_openapi(column.data_type);
+                    if column.primary_key {
+                        property["description"] = "Note:\nThis is a Primary Key.<pk/>".into();
+                    }
+                    properties.insert(column.name.to_string(), property);
+                }
+                schemas.insert(
+                    name.to_string(),
+                    json!({ "type": "object", "properties": properties }),
+                );
+
+                let schema_ref = json!({ "$ref": format!("#/components/schemas/{name}") });
+                let filters = columns
+                    .iter()
+                    .map(|column| {
+                        json!({
+                            "name": column.name,
+                            "in": "query",
+                            "required": false,
+                            "schema": { "type": "string", "format": column.data_type },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                let get_parameters = [
+                    filters.clone(),
+                    vec![
+                        query_param("select", "Filtering columns"),
+                        query_param("order", "Ordering"),
+                        query_param("limit", "Limiting and pagination"),
+                        query_param("offset", "Limiting and pagination"),
+                    ],
+                ]
+                .concat();
+
+                let mut path = Map::new();
+                for method in methods {
+                    let operation = match method.as_str() {
+                        "get" => json!({
+                            "tags": [name],
+                            "parameters": get_parameters.clone(),
+                            "responses": {
+                                "200": {
+                                    "description": "OK",
+                                    "content": json_content(json!({
+                                        "type": "array",
+                                        "items": schema_ref.clone(),
+                                    })),
+                                },
+                                "206": { "description": "Partial Content" },
+                            },
+                        }),
+                        "post" => json!({
+                            "tags": [name],
+                            "requestBody": { "content": json_content(schema_ref.clone()) },
+                            "responses": { "201": { "description": "Created" } },
+                        }),
+                        "patch" => json!({
+                            "tags": [name],
+                            "parameters": filters.clone(),
+                            "requestBody": { "content": json_content(schema_ref.clone()) },
+                            "responses": { "204": { "description": "No Content" } },
+                        }),
+                        "delete" => json!({
+                            "tags": [name],
+                            "parameters": filters.clone(),
+                            "responses": { "204": { "description": "No Content" } },
+                        }),
+                        _ => continue,
+                    };
+                    path.insert(method.clone(), operation);
+                }
+                paths.insert(format!("/{name}"), JsonValue::Object(path));
+            }
+            ObjectType::Function {
+                parameters,
+                volatile,
+                ..
+            } => {
+                if !privileges.functions.contains(name) {
+                    continue;
+                }
+
+                let mut properties = Map::new();
+                let mut required = vec![];
+                for parameter in parameters {
+                    properties.insert(
+                        parameter.name.to_string(),
+                        pg_type_to_openapi(parameter.type_),
+                    );
+                    if parameter.required {
+                        required.push(parameter.name);
+                    }
+                }
+
+                let tags = json!(["(rpc) ".to_string() + name]);
+                let responses = json!({ "200": { "description": "OK" } });
+                let mut path = Map::new();
+                // like in PostgREST, only functions that do not modify the
+                // database can be called with GET
+                if *volatile != ProcVolatility::Volatile {
+                    let query = parameters
+                        .iter()
+                        .map(|parameter| {
+                            json!({
+                                "name": parameter.name,
+                                "in": "query",
+                                "required": parameter.required,
+                                "schema": pg_type_to_openapi(parameter.type_),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    path.insert(
+                        "get".to_string(),
+                        json!({
+                            "tags": tags.clone(),
+                            "parameters": query,
+                            "responses": responses.clone(),
+                        }),
+                    );
+                }
+                path.insert(
+                    "post".to_string(),
+                    json!({
+                        "tags": tags,
+                        "requestBody": {
+                            "required": !required.is_empty(),
+                            "content": json_content(json!({
+                                "type": "object",
+                                "properties": properties,
+                                "required": required,
+                            })),
+                        },
+                        "responses": responses,
+                    }),
+                );
+                paths.insert(format!("/rpc/{name}"), JsonValue::Object(path));
+            }
+        }
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": format!("{schema_name} REST API"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server_url }],
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+fn query_param(name: &str, description: &str) -> JsonValue {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })
+}
+
+fn json_content(schema: JsonValue) -> JsonValue {
+    json!({ "application/json": { "schema": schema } })
+}
+
+/// Map a Postgres type to an OpenAPI schema, keeping the Postgres type as
+/// the format so that client generators can refine it.
+fn pg_type_to_openapi(data_type: &str) -> JsonValue {
+    if let Some(element) = data_type.strip_suffix("[]") {
+        return json!({ "type": "array", "items": pg_type_to_openapi(element), "format": data_type });
+    }
+
+    // strip modifiers, e.g. `character varying(10)` or `numeric(10,2)`
+    let base = data_type.split('(').next().unwrap_or(data_type).trim();
+    let openapi_type = match base {
+        "smallint" | "integer" | "bigint" | "int2" | "int4" | "int8" => "integer",
+        "real" | "double precision" | "numeric" | "float4" | "float8" => "number",
+        "boolean" | "bool" => "boolean",
+        // any json value
+        "json" | "jsonb" => return json!({ "format": data_type }),
+        _ => "string",
+    };
+    json!({ "type": openapi_type, "format": data_type })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pg_type_to_openapi() {
+        assert_eq!(
+            pg_type_to_openapi("integer"),
+            json!({ "type": "integer", "format": "integer" })
+        );
+        assert_eq!(
+            pg_type_to_openapi("numeric(10,2)"),
+            json!({ "type": "number", "format": "numeric(10,2)" })
+        );
+        assert_eq!(
+            pg_type_to_openapi("character varying(10)"),
+            json!({ "type": "string", "format": "character varying(10)" })
+        );
+        assert_eq!(pg_type_to_openapi("jsonb"), json!({ "format": "jsonb" }));
+        assert_eq!(
+            pg_type_to_openapi("text[]"),
+            json!({
+                "type": "array",
+                "items": { "type": "string", "format": "text" },
+                "format": "text[]",
+            })
+        );
+    }
+
+    #[test]
+    fn test_privileges() {
+        let privileges: Privileges = serde_json::from_value(json!({
+            "tables": { "items": ["get", "post"] },
+            "functions": ["search"],
+        }))
+        .unwrap();
+        assert_eq!(privileges.tables["items"], ["get", "post"]);
+        assert!(privileges.functions.contains("search"));
+    }
+}
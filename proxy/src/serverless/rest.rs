@@ -1,7 +1,7 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use bytes::Bytes;
 use http::Method;
@@ -53,23 +53,30 @@
 use super::conn_pool::AuthData;
 use super::conn_pool_lib::ConnInfo;
 use super::error::{ConnInfoError, Credentials, HttpCodeError, ReadPayloadError};
+use super::graphql::{self, GraphqlError};
 use super::http_conn_pool::{self, LocalProxyClient};
 use super::http_util::{
     ALLOW_POOL, CONN_STRING, NEON_REQUEST_ID, RAW_TEXT_OUTPUT, TXN_ISOLATION_LEVEL, TXN_READ_ONLY,
     get_conn_info, json_response, uuid_to_header_value,
 };
 use super::json::JsonConversionError;
+use super::openapi::{self, Privileges};
 use crate::auth::backend::ComputeCredentialKeys;
 use crate::cache::common::{count_cache_insert, count_cache_outcome, eviction_listener};
+use crate::cache::query::{CacheDirectives, QueryCacheKey};
 use crate::config::ProxyConfig;
 use crate::context::RequestContext;
+use crate::context::audit::AuditedQuery;
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use crate::http::read_body_with_limit;
+use crate::intern::EndpointIdInt;
 use crate::metrics::{CacheKind, Metrics};
 use crate::serverless::sql_over_http::HEADER_VALUE_TRUE;
 use crate::types::EndpointCacheKey;
 use crate::util::deserialize_json_string;
 
+const QUERY_CACHE_HEADER: HeaderName = HeaderName::from_static("neon-query-cache");
+
 static EMPTY_JSON_SCHEMA: &str = r#"{"schemas":[]}"#;
 const INTROSPECTION_SQL: &str = POSTGRESQL_INTROSPECTION_SQL;
 const HEADER_VALUE_ALLOW_ALL_ORIGINS: HeaderValue = HeaderValue::from_static("*");
@@ -155,29 +162,42 @@ pub struct ApiConfig {
     pub server_cors_allowed_origins: Option<Vec<String>>,
 }
 
+type SchemaEntry = (ApiConfig, DbSchemaOwned);
+
 // The DbSchemaCache is a cache of the ApiConfig and DbSchemaOwned for each endpoint
-pub(crate) struct DbSchemaCache(Cache<EndpointCacheKey, Arc<(ApiConfig, DbSchemaOwned)>>);
+pub(crate) struct DbSchemaCache {
+    schemas: Cache<EndpointCacheKey, Arc<SchemaEntry>>,
+    // OpenAPI documents by endpoint, schema and role, along with the schema entry they were
+    // generated from. They are only served while that entry is the cached one.
+    openapi: Cache<(EndpointCacheKey, String, String), (Weak<SchemaEntry>, Bytes)>,
+}
 impl DbSchemaCache {
     pub fn new(config: crate::config::CacheOptions) -> Self {
-        let builder = Cache::builder().name("schema");
-        let builder = config.moka(builder);
-
         let metrics = &Metrics::get().cache;
         if let Some(size) = config.size {
             metrics.capacity.set(CacheKind::Schema, size as i64);
+            metrics.capacity.set(CacheKind::OpenApi, size as i64);
         }
 
-        let builder =
-            builder.eviction_listener(|_k, _v, cause| eviction_listener(CacheKind::Schema, cause));
+        let builder = config.moka(Cache::builder().name("schema"));
+        let schemas = builder
+            .eviction_listener(|_k, _v, cause| eviction_listener(CacheKind::Schema, cause))
+            .build();
 
-        Self(builder.build())
+        let builder = config.moka(Cache::builder().name("openapi"));
+        let openapi = builder
+            .eviction_listener(|_k, _v, cause| eviction_listener(CacheKind::OpenApi, cause))
+            .build();
+
+        Self { schemas, openapi }
     }
 
     pub async fn maintain(&self) -> Result<Infallible, anyhow::Error> {
         let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
         loop {
             ticker.tick().await;
-            self.0.run_pending_tasks();
+            self.schemas.run_pending_tasks();
+            self.openapi.run_pending_tasks();
         }
     }
 
@@ -185,7 +205,43 @@ pub fn get_cached(
         &self,
         endpoint_id: &EndpointCacheKey,
     ) -> Option<Arc<(ApiConfig, DbSchemaOwned)>> {
-        count_cache_outcome(CacheKind::Schema, self.0.get(endpoint_id))
+        count_cache_outcome(CacheKind::Schema, self.schemas.get(endpoint_id))
+    }
+
+    fn get_openapi(
+        &self,
+        endpoint_id: &EndpointCacheKey,
+        entry: &Arc<SchemaEntry>,
+        schema_name: &str,
+        role: &str,
+    ) -> Option<Bytes> {
+        let key = (
+            endpoint_id.clone(),
+            schema_name.to_string(),
+            role.to_string(),
+        );
+        let cached = self
+            .openapi
+            .get(&key)
+            .filter(|(schema, _)| Weak::ptr_eq(schema, &Arc::downgrade(entry)));
+        count_cache_outcome(CacheKind::OpenApi, cached).map(|(_, document)| document)
+    }
+
+    fn insert_openapi(
+        &self,
+        endpoint_id: &EndpointCacheKey,
+        entry: &Arc<SchemaEntry>,
+        schema_name: &str,
+        role: &str,
+        document: Bytes,
+    ) {
+        let key = (
+            endpoint_id.clone(),
+            schema_name.to_string(),
+            role.to_string(),
+        );
+        count_cache_insert(CacheKind::OpenApi);
+        self.openapi.insert(key, (Arc::downgrade(entry), document));
     }
     pub async fn get_remote(
         &self,
@@ -219,7 +275,7 @@ pub async fn get_remote(
                 };
                 let value = Arc::new((api_config, schema_owned));
                 count_cache_insert(CacheKind::Schema);
-                self.0.insert(endpoint_id.clone(), value);
+                self.schemas.insert(endpoint_id.clone(), value);
                 return Err(e);
             }
             Err(e) => {
@@ -228,7 +284,7 @@ pub async fn get_remote(
         };
         let value = Arc::new((api_config, schema_owned));
         count_cache_insert(CacheKind::Schema);
-        self.0.insert(endpoint_id.clone(), value.clone());
+        self.schemas.insert(endpoint_id.clone(), value.clone());
         Ok(value)
     }
     async fn internal_get_remote(
@@ -368,6 +424,8 @@ pub(crate) enum RestError {
     SubzeroCore(#[from] SubzeroCoreError),
     #[error("schema is too large")]
     SchemaTooLarge,
+    #[error(transparent)]
+    Graphql(#[from] GraphqlError),
 }
 impl ReportableError for RestError {
     fn get_error_kind(&self) -> ErrorKind {
@@ -379,6 +437,7 @@ fn get_error_kind(&self) -> ErrorKind {
             RestError::JsonConversion(_) => ErrorKind::Postgres,
             RestError::SubzeroCore(_) => ErrorKind::User,
             RestError::SchemaTooLarge => ErrorKind::User,
+            RestError::Graphql(_) => ErrorKind::User,
         }
     }
 }
@@ -389,6 +448,7 @@ fn to_string_client(&self) -> String {
             RestError::ConnectCompute(c) => c.to_string_client(),
             RestError::ConnInfo(c) => c.to_string_client(),
             RestError::SchemaTooLarge => self.to_string(),
+            RestError::Graphql(g) => g.to_string(),
             RestError::Postgres(p) => p.to_string_client(),
             RestError::JsonConversion(_) => "could not parse postgres response".to_string(),
             RestError::SubzeroCore(s) => {
@@ -409,6 +469,9 @@ impl HttpCodeError for RestError {
     fn get_http_status_code(&self) -> StatusCode {
         match self {
             RestError::ReadPayload(e) => e.get_http_status_code(),
+            RestError::ConnectCompute(HttpConnError::SessionLimit(_)) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             RestError::ConnectCompute(h) => match h.get_error_kind() {
                 ErrorKind::User => StatusCode::BAD_REQUEST,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
@@ -417,6 +480,7 @@ fn get_http_status_code(&self) -> StatusCode {
             RestError::Postgres(e) => e.get_http_status_code(),
             RestError::JsonConversion(_) => StatusCode::INTERNAL_SERVER_ERROR,
             RestError::SchemaTooLarge => StatusCode::INTERNAL_SERVER_ERROR,
+            RestError::Graphql(_) => StatusCode::BAD_REQUEST,
             RestError::SubzeroCore(e) => {
                 let status = e.status_code();
                 StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
@@ -440,6 +504,61 @@ fn fmt_env_query<'a>(env: &'a HashMap<&'a str, &'a str>) -> Snippet<'a> {
         }
 }
 
+/// Look up the role in the jwt claims following `role_claim_key`, a path like
+/// `.role`, `.app_metadata.roles[0]` or `."https://example.com/claims".role`.
+fn role_from_claims<'a>(claims: &'a JsonValue, role_claim_key: &str) -> Option<&'a str> {
+    let mut value = claims;
+    let mut rest = role_claim_key;
+    while !rest.is_empty() {
+        if let Some(key) = rest.strip_prefix('.') {
+            let (key, tail) = match key.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted.find('"')?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => {
+                    let end = key.find(['.', '[']).unwrap_or(key.len());
+                    key.split_at(end)
+                }
+            };
+            value = value.get(key)?;
+            rest = tail;
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']')?;
+            value = value.get(index[..end].parse::<usize>().ok()?)?;
+            rest = &index[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    value.as_str()
+}
+
+/// The jwt claims passed to the sql context, only the role when there is no jwt.
+fn jwt_claims_env(jwt_claims: Option<&JsonValue>, role: Option<&str>) -> String {
+    let empty_json = "{}".to_string();
+    jwt_claims
+        .map(|v| serde_json::to_string(v).unwrap_or(empty_json.clone()))
+        .unwrap_or(if let Some(r) = role {
+            let claims: HashMap<&str, &str> = HashMap::from([("role", r)]);
+            serde_json::to_string(&claims).unwrap_or(empty_json.clone())
+        } else {
+            empty_json.clone()
+        })
+}
+
+fn search_path_env(schema_name: &str, db_extra_search_path: &Option<Vec<String>>) -> String {
+    let mut search_path = vec![schema_name];
+    if let Some(extra) = db_extra_search_path {
+        search_path.extend(extra.iter().map(|s| s.as_str()));
+    }
+    search_path
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 // TODO: see about removing the need for cloning the values (inner things are &Cow<str> already)
 fn to_sql_param(p: &Param) -> JsonValue {
     match p {
@@ -597,6 +716,21 @@ pub(crate) async fn handle(
             }
             r
         }
+        Err(RestError::Graphql(e)) => {
+            ctx.set_error_kind(ErrorKind::User);
+
+            tracing::info!(
+                kind = ErrorKind::User.to_metric_label(),
+                error = %e,
+                msg = "graphql error",
+                "forwarding error to user"
+            );
+
+            json_response(
+                StatusCode::BAD_REQUEST,
+                json!({ "errors": [{ "message": e.to_string() }] }),
+            )?
+        }
         Err(e @ RestError::SubzeroCore(_)) => {
             let error_kind = e.get_error_kind();
             ctx.set_error_kind(error_kind);
@@ -642,6 +776,11 @@ pub(crate) async fn handle(
                     e.detail,
                     e.hint,
                 ),
+                RestError::ConnectCompute(HttpConnError::SessionLimit(limit)) => (
+                    Some(String::from_utf8_lossy(&limit.sql_state()).into_owned()),
+                    None,
+                    None,
+                ),
                 _ => (None, None, None),
             };
 
@@ -715,6 +854,19 @@ async fn handle_inner(
         "credentials"
     );
 
+    // the graphql api lives next to the rest api, at /database/rest/graphql/v1
+    let graphql = request.uri().path() == format!("/{database_name}/rest/graphql/v1");
+    if graphql
+        && !config
+            .rest_config
+            .graphql_endpoints
+            .is_enabled(&conn_info.conn_info.user_info.endpoint)
+    {
+        return Err(RestError::SubzeroCore(NotFound {
+            target: request.uri().path().to_string(),
+        }));
+    }
+
     match conn_info.auth {
         AuthData::Jwt(jwt) => {
             let api_prefix = format!("/{database_name}/rest/v1/");
@@ -722,6 +874,7 @@ async fn handle_inner(
                 config,
                 ctx,
                 &api_prefix,
+                graphql,
                 request,
                 &connection_string,
                 conn_info.conn_info,
@@ -772,6 +925,7 @@ async fn handle_rest_inner(
     config: &'static ProxyConfig,
     ctx: &RequestContext,
     api_prefix: &str,
+    graphql: bool,
     request: Request<Incoming>,
     connection_string: &str,
     conn_info: ConnInfo,
@@ -793,6 +947,15 @@ async fn handle_rest_inner(
             message: "Failed to get endpoint cache key".to_string(),
         }))?;
 
+    // the query cache is opt-in per endpoint
+    let endpoint_id = EndpointIdInt::from(&conn_info.user_info.endpoint);
+    let query_cache = config.rest_config.query_cache.as_ref().filter(|_| {
+        config
+            .rest_config
+            .query_cache_endpoints
+            .is_enabled(&conn_info.user_info.endpoint)
+    });
+
     let (parts, originial_body) = request.into_parts();
 
     // try and get the cached entry for this endpoint
@@ -844,6 +1007,9 @@ async fn handle_rest_inner(
         .await
         .map_err(HttpConnError::from)?;
 
+    let session = backend.acquire_session(ctx, &conn_info.user_info).await?;
+    session.try_queries(1).map_err(HttpConnError::from)?;
+
     let auth_header = parts
         .headers
         .get(AUTHORIZATION)
@@ -874,9 +1040,7 @@ async fn handle_rest_inner(
 
     let db_schemas = &api_config.db_schemas; // list of schemas available for the api
     let db_extra_search_path = &api_config.db_extra_search_path;
-    // TODO: use this when we get a replacement for jsonpath_lib
-    // let role_claim_key = &api_config.role_claim_key;
-    // let role_claim_path = format!("${role_claim_key}");
+    let role_claim_key = &api_config.role_claim_key;
     let db_anon_role = &api_config.db_anon_role;
     let max_rows = api_config.db_max_rows.as_deref();
     let db_allowed_select_functions = api_config
@@ -899,11 +1063,11 @@ async fn handle_rest_inner(
 
     // read the role from the jwt claims (and set it to the "anon" role if not present)
     let (role, authenticated) = match &jwt_claims {
-        Some(claims) => match claims.get("role") {
-            Some(JsonValue::String(r)) => (Some(r), true),
-            _ => (db_anon_role.as_ref(), true),
+        Some(claims) => match role_from_claims(claims, role_claim_key) {
+            Some(r) => (Some(r), true),
+            None => (db_anon_role.as_deref(), true),
         },
-        None => (db_anon_role.as_ref(), false),
+        None => (db_anon_role.as_deref(), false),
     };
 
     // do not allow unauthenticated requests when there is no anonymous role setup
@@ -913,6 +1077,24 @@ async fn handle_rest_inner(
         }));
     }
 
+    if graphql {
+        return handle_graphql_inner(
+            config,
+            ctx,
+            parts,
+            originial_body,
+            response,
+            connection_string,
+            auth_header.clone(),
+            client,
+            api_config,
+            db_schema,
+            role.unwrap_or(""),
+            jwt_claims.as_ref(),
+        )
+        .await;
+    }
+
     // start deconstructing the request because subzero core mostly works with &str
     let method = parts.method;
     let method_str = method.as_str();
@@ -922,6 +1104,8 @@ async fn handle_rest_inner(
     // TODO: rename this to something more descriptive
     let root = match parts.uri.path().strip_prefix(api_prefix) {
         Some(p) => Ok(p),
+        // the api root can also be requested without the trailing slash
+        None if parts.uri.path() == api_prefix.trim_end_matches('/') => Ok(""),
         None => Err(RestError::SubzeroCore(NotFound {
             target: parts.uri.path().to_string(),
         })),
@@ -930,6 +1114,28 @@ async fn handle_rest_inner(
     // pick the current schema from the headers (or the first one from config)
     let schema_name = &DbSchema::pick_current_schema(db_schemas, method_str, &parts.headers)?;
 
+    // the api root describes the api
+    if root.is_empty() && method == Method::GET {
+        return handle_openapi_inner(
+            config,
+            ctx,
+            &parts.headers,
+            parts.uri.path(),
+            response,
+            connection_string,
+            api_prefix,
+            auth_header.clone(),
+            client,
+            db_schema_cache,
+            &endpoint_cache_key,
+            &entry,
+            schema_name,
+            role.unwrap_or(""),
+            jwt_claims.as_ref(),
+        )
+        .await;
+    }
+
     // add the content-profile header to the response
     let mut response_headers = vec![];
     if db_schemas.len() > 1 {
@@ -1010,24 +1216,8 @@ async fn handle_rest_inner(
     let headers_env = serde_json::to_string(&api_request.headers).unwrap_or(empty_json.clone());
     let cookies_env = serde_json::to_string(&api_request.cookies).unwrap_or(empty_json.clone());
     let get_env = serde_json::to_string(&api_request.get).unwrap_or(empty_json.clone());
-    let jwt_claims_env = jwt_claims
-        .as_ref()
-        .map(|v| serde_json::to_string(v).unwrap_or(empty_json.clone()))
-        .unwrap_or(if let Some(r) = env_role {
-            let claims: HashMap<&str, &str> = HashMap::from([("role", r)]);
-            serde_json::to_string(&claims).unwrap_or(empty_json.clone())
-        } else {
-            empty_json.clone()
-        });
-    let mut search_path = vec![api_request.schema_name];
-    if let Some(extra) = &db_extra_search_path {
-        search_path.extend(extra.iter().map(|s| s.as_str()));
-    }
-    let search_path_str = search_path
-        .into_iter()
-        .filter(|s| !s.is_empty())
-        .collect::<Vec<_>>()
-        .join(",");
+    let jwt_claims_env = jwt_claims_env(jwt_claims.as_ref(), env_role);
+    let search_path_str = search_path_env(api_request.schema_name, db_extra_search_path);
     let mut env: HashMap<&str, &str> = HashMap::from([
         ("request.method", api_request.method),
         ("request.path", api_request.path),
@@ -1049,6 +1239,50 @@ async fn handle_rest_inner(
         &api_request,
         &env,
     )?);
+    let main_parameters = main_parameters
+        .iter()
+        .map(|p| to_sql_param(&p.to_param()))
+        .collect::<Vec<_>>();
+
+    // serve the read from the query cache if we can, the key holds everything
+    // the result depends on for a given role, including the claims used by RLS
+    let mut query_cache_entry = None;
+    if let Some(query_cache) = query_cache
+        && method == Method::GET
+    {
+        let directives = CacheDirectives::from_headers(&parts.headers, query_cache.max_ttl());
+        let key = QueryCacheKey {
+            endpoint: endpoint_id,
+            role: role_str.to_string(),
+            query: format!("{main_statement}\n{jwt_claims_env}\n{search_path_str}"),
+            params: serde_json::to_string(&main_parameters)
+                .map_err(|e| RestError::JsonConversion(JsonConversionError::ParseJsonError(e)))?,
+        };
+
+        if directives.lookup
+            && let Some(cached) = query_cache.get(&key)
+        {
+            response = response
+                .status(StatusCode::from_u16(cached.status).unwrap_or(StatusCode::OK))
+                .header(QUERY_CACHE_HEADER, "hit");
+            for (header_name, header_value) in &cached.headers {
+                response = response.header(header_name, header_value);
+            }
+            return response
+                .body(
+                    Full::new(cached.body.clone())
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .map_err(|_| {
+                    RestError::SubzeroCore(InternalError {
+                        message: "Failed to build response".to_string(),
+                    })
+                });
+        }
+
+        query_cache_entry = directives.ttl.map(|ttl| (key, ttl));
+    }
 
     let mut headers = vec![
         (&NEON_REQUEST_ID, uuid_to_header_value(ctx.session_id())),
@@ -1068,6 +1302,9 @@ async fn handle_rest_inner(
         headers.push((&TXN_READ_ONLY, HEADER_VALUE_TRUE));
     }
 
+    // the env statement only sets up the request, with the jwt claims in it.
+    let mut audited = ctx.query_audit().start(&main_statement);
+
     // convert the parameters from subzero core representation to the local proxy repr.
     let req_body = serde_json::to_string(&BatchQueryData {
         queries: vec![
@@ -1080,10 +1317,7 @@ async fn handle_rest_inner(
             },
             QueryData {
                 query: main_statement.into(),
-                params: main_parameters
-                    .iter()
-                    .map(|p| to_sql_param(&p.to_param()))
-                    .collect(),
+                params: main_parameters,
             },
         ],
     })
@@ -1105,9 +1339,11 @@ async fn handle_rest_inner(
     // FIXME: check if there are other error codes or shapes of the response
     if response_parts.status.as_u16() > 399 {
         // turn this postgres error from the json into PostgresError
-        let postgres_error = serde_json::from_slice(&bytes)
+        let postgres_error: PostgresError = serde_json::from_slice(&bytes)
             .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
 
+        audited.fail(&postgres_error.code);
+        audited.finish();
         return Err(RestError::Postgres(postgres_error));
     }
 
@@ -1123,6 +1359,7 @@ struct EnvRows {}
 
     #[derive(Deserialize)]
     struct MainRows {
+        command: String,
         /// `main_statement` only returns a single row.
         rows: [MainRow; 1],
     }
@@ -1140,8 +1377,19 @@ struct MainRow {
         .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
 
     let QueryResults {
-        results: (_, MainRows { rows: [row] }),
+        results: (_, main_rows),
     } = results;
+    let MainRows {
+        command,
+        rows: [row],
+    } = main_rows;
+
+    // the write went through, drop the cached reads of the endpoint
+    if !api_request.read_only
+        && let Some(query_cache) = query_cache
+    {
+        query_cache.purge_endpoint(endpoint_id);
+    }
 
     // build the intermediate response object
     let api_response = ApiResponse {
@@ -1153,6 +1401,9 @@ struct MainRow {
         body: row.body,
     };
 
+    audited.complete(&command, Some(api_response.page_total));
+    audited.finish();
+
     // TODO: rollback the transaction if the page_total is not 1 and the accept_content_type is SingularJSON
     // we can not do this in the context of proxy for now
     // if api_request.accept_content_type == SingularJSON && api_response.page_total != 1 {
@@ -1263,10 +1514,22 @@ struct MainRow {
         })),
     }?;
 
+    let body = Bytes::from(api_response.body);
+    if let Some(query_cache) = query_cache
+        && method == Method::GET
+    {
+        if let Some((key, ttl)) = query_cache_entry
+            && (200..300).contains(&status)
+        {
+            let mut headers = response_headers.clone();
+            headers.push((CONTENT_TYPE.to_string(), http_content_type.to_string()));
+            query_cache.insert(key, status, headers, body.clone(), ttl);
+        }
+        response_headers.push((QUERY_CACHE_HEADER.to_string(), "miss".to_string()));
+    }
+
     // build the response body
-    let response_body = Full::new(Bytes::from(api_response.body))
-        .map_err(|never| match never {})
-        .boxed();
+    let response_body = Full::new(body).map_err(|never| match never {}).boxed();
 
     // build the response
     response = response
@@ -1285,3 +1548,416 @@ struct MainRow {
         })
     })
 }
+
+/// Run read only queries in a single transaction through local_proxy and return the raw
+/// response body.
+async fn run_read_only_queries(
+    config: &'static ProxyConfig,
+    ctx: &RequestContext,
+    client: &mut http_conn_pool::Client<LocalProxyClient>,
+    connection_string: &str,
+    auth_header: HeaderValue,
+    queries: Vec<QueryData<'_>>,
+) -> Result<Vec<u8>, RestError> {
+    let headers = vec![
+        (&NEON_REQUEST_ID, uuid_to_header_value(ctx.session_id())),
+        (
+            &CONN_STRING,
+            HeaderValue::from_str(connection_string).expect("invalid connection string"),
+        ),
+        (&AUTHORIZATION, auth_header),
+        (
+            &TXN_ISOLATION_LEVEL,
+            HeaderValue::from_static("ReadCommitted"),
+        ),
+        (&ALLOW_POOL, HEADER_VALUE_TRUE),
+        (&TXN_READ_ONLY, HEADER_VALUE_TRUE),
+    ];
+
+    // the first query only sets up the request, with the jwt claims in it.
+    let audit = ctx.query_audit();
+    let audited: Vec<_> = queries
+        .iter()
+        .skip(1)
+        .map(|query| audit.start(&query.query))
+        .collect();
+
+    let req_body = serde_json::to_string(&BatchQueryData { queries })
+        .map_err(|e| RestError::JsonConversion(JsonConversionError::ParseJsonError(e)))?;
+
+    let _metrics = client.metrics(ctx);
+
+    let proxy_response = make_raw_local_proxy_request(client, headers, req_body).await?;
+    let (response_parts, body) = proxy_response.into_parts();
+
+    let max_response = config.http_config.max_response_size_bytes;
+    let bytes = read_body_with_limit(body, max_response)
+        .await
+        .map_err(ReadPayloadError::from)?;
+
+    if response_parts.status.as_u16() > 399 {
+        let postgres_error: PostgresError = serde_json::from_slice(&bytes)
+            .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
+
+        // the queries run in a single transaction, which failed as a whole.
+        for mut query in audited {
+            query.fail(&postgres_error.code);
+            query.finish();
+        }
+        return Err(RestError::Postgres(postgres_error));
+    }
+
+    audited.into_iter().for_each(AuditedQuery::finish);
+    Ok(bytes)
+}
+
+/// Serve the OpenAPI description of the current schema, as visible to the role.
+#[allow(clippy::too_many_arguments)]
+async fn handle_openapi_inner(
+    config: &'static ProxyConfig,
+    ctx: &RequestContext,
+    request_headers: &HeaderMap,
+    path: &str,
+    response: Builder,
+    connection_string: &str,
+    api_prefix: &str,
+    auth_header: HeaderValue,
+    mut client: http_conn_pool::Client<LocalProxyClient>,
+    db_schema_cache: &DbSchemaCache,
+    endpoint_cache_key: &EndpointCacheKey,
+    entry: &Arc<SchemaEntry>,
+    schema_name: &str,
+    role: &str,
+    jwt_claims: Option<&JsonValue>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, RestError> {
+    let (api_config, db_schema_owned) = entry.as_ref();
+
+    let document = match db_schema_cache.get_openapi(endpoint_cache_key, entry, schema_name, role) {
+        Some(document) => document,
+        None => {
+            // the privileges are checked as the role, so the env query must run first
+            let headers: HashMap<&str, &str> = request_headers
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.to_str().unwrap_or("__BAD_HEADER__")))
+                .collect();
+            let headers_env = serde_json::to_string(&headers).unwrap_or("{}".to_string());
+            let jwt_claims_env = jwt_claims_env(jwt_claims, Some(role));
+            let search_path_str = search_path_env(schema_name, &api_config.db_extra_search_path);
+            let env: HashMap<&str, &str> = HashMap::from([
+                ("request.method", "GET"),
+                ("request.path", path),
+                ("request.headers", &headers_env),
+                ("request.cookies", "{}"),
+                ("request.get", "{}"),
+                ("request.jwt.claims", &jwt_claims_env),
+                ("search_path", &search_path_str),
+                ("role", role),
+            ]);
+
+            let (env_statement, env_parameters, _) = generate(fmt_env_query(&env));
+            let queries = vec![
+                QueryData {
+                    query: env_statement.into(),
+                    params: env_parameters
+                        .iter()
+                        .map(|p| to_sql_param(&p.to_param()))
+                        .collect(),
+                },
+                QueryData {
+                    query: openapi::PRIVILEGES_SQL.into(),
+                    params: vec![JsonValue::String(schema_name.to_string())],
+                },
+            ];
+
+            let bytes = run_read_only_queries(
+                config,
+                ctx,
+                &mut client,
+                connection_string,
+                auth_header,
+                queries,
+            )
+            .await?;
+
+            #[derive(Deserialize)]
+            struct QueryResults {
+                results: (EnvRows, PrivilegesRows),
+            }
+
+            #[derive(Deserialize)]
+            struct EnvRows {}
+
+            #[derive(Deserialize)]
+            struct PrivilegesRows {
+                rows: [Privileges; 1],
+            }
+
+            let QueryResults {
+                results: (_, PrivilegesRows { rows: [privileges] }),
+            } = serde_json::from_slice(&bytes)
+                .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
+
+            let host = request_headers
+                .get(HOST)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            let server_url = format!("https://{host}{}", api_prefix.trim_end_matches('/'));
+
+            let document = openapi::openapi_document(
+                db_schema_owned.borrow_schema(),
+                schema_name,
+                &privileges,
+                &server_url,
+            );
+            let document =
+                Bytes::from(serde_json::to_vec(&document).map_err(|e| {
+                    RestError::JsonConversion(JsonConversionError::ParseJsonError(e))
+                })?);
+            db_schema_cache.insert_openapi(
+                endpoint_cache_key,
+                entry,
+                schema_name,
+                role,
+                document.clone(),
+            );
+            document
+        }
+    };
+
+    let mut response = response
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/openapi+json");
+    if api_config.db_schemas.len() > 1 {
+        response = response.header("Content-Profile", schema_name);
+    }
+
+    response
+        .body(Full::new(document).map_err(|never| match never {}).boxed())
+        .map_err(|_| {
+            RestError::SubzeroCore(InternalError {
+                message: "Failed to build response".to_string(),
+            })
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRequest {
+    query: String,
+    #[serde(default)]
+    operation_name: Option<String>,
+    #[serde(default)]
+    variables: Option<serde_json::Map<String, JsonValue>>,
+}
+
+/// Run a GraphQL query: every top level field is turned into a read of the
+/// matching table, all of them in the same read only transaction.
+#[allow(clippy::too_many_arguments)]
+async fn handle_graphql_inner(
+    config: &'static ProxyConfig,
+    ctx: &RequestContext,
+    parts: http::request::Parts,
+    original_body: Incoming,
+    response: Builder,
+    connection_string: &str,
+    auth_header: HeaderValue,
+    mut client: http_conn_pool::Client<LocalProxyClient>,
+    api_config: &ApiConfig,
+    db_schema: &DbSchema<'_>,
+    role: &str,
+    jwt_claims: Option<&JsonValue>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, RestError> {
+    if parts.method != Method::POST {
+        return Err(GraphqlError::Unsupported("requests other than POST").into());
+    }
+
+    let body_bytes = read_body_with_limit(original_body, config.http_config.max_request_size_bytes)
+        .await
+        .map_err(ReadPayloadError::from)?;
+    let request: GraphqlRequest = serde_json::from_slice(&body_bytes)
+        .map_err(|e| GraphqlError::InvalidRequest(e.to_string()))?;
+
+    let fields = graphql::parse_query(
+        &request.query,
+        request.operation_name.as_deref(),
+        &request.variables.unwrap_or_default(),
+    )?;
+
+    let db_schemas = &api_config.db_schemas;
+    let max_rows = api_config.db_max_rows.as_deref();
+    let db_allowed_select_functions = api_config
+        .db_allowed_select_functions
+        .iter()
+        .map(|s| s.as_str())
+        .collect::<Vec<_>>();
+
+    // graphql queries only read, so the schema is picked like for a GET
+    let schema_name = &DbSchema::pick_current_schema(db_schemas, "GET", &parts.headers)?;
+    let path = parts.uri.path();
+
+    let headers: HashMap<&str, &str> = parts
+        .headers
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.to_str().unwrap_or("__BAD_HEADER__")))
+        .collect();
+
+    // construct the env (passed in to the sql context as GUCs)
+    let headers_env = serde_json::to_string(&headers).unwrap_or("{}".to_string());
+    let jwt_claims_env = jwt_claims_env(jwt_claims, Some(role));
+    let search_path_str = search_path_env(schema_name, &api_config.db_extra_search_path);
+    let env: HashMap<&str, &str> = HashMap::from([
+        ("request.method", "POST"),
+        ("request.path", path),
+        ("request.headers", &headers_env),
+        ("request.cookies", "{}"),
+        ("request.get", "{}"),
+        ("request.jwt.claims", &jwt_claims_env),
+        ("search_path", &search_path_str),
+        ("role", role),
+    ]);
+
+    let (env_statement, env_parameters, _) = generate(fmt_env_query(&env));
+    let mut queries = vec![QueryData {
+        query: env_statement.into(),
+        params: env_parameters
+            .iter()
+            .map(|p| to_sql_param(&p.to_param()))
+            .collect(),
+    }];
+
+    // each field goes through the same parsing, permission checks and sql
+    // generation as the equivalent rest request
+    for field in &fields {
+        let table_query = graphql::to_table_query(field)?;
+        let get = table_query
+            .params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        let mut api_request = parse(
+            schema_name,
+            &table_query.table,
+            db_schema,
+            "GET",
+            path,
+            get,
+            None,
+            headers.clone(),
+            HashMap::new(),
+            max_rows,
+        )
+        .map_err(RestError::SubzeroCore)?;
+
+        replace_select_star(db_schema, schema_name, role, &mut api_request.query)?;
+        check_safe_functions(&api_request, &db_allowed_select_functions)?;
+
+        let (main_statement, main_parameters, _) = generate(fmt_main_query(
+            db_schema,
+            api_request.schema_name,
+            &api_request,
+            &env,
+        )?);
+        queries.push(QueryData {
+            query: main_statement.into(),
+            params: main_parameters
+                .iter()
+                .map(|p| to_sql_param(&p.to_param()))
+                .collect(),
+        });
+    }
+
+    let bytes = run_read_only_queries(
+        config,
+        ctx,
+        &mut client,
+        connection_string,
+        auth_header,
+        queries,
+    )
+    .await?;
+
+    #[derive(Deserialize)]
+    struct QueryResults {
+        /// the env query, then one main query per field.
+        results: Vec<Rows>,
+    }
+
+    #[derive(Deserialize)]
+    struct Rows {
+        rows: Vec<Row>,
+    }
+
+    #[derive(Deserialize)]
+    struct Row {
+        body: Option<String>,
+    }
+
+    let QueryResults { results } = serde_json::from_slice(&bytes)
+        .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
+
+    let mut data = IndexMap::new();
+    for (field, rows) in fields.iter().zip(results.into_iter().skip(1)) {
+        let body = rows
+            .rows
+            .into_iter()
+            .next()
+            .and_then(|row| row.body)
+            .unwrap_or_else(|| "[]".to_string());
+        let body = RawValue::from_string(body)
+            .map_err(|e| RestError::SubzeroCore(JsonDeserialize { source: e }))?;
+        data.insert(field.response_key(), body);
+    }
+
+    let response_body = serde_json::to_string(&json!({ "data": data }))
+        .map_err(|e| RestError::JsonConversion(JsonConversionError::ParseJsonError(e)))?;
+
+    let mut response = response
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json");
+    if db_schemas.len() > 1 {
+        response = response.header("Content-Profile", schema_name.as_str());
+    }
+
+    response
+        .body(
+            Full::new(Bytes::from(response_body))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .map_err(|_| {
+            RestError::SubzeroCore(InternalError {
+                message: "Failed to build response".to_string(),
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_role_from_claims() {
+        let claims = json!({
+            "role": "web_user",
+            "app_metadata": { "roles": ["admin", "user"] },
+            "https://example.com/claims": { "role": "editor" },
+            "level": 1,
+        });
+
+        assert_eq!(role_from_claims(&claims, ".role"), Some("web_user"));
+        assert_eq!(
+            role_from_claims(&claims, ".app_metadata.roles[1]"),
+            Some("user")
+        );
+        assert_eq!(
+            role_from_claims(&claims, r#"."https://example.com/claims".role"#),
+            Some("editor")
+        );
+        assert_eq!(role_from_claims(&claims, ".level"), None);
+        assert_eq!(role_from_claims(&claims, ".missing"), None);
+        assert_eq!(role_from_claims(&claims, "role"), None);
+    }
+}
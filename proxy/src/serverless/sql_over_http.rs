@@ -7,19 +7,23 @@
 use http::Method;
 use http::header::AUTHORIZATION;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Full};
+use http_body_util::{BodyExt, Full, StreamBody};
 use http_utils::error::ApiError;
-use hyper::body::Incoming;
+use hyper::body::{Frame, Incoming};
 use hyper::http::{HeaderName, HeaderValue};
 use hyper::{Request, Response, StatusCode, header};
 use indexmap::IndexMap;
 use postgres_client::error::{DbError, ErrorPosition, SqlState};
-use postgres_client::{GenericClient, IsolationLevel, NoTls, ReadyForQueryStatus, Transaction};
+use postgres_client::types::Format;
+use postgres_client::{
+    Column, GenericClient, IsolationLevel, NoTls, ReadyForQueryStatus, RowStream,
+};
 use serde_json::Value;
 use serde_json::value::RawValue;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::{self, Instant};
 use tokio_util::sync::CancellationToken;
-use tracing::{Level, debug, error, info};
+use tracing::{Instrument, Level, debug, error, info};
 use typed_json::json;
 
 use super::backend::{LocalProxyConnError, PoolingBackend};
@@ -27,15 +31,25 @@
 use super::conn_pool_lib::{self, ConnInfo};
 use super::error::{ConnInfoError, HttpCodeError, ReadPayloadError};
 use super::http_util::{
-    ALLOW_POOL, ARRAY_MODE, CONN_STRING, NEON_REQUEST_ID, RAW_TEXT_OUTPUT, TXN_DEFERRABLE,
-    TXN_ISOLATION_LEVEL, TXN_READ_ONLY, get_conn_info, json_response, uuid_to_header_value,
+    ALLOW_POOL, ARRAY_MODE, BINARY_OUTPUT, CONN_STRING, NEON_REQUEST_ID, RAW_TEXT_OUTPUT,
+    STREAM_OUTPUT, TXN_DEFERRABLE, TXN_ISOLATION_LEVEL, TXN_READ_ONLY, TXN_SESSION, TYPED_MODE,
+    get_conn_info, json_response, uuid_to_header_value,
+};
+use super::json::{
+    JsonConversionError, ParamType, TypedParamsError, ValueFormat, json_to_pg_text,
+    param_types_to_oids, pg_row_to_json, typed_params_to_pg_text,
 };
-use super::json::{JsonConversionError, json_to_pg_text, pg_text_row_to_json};
+use super::txn_session::TxnSessionError;
 use crate::auth::backend::ComputeCredentialKeys;
+#[cfg(feature = "rest_broker")]
+use crate::cache::query::QueryCache;
 use crate::config::{HttpConfig, ProxyConfig};
 use crate::context::RequestContext;
+use crate::context::audit::{AuditedQuery, QueryAudit};
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use crate::http::read_body_with_limit;
+#[cfg(feature = "rest_broker")]
+use crate::intern::EndpointIdInt;
 use crate::metrics::{HttpDirection, Metrics};
 use crate::serverless::backend::HttpConnError;
 use crate::usage_metrics::{MetricCounter, MetricCounterRecorder};
@@ -50,6 +64,9 @@ struct QueryData {
     params: Vec<Option<String>>,
     #[serde(default)]
     array_mode: Option<bool>,
+    /// Parameter types, only allowed in typed mode.
+    #[serde(default)]
+    types: Option<Vec<ParamType>>,
 }
 
 #[derive(serde::Deserialize)]
@@ -178,7 +195,14 @@ fn get<'a, T: Default>(db: Option<&'a DbError>, x: impl FnOnce(&'a DbError) -> T
                 None => (None, None, None),
             };
 
-            let code = get(db_error, |db| db.code().code());
+            let session_limit_code;
+            let code = match &e {
+                SqlOverHttpError::ConnectCompute(HttpConnError::SessionLimit(limit)) => {
+                    session_limit_code = limit.sql_state();
+                    std::str::from_utf8(&session_limit_code).unwrap_or_default()
+                }
+                _ => get(db_error, |db| db.code().code()),
+            };
             let severity = get(db_error, |db| db.severity());
             let detail = get(db_error, |db| db.detail());
             let hint = get(db_error, |db| db.hint());
@@ -260,6 +284,16 @@ pub(crate) enum SqlOverHttpError {
     ResponseTooLarge(usize),
     #[error("invalid isolation level")]
     InvalidIsolationLevel,
+    #[error("invalid transaction session, expected 'begin' or session token")]
+    InvalidTxnSession,
+    #[error("{0}")]
+    TxnSession(#[from] TxnSessionError),
+    #[error("{0}")]
+    TypedParams(#[from] TypedParamsError),
+    #[error(
+        "streaming output is only supported for single queries outside of transaction sessions"
+    )]
+    StreamingUnsupported,
     /// for queries our customers choose to run
     #[error("{0}")]
     Postgres(#[source] postgres_client::Error),
@@ -280,6 +314,10 @@ fn get_error_kind(&self) -> ErrorKind {
             SqlOverHttpError::ConnInfo(e) => e.get_error_kind(),
             SqlOverHttpError::ResponseTooLarge(_) => ErrorKind::User,
             SqlOverHttpError::InvalidIsolationLevel => ErrorKind::User,
+            SqlOverHttpError::InvalidTxnSession => ErrorKind::User,
+            SqlOverHttpError::TxnSession(_) => ErrorKind::User,
+            SqlOverHttpError::TypedParams(_) => ErrorKind::User,
+            SqlOverHttpError::StreamingUnsupported => ErrorKind::User,
             // customer initiated SQL errors.
             SqlOverHttpError::Postgres(p) => {
                 if p.as_db_error().is_some() {
@@ -311,6 +349,10 @@ fn to_string_client(&self) -> String {
             SqlOverHttpError::ConnInfo(c) => c.to_string_client(),
             SqlOverHttpError::ResponseTooLarge(_) => self.to_string(),
             SqlOverHttpError::InvalidIsolationLevel => self.to_string(),
+            SqlOverHttpError::InvalidTxnSession => self.to_string(),
+            SqlOverHttpError::TxnSession(_) => self.to_string(),
+            SqlOverHttpError::TypedParams(_) => self.to_string(),
+            SqlOverHttpError::StreamingUnsupported => self.to_string(),
             SqlOverHttpError::Postgres(p) => p.to_string(),
             SqlOverHttpError::InternalPostgres(p) => p.to_string(),
             SqlOverHttpError::JsonConversion(_) => "could not parse postgres response".to_string(),
@@ -323,6 +365,9 @@ impl HttpCodeError for SqlOverHttpError {
     fn get_http_status_code(&self) -> StatusCode {
         match self {
             SqlOverHttpError::ReadPayload(e) => e.get_http_status_code(),
+            SqlOverHttpError::ConnectCompute(HttpConnError::SessionLimit(_)) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             SqlOverHttpError::ConnectCompute(h) => match h.get_error_kind() {
                 ErrorKind::User => StatusCode::BAD_REQUEST,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
@@ -330,6 +375,10 @@ fn get_http_status_code(&self) -> StatusCode {
             SqlOverHttpError::ConnInfo(_) => StatusCode::BAD_REQUEST,
             SqlOverHttpError::ResponseTooLarge(_) => StatusCode::INSUFFICIENT_STORAGE,
             SqlOverHttpError::InvalidIsolationLevel => StatusCode::BAD_REQUEST,
+            SqlOverHttpError::InvalidTxnSession => StatusCode::BAD_REQUEST,
+            SqlOverHttpError::TxnSession(_) => StatusCode::NOT_FOUND,
+            SqlOverHttpError::TypedParams(_) => StatusCode::BAD_REQUEST,
+            SqlOverHttpError::StreamingUnsupported => StatusCode::BAD_REQUEST,
             SqlOverHttpError::Postgres(_) => StatusCode::BAD_REQUEST,
             SqlOverHttpError::InternalPostgres(_) => StatusCode::INTERNAL_SERVER_ERROR,
             SqlOverHttpError::JsonConversion(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -357,11 +406,23 @@ fn get_error_kind(&self) -> ErrorKind {
 
 #[derive(Clone, Copy, Debug)]
 struct HttpHeaders {
-    raw_output: bool,
+    value_format: ValueFormat,
+    typed_mode: bool,
     default_array_mode: bool,
     txn_isolation_level: Option<IsolationLevel>,
     txn_read_only: bool,
     txn_deferrable: bool,
+    txn_session: Option<TxnSessionMode>,
+    stream_output: bool,
+}
+
+/// Interactive transaction requested with [`TXN_SESSION`] header.
+#[derive(Clone, Copy, Debug)]
+enum TxnSessionMode {
+    /// Start a transaction and keep it open after the request.
+    Begin,
+    /// Run the request in the transaction of this session.
+    Continue(uuid::Uuid),
 }
 
 impl HttpHeaders {
@@ -370,6 +431,19 @@ fn try_parse(headers: &hyper::http::HeaderMap) -> Result<Self, SqlOverHttpError>
         // strictly 'true' assumed to be false.
         let raw_output = headers.get(&RAW_TEXT_OUTPUT) == Some(&HEADER_VALUE_TRUE);
         let default_array_mode = headers.get(&ARRAY_MODE) == Some(&HEADER_VALUE_TRUE);
+        let typed_mode = headers.get(&TYPED_MODE) == Some(&HEADER_VALUE_TRUE);
+        let binary_output = headers.get(&BINARY_OUTPUT) == Some(&HEADER_VALUE_TRUE);
+        let stream_output =
+            headers.get(&STREAM_OUTPUT) == Some(&HEADER_VALUE_TRUE) || accepts_ndjson(headers);
+        let value_format = if binary_output {
+            ValueFormat::Binary
+        } else if raw_output {
+            ValueFormat::RawText
+        } else if typed_mode {
+            ValueFormat::Typed
+        } else {
+            ValueFormat::Json
+        };
 
         // isolation level, read only and deferrable
         let txn_isolation_level = match headers.get(&TXN_ISOLATION_LEVEL) {
@@ -382,16 +456,51 @@ fn try_parse(headers: &hyper::http::HeaderMap) -> Result<Self, SqlOverHttpError>
         let txn_read_only = headers.get(&TXN_READ_ONLY) == Some(&HEADER_VALUE_TRUE);
         let txn_deferrable = headers.get(&TXN_DEFERRABLE) == Some(&HEADER_VALUE_TRUE);
 
+        let txn_session = match headers.get(&TXN_SESSION) {
+            Some(x) if x.as_bytes() == b"begin" => Some(TxnSessionMode::Begin),
+            Some(x) => Some(TxnSessionMode::Continue(
+                x.to_str()
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or(SqlOverHttpError::InvalidTxnSession)?,
+            )),
+            None => None,
+        };
+
         Ok(Self {
-            raw_output,
+            value_format,
+            typed_mode,
             default_array_mode,
             txn_isolation_level,
             txn_read_only,
             txn_deferrable,
+            txn_session,
+            stream_output,
         })
     }
 }
 
+/// Whether the client has asked for a streamed response with `Accept` header.
+/// Quality values are not compared, anything but `q=0` counts.
+fn accepts_ndjson(headers: &hyper::http::HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            media_type.eq_ignore_ascii_case(NDJSON_CONTENT_TYPE)
+                && !params.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                })
+        })
+}
+
 fn map_header_to_isolation_level(level: &HeaderValue) -> Option<IsolationLevel> {
     match level.as_bytes() {
         b"Serializable" => Some(IsolationLevel::Serializable),
@@ -458,7 +567,7 @@ async fn handle_db_inner(
     config: &'static ProxyConfig,
     ctx: &RequestContext,
     request: Request<Incoming>,
-    conn_info: ConnInfo,
+    mut conn_info: ConnInfo,
     auth: AuthData,
     backend: Arc<PoolingBackend>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, SqlOverHttpError> {
@@ -473,6 +582,12 @@ async fn handle_db_inner(
         || headers.get(&ALLOW_POOL) == Some(&HEADER_VALUE_TRUE);
 
     let parsed_headers = HttpHeaders::try_parse(headers)?;
+    let purge = QueryCachePurge::new(config, &conn_info, parsed_headers.txn_read_only);
+
+    // read-only transactions can be served by a read replica of the branch.
+    if parsed_headers.txn_read_only {
+        conn_info.user_info.options = conn_info.user_info.options.with_read_only();
+    }
 
     let mut request_len = 0;
     let fetch_and_process_request = Box::pin(
@@ -497,6 +612,7 @@ async fn handle_db_inner(
         .map_err(SqlOverHttpError::from),
     );
 
+    let session_conn_info = conn_info.clone();
     let authenticate_and_connect = Box::pin(
         async {
             let keys = match auth {
@@ -510,6 +626,16 @@ async fn handle_db_inner(
                     .map_err(HttpConnError::AuthError)?,
             };
 
+            let session = backend.acquire_session(ctx, &conn_info.user_info).await?;
+
+            // Requests of interactive transaction are still authenticated,
+            // but run on the connection pinned to it.
+            if let Some(TxnSessionMode::Continue(token)) = parsed_headers.txn_session {
+                let client = backend.txn_sessions.take(token, &conn_info)?;
+                ctx.success();
+                return Ok((client, session));
+            }
+
             let client = match keys.keys {
                 ComputeCredentialKeys::JwtPayload(payload)
                     if backend.auth_backend.is_local_proxy() =>
@@ -538,12 +664,12 @@ async fn handle_db_inner(
             // not strictly necessary to mark success here,
             // but it's just insurance for if we forget it somewhere else
             ctx.success();
-            Ok::<_, SqlOverHttpError>(client)
+            Ok::<_, SqlOverHttpError>((client, session))
         }
         .map_err(SqlOverHttpError::from),
     );
 
-    let (payload, mut client) = match run_until_cancelled(
+    let (payload, (mut client, session)) = match run_until_cancelled(
         // Run both operations in parallel
         try_join(
             pin!(fetch_and_process_request),
@@ -557,37 +683,123 @@ async fn handle_db_inner(
         None => return Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Connect)),
     };
 
+    let queries = match &payload {
+        Payload::Single(_) => 1,
+        Payload::Batch(batch) => batch.queries.len() as u32,
+    };
+    if let Err(e) = session.try_queries(queries) {
+        // keep the transaction for when the client retries
+        if let Some(TxnSessionMode::Continue(token)) = parsed_headers.txn_session {
+            backend.txn_sessions.put(token, session_conn_info, client);
+        }
+        return Err(HttpConnError::from(e).into());
+    }
+
     let mut response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json");
 
+    let metrics = client.metrics(ctx);
+    let audit = ctx.query_audit();
+
+    if parsed_headers.stream_output {
+        let Payload::Single(stmt) = payload else {
+            return Err(SqlOverHttpError::StreamingUnsupported);
+        };
+        if parsed_headers.txn_session.is_some() {
+            return Err(SqlOverHttpError::StreamingUnsupported);
+        }
+
+        metrics.record_ingress(request_len as u64);
+        let body = stream_query(
+            &config.http_config,
+            cancel,
+            client,
+            stmt,
+            parsed_headers,
+            metrics,
+            audit,
+            purge,
+        )
+        .await?;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(body)
+            .expect("building response payload should not fail"));
+    }
+
     // Now execute the query and return the result.
-    let json_output = match payload {
-        Payload::Single(stmt) => {
-            stmt.process(&config.http_config, cancel, &mut client, parsed_headers)
-                .await?
+    let json_output = if let Some(txn_session) = parsed_headers.txn_session {
+        let (json_output, status) = process_in_txn_session(
+            &config.http_config,
+            cancel,
+            &mut client,
+            parsed_headers,
+            payload,
+            txn_session,
+            &audit,
+        )
+        .await?;
+
+        // Keep the connection pinned until the client finishes the
+        // transaction.
+        if status != ReadyForQueryStatus::Idle {
+            let token = match txn_session {
+                TxnSessionMode::Begin => backend.txn_sessions.open(session_conn_info, client),
+                TxnSessionMode::Continue(token) => {
+                    backend.txn_sessions.put(token, session_conn_info, client);
+                    token
+                }
+            };
+            response = response.header(TXN_SESSION.clone(), uuid_to_header_value(token));
+        } else {
+            info!("transaction session finished");
+            purge.purge();
         }
-        Payload::Batch(statements) => {
-            if parsed_headers.txn_read_only {
-                response = response.header(TXN_READ_ONLY.clone(), &HEADER_VALUE_TRUE);
-            }
-            if parsed_headers.txn_deferrable {
-                response = response.header(TXN_DEFERRABLE.clone(), &HEADER_VALUE_TRUE);
-            }
-            if let Some(txn_isolation_level) = parsed_headers
-                .txn_isolation_level
-                .and_then(map_isolation_level_to_headers)
-            {
-                response = response.header(TXN_ISOLATION_LEVEL.clone(), txn_isolation_level);
+        json_output
+    } else {
+        match payload {
+            Payload::Single(stmt) => {
+                stmt.process(
+                    &config.http_config,
+                    cancel,
+                    &mut client,
+                    parsed_headers,
+                    &audit,
+                )
+                .await?
             }
+            Payload::Batch(statements) => {
+                if parsed_headers.txn_read_only {
+                    response = response.header(TXN_READ_ONLY.clone(), &HEADER_VALUE_TRUE);
+                }
+                if parsed_headers.txn_deferrable {
+                    response = response.header(TXN_DEFERRABLE.clone(), &HEADER_VALUE_TRUE);
+                }
+                if let Some(txn_isolation_level) = parsed_headers
+                    .txn_isolation_level
+                    .and_then(map_isolation_level_to_headers)
+                {
+                    response = response.header(TXN_ISOLATION_LEVEL.clone(), txn_isolation_level);
+                }
 
-            statements
-                .process(&config.http_config, cancel, &mut client, parsed_headers)
-                .await?
+                statements
+                    .process(
+                        &config.http_config,
+                        cancel,
+                        &mut client,
+                        parsed_headers,
+                        &audit,
+                    )
+                    .await?
+            }
         }
     };
-
-    let metrics = client.metrics(ctx);
+    // the statements are committed by now.
+    if parsed_headers.txn_session.is_none() {
+        purge.purge();
+    }
 
     let len = json_output.len();
     let response = response
@@ -613,14 +825,62 @@ async fn handle_db_inner(
     Ok(response)
 }
 
+/// Purge of the cached REST reads of the endpoint after a request which may have
+/// written to it, see [`crate::cache::query`]. Only the requests sent with
+/// [`TXN_READ_ONLY`] are known not to write.
+#[derive(Default)]
+struct QueryCachePurge {
+    #[cfg(feature = "rest_broker")]
+    target: Option<(Arc<QueryCache>, EndpointIdInt)>,
+}
+
+impl QueryCachePurge {
+    fn new(config: &ProxyConfig, conn_info: &ConnInfo, read_only: bool) -> Self {
+        #[cfg(feature = "rest_broker")]
+        {
+            let endpoint = &conn_info.user_info.endpoint;
+            let target = config
+                .rest_config
+                .query_cache
+                .clone()
+                .filter(|_| {
+                    !read_only
+                        && config
+                            .rest_config
+                            .query_cache_endpoints
+                            .is_enabled(endpoint)
+                })
+                .map(|cache| (cache, EndpointIdInt::from(endpoint)));
+            Self { target }
+        }
+        #[cfg(not(feature = "rest_broker"))]
+        {
+            let _ = (config, conn_info, read_only);
+            Self::default()
+        }
+    }
+
+    fn purge(&self) {
+        #[cfg(feature = "rest_broker")]
+        if let Some((cache, endpoint)) = &self.target {
+            cache.purge_endpoint(*endpoint);
+        }
+    }
+}
+
 static HEADERS_TO_FORWARD: &[&HeaderName] = &[
     &AUTHORIZATION,
     &CONN_STRING,
     &RAW_TEXT_OUTPUT,
     &ARRAY_MODE,
+    &TYPED_MODE,
+    &BINARY_OUTPUT,
+    &STREAM_OUTPUT,
+    &header::ACCEPT,
     &TXN_ISOLATION_LEVEL,
     &TXN_READ_ONLY,
     &TXN_DEFERRABLE,
+    &TXN_SESSION,
 ];
 
 async fn handle_auth_broker_inner(
@@ -635,6 +895,10 @@ async fn handle_auth_broker_inner(
         .await
         .map_err(HttpConnError::from)?;
 
+    // the queries are only parsed by local_proxy, count the request as one
+    let session = backend.acquire_session(ctx, &conn_info.user_info).await?;
+    session.try_queries(1).map_err(HttpConnError::from)?;
+
     let mut client = backend.connect_to_local_proxy(ctx, conn_info).await?;
 
     let local_proxy_uri = ::http::Uri::from_static("http://proxy.local/sql");
@@ -675,6 +939,7 @@ async fn process(
         cancel: CancellationToken,
         client: &mut Client,
         parsed_headers: HttpHeaders,
+        audit: &QueryAudit,
     ) -> Result<String, SqlOverHttpError> {
         let (inner, mut discard) = client.inner();
         let cancel_token = inner.cancel_token();
@@ -687,7 +952,8 @@ async fn process(
                 &mut *inner,
                 self,
                 json::ValueSer::new(&mut json_buf),
-                parsed_headers
+                parsed_headers,
+                audit,
             )),
             pin!(cancel.cancelled()),
         )
@@ -750,6 +1016,7 @@ async fn process(
         cancel: CancellationToken,
         client: &mut Client,
         parsed_headers: HttpHeaders,
+        audit: &QueryAudit,
     ) -> Result<String, SqlOverHttpError> {
         info!("starting transaction");
         let (inner, mut discard) = client.inner();
@@ -781,10 +1048,11 @@ async fn process(
             &mut transaction,
             self,
             parsed_headers,
+            audit,
         )
         .await
         {
-            Ok(json_output) => {
+            Ok((json_output, _status)) => {
                 info!("commit");
                 transaction
                     .commit()
@@ -815,27 +1083,117 @@ async fn process(
     }
 }
 
-async fn query_batch(
+/// Run request queries in interactive transaction, starting it first for
+/// [`TxnSessionMode::Begin`]. Unlike with [`BatchQueryData`], queries are not
+/// wrapped into a transaction of their own. On any error the connection is
+/// discarded, which rolls the transaction back.
+async fn process_in_txn_session(
+    config: &'static HttpConfig,
+    cancel: CancellationToken,
+    client: &mut Client,
+    parsed_headers: HttpHeaders,
+    payload: Payload,
+    mode: TxnSessionMode,
+    audit: &QueryAudit,
+) -> Result<(String, ReadyForQueryStatus), SqlOverHttpError> {
+    let (inner, mut discard) = client.inner();
+    let cancel_token = inner.cancel_token();
+
+    if let TxnSessionMode::Begin = mode {
+        info!("starting transaction session");
+        let mut builder = inner.build_transaction();
+        if let Some(isolation_level) = parsed_headers.txn_isolation_level {
+            builder = builder.isolation_level(isolation_level);
+        }
+        if parsed_headers.txn_read_only {
+            builder = builder.read_only(true);
+        }
+        if parsed_headers.txn_deferrable {
+            builder = builder.deferrable(true);
+        }
+        builder
+            .start()
+            .await
+            .inspect_err(|_| discard.discard())
+            .map_err(SqlOverHttpError::Postgres)?
+            .detach();
+    }
+
+    let result = match payload {
+        Payload::Single(stmt) => {
+            let mut json_buf = vec![];
+            let res = match select(
+                pin!(query_to_json(
+                    config,
+                    &mut *inner,
+                    stmt,
+                    json::ValueSer::new(&mut json_buf),
+                    parsed_headers,
+                    audit,
+                )),
+                pin!(cancel.cancelled()),
+            )
+            .await
+            {
+                Either::Left((res, _not_yet_cancelled)) => res,
+                Either::Right((_cancelled, _query)) => {
+                    Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres))
+                }
+            };
+            res.map(|status| {
+                let json_output = String::from_utf8(json_buf).expect("json should be valid utf8");
+                (json_output, status)
+            })
+        }
+        Payload::Batch(statements) => {
+            query_batch_to_json(
+                config,
+                cancel,
+                &mut *inner,
+                statements,
+                parsed_headers,
+                audit,
+            )
+            .await
+        }
+    };
+
+    if let Err(err) = &result {
+        if let SqlOverHttpError::Cancelled(_) = err {
+            tracing::info!("cancelling query");
+            if let Err(err) = cancel_token.cancel_query(NoTls).await {
+                tracing::warn!(?err, "could not cancel query");
+            }
+        }
+        discard.discard();
+    }
+    result
+}
+
+async fn query_batch<T: GenericClient>(
     config: &'static HttpConfig,
     cancel: CancellationToken,
-    transaction: &mut Transaction<'_>,
+    client: &mut T,
     queries: BatchQueryData,
     parsed_headers: HttpHeaders,
     results: &mut json::ListSer<'_>,
-) -> Result<(), SqlOverHttpError> {
+    audit: &QueryAudit,
+) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+    let mut status = ReadyForQueryStatus::Unknown;
     for stmt in queries.queries {
         let query = pin!(query_to_json(
             config,
-            transaction,
+            client,
             stmt,
             results.entry(),
             parsed_headers,
+            audit,
         ));
         let cancelled = pin!(cancel.cancelled());
         let res = select(query, cancelled).await;
         match res {
             // TODO: maybe we should check that the transaction bit is set here
-            Either::Left((Ok(_), _cancelled)) => {}
+            Either::Left((Ok(s), _cancelled)) => status = s,
             Either::Left((Err(e), _cancelled)) => {
                 return Err(e);
             }
@@ -845,44 +1203,60 @@ async fn query_batch(
         }
     }
 
-    Ok(())
+    Ok(status)
 }
 
-async fn query_batch_to_json(
+/// Run the queries and return their results along with the connection
+/// status after the last one.
+async fn query_batch_to_json<T: GenericClient>(
     config: &'static HttpConfig,
     cancel: CancellationToken,
-    tx: &mut Transaction<'_>,
+    client: &mut T,
     queries: BatchQueryData,
     headers: HttpHeaders,
-) -> Result<String, SqlOverHttpError> {
+    audit: &QueryAudit,
+) -> Result<(String, ReadyForQueryStatus), SqlOverHttpError> {
+    let mut status = ReadyForQueryStatus::Unknown;
     let json_output = json::value_to_string!(|obj| json::value_as_object!(|obj| {
         let results = obj.key("results");
         json::value_as_list!(|results| {
-            query_batch(config, cancel, tx, queries, headers, results).await?;
+            status = query_batch(config, cancel, client, queries, headers, results, audit).await?;
         });
     }));
 
-    Ok(json_output)
+    Ok((json_output, status))
 }
 
-async fn query_to_json<T: GenericClient>(
-    config: &'static HttpConfig,
-    client: &mut T,
-    data: QueryData,
-    output: json::ValueSer<'_>,
+/// Send the query with parameters converted according to the typed mode and
+/// return the stream of its rows along with their format.
+async fn start_query<'c, T: GenericClient>(
+    client: &'c mut T,
+    query: &str,
+    mut params: Vec<Option<String>>,
+    types: Option<&[ParamType]>,
     parsed_headers: HttpHeaders,
-) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
-    let query_start = Instant::now();
+) -> Result<(RowStream<'c>, Format), SqlOverHttpError> {
+    let param_types = match types {
+        Some(_) if !parsed_headers.typed_mode => return Err(TypedParamsError::NotTypedMode.into()),
+        Some(types) => param_types_to_oids(types)?,
+        None => vec![],
+    };
+    typed_params_to_pg_text(&param_types, &mut params)?;
+    let output_format = match parsed_headers.value_format {
+        ValueFormat::Binary => Format::Binary,
+        _ => Format::Text,
+    };
 
-    let mut output = json::ObjectSer::new(output);
-    let mut row_stream = client
-        .query_raw_txt(&data.query, data.params)
+    let row_stream = client
+        .query_raw_typed_txt(query, &param_types, params, output_format)
         .await
         .map_err(SqlOverHttpError::Postgres)?;
-    let query_acknowledged = Instant::now();
+    Ok((row_stream, output_format))
+}
 
-    let mut json_fields = output.key("fields").list();
-    for c in row_stream.statement.columns() {
+fn fields_to_json(output: json::ValueSer<'_>, columns: &[Column], output_format: Format) {
+    let mut json_fields = output.list();
+    for c in columns {
         let json_field = json_fields.entry();
         json::value_as_object!(|json_field| {
             json_field.entry("name", c.name());
@@ -891,13 +1265,76 @@ async fn query_to_json<T: GenericClient>(
             json_field.entry("columnID", c.column_id());
             json_field.entry("dataTypeSize", c.type_size());
             json_field.entry("dataTypeModifier", c.type_modifier());
-            json_field.entry("format", "text");
+            json_field.entry(
+                "format",
+                match output_format {
+                    Format::Text => "text",
+                    Format::Binary => "binary",
+                },
+            );
         });
     }
     json_fields.finish();
+}
+
+/// Split the command tag into the command name and number of rows affected.
+fn parse_command_tag(command_tag: &str) -> (&str, Option<i64>) {
+    let mut command_tag_split = command_tag.split(' ');
+    let command_tag_name = command_tag_split.next().unwrap_or_default();
+    let command_tag_count = if command_tag_name == "INSERT" {
+        // INSERT returns OID first and then number of rows
+        command_tag_split.nth(1)
+    } else {
+        // other commands return number of rows (if any)
+        command_tag_split.next()
+    }
+    .and_then(|s| s.parse::<i64>().ok());
+    (command_tag_name, command_tag_count)
+}
+
+async fn query_to_json<T: GenericClient>(
+    config: &'static HttpConfig,
+    client: &mut T,
+    data: QueryData,
+    output: json::ValueSer<'_>,
+    parsed_headers: HttpHeaders,
+    audit: &QueryAudit,
+) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+    let mut query = audit.start(&data.query);
+    let res = run_query_to_json(config, client, data, output, parsed_headers, &mut query).await;
+    audit_result(query, &res);
+    res
+}
+
+async fn run_query_to_json<T: GenericClient>(
+    config: &'static HttpConfig,
+    client: &mut T,
+    data: QueryData,
+    output: json::ValueSer<'_>,
+    parsed_headers: HttpHeaders,
+    query: &mut AuditedQuery,
+) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+    let query_start = Instant::now();
+
+    let mut output = json::ObjectSer::new(output);
+    let (mut row_stream, output_format) = start_query(
+        client,
+        &data.query,
+        data.params,
+        data.types.as_deref(),
+        parsed_headers,
+    )
+    .await?;
+    let query_acknowledged = Instant::now();
+
+    fields_to_json(
+        output.key("fields"),
+        row_stream.statement.columns(),
+        output_format,
+    );
 
     let array_mode = data.array_mode.unwrap_or(parsed_headers.default_array_mode);
-    let raw_output = parsed_headers.raw_output;
+    let value_format = parsed_headers.value_format;
 
     // Manually drain the stream into a vector to leave row_stream hanging
     // around to get a command tag. Also check that the response is not too
@@ -907,7 +1344,7 @@ async fn query_to_json<T: GenericClient>(
     while let Some(row) = row_stream.next().await {
         let row = row.map_err(SqlOverHttpError::Postgres)?;
 
-        // we don't have a streaming response support yet so this is to prevent OOM
+        // the whole result is buffered so this is to prevent OOM
         // from a malicious query (eg a cross join)
         if json_rows.as_buffer().len() > config.max_response_size_bytes {
             return Err(SqlOverHttpError::ResponseTooLarge(
@@ -915,7 +1352,7 @@ async fn query_to_json<T: GenericClient>(
             ));
         }
 
-        pg_text_row_to_json(json_rows.entry(), &row, raw_output, array_mode)?;
+        pg_row_to_json(json_rows.entry(), &row, value_format, array_mode)?;
         rows += 1;
 
         // assumption: parsing pg text and converting to json takes CPU time.
@@ -932,16 +1369,7 @@ async fn query_to_json<T: GenericClient>(
 
     // grab the command tag and number of rows affected
     let command_tag = row_stream.command_tag.unwrap_or_default();
-    let mut command_tag_split = command_tag.split(' ');
-    let command_tag_name = command_tag_split.next().unwrap_or_default();
-    let command_tag_count = if command_tag_name == "INSERT" {
-        // INSERT returns OID first and then number of rows
-        command_tag_split.nth(1)
-    } else {
-        // other commands return number of rows (if any)
-        command_tag_split.next()
-    }
-    .and_then(|s| s.parse::<i64>().ok());
+    let (command_tag_name, command_tag_count) = parse_command_tag(&command_tag);
 
     info!(
         rows,
@@ -951,6 +1379,10 @@ async fn query_to_json<T: GenericClient>(
         response = ?(query_resp_end - query_start),
         "finished executing query"
     );
+    query.complete(
+        command_tag_name,
+        command_tag_count.and_then(|n| u64::try_from(n).ok()),
+    );
 
     output.entry("command", command_tag_name);
     output.entry("rowCount", command_tag_count);
@@ -960,7 +1392,299 @@ async fn query_to_json<T: GenericClient>(
     Ok(ready)
 }
 
-enum Client {
+/// Run a single query in the background and stream its result as newline
+/// delimited JSON: a line with the fields, a line per row, and a line with
+/// the command tag. Rows are sent as they arrive, and the query is paused
+/// while the client doesn't keep up.
+///
+/// Errors which happen before the first line is sent are returned as usual,
+/// later ones are sent as the last line, `{"error": {...}}`. Each line must
+/// fit into `max_response_size_bytes`, but the whole result doesn't have to.
+#[allow(clippy::too_many_arguments)]
+async fn stream_query(
+    config: &'static HttpConfig,
+    cancel: CancellationToken,
+    mut client: Client,
+    data: QueryData,
+    parsed_headers: HttpHeaders,
+    metrics: Arc<MetricCounter>,
+    audit: QueryAudit,
+    purge: QueryCachePurge,
+) -> Result<BoxBody<Bytes, hyper::Error>, SqlOverHttpError> {
+    let (mut writer, body) = NdjsonWriter::new(config.max_response_size_bytes);
+
+    tokio::spawn(
+        async move {
+            let (inner, mut discard) = client.inner();
+            let cancel_token = inner.cancel_token();
+            let client_gone = writer.tx.clone();
+
+            let res = {
+                let query = pin!(query_to_ndjson(
+                    &mut *inner,
+                    data,
+                    parsed_headers,
+                    &mut writer,
+                    &audit,
+                ));
+                // stop when the client goes away as well
+                let cancelled = pin!(async {
+                    tokio::select! {
+                        () = cancel.cancelled() => {}
+                        () = client_gone.closed() => {}
+                    }
+                });
+                match select(query, cancelled).await {
+                    Either::Left((res, _not_yet_cancelled)) => res,
+                    Either::Right((_cancelled, _query)) => {
+                        Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres))
+                    }
+                }
+            };
+
+            match res {
+                Ok(_) => purge.purge(),
+                Err(e) => {
+                    if let SqlOverHttpError::Cancelled(_) = e {
+                        tracing::info!("cancelling query");
+                        if let Err(err) = cancel_token.cancel_query(NoTls).await {
+                            tracing::warn!(?err, "could not cancel query");
+                        }
+                    }
+                    discard.discard();
+                    writer.fail(e).await;
+                }
+            }
+
+            metrics.record_egress(writer.sent as u64);
+            Metrics::get()
+                .proxy
+                .http_conn_content_length_bytes
+                .observe(HttpDirection::Response, writer.sent as f64);
+        }
+        .in_current_span(),
+    );
+
+    body.into_body().await
+}
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Lines of streamed response waiting to be sent to the client.
+const NDJSON_BUFFERED_LINES: usize = 16;
+
+/// Sending side of a streamed response, one JSON value per line.
+struct NdjsonWriter {
+    tx: mpsc::Sender<Bytes>,
+    /// Tells the handler whether to respond with the stream or with an error.
+    started: Option<oneshot::Sender<Result<(), SqlOverHttpError>>>,
+    max_line_size: usize,
+    sent: usize,
+}
+
+/// Receiving side of a streamed response.
+struct NdjsonBody {
+    started: oneshot::Receiver<Result<(), SqlOverHttpError>>,
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl NdjsonWriter {
+    fn new(max_line_size: usize) -> (Self, NdjsonBody) {
+        let (tx, rx) = mpsc::channel(NDJSON_BUFFERED_LINES);
+        let (started_tx, started) = oneshot::channel();
+        let writer = NdjsonWriter {
+            tx,
+            started: Some(started_tx),
+            max_line_size,
+            sent: 0,
+        };
+        (writer, NdjsonBody { started, rx })
+    }
+
+    /// Let the handler respond, errors are sent as the last line from now on.
+    fn start(&mut self) {
+        if let Some(started) = self.started.take() {
+            // handler might be gone already, the next send fails then
+            let _ = started.send(Ok(()));
+        }
+    }
+
+    async fn send(&mut self, mut line: Vec<u8>) -> Result<(), SqlOverHttpError> {
+        if line.len() > self.max_line_size {
+            return Err(SqlOverHttpError::ResponseTooLarge(self.max_line_size));
+        }
+        line.push(b'\n');
+        self.sent += line.len();
+        self.tx
+            .send(Bytes::from(line))
+            .await
+            .map_err(|_| SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres))
+    }
+
+    async fn fail(&mut self, e: SqlOverHttpError) {
+        match self.started.take() {
+            Some(started) => {
+                // handler might be gone already, nothing to do then
+                let _ = started.send(Err(e));
+            }
+            None => {
+                info!(error = %e, "streamed query failed");
+                let mut line = error_to_ndjson(&e);
+                line.push(b'\n');
+                self.sent += line.len();
+                let _ = self.tx.send(Bytes::from(line)).await;
+            }
+        }
+    }
+}
+
+impl NdjsonBody {
+    /// Wait until the query has started, and return its lines as the body.
+    async fn into_body(self) -> Result<BoxBody<Bytes, hyper::Error>, SqlOverHttpError> {
+        match self.started.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(e),
+            // task has panicked
+            Err(_) => return Err(SqlOverHttpError::Cancelled(SqlOverHttpCancel::Postgres)),
+        }
+
+        let mut rx = self.rx;
+        let frames = futures::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+                .map(|line| line.map(|line| Ok::<_, hyper::Error>(Frame::data(line))))
+        });
+        Ok(StreamBody::new(frames).boxed())
+    }
+}
+
+async fn query_to_ndjson(
+    client: &mut postgres_client::Client,
+    data: QueryData,
+    parsed_headers: HttpHeaders,
+    writer: &mut NdjsonWriter,
+    audit: &QueryAudit,
+) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+    let mut query = audit.start(&data.query);
+    let res = run_query_to_ndjson(client, data, parsed_headers, writer, &mut query).await;
+    audit_result(query, &res);
+    res
+}
+
+async fn run_query_to_ndjson(
+    client: &mut postgres_client::Client,
+    data: QueryData,
+    parsed_headers: HttpHeaders,
+    writer: &mut NdjsonWriter,
+    query: &mut AuditedQuery,
+) -> Result<ReadyForQueryStatus, SqlOverHttpError> {
+    let query_start = Instant::now();
+
+    let (mut row_stream, output_format) = start_query(
+        client,
+        &data.query,
+        data.params,
+        data.types.as_deref(),
+        parsed_headers,
+    )
+    .await?;
+    let query_acknowledged = Instant::now();
+
+    let array_mode = data.array_mode.unwrap_or(parsed_headers.default_array_mode);
+    let value_format = parsed_headers.value_format;
+
+    let line = json::value_to_vec!(|obj| json::value_as_object!(|obj| {
+        fields_to_json(
+            obj.key("fields"),
+            row_stream.statement.columns(),
+            output_format,
+        );
+        obj.entry("rowAsArray", array_mode);
+    }));
+    writer.start();
+    writer.send(line).await?;
+
+    let mut rows = 0;
+    while let Some(row) = row_stream.next().await {
+        let row = row.map_err(SqlOverHttpError::Postgres)?;
+        let line = json::value_to_vec!(|v| pg_row_to_json(v, &row, value_format, array_mode)?);
+        writer.send(line).await?;
+        rows += 1;
+
+        // see the comment in query_to_json
+        tokio::task::consume_budget().await;
+    }
+
+    let query_resp_end = Instant::now();
+
+    let ready = row_stream.status;
+    let command_tag = row_stream.command_tag.unwrap_or_default();
+    let (command_tag_name, command_tag_count) = parse_command_tag(&command_tag);
+
+    info!(
+        rows,
+        ?ready,
+        command_tag,
+        acknowledgement = ?(query_acknowledged - query_start),
+        response = ?(query_resp_end - query_start),
+        "finished streaming query"
+    );
+    query.complete(
+        command_tag_name,
+        command_tag_count.and_then(|n| u64::try_from(n).ok()),
+    );
+
+    writer
+        .send(command_tag_to_ndjson(command_tag_name, command_tag_count))
+        .await?;
+
+    Ok(ready)
+}
+
+/// Last line of a streamed response.
+fn command_tag_to_ndjson(command_tag_name: &str, command_tag_count: Option<i64>) -> Vec<u8> {
+    json::value_to_vec!(|obj| json::value_as_object!(|obj| {
+        obj.entry("command", command_tag_name);
+        obj.entry("rowCount", command_tag_count);
+    }))
+}
+
+/// Record the outcome of the query in the audit log. Queries which didn't get
+/// an answer from the database are recorded as aborted.
+fn audit_result<T>(mut query: AuditedQuery, res: &Result<T, SqlOverHttpError>) {
+    match res {
+        Ok(_) => query.finish(),
+        Err(SqlOverHttpError::Postgres(e)) => {
+            if let Some(db_error) = e.as_db_error() {
+                query.fail(db_error.code().code());
+                query.finish();
+            }
+        }
+        Err(_) => {}
+    }
+}
+
+/// Error which happened in the middle of a streamed response.
+fn error_to_ndjson(e: &SqlOverHttpError) -> Vec<u8> {
+    let db_error = match e {
+        SqlOverHttpError::Postgres(e) => e.as_db_error(),
+        _ => None,
+    };
+    let message = match db_error {
+        Some(db_error) => db_error.message().to_owned(),
+        None => e.to_string_client(),
+    };
+    let code = db_error.map(|db| db.code().code());
+
+    json::value_to_vec!(|obj| json::value_as_object!(|obj| {
+        let error = obj.key("error");
+        json::value_as_object!(|error| {
+            error.entry("message", message.as_str());
+            error.entry("code", code);
+        });
+    }))
+}
+
+pub(crate) enum Client {
     Remote(conn_pool_lib::Client<postgres_client::Client>),
     Local(conn_pool_lib::Client<postgres_client::Client>),
 }
@@ -1005,6 +1729,158 @@ fn discard(&mut self) {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_txn_session_header() {
+        let mut headers = hyper::http::HeaderMap::new();
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(parsed.txn_session.is_none());
+
+        headers.insert(&TXN_SESSION, HeaderValue::from_static("begin"));
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(matches!(parsed.txn_session, Some(TxnSessionMode::Begin)));
+
+        let token = uuid::Uuid::new_v4();
+        headers.insert(&TXN_SESSION, uuid_to_header_value(token));
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(matches!(parsed.txn_session, Some(TxnSessionMode::Continue(t)) if t == token));
+
+        headers.insert(&TXN_SESSION, HeaderValue::from_static("commit"));
+        assert!(matches!(
+            HttpHeaders::try_parse(&headers),
+            Err(SqlOverHttpError::InvalidTxnSession)
+        ));
+    }
+
+    #[test]
+    fn test_value_format_headers() {
+        let mut headers = hyper::http::HeaderMap::new();
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert_eq!(parsed.value_format, ValueFormat::Json);
+        assert!(!parsed.typed_mode);
+
+        headers.insert(&TYPED_MODE, HEADER_VALUE_TRUE);
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert_eq!(parsed.value_format, ValueFormat::Typed);
+        assert!(parsed.typed_mode);
+
+        headers.insert(&RAW_TEXT_OUTPUT, HEADER_VALUE_TRUE);
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert_eq!(parsed.value_format, ValueFormat::RawText);
+
+        headers.insert(&BINARY_OUTPUT, HEADER_VALUE_TRUE);
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert_eq!(parsed.value_format, ValueFormat::Binary);
+        assert!(parsed.typed_mode);
+    }
+
+    #[test]
+    fn test_stream_output_headers() {
+        let mut headers = hyper::http::HeaderMap::new();
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(!parsed.stream_output);
+
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(!parsed.stream_output);
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/json;q=0.5, Application/X-NDJSON"),
+        );
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(parsed.stream_output);
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/x-ndjson; q=0, application/json"),
+        );
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(!parsed.stream_output);
+
+        headers.remove(header::ACCEPT);
+        headers.insert(&STREAM_OUTPUT, HEADER_VALUE_TRUE);
+        let parsed = HttpHeaders::try_parse(&headers).unwrap();
+        assert!(parsed.stream_output);
+    }
+
+    async fn read_ndjson(body: NdjsonBody) -> Result<Vec<Value>, SqlOverHttpError> {
+        let body = body.into_body().await?;
+        let body = body.collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.ends_with('\n'));
+        Ok(body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_lines() {
+        let (mut writer, body) = NdjsonWriter::new(1024);
+        writer.start();
+        writer
+            .send(br#"{"fields":[],"rowAsArray":true}"#.to_vec())
+            .await
+            .unwrap();
+        writer.send(br#"[1,"a"]"#.to_vec()).await.unwrap();
+        writer.send(br#"[2,"b"]"#.to_vec()).await.unwrap();
+        writer
+            .send(command_tag_to_ndjson("SELECT", Some(2)))
+            .await
+            .unwrap();
+        let sent = writer.sent;
+        drop(writer);
+
+        let lines = read_ndjson(body).await.unwrap();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({"fields": [], "rowAsArray": true}),
+                serde_json::json!([1, "a"]),
+                serde_json::json!([2, "b"]),
+                serde_json::json!({"command": "SELECT", "rowCount": 2}),
+            ]
+        );
+        let len = lines
+            .iter()
+            .map(|line| line.to_string().len() + 1)
+            .sum::<usize>();
+        assert_eq!(sent, len);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_error_after_rows() {
+        let (mut writer, body) = NdjsonWriter::new(16);
+        writer.start();
+        writer.send(br#"[1]"#.to_vec()).await.unwrap();
+        let e = writer.send(vec![b'1'; 17]).await.unwrap_err();
+        assert!(matches!(e, SqlOverHttpError::ResponseTooLarge(16)));
+        let message = e.to_string_client();
+        writer.fail(e).await;
+        drop(writer);
+
+        let lines = read_ndjson(body).await.unwrap();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!([1]),
+                serde_json::json!({"error": {"message": message, "code": null}}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_error_before_start() {
+        let (mut writer, body) = NdjsonWriter::new(16);
+        writer.fail(SqlOverHttpError::ResponseTooLarge(16)).await;
+        drop(writer);
+
+        assert!(matches!(
+            read_ndjson(body).await,
+            Err(SqlOverHttpError::ResponseTooLarge(16))
+        ));
+    }
+
     #[test]
     fn test_payload() {
         let payload = "{\"query\":\"SELECT * FROM users WHERE name = ?\",\"params\":[\"test\"],\"arrayMode\":true}";
@@ -1015,10 +1891,12 @@ fn test_payload() {
                 query,
                 params,
                 array_mode,
+                types,
             }) => {
                 assert_eq!(query, "SELECT * FROM users WHERE name = ?");
                 assert_eq!(params, vec![Some(String::from("test"))]);
                 assert!(array_mode.unwrap());
+                assert!(types.is_none());
             }
             Payload::Batch(_) => {
                 panic!("deserialization failed: case with single query, one param, and array mode")
@@ -1051,10 +1929,12 @@ fn test_payload() {
                 query,
                 params,
                 array_mode,
+                types,
             }) => {
                 assert_eq!(query, "SELECT 1");
                 assert_eq!(params, vec![]);
                 assert!(array_mode.is_none());
+                assert!(types.is_none());
             }
             Payload::Batch(_) => panic!("deserialization failed: case with only one query"),
         }
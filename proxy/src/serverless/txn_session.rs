@@ -0,0 +1,155 @@
+//! Interactive transactions over SQL over HTTP.
+//!
+//! A request with `Neon-Txn-Session: begin` starts a transaction, runs its
+//! queries in it and, instead of releasing the connection, pins it to a
+//! random session token which is returned in the same header. Requests
+//! carrying the token run in that transaction. Once the transaction is over
+//! (the client ran COMMIT or ROLLBACK) the connection goes back to the pool
+//! and the token is no longer returned. Any error also ends the session:
+//! the connection is discarded, so the server rolls the transaction back.
+//!
+//! Sessions idle for longer than `txn_session_idle_timeout` are rolled back.
+//! They are also registered in the cancel set, so they get shed together
+//! with HTTP connections when the proxy has too many clients.
+
+use std::time::Duration;
+
+use clashmap::ClashMap;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use super::cancel_set::CancelGuard;
+use super::conn_pool_lib::ConnInfo;
+use super::sql_over_http::Client;
+use crate::config::HttpConfig;
+use crate::metrics::Metrics;
+
+struct TxnSession {
+    conn_info: ConnInfo,
+    client: Client,
+    last_used: Instant,
+    cancel: CancellationToken,
+    _cancel_guard: CancelGuard<'static>,
+}
+
+impl TxnSession {
+    fn belongs_to(&self, conn_info: &ConnInfo) -> bool {
+        self.conn_info.user_info.endpoint == conn_info.user_info.endpoint
+            && self.conn_info.user_info.user == conn_info.user_info.user
+            && self.conn_info.dbname == conn_info.dbname
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TxnSessionError {
+    #[error("transaction session {0} not found, it might have been closed after idle timeout")]
+    NotFound(Uuid),
+}
+
+/// Connections pinned to open interactive transactions, by session token.
+pub(crate) struct TxnSessions {
+    sessions: ClashMap<Uuid, TxnSession>,
+    config: &'static HttpConfig,
+}
+
+impl TxnSessions {
+    pub(crate) fn new(config: &'static HttpConfig) -> Self {
+        Self {
+            sessions: ClashMap::new(),
+            config,
+        }
+    }
+
+    /// Pin `client` in open transaction to a new session, returns its token.
+    pub(crate) fn open(&self, conn_info: ConnInfo, client: Client) -> Uuid {
+        let token = Uuid::new_v4();
+        info!(%token, "opened transaction session");
+        self.put(token, conn_info, client);
+        token
+    }
+
+    /// Take the session connection for running a request. Session is absent
+    /// until the request puts it back, so concurrent requests with the same
+    /// token fail.
+    pub(crate) fn take(
+        &self,
+        token: Uuid,
+        conn_info: &ConnInfo,
+    ) -> Result<Client, TxnSessionError> {
+        // A token of another endpoint or role is treated as unknown, so
+        // leaking it doesn't abort someone else's transaction.
+        let (_, session) = self
+            .sessions
+            .remove_if(&token, |_, session| session.belongs_to(conn_info))
+            .ok_or(TxnSessionError::NotFound(token))?;
+        Metrics::get().proxy.http_txn_sessions.get_metric().dec();
+        if session.cancel.is_cancelled() {
+            info!(%token, "transaction session was cancelled");
+            return Err(TxnSessionError::NotFound(token));
+        }
+        Ok(session.client)
+    }
+
+    /// Return connection of a session which is still in transaction.
+    pub(crate) fn put(&self, token: Uuid, conn_info: ConnInfo, client: Client) {
+        let cancel = CancellationToken::new();
+        let cancel_guard = self.config.cancel_set.insert(token, cancel.clone());
+        Metrics::get().proxy.http_txn_sessions.get_metric().inc();
+        self.sessions.insert(
+            token,
+            TxnSession {
+                conn_info,
+                client,
+                last_used: Instant::now(),
+                cancel,
+                _cancel_guard: cancel_guard,
+            },
+        );
+    }
+
+    /// Periodically roll back sessions which are idle for too long or were
+    /// cancelled. Dropped connections go back to the pool, which resets them
+    /// with ROLLBACK.
+    pub(crate) async fn gc_worker(&self) {
+        let idle_timeout = self.config.txn_session_idle_timeout;
+        let mut interval =
+            tokio::time::interval(std::cmp::max(idle_timeout / 4, Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            self.gc(idle_timeout);
+        }
+    }
+
+    fn gc(&self, idle_timeout: Duration) {
+        let mut removed = 0;
+        self.sessions.retain(|token, session| {
+            if session.cancel.is_cancelled() || session.last_used.elapsed() >= idle_timeout {
+                info!(%token, "closing idle transaction session");
+                removed += 1;
+                return false;
+            }
+            true
+        });
+        Metrics::get()
+            .proxy
+            .http_txn_sessions
+            .get_metric()
+            .dec_by(removed);
+        debug!(removed, "transaction sessions gc");
+    }
+
+    pub(crate) fn shutdown(&self) {
+        let mut removed = 0;
+        self.sessions.retain(|_, _| {
+            removed += 1;
+            false
+        });
+        Metrics::get()
+            .proxy
+            .http_txn_sessions
+            .get_metric()
+            .dec_by(removed);
+    }
+}
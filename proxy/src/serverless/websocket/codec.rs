@@ -0,0 +1,260 @@
+//! Server side of the websocket framing.
+//!
+//! <https://datatracker.ietf.org/doc/html/rfc6455#section-5.2>
+//!
+//! Unlike `framed_websockets`, it keeps the `RSV1` bit of the frames, which marks
+//! the messages compressed with permessage-deflate.
+
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Larger frames from the client are rejected.
+const MAX_FRAME_SIZE: usize = 64 << 20;
+/// Control frames can't be fragmented, nor be larger.
+const MAX_CONTROL_FRAME_SIZE: usize = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpCode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl OpCode {
+    fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = io::Error;
+
+    fn try_from(opcode: u8) -> io::Result<Self> {
+        match opcode {
+            0x0 => Ok(OpCode::Continuation),
+            0x1 => Ok(OpCode::Text),
+            0x2 => Ok(OpCode::Binary),
+            0x8 => Ok(OpCode::Close),
+            0x9 => Ok(OpCode::Ping),
+            0xa => Ok(OpCode::Pong),
+            _ => Err(io::Error::other(format!(
+                "invalid websocket opcode {opcode}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    /// Set on the first frame of the compressed messages.
+    pub(crate) rsv1: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: BytesMut,
+}
+
+impl Frame {
+    pub(crate) fn new(opcode: OpCode, payload: BytesMut) -> Self {
+        Self {
+            fin: true,
+            rsv1: false,
+            opcode,
+            payload,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct WebSocketCodec;
+
+impl Decoder for WebSocketCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Frame>> {
+        let [first, second, ..] = src[..] else {
+            return Ok(None);
+        };
+
+        let fin = first & 0x80 != 0;
+        let rsv1 = first & 0x40 != 0;
+        if first & 0x30 != 0 {
+            return Err(io::Error::other(
+                "reserved bits are set in the websocket frame",
+            ));
+        }
+        let opcode = OpCode::try_from(first & 0x0f)?;
+
+        // the frames of the clients are always masked.
+        if second & 0x80 == 0 {
+            return Err(io::Error::other("unmasked websocket frame from the client"));
+        }
+        let (len, header_len) = match second & 0x7f {
+            126 => {
+                let Some(len) = src.get(2..4) else {
+                    return Ok(None);
+                };
+                (u16::from_be_bytes([len[0], len[1]]) as usize, 4)
+            }
+            127 => {
+                let Some(len) = src.get(2..10) else {
+                    return Ok(None);
+                };
+                let len = u64::from_be_bytes(len.try_into().expect("slice is 8 bytes"));
+                (usize::try_from(len).unwrap_or(usize::MAX), 10)
+            }
+            len => (len as usize, 2),
+        };
+
+        if opcode.is_control() && (!fin || rsv1 || len > MAX_CONTROL_FRAME_SIZE) {
+            return Err(io::Error::other("invalid websocket control frame"));
+        }
+        if len > MAX_FRAME_SIZE {
+            return Err(io::Error::other("websocket frame is too large"));
+        }
+
+        let frame_len = header_len + 4 + len;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let mut mask = [0; 4];
+        mask.copy_from_slice(&src[header_len..header_len + 4]);
+        src.advance(header_len + 4);
+        let mut payload = src.split_to(len);
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+
+        Ok(Some(Frame {
+            fin,
+            rsv1,
+            opcode,
+            payload,
+        }))
+    }
+}
+
+impl Encoder<Frame> for WebSocketCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> io::Result<()> {
+        let len = frame.payload.len();
+        dst.reserve(len + 10);
+
+        let first = (u8::from(frame.fin) << 7) | (u8::from(frame.rsv1) << 6) | frame.opcode as u8;
+        dst.put_u8(first);
+        // the frames of the server are never masked.
+        if len < 126 {
+            dst.put_u8(len as u8);
+        } else if let Ok(len) = u16::try_from(len) {
+            dst.put_u8(126);
+            dst.put_u16(len);
+        } else {
+            dst.put_u8(127);
+            dst.put_u64(len as u64);
+        }
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+/// Encode the frame like a client does, with a mask.
+#[cfg(test)]
+pub(super) fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![first];
+    match payload.len() {
+        len @ 0..126 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_frames() {
+        let long = vec![7; 300];
+        let mut stream = client_frame(0x82, b"hello");
+        stream.extend(client_frame(0x42, &long));
+        stream.extend(client_frame(0x80, b""));
+        stream.extend(client_frame(0x89, b"ping"));
+
+        let mut codec = WebSocketCodec;
+        let mut src = BytesMut::new();
+        let mut frames = vec![];
+        // the frames can be split anywhere between reads
+        for b in stream {
+            src.put_u8(b);
+            while let Some(frame) = codec.decode(&mut src).unwrap() {
+                frames.push((frame.fin, frame.rsv1, frame.opcode, frame.payload.to_vec()));
+            }
+        }
+
+        assert_eq!(
+            frames,
+            [
+                (true, false, OpCode::Binary, b"hello".to_vec()),
+                (false, true, OpCode::Binary, long),
+                (true, false, OpCode::Continuation, vec![]),
+                (true, false, OpCode::Ping, b"ping".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_frames() {
+        for frame in [
+            // not masked
+            vec![0x82, 0x01, b'a'],
+            // rsv2
+            client_frame(0xa2, b"a"),
+            // unknown opcode
+            client_frame(0x83, b"a"),
+            // fragmented ping
+            client_frame(0x09, b"a"),
+        ] {
+            WebSocketCodec
+                .decode(&mut BytesMut::from(&frame[..]))
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn encode_frames() {
+        let mut dst = BytesMut::new();
+        let mut codec = WebSocketCodec;
+        codec
+            .encode(
+                Frame::new(OpCode::Binary, BytesMut::from(&b"hi"[..])),
+                &mut dst,
+            )
+            .unwrap();
+        assert_eq!(&dst[..], b"\x82\x02hi");
+
+        dst.clear();
+        let mut frame = Frame::new(OpCode::Binary, BytesMut::from(&[0; 200][..]));
+        frame.rsv1 = true;
+        codec.encode(frame, &mut dst).unwrap();
+        assert_eq!(&dst[..4], b"\xc2\x7e\x00\xc8");
+        assert_eq!(dst.len(), 204);
+    }
+}
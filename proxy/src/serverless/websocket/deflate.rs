@@ -0,0 +1,293 @@
+//! The permessage-deflate extension of websockets.
+//!
+//! <https://datatracker.ietf.org/doc/html/rfc7692>
+
+use std::io;
+
+use bytes::BytesMut;
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use super::MAX_MESSAGE_SIZE;
+
+pub(crate) const EXTENSION: &str = "permessage-deflate";
+
+/// Ending of the compressed messages, which is removed before they are sent.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+/// Smaller messages are sent uncompressed.
+const MIN_COMPRESSED_SIZE: usize = 64;
+
+/// Parameters of permessage-deflate agreed with the client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DeflateConfig {
+    /// The proxy resets the compression after each message.
+    server_no_context_takeover: bool,
+    /// The client resets the compression after each message.
+    client_no_context_takeover: bool,
+}
+
+impl DeflateConfig {
+    /// Accept the first of the offers of the client in `Sec-WebSocket-Extensions`
+    /// that the proxy supports.
+    pub(crate) fn negotiate<'a>(offers: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        offers
+            .into_iter()
+            .flat_map(|header| header.split(','))
+            .find_map(Self::accept)
+    }
+
+    fn accept(offer: &str) -> Option<Self> {
+        let mut params = offer.split(';').map(str::trim);
+        if params.next() != Some(EXTENSION) {
+            return None;
+        }
+
+        let mut config = Self::default();
+        let mut client_max_window_bits = false;
+        for param in params {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            };
+            let duplicate = match (name, value) {
+                ("server_no_context_takeover", None) => {
+                    std::mem::replace(&mut config.server_no_context_takeover, true)
+                }
+                ("client_no_context_takeover", None) => {
+                    std::mem::replace(&mut config.client_no_context_takeover, true)
+                }
+                // the proxy always compresses with the largest window.
+                ("server_max_window_bits", Some("15")) => false,
+                // the proxy can decompress with any window.
+                ("client_max_window_bits", bits) if bits.is_none_or(is_window_bits) => {
+                    std::mem::replace(&mut client_max_window_bits, true)
+                }
+                _ => return None,
+            };
+            if duplicate {
+                return None;
+            }
+        }
+        Some(config)
+    }
+
+    /// Response to the offer, for `Sec-WebSocket-Extensions`.
+    pub(crate) fn response(&self) -> &'static str {
+        match (
+            self.server_no_context_takeover,
+            self.client_no_context_takeover,
+        ) {
+            (false, false) => "permessage-deflate",
+            (true, false) => "permessage-deflate; server_no_context_takeover",
+            (false, true) => "permessage-deflate; client_no_context_takeover",
+            (true, true) => {
+                "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+            }
+        }
+    }
+}
+
+fn is_window_bits(bits: &str) -> bool {
+    matches!(bits.parse::<u8>(), Ok(8..=15))
+}
+
+/// Compression and decompression of the messages of a websocket.
+pub(crate) struct Deflate {
+    config: DeflateConfig,
+    compress: Compress,
+    decompress: Decompress,
+}
+
+impl Deflate {
+    pub(crate) fn new(config: DeflateConfig) -> Self {
+        Self {
+            config,
+            compress: Compress::new(Compression::fast(), false),
+            decompress: Decompress::new(false),
+        }
+    }
+
+    /// Compress the message, unless it's not worth it.
+    pub(crate) fn compress(&mut self, message: &[u8]) -> io::Result<Option<BytesMut>> {
+        if message.len() < MIN_COMPRESSED_SIZE {
+            return Ok(None);
+        }
+
+        let mut compressed = Vec::with_capacity(message.len() / 2 + TRAILER.len());
+        let mut consumed = 0;
+        loop {
+            if compressed.len() == compressed.capacity() {
+                compressed.reserve(1024);
+            }
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(&message[consumed..], &mut compressed, FlushCompress::Sync)
+                .map_err(io::Error::other)?;
+            consumed += (self.compress.total_in() - before) as usize;
+
+            // the flush is done once there's room left in the output.
+            if consumed == message.len() && compressed.len() < compressed.capacity() {
+                break;
+            }
+        }
+
+        if self.config.server_no_context_takeover {
+            self.compress.reset();
+        }
+
+        if !compressed.ends_with(&TRAILER) {
+            return Err(io::Error::other("deflate did not flush the message"));
+        }
+        compressed.truncate(compressed.len() - TRAILER.len());
+        Ok(Some(BytesMut::from(&compressed[..])))
+    }
+
+    /// Decompress the next frame of a compressed message. The last frame of the message is `fin`.
+    pub(crate) fn decompress(
+        &mut self,
+        frame: &[u8],
+        fin: bool,
+        message: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        self.decompress_into(frame, message)?;
+        if fin {
+            self.decompress_into(&TRAILER, message)?;
+            if self.config.client_no_context_takeover {
+                self.decompress.reset(false);
+            }
+        }
+        Ok(())
+    }
+
+    fn decompress_into(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        let mut consumed = 0;
+        loop {
+            if output.len() == output.capacity() {
+                if output.len() >= MAX_MESSAGE_SIZE {
+                    return Err(io::Error::other("decompressed message is too large"));
+                }
+                output.reserve((input.len() * 2).clamp(1024, MAX_MESSAGE_SIZE - output.len()));
+            }
+            let before = self.decompress.total_in();
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], output, FlushDecompress::Sync)
+                .map_err(io::Error::other)?;
+            consumed += (self.decompress.total_in() - before) as usize;
+
+            if status == Status::StreamEnd {
+                return Err(io::Error::other("unexpected end of the deflate stream"));
+            }
+            if consumed == input.len() && output.len() < output.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        let cases = [
+            (vec![], None),
+            (vec!["x-webkit-deflate-frame"], None),
+            (vec!["permessage-deflate"], Some(DeflateConfig::default())),
+            (
+                vec!["permessage-deflate; client_max_window_bits"],
+                Some(DeflateConfig::default()),
+            ),
+            (
+                // the first offer isn't supported
+                vec![
+                    "permessage-deflate; server_max_window_bits=10, permessage-deflate; server_no_context_takeover",
+                ],
+                Some(DeflateConfig {
+                    server_no_context_takeover: true,
+                    client_no_context_takeover: false,
+                }),
+            ),
+            (
+                vec![
+                    "foo",
+                    "permessage-deflate;client_no_context_takeover;server_no_context_takeover",
+                ],
+                Some(DeflateConfig {
+                    server_no_context_takeover: true,
+                    client_no_context_takeover: true,
+                }),
+            ),
+            (
+                vec!["permessage-deflate; server_no_context_takeover; server_no_context_takeover"],
+                None,
+            ),
+            (vec!["permessage-deflate; unknown"], None),
+        ];
+
+        for (offers, expected) in cases {
+            assert_eq!(
+                DeflateConfig::negotiate(offers.clone()),
+                expected,
+                "{offers:?}"
+            );
+        }
+
+        let config = DeflateConfig {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        };
+        assert_eq!(
+            config.response(),
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        for config in [
+            DeflateConfig::default(),
+            DeflateConfig {
+                server_no_context_takeover: true,
+                client_no_context_takeover: true,
+            },
+        ] {
+            // the messages of both sides use the same parameters here.
+            let mut server = Deflate::new(config);
+            let mut client = Deflate::new(config);
+
+            for _ in 0..3 {
+                let message = b"select * from very_long_table_name where id = 1; ".repeat(20);
+                let compressed = server.compress(&message).unwrap().unwrap();
+                assert!(compressed.len() < message.len() / 4);
+
+                // the message can be split in several frames.
+                let mut decompressed = vec![];
+                let (first, second) = compressed.split_at(compressed.len() / 2);
+                client.decompress(first, false, &mut decompressed).unwrap();
+                client.decompress(second, true, &mut decompressed).unwrap();
+                assert_eq!(decompressed, message);
+            }
+
+            assert_eq!(server.compress(b"select 1").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn decompression_bomb() {
+        let mut server = Deflate::new(DeflateConfig::default());
+        let mut client = Deflate::new(DeflateConfig::default());
+
+        let chunk = vec![0; 1 << 20];
+        let mut message = vec![];
+        let mut res = Ok(());
+        for _ in 0..=MAX_MESSAGE_SIZE >> 20 {
+            let compressed = server.compress(&chunk).unwrap().unwrap();
+            res = client.decompress(&compressed, false, &mut message);
+            if res.is_err() {
+                break;
+            }
+        }
+        res.unwrap_err();
+    }
+}
@@ -0,0 +1,592 @@
+mod codec;
+mod deflate;
+mod mux;
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use anyhow::Context as _;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Sink, Stream};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL,
+};
+use hyper::upgrade::OnUpgrade;
+use hyper_util::rt::TokioIo;
+use pin_project_lite::pin_project;
+use tokio::io::{self, AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::codec::Framed;
+use tracing::warn;
+
+use self::codec::{Frame, OpCode, WebSocketCodec};
+use self::deflate::{Deflate, DeflateConfig};
+use crate::cancellation::CancellationHandler;
+use crate::config::{HttpConfig, ProxyConfig};
+use crate::context::RequestContext;
+use crate::error::ReportableError;
+use crate::metrics::Metrics;
+use crate::pglb::{ClientMode, handle_connection};
+use crate::proxy::ErrorSource;
+use crate::rate_limiter::EndpointRateLimiter;
+
+/// Larger messages from the client are rejected, once reassembled and decompressed.
+pub(crate) const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// The extensions and subprotocol of a websocket, agreed on during the upgrade.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct WebSocketOptions {
+    deflate: Option<DeflateConfig>,
+    multiplexed: bool,
+}
+
+impl WebSocketOptions {
+    /// Accept the extensions and the subprotocol the client asked for, if they are enabled.
+    pub(crate) fn negotiate(config: &HttpConfig, headers: &HeaderMap) -> Self {
+        let values = |name: HeaderName| {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+        };
+
+        let deflate = if config.websocket_compression {
+            DeflateConfig::negotiate(values(SEC_WEBSOCKET_EXTENSIONS))
+        } else {
+            None
+        };
+        let multiplexed = config.websocket_multiplexing
+            && values(SEC_WEBSOCKET_PROTOCOL)
+                .flat_map(|protocols| protocols.split(','))
+                .any(|protocol| protocol.trim() == mux::PROTOCOL);
+
+        Self {
+            deflate,
+            multiplexed,
+        }
+    }
+
+    /// Headers of the upgrade response which confirm the options.
+    pub(crate) fn response_headers(&self, headers: &mut HeaderMap) {
+        if let Some(deflate) = &self.deflate {
+            headers.insert(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_static(deflate.response()),
+            );
+        }
+        if self.multiplexed {
+            headers.insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(mux::PROTOCOL),
+            );
+        }
+    }
+}
+
+/// The binary messages of a websocket. Pings are answered, and compressed or
+/// fragmented messages are returned whole.
+pub(crate) struct WebSocket<S> {
+    framed: Framed<S, WebSocketCodec>,
+    deflate: Option<Box<Deflate>>,
+    /// The fragments received so far of the current message.
+    message: Option<PartialMessage>,
+    /// Pong or close frame to send back to the client.
+    reply: Option<Frame>,
+    flush_reply: bool,
+    /// The client closed the websocket.
+    closed: bool,
+    close_sent: bool,
+}
+
+struct PartialMessage {
+    compressed: bool,
+    payload: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WebSocket<S> {
+    pub(crate) fn new(io: S, deflate: Option<DeflateConfig>) -> Self {
+        Self {
+            framed: Framed::new(io, WebSocketCodec),
+            deflate: deflate.map(|config| Box::new(Deflate::new(config))),
+            message: None,
+            reply: None,
+            flush_reply: false,
+            closed: false,
+            close_sent: false,
+        }
+    }
+
+    fn poll_reply(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.reply.is_some() {
+            ready!(Pin::new(&mut self.framed).poll_ready(cx))?;
+            if let Some(reply) = self.reply.take() {
+                Pin::new(&mut self.framed).start_send(reply)?;
+                self.flush_reply = true;
+            }
+        }
+        if self.flush_reply {
+            ready!(Pin::new(&mut self.framed).poll_flush(cx))?;
+            self.flush_reply = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Add the data frame to the current message. Returns the message once it's complete.
+    fn receive(&mut self, frame: Frame) -> io::Result<Option<Bytes>> {
+        let first = frame.opcode == OpCode::Binary;
+        if first == self.message.is_some() {
+            return Err(io::Error::other("unexpected websocket continuation frame"));
+        }
+        if frame.rsv1 && (!first || self.deflate.is_none()) {
+            return Err(io::Error::other("unexpected compressed websocket frame"));
+        }
+        if first && frame.fin && !frame.rsv1 {
+            return Ok(Some(frame.payload.freeze()));
+        }
+
+        let message = self.message.get_or_insert_with(|| PartialMessage {
+            compressed: frame.rsv1,
+            payload: vec![],
+        });
+        match &mut self.deflate {
+            Some(deflate) if message.compressed => {
+                deflate.decompress(&frame.payload, frame.fin, &mut message.payload)?;
+            }
+            _ => {
+                if message.payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                    return Err(io::Error::other("websocket message is too large"));
+                }
+                message.payload.extend_from_slice(&frame.payload);
+            }
+        }
+
+        if !frame.fin {
+            return Ok(None);
+        }
+        Ok(self.message.take().map(|message| message.payload.into()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocket<S> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let res = ready!(this.poll_reply(cx));
+            if this.closed {
+                // the client doesn't have to wait for its close frame to be echoed.
+                return Poll::Ready(None);
+            }
+            res?;
+
+            let Some(mut frame) = ready!(Pin::new(&mut this.framed).poll_next(cx)).transpose()?
+            else {
+                return Poll::Ready(None);
+            };
+            match frame.opcode {
+                OpCode::Ping => this.reply = Some(Frame::new(OpCode::Pong, frame.payload)),
+                OpCode::Pong => {}
+                OpCode::Close => {
+                    // echo the status code, after which the websocket is closed.
+                    frame.payload.truncate(2);
+                    if !this.close_sent {
+                        this.reply = Some(Frame::new(OpCode::Close, frame.payload));
+                        this.close_sent = true;
+                    }
+                    this.closed = true;
+                }
+                OpCode::Text => {
+                    // We expect to see only binary messages.
+                    let error = "unexpected text message in the websocket";
+                    warn!(length = frame.payload.len(), error);
+                    return Poll::Ready(Some(Err(io::Error::other(error))));
+                }
+                OpCode::Binary | OpCode::Continuation => {
+                    if let Some(message) = this.receive(frame)? {
+                        return Poll::Ready(Some(Ok(message)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<BytesMut> for WebSocket<S> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        Pin::new(&mut this.framed).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: BytesMut) -> io::Result<()> {
+        let this = self.get_mut();
+        let compressed = match &mut this.deflate {
+            Some(deflate) => deflate.compress(&message)?,
+            None => None,
+        };
+        let frame = match compressed {
+            Some(payload) => Frame {
+                rsv1: true,
+                ..Frame::new(OpCode::Binary, payload)
+            },
+            None => Frame::new(OpCode::Binary, message),
+        };
+        Pin::new(&mut this.framed).start_send(frame)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        Pin::new(&mut this.framed).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_reply(cx))?;
+        if !this.close_sent {
+            ready!(Pin::new(&mut this.framed).poll_ready(cx))?;
+            // normal closure
+            let status = BytesMut::from(&1000_u16.to_be_bytes()[..]);
+            Pin::new(&mut this.framed).start_send(Frame::new(OpCode::Close, status))?;
+            this.close_sent = true;
+        }
+        Pin::new(&mut this.framed).poll_close(cx)
+    }
+}
+
+pin_project! {
+    /// This is a wrapper around a [`WebSocket`] that
+    /// implements [`AsyncRead`] and [`AsyncWrite`].
+    pub(crate) struct WebSocketRw<S> {
+        #[pin]
+        stream: WebSocket<S>,
+        recv: Bytes,
+        send: BytesMut,
+    }
+}
+
+impl<S> WebSocketRw<S> {
+    pub(crate) fn new(stream: WebSocket<S>) -> Self {
+        Self {
+            stream,
+            recv: Bytes::new(),
+            send: BytesMut::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketRw<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let mut stream = this.stream;
+
+        ready!(stream.as_mut().poll_ready(cx))?;
+
+        this.send.put(buf);
+        stream.as_mut().start_send(this.send.split())?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let stream = self.project().stream;
+        stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let stream = self.project().stream;
+        stream.poll_close(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketRw<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let bytes = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = std::cmp::min(bytes.len(), buf.remaining());
+        buf.put_slice(&bytes[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncBufRead for WebSocketRw<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        // Please refer to poll_fill_buf's documentation.
+        const EOF: Poll<io::Result<&[u8]>> = Poll::Ready(Ok(&[]));
+
+        let mut this = self.project();
+        loop {
+            if !this.recv.chunk().is_empty() {
+                let chunk = (*this.recv).chunk();
+                return Poll::Ready(Ok(chunk));
+            }
+
+            match ready!(this.stream.as_mut().poll_next(cx)).transpose()? {
+                Some(message) => {
+                    debug_assert!(this.recv.is_empty());
+                    *this.recv = message;
+                }
+                None => return EOF,
+            }
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+        self.project().recv.advance(amount);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn serve_websocket(
+    config: &'static ProxyConfig,
+    auth_backend: &'static crate::auth::Backend<'static, ()>,
+    ctx: RequestContext,
+    websocket: OnUpgrade,
+    options: WebSocketOptions,
+    cancellation_handler: Arc<CancellationHandler>,
+    endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    hostname: Option<String>,
+    cancellations: tokio_util::task::task_tracker::TaskTracker,
+) -> anyhow::Result<()> {
+    let websocket = websocket.await?;
+    let websocket = WebSocket::new(TokioIo::new(websocket), options.deflate);
+
+    if options.multiplexed {
+        // each session of the websocket has its own context.
+        let res = mux::serve_multiplexed(
+            config,
+            auth_backend,
+            &ctx,
+            websocket,
+            cancellation_handler,
+            endpoint_rate_limiter,
+            hostname,
+            cancellations,
+        )
+        .await;
+        if res.is_ok() {
+            ctx.set_success();
+        }
+        return res;
+    }
+
+    serve_session(
+        config,
+        auth_backend,
+        ctx,
+        WebSocketRw::new(websocket),
+        cancellation_handler,
+        endpoint_rate_limiter,
+        hostname,
+        cancellations,
+    )
+    .await
+}
+
+/// Serve a postgres session over the client stream.
+#[allow(clippy::too_many_arguments)]
+async fn serve_session<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    config: &'static ProxyConfig,
+    auth_backend: &'static crate::auth::Backend<'static, ()>,
+    ctx: RequestContext,
+    client: S,
+    cancellation_handler: Arc<CancellationHandler>,
+    endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    hostname: Option<String>,
+    cancellations: tokio_util::task::task_tracker::TaskTracker,
+) -> anyhow::Result<()> {
+    let conn_gauge = Metrics::get()
+        .proxy
+        .client_connections
+        .guard(crate::metrics::Protocol::Ws);
+
+    let res = Box::pin(handle_connection(
+        config,
+        auth_backend,
+        &ctx,
+        cancellation_handler,
+        client,
+        ClientMode::Websockets { hostname },
+        endpoint_rate_limiter,
+        conn_gauge,
+        cancellations,
+    ))
+    .await;
+
+    match res {
+        Err(e) => {
+            ctx.set_error_kind(e.get_error_kind());
+            Err(e.into())
+        }
+        Ok(None) => {
+            ctx.set_success();
+            Ok(())
+        }
+        Ok(Some(p)) => {
+            ctx.set_success();
+            ctx.log_connect();
+            match p.proxy_pass().await {
+                Ok(()) => Ok(()),
+                Err(ErrorSource::Client(err)) => Err(err).context("client"),
+                Err(ErrorSource::Compute(err)) => Err(err).context("compute"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex};
+    use tokio::task::JoinSet;
+    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    use super::codec::client_frame;
+    use super::*;
+
+    #[tokio::test]
+    async fn websocket_stream_wrapper_happy_path() {
+        let (stream1, stream2) = duplex(1024);
+
+        let mut js = JoinSet::new();
+
+        js.spawn(async move {
+            let mut client = WebSocketStream::from_raw_socket(stream1, Role::Client, None).await;
+
+            client
+                .send(Message::Binary(b"hello world".to_vec()))
+                .await
+                .unwrap();
+
+            let message = client.next().await.unwrap().unwrap();
+            assert_eq!(message, Message::Binary(b"websockets are cool".to_vec()));
+
+            client.close(None).await.unwrap();
+        });
+
+        js.spawn(async move {
+            let mut rw = pin!(WebSocketRw::new(WebSocket::new(stream2, None)));
+
+            let mut buf = vec![0; 1024];
+            let n = rw.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"hello world");
+
+            rw.write_all(b"websockets are cool").await.unwrap();
+            rw.flush().await.unwrap();
+
+            let n = rw.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(n, 0);
+        });
+
+        js.join_next().await.unwrap().unwrap();
+        js.join_next().await.unwrap().unwrap();
+    }
+
+    /// Read the next frame the proxy sent to the client.
+    async fn read_frame(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        client.read_exact(&mut header).await.unwrap();
+        let len = match header[1] {
+            126 => usize::from(client.read_u16().await.unwrap()),
+            127 => client.read_u64().await.unwrap() as usize,
+            len => usize::from(len),
+        };
+        let mut payload = vec![0; len];
+        client.read_exact(&mut payload).await.unwrap();
+        (header[0], payload)
+    }
+
+    #[tokio::test]
+    async fn websocket_compression() {
+        let (mut client, server) = duplex(1 << 16);
+        let mut rw = pin!(WebSocketRw::new(WebSocket::new(
+            server,
+            Some(DeflateConfig::default())
+        )));
+        let mut client_deflate = Deflate::new(DeflateConfig::default());
+
+        let query = b"select * from a_table_with_a_long_name where a_column = 1; ".repeat(10);
+        for _ in 0..2 {
+            // the compressed message is fragmented, with a ping in between.
+            let compressed = client_deflate.compress(&query).unwrap().unwrap();
+            let (first, second) = compressed.split_at(10);
+            client.write_all(&client_frame(0x42, first)).await.unwrap();
+            client
+                .write_all(&client_frame(0x89, b"ping"))
+                .await
+                .unwrap();
+            client.write_all(&client_frame(0x80, second)).await.unwrap();
+
+            let mut buf = vec![0; query.len()];
+            rw.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, query);
+            assert_eq!(read_frame(&mut client).await, (0x8a, b"ping".to_vec()));
+
+            rw.write_all(&query).await.unwrap();
+            rw.flush().await.unwrap();
+            let (first, compressed) = read_frame(&mut client).await;
+            assert_eq!(first, 0xc2);
+            assert!(compressed.len() < query.len() / 4);
+            let mut message = vec![];
+            client_deflate
+                .decompress(&compressed, true, &mut message)
+                .unwrap();
+            assert_eq!(message, query);
+        }
+
+        // short messages are not compressed.
+        client
+            .write_all(&client_frame(0x82, b"sync"))
+            .await
+            .unwrap();
+        let mut buf = [0; 4];
+        rw.read_exact(&mut buf).await.unwrap();
+        rw.write_all(b"ready").await.unwrap();
+        rw.flush().await.unwrap();
+        assert_eq!(read_frame(&mut client).await, (0x82, b"ready".to_vec()));
+
+        client
+            .write_all(&client_frame(0x88, &1000_u16.to_be_bytes()))
+            .await
+            .unwrap();
+        assert_eq!(rw.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(
+            read_frame(&mut client).await,
+            (0x88, 1000_u16.to_be_bytes().to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn websocket_protocol_errors() {
+        for frames in [
+            // text messages
+            vec![client_frame(0x81, b"select 1")],
+            // compression wasn't negotiated
+            vec![client_frame(0xc2, b"select 1")],
+            // continuation without a message
+            vec![client_frame(0x80, b"select 1")],
+            // new message before the end of the previous one
+            vec![client_frame(0x02, b"sel"), client_frame(0x82, b"ect 1")],
+        ] {
+            let (mut client, server) = duplex(1024);
+            let mut rw = pin!(WebSocketRw::new(WebSocket::new(server, None)));
+            client.write_all(&frames.concat()).await.unwrap();
+            let mut buf = vec![];
+            rw.read_to_end(&mut buf).await.unwrap_err();
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! The `neon-mux-v1` subprotocol, which carries several postgres sessions over
+//! one websocket.
+//!
+//! Each binary message of the websocket belongs to one of the sessions:
+//!
+//! ```text
+//! stream id: u32 | kind: u8 | payload
+//! ```
+//!
+//! The client opens a session with `O`, sends the postgres messages of the
+//! session with `D`, and closes its side of the session with `C`. The proxy
+//! sends the responses with `D`, and `C` once the session is over, after which
+//! the id can be reused. Each session has its own startup, authentication and
+//! cancel key, like the sessions of separate websockets.
+//!
+//! A session which doesn't keep up with the data the client sends gets closed,
+//! instead of holding up the other sessions of the websocket.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
+use anyhow::{Context, bail, ensure};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinSet;
+use tracing::{Instrument, debug, warn};
+
+use super::{WebSocket, serve_session};
+use crate::cancellation::CancellationHandler;
+use crate::config::ProxyConfig;
+use crate::context::RequestContext;
+use crate::pglb::inprocess;
+use crate::rate_limiter::EndpointRateLimiter;
+
+pub(crate) const PROTOCOL: &str = "neon-mux-v1";
+
+const OPEN: u8 = b'O';
+const DATA: u8 = b'D';
+const CLOSE: u8 = b'C';
+
+/// More sessions at once over a websocket are rejected.
+const MAX_SESSIONS: usize = 32;
+/// Messages buffered for each session, and for the websocket.
+const CHANNEL_SIZE: usize = 16;
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+struct MuxMessage {
+    id: u32,
+    kind: u8,
+    payload: Bytes,
+}
+
+impl MuxMessage {
+    fn decode(mut message: Bytes) -> anyhow::Result<Self> {
+        ensure!(message.len() >= 5, "multiplexed message is too short");
+        let id = message.get_u32();
+        let kind = message.get_u8();
+        Ok(Self {
+            id,
+            kind,
+            payload: message,
+        })
+    }
+
+    fn encode(&self) -> BytesMut {
+        let mut message = BytesMut::with_capacity(self.payload.len() + 5);
+        message.put_u32(self.id);
+        message.put_u8(self.kind);
+        message.put_slice(&self.payload);
+        message
+    }
+}
+
+/// Serve the sessions the client opens over the websocket, until it's closed.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn serve_multiplexed<S: AsyncRead + AsyncWrite + Unpin>(
+    config: &'static ProxyConfig,
+    auth_backend: &'static crate::auth::Backend<'static, ()>,
+    ctx: &RequestContext,
+    websocket: WebSocket<S>,
+    cancellation_handler: Arc<CancellationHandler>,
+    endpoint_rate_limiter: Arc<EndpointRateLimiter>,
+    hostname: Option<String>,
+    cancellations: tokio_util::task::task_tracker::TaskTracker,
+) -> anyhow::Result<()> {
+    multiplex(websocket, |stream| {
+        let ctx = ctx.new_session();
+        let span = ctx.span();
+        serve_session(
+            config,
+            auth_backend,
+            ctx,
+            stream,
+            cancellation_handler.clone(),
+            endpoint_rate_limiter.clone(),
+            hostname.clone(),
+            cancellations.clone(),
+        )
+        .instrument(span)
+    })
+    .await
+}
+
+/// The state of a session which is not over yet.
+enum StreamState {
+    Open(mpsc::Sender<Bytes>),
+    /// The client closed its side of the session.
+    Closed,
+    /// The proxy closed the side of the client, as the session didn't keep up
+    /// with the data of the client. The client didn't see the close yet.
+    Reset,
+}
+
+/// Carry the sessions over the websocket, each served by `serve` from the time
+/// the client opens it.
+async fn multiplex<S, F>(
+    mut websocket: WebSocket<S>,
+    mut serve: impl FnMut(inprocess::Stream) -> F,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let (connector, mut acceptor) = inprocess::Connection::new();
+    let (outgoing_tx, mut outgoing) = mpsc::channel(CHANNEL_SIZE);
+    let mut streams: HashMap<u32, StreamState> = HashMap::new();
+    let mut pumps = JoinSet::new();
+    let mut sessions = JoinSet::new();
+
+    let res = async {
+        loop {
+            tokio::select! {
+                message = websocket.next() => {
+                    let Some(message) = message.transpose()? else {
+                        return anyhow::Ok(());
+                    };
+                    let message = MuxMessage::decode(message)?;
+                    match message.kind {
+                        OPEN => {
+                            ensure!(
+                                streams.len() < MAX_SESSIONS,
+                                "too many multiplexed sessions"
+                            );
+                            let Entry::Vacant(entry) = streams.entry(message.id) else {
+                                bail!("multiplexed stream {} is already open", message.id);
+                            };
+                            let stream = connector.open_stream().await?;
+                            // the other end is queued for the acceptor, serve it right away.
+                            let session = acceptor
+                                .accept_stream()
+                                .await?
+                                .context("in-process connection is closed")?;
+                            debug!(
+                                id = message.id,
+                                stream_id = %stream.id(),
+                                "opened multiplexed stream"
+                            );
+
+                            let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+                            entry.insert(StreamState::Open(tx));
+                            pumps.spawn(pump(message.id, stream, rx, outgoing_tx.clone()));
+                            sessions.spawn(serve(session));
+                        }
+                        DATA => {
+                            let Some(state) = streams.get_mut(&message.id) else {
+                                bail!("multiplexed stream {} is not open", message.id);
+                            };
+                            match state {
+                                // Waiting for a busy session would hold up the others, and
+                                // the responses it might be waiting for itself.
+                                StreamState::Open(tx) => {
+                                    let res = tx.try_send(message.payload);
+                                    if let Err(TrySendError::Full(_)) = res {
+                                        warn!(
+                                            id = message.id,
+                                            "multiplexed session doesn't keep up, closing it"
+                                        );
+                                        *state = StreamState::Reset;
+                                    }
+                                    // if the session is gone, its close is on the way.
+                                }
+                                StreamState::Closed => {
+                                    bail!("multiplexed stream {} is already closed", message.id);
+                                }
+                                StreamState::Reset => {}
+                            }
+                        }
+                        CLOSE => {
+                            let Some(state) = streams.get_mut(&message.id) else {
+                                bail!("multiplexed stream {} is not open", message.id);
+                            };
+                            match state {
+                                StreamState::Open(_) => *state = StreamState::Closed,
+                                StreamState::Closed => {
+                                    bail!("multiplexed stream {} is already closed", message.id);
+                                }
+                                StreamState::Reset => {}
+                            }
+                        }
+                        kind => bail!("unknown multiplexed message kind {kind}"),
+                    }
+                }
+                Some(message) = outgoing.recv() => {
+                    forward(&mut websocket, &mut streams, message).await?;
+                }
+                Some(res) = sessions.join_next() => log_session_result(res),
+            }
+        }
+    }
+    .await;
+
+    // The sessions see the end of their streams once the pumps are gone.
+    drop(streams);
+    pumps.shutdown().await;
+    while let Some(res) = sessions.join_next().await {
+        log_session_result(res);
+    }
+
+    res
+}
+
+/// Send the message of a session to the client.
+async fn forward<S: AsyncRead + AsyncWrite + Unpin>(
+    websocket: &mut WebSocket<S>,
+    streams: &mut HashMap<u32, StreamState>,
+    message: MuxMessage,
+) -> std::io::Result<()> {
+    if message.kind == CLOSE {
+        streams.remove(&message.id);
+    }
+    websocket.send(message.encode()).await
+}
+
+fn log_session_result(res: Result<anyhow::Result<()>, tokio::task::JoinError>) {
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("error in multiplexed websocket session: {e:#}"),
+        Err(e) => warn!("multiplexed websocket session panicked: {e}"),
+    }
+}
+
+/// Copy the data of the client to the session, and the data of the session to the client.
+async fn pump(
+    id: u32,
+    stream: inprocess::Stream,
+    mut incoming: mpsc::Receiver<Bytes>,
+    outgoing: mpsc::Sender<MuxMessage>,
+) {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let to_session = async move {
+        while let Some(data) = incoming.recv().await {
+            if writer.write_all(&data).await.is_err() {
+                return;
+            }
+        }
+        // the client closed its side of the session.
+        let _ = writer.shutdown().await;
+    };
+
+    let from_session = async move {
+        let mut buf = BytesMut::new();
+        loop {
+            buf.reserve(READ_BUFFER_SIZE);
+            match reader.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+            let data = MuxMessage {
+                id,
+                kind: DATA,
+                payload: buf.split().freeze(),
+            };
+            if outgoing.send(data).await.is_err() {
+                return;
+            }
+        }
+        let close = MuxMessage {
+            id,
+            kind: CLOSE,
+            payload: Bytes::new(),
+        };
+        let _ = outgoing.send(close).await;
+    };
+
+    tokio::join!(to_session, from_session);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+
+    #[test]
+    fn mux_messages() {
+        let message = MuxMessage {
+            id: 7,
+            kind: DATA,
+            payload: Bytes::from_static(b"Q\0\0\0\x0dselect 1\0"),
+        };
+        let encoded = message.encode();
+        assert_eq!(&encoded[..5], b"\0\0\0\x07D");
+        assert_eq!(MuxMessage::decode(encoded.freeze()).unwrap(), message);
+
+        MuxMessage::decode(Bytes::from_static(b"\0\0\0\x07")).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn pump_streams() {
+        let (connector, mut acceptor) = inprocess::Connection::new();
+        let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+        let (outgoing_tx, mut outgoing) = mpsc::channel(CHANNEL_SIZE);
+        let pump = tokio::spawn(pump(
+            3,
+            connector.open_stream().await.unwrap(),
+            rx,
+            outgoing_tx,
+        ));
+
+        // an echo session, which ends once the client closes its side.
+        let mut session = acceptor.accept_stream().await.unwrap().unwrap();
+        let session = tokio::spawn(async move {
+            let (mut reader, mut writer) = tokio::io::split(&mut session);
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        tx.send(Bytes::from_static(b"hello")).await.unwrap();
+        let message = outgoing.recv().await.unwrap();
+        assert_eq!((message.id, message.kind), (3, DATA));
+        assert_eq!(&message.payload[..], b"hello");
+
+        drop(tx);
+        session.await.unwrap();
+        let message = outgoing.recv().await.unwrap();
+        assert_eq!((message.id, message.kind), (3, CLOSE));
+        pump.await.unwrap();
+    }
+
+    fn message(id: u32, kind: u8, payload: &[u8]) -> Message {
+        let message = MuxMessage {
+            id,
+            kind,
+            payload: Bytes::copy_from_slice(payload),
+        };
+        Message::Binary(message.encode().to_vec())
+    }
+
+    #[tokio::test]
+    async fn stalled_session() {
+        let (client, server) = duplex(1 << 16);
+        let mut client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+
+        // the first session never reads, the others echo.
+        let stalled = CancellationToken::new();
+        let serve = {
+            let stalled = stalled.clone();
+            let mut opened = 0;
+            move |mut stream: inprocess::Stream| {
+                opened += 1;
+                let first = opened == 1;
+                let stalled = stalled.clone();
+                async move {
+                    if first {
+                        stalled.cancelled().await;
+                    } else {
+                        let (mut reader, mut writer) = tokio::io::split(&mut stream);
+                        tokio::io::copy(&mut reader, &mut writer).await?;
+                    }
+                    anyhow::Ok(())
+                }
+            }
+        };
+        let mux = tokio::spawn(multiplex(WebSocket::new(server, None), serve));
+
+        client.send(message(1, OPEN, b"")).await.unwrap();
+        client.send(message(2, OPEN, b"")).await.unwrap();
+        // more than the stream and the channel of the session can hold.
+        for _ in 0..64 {
+            client.send(message(1, DATA, &[0; 1024])).await.unwrap();
+        }
+
+        // the other session is still served.
+        client.send(message(2, DATA, b"hello")).await.unwrap();
+        let Message::Binary(reply) = client.next().await.unwrap().unwrap() else {
+            panic!("wrong message");
+        };
+        let reply = MuxMessage::decode(reply.into()).unwrap();
+        assert_eq!((reply.id, reply.kind), (2, DATA));
+        assert_eq!(&reply.payload[..], b"hello");
+
+        // the stalled session is closed once it's done.
+        stalled.cancel();
+        let Message::Binary(reply) = client.next().await.unwrap().unwrap() else {
+            panic!("wrong message");
+        };
+        let reply = MuxMessage::decode(reply.into()).unwrap();
+        assert_eq!((reply.id, reply.kind), (1, CLOSE));
+
+        client.close(None).await.unwrap();
+        mux.await.unwrap().unwrap();
+    }
+}
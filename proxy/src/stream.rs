@@ -3,6 +3,7 @@
 use std::{io, task};
 
 use rustls::ServerConfig;
+use rustls::pki_types::CertificateDer;
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio_rustls::server::TlsStream;
@@ -10,8 +11,7 @@
 use crate::error::{ErrorKind, ReportableError, UserFacingError};
 use crate::metrics::Metrics;
 use crate::pqproto::{
-    BeMessage, FE_PASSWORD_MESSAGE, FeStartupPacket, SQLSTATE_INTERNAL_ERROR, WriteBuf,
-    read_message, read_startup,
+    BeMessage, FE_PASSWORD_MESSAGE, FeStartupPacket, WriteBuf, read_message, read_startup,
 };
 use crate::tls::TlsServerEndPoint;
 
@@ -197,6 +197,7 @@ pub(crate) async fn throw_error<E>(
     {
         let error_kind = error.get_error_kind();
         let msg = error.to_string_client();
+        let sql_state = error.sql_state();
 
         if error_kind != ErrorKind::RateLimit && error_kind != ErrorKind::User {
             tracing::info!(
@@ -231,8 +232,8 @@ pub(crate) async fn throw_error<E>(
             msg = &probe_msg;
         }
 
-        // TODO: either preserve the error code from postgres, or assign error codes to proxy errors.
-        self.write.write_error(msg, SQLSTATE_INTERNAL_ERROR);
+        // TODO: preserve the error code from postgres.
+        self.write.write_error(msg, sql_state);
 
         self.flush()
             .await
@@ -271,6 +272,14 @@ pub fn sni_hostname(&self) -> Option<&str> {
         }
     }
 
+    /// Return the certificate chain presented by the client, if any.
+    pub(crate) fn client_certificates(&self) -> Option<&[CertificateDer<'static>]> {
+        match self {
+            Stream::Raw { .. } => None,
+            Stream::Tls { tls, .. } => tls.get_ref().1.peer_certificates(),
+        }
+    }
+
     pub(crate) fn tls_server_end_point(&self) -> TlsServerEndPoint {
         match self {
             Stream::Raw { .. } => TlsServerEndPoint::Undefined,
@@ -0,0 +1,257 @@
+//! Authentication of postgres clients with TLS client certificates.
+
+use std::sync::Arc;
+
+use anyhow::{Context, bail};
+use itertools::Itertools;
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{
+    CryptoProvider, WebPkiSupportedAlgorithms, ring, verify_tls12_signature, verify_tls13_signature,
+};
+use rustls::pki_types::{CertificateDer, UnixTime};
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, SignatureScheme};
+use thiserror::Error;
+use x509_cert::der::{Reader, SliceReader, oid};
+
+use crate::control_plane::messages::{ClientCertAuthConfig, ClientCertRoleMapping};
+use crate::error::{ReportableError, UserFacingError};
+use crate::intern::RoleNameInt;
+use crate::types::RoleName;
+
+/// Asks the client for a certificate during the handshake, but only checks
+/// that the client holds the private key of the certificate it presents.
+///
+/// The CAs we trust depend on the endpoint, which we only know after the
+/// handshake, so the chain itself is verified by [`ClientCertAuth`].
+#[derive(Debug)]
+pub(crate) struct DeferredClientCertVerifier {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl DeferredClientCertVerifier {
+    pub(crate) fn new(provider: &CryptoProvider) -> Self {
+        Self {
+            algorithms: provider.signature_verification_algorithms,
+        }
+    }
+}
+
+impl ClientCertVerifier for DeferredClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Client certificate authentication settings of an endpoint.
+#[derive(Debug)]
+pub struct ClientCertAuth {
+    verifier: Arc<dyn ClientCertVerifier>,
+    role_mapping: Vec<ClientCertRoleMapping>,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ClientCertError {
+    #[error("client certificate is not trusted by this endpoint: {0}")]
+    Untrusted(rustls::Error),
+
+    #[error("client certificate has no common name")]
+    MissingCommonName,
+
+    #[error("client certificate for '{common_name}' is not allowed to log in as role '{role}'")]
+    RoleNotAllowed { common_name: String, role: RoleName },
+}
+
+impl UserFacingError for ClientCertError {}
+
+impl ReportableError for ClientCertError {
+    fn get_error_kind(&self) -> crate::error::ErrorKind {
+        crate::error::ErrorKind::User
+    }
+}
+
+impl ClientCertAuth {
+    pub(crate) fn new(config: ClientCertAuthConfig) -> anyhow::Result<Self> {
+        let certs: Vec<_> = rustls_pemfile::certs(&mut config.trusted_ca_bundle.as_bytes())
+            .try_collect()
+            .context("Failed to parse trusted CA bundle")?;
+        if certs.is_empty() {
+            bail!("trusted CA bundle is empty");
+        }
+
+        let mut roots = RootCertStore::empty();
+        for cert in certs {
+            roots.add(cert).context("invalid CA certificate")?;
+        }
+
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            ring::default_provider().into(),
+        )
+        .build()
+        .context("Failed to build client certificate verifier")?;
+
+        Ok(Self {
+            verifier,
+            role_mapping: config.role_mapping,
+        })
+    }
+
+    /// Verify the certificate chain presented by the client and check that
+    /// its subject may log in as `role`.
+    pub(crate) fn authenticate(
+        &self,
+        chain: &[CertificateDer<'_>],
+        role: &RoleName,
+    ) -> Result<(), ClientCertError> {
+        let Some((end_entity, intermediates)) = chain.split_first() else {
+            return Err(ClientCertError::Untrusted(
+                rustls::Error::NoCertificatesPresented,
+            ));
+        };
+
+        self.verifier
+            .verify_client_cert(end_entity, intermediates, UnixTime::now())
+            .map_err(ClientCertError::Untrusted)?;
+
+        let common_name = common_name(end_entity).ok_or(ClientCertError::MissingCommonName)?;
+
+        let role_name = RoleNameInt::from(role);
+        let allowed = self
+            .role_mapping
+            .iter()
+            .any(|rule| rule.common_name == common_name && rule.role_name == role_name);
+        if !allowed {
+            return Err(ClientCertError::RoleNotAllowed {
+                common_name,
+                role: role.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let certificate = SliceReader::new(cert)
+        .ok()?
+        .decode::<x509_cert::Certificate>()
+        .ok()?;
+
+    certificate
+        .tbs_certificate
+        .subject
+        .0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attr| attr.oid == oid::db::rfc4519::CN)
+        .and_then(|attr| attr.to_string().strip_prefix("CN=").map(str::to_owned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue_client_cert(
+        ca: &rcgen::Certificate,
+        ca_key: &rcgen::KeyPair,
+        common_name: &str,
+    ) -> CertificateDer<'static> {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        params.signed_by(&key, ca, ca_key).unwrap().der().clone()
+    }
+
+    fn new_ca() -> (rcgen::Certificate, rcgen::KeyPair) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    #[test]
+    fn client_cert_auth() {
+        let (ca, ca_key) = new_ca();
+        let (other_ca, other_ca_key) = new_ca();
+
+        let auth = ClientCertAuth::new(ClientCertAuthConfig {
+            trusted_ca_bundle: ca.pem(),
+            role_mapping: vec![ClientCertRoleMapping {
+                common_name: "billing-service".to_owned(),
+                role_name: RoleNameInt::from(&RoleName::from("billing")),
+            }],
+        })
+        .unwrap();
+
+        let cert = issue_client_cert(&ca, &ca_key, "billing-service");
+        auth.authenticate(&[cert.clone()], &"billing".into())
+            .unwrap();
+
+        let err = auth.authenticate(&[cert], &"admin".into()).unwrap_err();
+        assert!(
+            matches!(err, ClientCertError::RoleNotAllowed { .. }),
+            "{err}"
+        );
+
+        let cert = issue_client_cert(&other_ca, &other_ca_key, "billing-service");
+        let err = auth.authenticate(&[cert], &"billing".into()).unwrap_err();
+        assert!(matches!(err, ClientCertError::Untrusted(_)), "{err}");
+
+        let err = auth.authenticate(&[], &"billing".into()).unwrap_err();
+        assert!(matches!(err, ClientCertError::Untrusted(_)), "{err}");
+    }
+
+    #[test]
+    fn client_cert_auth_empty_bundle() {
+        ClientCertAuth::new(ClientCertAuthConfig {
+            trusted_ca_bundle: String::new(),
+            role_mapping: vec![],
+        })
+        .unwrap_err();
+    }
+}
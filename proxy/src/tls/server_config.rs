@@ -9,6 +9,7 @@
 use rustls::sign::CertifiedKey;
 use x509_cert::der::{Reader, SliceReader};
 
+use super::client_cert::DeferredClientCertVerifier;
 use super::{PG_ALPN_PROTOCOL, TlsServerEndPoint};
 
 pub struct TlsConfig {
@@ -21,11 +22,15 @@ pub struct TlsConfig {
 }
 
 /// Configure TLS for the main endpoint.
+///
+/// With `client_cert_auth`, postgres clients are asked for a certificate
+/// which endpoints can accept in place of a password.
 pub fn configure_tls(
     key_path: &Path,
     cert_path: &Path,
     certs_dir: Option<&Path>,
     allow_tls_keylogfile: bool,
+    client_cert_auth: bool,
 ) -> anyhow::Result<TlsConfig> {
     // add default certificate
     let mut cert_resolver = CertResolver::parse_new(key_path, cert_path)?;
@@ -50,13 +55,17 @@ pub fn configure_tls(
 
     let cert_resolver = Arc::new(cert_resolver);
 
+    let provider = Arc::new(ring::default_provider());
+
     // allow TLS 1.2 to be compatible with older client libraries
-    let mut config =
-        rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
-            .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])
-            .context("ring should support TLS1.2 and TLS1.3")?
-            .with_no_client_auth()
-            .with_cert_resolver(cert_resolver.clone());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])
+        .context("ring should support TLS1.2 and TLS1.3")?;
+
+    let mut config = builder
+        .clone()
+        .with_no_client_auth()
+        .with_cert_resolver(cert_resolver.clone());
 
     config.alpn_protocols = vec![PG_ALPN_PROTOCOL.to_vec()];
 
@@ -66,7 +75,17 @@ pub fn configure_tls(
     }
 
     let mut http_config = config.clone();
-    let mut pg_config = config;
+    let mut pg_config = if client_cert_auth {
+        // only postgres clients are asked for a certificate,
+        // browsers would prompt the user to pick one.
+        let mut pg_config = builder
+            .with_client_cert_verifier(Arc::new(DeferredClientCertVerifier::new(&provider)))
+            .with_cert_resolver(cert_resolver.clone());
+        pg_config.key_log = config.key_log.clone();
+        pg_config
+    } else {
+        config
+    };
 
     http_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
     pg_config.alpn_protocols = vec![b"postgresql".to_vec()];
@@ -8,8 +8,8 @@
 use http_utils::error::HttpErrorBody;
 use reqwest::{IntoUrl, Method, Response, StatusCode};
 use safekeeper_api::models::{
-    self, PullTimelineRequest, PullTimelineResponse, SafekeeperStatus, SafekeeperUtilization,
-    TimelineCreateRequest,
+    self, PullTimelineProgress, PullTimelineRequest, PullTimelineResponse, SafekeeperStatus,
+    SafekeeperUtilization, TimelineCreateRequest,
 };
 use utils::id::{NodeId, TenantId, TimelineId};
 use utils::logging::SecretString;
@@ -182,6 +182,35 @@ pub async fn snapshot(
         self.get(&uri).await
     }
 
+    /// Request resumable snapshot: the donor adds a manifest and segment
+    /// checksums to the archive and skips segments below `from_segno`.
+    pub async fn snapshot_resumable(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+        stream_to: NodeId,
+        from_segno: u64,
+    ) -> Result<reqwest::Response> {
+        let uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/snapshot/{}?from_segno={}",
+            self.mgmt_api_endpoint, tenant_id, timeline_id, stream_to.0, from_segno
+        );
+        self.get(&uri).await
+    }
+
+    pub async fn pull_timeline_progress(
+        &self,
+        tenant_id: TenantId,
+        timeline_id: TimelineId,
+    ) -> Result<PullTimelineProgress> {
+        let uri = format!(
+            "{}/v1/tenant/{}/timeline/{}/pull_progress",
+            self.mgmt_api_endpoint, tenant_id, timeline_id
+        );
+        let resp = self.get(&uri).await?;
+        resp.json().await.map_err(Error::ReceiveBody)
+    }
+
     pub async fn status(&self) -> Result<SafekeeperStatus> {
         let uri = format!("{}/v1/status", self.mgmt_api_endpoint);
         let resp = self.get(&uri).await?;
@@ -22,7 +22,8 @@
     DEFAULT_MAX_GLOBAL_DISK_USAGE_RATIO, DEFAULT_MAX_OFFLOADER_LAG_BYTES,
     DEFAULT_MAX_REELECT_OFFLOADER_LAG_BYTES, DEFAULT_MAX_TIMELINE_DISK_USAGE_BYTES,
     DEFAULT_PARTIAL_BACKUP_CONCURRENCY, DEFAULT_PARTIAL_BACKUP_TIMEOUT, DEFAULT_PG_LISTEN_ADDR,
-    DEFAULT_SSL_CERT_FILE, DEFAULT_SSL_CERT_RELOAD_PERIOD, DEFAULT_SSL_KEY_FILE,
+    DEFAULT_PULL_TIMELINE_CONCURRENCY, DEFAULT_SSL_CERT_FILE, DEFAULT_SSL_CERT_RELOAD_PERIOD,
+    DEFAULT_SSL_KEY_FILE,
 };
 use safekeeper::hadron;
 use safekeeper::wal_backup::WalBackup;
@@ -145,6 +146,13 @@ struct Args {
     /// structure on the file system.
     #[arg(long, value_parser = parse_remote_storage, verbatim_doc_comment)]
     remote_storage: Option<RemoteStorageConfig>,
+    /// Directory for cold WAL segments, e.g. on a bigger and slower disk than
+    /// the data directory. Committed segments which are not needed by active
+    /// walsenders are moved there and still served to readers. If not set,
+    /// all WAL is kept in the data directory. Relative path is resolved
+    /// against the data directory.
+    #[arg(long, verbatim_doc_comment)]
+    cold_wal_dir: Option<Utf8PathBuf>,
     /// Safekeeper won't be elected for WAL offloading if it is lagging for more than this value in bytes
     #[arg(long, default_value_t = DEFAULT_MAX_OFFLOADER_LAG_BYTES)]
     max_offloader_lag: u64,
@@ -212,6 +220,10 @@ struct Args {
     /// Number of allowed concurrent uploads of partial segments to remote storage.
     #[arg(long, default_value = DEFAULT_PARTIAL_BACKUP_CONCURRENCY)]
     partial_backup_concurrency: usize,
+    /// Number of allowed concurrent timeline snapshot transfers. Pulls into
+    /// this safekeeper and snapshots streamed to peers are limited separately.
+    #[arg(long, default_value = DEFAULT_PULL_TIMELINE_CONCURRENCY)]
+    pull_timeline_concurrency: usize,
     /// How long a timeline must be resident before it is eligible for eviction.
     /// Usually, timeline eviction has to wait for `partial_backup_timeout` before being eligible for eviction,
     /// but if a timeline is un-evicted and then _not_ written to, it would immediately flap to evicting again,
@@ -412,8 +424,22 @@ async fn main() -> anyhow::Result<()> {
         None => Vec::new(),
     };
 
+    let cold_wal_dir = match args.cold_wal_dir.as_ref() {
+        None => None,
+        Some(dir) => {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create cold WAL directory {dir:?}"))?;
+            let dir = dir.canonicalize_utf8().with_context(|| {
+                format!("Failed to get the absolute path for cold WAL directory {dir:?}")
+            })?;
+            info!("using cold WAL directory {dir}");
+            Some(dir)
+        }
+    };
+
     let conf = Arc::new(SafeKeeperConf {
         workdir,
+        cold_wal_dir,
         my_id: id,
         listen_pg_addr: args.listen_pg,
         listen_pg_addr_tenant_only: args.listen_pg_tenant_only,
@@ -446,6 +472,7 @@ async fn main() -> anyhow::Result<()> {
         delete_offloaded_wal: args.delete_offloaded_wal,
         control_file_save_interval: args.control_file_save_interval,
         partial_backup_concurrency: args.partial_backup_concurrency,
+        pull_timeline_concurrency: args.pull_timeline_concurrency,
         eviction_min_resident: args.eviction_min_resident,
         wal_reader_fanout: args.wal_reader_fanout,
         max_delta_for_fanout: args.max_delta_for_fanout,
@@ -638,14 +665,23 @@ async fn start_safekeeper(conf: Arc<SafeKeeperConf>) -> Result<()> {
 
     /* BEGIN_HADRON */
     // Spawn global disk usage watcher task, if a global disk usage limit is specified.
+    // With a cold WAL directory configured, its filesystem is watched as well, with
+    // the same ratio applied to its capacity.
     let interval = conf.global_disk_check_interval;
-    let data_dir = conf.workdir.clone();
-    // Use the safekeeper data directory to compute filesystem capacity. This only runs once on startup, so
+    let mut watched_dirs = vec![conf.workdir.clone()];
+    watched_dirs.extend(conf.cold_wal_dir.clone());
+    // Use the safekeeper data directories to compute filesystem capacity. This only runs once on startup, so
     // there is little point to continue if we can't have the proper protections in place.
-    let fs_capacity_bytes = get_filesystem_capacity(data_dir.as_std_path())
-        .expect("Failed to get filesystem capacity for data directory");
-    let limit: u64 = (conf.max_global_disk_usage_ratio * fs_capacity_bytes as f64) as u64;
-    if limit > 0 {
+    let watched_dirs = watched_dirs
+        .into_iter()
+        .map(|dir| {
+            let fs_capacity_bytes = get_filesystem_capacity(dir.as_std_path())
+                .expect("Failed to get filesystem capacity for data directory");
+            let limit: u64 = (conf.max_global_disk_usage_ratio * fs_capacity_bytes as f64) as u64;
+            (dir, limit)
+        })
+        .collect::<Vec<_>>();
+    if watched_dirs.iter().all(|(_, limit)| *limit > 0) {
         let disk_usage_watch_handle = BACKGROUND_RUNTIME
             .handle()
             .spawn(async move {
@@ -655,24 +691,29 @@ async fn start_safekeeper(conf: Arc<SafeKeeperConf>) -> Result<()> {
 
                 loop {
                     ticker.tick().await;
-                    let data_dir_clone = data_dir.clone();
                     let check_start = Instant::now();
 
-                    let usage = tokio::task::spawn_blocking(move || {
-                        get_filesystem_usage(data_dir_clone.as_std_path())
-                    })
-                    .await
-                    .unwrap_or(0);
+                    let mut exceeded = false;
+                    for (dir, limit) in watched_dirs.iter() {
+                        let dir_clone = dir.clone();
+                        let usage = tokio::task::spawn_blocking(move || {
+                            get_filesystem_usage(dir_clone.as_std_path())
+                        })
+                        .await
+                        .unwrap_or(0);
+
+                        if usage > *limit {
+                            warn!(
+                                "Global disk usage exceeded limit for {}. Usage: {} bytes, limit: {} bytes",
+                                dir, usage, limit
+                            );
+                            exceeded = true;
+                        }
+                    }
 
                     let elapsed = check_start.elapsed().as_secs_f64();
                     GLOBAL_DISK_UTIL_CHECK_SECONDS.observe(elapsed);
-                    if usage > limit {
-                        warn!(
-                            "Global disk usage exceeded limit. Usage: {} bytes, limit: {} bytes",
-                            usage, limit
-                        );
-                    }
-                    GLOBAL_DISK_LIMIT_EXCEEDED.store(usage > limit, Ordering::Relaxed);
+                    GLOBAL_DISK_LIMIT_EXCEEDED.store(exceeded, Ordering::Relaxed);
                 }
             })
             .map(|res| ("Global disk usage watcher".to_string(), res));
@@ -63,6 +63,11 @@ async fn push_loop(
             let all_tlis = active_timelines_set.get_all();
             let mut n_pushed_tlis = 0;
             for tli in &all_tlis {
+                // Pageservers pick WAL source among safekeepers they see in
+                // the broker, so witness must not advertise itself.
+                if tli.is_witness().await {
+                    continue;
+                }
                 let sk_info = tli.get_safekeeper_info(&conf).await;
                 yield sk_info;
                 BROKER_PUSHED_UPDATES.inc();
@@ -188,6 +193,10 @@ async fn discover_loop(
                     .ok_or_else(|| anyhow!("missing tenant_timeline_id"))?;
                 let ttid = parse_proto_ttid(proto_ttid)?;
                 if let Ok(tli) = global_timelines.get(ttid) {
+                    // witness can't serve WAL, don't advertise it
+                    if tli.is_witness().await {
+                        continue;
+                    }
                     // we received a discovery request for a timeline we know about
                     discover_counter.inc();
 
@@ -15,13 +15,15 @@
 use utils::bin_ser::LeSer;
 use utils::crashsafe::durable_rename;
 
-use crate::control_file_upgrade::{downgrade_v10_to_v9, upgrade_control_file};
+use crate::control_file_upgrade::{
+    downgrade_v10_to_v9, downgrade_v11_to_v10, upgrade_control_file,
+};
 use crate::metrics::PERSIST_CONTROL_FILE_SECONDS;
 use crate::metrics::WAL_DISK_IO_ERRORS;
 use crate::state::{EvictionState, TimelinePersistentState};
 
 pub const SK_MAGIC: u32 = 0xcafeceefu32;
-pub const SK_FORMAT_VERSION: u32 = 10;
+pub const SK_FORMAT_VERSION: u32 = 11;
 
 // contains persistent metadata for safekeeper
 pub const CONTROL_FILE_NAME: &str = "safekeeper.control";
@@ -177,6 +179,13 @@ pub(crate) fn write_to_buf(&self) -> Result<Vec<u8>> {
             let prev = downgrade_v10_to_v9(self);
             WriteBytesExt::write_u32::<LittleEndian>(&mut buf, PREV_FORMAT_VERSION)?;
             prev.ser_into(&mut buf)?;
+        } else if !self.mconf.has_witnesses() {
+            // Likewise, keep writing v10 until witnesses are used so that
+            // rollback to version without member roles remains possible.
+            const PREV_FORMAT_VERSION: u32 = 10;
+            let prev = downgrade_v11_to_v10(self);
+            WriteBytesExt::write_u32::<LittleEndian>(&mut buf, PREV_FORMAT_VERSION)?;
+            prev.ser_into(&mut buf)?;
         } else {
             // otherwise, we write the current format version
             WriteBytesExt::write_u32::<LittleEndian>(&mut buf, SK_FORMAT_VERSION)?;
@@ -4,7 +4,10 @@
 use anyhow::{Result, bail};
 use postgres_versioninfo::PgVersionId;
 use pq_proto::SystemId;
-use safekeeper_api::membership::{Configuration, INVALID_GENERATION};
+use safekeeper_api::membership::{
+    Configuration, INVALID_GENERATION, MemberSet, SafekeeperGeneration, SafekeeperId,
+    SafekeeperRole,
+};
 use safekeeper_api::{ServerInfo, Term};
 use serde::{Deserialize, Serialize};
 use tracing::*;
@@ -318,6 +321,108 @@ pub struct TimelinePersistentStateV9 {
     pub eviction_state: EvictionState,
 }
 
+/// Membership member as of v10, before witness role was added.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SafekeeperIdV10 {
+    pub id: NodeId,
+    pub host: String,
+    pub pg_port: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub struct MemberSetV10 {
+    pub m: Vec<SafekeeperIdV10>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConfigurationV10 {
+    pub generation: SafekeeperGeneration,
+    pub members: MemberSetV10,
+    pub new_members: Option<MemberSetV10>,
+}
+
+impl From<MemberSetV10> for MemberSet {
+    fn from(mset: MemberSetV10) -> Self {
+        MemberSet {
+            m: mset
+                .m
+                .into_iter()
+                .map(|sk| SafekeeperId {
+                    id: sk.id,
+                    host: sk.host,
+                    pg_port: sk.pg_port,
+                    role: SafekeeperRole::Full,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&MemberSet> for MemberSetV10 {
+    fn from(mset: &MemberSet) -> Self {
+        MemberSetV10 {
+            m: mset
+                .m
+                .iter()
+                .map(|sk| SafekeeperIdV10 {
+                    id: sk.id,
+                    host: sk.host.clone(),
+                    pg_port: sk.pg_port,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TimelinePersistentStateV10 {
+    #[serde(with = "hex")]
+    pub tenant_id: TenantId,
+    #[serde(with = "hex")]
+    pub timeline_id: TimelineId,
+    /// Membership configuration.
+    pub mconf: ConfigurationV10,
+    /// persistent acceptor state
+    pub acceptor_state: AcceptorState,
+    /// information about server
+    pub server: ServerInfo,
+    /// Unique id of the last *elected* proposer we dealt with. Not needed
+    /// for correctness, exists for monitoring purposes.
+    #[serde(with = "hex")]
+    pub proposer_uuid: PgUuid,
+    /// Since which LSN this timeline generally starts. Safekeeper might have
+    /// joined later.
+    pub timeline_start_lsn: Lsn,
+    /// Since which LSN safekeeper has (had) WAL for this timeline.
+    /// All WAL segments next to one containing local_start_lsn are
+    /// filled with data from the beginning.
+    pub local_start_lsn: Lsn,
+    /// Part of WAL acknowledged by quorum *and available locally*. Always points
+    /// to record boundary.
+    pub commit_lsn: Lsn,
+    /// LSN that points to the end of the last backed up segment. Useful to
+    /// persist to avoid finding out offloading progress on boot.
+    pub backup_lsn: Lsn,
+    /// Minimal LSN which may be needed for recovery of some safekeeper (end_lsn
+    /// of last record streamed to everyone). Persisting it helps skipping
+    /// recovery in walproposer, generally we compute it from peers. In
+    /// walproposer proto called 'truncate_lsn'. Updates are currently drived
+    /// only by walproposer.
+    pub peer_horizon_lsn: Lsn,
+    /// LSN of the oldest known checkpoint made by pageserver and successfully
+    /// pushed to s3. We don't remove WAL beyond it. Persisted only for
+    /// informational purposes, we receive it from pageserver (or broker).
+    pub remote_consistent_lsn: Lsn,
+    /// Holds names of partial segments uploaded to remote storage. Used to
+    /// clean up old objects without leaving garbage in remote storage.
+    pub partial_backup: wal_backup_partial::State,
+    /// Eviction state of the timeline. If it's Offloaded, we should download
+    /// WAL files from remote storage to serve the timeline.
+    pub eviction_state: EvictionState,
+    pub creation_ts: std::time::SystemTime,
+}
+
 pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersistentState> {
     // migrate to storing full term history
     if version == 1 {
@@ -350,6 +455,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     // migrate to hexing some ids
     } else if version == 2 {
@@ -376,6 +482,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     // migrate to moving tenant_id/timeline_id to the top and adding some lsns
     } else if version == 3 {
@@ -402,6 +509,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     // migrate to having timeline_start_lsn
     } else if version == 4 {
@@ -428,6 +536,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     } else if version == 5 {
         info!("reading safekeeper control file version {}", version);
@@ -474,6 +583,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     } else if version == 8 {
         let oldstate = SafeKeeperStateV8::des(&buf[..buf.len()])?;
@@ -494,6 +604,7 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: oldstate.partial_backup,
             eviction_state: EvictionState::Present,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         });
     } else if version == 9 {
         let oldstate = TimelinePersistentStateV9::des(&buf[..buf.len()])?;
@@ -513,6 +624,32 @@ pub fn upgrade_control_file(buf: &[u8], version: u32) -> Result<TimelinePersiste
             partial_backup: oldstate.partial_backup,
             eviction_state: oldstate.eviction_state,
             creation_ts: std::time::SystemTime::UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
+        });
+    // migrate to having member roles
+    } else if version == 10 {
+        let oldstate = TimelinePersistentStateV10::des(&buf[..buf.len()])?;
+        return Ok(TimelinePersistentState {
+            tenant_id: oldstate.tenant_id,
+            timeline_id: oldstate.timeline_id,
+            mconf: Configuration {
+                generation: oldstate.mconf.generation,
+                members: oldstate.mconf.members.into(),
+                new_members: oldstate.mconf.new_members.map(Into::into),
+            },
+            acceptor_state: oldstate.acceptor_state,
+            server: oldstate.server,
+            proposer_uuid: oldstate.proposer_uuid,
+            timeline_start_lsn: oldstate.timeline_start_lsn,
+            local_start_lsn: oldstate.local_start_lsn,
+            commit_lsn: oldstate.commit_lsn,
+            backup_lsn: oldstate.backup_lsn,
+            peer_horizon_lsn: oldstate.peer_horizon_lsn,
+            remote_consistent_lsn: oldstate.remote_consistent_lsn,
+            partial_backup: oldstate.partial_backup,
+            eviction_state: oldstate.eviction_state,
+            creation_ts: oldstate.creation_ts,
+            witness_flush_lsn: Lsn(0),
         });
     }
 
@@ -544,6 +681,33 @@ pub fn downgrade_v10_to_v9(state: &TimelinePersistentState) -> TimelinePersisten
     }
 }
 
+/// Like [`downgrade_v10_to_v9`], allows rollback to the version without
+/// member roles as long as witnesses are not used.
+pub fn downgrade_v11_to_v10(state: &TimelinePersistentState) -> TimelinePersistentStateV10 {
+    assert!(!state.mconf.has_witnesses());
+    TimelinePersistentStateV10 {
+        tenant_id: state.tenant_id,
+        timeline_id: state.timeline_id,
+        mconf: ConfigurationV10 {
+            generation: state.mconf.generation,
+            members: (&state.mconf.members).into(),
+            new_members: state.mconf.new_members.as_ref().map(Into::into),
+        },
+        acceptor_state: state.acceptor_state.clone(),
+        server: state.server.clone(),
+        proposer_uuid: state.proposer_uuid,
+        timeline_start_lsn: state.timeline_start_lsn,
+        local_start_lsn: state.local_start_lsn,
+        commit_lsn: state.commit_lsn,
+        backup_lsn: state.backup_lsn,
+        peer_horizon_lsn: state.peer_horizon_lsn,
+        remote_consistent_lsn: state.remote_consistent_lsn,
+        partial_backup: state.partial_backup.clone(),
+        eviction_state: state.eviction_state,
+        creation_ts: state.creation_ts,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -785,4 +949,61 @@ fn roundtrip_v4() {
 
         assert_eq!(state, deser);
     }
+
+    #[test]
+    fn upgrade_v10() {
+        let tenant_id = TenantId::from_str("cf0480929707ee75372337efaa5ecf96").unwrap();
+        let timeline_id = TimelineId::from_str("112ded66422aa5e953e5440fa5427ac4").unwrap();
+        let member = |id| SafekeeperIdV10 {
+            id: NodeId(id),
+            host: format!("sk-{id}.org"),
+            pg_port: 5432,
+        };
+        let state = TimelinePersistentStateV10 {
+            tenant_id,
+            timeline_id,
+            mconf: ConfigurationV10 {
+                generation: SafekeeperGeneration::new(2),
+                members: MemberSetV10 {
+                    m: vec![member(1), member(2), member(3)],
+                },
+                new_members: Some(MemberSetV10 {
+                    m: vec![member(1), member(2), member(4)],
+                }),
+            },
+            acceptor_state: AcceptorState {
+                term: 42,
+                term_history: TermHistory(vec![TermLsn {
+                    lsn: Lsn(0x1),
+                    term: 41,
+                }]),
+            },
+            server: ServerInfo {
+                pg_version: PgVersionId::from(PgMajorVersion::PG17),
+                system_id: 0x1234567887654321,
+                wal_seg_size: 0x12345678,
+            },
+            proposer_uuid: [0; 16],
+            timeline_start_lsn: Lsn(0x12345600),
+            local_start_lsn: Lsn(0x12345600),
+            commit_lsn: Lsn(1234567800),
+            backup_lsn: Lsn(1234567300),
+            peer_horizon_lsn: Lsn(9999999),
+            remote_consistent_lsn: Lsn(1234560000),
+            partial_backup: wal_backup_partial::State::default(),
+            eviction_state: EvictionState::Present,
+            creation_ts: std::time::SystemTime::UNIX_EPOCH,
+        };
+
+        let ser = state.ser().unwrap();
+        let upgraded = upgrade_control_file(&ser, 10).unwrap();
+        assert_eq!(upgraded.mconf.generation, state.mconf.generation);
+        assert_eq!(upgraded.mconf.members.m.len(), 3);
+        assert!(!upgraded.mconf.has_witnesses());
+        assert_eq!(upgraded.commit_lsn, state.commit_lsn);
+        assert_eq!(upgraded.witness_flush_lsn, Lsn(0));
+
+        // Without witnesses state can be written back in v10 format losslessly.
+        assert_eq!(downgrade_v11_to_v10(&upgraded), state);
+    }
 }
@@ -36,8 +36,10 @@
 use crate::hadron::{get_filesystem_capacity, get_filesystem_usage};
 use crate::safekeeper::TermLsn;
 use crate::timelines_global_map::DeleteOrExclude;
+use crate::wal_range::{WalRangeFormat, WalRangeRequest};
 use crate::{
     GlobalTimelines, SafeKeeperConf, copy_timeline, debug_dump, patch_control_file, pull_timeline,
+    wal_range,
 };
 use serde_json::json;
 
@@ -101,6 +103,10 @@ async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<
         timeline_id: request_data.timeline_id,
     };
     check_permission(&request, Some(ttid.tenant_id))?;
+    request_data
+        .mconf
+        .validate_witnesses()
+        .map_err(ApiError::BadRequest)?;
 
     let server_info = ServerInfo {
         pg_version: request_data.pg_version,
@@ -269,9 +275,29 @@ async fn timeline_pull_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, resp)
 }
 
+/// Report progress of the latest pull of the timeline into this safekeeper.
+async fn timeline_pull_progress_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let progress = get_global_timelines(&request)
+        .get_pull_progress(&ttid)
+        .ok_or_else(|| ApiError::NotFound(anyhow::anyhow!("no pull of {ttid} found").into()))?;
+    json_response(StatusCode::OK, progress)
+}
+
 /// Stream tar archive with all timeline data.
+///
+/// With `from_segno` query param the archive is resumable, see
+/// [`pull_timeline::stream_snapshot`].
 async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let destination = parse_request_param(&request, "destination_id")?;
+    let from_segno: Option<u64> = parse_query_param(&request, "from_segno")?;
     let ttid = TenantTimelineId::new(
         parse_request_param(&request, "tenant_id")?,
         parse_request_param(&request, "timeline_id")?,
@@ -281,6 +307,7 @@ async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Bo
     let global_timelines = get_global_timelines(&request);
     let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
     let storage = global_timelines.get_wal_backup().get_storage();
+    let rate_limiter = global_timelines.get_global_rate_limiter();
 
     // To stream the body use wrap_stream which wants Stream of Result<Bytes>,
     // so create the chan and write to it in another task.
@@ -291,8 +318,10 @@ async fn timeline_snapshot_handler(request: Request<Body>) -> Result<Response<Bo
         tli,
         conf.my_id,
         destination,
+        from_segno,
         tx,
         storage,
+        rate_limiter,
     ));
 
     let rx_stream = ReceiverStream::new(rx);
@@ -507,6 +536,50 @@ async fn timeline_digest_handler(request: Request<Body>) -> Result<Response<Body
     json_response(StatusCode::OK, response)
 }
 
+/// Stream WAL of the timeline between start_lsn and end_lsn (flush_lsn by
+/// default), either raw or as decoded records in newline delimited JSON.
+async fn timeline_wal_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let ttid = TenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    check_permission(&request, Some(ttid.tenant_id))?;
+
+    let start_lsn: Option<Lsn> = parse_query_param(&request, "start_lsn")?;
+    let wal_request = WalRangeRequest {
+        start_lsn: start_lsn.ok_or(ApiError::BadRequest(anyhow::anyhow!(
+            "start_lsn is required"
+        )))?,
+        end_lsn: parse_query_param(&request, "end_lsn")?,
+        format: parse_query_param(&request, "format")?.unwrap_or_default(),
+    };
+
+    let global_timelines = get_global_timelines(&request);
+    let tli = global_timelines.get(ttid).map_err(ApiError::from)?;
+    let tli = tli
+        .wal_residence_guard()
+        .await
+        .map_err(ApiError::InternalServerError)?;
+    let range = wal_range::validate(&tli, wal_request)
+        .await
+        .map_err(ApiError::BadRequest)?;
+
+    let content_type = match wal_request.format {
+        WalRangeFormat::Raw => "application/octet-stream",
+        WalRangeFormat::Json => "application/x-ndjson",
+    };
+    let (tx, rx) = mpsc::channel(1);
+    task::spawn(wal_range::stream_wal_range(tli, range, tx));
+
+    let body = Body::wrap_stream(ReceiverStream::new(rx));
+    let response = Response::builder()
+        .status(200)
+        .header(hyper::header::CONTENT_TYPE, content_type)
+        .body(body)
+        .unwrap();
+    Ok(response)
+}
+
 /// Unevict timeline and remove uploaded partial segment(s) from the remote storage.
 /// Successfull response returns list of segments existed before the deletion.
 /// Aimed for one-off usage not normally needed.
@@ -771,6 +844,10 @@ pub fn make_router(
         .post("/v1/pull_timeline", |r| {
             request_span(r, timeline_pull_handler)
         })
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/pull_progress",
+            |r| request_span(r, timeline_pull_progress_handler),
+        )
         .put("/v1/tenant/:tenant_id/timeline/:timeline_id/exclude", |r| {
             request_span(r, timeline_exclude_handler)
         })
@@ -797,6 +874,9 @@ pub fn make_router(
         .get("/v1/tenant/:tenant_id/timeline/:timeline_id/digest", |r| {
             request_span(r, timeline_digest_handler)
         })
+        .get("/v1/tenant/:tenant_id/timeline/:timeline_id/wal", |r| {
+            request_span(r, timeline_wal_handler)
+        })
         .post(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/backup_partial_reset",
             |r| request_span(r, timeline_backup_partial_reset),
@@ -42,9 +42,11 @@
 pub mod timelines_set;
 pub mod wal_backup;
 pub mod wal_backup_partial;
+pub mod wal_range;
 pub mod wal_reader_stream;
 pub mod wal_service;
 pub mod wal_storage;
+pub mod witness;
 
 #[cfg(any(test, feature = "benchmarking"))]
 pub mod test_utils;
@@ -75,6 +77,7 @@ pub mod defaults {
     pub const DEFAULT_CONTROL_FILE_SAVE_INTERVAL: &str = "300s";
     pub const DEFAULT_PARTIAL_BACKUP_CONCURRENCY: &str = "5";
     pub const DEFAULT_EVICTION_CONCURRENCY: usize = 2;
+    pub const DEFAULT_PULL_TIMELINE_CONCURRENCY: &str = "4";
 
     // By default, our required residency before eviction is the same as the period that passes
     // before uploading a partial segment, so that in normal operation the eviction can happen
@@ -99,6 +102,11 @@ pub struct SafeKeeperConf {
     // to the process but different unit tests work on different
     // data directories to avoid clashing with each other.
     pub workdir: Utf8PathBuf,
+    /// Optional directory for cold WAL segments, typically on a cheaper and
+    /// bigger disk. Committed segments not needed by walsenders are moved there
+    /// from the timeline directory in `workdir`. Laid out as
+    /// `<cold_wal_dir>/<tenant_id>/<timeline_id>/`.
+    pub cold_wal_dir: Option<Utf8PathBuf>,
     pub my_id: NodeId,
     pub listen_pg_addr: String,
     pub listen_pg_addr_tenant_only: Option<String>,
@@ -141,6 +149,7 @@ pub struct SafeKeeperConf {
     pub delete_offloaded_wal: bool,
     pub control_file_save_interval: Duration,
     pub partial_backup_concurrency: usize,
+    pub pull_timeline_concurrency: usize,
     pub eviction_min_resident: Duration,
     pub wal_reader_fanout: bool,
     pub max_delta_for_fanout: Option<u64>,
@@ -157,6 +166,7 @@ impl SafeKeeperConf {
     pub fn dummy() -> Self {
         SafeKeeperConf {
             workdir: Utf8PathBuf::from("./"),
+            cold_wal_dir: None,
             no_sync: false,
             listen_pg_addr: defaults::DEFAULT_PG_LISTEN_ADDR.to_string(),
             listen_pg_addr_tenant_only: None,
@@ -193,6 +203,7 @@ pub fn dummy() -> Self {
             delete_offloaded_wal: false,
             control_file_save_interval: Duration::from_secs(1),
             partial_backup_concurrency: 1,
+            pull_timeline_concurrency: 1,
             eviction_min_resident: Duration::ZERO,
             wal_reader_fanout: false,
             max_delta_for_fanout: None,
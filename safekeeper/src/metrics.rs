@@ -189,6 +189,13 @@
     )
     .expect("Failed to register safekeeper_removed_wal_segments_total counter")
 });
+pub static MOVED_TO_COLD_WAL_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "safekeeper_moved_to_cold_wal_segments_total",
+        "Number of WAL segments moved from the hot to the cold WAL directory"
+    )
+    .expect("Failed to register safekeeper_moved_to_cold_wal_segments_total counter")
+});
 pub static BACKED_UP_SEGMENTS: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "safekeeper_backed_up_segments_total",
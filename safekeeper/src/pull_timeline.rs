@@ -1,25 +1,31 @@
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::ffi::OsStr;
 use std::io::{self, ErrorKind};
 use std::ops::RangeInclusive;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result, anyhow, bail};
 use bytes::Bytes;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use chrono::{DateTime, Utc};
 use futures::{SinkExt, StreamExt, TryStreamExt};
 use http::StatusCode;
 use http_utils::error::ApiError;
+use postgres_ffi::v14::xlog_utils::{IsPartialXLogFileName, IsXLogFileName, XLogFromFileName};
 use postgres_ffi::{PG_TLI, XLogFileName, XLogSegNo};
 use remote_storage::GenericRemoteStorage;
 use reqwest::Certificate;
-use safekeeper_api::models::{PullTimelineRequest, PullTimelineResponse, TimelineStatus};
+use safekeeper_api::models::{
+    PullTimelineProgress, PullTimelineRequest, PullTimelineResponse, PullTimelineStatus,
+    TimelineStatus,
+};
 use safekeeper_api::{Term, membership};
 use safekeeper_client::mgmt_api;
 use safekeeper_client::mgmt_api::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::fs::OpenOptions;
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc;
 use tokio::task;
 use tokio::time::sleep;
@@ -27,28 +33,63 @@
 use tokio_util::io::{CopyToBytes, SinkWriter};
 use tokio_util::sync::PollSender;
 use tracing::{error, info, instrument, warn};
-use utils::crashsafe::fsync_async_opt;
+use utils::crashsafe::{durable_rename, fsync_async_opt};
+use utils::fs_ext::ignore_not_found;
 use utils::id::{NodeId, TenantTimelineId};
 use utils::logging::SecretString;
 use utils::lsn::Lsn;
 use utils::pausable_failpoint;
 
+use crate::SafeKeeperConf;
 use crate::control_file::CONTROL_FILE_NAME;
+use crate::rate_limit::RateLimiter;
 use crate::state::{EvictionState, TimelinePersistentState};
 use crate::timeline::{Timeline, TimelineError, WalResidentTimeline};
-use crate::timelines_global_map::{create_temp_timeline_dir, validate_temp_timeline};
-use crate::wal_storage::{open_wal_file, wal_file_paths};
+use crate::timelines_global_map::{create_pull_timeline_dir, validate_temp_timeline};
+use crate::wal_storage::{open_wal_file_tiered, wal_file_paths};
 use crate::{GlobalTimelines, debug_dump, wal_backup};
 
+/// Name of the snapshot manifest entry, sent after the control file in
+/// resumable snapshots.
+const SNAPSHOT_MANIFEST_NAME: &str = "pull_manifest.json";
+/// In resumable snapshots each WAL segment entry is followed by an entry with
+/// this suffix holding hex encoded crc32c of the segment.
+const SEGMENT_CHECKSUM_SUFFIX: &str = ".crc32c";
+/// Receiver side state of a pull, persisted in the pull directory to allow
+/// resuming.
+const PULL_STATE_NAME: &str = "pull_state.json";
+/// How many times pull_timeline tries to download the snapshot from the same
+/// donor before giving up.
+const PULL_TIMELINE_ATTEMPTS: u32 = 5;
+
+/// Describes what is sent in the resumable snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotManifest {
+    term: Term,
+    last_log_term: Term,
+    flush_lsn: Lsn,
+    wal_seg_size: usize,
+    /// Inclusive range of segments following in the archive, None if there
+    /// are no segments.
+    segnos: Option<(XLogSegNo, XLogSegNo)>,
+}
+
 /// Stream tar archive of timeline to tx.
+///
+/// If `from_segno` is set, the snapshot is resumable: control file is
+/// followed by [`SnapshotManifest`], segments below `from_segno` are skipped
+/// and each segment is followed by its checksum.
 #[instrument(name = "snapshot", skip_all, fields(ttid = %tli.ttid))]
 pub async fn stream_snapshot(
     tli: Arc<Timeline>,
     source: NodeId,
     destination: NodeId,
+    from_segno: Option<XLogSegNo>,
     tx: mpsc::Sender<Result<Bytes>>,
     storage: Option<Arc<GenericRemoteStorage>>,
+    rate_limiter: RateLimiter,
 ) {
+    let _permit = rate_limiter.acquire_snapshot().await;
     match tli.try_wal_residence_guard().await {
         Err(e) => {
             tx.send(Err(anyhow!("Error checking residence: {:#}", e)))
@@ -62,6 +103,7 @@ pub async fn stream_snapshot(
                         resident_tli,
                         source,
                         destination,
+                        from_segno,
                         tx.clone(),
                         storage,
                     )
@@ -167,6 +209,7 @@ pub async fn stream_snapshot_resident_guts(
     tli: WalResidentTimeline,
     source: NodeId,
     destination: NodeId,
+    from_segno: Option<XLogSegNo>,
     tx: mpsc::Sender<Result<Bytes>>,
     storage: Option<Arc<GenericRemoteStorage>>,
 ) -> Result<()> {
@@ -177,8 +220,33 @@ pub async fn stream_snapshot_resident_guts(
         .await?;
     pausable_failpoint!("sk-snapshot-after-list-pausable");
 
-    if let Some(from_to_segno) = &bctx.from_to_segno {
+    // Receiver already has segments below from_segno.
+    let from_to_segno = match from_segno {
+        Some(from_segno) => bctx
+            .from_to_segno
+            .as_ref()
+            .map(|r| max(*r.start(), from_segno)..=*r.end()),
+        None => bctx.from_to_segno.clone(),
+    };
+    if from_segno.is_some() {
+        let manifest = SnapshotManifest {
+            term: bctx.term,
+            last_log_term: bctx.last_log_term,
+            flush_lsn: bctx.flush_lsn,
+            wal_seg_size: bctx.wal_seg_size,
+            segnos: from_to_segno.as_ref().map(|r| (*r.start(), *r.end())),
+        };
+        let buf = serde_json::to_vec(&manifest)?;
+        let mut header = Header::new_gnu();
+        header.set_size(buf.len() as u64);
+        ar.append_data(&mut header, SNAPSHOT_MANIFEST_NAME, buf.as_slice())
+            .await
+            .context("failed to append to archive")?;
+    }
+
+    if let Some(from_to_segno) = &from_to_segno {
         let tli_dir = tli.get_timeline_dir();
+        let cold_tli_dir = tli.get_cold_timeline_dir();
         info!(
             "sending {} segments [{:#X}-{:#X}], term={}, last_log_term={}, flush_lsn={}",
             from_to_segno.end() - from_to_segno.start() + 1,
@@ -190,7 +258,8 @@ pub async fn stream_snapshot_resident_guts(
         );
         for segno in from_to_segno.clone() {
             let Some((mut sf, is_partial)) =
-                open_wal_file(&tli_dir, segno, bctx.wal_seg_size).await?
+                open_wal_file_tiered(&tli_dir, cold_tli_dir.as_deref(), segno, bctx.wal_seg_size)
+                    .await?
             else {
                 // File is not found
                 let (wal_file_path, _wal_file_partial_path) =
@@ -202,7 +271,27 @@ pub async fn stream_snapshot_resident_guts(
             if is_partial {
                 wal_file_name.push_str(".partial");
             }
-            ar.append_file(&wal_file_name, &mut sf).await?;
+            if from_segno.is_none() {
+                ar.append_file(&wal_file_name, &mut sf).await?;
+                continue;
+            }
+            // Read the whole segment to send exactly the bytes checksum is
+            // calculated over.
+            let mut buf = Vec::with_capacity(bctx.wal_seg_size);
+            sf.read_to_end(&mut buf).await?;
+            let mut header = Header::new_gnu();
+            header.set_size(buf.len() as u64);
+            ar.append_data(&mut header, &wal_file_name, buf.as_slice())
+                .await?;
+            let checksum = format!("{:08x}", crc32c::crc32c(&buf));
+            let mut header = Header::new_gnu();
+            header.set_size(checksum.len() as u64);
+            ar.append_data(
+                &mut header,
+                format!("{wal_file_name}{SEGMENT_CHECKSUM_SUFFIX}"),
+                checksum.as_bytes(),
+            )
+            .await?;
         }
     } else {
         info!("Not including any segments into the snapshot");
@@ -706,81 +795,665 @@ async fn pull_timeline(
     );
 
     let conf = &global_timelines.get_global_config();
-
-    let (_tmp_dir, tli_dir_path) = create_temp_timeline_dir(conf, ttid).await?;
+    let _permit = global_timelines
+        .get_global_rate_limiter()
+        .acquire_pull_timeline()
+        .await;
+
+    // Data received by previous failed pulls is in the pull directory, it
+    // will be reused if possible.
+    let tli_dir_path = create_pull_timeline_dir(conf, ttid).await?;
     let client = Client::new(http_client, host.clone(), sk_auth_token.clone());
-    // Request stream with basebackup archive.
+    let mut progress = PullTimelineProgress {
+        tenant_id: ttid.tenant_id,
+        timeline_id: ttid.timeline_id,
+        safekeeper_host: host.clone(),
+        status: PullTimelineStatus::InProgress,
+        attempts: 0,
+        segments_total: 0,
+        segments_done: 0,
+        bytes_received: 0,
+        last_error: None,
+    };
+    loop {
+        progress.attempts += 1;
+        global_timelines.report_pull_progress(&progress);
+        match download_snapshot(
+            |from_segno| request_snapshot(&client, conf, ttid, from_segno),
+            conf,
+            ttid,
+            &host,
+            &tli_dir_path,
+            &global_timelines,
+            &mut progress,
+        )
+        .await
+        {
+            Ok(()) => break,
+            Err(e) => {
+                progress.last_error = Some(format!("{e:#}"));
+                if progress.attempts >= PULL_TIMELINE_ATTEMPTS {
+                    progress.status = PullTimelineStatus::Failed;
+                    global_timelines.report_pull_progress(&progress);
+                    return Err(e);
+                }
+                warn!(
+                    "attempt {} to pull timeline {} from {} failed, retrying: {:#}",
+                    progress.attempts, ttid, host, e
+                );
+                global_timelines.report_pull_progress(&progress);
+                sleep(Duration::from_millis(100 << progress.attempts)).await;
+            }
+        }
+    }
+
+    // Resume bookkeeping is not part of the timeline.
+    for name in [PULL_STATE_NAME, SNAPSHOT_MANIFEST_NAME] {
+        tokio::fs::remove_file(tli_dir_path.join(name))
+            .await
+            .or_else(ignore_not_found)?;
+    }
+    // fsync pull directory to remember its contents.
+    fsync_async_opt(&tli_dir_path, !conf.no_sync).await?;
+
+    let generation = mconf.as_ref().map(|c| c.generation);
+
+    // Let's create timeline from pull directory and verify that it's correct
+    let res = async {
+        let (commit_lsn, flush_lsn) =
+            validate_temp_timeline(conf, ttid, &tli_dir_path, generation).await?;
+        info!(
+            "finished downloading timeline {}, commit_lsn={}, flush_lsn={}",
+            ttid, commit_lsn, flush_lsn
+        );
+        assert!(status.commit_lsn <= status.flush_lsn);
+
+        // Finally, load the timeline.
+        global_timelines
+            .load_temp_timeline(ttid, &tli_dir_path, generation)
+            .await
+    }
+    .await;
+    let timeline = match res {
+        Ok(timeline) => timeline,
+        Err(e) => {
+            // Downloaded data can't be used, don't try to resume from it.
+            if let Err(rm_err) = tokio::fs::remove_dir_all(&tli_dir_path)
+                .await
+                .or_else(ignore_not_found)
+            {
+                warn!("failed to remove pull directory {tli_dir_path}: {rm_err}");
+            }
+            progress.status = PullTimelineStatus::Failed;
+            progress.last_error = Some(format!("{e:#}"));
+            global_timelines.report_pull_progress(&progress);
+            return Err(e);
+        }
+    };
+    progress.status = PullTimelineStatus::Finished;
+    progress.last_error = None;
+    global_timelines.report_pull_progress(&progress);
+
+    if let Some(mconf) = mconf {
+        // Switch to provided mconf to guarantee that the timeline will not
+        // be deleted by request with older generation.
+        // The generation might already be higer than the one in mconf, e.g.
+        // if another membership_switch request was executed between `load_temp_timeline`
+        // and `membership_switch`, but that's totaly fine. `membership_switch` will
+        // ignore switch to older generation.
+        timeline.membership_switch(mconf).await?;
+    }
+
+    Ok(PullTimelineResponse {
+        safekeeper_host: Some(host),
+    })
+}
+
+/// Receiver side state of the pull, see [`PULL_STATE_NAME`].
+#[derive(Debug, Serialize, Deserialize)]
+struct PullState {
+    safekeeper_host: String,
+    last_log_term: Term,
+    wal_seg_size: usize,
+    /// All complete segments up to this one are received and verified.
+    verified_up_to: Option<XLogSegNo>,
+}
+
+async fn read_pull_state(tli_dir_path: &Utf8Path) -> Result<Option<PullState>> {
+    match tokio::fs::read(tli_dir_path.join(PULL_STATE_NAME)).await {
+        Ok(buf) => match serde_json::from_slice(&buf) {
+            Ok(state) => Ok(Some(state)),
+            Err(e) => {
+                warn!("ignoring unparsable pull state: {e}");
+                Ok(None)
+            }
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn persist_pull_state(
+    tli_dir_path: &Utf8Path,
+    state: &PullState,
+    do_fsync: bool,
+) -> Result<()> {
+    let tmp_path = tli_dir_path.join(format!("{PULL_STATE_NAME}.tmp"));
+    tokio::fs::write(&tmp_path, serde_json::to_vec(state)?).await?;
+    durable_rename(&tmp_path, tli_dir_path.join(PULL_STATE_NAME), do_fsync).await?;
+    Ok(())
+}
+
+fn is_segment_file_name(fname: &OsStr) -> bool {
+    IsXLogFileName(fname) || IsPartialXLogFileName(fname)
+}
+
+/// Remove everything from the pull directory except pull state and complete
+/// segments up to `keep.0` (wal_seg_size is `keep.1`). Returns the number of
+/// kept segments.
+async fn clean_pull_dir(tli_dir_path: &Utf8Path, keep: Option<(XLogSegNo, usize)>) -> Result<u64> {
+    let mut kept = 0;
+    let mut entries = tokio::fs::read_dir(tli_dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let fname = path.file_name().unwrap();
+        if let Some((keep_up_to, wal_seg_size)) = keep {
+            if fname == OsStr::new(PULL_STATE_NAME) {
+                continue;
+            }
+            if IsXLogFileName(fname) && XLogFromFileName(fname, wal_seg_size)?.0 <= keep_up_to {
+                kept += 1;
+                continue;
+            }
+        }
+        tokio::fs::remove_file(&path).await?;
+    }
+    Ok(kept)
+}
+
+/// Remove all WAL segments from the pull directory.
+async fn remove_pulled_segments(tli_dir_path: &Utf8Path) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(tli_dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if is_segment_file_name(path.file_name().unwrap()) {
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Request resumable snapshot of the timeline from the donor, returning the
+/// tar archive stream.
+async fn request_snapshot(
+    client: &Client,
+    conf: &SafeKeeperConf,
+    ttid: TenantTimelineId,
+    from_segno: XLogSegNo,
+) -> Result<impl AsyncRead + Unpin> {
     let bb_resp = client
-        .snapshot(status.tenant_id, status.timeline_id, conf.my_id)
+        .snapshot_resumable(ttid.tenant_id, ttid.timeline_id, conf.my_id, from_segno)
         .await?;
 
     // Make Stream of Bytes from it...
     let bb_stream = bb_resp.bytes_stream().map_err(std::io::Error::other);
     // and turn it into StreamReader implementing AsyncRead.
-    let bb_reader = tokio_util::io::StreamReader::new(bb_stream);
+    Ok(tokio_util::io::StreamReader::new(bb_stream))
+}
+
+/// Download snapshot from `host` into the pull directory, continuing from
+/// the segments verified by previous attempts if the donor's history still
+/// contains them. `fetch` requests the archive starting with the given
+/// segment.
+async fn download_snapshot<F, Fut, R>(
+    fetch: F,
+    conf: &SafeKeeperConf,
+    ttid: TenantTimelineId,
+    host: &str,
+    tli_dir_path: &Utf8Path,
+    global_timelines: &GlobalTimelines,
+    progress: &mut PullTimelineProgress,
+) -> Result<()>
+where
+    F: FnOnce(XLogSegNo) -> Fut,
+    Fut: Future<Output = Result<R>>,
+    R: AsyncRead + Unpin,
+{
+    let mut state = read_pull_state(tli_dir_path)
+        .await?
+        .filter(|s| s.safekeeper_host == host);
+    let verified_up_to = state.as_ref().and_then(|s| s.verified_up_to);
+    let kept = clean_pull_dir(
+        tli_dir_path,
+        state
+            .as_ref()
+            .and_then(|s| s.verified_up_to.map(|segno| (segno, s.wal_seg_size))),
+    )
+    .await?;
+    if state.is_none() {
+        tokio::fs::remove_file(tli_dir_path.join(PULL_STATE_NAME))
+            .await
+            .or_else(ignore_not_found)?;
+    }
+    progress.segments_done = kept;
+    progress.segments_total = kept;
+
+    let from_segno = verified_up_to.map_or(0, |segno| segno + 1);
+    if from_segno > 0 {
+        info!("resuming pull of timeline {ttid} from segment {from_segno:#X}");
+    }
+    // Request stream with basebackup archive.
+    let bb_reader = fetch(from_segno).await?;
 
     // Extract it on the fly to the disk. We don't use simple unpack() to fsync
     // files.
+    let mut manifest: Option<SnapshotManifest> = None;
+    // Set if donor doesn't support resumable snapshots.
+    let mut legacy = false;
+    // Next segment expected in the archive.
+    let mut expected_segno = 0;
+    // Segment which is written, but whose checksum is not checked yet: name,
+    // segno, is_partial, crc32c.
+    let mut unverified: Option<(String, XLogSegNo, bool, u32)> = None;
+    let mut buf = vec![0u8; 64 * 1024];
     let mut entries = Archive::new(bb_reader).entries()?;
     while let Some(base_tar_entry) = entries.next().await {
         let mut entry = base_tar_entry?;
         let header = entry.header();
         let file_path = header.path()?.into_owned();
-        match header.entry_type() {
-            tokio_tar::EntryType::Regular => {
-                let utf8_file_path =
-                    Utf8PathBuf::from_path_buf(file_path).expect("non-Unicode path");
-                let dst_path = tli_dir_path.join(utf8_file_path);
-                let mut f = OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(&dst_path)
-                    .await?;
-                tokio::io::copy(&mut entry, &mut f).await?;
-                // fsync the file
-                f.sync_all().await?;
+        if header.entry_type() != tokio_tar::EntryType::Regular {
+            bail!(
+                "entry {} in backup tar archive is of unexpected type: {:?}",
+                file_path.display(),
+                header.entry_type()
+            );
+        }
+        let utf8_file_path = Utf8PathBuf::from_path_buf(file_path).expect("non-Unicode path");
+        let name = utf8_file_path.as_str();
+
+        if let Some((seg_name, segno, is_partial, crc)) = unverified.take() {
+            if name != format!("{seg_name}{SEGMENT_CHECKSUM_SUFFIX}") {
+                bail!("expected checksum of segment {seg_name}, got entry {name}");
             }
-            _ => {
+            let mut expected = String::new();
+            entry.read_to_string(&mut expected).await?;
+            if expected != format!("{crc:08x}") {
+                tokio::fs::remove_file(tli_dir_path.join(&seg_name)).await?;
                 bail!(
-                    "entry {} in backup tar archive is of unexpected type: {:?}",
-                    file_path.display(),
-                    header.entry_type()
+                    "checksum mismatch for segment {seg_name}: expected {expected}, got {crc:08x}"
                 );
             }
+            if !is_partial {
+                let state = state
+                    .as_mut()
+                    .expect("pull state is initialized with manifest");
+                state.verified_up_to = Some(segno);
+                persist_pull_state(tli_dir_path, state, !conf.no_sync).await?;
+            }
+            progress.segments_done += 1;
+            global_timelines.report_pull_progress(progress);
+            continue;
+        }
+
+        if name == SNAPSHOT_MANIFEST_NAME {
+            let mut mbuf = Vec::new();
+            entry.read_to_end(&mut mbuf).await?;
+            let m: SnapshotManifest = serde_json::from_slice(&mbuf)?;
+            // Local segments can be used only if donor history still
+            // contains them and it sends exactly the rest.
+            let can_resume = state.as_ref().is_some_and(|s| {
+                s.last_log_term == m.last_log_term
+                    && s.wal_seg_size == m.wal_seg_size
+                    && match s.verified_up_to {
+                        None => true,
+                        Some(segno) => {
+                            m.flush_lsn.segment_number(m.wal_seg_size) > segno
+                                && m.segnos.is_some_and(|(from, _)| from == segno + 1)
+                        }
+                    }
+            });
+            if !can_resume {
+                if kept > 0 {
+                    info!(
+                        "cannot resume pull of timeline {ttid} from segment {from_segno:#X}, starting over"
+                    );
+                }
+                remove_pulled_segments(tli_dir_path).await?;
+                progress.segments_done = 0;
+                state = Some(PullState {
+                    safekeeper_host: host.to_owned(),
+                    last_log_term: m.last_log_term,
+                    wal_seg_size: m.wal_seg_size,
+                    verified_up_to: None,
+                });
+            }
+            persist_pull_state(
+                tli_dir_path,
+                state.as_ref().expect("just initialized"),
+                !conf.no_sync,
+            )
+            .await?;
+            if let Some((from, upto)) = m.segnos {
+                expected_segno = from;
+                progress.segments_total = progress.segments_done + (upto - from + 1);
+            } else {
+                progress.segments_total = progress.segments_done;
+            }
+            global_timelines.report_pull_progress(progress);
+            manifest = Some(m);
+            continue;
+        }
+
+        let is_segment = is_segment_file_name(OsStr::new(name));
+        if is_segment && manifest.is_none() && !legacy {
+            // Control file is followed by manifest unless the donor is too
+            // old, so receive everything from scratch.
+            info!("donor {host} doesn't support resumable snapshots");
+            legacy = true;
+            remove_pulled_segments(tli_dir_path).await?;
+            tokio::fs::remove_file(tli_dir_path.join(PULL_STATE_NAME))
+                .await
+                .or_else(ignore_not_found)?;
+            state = None;
+            progress.segments_done = 0;
+        }
+
+        let dst_path = tli_dir_path.join(&utf8_file_path);
+        let mut f = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&dst_path)
+            .await?;
+        let mut crc = 0;
+        loop {
+            let n = entry.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            crc = crc32c::crc32c_append(crc, &buf[..n]);
+            f.write_all(&buf[..n]).await?;
+            progress.bytes_received += n as u64;
+        }
+        // fsync the file
+        f.sync_all().await?;
+
+        if is_segment {
+            if let Some(m) = &manifest {
+                let (segno, _) = XLogFromFileName(OsStr::new(name), m.wal_seg_size)?;
+                if segno != expected_segno {
+                    bail!("expected segment {expected_segno:#X} in snapshot, got {name}");
+                }
+                expected_segno += 1;
+                unverified = Some((
+                    name.to_owned(),
+                    segno,
+                    IsPartialXLogFileName(OsStr::new(name)),
+                    crc,
+                ));
+            } else {
+                progress.segments_done += 1;
+                global_timelines.report_pull_progress(progress);
+            }
         }
     }
 
-    // fsync temp timeline directory to remember its contents.
-    fsync_async_opt(&tli_dir_path, !conf.no_sync).await?;
+    // Abrupt stream end is not necessarily reported as an error, so check that
+    // everything promised by the manifest was received.
+    if let Some((seg_name, ..)) = unverified {
+        bail!("snapshot stream ended before checksum of segment {seg_name}");
+    }
+    match &manifest {
+        Some(m) => {
+            if let Some((_, upto)) = m.segnos {
+                if expected_segno != upto + 1 {
+                    bail!(
+                        "snapshot stream ended prematurely: expected segments up to {upto:#X}, next expected {expected_segno:#X}"
+                    );
+                }
+            }
+        }
+        None if !legacy => {
+            // Old donor which had no segments to send.
+            remove_pulled_segments(tli_dir_path).await?;
+        }
+        None => {}
+    }
 
-    let generation = mconf.as_ref().map(|c| c.generation);
+    // fsync pull directory to remember its contents.
+    fsync_async_opt(tli_dir_path, !conf.no_sync).await?;
+    Ok(())
+}
 
-    // Let's create timeline from temp directory and verify that it's correct
-    let (commit_lsn, flush_lsn) =
-        validate_temp_timeline(conf, ttid, &tli_dir_path, generation).await?;
-    info!(
-        "finished downloading timeline {}, commit_lsn={}, flush_lsn={}",
-        ttid, commit_lsn, flush_lsn
-    );
-    assert!(status.commit_lsn <= status.flush_lsn);
+#[cfg(test)]
+mod tests {
+    use camino_tempfile::Utf8TempDir;
 
-    // Finally, load the timeline.
-    let timeline = global_timelines
-        .load_temp_timeline(ttid, &tli_dir_path, generation)
-        .await?;
+    use super::*;
+    use crate::wal_backup::WalBackup;
 
-    if let Some(mconf) = mconf {
-        // Switch to provided mconf to guarantee that the timeline will not
-        // be deleted by request with older generation.
-        // The generation might already be higer than the one in mconf, e.g.
-        // if another membership_switch request was executed between `load_temp_timeline`
-        // and `membership_switch`, but that's totaly fine. `membership_switch` will
-        // ignore switch to older generation.
-        timeline.membership_switch(mconf).await?;
+    const WAL_SEG_SIZE: usize = 8192;
+    const HOST: &str = "donor:7676";
+
+    struct TestPull {
+        tempdir: Utf8TempDir,
+        conf: SafeKeeperConf,
+        global_timelines: GlobalTimelines,
+        ttid: TenantTimelineId,
+        progress: PullTimelineProgress,
     }
 
-    Ok(PullTimelineResponse {
-        safekeeper_host: Some(host),
-    })
+    impl TestPull {
+        async fn new() -> Self {
+            let tempdir = camino_tempfile::tempdir().unwrap();
+            let mut conf = SafeKeeperConf::dummy();
+            conf.no_sync = true;
+            let wal_backup = Arc::new(WalBackup::new(&conf).await.unwrap());
+            let global_timelines = GlobalTimelines::new(Arc::new(conf.clone()), wal_backup);
+            let ttid = TenantTimelineId::generate();
+            let progress = PullTimelineProgress {
+                tenant_id: ttid.tenant_id,
+                timeline_id: ttid.timeline_id,
+                safekeeper_host: HOST.to_owned(),
+                status: PullTimelineStatus::InProgress,
+                attempts: 0,
+                segments_total: 0,
+                segments_done: 0,
+                bytes_received: 0,
+                last_error: None,
+            };
+            Self {
+                tempdir,
+                conf,
+                global_timelines,
+                ttid,
+                progress,
+            }
+        }
+
+        fn dir(&self) -> &Utf8Path {
+            self.tempdir.path()
+        }
+
+        /// Download `archive`, checking that the pull starts at `from_segno`.
+        async fn download(&mut self, archive: Vec<u8>, from_segno: XLogSegNo) -> Result<()> {
+            download_snapshot(
+                |segno| async move {
+                    assert_eq!(segno, from_segno);
+                    Ok(std::io::Cursor::new(archive))
+                },
+                &self.conf,
+                self.ttid,
+                HOST,
+                self.tempdir.path(),
+                &self.global_timelines,
+                &mut self.progress,
+            )
+            .await
+        }
+
+        async fn verified_up_to(&self) -> Option<XLogSegNo> {
+            read_pull_state(self.dir())
+                .await
+                .unwrap()
+                .expect("pull state should exist")
+                .verified_up_to
+        }
+    }
+
+    fn segment_name(segno: XLogSegNo) -> String {
+        XLogFileName(PG_TLI, segno, WAL_SEG_SIZE)
+    }
+
+    fn segment_data(segno: XLogSegNo) -> Vec<u8> {
+        vec![segno as u8; WAL_SEG_SIZE]
+    }
+
+    /// Builds resumable snapshot archive sending segments `from..=upto`. If
+    /// `corrupt` is set, the checksum of that segment is wrong. If `truncate`
+    /// is set, the archive ends before that segment.
+    #[derive(Default)]
+    struct ArchiveBuilder {
+        corrupt: Option<XLogSegNo>,
+        truncate: Option<XLogSegNo>,
+    }
+
+    impl ArchiveBuilder {
+        async fn build(&self, from: XLogSegNo, upto: XLogSegNo) -> Vec<u8> {
+            let mut ar = Builder::new(Vec::new());
+            let manifest = SnapshotManifest {
+                term: 2,
+                last_log_term: 2,
+                flush_lsn: Lsn((upto * WAL_SEG_SIZE as u64) + 100),
+                wal_seg_size: WAL_SEG_SIZE,
+                segnos: Some((from, upto)),
+            };
+            let entries = [
+                (CONTROL_FILE_NAME.to_owned(), b"control".to_vec()),
+                (
+                    SNAPSHOT_MANIFEST_NAME.to_owned(),
+                    serde_json::to_vec(&manifest).unwrap(),
+                ),
+            ]
+            .into_iter()
+            .chain(
+                (from..=upto)
+                    .take_while(|&segno| Some(segno) != self.truncate)
+                    .flat_map(|segno| {
+                        let data = segment_data(segno);
+                        let mut crc = crc32c::crc32c(&data);
+                        if Some(segno) == self.corrupt {
+                            crc ^= 1;
+                        }
+                        let mut name = segment_name(segno);
+                        if segno == upto {
+                            name.push_str(".partial");
+                        }
+                        let crc_name = format!("{name}{SEGMENT_CHECKSUM_SUFFIX}");
+                        [(name, data), (crc_name, format!("{crc:08x}").into_bytes())]
+                    }),
+            );
+            for (name, data) in entries {
+                let mut header = Header::new_gnu();
+                header.set_size(data.len() as u64);
+                ar.append_data(&mut header, name, data.as_slice())
+                    .await
+                    .unwrap();
+            }
+            ar.into_inner().await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_reuses_verified_segments() {
+        let mut pull = TestPull::new().await;
+
+        // Connection breaks after two segments were received.
+        let archive = ArchiveBuilder {
+            truncate: Some(3),
+            ..Default::default()
+        }
+        .build(1, 4)
+        .await;
+        let err = pull.download(archive, 0).await.unwrap_err();
+        assert!(err.to_string().contains("ended prematurely"), "{err:#}");
+        assert_eq!(pull.verified_up_to().await, Some(2));
+
+        // Mark the received segment to check that it is not downloaded again.
+        let seg1 = pull.dir().join(segment_name(1));
+        tokio::fs::write(&seg1, b"kept").await.unwrap();
+
+        let archive = ArchiveBuilder::default().build(3, 4).await;
+        pull.download(archive, 3).await.unwrap();
+        assert_eq!(tokio::fs::read(&seg1).await.unwrap(), b"kept");
+        for segno in 2..=3 {
+            let data = tokio::fs::read(pull.dir().join(segment_name(segno)))
+                .await
+                .unwrap();
+            assert_eq!(data, segment_data(segno));
+        }
+        let partial = pull.dir().join(format!("{}.partial", segment_name(4)));
+        assert!(partial.exists());
+        assert_eq!(pull.progress.segments_done, 4);
+        assert_eq!(pull.progress.segments_total, 4);
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch_forces_redownload() {
+        let mut pull = TestPull::new().await;
+
+        let archive = ArchiveBuilder {
+            corrupt: Some(2),
+            ..Default::default()
+        }
+        .build(1, 3)
+        .await;
+        let err = pull.download(archive, 0).await.unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{err:#}");
+        // Corrupted segment is removed and not counted as verified.
+        assert!(!pull.dir().join(segment_name(2)).exists());
+        assert_eq!(pull.verified_up_to().await, Some(1));
+
+        // Next attempt downloads the segment again.
+        let archive = ArchiveBuilder::default().build(2, 3).await;
+        pull.download(archive, 2).await.unwrap();
+        let data = tokio::fs::read(pull.dir().join(segment_name(2)))
+            .await
+            .unwrap();
+        assert_eq!(data, segment_data(2));
+    }
+
+    #[tokio::test]
+    async fn test_clean_pull_dir() {
+        let tempdir = camino_tempfile::tempdir().unwrap();
+        let dir = tempdir.path();
+        let files = [
+            PULL_STATE_NAME.to_owned(),
+            SNAPSHOT_MANIFEST_NAME.to_owned(),
+            CONTROL_FILE_NAME.to_owned(),
+            segment_name(1),
+            segment_name(2),
+            segment_name(3),
+            format!("{}.partial", segment_name(4)),
+        ];
+        for name in &files {
+            tokio::fs::write(dir.join(name), b"data").await.unwrap();
+        }
+
+        let kept = clean_pull_dir(dir, Some((2, WAL_SEG_SIZE))).await.unwrap();
+        assert_eq!(kept, 2);
+        let mut left = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        let mut expected = vec![PULL_STATE_NAME.to_owned(), segment_name(1), segment_name(2)];
+        expected.sort();
+        assert_eq!(left, expected);
+
+        // Without state to resume from everything is removed.
+        let kept = clean_pull_dir(dir, None).await.unwrap();
+        assert_eq!(kept, 0);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+    }
 }
@@ -9,16 +9,22 @@
 pub struct RateLimiter {
     partial_backup: Arc<tokio::sync::Semaphore>,
     eviction: Arc<tokio::sync::Semaphore>,
+    pull_timeline: Arc<tokio::sync::Semaphore>,
+    snapshot: Arc<tokio::sync::Semaphore>,
 }
 
 impl RateLimiter {
     /// Create a new rate limiter.
     /// - `partial_backup_max`: maximum number of concurrent partial backups.
     /// - `eviction_max`: maximum number of concurrent timeline evictions.
-    pub fn new(partial_backup_max: usize, eviction_max: usize) -> Self {
+    /// - `pull_timeline_max`: maximum number of concurrent timeline pulls into
+    ///   this safekeeper, and separately of snapshots streamed to peers.
+    pub fn new(partial_backup_max: usize, eviction_max: usize, pull_timeline_max: usize) -> Self {
         Self {
             partial_backup: Arc::new(tokio::sync::Semaphore::new(partial_backup_max)),
             eviction: Arc::new(tokio::sync::Semaphore::new(eviction_max)),
+            pull_timeline: Arc::new(tokio::sync::Semaphore::new(pull_timeline_max)),
+            snapshot: Arc::new(tokio::sync::Semaphore::new(pull_timeline_max)),
         }
     }
 
@@ -35,6 +41,33 @@ pub async fn acquire_partial_backup(&self) -> tokio::sync::OwnedSemaphorePermit
             .expect("semaphore is closed")
     }
 
+    /// Get a permit for pulling a timeline from a peer. This will block if the
+    /// maximum number of concurrent pulls is reached.
+    pub async fn acquire_pull_timeline(&self) -> tokio::sync::OwnedSemaphorePermit {
+        let _timer = MISC_OPERATION_SECONDS
+            .with_label_values(&["pull_timeline_permit_acquire"])
+            .start_timer();
+        self.pull_timeline
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is closed")
+    }
+
+    /// Get a permit for streaming a timeline snapshot to a peer. Limited
+    /// separately from pulls so that two safekeepers pulling from each other
+    /// can't deadlock.
+    pub async fn acquire_snapshot(&self) -> tokio::sync::OwnedSemaphorePermit {
+        let _timer = MISC_OPERATION_SECONDS
+            .with_label_values(&["snapshot_permit_acquire"])
+            .start_timer();
+        self.snapshot
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is closed")
+    }
+
     /// Try to get a permit for timeline eviction. This will return None if the maximum number of
     /// concurrent timeline evictions is reached.
     pub fn try_acquire_eviction(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
@@ -573,6 +573,16 @@ async fn run(&mut self) -> anyhow::Result<()> {
         // Tracks whether we have unflushed appends.
         let mut dirty = false;
 
+        // In remote_apply commit mode pageserver progress is reported to the
+        // proposer. Feedback is forwarded only after election, before that
+        // proposer doesn't expect AppendResponse.
+        let mut pageserver_feedback_rx = self
+            .tli
+            .get_walreceivers()
+            .pageserver_feedback_tx
+            .subscribe();
+        let mut elected = false;
+
         while !self.tli.is_cancelled() {
             let reply = tokio::select! {
                 // Process inbound message.
@@ -589,6 +599,7 @@ async fn run(&mut self) -> anyhow::Result<()> {
                     // Update walreceiver state in shmem for reporting.
                     if let ProposerAcceptorMessage::Elected(_) = &msg {
                         walreceiver_guard.get().status = WalReceiverStatus::Streaming;
+                        elected = true;
                     }
 
                     // Don't flush the WAL on every append, only periodically via flush_ticker.
@@ -626,6 +637,14 @@ async fn run(&mut self) -> anyhow::Result<()> {
                         .await?
                 }
 
+                feedback = pageserver_feedback_rx.recv(), if elected => {
+                    match feedback {
+                        Ok(feedback) => self.tli.record_pageserver_feedback(&feedback).await,
+                        // Lagged, latest feedback will arrive soon.
+                        Err(_) => None,
+                    }
+                }
+
                 // Update histogram metrics periodically.
                 _ = metrics_ticker.tick() => {
                     WAL_RECEIVER_QUEUE_DEPTH.observe(self.msg_rx.len() as f64);
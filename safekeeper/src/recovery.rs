@@ -73,7 +73,8 @@ async fn recovery_needed(
     let last_log_term = ss.sk.last_log_term();
     let flush_lsn = ss.sk.flush_lsn();
     // note that peers contain myself, but that's ok -- we are interested only in peers which are strictly ahead of us.
-    let mut peers = ss.get_peers(heartbeat_timeout);
+    // Witnesses have no WAL to recover from.
+    let mut peers = ss.get_full_peers(heartbeat_timeout);
     // Sort by <last log term, lsn> pairs.
     peers.sort_by(|p1, p2| {
         let tl1 = TermLsn {
@@ -31,3 +31,28 @@ pub(crate) fn calc_horizon_lsn(state: &StateSnapshot, extra_horizon_lsn: Option<
 
     horizon_lsn
 }
+
+/// Get LSN up to which WAL can be moved to the cold WAL directory.
+///
+/// Only committed WAL is moved, and, like removal, not beyond local commit_lsn
+/// as we start looking for the end of WAL from there. If WAL backup is enabled,
+/// only uploaded WAL is moved, so that backup always reads from the hot
+/// directory. Finally, WAL still needed by active walsenders (`walsenders_lsn`)
+/// stays hot to keep them reading from the fast disk.
+pub(crate) fn calc_cold_horizon_lsn(
+    state: &StateSnapshot,
+    wal_backup_enabled: bool,
+    walsenders_lsn: Option<Lsn>,
+) -> Lsn {
+    use std::cmp::min;
+
+    let mut horizon_lsn = min(state.cfile_commit_lsn, state.flush_lsn);
+    if wal_backup_enabled {
+        horizon_lsn = min(horizon_lsn, state.cfile_backup_lsn);
+    }
+    if let Some(walsenders_lsn) = walsenders_lsn {
+        horizon_lsn = min(horizon_lsn, walsenders_lsn);
+    }
+
+    horizon_lsn
+}
@@ -1,6 +1,7 @@
 //! Acceptor part of proposer-acceptor consensus algorithm.
 
 use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::fmt;
 use std::io::Read;
 use std::str::FromStr;
@@ -12,7 +13,7 @@
 use postgres_versioninfo::{PgMajorVersion, PgVersionId};
 use pq_proto::SystemId;
 use safekeeper_api::membership::{
-    INVALID_GENERATION, MemberSet, SafekeeperGeneration as Generation, SafekeeperId,
+    INVALID_GENERATION, MemberSet, SafekeeperGeneration as Generation, SafekeeperId, SafekeeperRole,
 };
 use safekeeper_api::models::HotStandbyFeedback;
 use safekeeper_api::{Term, membership};
@@ -25,11 +26,17 @@
 use utils::pageserver_feedback::PageserverFeedback;
 
 use crate::metrics::{MISC_OPERATION_SECONDS, PROPOSER_ACCEPTOR_MESSAGES_TOTAL};
-use crate::state::TimelineState;
+use crate::state::{MembershipSwitchResult, TimelineState};
+use crate::witness::WitnessWal;
 use crate::{control_file, wal_storage};
 
 pub const SK_PROTO_VERSION_2: u32 = 2;
 pub const SK_PROTO_VERSION_3: u32 = 3;
+/// v3 plus safekeeper roles in membership configuration.
+pub const SK_PROTO_VERSION_4: u32 = 4;
+/// v4 plus commit mode negotiation and shard count in greeting, and apply_lsn in
+/// AppendResponse.
+pub const SK_PROTO_VERSION_5: u32 = 5;
 pub const UNKNOWN_SERVER_VERSION: PgVersionId = PgVersionId::UNKNOWN;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
@@ -210,6 +217,44 @@ pub fn get_last_log_term(&self, flush_lsn: Lsn) -> Term {
     }
 }
 
+/// What safekeeper waits for before acknowledging WAL to the proposer.
+/// Proposer considers WAL committed once quorum acknowledged it, so this
+/// defines durability of the timeline commits. Requested by proposer in the
+/// greeting since proto v5, older proposers always get `Flush`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommitMode {
+    /// WAL is acknowledged once it is fsynced.
+    #[default]
+    Flush,
+    /// WAL is acknowledged once it is written, fsync happens in background.
+    /// Committed WAL might be lost if majority of safekeepers crash.
+    Write,
+    /// Like `Flush`, but safekeeper also reports in `apply_lsn` up to where
+    /// pageservers ingested WAL, and proposer confirms commits to clients
+    /// only after that. commit_lsn itself can't wait for pageservers as they
+    /// get only committed WAL.
+    RemoteApply,
+}
+
+impl CommitMode {
+    fn from_u8(v: u8) -> Result<Self> {
+        match v {
+            0 => Ok(CommitMode::Flush),
+            1 => Ok(CommitMode::Write),
+            2 => Ok(CommitMode::RemoteApply),
+            v => bail!("unknown commit mode {v}"),
+        }
+    }
+
+    fn as_u8(self) -> u8 {
+        match self {
+            CommitMode::Flush => 0,
+            CommitMode::Write => 1,
+            CommitMode::RemoteApply => 2,
+        }
+    }
+}
+
 // protocol messages
 
 /// Initial Proposer -> Acceptor message
@@ -222,6 +267,10 @@ pub struct ProposerGreeting {
     pub pg_version: PgVersionId,
     pub system_id: SystemId,
     pub wal_seg_size: u32,
+    /// Requested commit mode, since proto v5.
+    pub commit_mode: CommitMode,
+    /// Number of pageserver shards of the tenant, since proto v5.
+    pub shard_count: u8,
 }
 
 /// V2 of the message; exists as a struct because we (de)serialized it as is.
@@ -246,6 +295,9 @@ pub struct AcceptorGreeting {
     node_id: NodeId,
     mconf: membership::Configuration,
     term: u64,
+    /// Commit mode safekeeper is going to follow, might differ from the
+    /// requested one.
+    commit_mode: CommitMode,
 }
 
 /// Vote request sent from proposer to safekeepers
@@ -345,6 +397,9 @@ pub struct AppendResponse {
     // We report back our awareness about which WAL is committed, as this is
     // a criterion for walproposer --sync mode exit
     pub commit_lsn: Lsn,
+    // In remote_apply commit mode, position up to which WAL was flushed here
+    // and ingested by pageservers; 0 otherwise. Since proto v5.
+    pub apply_lsn: Lsn,
     pub hs_feedback: HotStandbyFeedback,
     pub pageserver_feedback: Option<PageserverFeedback>,
 }
@@ -356,6 +411,7 @@ fn term_only(generation: Generation, term: Term) -> AppendResponse {
             term,
             flush_lsn: Lsn(0),
             commit_lsn: Lsn(0),
+            apply_lsn: Lsn(0),
             hs_feedback: HotStandbyFeedback::empty(),
             pageserver_feedback: None,
         }
@@ -424,68 +480,83 @@ fn get_cstr(buf: &mut Bytes) -> Result<String> {
         }
     }
 
-    /// Read membership::Configuration from Bytes.
-    fn get_mconf(buf: &mut Bytes) -> Result<membership::Configuration> {
-        let generation = Generation::new(buf.get_u32_f().with_context(|| "reading generation")?);
-        let members_len = buf.get_u32_f().with_context(|| "reading members_len")?;
-        // Main member set must have at least someone in valid configuration.
-        // Empty conf is allowed until we fully migrate.
-        if generation != INVALID_GENERATION && members_len == 0 {
-            bail!("empty members_len");
-        }
+    /// Read `len` members of a member set from Bytes. Since proto v4 each
+    /// member is followed by its role byte.
+    fn get_member_set(
+        buf: &mut Bytes,
+        len: u32,
+        proto_version: u32,
+        what: &str,
+    ) -> Result<MemberSet> {
         let mut members = MemberSet::empty();
-        for i in 0..members_len {
+        for i in 0..len {
             let id = buf
                 .get_u64_f()
-                .with_context(|| format!("reading member {i} node_id"))?;
-            let host = Self::get_cstr(buf).with_context(|| format!("reading member {i} host"))?;
+                .with_context(|| format!("reading {what} {i} node_id"))?;
+            let host = Self::get_cstr(buf).with_context(|| format!("reading {what} {i} host"))?;
             let pg_port = buf
                 .get_u16_f()
-                .with_context(|| format!("reading member {i} port"))?;
+                .with_context(|| format!("reading {what} {i} port"))?;
+            let role = if proto_version >= SK_PROTO_VERSION_4 {
+                match buf
+                    .get_u8_f()
+                    .with_context(|| format!("reading {what} {i} role"))?
+                {
+                    0 => SafekeeperRole::Full,
+                    1 => SafekeeperRole::Witness,
+                    r => bail!("unknown {what} {i} role {r}"),
+                }
+            } else {
+                SafekeeperRole::Full
+            };
             let sk = SafekeeperId {
                 id: NodeId(id),
                 host,
                 pg_port,
+                role,
             };
             members.add(sk)?;
         }
+        Ok(members)
+    }
+
+    /// Read membership::Configuration from Bytes.
+    fn get_mconf(buf: &mut Bytes, proto_version: u32) -> Result<membership::Configuration> {
+        let generation = Generation::new(buf.get_u32_f().with_context(|| "reading generation")?);
+        let members_len = buf.get_u32_f().with_context(|| "reading members_len")?;
+        // Main member set must have at least someone in valid configuration.
+        // Empty conf is allowed until we fully migrate.
+        if generation != INVALID_GENERATION && members_len == 0 {
+            bail!("empty members_len");
+        }
+        let members = Self::get_member_set(buf, members_len, proto_version, "member")?;
         let new_members_len = buf.get_u32_f().with_context(|| "reading new_members_len")?;
         // Non joint conf.
-        if new_members_len == 0 {
-            Ok(membership::Configuration {
-                generation,
-                members,
-                new_members: None,
-            })
+        let new_members = if new_members_len == 0 {
+            None
         } else {
-            let mut new_members = MemberSet::empty();
-            for i in 0..new_members_len {
-                let id = buf
-                    .get_u64_f()
-                    .with_context(|| format!("reading new member {i} node_id"))?;
-                let host =
-                    Self::get_cstr(buf).with_context(|| format!("reading new member {i} host"))?;
-                let pg_port = buf
-                    .get_u16_f()
-                    .with_context(|| format!("reading new member {i} port"))?;
-                let sk = SafekeeperId {
-                    id: NodeId(id),
-                    host,
-                    pg_port,
-                };
-                new_members.add(sk)?;
-            }
-            Ok(membership::Configuration {
-                generation,
-                members,
-                new_members: Some(new_members),
-            })
-        }
+            Some(Self::get_member_set(
+                buf,
+                new_members_len,
+                proto_version,
+                "new member",
+            )?)
+        };
+        let mconf = membership::Configuration {
+            generation,
+            members,
+            new_members,
+        };
+        mconf.validate_witnesses()?;
+        Ok(mconf)
     }
 
     /// Parse proposer message.
     pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAcceptorMessage> {
-        if proto_version == SK_PROTO_VERSION_3 {
+        if matches!(
+            proto_version,
+            SK_PROTO_VERSION_3 | SK_PROTO_VERSION_4 | SK_PROTO_VERSION_5
+        ) {
             if msg_bytes.is_empty() {
                 bail!("ProposerAcceptorMessage is not complete: missing tag");
             }
@@ -500,7 +571,7 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                     let timeline_id_str =
                         Self::get_cstr(&mut msg_bytes).with_context(|| "reading timeline_id")?;
                     let timeline_id = TimelineId::from_str(&timeline_id_str)?;
-                    let mconf = Self::get_mconf(&mut msg_bytes)?;
+                    let mconf = Self::get_mconf(&mut msg_bytes, proto_version)?;
                     let pg_version = msg_bytes
                         .get_u32_f()
                         .with_context(|| "reading pg_version")?;
@@ -508,6 +579,19 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                     let wal_seg_size = msg_bytes
                         .get_u32_f()
                         .with_context(|| "reading wal_seg_size")?;
+                    let (commit_mode, shard_count) = if proto_version >= SK_PROTO_VERSION_5 {
+                        let commit_mode = CommitMode::from_u8(
+                            msg_bytes
+                                .get_u8_f()
+                                .with_context(|| "reading commit_mode")?,
+                        )?;
+                        let shard_count = msg_bytes
+                            .get_u8_f()
+                            .with_context(|| "reading shard_count")?;
+                        (commit_mode, shard_count)
+                    } else {
+                        (CommitMode::Flush, 1)
+                    };
                     let g = ProposerGreeting {
                         tenant_id,
                         timeline_id,
@@ -515,6 +599,8 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                         pg_version: PgVersionId::from_full_pg_version(pg_version),
                         system_id,
                         wal_seg_size,
+                        commit_mode,
+                        shard_count,
                     };
                     Ok(ProposerAcceptorMessage::Greeting(g))
                 }
@@ -623,6 +709,8 @@ pub fn parse(mut msg_bytes: Bytes, proto_version: u32) -> Result<ProposerAccepto
                         pg_version: msgv2.pg_version,
                         system_id: msgv2.system_id,
                         wal_seg_size: msgv2.wal_seg_size,
+                        commit_mode: CommitMode::Flush,
+                        shard_count: 1,
                     };
                     Ok(ProposerAcceptorMessage::Greeting(g))
                 }
@@ -755,36 +843,63 @@ fn put_cstr(buf: &mut BytesMut, s: &str) {
         buf.put_u8(0); // null terminator
     }
 
-    /// Serialize membership::Configuration into buf.
-    fn serialize_mconf(buf: &mut BytesMut, mconf: &membership::Configuration) {
-        buf.put_u32(mconf.generation.into_inner());
-        buf.put_u32(mconf.members.m.len() as u32);
-        for sk in &mconf.members.m {
+    /// Serialize member set into buf. Since proto v4 each member is followed
+    /// by its role byte.
+    fn serialize_member_set(buf: &mut BytesMut, members: &MemberSet, proto_version: u32) {
+        buf.put_u32(members.m.len() as u32);
+        for sk in &members.m {
             buf.put_u64(sk.id.0);
             Self::put_cstr(buf, &sk.host);
             buf.put_u16(sk.pg_port);
+            if proto_version >= SK_PROTO_VERSION_4 {
+                buf.put_u8(match sk.role {
+                    SafekeeperRole::Full => 0,
+                    SafekeeperRole::Witness => 1,
+                });
+            }
         }
+    }
+
+    /// Serialize membership::Configuration into buf.
+    fn serialize_mconf(
+        buf: &mut BytesMut,
+        mconf: &membership::Configuration,
+        proto_version: u32,
+    ) -> Result<()> {
+        // Proposers speaking v3 don't know about witnesses and might pick one
+        // as a donor, so refuse to hand them such configuration.
+        if proto_version < SK_PROTO_VERSION_4 && mconf.has_witnesses() {
+            bail!(
+                "configuration {} has witnesses, proto version {} can't represent it",
+                mconf,
+                proto_version
+            );
+        }
+        buf.put_u32(mconf.generation.into_inner());
+        Self::serialize_member_set(buf, &mconf.members, proto_version);
         if let Some(ref new_members) = mconf.new_members {
-            buf.put_u32(new_members.m.len() as u32);
-            for sk in &new_members.m {
-                buf.put_u64(sk.id.0);
-                Self::put_cstr(buf, &sk.host);
-                buf.put_u16(sk.pg_port);
-            }
+            Self::serialize_member_set(buf, new_members, proto_version);
         } else {
             buf.put_u32(0);
         }
+        Ok(())
     }
 
     /// Serialize acceptor -> proposer message.
     pub fn serialize(&self, buf: &mut BytesMut, proto_version: u32) -> Result<()> {
-        if proto_version == SK_PROTO_VERSION_3 {
+        if matches!(
+            proto_version,
+            SK_PROTO_VERSION_3 | SK_PROTO_VERSION_4 | SK_PROTO_VERSION_5
+        ) {
             match self {
                 AcceptorProposerMessage::Greeting(msg) => {
                     buf.put_u8(b'g');
                     buf.put_u64(msg.node_id.0);
-                    Self::serialize_mconf(buf, &msg.mconf);
-                    buf.put_u64(msg.term)
+                    Self::serialize_mconf(buf, &msg.mconf, proto_version)?;
+                    buf.put_u64(msg.term);
+                    if proto_version >= SK_PROTO_VERSION_5 {
+                        buf.put_u8(msg.commit_mode.as_u8());
+                    }
                 }
                 AcceptorProposerMessage::VoteResponse(msg) => {
                     buf.put_u8(b'v');
@@ -805,6 +920,9 @@ pub fn serialize(&self, buf: &mut BytesMut, proto_version: u32) -> Result<()> {
                     buf.put_u64(msg.term);
                     buf.put_u64(msg.flush_lsn.into());
                     buf.put_u64(msg.commit_lsn.into());
+                    if proto_version >= SK_PROTO_VERSION_5 {
+                        buf.put_u64(msg.apply_lsn.into());
+                    }
                     buf.put_i64(msg.hs_feedback.ts);
                     buf.put_u64(msg.hs_feedback.xmin);
                     buf.put_u64(msg.hs_feedback.catalog_xmin);
@@ -878,6 +996,17 @@ pub struct SafeKeeper<CTRL: control_file::Storage, WAL: wal_storage::Storage> {
 
     pub state: TimelineState<CTRL>, // persistent state storage
     pub wal_store: WAL,
+    /// Set if we are a witness member of the current configuration; then WAL
+    /// is not written to `wal_store`, only positions are tracked.
+    witness: Option<WitnessWal>,
+
+    /// Commit mode negotiated with the current proposer.
+    commit_mode: CommitMode,
+    /// Number of pageserver shards of the tenant, from the proposer greeting.
+    shard_count: u8,
+    /// Last WAL position received and ingested by pageservers, per shard
+    /// number, from their feedback to us.
+    ps_received_lsns: HashMap<u32, Lsn>,
 
     node_id: NodeId, // safekeeper's node id
 }
@@ -905,14 +1034,122 @@ pub fn new(
             );
         }
 
+        let witness = if state.mconf.is_witness(node_id) {
+            Some(Self::new_witness_wal(&state, &wal_store)?)
+        } else {
+            None
+        };
+
         Ok(SafeKeeper {
             term_start_lsn: Lsn(0),
             state,
             wal_store,
+            witness,
+            commit_mode: CommitMode::Flush,
+            shard_count: 1,
+            ps_received_lsns: HashMap::new(),
             node_id,
         })
     }
 
+    /// Create witness position tracker. Timeline might have had WAL before
+    /// becoming a witness (e.g. pulled from a peer), so take it into account.
+    fn new_witness_wal(state: &TimelineState<CTRL>, wal_store: &WAL) -> Result<WitnessWal> {
+        let flush_lsn = max(
+            max(wal_store.flush_lsn(), state.witness_flush_lsn),
+            state.commit_lsn,
+        );
+        Ok(WitnessWal::new(
+            flush_lsn,
+            PgMajorVersion::try_from(state.server.pg_version)?,
+        ))
+    }
+
+    /// Whether we are a witness in the current configuration.
+    pub fn is_witness(&self) -> bool {
+        self.witness.is_some()
+    }
+
+    fn write_lsn(&self) -> Lsn {
+        match &self.witness {
+            Some(witness) => witness.write_lsn(),
+            None => self.wal_store.write_lsn(),
+        }
+    }
+
+    fn write_record_lsn(&self) -> Lsn {
+        match &self.witness {
+            Some(witness) => witness.write_record_lsn(),
+            None => self.wal_store.write_record_lsn(),
+        }
+    }
+
+    /// LSN of last durably stored (or, for witness, acknowledged) WAL record.
+    pub fn storage_flush_lsn(&self) -> Lsn {
+        match &self.witness {
+            Some(witness) => witness.flush_lsn(),
+            None => self.wal_store.flush_lsn(),
+        }
+    }
+
+    async fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
+        match &mut self.witness {
+            Some(witness) => witness.write_wal(startpos, buf),
+            None => self.wal_store.write_wal(startpos, buf).await,
+        }
+    }
+
+    /// Flush WAL. Witness has nothing to fsync, but the position it is going
+    /// to acknowledge must survive restart, so it is persisted in the control
+    /// file instead.
+    async fn flush_wal(&mut self) -> Result<()> {
+        let Some(witness) = &mut self.witness else {
+            return self.wal_store.flush_wal().await;
+        };
+        let flush_lsn = witness.flush_wal();
+        if flush_lsn != self.state.witness_flush_lsn {
+            let mut state = self.state.start_change();
+            state.witness_flush_lsn = flush_lsn;
+            self.state.finish_change(&state).await?;
+        }
+        Ok(())
+    }
+
+    async fn truncate_wal(&mut self, end_pos: Lsn) -> Result<()> {
+        match &mut self.witness {
+            Some(witness) => witness.truncate_wal(end_pos),
+            None => self.wal_store.truncate_wal(end_pos).await,
+        }
+    }
+
+    /// Switch into the given configuration if it is higher and update the
+    /// witness state accordingly.
+    pub async fn membership_switch(
+        &mut self,
+        to: membership::Configuration,
+    ) -> Result<MembershipSwitchResult> {
+        let res = self.state.membership_switch(to).await?;
+        let is_witness = self.state.mconf.is_witness(self.node_id);
+        if is_witness && self.witness.is_none() {
+            // Member can't change its role, so this happens only when a
+            // timeline was created (pulled) with data and then joined as a
+            // witness.
+            let witness = Self::new_witness_wal(&self.state, &self.wal_store)?;
+            info!(
+                "became witness in {}, flush_lsn={}",
+                self.state.mconf,
+                witness.flush_lsn()
+            );
+            self.witness = Some(witness);
+        } else if !is_witness && self.witness.is_some() {
+            bail!(
+                "witness can't become a full member, configuration {}",
+                self.state.mconf
+            );
+        }
+        Ok(res)
+    }
+
     /// Get history of term switches for the available WAL
     fn get_term_history(&self) -> TermHistory {
         self.state
@@ -929,7 +1166,63 @@ pub fn get_last_log_term(&self) -> Term {
 
     /// wal_store wrapper avoiding commit_lsn <= flush_lsn violation when we don't have WAL yet.
     pub fn flush_lsn(&self) -> Lsn {
-        max(self.wal_store.flush_lsn(), self.state.timeline_start_lsn)
+        max(self.storage_flush_lsn(), self.state.timeline_start_lsn)
+    }
+
+    /// Position acknowledged to the proposer as flushed in AppendResponse.
+    fn ack_lsn(&self) -> Lsn {
+        match self.commit_mode {
+            CommitMode::Flush | CommitMode::RemoteApply => self.flush_lsn(),
+            CommitMode::Write => max(self.write_record_lsn(), self.flush_lsn()),
+        }
+    }
+
+    /// Position up to which WAL is flushed here and ingested by all
+    /// pageserver shards, in remote_apply mode. Invalid until every shard
+    /// has reported to us.
+    fn apply_lsn(&self) -> Lsn {
+        if self.commit_mode != CommitMode::RemoteApply {
+            return Lsn::INVALID;
+        }
+        let mut apply_lsn = self.flush_lsn();
+        for shard_number in 0..u32::from(self.shard_count) {
+            match self.ps_received_lsns.get(&shard_number) {
+                Some(&received_lsn) => apply_lsn = min(apply_lsn, received_lsn),
+                None => return Lsn::INVALID,
+            }
+        }
+        apply_lsn
+    }
+
+    /// Choose commit mode to follow given the one requested by proposer.
+    fn negotiate_commit_mode(&self, requested: CommitMode) -> CommitMode {
+        match requested {
+            // Witness doesn't serve pageservers, so it has nothing to report.
+            // That's fine as any quorum includes a full member which does.
+            CommitMode::RemoteApply if self.is_witness() => CommitMode::Flush,
+            requested => requested,
+        }
+    }
+
+    /// Remember pageserver feedback. In remote_apply mode, returns
+    /// AppendResponse with the new apply_lsn if it advanced.
+    pub fn record_pageserver_feedback(
+        &mut self,
+        feedback: &PageserverFeedback,
+    ) -> Option<AcceptorProposerMessage> {
+        let prev_apply_lsn = self.apply_lsn();
+        let received_lsn = self
+            .ps_received_lsns
+            .entry(feedback.shard_number)
+            .or_insert(Lsn::INVALID);
+        *received_lsn = max(*received_lsn, feedback.last_received_lsn);
+        if self.apply_lsn() > prev_apply_lsn {
+            Some(AcceptorProposerMessage::AppendResponse(
+                self.append_response(),
+            ))
+        } else {
+            None
+        }
     }
 
     /// Process message from proposer and possibly form reply. Concurrent
@@ -1034,12 +1327,22 @@ async fn handle_greeting(
             );
         }
         // Switch into conf given by proposer conf if it is higher.
-        self.state.membership_switch(msg.mconf.clone()).await?;
+        self.membership_switch(msg.mconf.clone()).await?;
+
+        self.commit_mode = self.negotiate_commit_mode(msg.commit_mode);
+        self.shard_count = max(msg.shard_count, 1);
+        if self.commit_mode != msg.commit_mode {
+            info!(
+                "proposer requested commit mode {:?}, using {:?}",
+                msg.commit_mode, self.commit_mode
+            );
+        }
 
         let apg = AcceptorGreeting {
             node_id: self.node_id,
             mconf: self.state.mconf.clone(),
             term: self.state.acceptor_state.term,
+            commit_mode: self.commit_mode,
         };
         info!(
             "processed greeting {:?} from walproposer, sending {:?}",
@@ -1065,7 +1368,7 @@ async fn handle_vote_request(
         // streaming at end of our WAL, without overlap. WAL is truncated at
         // streaming point and commit_lsn may be advanced from peers, so this
         // also avoids possible spurious attempt to truncate committed WAL.
-        self.wal_store.flush_wal().await?;
+        self.flush_wal().await?;
         // initialize with refusal
         let mut resp = VoteResponse {
             generation: self.state.mconf.generation,
@@ -1093,8 +1396,9 @@ fn append_response(&self) -> AppendResponse {
         let ar = AppendResponse {
             generation: self.state.mconf.generation,
             term: self.state.acceptor_state.term,
-            flush_lsn: self.flush_lsn(),
+            flush_lsn: self.ack_lsn(),
             commit_lsn: self.state.commit_lsn,
+            apply_lsn: self.apply_lsn(),
             // will be filled by the upper code to avoid bothering safekeeper
             hs_feedback: HotStandbyFeedback::empty(),
             pageserver_feedback: None,
@@ -1189,15 +1493,15 @@ async fn handle_elected(
         // segment and page headers.
         //
         // If we fail before first WAL write flush this action would be
-        // repeated, that's ok because it is idempotent.
-        if self.wal_store.flush_lsn() == Lsn::INVALID {
+        // repeated, that's ok because it is idempotent. Witness has no WAL.
+        if self.witness.is_none() && self.wal_store.flush_lsn() == Lsn::INVALID {
             self.wal_store
                 .initialize_first_segment(msg.start_streaming_at)
                 .await?;
         }
 
         // truncate wal, update the LSNs
-        self.wal_store.truncate_wal(msg.start_streaming_at).await?;
+        self.truncate_wal(msg.start_streaming_at).await?;
 
         // and now adopt term history from proposer
         {
@@ -1243,6 +1547,10 @@ async fn handle_elected(
                 max(state.remote_consistent_lsn, state.timeline_start_lsn);
 
             state.acceptor_state.term_history = msg.term_history.clone();
+            if self.witness.is_some() {
+                // Truncation might have moved witness position back.
+                state.witness_flush_lsn = msg.start_streaming_at;
+            }
             self.state.finish_change(&state).await?;
         }
 
@@ -1328,8 +1636,8 @@ async fn handle_append_request(
         // when walproposer reconnects to safekeeper and writes some more data
         // while first connection still gets some packets later. It might be
         // better to not log this as error! above.
-        let write_lsn = self.wal_store.write_lsn();
-        let flush_lsn = self.wal_store.flush_lsn();
+        let write_lsn = self.write_lsn();
+        let ack_lsn = self.ack_lsn();
         if write_lsn > msg.h.begin_lsn {
             bail!(
                 "append request rewrites WAL written before, write_lsn={}, msg lsn={}",
@@ -1350,14 +1658,13 @@ async fn handle_append_request(
 
         // do the job
         if !msg.wal_data.is_empty() {
-            self.wal_store
-                .write_wal(msg.h.begin_lsn, &msg.wal_data)
-                .await?;
+            self.write_wal(msg.h.begin_lsn, &msg.wal_data).await?;
         }
 
-        // flush wal to the disk, if required
-        if require_flush {
-            self.wal_store.flush_wal().await?;
+        // flush wal to the disk, if required; in write commit mode flush
+        // happens only periodically.
+        if require_flush && self.commit_mode != CommitMode::Write {
+            self.flush_wal().await?;
         }
 
         // Update commit_lsn. It will be flushed to the control file regularly by the timeline
@@ -1384,10 +1691,10 @@ async fn handle_append_request(
             require_flush,
         );
 
-        // If flush_lsn hasn't updated, AppendResponse is not very useful.
-        // This is the common case for !require_flush, but a flush can still
-        // happen on segment bounds.
-        if !require_flush && flush_lsn == self.flush_lsn() {
+        // If acknowledged LSN hasn't updated, AppendResponse is not very
+        // useful. This is the common case for !require_flush, but a flush can
+        // still happen on segment bounds.
+        if !require_flush && ack_lsn == self.ack_lsn() {
             return Ok(None);
         }
 
@@ -1397,7 +1704,7 @@ async fn handle_append_request(
 
     /// Flush WAL to disk. Return AppendResponse with latest LSNs.
     async fn handle_flush(&mut self) -> Result<Option<AcceptorProposerMessage>> {
-        self.wal_store.flush_wal().await?;
+        self.flush_wal().await?;
         Ok(Some(AcceptorProposerMessage::AppendResponse(
             self.append_response(),
         )))
@@ -1478,6 +1785,10 @@ fn flush_lsn(&self) -> Lsn {
             self.lsn
         }
 
+        fn write_record_lsn(&self) -> Lsn {
+            self.lsn
+        }
+
         async fn initialize_first_segment(&mut self, _init_lsn: Lsn) -> Result<()> {
             Ok(())
         }
@@ -1547,6 +1858,148 @@ async fn test_voting() {
         }
     }
 
+    fn witness_mconf() -> Configuration {
+        let sk = |id: u64, role: SafekeeperRole| SafekeeperId {
+            id: NodeId(id),
+            host: format!("sk-{id}"),
+            pg_port: 5432,
+            role,
+        };
+        Configuration {
+            generation: SafekeeperGeneration::new(1),
+            members: MemberSet::new(vec![
+                sk(0, SafekeeperRole::Full),
+                sk(1, SafekeeperRole::Full),
+                sk(2, SafekeeperRole::Witness),
+            ])
+            .unwrap(),
+            new_members: None,
+        }
+    }
+
+    #[test]
+    fn test_mconf_roles_encoding() {
+        let mconf = witness_mconf();
+
+        let mut buf = BytesMut::new();
+        AcceptorProposerMessage::serialize_mconf(&mut buf, &mconf, SK_PROTO_VERSION_4).unwrap();
+        let decoded =
+            ProposerAcceptorMessage::get_mconf(&mut buf.freeze(), SK_PROTO_VERSION_4).unwrap();
+        assert_eq!(decoded, mconf);
+
+        // v3 has no roles and can't carry witnesses.
+        let mut buf = BytesMut::new();
+        assert!(
+            AcceptorProposerMessage::serialize_mconf(&mut buf, &mconf, SK_PROTO_VERSION_3).is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_witness_doesnt_write_wal() {
+        let mut state = test_sk_state();
+        state.server.pg_version = PgVersionId::from_full_pg_version(170000);
+        state.mconf = witness_mconf();
+        let storage = InMemoryState {
+            persisted_state: state,
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(TimelineState::new(storage), wal_store, NodeId(2)).unwrap();
+        assert!(sk.is_witness());
+        assert_eq!(
+            sk.negotiate_commit_mode(CommitMode::RemoteApply),
+            CommitMode::Flush
+        );
+
+        let pem = ProposerElected {
+            generation: SafekeeperGeneration::new(1),
+            term: 1,
+            start_streaming_at: Lsn(0x1000028),
+            term_history: TermHistory(vec![TermLsn {
+                term: 1,
+                lsn: Lsn(0x1000028),
+            }]),
+        };
+        sk.process_msg(&ProposerAcceptorMessage::Elected(pem))
+            .await
+            .unwrap();
+        sk.process_msg(&ProposerAcceptorMessage::FlushWAL)
+            .await
+            .unwrap();
+
+        assert_eq!(sk.wal_store.lsn, Lsn(0));
+        assert_eq!(sk.flush_lsn(), Lsn(0x1000028));
+        assert_eq!(sk.state.witness_flush_lsn, Lsn(0x1000028));
+
+        // witness can't become a full member
+        let mut mconf = witness_mconf();
+        mconf.generation = SafekeeperGeneration::new(2);
+        mconf.members.m[2].role = SafekeeperRole::Full;
+        assert!(sk.membership_switch(mconf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_remote_apply_commit_mode() {
+        let storage = InMemoryState {
+            persisted_state: test_sk_state(),
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0) };
+        let mut sk = SafeKeeper::new(TimelineState::new(storage), wal_store, NodeId(0)).unwrap();
+        sk.commit_mode = sk.negotiate_commit_mode(CommitMode::RemoteApply);
+        assert_eq!(sk.commit_mode, CommitMode::RemoteApply);
+        sk.shard_count = 2;
+
+        let pem = ProposerElected {
+            generation: Generation::new(0),
+            term: 1,
+            start_streaming_at: Lsn(1),
+            term_history: TermHistory(vec![TermLsn {
+                term: 1,
+                lsn: Lsn(1),
+            }]),
+        };
+        sk.process_msg(&ProposerAcceptorMessage::Elected(pem))
+            .await
+            .unwrap();
+        let append_request = AppendRequest {
+            h: AppendRequestHeader {
+                generation: Generation::new(0),
+                term: 1,
+                begin_lsn: Lsn(1),
+                end_lsn: Lsn(3),
+                commit_lsn: Lsn(0),
+                truncate_lsn: Lsn(0),
+            },
+            wal_data: Bytes::from_static(b"bb"),
+        };
+        sk.process_msg(&ProposerAcceptorMessage::AppendRequest(append_request))
+            .await
+            .unwrap();
+        assert_eq!(sk.apply_lsn(), Lsn::INVALID);
+
+        // nothing is reported until all shards sent feedback
+        let mut feedback = PageserverFeedback::empty();
+        feedback.last_received_lsn = Lsn(2);
+        assert!(sk.record_pageserver_feedback(&feedback).is_none());
+        assert_eq!(sk.apply_lsn(), Lsn::INVALID);
+
+        // lagging shard holds it back
+        feedback.shard_number = 1;
+        feedback.last_received_lsn = Lsn(5);
+        let Some(AcceptorProposerMessage::AppendResponse(resp)) =
+            sk.record_pageserver_feedback(&feedback)
+        else {
+            panic!("expected AppendResponse");
+        };
+        assert_eq!(resp.flush_lsn, Lsn(3));
+        assert_eq!(resp.apply_lsn, Lsn(2));
+
+        // pageserver progress is reported, but not beyond flush_lsn
+        feedback.shard_number = 0;
+        feedback.last_received_lsn = Lsn(5);
+        sk.record_pageserver_feedback(&feedback);
+        assert_eq!(sk.apply_lsn(), Lsn(3));
+    }
+
     #[tokio::test]
     async fn test_last_log_term_switch() {
         let storage = InMemoryState {
@@ -1758,6 +2211,7 @@ fn test_sk_state_bincode_serde_roundtrip() {
                     id: NodeId(1),
                     host: "hehe.org".to_owned(),
                     pg_port: 5432,
+                    role: SafekeeperRole::Full,
                 }])
                 .expect("duplicate member"),
                 new_members: None,
@@ -1788,6 +2242,7 @@ fn test_sk_state_bincode_serde_roundtrip() {
             partial_backup: crate::wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: UNIX_EPOCH,
+            witness_flush_lsn: Lsn(0),
         };
 
         let ser = state.ser().unwrap();
@@ -69,6 +69,10 @@ pub struct TimelinePersistentState {
     /// WAL files from remote storage to serve the timeline.
     pub eviction_state: EvictionState,
     pub creation_ts: SystemTime,
+    /// End of the last WAL record acknowledged as flushed by a witness member,
+    /// which doesn't store WAL and so can't find it on disk. Unused on full
+    /// members.
+    pub witness_flush_lsn: Lsn,
 }
 
 /// State of the local WAL files. Used to track current timeline state,
@@ -147,6 +151,7 @@ pub fn new(
             partial_backup: wal_backup_partial::State::default(),
             eviction_state: EvictionState::Present,
             creation_ts: SystemTime::now(),
+            witness_flush_lsn: Lsn(0),
         })
     }
 
@@ -275,6 +280,8 @@ pub async fn membership_switch(&mut self, to: Configuration) -> Result<Membershi
                 to, self.mconf
             );
         } else {
+            to.validate_witnesses()?;
+            self.mconf.validate_role_change(&to)?;
             let mut state = self.start_change();
             state.mconf = to.clone();
             self.finish_change(&state).await?;
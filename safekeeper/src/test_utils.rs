@@ -67,7 +67,8 @@ pub async fn make_safekeeper(
         pstate.tenant_id = ttid.tenant_id;
         pstate.timeline_id = ttid.timeline_id;
 
-        let wal = wal_storage::PhysicalStorage::new(&ttid, &timeline_dir, &pstate, conf.no_sync)?;
+        let wal =
+            wal_storage::PhysicalStorage::new(&ttid, &timeline_dir, None, &pstate, conf.no_sync)?;
         let ctrl =
             control_file::FileStorage::create_new(&timeline_dir, pstate, conf.no_sync).await?;
         let state = TimelineState::new(ctrl);
@@ -115,7 +116,7 @@ pub async fn make_timeline(
             &mut timeline.write_shared_state().await,
             &conf,
             Arc::new(TimelinesSet::default()), // ignored for now
-            RateLimiter::new(0, 0),
+            RateLimiter::new(0, 0, 0),
             wal_backup,
         );
         Ok(timeline)
@@ -24,6 +24,7 @@
 use tracing::*;
 use utils::id::{NodeId, TenantId, TenantTimelineId};
 use utils::lsn::Lsn;
+use utils::pageserver_feedback::PageserverFeedback;
 use utils::sync::gate::Gate;
 
 use crate::metrics::{
@@ -154,7 +155,7 @@ pub enum StateSK {
 impl StateSK {
     pub fn flush_lsn(&self) -> Lsn {
         match self {
-            StateSK::Loaded(sk) => sk.wal_store.flush_lsn(),
+            StateSK::Loaded(sk) => sk.storage_flush_lsn(),
             StateSK::Offloaded(state) => match state.eviction_state {
                 EvictionState::Offloaded(flush_lsn) => flush_lsn,
                 _ => panic!("StateSK::Offloaded mismatches with eviction_state from control_file"),
@@ -194,7 +195,10 @@ pub async fn membership_switch(
         &mut self,
         to: Configuration,
     ) -> Result<TimelineMembershipSwitchResponse> {
-        let result = self.state_mut().membership_switch(to).await?;
+        let result = match self {
+            StateSK::Loaded(sk) => sk.membership_switch(to).await?,
+            _ => self.state_mut().membership_switch(to).await?,
+        };
         let flush_lsn = self.flush_lsn();
         let last_log_term = self.state().acceptor_state.get_last_log_term(flush_lsn);
 
@@ -324,6 +328,7 @@ pub fn new(sk: StateSK) -> Self {
     /// Restore SharedState from control file. If file doesn't exist, bails out.
     pub fn restore(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Result<Self> {
         let timeline_dir = get_timeline_dir(conf, ttid);
+        let cold_timeline_dir = get_cold_timeline_dir(conf, ttid);
         let control_store = control_file::FileStorage::restore_new(&timeline_dir, conf.no_sync)?;
         if control_store.server.wal_seg_size == 0 {
             bail!(TimelineError::UninitializedWalSegSize(*ttid));
@@ -334,6 +339,7 @@ pub fn restore(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Result<Self> {
                 let wal_store = wal_storage::PhysicalStorage::new(
                     ttid,
                     &timeline_dir,
+                    cold_timeline_dir.as_deref(),
                     &control_store,
                     conf.no_sync,
                 )?;
@@ -400,6 +406,16 @@ pub(crate) fn get_peers(&self, heartbeat_timeout: Duration) -> Vec<PeerInfo> {
             .cloned()
             .collect()
     }
+
+    /// Like [`Self::get_peers`], but without witness members of the current
+    /// configuration: they have no WAL, so can't serve as donors or offloaders.
+    pub(crate) fn get_full_peers(&self, heartbeat_timeout: Duration) -> Vec<PeerInfo> {
+        let mconf = &self.sk.state().mconf;
+        self.get_peers(heartbeat_timeout)
+            .into_iter()
+            .filter(|p| !mconf.is_witness(p.sk_id))
+            .collect()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -468,6 +484,7 @@ pub struct Timeline {
     walsenders: Arc<WalSenders>,
     walreceivers: Arc<WalReceivers>,
     timeline_dir: Utf8PathBuf,
+    cold_timeline_dir: Option<Utf8PathBuf>,
     manager_ctl: ManagerCtl,
     conf: Arc<SafeKeeperConf>,
 
@@ -486,6 +503,8 @@ pub struct Timeline {
     pub(crate) broker_active: AtomicBool,
     pub(crate) wal_backup_active: AtomicBool,
     pub(crate) last_removed_segno: AtomicU64,
+    /// All segments <= this were moved to the cold WAL directory (or removed).
+    pub(crate) last_cold_segno: AtomicU64,
     pub(crate) mgr_status: AtomicStatus,
 }
 
@@ -513,6 +532,7 @@ pub fn new(
             ttid,
             remote_path: remote_path.to_owned(),
             timeline_dir: timeline_dir.to_owned(),
+            cold_timeline_dir: get_cold_timeline_dir(&conf, &ttid),
             commit_lsn_watch_tx,
             commit_lsn_watch_rx,
             term_flush_lsn_watch_tx,
@@ -530,6 +550,7 @@ pub fn new(
             broker_active: AtomicBool::new(false),
             wal_backup_active: AtomicBool::new(false),
             last_removed_segno: AtomicU64::new(0),
+            last_cold_segno: AtomicU64::new(0),
             mgr_status: AtomicStatus::new(),
             wal_backup,
         })
@@ -544,17 +565,27 @@ pub fn load_timeline(
         let _enter = info_span!("load_timeline", timeline = %ttid.timeline_id).entered();
 
         let shared_state = SharedState::restore(conf.as_ref(), &ttid)?;
+        let wal_seg_size = shared_state.get_wal_seg_size();
         let timeline_dir = get_timeline_dir(conf.as_ref(), &ttid);
         let remote_path = remote_timeline_path(&ttid)?;
 
-        Ok(Timeline::new(
+        let tli = Timeline::new(
             ttid,
             &timeline_dir,
             &remote_path,
             shared_state,
             conf,
             wal_backup,
-        ))
+        );
+        // Segments moved to the cold directory before restart don't take
+        // space in the hot one.
+        if let Some(cold_timeline_dir) = &tli.cold_timeline_dir {
+            let last_cold_segno =
+                wal_storage::find_last_cold_segno(cold_timeline_dir, wal_seg_size)?;
+            tli.last_cold_segno
+                .store(last_cold_segno, Ordering::Relaxed);
+        }
+        Ok(tli)
     }
 
     /// Bootstrap new or existing timeline starting background tasks.
@@ -638,6 +669,9 @@ pub async fn delete(
         }
 
         let dir_existed = delete_dir(&self.timeline_dir).await?;
+        if let Some(cold_timeline_dir) = &self.cold_timeline_dir {
+            delete_dir(cold_timeline_dir).await?;
+        }
         Ok(dir_existed)
     }
 
@@ -828,6 +862,13 @@ pub fn get_walreceivers(&self) -> &Arc<WalReceivers> {
         &self.walreceivers
     }
 
+    /// Whether this safekeeper is a witness member of the timeline, i.e.
+    /// doesn't store WAL.
+    pub async fn is_witness(&self) -> bool {
+        let shared_state = self.read_shared_state().await;
+        shared_state.sk.state().mconf.is_witness(self.conf.my_id)
+    }
+
     /// Returns flush_lsn.
     pub async fn get_flush_lsn(&self) -> Lsn {
         self.read_shared_state().await.sk.flush_lsn()
@@ -1077,15 +1118,20 @@ fn hadron_check_disk_usage(
         // The disk usage is calculated based on the number of segments between `last_removed_segno`
         // and the current flush LSN segment number. `last_removed_segno` is advanced after
         // unneeded WAL files are physically removed from disk (see `update_wal_removal_end()`
-        // in `timeline_manager.rs`).
+        // in `timeline_manager.rs`). If the cold WAL directory is configured, the limit applies
+        // to the hot directory only, so segments moved to the cold one (up to `last_cold_segno`)
+        // are not counted; the cold disk is guarded by the global disk usage check.
         let max_timeline_disk_usage_bytes = self.conf.max_timeline_disk_usage_bytes;
         if max_timeline_disk_usage_bytes > 0 {
-            let last_removed_segno = self.last_removed_segno.load(Ordering::Relaxed);
+            let last_removed_segno = max(
+                self.last_removed_segno.load(Ordering::Relaxed),
+                self.last_cold_segno.load(Ordering::Relaxed),
+            );
             let flush_lsn = shared_state_locked.sk.flush_lsn();
             let wal_seg_size = shared_state_locked.sk.state().server.wal_seg_size as u64;
             let current_segno = flush_lsn.segment_number(wal_seg_size as usize);
 
-            let segno_count = current_segno - last_removed_segno;
+            let segno_count = current_segno.saturating_sub(last_removed_segno);
             let disk_usage_bytes = segno_count * wal_seg_size;
 
             if disk_usage_bytes > max_timeline_disk_usage_bytes {
@@ -1135,12 +1181,30 @@ pub async fn process_msg(
         Ok(rmsg)
     }
 
+    /// Pass pageserver feedback to the safekeeper, possibly forming
+    /// AppendResponse for the proposer.
+    pub async fn record_pageserver_feedback(
+        &self,
+        feedback: &PageserverFeedback,
+    ) -> Option<AcceptorProposerMessage> {
+        let mut shared_state = self.write_shared_state().await;
+        let mut rmsg = shared_state
+            .sk
+            .safekeeper()
+            .record_pageserver_feedback(feedback);
+        if let Some(AcceptorProposerMessage::AppendResponse(ref mut resp)) = rmsg {
+            resp.hs_feedback = self.walsenders.get_hotstandby().hs_feedback;
+        }
+        rmsg
+    }
+
     pub async fn get_walreader(&self, start_lsn: Lsn) -> Result<WalReader> {
         let (_, persisted_state) = self.get_state().await;
 
         WalReader::new(
             &self.ttid,
             self.timeline_dir.clone(),
+            self.cold_timeline_dir.clone(),
             &persisted_state,
             start_lsn,
             self.wal_backup.clone(),
@@ -1151,6 +1215,11 @@ pub fn get_timeline_dir(&self) -> Utf8PathBuf {
         self.timeline_dir.clone()
     }
 
+    /// Directory with cold WAL segments, if the cold WAL directory is configured.
+    pub fn get_cold_timeline_dir(&self) -> Option<Utf8PathBuf> {
+        self.cold_timeline_dir.clone()
+    }
+
     /// Update in memory remote consistent lsn.
     pub async fn update_remote_consistent_lsn(&self, candidate: Lsn) {
         let mut shared_state = self.write_shared_state().await;
@@ -1179,6 +1248,10 @@ pub(crate) fn timeline_dir(&self) -> &Utf8PathBuf {
         &self.tli.timeline_dir
     }
 
+    pub(crate) fn cold_timeline_dir(&self) -> Option<&Utf8PathBuf> {
+        self.tli.cold_timeline_dir.as_ref()
+    }
+
     /// Manager requests this state on startup.
     pub(crate) async fn bootstrap_mgr(&self) -> (bool, Option<PartialRemoteSegment>) {
         let shared_state = self.read_shared_state().await;
@@ -1252,6 +1325,7 @@ pub(crate) async fn switch_to_present(&self) -> anyhow::Result<()> {
         let wal_store = wal_storage::PhysicalStorage::new(
             &self.ttid,
             &self.timeline_dir,
+            self.cold_timeline_dir.as_deref(),
             shared.sk.state(),
             self.conf.no_sync,
         )?;
@@ -1312,3 +1386,16 @@ pub fn get_tenant_dir(conf: &SafeKeeperConf, tenant_id: &TenantId) -> Utf8PathBu
 pub fn get_timeline_dir(conf: &SafeKeeperConf, ttid: &TenantTimelineId) -> Utf8PathBuf {
     get_tenant_dir(conf, &ttid.tenant_id).join(ttid.timeline_id.to_string())
 }
+
+/// Get a path to the timeline directory for cold WAL segments, if the cold WAL
+/// directory is configured. The directory is created lazily on the first move.
+pub fn get_cold_timeline_dir(
+    conf: &SafeKeeperConf,
+    ttid: &TenantTimelineId,
+) -> Option<Utf8PathBuf> {
+    conf.cold_wal_dir.as_ref().map(|cold_wal_dir| {
+        cold_wal_dir
+            .join(ttid.tenant_id.to_string())
+            .join(ttid.timeline_id.to_string())
+    })
+}
@@ -38,6 +38,7 @@ pub(crate) fn ready_for_eviction(
         self.backup_task.is_none()
             && self.recovery_task.is_none()
             && self.wal_removal_task.is_none()
+            && self.wal_tiering_task.is_none()
             && self.partial_backup_task.is_none()
             && next_event.is_none()
             && self.access_service.is_empty()
@@ -29,7 +29,7 @@
 };
 use crate::rate_limit::{RateLimiter, rand_duration};
 use crate::recovery::recovery_main;
-use crate::remove_wal::calc_horizon_lsn;
+use crate::remove_wal::{calc_cold_horizon_lsn, calc_horizon_lsn};
 use crate::send_wal::WalSenders;
 use crate::state::TimelineState;
 use crate::timeline::{ManagerTimeline, ReadGuardSharedState, StateSK, WalResidentTimeline};
@@ -76,7 +76,7 @@ fn new(read_guard: ReadGuardSharedState, heartbeat_timeout: Duration) -> Self {
             cfile_last_persist_at: state.pers.last_persist_at(),
             inmem_flush_pending: Self::has_unflushed_inmem_state(state),
             wal_removal_on_hold: read_guard.wal_removal_on_hold,
-            peers: read_guard.get_peers(heartbeat_timeout),
+            peers: read_guard.get_full_peers(heartbeat_timeout),
         }
     }
 
@@ -207,12 +207,14 @@ pub(crate) struct Manager {
     pub(crate) num_computes_rx: tokio::sync::watch::Receiver<usize>,
     pub(crate) tli_broker_active: TimelineSetGuard,
     pub(crate) last_removed_segno: XLogSegNo,
+    pub(crate) last_cold_segno: XLogSegNo,
     pub(crate) is_offloaded: bool,
 
     // background tasks
     pub(crate) backup_task: Option<WalBackupTaskHandle>,
     pub(crate) recovery_task: Option<JoinHandle<()>>,
     pub(crate) wal_removal_task: Option<JoinHandle<anyhow::Result<u64>>>,
+    pub(crate) wal_tiering_task: Option<JoinHandle<anyhow::Result<u64>>>,
 
     // partial backup
     pub(crate) partial_backup_task:
@@ -296,6 +298,9 @@ pub async fn main_task(
             mgr.set_status(Status::UpdateWalRemoval);
             mgr.update_wal_removal(&state_snapshot).await;
 
+            mgr.set_status(Status::UpdateWalTiering);
+            mgr.update_wal_tiering(&state_snapshot).await;
+
             mgr.set_status(Status::UpdatePartialBackup);
             mgr.update_partial_backup(&state_snapshot).await;
 
@@ -356,6 +361,11 @@ pub async fn main_task(
                 mgr.wal_removal_task = None;
                 mgr.update_wal_removal_end(res);
             }
+            res = await_task_finish(mgr.wal_tiering_task.as_mut()) => {
+                // WAL tiering task finished
+                mgr.wal_tiering_task = None;
+                mgr.update_wal_tiering_end(res);
+            }
             res = await_task_finish(mgr.partial_backup_task.as_mut().map(|(handle, _)| handle)) => {
                 // partial backup task finished
                 mgr.partial_backup_task = None;
@@ -403,6 +413,11 @@ pub async fn main_task(
         mgr.update_wal_removal_end(res);
     }
 
+    if let Some(wal_tiering_task) = &mut mgr.wal_tiering_task {
+        let res = wal_tiering_task.await;
+        mgr.update_wal_tiering_end(res);
+    }
+
     // If timeline is deleted while evicted decrement the gauge.
     if mgr.tli.is_cancelled() && mgr.is_offloaded {
         NUM_EVICTED_TIMELINES.dec();
@@ -429,10 +444,14 @@ async fn new(
             num_computes_rx: tli.get_walreceivers().get_num_rx(),
             tli_broker_active: broker_active_set.guard(tli.clone()),
             last_removed_segno: 0,
+            last_cold_segno: tli
+                .last_cold_segno
+                .load(std::sync::atomic::Ordering::Relaxed),
             is_offloaded,
             backup_task: None,
             recovery_task: None,
             wal_removal_task: None,
+            wal_tiering_task: None,
             partial_backup_task: None,
             partial_backup_uploaded,
             access_service: AccessService::new(manager_tx),
@@ -559,6 +578,10 @@ async fn update_wal_removal(&mut self, state: &StateSnapshot) {
             // WAL removal is already in progress or hold off
             return;
         }
+        if self.wal_tiering_task.is_some() {
+            // don't race with moving segments between directories
+            return;
+        }
 
         // If enabled, we use LSN of the most lagging walsender as a WAL removal horizon.
         // This allows to get better read speed for pageservers that are lagging behind,
@@ -626,6 +649,81 @@ fn update_wal_removal_end(&mut self, res: Result<anyhow::Result<u64>, JoinError>
             .store(new_last_removed_segno, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Spawns task moving WAL segments to the cold WAL directory if needed.
+    async fn update_wal_tiering(&mut self, state: &StateSnapshot) {
+        let Some(cold_timeline_dir) = self.tli.cold_timeline_dir().cloned() else {
+            // tiering is disabled
+            return;
+        };
+        if self.wal_tiering_task.is_some() || self.wal_removal_task.is_some() {
+            // tiering is already in progress, or removal is running and would
+            // race with us
+            return;
+        }
+
+        let cold_horizon_lsn = calc_cold_horizon_lsn(
+            state,
+            self.wal_backup.get_storage().is_some(),
+            self.walsenders.laggard_lsn(),
+        );
+        // Segment containing the horizon is needed, move only the previous ones.
+        let cold_horizon_segno = cold_horizon_lsn
+            .segment_number(self.wal_seg_size)
+            .saturating_sub(1);
+
+        if cold_horizon_segno <= self.last_cold_segno
+            || cold_horizon_segno <= self.last_removed_segno
+        {
+            return;
+        }
+
+        let Ok(timeline_gate_guard) = self.tli.gate.enter() else {
+            tracing::info!("Timeline shutdown, not spawning WAL tiering task");
+            return;
+        };
+
+        let timeline_dir = self.tli.timeline_dir().clone();
+        let wal_seg_size = self.wal_seg_size;
+        let no_sync = self.conf.no_sync;
+        self.wal_tiering_task = Some(tokio::spawn(
+            async move {
+                let _timeline_gate_guard = timeline_gate_guard;
+
+                crate::wal_storage::move_segments_to_cold(
+                    &timeline_dir,
+                    &cold_timeline_dir,
+                    wal_seg_size,
+                    cold_horizon_segno,
+                    no_sync,
+                )
+                .await?;
+                Ok(cold_horizon_segno)
+            }
+            .instrument(info_span!("WAL tiering", ttid=%self.tli.ttid)),
+        ));
+    }
+
+    /// Update the state after WAL tiering task finished.
+    fn update_wal_tiering_end(&mut self, res: Result<anyhow::Result<u64>, JoinError>) {
+        let new_last_cold_segno = match res {
+            Ok(Ok(segno)) => segno,
+            Err(e) => {
+                warn!("WAL tiering task failed: {:?}", e);
+                return;
+            }
+            Ok(Err(e)) => {
+                warn!("WAL tiering task failed: {:?}", e);
+                return;
+            }
+        };
+
+        self.last_cold_segno = new_last_cold_segno;
+        // update the state in Arc<Timeline>
+        self.tli
+            .last_cold_segno
+            .store(new_last_cold_segno, std::sync::atomic::Ordering::Relaxed);
+    }
+
     /// Spawns partial WAL backup task if needed.
     async fn update_partial_backup(&mut self, state: &StateSnapshot) {
         // check if WAL backup is enabled and should be started
@@ -810,6 +908,7 @@ pub enum Status {
     UpdateBackup,
     UpdateControlFile,
     UpdateWalRemoval,
+    UpdateWalTiering,
     UpdatePartialBackup,
     EvictTimeline,
     Wait,
@@ -11,7 +11,7 @@
 use camino::Utf8PathBuf;
 use camino_tempfile::Utf8TempDir;
 use safekeeper_api::membership::{Configuration, SafekeeperGeneration};
-use safekeeper_api::models::{SafekeeperUtilization, TimelineDeleteResult};
+use safekeeper_api::models::{PullTimelineProgress, SafekeeperUtilization, TimelineDeleteResult};
 use safekeeper_api::{ServerInfo, membership};
 use tokio::fs;
 use tracing::*;
@@ -23,7 +23,9 @@
 use crate::http::routes::DeleteOrExcludeError;
 use crate::rate_limit::RateLimiter;
 use crate::state::TimelinePersistentState;
-use crate::timeline::{Timeline, TimelineError, delete_dir, get_tenant_dir, get_timeline_dir};
+use crate::timeline::{
+    Timeline, TimelineError, delete_dir, get_cold_timeline_dir, get_tenant_dir, get_timeline_dir,
+};
 use crate::timelines_set::TimelinesSet;
 use crate::wal_backup::WalBackup;
 use crate::wal_storage::Storage;
@@ -53,6 +55,10 @@ struct GlobalTimelinesState {
     /// This is only soft-enforced, as this map is dropped on restart.
     tenant_tombstones: HashMap<TenantId, Instant>,
 
+    /// Progress of the latest pull_timeline of each timeline pulled since
+    /// start.
+    pull_progress: HashMap<TenantTimelineId, PullTimelineProgress>,
+
     conf: Arc<SafeKeeperConf>,
     broker_active_set: Arc<TimelinesSet>,
     global_rate_limiter: RateLimiter,
@@ -155,9 +161,10 @@ pub fn new(conf: Arc<SafeKeeperConf>, wal_backup: Arc<WalBackup>) -> Self {
                 timelines: HashMap::new(),
                 timeline_tombstones: HashMap::new(),
                 tenant_tombstones: HashMap::new(),
+                pull_progress: HashMap::new(),
                 conf,
                 broker_active_set: Arc::new(TimelinesSet::default()),
-                global_rate_limiter: RateLimiter::new(1, 1),
+                global_rate_limiter: RateLimiter::new(1, 1, 1),
                 wal_backup,
             }),
         }
@@ -172,6 +179,7 @@ pub async fn init(&self) -> Result<()> {
             state.global_rate_limiter = RateLimiter::new(
                 state.conf.partial_backup_concurrency,
                 DEFAULT_EVICTION_CONCURRENCY,
+                state.conf.pull_timeline_concurrency,
             );
 
             // Iterate through all directories and load tenants for all directories
@@ -284,6 +292,23 @@ pub fn get_wal_backup(&self) -> Arc<WalBackup> {
         self.state.lock().unwrap().wal_backup.clone()
     }
 
+    pub fn get_global_rate_limiter(&self) -> RateLimiter {
+        self.state.lock().unwrap().global_rate_limiter.clone()
+    }
+
+    pub(crate) fn report_pull_progress(&self, progress: &PullTimelineProgress) {
+        let ttid = TenantTimelineId::new(progress.tenant_id, progress.timeline_id);
+        self.state
+            .lock()
+            .unwrap()
+            .pull_progress
+            .insert(ttid, progress.clone());
+    }
+
+    pub fn get_pull_progress(&self, ttid: &TenantTimelineId) -> Option<PullTimelineProgress> {
+        self.state.lock().unwrap().pull_progress.get(ttid).cloned()
+    }
+
     /// Create a new timeline with the given id. If the timeline already exists, returns
     /// an existing timeline.
     pub(crate) async fn create(
@@ -602,8 +627,12 @@ pub(crate) async fn delete_or_exclude(
             }
             Err(_) => {
                 // Timeline is not memory, but it may still exist on disk in broken state.
-                let dir_path = get_timeline_dir(self.state.lock().unwrap().conf.as_ref(), ttid);
+                let conf = self.state.lock().unwrap().conf.clone();
+                let dir_path = get_timeline_dir(conf.as_ref(), ttid);
                 let dir_existed = delete_dir(&dir_path).await?;
+                if let Some(cold_dir_path) = get_cold_timeline_dir(conf.as_ref(), ttid) {
+                    delete_dir(&cold_dir_path).await?;
+                }
 
                 Ok(TimelineDeleteResult { dir_existed })
             }
@@ -660,8 +689,12 @@ pub async fn delete_all_for_tenant(
         // Note that we could concurrently create new timelines while we were deleting them,
         // so the directory may be not empty. In this case timelines will have bad state
         // and timeline background jobs can panic.
-        let tenant_dir = get_tenant_dir(self.state.lock().unwrap().conf.as_ref(), tenant_id);
+        let conf = self.state.lock().unwrap().conf.clone();
+        let tenant_dir = get_tenant_dir(conf.as_ref(), tenant_id);
         delete_dir(&tenant_dir).await?;
+        if let Some(cold_wal_dir) = &conf.cold_wal_dir {
+            delete_dir(&cold_wal_dir.join(tenant_id.to_string())).await?;
+        }
 
         Ok(deleted)
     }
@@ -719,6 +752,21 @@ pub async fn create_temp_timeline_dir(
     Ok((tli_dir, tli_dir_path))
 }
 
+/// Create (or reuse existing) directory for pulling a timeline. Unlike
+/// [`create_temp_timeline_dir`] it has a stable name and is not removed on
+/// failure, so that the next pull can continue from the data already received.
+pub async fn create_pull_timeline_dir(
+    conf: &SafeKeeperConf,
+    ttid: TenantTimelineId,
+) -> Result<Utf8PathBuf> {
+    let tli_dir = conf
+        .workdir
+        .join("tmp")
+        .join(format!("{}_{}_pull", ttid.tenant_id, ttid.timeline_id));
+    tokio::fs::create_dir_all(&tli_dir).await?;
+    Ok(tli_dir)
+}
+
 /// Do basic validation of a temp timeline, before moving it to the global map.
 pub async fn validate_temp_timeline(
     conf: &SafeKeeperConf,
@@ -742,7 +790,8 @@ pub async fn validate_temp_timeline(
         }
     }
 
-    let wal_store = wal_storage::PhysicalStorage::new(&ttid, path, &control_store, conf.no_sync)?;
+    let wal_store =
+        wal_storage::PhysicalStorage::new(&ttid, path, None, &control_store, conf.no_sync)?;
 
     let commit_lsn = control_store.commit_lsn;
     let flush_lsn = wal_store.flush_lsn();
@@ -0,0 +1,312 @@
+//! Reading arbitrary WAL ranges of a timeline over HTTP, for debugging ingest
+//! problems without fetching segments off the disk by hand.
+//!
+//! WAL is read with [`StreamingWalReader`], so segments already removed
+//! locally are fetched from remote storage. It is either streamed as is, or
+//! decoded into records and sent as newline delimited JSON, one object per
+//! record.
+
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::Bytes;
+use futures::StreamExt;
+use pageserver_api::shard::ShardIdentity;
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::walrecord::{DecodedWALRecord, decode_wal_record};
+use postgres_ffi::{MAX_SEND_SIZE, PgMajorVersion};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::*;
+use utils::lsn::Lsn;
+use wal_decoder::models::{InterpretedWalRecord, MetadataRecord};
+
+use crate::send_wal::EndWatch;
+use crate::timeline::WalResidentTimeline;
+use crate::wal_reader_stream::StreamingWalReader;
+
+/// How WAL is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalRangeFormat {
+    /// WAL bytes as they are in segments.
+    #[default]
+    Raw,
+    /// Decoded records as newline delimited JSON. Range must start at the
+    /// beginning of a record.
+    Json,
+}
+
+impl FromStr for WalRangeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "raw" => Ok(WalRangeFormat::Raw),
+            "json" => Ok(WalRangeFormat::Json),
+            _ => bail!("unknown WAL format {s}, expected raw or json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WalRangeRequest {
+    pub start_lsn: Lsn,
+    /// Defaults to flush_lsn.
+    pub end_lsn: Option<Lsn>,
+    pub format: WalRangeFormat,
+}
+
+/// Range checked against the WAL safekeeper has, ready to be streamed.
+pub struct WalRange {
+    start_lsn: Lsn,
+    end_lsn: Lsn,
+    format: WalRangeFormat,
+    pg_version: PgMajorVersion,
+}
+
+/// Check that requested WAL exists on the timeline: it must be within
+/// [timeline_start_lsn, flush_lsn]. Done before streaming to report bad
+/// requests with a proper status code.
+pub async fn validate(tli: &WalResidentTimeline, request: WalRangeRequest) -> Result<WalRange> {
+    if tli.is_witness().await {
+        bail!("witness safekeeper doesn't store WAL");
+    }
+    let (_, persisted_state) = tli.get_state().await;
+    let flush_lsn = tli.get_flush_lsn().await;
+    let end_lsn = request.end_lsn.unwrap_or(flush_lsn);
+
+    if request.start_lsn > end_lsn {
+        bail!(
+            "start_lsn {} is greater than end_lsn {}",
+            request.start_lsn,
+            end_lsn
+        );
+    }
+    if request.start_lsn < persisted_state.timeline_start_lsn {
+        bail!(
+            "start_lsn {} is before the start of the timeline {}",
+            request.start_lsn,
+            persisted_state.timeline_start_lsn
+        );
+    }
+    if end_lsn > flush_lsn {
+        bail!("end_lsn {} is after flush_lsn {}", end_lsn, flush_lsn);
+    }
+    let pg_version = PgMajorVersion::try_from(persisted_state.server.pg_version)?;
+
+    Ok(WalRange {
+        start_lsn: request.start_lsn,
+        end_lsn,
+        format: request.format,
+        pg_version,
+    })
+}
+
+/// Decoded WAL record as it is shown in JSON output.
+#[derive(Serialize)]
+struct WalRecordInfo<'a> {
+    /// End of the record, i.e. start of the next one.
+    end_lsn: Lsn,
+    len: usize,
+    xid: u32,
+    rmid: u8,
+    info: u8,
+    blocks: Vec<BlockInfo>,
+    /// Interpretation of the record by the pageserver, if it changes
+    /// metadata.
+    metadata: Option<&'a MetadataRecord>,
+}
+
+#[derive(Serialize)]
+struct BlockInfo {
+    spcnode: u32,
+    dbnode: u32,
+    relnode: u32,
+    forknum: u8,
+    blkno: u32,
+    has_image: bool,
+    will_init: bool,
+}
+
+/// Stream WAL range to `tx`. Errors are sent to `tx` as well, which aborts
+/// the response.
+pub async fn stream_wal_range(
+    tli: WalResidentTimeline,
+    range: WalRange,
+    tx: mpsc::Sender<Result<Bytes>>,
+) {
+    if let Err(e) = stream_wal_range_guts(tli, &range, &tx).await {
+        warn!(
+            "failed to stream WAL range {}-{}: {:#}",
+            range.start_lsn, range.end_lsn, e
+        );
+        // receiver might be gone already, nothing to do then
+        let _ = tx.send(Err(e)).await;
+    }
+}
+
+async fn stream_wal_range_guts(
+    tli: WalResidentTimeline,
+    range: &WalRange,
+    tx: &mpsc::Sender<Result<Bytes>>,
+) -> Result<()> {
+    if range.start_lsn == range.end_lsn {
+        return Ok(());
+    }
+
+    // Range is within flush_lsn, so the reader never waits on the watch.
+    let end_watch = EndWatch::Flush(tli.get_term_flush_lsn_watch_rx());
+    let mut reader = StreamingWalReader::new(
+        tli,
+        None,
+        range.start_lsn,
+        range.end_lsn,
+        end_watch,
+        MAX_SEND_SIZE,
+    );
+    let mut decoder = WalStreamDecoder::new(range.start_lsn, range.pg_version);
+    let shards = [ShardIdentity::unsharded()];
+
+    loop {
+        let wal = reader
+            .next()
+            .await
+            .and_then(|wor| wor.get_wal())
+            .ok_or_else(|| anyhow!("WAL stream ended unexpectedly"))??;
+        let done = wal.wal_end_lsn >= range.end_lsn;
+
+        let chunk = match range.format {
+            WalRangeFormat::Raw => wal.wal,
+            WalRangeFormat::Json => {
+                decoder.feed_bytes(&wal.wal);
+                let mut out = Vec::new();
+                while let Some((end_lsn, recdata)) = decoder.poll_decode()? {
+                    write_record_json(&mut out, end_lsn, recdata, &shards, range.pg_version)
+                        .with_context(|| format!("failed to decode record ending at {end_lsn}"))?;
+                }
+                Bytes::from(out)
+            }
+        };
+        if !chunk.is_empty() && tx.send(Ok(chunk)).await.is_err() {
+            // client went away
+            return Ok(());
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+fn write_record_json(
+    out: &mut Vec<u8>,
+    end_lsn: Lsn,
+    recdata: Bytes,
+    shards: &[ShardIdentity],
+    pg_version: PgMajorVersion,
+) -> Result<()> {
+    let len = recdata.len();
+    let mut decoded = DecodedWALRecord::default();
+    decode_wal_record(recdata.clone(), &mut decoded, pg_version)?;
+    let mut interpreted =
+        InterpretedWalRecord::from_bytes_filtered(recdata, shards, end_lsn, pg_version)?;
+    let interpreted = interpreted
+        .remove(&shards[0])
+        .expect("record is produced for each shard");
+
+    let info = WalRecordInfo {
+        end_lsn,
+        len,
+        xid: decoded.xl_xid,
+        rmid: decoded.xl_rmid,
+        info: decoded.xl_info,
+        blocks: decoded
+            .blocks
+            .iter()
+            .map(|blk| BlockInfo {
+                spcnode: blk.rnode_spcnode,
+                dbnode: blk.rnode_dbnode,
+                relnode: blk.rnode_relnode,
+                forknum: blk.forknum,
+                blkno: blk.blkno,
+                has_image: blk.has_image,
+                will_init: blk.will_init,
+            })
+            .collect(),
+        metadata: interpreted.metadata_record.as_ref(),
+    };
+    serde_json::to_writer(&mut *out, &info)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::ReceiverStream;
+    use utils::id::{NodeId, TenantTimelineId};
+    use utils::lsn::Lsn;
+
+    use super::*;
+    use crate::test_utils::Env;
+
+    #[tokio::test]
+    async fn test_stream_wal_range() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let start_lsn = Lsn::from_str("0/149FD18").unwrap();
+        let env = Env::new(true).unwrap();
+        let tli = env
+            .make_timeline(NodeId(1), TenantTimelineId::generate(), start_lsn)
+            .await
+            .unwrap();
+        let resident_tli = tli.wal_residence_guard().await.unwrap();
+        Env::write_wal(tli.clone(), start_lsn, 8 * 1024, 50, c"neon-file:", None)
+            .await
+            .unwrap();
+        let end_lsn = resident_tli.get_flush_lsn().await;
+
+        // range beyond flush_lsn is refused
+        let request = WalRangeRequest {
+            start_lsn,
+            end_lsn: Some(end_lsn + 1),
+            format: WalRangeFormat::Raw,
+        };
+        assert!(validate(&resident_tli, request).await.is_err());
+
+        let request = WalRangeRequest {
+            start_lsn,
+            end_lsn: None,
+            format: WalRangeFormat::Raw,
+        };
+        let range = validate(&resident_tli, request).await.unwrap();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(stream_wal_range(resident_tli, range, tx));
+        let raw: Vec<u8> = ReceiverStream::new(rx)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+        assert_eq!(raw.len() as u64, end_lsn.0 - start_lsn.0);
+
+        let request = WalRangeRequest {
+            start_lsn,
+            end_lsn: None,
+            format: WalRangeFormat::Json,
+        };
+        let resident_tli = tli.wal_residence_guard().await.unwrap();
+        let range = validate(&resident_tli, request).await.unwrap();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(stream_wal_range(resident_tli, range, tx));
+        let json: Vec<u8> = ReceiverStream::new(rx)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+        let records = String::from_utf8(json).unwrap();
+        assert_eq!(records.lines().count(), 50);
+        let last: serde_json::Value =
+            serde_json::from_str(records.lines().last().unwrap()).unwrap();
+        assert_eq!(last["end_lsn"], serde_json::json!(end_lsn.to_string()));
+    }
+}
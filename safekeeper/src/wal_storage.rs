@@ -6,6 +6,10 @@
 //! - 000000010000000000000002.partial
 //!
 //! Note that last file has `.partial` suffix, that's different from postgres.
+//!
+//! Optionally, a second (cold) directory can be configured. Completed segments
+//! which are already committed and not needed by walsenders are moved there by
+//! the timeline manager; readers look into both directories transparently.
 
 use std::cmp::{max, min};
 use std::future::Future;
@@ -31,8 +35,8 @@
 use utils::lsn::Lsn;
 
 use crate::metrics::{
-    REMOVED_WAL_SEGMENTS, WAL_DISK_IO_ERRORS, WAL_STORAGE_OPERATION_SECONDS, WalStorageMetrics,
-    time_io_closure,
+    MOVED_TO_COLD_WAL_SEGMENTS, REMOVED_WAL_SEGMENTS, WAL_DISK_IO_ERRORS,
+    WAL_STORAGE_OPERATION_SECONDS, WalStorageMetrics, time_io_closure,
 };
 use crate::state::TimelinePersistentState;
 use crate::wal_backup::{WalBackup, read_object, remote_timeline_path};
@@ -42,6 +46,8 @@ pub trait Storage {
     fn write_lsn(&self) -> Lsn;
     /// LSN of last durably stored WAL record.
     fn flush_lsn(&self) -> Lsn;
+    /// End of the last fully written WAL record, not necessarily synced.
+    fn write_record_lsn(&self) -> Lsn;
 
     /// Initialize segment by creating proper long header at the beginning of
     /// the segment and short header at the page of given LSN. This is only used
@@ -88,6 +94,9 @@ fn close(&mut self) {}
 pub struct PhysicalStorage {
     metrics: WalStorageMetrics,
     timeline_dir: Utf8PathBuf,
+    /// Directory with cold (moved out of `timeline_dir`) segments, if tiering
+    /// is enabled.
+    cold_timeline_dir: Option<Utf8PathBuf>,
 
     /// Disables fsync if true.
     no_sync: bool,
@@ -168,6 +177,7 @@ impl PhysicalStorage {
     pub fn new(
         ttid: &TenantTimelineId,
         timeline_dir: &Utf8Path,
+        cold_timeline_dir: Option<&Utf8Path>,
         state: &TimelinePersistentState,
         no_sync: bool,
     ) -> Result<PhysicalStorage> {
@@ -222,6 +232,7 @@ pub fn new(
         Ok(PhysicalStorage {
             metrics: WalStorageMetrics::default(),
             timeline_dir: timeline_dir.to_path_buf(),
+            cold_timeline_dir: cold_timeline_dir.map(Utf8Path::to_path_buf),
             no_sync,
             wal_seg_size,
             pg_version: state.server.pg_version,
@@ -415,6 +426,10 @@ fn flush_lsn(&self) -> Lsn {
         self.flush_record_lsn
     }
 
+    fn write_record_lsn(&self) -> Lsn {
+        self.write_record_lsn
+    }
+
     async fn initialize_first_segment(&mut self, init_lsn: Lsn) -> Result<()> {
         let _timer = WAL_STORAGE_OPERATION_SECONDS
             .with_label_values(&["initialize_first_segment"])
@@ -599,9 +614,20 @@ async fn truncate_wal(&mut self, end_pos: Lsn) -> Result<()> {
 
     fn remove_up_to(&self, segno_up_to: XLogSegNo) -> BoxFuture<'static, anyhow::Result<()>> {
         let timeline_dir = self.timeline_dir.clone();
+        let cold_timeline_dir = self.cold_timeline_dir.clone();
         let wal_seg_size = self.wal_seg_size;
         Box::pin(async move {
-            remove_segments_from_disk(&timeline_dir, wal_seg_size, |x| x <= segno_up_to).await
+            remove_segments_from_disk(&timeline_dir, wal_seg_size, |x| x <= segno_up_to).await?;
+            if let Some(cold_timeline_dir) = cold_timeline_dir {
+                // Cold directory is created lazily by the first move.
+                if fs::try_exists(&cold_timeline_dir).await? {
+                    remove_segments_from_disk(&cold_timeline_dir, wal_seg_size, |x| {
+                        x <= segno_up_to
+                    })
+                    .await?;
+                }
+            }
+            Ok(())
         })
     }
 
@@ -656,9 +682,97 @@ async fn remove_segments_from_disk(
     Ok(())
 }
 
+/// Move all completed WAL segments <= `segno_up_to` from `timeline_dir` to
+/// `cold_timeline_dir`. Returns number of moved segments.
+///
+/// The cold directory is usually on a different filesystem, so the segment is
+/// first copied under a temporary name, durably renamed and only then removed
+/// from the hot directory. This way every segment is always present in at least
+/// one of the directories, and readers which look into the hot directory first
+/// and then into the cold one never miss it. `.partial` segments are never
+/// moved.
+pub(crate) async fn move_segments_to_cold(
+    timeline_dir: &Utf8Path,
+    cold_timeline_dir: &Utf8Path,
+    wal_seg_size: usize,
+    segno_up_to: XLogSegNo,
+    no_sync: bool,
+) -> Result<usize> {
+    let _timer = WAL_STORAGE_OPERATION_SECONDS
+        .with_label_values(&["move_segments_to_cold"])
+        .start_timer();
+
+    fs::create_dir_all(cold_timeline_dir).await?;
+
+    let mut n_moved = 0;
+    let mut min_moved = u64::MAX;
+    let mut max_moved = u64::MIN;
+
+    let mut entries = fs::read_dir(timeline_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let entry_path = entry.path();
+        let fname = entry_path.file_name().unwrap();
+        if !IsXLogFileName(fname) {
+            continue;
+        }
+        let (segno, _) = XLogFromFileName(fname, wal_seg_size)?;
+        if segno > segno_up_to {
+            continue;
+        }
+
+        let fname = fname.to_str().expect("WAL segment names are ASCII");
+        let cold_path = cold_timeline_dir.join(fname);
+        let tmp_path = cold_timeline_dir.join(format!("{fname}.tmp"));
+        fs::copy(&entry_path, &tmp_path)
+            .await
+            .with_context(|| format!("failed to copy {fname} to {tmp_path}"))?;
+        durable_rename(&tmp_path, &cold_path, !no_sync).await?;
+        remove_file(&entry_path).await?;
+
+        n_moved += 1;
+        min_moved = min(min_moved, segno);
+        max_moved = max(max_moved, segno);
+        MOVED_TO_COLD_WAL_SEGMENTS.inc();
+    }
+
+    if n_moved > 0 {
+        info!(
+            "moved {} WAL segments [{}; {}] to {}",
+            n_moved, min_moved, max_moved, cold_timeline_dir
+        );
+    }
+    Ok(n_moved)
+}
+
+/// Find the highest complete segment in `cold_timeline_dir`, i.e. up to where
+/// segments were moved there before restart. Returns 0 if there are none.
+///
+/// If a move was interrupted, some lower segments might still be in the hot
+/// directory; the next move picks them up.
+pub(crate) fn find_last_cold_segno(
+    cold_timeline_dir: &Utf8Path,
+    wal_seg_size: usize,
+) -> Result<XLogSegNo> {
+    let entries = match std::fs::read_dir(cold_timeline_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut last_cold_segno = 0;
+    for entry in entries {
+        let fname = entry?.file_name();
+        if IsXLogFileName(&fname) {
+            let (segno, _) = XLogFromFileName(&fname, wal_seg_size)?;
+            last_cold_segno = max(last_cold_segno, segno);
+        }
+    }
+    Ok(last_cold_segno)
+}
+
 pub struct WalReader {
     remote_path: RemotePath,
     timeline_dir: Utf8PathBuf,
+    cold_timeline_dir: Option<Utf8PathBuf>,
     wal_seg_size: usize,
     pos: Lsn,
     wal_segment: Option<Pin<Box<dyn AsyncRead + Send + Sync>>>,
@@ -681,6 +795,7 @@ impl WalReader {
     pub fn new(
         ttid: &TenantTimelineId,
         timeline_dir: Utf8PathBuf,
+        cold_timeline_dir: Option<Utf8PathBuf>,
         state: &TimelinePersistentState,
         start_pos: Lsn,
         wal_backup: Arc<WalBackup>,
@@ -709,6 +824,7 @@ pub fn new(
         Ok(Self {
             remote_path: remote_timeline_path(ttid)?,
             timeline_dir,
+            cold_timeline_dir,
             wal_seg_size: state.server.wal_seg_size as usize,
             pos: start_pos,
             wal_segment: None,
@@ -808,7 +924,13 @@ async fn open_segment(&self) -> Result<Pin<Box<dyn AsyncRead + Send + Sync>>> {
 
         // Try to open local file, if we may have WAL locally
         if self.pos >= self.local_start_lsn {
-            let res = open_wal_file(&self.timeline_dir, segno, self.wal_seg_size).await?;
+            let res = open_wal_file_tiered(
+                &self.timeline_dir,
+                self.cold_timeline_dir.as_deref(),
+                segno,
+                self.wal_seg_size,
+            )
+            .await?;
             if let Some((mut file, _)) = res {
                 file.seek(SeekFrom::Start(xlogoff as u64)).await?;
                 return Ok(Box::pin(file));
@@ -860,6 +982,24 @@ pub(crate) async fn open_wal_file(
     Ok(Some((pf, false)))
 }
 
+/// Like [`open_wal_file`], but if the segment is not found in `timeline_dir`
+/// looks it up in `cold_timeline_dir` as well. Hot directory must be checked
+/// first, see [`move_segments_to_cold`].
+pub(crate) async fn open_wal_file_tiered(
+    timeline_dir: &Utf8Path,
+    cold_timeline_dir: Option<&Utf8Path>,
+    segno: XLogSegNo,
+    wal_seg_size: usize,
+) -> Result<Option<(tokio::fs::File, bool)>> {
+    if let Some(res) = open_wal_file(timeline_dir, segno, wal_seg_size).await? {
+        return Ok(Some(res));
+    }
+    match cold_timeline_dir {
+        Some(cold_timeline_dir) => open_wal_file(cold_timeline_dir, segno, wal_seg_size).await,
+        None => Ok(None),
+    }
+}
+
 /// Helper returning full path to WAL segment file and its .partial brother.
 pub fn wal_file_paths(
     timeline_dir: &Utf8Path,
@@ -871,3 +1011,81 @@ pub fn wal_file_paths(
     let wal_file_partial_path = timeline_dir.join(wal_file_name + ".partial");
     (wal_file_path, wal_file_partial_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use postgres_ffi::WAL_SEGMENT_SIZE;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_move_segments_to_cold() {
+        let tempdir = camino_tempfile::tempdir().unwrap();
+        let hot_dir = tempdir.path().join("hot");
+        let cold_dir = tempdir.path().join("cold");
+        fs::create_dir_all(&hot_dir).await.unwrap();
+
+        for segno in 1..=3 {
+            let (path, _) = wal_file_paths(&hot_dir, segno, WAL_SEGMENT_SIZE);
+            fs::write(&path, format!("segment {segno}")).await.unwrap();
+        }
+        let (_, partial_path) = wal_file_paths(&hot_dir, 4, WAL_SEGMENT_SIZE);
+        fs::write(&partial_path, "partial").await.unwrap();
+
+        // Partial segment is never moved, even if it is below the horizon.
+        let moved = move_segments_to_cold(&hot_dir, &cold_dir, WAL_SEGMENT_SIZE, 4, true)
+            .await
+            .unwrap();
+        assert_eq!(moved, 3);
+
+        for segno in 1..=3 {
+            assert!(
+                open_wal_file(&hot_dir, segno, WAL_SEGMENT_SIZE)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            let (mut file, is_partial) =
+                open_wal_file_tiered(&hot_dir, Some(&cold_dir), segno, WAL_SEGMENT_SIZE)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            assert!(!is_partial);
+            let mut content = String::new();
+            file.read_to_string(&mut content).await.unwrap();
+            assert_eq!(content, format!("segment {segno}"));
+        }
+        let (_, is_partial) = open_wal_file_tiered(&hot_dir, Some(&cold_dir), 4, WAL_SEGMENT_SIZE)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(is_partial);
+        assert!(
+            open_wal_file(&cold_dir, 4, WAL_SEGMENT_SIZE)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Position of moved segments is recovered after restart.
+        assert_eq!(
+            find_last_cold_segno(&cold_dir, WAL_SEGMENT_SIZE).unwrap(),
+            3
+        );
+        assert_eq!(
+            find_last_cold_segno(&tempdir.path().join("none"), WAL_SEGMENT_SIZE).unwrap(),
+            0
+        );
+
+        // Removal works on the cold directory as well.
+        remove_segments_from_disk(&cold_dir, WAL_SEGMENT_SIZE, |segno| segno <= 2)
+            .await
+            .unwrap();
+        assert!(
+            open_wal_file_tiered(&hot_dir, Some(&cold_dir), 2, WAL_SEGMENT_SIZE)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
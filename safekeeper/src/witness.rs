@@ -0,0 +1,117 @@
+//! Witness safekeepers vote and ack WAL like normal members but don't store
+//! WAL. This module has WAL position tracking for them.
+//!
+//! Witness still must report record aligned positions to the proposer, so
+//! incoming WAL is fed through the decoder and thrown away after that. The
+//! flush position is made durable by persisting it in the control file
+//! (`witness_flush_lsn`) instead of fsyncing segments, which is the caller's
+//! job.
+//!
+//! Since witness has no WAL, it can never be a donor: walproposer doesn't
+//! pick it for recovery and it is excluded from peer recovery and offloading.
+
+use anyhow::{Result, bail};
+use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_versioninfo::PgMajorVersion;
+use tracing::*;
+use utils::lsn::Lsn;
+
+/// WAL position tracker of a witness.
+///
+/// `write_lsn` >= `write_record_lsn` >= `flush_record_lsn`, same as in
+/// [`crate::wal_storage::PhysicalStorage`].
+pub struct WitnessWal {
+    /// End of the received WAL.
+    write_lsn: Lsn,
+    /// End of the last fully received record.
+    write_record_lsn: Lsn,
+    /// End of the last record which was acknowledged as flushed.
+    flush_record_lsn: Lsn,
+    /// Decoder is required for finding the record boundaries.
+    decoder: WalStreamDecoder,
+}
+
+impl WitnessWal {
+    /// Create tracker positioned at `flush_lsn`, which must be the end of a
+    /// record.
+    pub fn new(flush_lsn: Lsn, pg_version: PgMajorVersion) -> Self {
+        WitnessWal {
+            write_lsn: flush_lsn,
+            write_record_lsn: flush_lsn,
+            flush_record_lsn: flush_lsn,
+            decoder: WalStreamDecoder::new(flush_lsn, pg_version),
+        }
+    }
+
+    pub fn write_lsn(&self) -> Lsn {
+        self.write_lsn
+    }
+
+    pub fn write_record_lsn(&self) -> Lsn {
+        self.write_record_lsn
+    }
+
+    pub fn flush_lsn(&self) -> Lsn {
+        self.flush_record_lsn
+    }
+
+    /// Account piece of WAL starting at `startpos`. Like with normal storage,
+    /// writes must be sequential.
+    pub fn write_wal(&mut self, startpos: Lsn, buf: &[u8]) -> Result<()> {
+        if self.write_lsn > startpos {
+            bail!(
+                "witness write_wal rewrites WAL written before, write_lsn={}, startpos={}",
+                self.write_lsn,
+                startpos
+            );
+        }
+        if self.write_lsn < startpos && self.write_lsn != Lsn(0) {
+            bail!(
+                "witness write_wal creates gap in written WAL, write_lsn={}, startpos={}",
+                self.write_lsn,
+                startpos
+            );
+        }
+
+        if self.decoder.available() != startpos {
+            info!(
+                "restart witness decoder from {} to {}",
+                self.decoder.available(),
+                startpos,
+            );
+            let pg_version = self.decoder.pg_version;
+            self.decoder = WalStreamDecoder::new(startpos, pg_version);
+        }
+        self.decoder.feed_bytes(buf);
+        while let Some((lsn, _rec)) = self.decoder.poll_decode()? {
+            self.write_record_lsn = lsn;
+        }
+        self.write_lsn = startpos + buf.len() as u64;
+        Ok(())
+    }
+
+    /// Mark everything received up to the last complete record as flushed.
+    /// Returns new flush position; the caller must persist it before
+    /// acknowledging.
+    pub fn flush_wal(&mut self) -> Lsn {
+        self.flush_record_lsn = self.write_record_lsn;
+        self.flush_record_lsn
+    }
+
+    /// Reset position to `end_pos`, which must be the end of a record.
+    pub fn truncate_wal(&mut self, end_pos: Lsn) -> Result<()> {
+        if self.write_record_lsn != Lsn(0) && end_pos > self.write_record_lsn {
+            bail!(
+                "witness truncate_wal called on non-written WAL, write_record_lsn={}, end_pos={}",
+                self.write_record_lsn,
+                end_pos
+            );
+        }
+        self.write_lsn = end_pos;
+        self.write_record_lsn = end_pos;
+        self.flush_record_lsn = end_pos;
+        let pg_version = self.decoder.pg_version;
+        self.decoder = WalStreamDecoder::new(end_pos, pg_version);
+        Ok(())
+    }
+}
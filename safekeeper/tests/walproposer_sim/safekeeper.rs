@@ -16,13 +16,14 @@
 use http::Uri;
 use safekeeper::SafeKeeperConf;
 use safekeeper::safekeeper::{
-    ProposerAcceptorMessage, SK_PROTO_VERSION_3, SafeKeeper, UNKNOWN_SERVER_VERSION,
+    ProposerAcceptorMessage, SK_PROTO_VERSION_3, SK_PROTO_VERSION_4, SafeKeeper,
+    UNKNOWN_SERVER_VERSION,
 };
 use safekeeper::state::{TimelinePersistentState, TimelineState};
 use safekeeper::timeline::TimelineError;
 use safekeeper::wal_storage::Storage;
 use safekeeper_api::ServerInfo;
-use safekeeper_api::membership::Configuration;
+use safekeeper_api::membership::{Configuration, INVALID_GENERATION};
 use tracing::{debug, info_span, warn};
 use utils::id::{NodeId, TenantId, TenantTimelineId, TimelineId};
 use utils::lsn::Lsn;
@@ -38,11 +39,13 @@ struct GlobalMap {
     timelines: HashMap<TenantTimelineId, SharedState>,
     conf: SafeKeeperConf,
     disk: Arc<SafekeeperDisk>,
+    /// Membership configuration of new timelines.
+    mconf: Configuration,
 }
 
 impl GlobalMap {
     /// Restores global state from disk.
-    fn new(disk: Arc<SafekeeperDisk>, conf: SafeKeeperConf) -> Result<Self> {
+    fn new(disk: Arc<SafekeeperDisk>, conf: SafeKeeperConf, mconf: Configuration) -> Result<Self> {
         let mut timelines = HashMap::new();
 
         for (&ttid, disk) in disk.timelines.lock().iter() {
@@ -82,6 +85,7 @@ fn new(disk: Arc<SafekeeperDisk>, conf: SafeKeeperConf) -> Result<Self> {
             timelines,
             conf,
             disk,
+            mconf,
         })
     }
 
@@ -97,7 +101,7 @@ fn create(&mut self, ttid: TenantTimelineId, server_info: ServerInfo) -> Result<
 
         let state = TimelinePersistentState::new(
             &ttid,
-            Configuration::empty(),
+            self.mconf.clone(),
             server_info,
             commit_lsn,
             local_start_lsn,
@@ -139,17 +143,23 @@ struct ConnState {
     greeting: bool,
     ttid: TenantTimelineId,
     flush_pending: bool,
+    proto_version: u32,
 
     runtime: tokio::runtime::Runtime,
 }
 
-pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
+/// Run a safekeeper, which creates timelines with the given membership
+/// configuration. Roles of the members are sent to walproposer only in proto
+/// v4, so it is used when generations are enabled.
+pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>, mconf: Configuration) -> Result<()> {
     let _enter = info_span!("safekeeper", id = os.id()).entered();
     debug!("started server");
     os.log_event("started;safekeeper".to_owned());
     let conf = SafeKeeperConf {
         workdir: Utf8PathBuf::from("."),
-        my_id: NodeId(os.id() as u64),
+        cold_wal_dir: None,
+        // 0 is invalid safekeeper id, so shift node ids by one
+        my_id: NodeId(os.id() as u64 + 1),
         listen_pg_addr: String::new(),
         listen_http_addr: String::new(),
         listen_https_addr: None,
@@ -181,6 +191,7 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         delete_offloaded_wal: false,
         control_file_save_interval: Duration::from_secs(1),
         partial_backup_concurrency: 1,
+        pull_timeline_concurrency: 1,
         eviction_min_resident: Duration::ZERO,
         wal_reader_fanout: false,
         max_delta_for_fanout: None,
@@ -200,11 +211,16 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
         /* END_HADRON */
     };
 
-    let mut global = GlobalMap::new(disk, conf.clone())?;
+    let proto_version = if mconf.generation == INVALID_GENERATION {
+        SK_PROTO_VERSION_3
+    } else {
+        SK_PROTO_VERSION_4
+    };
+    let mut global = GlobalMap::new(disk, conf.clone(), mconf)?;
     let mut conns: HashMap<usize, ConnState> = HashMap::new();
 
     for (&_ttid, shared_state) in global.timelines.iter_mut() {
-        let flush_lsn = shared_state.sk.wal_store.flush_lsn();
+        let flush_lsn = shared_state.sk.storage_flush_lsn();
         let commit_lsn = shared_state.sk.state.commit_lsn;
         os.log_event(format!("tli_loaded;{};{}", flush_lsn.0, commit_lsn.0));
     }
@@ -242,6 +258,7 @@ pub fn run_server(os: NodeOs, disk: Arc<SafekeeperDisk>) -> Result<()> {
                             greeting: false,
                             ttid: TenantTimelineId::empty(),
                             flush_pending: false,
+                            proto_version,
                             runtime: tokio::runtime::Builder::new_current_thread().build()?,
                         },
                     );
@@ -303,7 +320,7 @@ fn process_any(&mut self, any: AnyMessage, global: &mut GlobalMap) -> Result<()>
                 bail!("finished processing START_REPLICATION")
             }
 
-            let msg = ProposerAcceptorMessage::parse(copy_data, SK_PROTO_VERSION_3)?;
+            let msg = ProposerAcceptorMessage::parse(copy_data, self.proto_version)?;
             debug!("got msg: {:?}", msg);
             self.process(msg, global)
         } else {
@@ -327,7 +344,12 @@ fn process_start_replication(
         let end_lsn = parts.next().unwrap().parse::<u64>()?;
 
         let ttid = TenantTimelineId::new(tenant_id, timeline_id);
+        let my_id = global.conf.my_id;
         let shared_state = global.get(&ttid);
+        if shared_state.sk.is_witness() {
+            // walproposer must never pick witness as a donor
+            bail!("witness {my_id} was asked for WAL");
+        }
 
         // read bytes from start_lsn to end_lsn
         let mut buf = vec![0; (end_lsn - start_lsn) as usize];
@@ -419,7 +441,7 @@ fn process_sk_msg(
             // TODO: if this is AppendResponse, fill in proper hot standby feedback and disk consistent lsn
 
             let mut buf = BytesMut::with_capacity(128);
-            reply.serialize(&mut buf, SK_PROTO_VERSION_3)?;
+            reply.serialize(&mut buf, self.proto_version)?;
 
             self.tcp.send(AnyMessage::Bytes(buf.into()));
         }
@@ -185,6 +185,10 @@ fn flush_lsn(&self) -> Lsn {
         self.flush_record_lsn
     }
 
+    fn write_record_lsn(&self) -> Lsn {
+        self.write_record_lsn
+    }
+
     async fn initialize_first_segment(&mut self, _init_lsn: Lsn) -> Result<()> {
         Ok(())
     }
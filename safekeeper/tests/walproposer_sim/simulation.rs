@@ -1,4 +1,4 @@
-use std::cell::Cell;
+use std::cell::RefCell;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -8,8 +8,12 @@
 use desim::proto::{AnyMessage, NodeEvent};
 use desim::world::{Node, World};
 use rand::{Rng, SeedableRng};
+use safekeeper::safekeeper::{SK_PROTO_VERSION_3, SK_PROTO_VERSION_4};
+use safekeeper_api::membership::{
+    Configuration, MemberSet, SafekeeperGeneration, SafekeeperId, SafekeeperRole,
+};
 use tracing::{debug, info_span, warn};
-use utils::id::TenantTimelineId;
+use utils::id::{NodeId, TenantTimelineId};
 use utils::lsn::Lsn;
 use walproposer::walproposer::{Config, Wrapper};
 
@@ -25,36 +29,58 @@ pub struct SafekeeperNode {
     pub node: Arc<Node>,
     pub id: u32,
     pub disk: Arc<SafekeeperDisk>,
-    pub thread: Cell<ExternalHandle>,
+    pub thread: RefCell<ExternalHandle>,
+    /// Membership configuration of new timelines.
+    mconf: Configuration,
 }
 
 impl SafekeeperNode {
     /// Create and start a safekeeper at the specified Node.
-    pub fn new(node: Arc<Node>) -> Self {
+    pub fn new(node: Arc<Node>, mconf: Configuration) -> Self {
         let disk = Arc::new(SafekeeperDisk::new());
-        let thread = Cell::new(SafekeeperNode::launch(disk.clone(), node.clone()));
+        let thread = RefCell::new(SafekeeperNode::launch(
+            disk.clone(),
+            node.clone(),
+            mconf.clone(),
+        ));
 
         Self {
             id: node.id,
             node,
             disk,
             thread,
+            mconf,
         }
     }
 
-    fn launch(disk: Arc<SafekeeperDisk>, node: Arc<Node>) -> ExternalHandle {
+    fn launch(disk: Arc<SafekeeperDisk>, node: Arc<Node>, mconf: Configuration) -> ExternalHandle {
         // start the server thread
         node.launch(move |os| {
-            run_server(os, disk).expect("server should finish without errors");
+            run_server(os, disk, mconf).expect("server should finish without errors");
         })
     }
 
     /// Restart the safekeeper.
     pub fn restart(&self) {
-        let new_thread = SafekeeperNode::launch(self.disk.clone(), self.node.clone());
+        let new_thread = self.launch_again();
         let old_thread = self.thread.replace(new_thread);
         old_thread.crash_stop();
     }
+
+    /// Crash the safekeeper, it stays down until [`Self::start`] is called.
+    pub fn stop(&self) {
+        self.thread.borrow().crash_stop();
+    }
+
+    /// Start the safekeeper stopped by [`Self::stop`].
+    pub fn start(&self) {
+        let new_thread = self.launch_again();
+        self.thread.replace(new_thread);
+    }
+
+    fn launch_again(&self) -> ExternalHandle {
+        SafekeeperNode::launch(self.disk.clone(), self.node.clone(), self.mconf.clone())
+    }
 }
 
 /// Simulated walproposer node.
@@ -72,6 +98,7 @@ fn start(
         disk: Arc<DiskWalProposer>,
         ttid: TenantTimelineId,
         addrs: Vec<String>,
+        proto_version: u32,
         lsn: Option<Lsn>,
     ) {
         let sync_safekeepers = lsn.is_none();
@@ -91,6 +118,7 @@ fn start(
             safekeeper_reconnect_timeout: 1000,
             safekeeper_connection_timeout: 5000,
             sync_safekeepers,
+            proto_version,
         };
         let args = walproposer_api::Args {
             os,
@@ -104,14 +132,19 @@ fn start(
     }
 
     /// Start walproposer in a sync_safekeepers mode.
-    pub fn launch_sync(ttid: TenantTimelineId, addrs: Vec<String>, node: Arc<Node>) -> Self {
+    pub fn launch_sync(
+        ttid: TenantTimelineId,
+        addrs: Vec<String>,
+        proto_version: u32,
+        node: Arc<Node>,
+    ) -> Self {
         debug!("sync_safekeepers started at node {}", node.id);
         let disk = DiskWalProposer::new();
         let disk_wp = disk.clone();
 
         // start the client thread
         let handle = node.launch(move |os| {
-            WalProposer::start(os, disk_wp, ttid, addrs, None);
+            WalProposer::start(os, disk_wp, ttid, addrs, proto_version, None);
         });
 
         Self {
@@ -126,6 +159,7 @@ pub fn launch_sync(ttid: TenantTimelineId, addrs: Vec<String>, node: Arc<Node>)
     pub fn launch_walproposer(
         ttid: TenantTimelineId,
         addrs: Vec<String>,
+        proto_version: u32,
         node: Arc<Node>,
         lsn: Lsn,
     ) -> Self {
@@ -136,7 +170,7 @@ pub fn launch_walproposer(
 
         // start the client thread
         let handle = node.launch(move |os| {
-            WalProposer::start(os, disk_wp, ttid, addrs, Some(lsn));
+            WalProposer::start(os, disk_wp, ttid, addrs, proto_version, Some(lsn));
         });
 
         Self {
@@ -171,6 +205,24 @@ pub fn write_tx(&mut self, cnt: usize) {
     pub fn stop(&self) {
         self.thread.crash_stop();
     }
+
+    /// End of the WAL written by walproposer.
+    pub fn flush_lsn(&self) -> Lsn {
+        self.disk.lock().flush_rec_ptr()
+    }
+
+    /// LSN returned by sync_safekeepers, if it has finished successfully.
+    pub fn synced_lsn(&self) -> Option<Lsn> {
+        assert!(self.sync_safekeepers);
+        if !self.thread.is_finished() {
+            return None;
+        }
+        let res = self.thread.result();
+        if res.0 != 0 {
+            return None;
+        }
+        Lsn::from_str(&res.1).ok()
+    }
 }
 
 /// Holds basic simulation settings, such as network options.
@@ -178,6 +230,9 @@ pub struct TestConfig {
     pub network: NetworkOptions,
     pub timeout: u64,
     pub clock: Option<SimClock>,
+    /// Index of the safekeeper which is a witness member. If set, timelines
+    /// are created with membership configuration, which enables generations.
+    pub witness: Option<usize>,
 }
 
 impl TestConfig {
@@ -199,6 +254,7 @@ pub fn new(clock: Option<SimClock>) -> Self {
             },
             timeout: 1_000 * 10,
             clock,
+            witness: None,
         }
     }
 
@@ -210,14 +266,40 @@ pub fn start(&self, seed: u64) -> Test {
             clock.set_clock(world.clock());
         }
 
-        let servers = [
-            SafekeeperNode::new(world.new_node()),
-            SafekeeperNode::new(world.new_node()),
-            SafekeeperNode::new(world.new_node()),
-        ];
+        let nodes = [world.new_node(), world.new_node(), world.new_node()];
+        let server_ids = [nodes[0].id, nodes[1].id, nodes[2].id];
+        let mut safekeepers_addrs = server_ids.map(|id| format!("node:{id}")).to_vec();
+
+        let (mconf, proto_version) = match self.witness {
+            Some(witness) => {
+                let members = server_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &id)| SafekeeperId {
+                        // safekeeper ids are shifted by one from node ids
+                        id: NodeId(id as u64 + 1),
+                        host: "node".to_owned(),
+                        pg_port: id as u16,
+                        role: if i == witness {
+                            SafekeeperRole::Witness
+                        } else {
+                            SafekeeperRole::Full
+                        },
+                    })
+                    .collect();
+                let generation = SafekeeperGeneration::new(1);
+                let mconf = Configuration {
+                    generation,
+                    members: MemberSet::new(members).expect("unique members"),
+                    new_members: None,
+                };
+                safekeepers_addrs[0] = format!("g#{generation}:{}", safekeepers_addrs[0]);
+                (mconf, SK_PROTO_VERSION_4)
+            }
+            None => (Configuration::empty(), SK_PROTO_VERSION_3),
+        };
 
-        let server_ids = [servers[0].id, servers[1].id, servers[2].id];
-        let safekeepers_addrs = server_ids.map(|id| format!("node:{id}")).to_vec();
+        let servers = nodes.map(|node| SafekeeperNode::new(node, mconf.clone()));
 
         let ttid = TenantTimelineId::generate();
 
@@ -225,6 +307,7 @@ pub fn start(&self, seed: u64) -> Test {
             world,
             servers,
             sk_list: safekeepers_addrs,
+            proto_version,
             ttid,
             timeout: self.timeout,
         }
@@ -236,6 +319,7 @@ pub struct Test {
     pub world: Arc<World>,
     pub servers: [SafekeeperNode; 3],
     pub sk_list: Vec<String>,
+    pub proto_version: u32,
     pub ttid: TenantTimelineId,
     pub timeout: u64,
 }
@@ -263,7 +347,12 @@ pub fn sync_safekeepers(&self) -> anyhow::Result<Lsn> {
 
     /// Spawn a new sync_safekeepers thread.
     pub fn launch_sync_safekeepers(&self) -> WalProposer {
-        WalProposer::launch_sync(self.ttid, self.sk_list.clone(), self.world.new_node())
+        WalProposer::launch_sync(
+            self.ttid,
+            self.sk_list.clone(),
+            self.proto_version,
+            self.world.new_node(),
+        )
     }
 
     /// Spawn a new walproposer thread.
@@ -275,7 +364,13 @@ pub fn launch_walproposer(&self, lsn: Lsn) -> WalProposer {
             lsn
         };
 
-        WalProposer::launch_walproposer(self.ttid, self.sk_list.clone(), self.world.new_node(), lsn)
+        WalProposer::launch_walproposer(
+            self.ttid,
+            self.sk_list.clone(),
+            self.proto_version,
+            self.world.new_node(),
+            lsn,
+        )
     }
 
     /// Execute the simulation for the specified duration.
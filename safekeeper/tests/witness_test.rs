@@ -0,0 +1,156 @@
+use tracing::info;
+use utils::lsn::Lsn;
+
+use crate::walproposer_sim::log::init_logger;
+use crate::walproposer_sim::simulation::{Test, TestConfig};
+
+pub mod walproposer_sim;
+
+/// Index of the witness member, servers 0 and 1 are full members.
+const WITNESS: usize = 2;
+
+fn start_test() -> Test {
+    let clock = init_logger();
+    let mut config = TestConfig::new(Some(clock));
+    config.witness = Some(WITNESS);
+    config.timeout = 1_000 * 60;
+    config.start(1337)
+}
+
+/// Wait until walproposer reports commit_lsn at least `lsn`.
+fn wait_for_commit(test: &Test, lsn: Lsn) {
+    for _ in 0..100 {
+        test.poll_for_duration(100);
+        let committed = test.world.take_events().iter().any(|event| {
+            event
+                .data
+                .strip_prefix("commit_lsn;")
+                .is_some_and(|commit_lsn| commit_lsn.parse::<u64>().unwrap() >= lsn.0)
+        });
+        if committed {
+            return;
+        }
+    }
+    panic!("commit_lsn didn't reach {lsn}");
+}
+
+/// Restart the safekeeper and return flush_lsn it has loaded from disk.
+fn restart_and_get_flush_lsn(test: &Test, server: usize) -> Lsn {
+    test.world.take_events();
+    test.servers[server].restart();
+    test.poll_for_duration(10);
+    let node = test.servers[server].id;
+    test.world
+        .take_events()
+        .iter()
+        .filter(|event| event.node == node)
+        .find_map(|event| {
+            let loaded = event.data.strip_prefix("tli_loaded;")?;
+            Some(Lsn(loaded.split(';').next()?.parse().unwrap()))
+        })
+        .expect("safekeeper should load the timeline")
+}
+
+// Test that full member and witness form a quorum, and that the other full
+// member catches up the WAL it has missed from walproposer.
+#[test]
+fn witness_acks_and_full_member_catches_up() {
+    let test = start_test();
+
+    let lsn = test.sync_safekeepers().unwrap();
+    let mut wp = test.launch_walproposer(lsn);
+    wp.write_tx(3);
+    wait_for_commit(&test, wp.flush_lsn());
+
+    // Only server 0 and the witness are alive, they acknowledge the WAL.
+    test.servers[1].stop();
+    wp.write_tx(10);
+    let end_lsn = wp.flush_lsn();
+    wait_for_commit(&test, end_lsn);
+    info!("committed {} with witness in the quorum", end_lsn);
+
+    // Server 1 gets the WAL it has missed from walproposer.
+    test.servers[1].start();
+    test.poll_for_duration(2000);
+    let flush_lsn = restart_and_get_flush_lsn(&test, 1);
+    assert!(
+        flush_lsn >= end_lsn,
+        "server 1 has flushed only up to {flush_lsn}, expected {end_lsn}"
+    );
+}
+
+// Test that the witness having the highest flush_lsn is not picked as donor:
+// election waits for the full member holding the WAL.
+#[test]
+fn witness_ahead_is_not_donor() {
+    let test = start_test();
+
+    let lsn = test.sync_safekeepers().unwrap();
+    let mut wp = test.launch_walproposer(lsn);
+    wp.write_tx(3);
+    wait_for_commit(&test, wp.flush_lsn());
+
+    // Commit WAL on server 0 and the witness only.
+    test.servers[1].stop();
+    wp.write_tx(10);
+    let end_lsn = wp.flush_lsn();
+    wait_for_commit(&test, end_lsn);
+    wp.stop();
+
+    // Server 1 and the witness make a quorum, but only the witness has
+    // end_lsn, so the election can't finish.
+    test.servers[0].stop();
+    test.servers[1].start();
+    let sync = test.launch_sync_safekeepers();
+    test.poll_for_duration(1000);
+    assert_eq!(sync.synced_lsn(), None);
+
+    // Server 0 has the WAL and becomes the donor.
+    test.servers[0].start();
+    for _ in 0..100 {
+        if sync.synced_lsn().is_some() {
+            break;
+        }
+        test.poll_for_duration(100);
+    }
+    assert_eq!(sync.synced_lsn(), Some(end_lsn));
+}
+
+// Test that the witness doesn't forget WAL it has acknowledged: after a crash
+// it still votes with the flush_lsn it has acked, so election waits for the
+// full member holding the WAL.
+#[test]
+fn witness_restart_keeps_acked_lsn() {
+    let test = start_test();
+
+    let lsn = test.sync_safekeepers().unwrap();
+    let mut wp = test.launch_walproposer(lsn);
+    wp.write_tx(3);
+    wait_for_commit(&test, wp.flush_lsn());
+
+    // Commit WAL on server 0 and the witness only.
+    test.servers[1].stop();
+    wp.write_tx(10);
+    let end_lsn = wp.flush_lsn();
+    wait_for_commit(&test, end_lsn);
+    wp.stop();
+
+    // Crash the witness right after it has acknowledged end_lsn.
+    let flush_lsn = restart_and_get_flush_lsn(&test, WITNESS);
+    assert_eq!(flush_lsn, end_lsn);
+
+    test.servers[0].stop();
+    test.servers[1].start();
+    let sync = test.launch_sync_safekeepers();
+    test.poll_for_duration(1000);
+    assert_eq!(sync.synced_lsn(), None);
+
+    test.servers[0].start();
+    for _ in 0..100 {
+        if sync.synced_lsn().is_some() {
+            break;
+        }
+        test.poll_for_duration(100);
+    }
+    assert_eq!(sync.synced_lsn(), Some(end_lsn));
+}
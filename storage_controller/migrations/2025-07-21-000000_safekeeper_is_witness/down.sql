@@ -0,0 +1 @@
+ALTER TABLE safekeepers DROP COLUMN is_witness;
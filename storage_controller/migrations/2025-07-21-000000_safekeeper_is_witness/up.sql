@@ -0,0 +1 @@
+ALTER TABLE safekeepers ADD COLUMN is_witness BOOLEAN NOT NULL DEFAULT FALSE;
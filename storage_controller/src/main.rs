@@ -217,6 +217,12 @@ struct Cli {
     #[arg(long, default_value = "3", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     timeline_safekeeper_count: usize,
 
+    /// How many of `timeline_safekeeper_count` safekeepers are witnesses, i.e.
+    /// vote but don't store WAL. Must be less than a majority. Witnesses are
+    /// picked among safekeepers registered as such.
+    #[arg(long, default_value = "0")]
+    timeline_safekeeper_witness_count: usize,
+
     /// When set, actively checks and initiates heatmap downloads/uploads during reconciliation.
     /// This speed up migrations by avoiding the default wait for the heatmap download interval.
     /// Primarily useful for testing to reduce test execution time.
@@ -369,6 +375,15 @@ async fn async_main() -> anyhow::Result<()> {
         StrictMode::Strict
     };
 
+    // Witnesses must not be able to form a quorum on their own.
+    if args.timeline_safekeeper_witness_count >= args.timeline_safekeeper_count / 2 + 1 {
+        anyhow::bail!(
+            "--timeline-safekeeper-witness-count {} must be less than a majority of --timeline-safekeeper-count {}",
+            args.timeline_safekeeper_witness_count,
+            args.timeline_safekeeper_count
+        );
+    }
+
     let secrets = Secrets::load(&args).await?;
 
     // Validate required secrets and arguments are provided in strict mode
@@ -475,6 +490,7 @@ async fn async_main() -> anyhow::Result<()> {
         timelines_onto_safekeepers: args.timelines_onto_safekeepers,
         use_local_compute_notifications: args.use_local_compute_notifications,
         timeline_safekeeper_count: args.timeline_safekeeper_count,
+        timeline_safekeeper_witness_count: args.timeline_safekeeper_witness_count,
         posthog_config: posthog_config.clone(),
         kick_secondary_downloads: args.kick_secondary_downloads,
         shard_split_request_timeout: args
@@ -2454,6 +2454,9 @@ pub(crate) struct SafekeeperPersistence {
     pub(crate) availability_zone_id: String,
     pub(crate) scheduling_policy: SkSchedulingPolicyFromSql,
     pub(crate) https_port: Option<i32>,
+    /// Witness safekeepers vote but don't store WAL. Can't be changed after
+    /// creation, as it is part of timelines membership configurations.
+    pub(crate) is_witness: bool,
 }
 
 /// Wrapper struct around [`SkSchedulingPolicy`] because both it and [`FromSql`] are from foreign crates,
@@ -2497,6 +2500,7 @@ pub(crate) fn from_upsert(
             https_port: upsert.https_port,
             availability_zone_id: upsert.availability_zone_id,
             scheduling_policy: SkSchedulingPolicyFromSql(scheduling_policy),
+            is_witness: upsert.is_witness,
         }
     }
     pub(crate) fn as_describe_response(&self) -> Result<SafekeeperDescribeResponse, DatabaseError> {
@@ -2510,6 +2514,7 @@ pub(crate) fn as_describe_response(&self) -> Result<SafekeeperDescribeResponse,
             https_port: self.https_port,
             availability_zone_id: self.availability_zone_id.clone(),
             scheduling_policy: self.scheduling_policy.0,
+            is_witness: self.is_witness,
         })
     }
 }
@@ -2530,6 +2535,8 @@ pub(crate) struct SafekeeperUpsert {
     pub(crate) http_port: i32,
     pub(crate) https_port: Option<i32>,
     pub(crate) availability_zone_id: String,
+    #[serde(default)]
+    pub(crate) is_witness: bool,
 }
 
 impl SafekeeperUpsert {
@@ -2548,6 +2555,7 @@ fn as_insert_or_update(&self) -> anyhow::Result<InsertUpdateSafekeeper<'_>> {
             availability_zone_id: &self.availability_zone_id,
             // None means a wish to not update this column. We expose abilities to update it via other means.
             scheduling_policy: None,
+            is_witness: self.is_witness,
         })
     }
 }
@@ -2564,6 +2572,7 @@ struct InsertUpdateSafekeeper<'a> {
     https_port: Option<i32>,
     availability_zone_id: &'a str,
     scheduling_policy: Option<&'a str>,
+    is_witness: bool,
 }
 
 #[derive(Serialize, Deserialize, FromSqlRow, AsExpression, Eq, PartialEq, Debug, Copy, Clone)]
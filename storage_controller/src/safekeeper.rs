@@ -2,7 +2,7 @@
 
 use pageserver_api::controller_api::{SafekeeperDescribeResponse, SkSchedulingPolicy};
 use reqwest::StatusCode;
-use safekeeper_api::membership::SafekeeperId;
+use safekeeper_api::membership::{SafekeeperId, SafekeeperRole};
 use safekeeper_client::mgmt_api;
 use tokio_util::sync::CancellationToken;
 use utils::backoff;
@@ -98,8 +98,16 @@ pub(crate) fn get_safekeeper_id(&self) -> SafekeeperId {
             id: self.id,
             host: self.skp.host.clone(),
             pg_port: self.skp.port as u16,
+            role: if self.is_witness() {
+                SafekeeperRole::Witness
+            } else {
+                SafekeeperRole::Full
+            },
         }
     }
+    pub(crate) fn is_witness(&self) -> bool {
+        self.skp.is_witness
+    }
     /// Perform an operation (which is given a [`SafekeeperClient`]) with retries
     #[allow(clippy::too_many_arguments)]
     pub(crate) async fn with_client_retries<T, O, F>(
@@ -178,6 +186,7 @@ pub(crate) fn update_from_record(
             http_port,
             https_port,
             id,
+            is_witness,
             port: _,
             region_id: _,
             version: _,
@@ -195,6 +204,9 @@ pub(crate) fn update_from_record(
                 https is enabled, but https port is not specified"
             );
         }
+        if is_witness != self.is_witness() {
+            anyhow::bail!("cannot update safekeeper {id}: witness flag can't be changed");
+        }
         self.skp =
             crate::persistence::SafekeeperPersistence::from_upsert(record, self.scheduling_policy);
         self.listen_http_port = http_port as u16;
@@ -78,6 +78,7 @@ pub mod sql_types {
         availability_zone_id -> Text,
         scheduling_policy -> Varchar,
         https_port -> Nullable<Int4>,
+        is_witness -> Bool,
     }
 }
 
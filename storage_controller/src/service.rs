@@ -479,6 +479,9 @@ pub struct Config {
     /// Safekeepers will be choosen from different availability zones.
     pub timeline_safekeeper_count: usize,
 
+    /// How many of `timeline_safekeeper_count` safekeepers are witnesses.
+    pub timeline_safekeeper_witness_count: usize,
+
     /// PostHog integration config
     pub posthog_config: Option<PostHogConfig>,
 
@@ -2028,6 +2031,7 @@ pub async fn spawn(config: Config, persistence: Arc<Persistence>) -> anyhow::Res
         };
         tracing::info!(
             timeline_safekeeper_count = config.timeline_safekeeper_count,
+            timeline_safekeeper_witness_count = config.timeline_safekeeper_witness_count,
             timelines_onto_safekeepers = config.timelines_onto_safekeepers,
             "viability test result (test timeline creation on safekeepers): {test_sk_res_str}",
         );
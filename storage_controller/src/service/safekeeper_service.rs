@@ -46,7 +46,9 @@ fn make_member_set(safekeepers: &[Safekeeper]) -> Result<MemberSet, anyhow::Erro
             .map(|sk| sk.get_safekeeper_id())
             .collect::<Vec<_>>();
 
-        MemberSet::new(members)
+        let mset = MemberSet::new(members)?;
+        mset.validate_witnesses()?;
+        Ok(mset)
     }
 
     fn get_safekeepers(&self, ids: &[i64]) -> Result<Vec<Safekeeper>, ApiError> {
@@ -722,7 +724,12 @@ pub(crate) async fn safekeepers_for_new_timeline(
                         hostname: sk.1.skp.host.clone(),
                         id: NodeId(sk.1.skp.id as u64),
                     };
-                    Some((utilization_opt, info, sk.1.skp.availability_zone_id.clone()))
+                    Some((
+                        utilization_opt,
+                        info,
+                        sk.1.skp.availability_zone_id.clone(),
+                        sk.1.is_witness(),
+                    ))
                 })
                 .collect::<Vec<_>>()
         };
@@ -737,16 +744,27 @@ pub(crate) async fn safekeepers_for_new_timeline(
         });
         // Number of safekeepers in different AZs we are looking for
         let wanted_count = self.config.timeline_safekeeper_count;
+        // Of them, this many are witnesses (checked to be less than quorum on startup).
+        let wanted_witness_count = self.config.timeline_safekeeper_witness_count;
 
         let mut sks = Vec::new();
         let mut azs = HashSet::new();
-        for (_sk_util, sk_info, az_id) in all_safekeepers.iter() {
-            if !azs.insert(az_id) {
-                continue;
-            }
-            sks.push(sk_info.clone());
-            if sks.len() == wanted_count {
-                break;
+        // Pick full members first, then fill the rest with witnesses from the
+        // remaining AZs.
+        for (witnesses, wanted) in [
+            (false, wanted_count - wanted_witness_count),
+            (true, wanted_witness_count),
+        ] {
+            let mut picked = 0;
+            for (_sk_util, sk_info, az_id, is_witness) in all_safekeepers.iter() {
+                if picked == wanted {
+                    break;
+                }
+                if *is_witness != witnesses || !azs.insert(az_id) {
+                    continue;
+                }
+                sks.push(sk_info.clone());
+                picked += 1;
             }
         }
         if sks.len() == wanted_count {
@@ -801,6 +819,15 @@ pub(crate) async fn upsert_safekeeper(
                 .into(),
             ));
         }
+        // Witness flag is a part of timelines membership configurations.
+        if let Some(sk) = self.inner.read().unwrap().safekeepers.get(&node_id) {
+            if sk.is_witness() != record.is_witness {
+                return Err(ApiError::PreconditionFailed(
+                    format!("cannot upsert safekeeper {node_id}: witness flag can't be changed")
+                        .into(),
+                ));
+            }
+        }
 
         self.persistence.safekeeper_upsert(record.clone()).await?;
         {
@@ -3798,6 +3798,27 @@ class NeonProxy(PgProtocol):
                 *["--auth-endpoint", self.pg_conn_url],
             ]
 
+    class File(AuthBackend):
+        def __init__(self, path: Path, pg_conn_url: str):
+            self.path = path
+            self.pg_conn_url = pg_conn_url
+
+        @property
+        def default_conn_url(self) -> str | None:
+            return self.pg_conn_url
+
+        def write_endpoints(self, endpoints: list[dict[str, Any]]):
+            """Replace the endpoints of the file, the proxy reloads them shortly after."""
+            self.path.write_text(json.dumps({"endpoints": endpoints}))
+
+        def extra_args(self) -> list[str]:
+            return [
+                # File auth backend params
+                *["--auth-backend", "file"],
+                *["--control-plane-file", str(self.path)],
+                *["--control-plane-file-poll-interval", "100ms"],
+            ]
+
     def __init__(
         self,
         neon_binpath: Path,
@@ -3811,6 +3832,7 @@ class NeonProxy(PgProtocol):
         auth_backend: NeonProxy.AuthBackend,
         metric_collection_endpoint: str | None = None,
         metric_collection_interval: str | None = None,
+        extra_args: list[str] | None = None,
     ):
         host = "127.0.0.1"
         domain = "proxy.local.neon.build"  # resolves to 127.0.0.1
@@ -3829,6 +3851,7 @@ class NeonProxy(PgProtocol):
         self.auth_backend = auth_backend
         self.metric_collection_endpoint = metric_collection_endpoint
         self.metric_collection_interval = metric_collection_interval
+        self.extra_args = extra_args or []
         self.http_timeout_seconds = 15
         self._popen: subprocess.Popen[bytes] | None = None
 
@@ -3863,6 +3886,7 @@ class NeonProxy(PgProtocol):
             *["--sni-router-tls-key", str(self.test_output_dir / "router.key")],
             *["--sni-router-destination", "local.neon.build"],
             *self.auth_backend.extra_args(),
+            *self.extra_args,
         ]
 
         if (
@@ -4316,6 +4340,7 @@ class NeonRestBrokerProxy(LogUtils):
         http_port: int,
         mgmt_port: int,
         config_path: Path | None = None,
+        extra_args: list[str] | None = None,
     ):
         self.neon_binpath = neon_binpath
         self.test_output_dir = test_output_dir
@@ -4323,6 +4348,7 @@ class NeonRestBrokerProxy(LogUtils):
         self.http_port = http_port
         self.mgmt_port = mgmt_port
         self.config_path = config_path or (test_output_dir / "rest_broker_proxy.json")
+        self.extra_args = extra_args or []
         self.host = "127.0.0.1"
         self.running = False
         self.logfile = test_output_dir / "rest_broker_proxy.log"
@@ -4384,6 +4410,7 @@ class NeonRestBrokerProxy(LogUtils):
             "local",
             "--config-path",
             str(self.config_path),
+            *self.extra_args,
         ]
 
         log.info(f"Starting rest broker proxy with command: {' '.join(cmd)}")
@@ -4502,12 +4529,19 @@ def link_proxy(
         yield proxy
 
 
+@pytest.fixture(scope="function")
+def static_proxy_extra_args() -> list[str]:
+    """Extra arguments of `static_proxy`, tests can override them with `pytest.mark.parametrize`."""
+    return []
+
+
 @pytest.fixture(scope="function")
 def static_proxy(
     vanilla_pg: VanillaPostgres,
     port_distributor: PortDistributor,
     neon_binpath: Path,
     test_output_dir: Path,
+    static_proxy_extra_args: list[str],
 ) -> Iterator[NeonProxy]:
     """Neon proxy that routes directly to vanilla postgres."""
 
@@ -4551,6 +4585,64 @@ def static_proxy(
         router_tls_port=router_tls_port,
         external_http_port=external_http_port,
         auth_backend=NeonProxy.Postgres(auth_endpoint),
+        extra_args=static_proxy_extra_args,
+    ) as proxy:
+        proxy.start()
+        yield proxy
+
+
+@pytest.fixture(scope="function")
+def file_proxy(
+    vanilla_pg: VanillaPostgres,
+    port_distributor: PortDistributor,
+    neon_binpath: Path,
+    test_output_dir: Path,
+) -> Iterator[NeonProxy]:
+    """Neon proxy that serves the endpoints of a file, which route to vanilla postgres."""
+
+    port = vanilla_pg.default_options["port"]
+    host = vanilla_pg.default_options["host"]
+    dbname = vanilla_pg.default_options["dbname"]
+
+    vanilla_pg.start()
+    vanilla_pg.safe_psql("create user proxy with login superuser password 'password'")
+    secret = vanilla_pg.safe_psql("select rolpassword from pg_authid where rolname = 'proxy'")[0][0]
+
+    vanilla_pg.stop()
+    vanilla_pg.edit_hba(
+        [
+            "local all all              trust",
+            "host  all all 127.0.0.1/32 scram-sha-256",
+            "host  all all ::1/128      scram-sha-256",
+        ]
+    )
+    vanilla_pg.start()
+
+    auth_backend = NeonProxy.File(
+        test_output_dir / "endpoints.json",
+        f"postgres://proxy:password@{host}:{port}/{dbname}",
+    )
+    # the endpoint is taken from the SNI of the default connection string.
+    auth_backend.write_endpoints(
+        [
+            {
+                "id": "proxy",
+                "compute": {"host": host, "port": port},
+                "roles": [{"name": "proxy", "secret": secret}],
+            }
+        ]
+    )
+
+    with NeonProxy(
+        neon_binpath=neon_binpath,
+        test_output_dir=test_output_dir,
+        proxy_port=port_distributor.get_port(),
+        http_port=port_distributor.get_port(),
+        mgmt_port=port_distributor.get_port(),
+        router_port=port_distributor.get_port(),
+        router_tls_port=port_distributor.get_port(),
+        external_http_port=port_distributor.get_port(),
+        auth_backend=auth_backend,
     ) as proxy:
         proxy.start()
         yield proxy
@@ -4608,11 +4700,18 @@ def local_proxy_fixed_port(
         yield proxy
 
 
+@pytest.fixture(scope="function")
+def rest_broker_proxy_extra_args() -> list[str]:
+    """Extra arguments of `rest_broker_proxy`, overridden with `pytest.mark.parametrize`."""
+    return []
+
+
 @pytest.fixture(scope="function")
 def rest_broker_proxy(
     port_distributor: PortDistributor,
     neon_binpath: Path,
     test_output_dir: Path,
+    rest_broker_proxy_extra_args: list[str],
 ) -> Iterator[NeonRestBrokerProxy]:
     """Rest broker proxy that handles both auth broker and rest broker functionality."""
 
@@ -4626,6 +4725,7 @@ def rest_broker_proxy(
         wss_port=wss_port,
         http_port=http_port,
         mgmt_port=mgmt_port,
+        extra_args=rest_broker_proxy_extra_args,
     ) as proxy:
         proxy.start()
         yield proxy
@@ -12,6 +12,7 @@ import psycopg2
 import pytest
 import requests
 from fixtures.neon_fixtures import PSQL, NeonProxy, VanillaPostgres
+from fixtures.utils import wait_until
 
 if TYPE_CHECKING:
     from typing import Any
@@ -147,6 +148,84 @@ async def test_proxy_arbitrary_params(static_proxy: NeonProxy):
         assert out == '"PT0S"'
 
 
+@pytest.mark.asyncio
+async def test_proxy_read_only_fallback(static_proxy: NeonProxy):
+    """
+    Check that read-only sessions connect to the primary compute
+    when the control plane does not describe a read replica.
+    """
+    options = "neon_proxy_read_only:true"
+    with closing(await static_proxy.connect_async(server_settings={"options": options})) as conn:
+        assert await conn.fetchval("select 1") == 1
+
+    # `target_session_attrs` is only for the proxy, even with all the params forwarded.
+    options = "neon_proxy_params_compat:true"
+    with closing(
+        await static_proxy.connect_async(
+            server_settings={"target_session_attrs": "read-only", "options": options}
+        )
+    ) as conn:
+        assert await conn.fetchval("select 1") == 1
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("static_proxy_extra_args", [["--transaction-pool-max-idle-conns", "10"]])
+async def test_proxy_transaction_pooling(static_proxy: NeonProxy):
+    """
+    Check that clients in transaction pooling mode share compute connections
+    between transactions, and can keep using their prepared statements.
+    """
+
+    options = "neon_proxy_pool_mode:transaction"
+    conn1 = await static_proxy.connect_async(server_settings={"options": options})
+    conn2 = await static_proxy.connect_async(server_settings={"options": options})
+    with closing(conn1), closing(conn2):
+        # one transaction at a time only needs a single compute connection.
+        pids = {await conn.fetchval("select pg_backend_pid()") for conn in [conn1, conn2, conn1]}
+        assert len(pids) == 1
+
+        # a transaction keeps its compute connection until it ends.
+        async with conn1.transaction():
+            pid1 = await conn1.fetchval("select pg_backend_pid()")
+            pid2 = await conn2.fetchval("select pg_backend_pid()")
+            assert pid1 != pid2
+            assert await conn1.fetchval("select pg_backend_pid()") == pid1
+
+        stmt = await conn1.prepare("select $1::int + 1")
+        assert await stmt.fetchval(1) == 2
+
+        # while the connection the statement was prepared on is busy,
+        # it is prepared again on another connection.
+        async with conn2.transaction():
+            await conn2.fetchval("select pg_backend_pid()")
+            assert await stmt.fetchval(41) == 42
+
+        # the same statement name can be used by several clients for different queries.
+        assert await conn2.fetchval("select $1::text", "a") == "a"
+        assert await conn1.fetchval("select $1::int", 1) == 1
+
+
+def test_proxy_file_backend(file_proxy: NeonProxy):
+    """
+    Check that the proxy serves the endpoints of the file auth backend,
+    and picks up the changes to the file.
+    """
+
+    assert file_proxy.safe_psql("select 1")[0][0] == 1
+
+    # an endpoint which is not in the file.
+    other = "other.local.neon.build"
+    with pytest.raises(psycopg2.Error):
+        file_proxy.safe_psql("select 1", host=other)
+
+    auth_backend = file_proxy.auth_backend
+    assert isinstance(auth_backend, NeonProxy.File)
+    endpoints = json.loads(auth_backend.path.read_text())["endpoints"]
+    auth_backend.write_endpoints([*endpoints, {**endpoints[0], "id": "other"}])
+
+    wait_until(lambda: file_proxy.safe_psql("select 1", host=other))
+
+
 def test_auth_errors(static_proxy: NeonProxy):
     """
     Check that we throw very specific errors in some unsuccessful auth scenarios.
@@ -358,6 +437,105 @@ def test_sql_over_http_output_options(static_proxy: NeonProxy):
     assert rows == [["1", "a", "{1,2,3}"]]
 
 
+def test_sql_over_http_typed_mode(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http3 with login password 'http3' superuser")
+
+    def q(body: dict[str, Any], headers: dict[str, str]) -> requests.Response:
+        connstr = (
+            f"postgresql://http3:http3@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        )
+        return requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps(body),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                **headers,
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+
+    typed = {"Neon-Typed-Mode": "true"}
+
+    # declared types decide how parameters are parsed, bytea goes as base64
+    body: dict[str, Any] = {
+        "query": "select $1 as n, $2 as b, pg_typeof($1)::text as t, $3 as arr",
+        "params": ["123456789012345678901234567890.5", "3q2+7w==", [1, 2]],
+        "types": ["numeric", "bytea", "int8[]"],
+    }
+    response = q(body, typed)
+    assert response.status_code == 200, response.text
+    res = response.json()
+    assert res["rows"] == [
+        {
+            "n": "123456789012345678901234567890.5",
+            "b": "3q2+7w==",
+            "t": "numeric",
+            "arr": ["1", "2"],
+        }
+    ]
+    assert [f["dataTypeID"] for f in res["fields"]] == [1700, 17, 25, 1016]
+
+    # types are refused without typed mode
+    response = q(body, {})
+    assert response.status_code == 400, response.text
+
+    # binary output returns columns as base64 of their binary representation
+    body = {"query": "select 1::int4 as n, null::text as s"}
+    response = q(body, {"Neon-Binary-Output": "true"})
+    assert response.status_code == 200, response.text
+    res = response.json()
+    assert res["rows"] == [{"n": "AAAAAQ==", "s": None}]
+    assert all(f["format"] == "binary" for f in res["fields"])
+
+
+def test_sql_over_http_stream_output(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http4 with login password 'http4' superuser")
+
+    def q(body: dict[str, Any]) -> requests.Response:
+        connstr = (
+            f"postgresql://http4:http4@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        )
+        return requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps(body),
+            headers={
+                "Content-Type": "application/sql",
+                "Neon-Connection-String": connstr,
+                "Neon-Stream-Output": "true",
+            },
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+            stream=True,
+        )
+
+    # result larger than max_response_size_bytes is streamed line by line
+    query = "select g as n, repeat('x', 1000) as s from generate_series(1, 20000) g"
+    response = q({"query": query})
+    assert response.status_code == 200, response.text
+    assert response.headers["Content-Type"] == "application/x-ndjson"
+    lines = [json.loads(line) for line in response.iter_lines()]
+    assert [f["name"] for f in lines[0]["fields"]] == ["n", "s"]
+    assert lines[0]["rowAsArray"] is False
+    assert len(lines) == 20002
+    assert lines[1] == {"n": 1, "s": "x" * 1000}
+    assert lines[-1] == {"command": "SELECT", "rowCount": 20000}
+
+    # errors before the first row are returned as usual
+    response = q({"query": "select * from no_such_table"})
+    assert response.status_code == 400
+    assert response.json()["code"] == "42P01"
+
+    # errors while streaming are sent as the last line
+    response = q({"query": "select 1 / (3 - g) from generate_series(1, 5) g"})
+    assert response.status_code == 200, response.text
+    lines = [json.loads(line) for line in response.iter_lines()]
+    assert lines[-1]["error"]["code"] == "22012"
+
+    # batches are not streamed
+    response = q({"queries": [{"query": "select 1"}]})
+    assert response.status_code == 400
+
+
 def test_sql_over_http_batch(static_proxy: NeonProxy):
     static_proxy.safe_psql("create role http with login password 'http' superuser")
 
@@ -438,6 +616,56 @@ def test_sql_over_http_batch(static_proxy: NeonProxy):
     assert result[0]["rows"] == [{"answer": 42}]
 
 
+def test_sql_over_http_txn_session(static_proxy: NeonProxy):
+    static_proxy.safe_psql("create role http with login password 'http' superuser")
+    static_proxy.safe_psql("create table if not exists txn_session(val int)")
+
+    def q(sql: str, session: str | None) -> tuple[Any, str | None]:
+        connstr = f"postgresql://http:http@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+        headers = {"Content-Type": "application/sql", "Neon-Connection-String": connstr}
+        if session is not None:
+            headers["Neon-Txn-Session"] = session
+        response = requests.post(
+            f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+            data=json.dumps({"query": sql}),
+            headers=headers,
+            verify=str(static_proxy.test_output_dir / "proxy.crt"),
+        )
+        assert response.status_code == 200, response.text
+        return response.json(), response.headers.get("Neon-Txn-Session")
+
+    def count() -> int:
+        return q("select count(*)::int as n from txn_session", None)[0]["rows"][0]["n"]
+
+    # rows inserted in the session are invisible until commit
+    _, token = q("insert into txn_session values (1)", "begin")
+    assert token is not None
+    _, token2 = q("insert into txn_session values (2)", token)
+    assert token2 == token
+    assert count() == 0
+    _, token = q("commit", token)
+    assert token is None
+    assert count() == 2
+
+    # rollback ends the session too, and its token is not accepted anymore
+    _, token = q("insert into txn_session values (3)", "begin")
+    _, end = q("rollback", token)
+    assert end is None
+    assert count() == 2
+    connstr = f"postgresql://http:http@{static_proxy.domain}:{static_proxy.proxy_port}/postgres"
+    response = requests.post(
+        f"https://{static_proxy.domain}:{static_proxy.external_http_port}/sql",
+        data=json.dumps({"query": "select 1"}),
+        headers={
+            "Content-Type": "application/sql",
+            "Neon-Connection-String": connstr,
+            "Neon-Txn-Session": token,
+        },
+        verify=str(static_proxy.test_output_dir / "proxy.crt"),
+    )
+    assert response.status_code == 404
+
+
 def test_sql_over_http_batch_output_options(static_proxy: NeonProxy):
     static_proxy.safe_psql("create role http with login password 'http' superuser")
 
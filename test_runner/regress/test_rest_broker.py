@@ -2,6 +2,7 @@ import json
 import signal
 import time
 
+import pytest
 import requests
 from fixtures.utils import skip_if_proxy_lacks_rest_broker
 from jwcrypto import jwt
@@ -135,3 +136,225 @@ def test_rest_broker_happy(
 
     # check the response body
     assert response.json() == [{"name": "test_item"}]
+
+
+def setup_rest_broker(
+    local_proxy, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+) -> str:
+    """Bootstrap the database and the proxies, and return a JWT for the authenticated role."""
+
+    # Create the required roles for PostgREST authentication
+    vanilla_pg.safe_psql("CREATE ROLE authenticator LOGIN")
+    vanilla_pg.safe_psql("CREATE ROLE authenticated")
+    vanilla_pg.safe_psql("CREATE ROLE anon")
+    vanilla_pg.safe_psql("GRANT authenticated TO authenticator")
+    vanilla_pg.safe_psql("GRANT anon TO authenticator")
+
+    # Create the pgrst schema and configuration function required by the rest broker
+    vanilla_pg.safe_psql("CREATE SCHEMA IF NOT EXISTS pgrst")
+    vanilla_pg.safe_psql("""
+        CREATE OR REPLACE FUNCTION pgrst.pre_config()
+        RETURNS VOID AS $$
+          SELECT
+              set_config('pgrst.db_schemas', 'test', true)
+            , set_config('pgrst.db_aggregates_enabled', 'true', true)
+            , set_config('pgrst.db_anon_role', 'anon', true)
+            , set_config('pgrst.jwt_aud', '', true)
+            , set_config('pgrst.jwt_secret', '', true)
+            , set_config('pgrst.jwt_role_claim_key', '."role"', true)
+
+        $$ LANGUAGE SQL;
+    """)
+    vanilla_pg.safe_psql("GRANT USAGE ON SCHEMA pgrst TO authenticator")
+    vanilla_pg.safe_psql("GRANT EXECUTE ON ALL FUNCTIONS IN SCHEMA pgrst TO authenticator")
+
+    # Bootstrap the database with test data
+    vanilla_pg.safe_psql("CREATE SCHEMA IF NOT EXISTS test")
+    vanilla_pg.safe_psql("""
+        CREATE TABLE IF NOT EXISTS test.items (
+            id SERIAL PRIMARY KEY,
+            name TEXT NOT NULL
+        )
+    """)
+    vanilla_pg.safe_psql("INSERT INTO test.items (name) VALUES ('test_item')")
+
+    # Grant access to the test schema for the authenticated role
+    vanilla_pg.safe_psql("GRANT USAGE ON SCHEMA test TO authenticated")
+    vanilla_pg.safe_psql("GRANT SELECT ON ALL TABLES IN SCHEMA test TO authenticated")
+
+    # Set up HTTP server to serve JWKS (like static_auth_broker)
+    # Generate public key from the JWK
+    public_key = neon_authorize_jwk.export_public(as_dict=True)
+
+    # Set up the httpserver to serve the JWKS
+    httpserver.expect_request("/.well-known/jwks.json").respond_with_json({"keys": [public_key]})
+
+    # Create JWKS configuration for the rest broker proxy
+    jwks_config = {
+        "jwks": [
+            {
+                "id": "1",
+                "role_names": ["authenticator", "authenticated", "anon"],
+                "jwks_url": httpserver.url_for("/.well-known/jwks.json"),
+                "provider_name": "foo",
+                "jwt_audience": None,
+            }
+        ]
+    }
+
+    # Write the JWKS config to the config file that rest_broker_proxy expects
+    config_file = rest_broker_proxy.config_path
+    with open(config_file, "w") as f:
+        json.dump(jwks_config, f)
+
+    # Write the same config to the local_proxy config file
+    local_config_file = local_proxy.config_path
+    with open(local_config_file, "w") as f:
+        json.dump(jwks_config, f)
+
+    # Signal both proxies to reload their config
+    if rest_broker_proxy._popen is not None:
+        rest_broker_proxy._popen.send_signal(signal.SIGHUP)
+    if local_proxy._popen is not None:
+        local_proxy._popen.send_signal(signal.SIGHUP)
+    # Wait a bit for config to reload
+    time.sleep(0.5)
+
+    # Generate a proper JWT token using the JWK (similar to test_auth_broker.py)
+    token = jwt.JWT(
+        header={"kid": neon_authorize_jwk.key_id, "alg": "RS256"},
+        claims={
+            "sub": "user",
+            "role": "authenticated",  # role that's in role_names
+            "exp": 9999999999,  # expires far in the future
+            "iat": 1000000000,  # issued at
+        },
+    )
+    token.make_signed_token(neon_authorize_jwk)
+
+    return token.serialize()
+
+
+@skip_if_proxy_lacks_rest_broker()
+@pytest.mark.parametrize("rest_broker_proxy_extra_args", [["--rest-graphql-endpoints", "*"]])
+def test_rest_broker_graphql(
+    local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+):
+    """Test GraphQL queries, translated to the same SQL as the REST API."""
+
+    token = setup_rest_broker(
+        local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+    )
+    vanilla_pg.safe_psql("INSERT INTO test.items (name) VALUES ('other_item')")
+
+    url = f"https://foo.apirest.c-2.local.neon.build:{rest_broker_proxy.wss_port}/postgres/rest/graphql/v1"
+    headers = {"Authorization": f"Bearer {token}"}
+
+    response = requests.post(
+        url,
+        headers=headers,
+        json={
+            "query": """
+                query Items($name: String) {
+                  first: items(filter: {name: {eq: $name}}) { id label: name }
+                  all: items(order: {id: desc}, limit: 1) { name }
+                }
+            """,
+            "variables": {"name": "test_item"},
+        },
+        verify=False,
+    )
+    assert response.status_code == 200, response.text
+    assert response.json() == {
+        "data": {
+            "first": [{"id": 1, "label": "test_item"}],
+            "all": [{"name": "other_item"}],
+        }
+    }
+
+    # mutations are not supported
+    response = requests.post(
+        url,
+        headers=headers,
+        json={"query": "mutation { items { id } }"},
+        verify=False,
+    )
+    assert response.status_code == 400
+    assert response.json() == {"errors": [{"message": "mutations are not supported"}]}
+
+
+@skip_if_proxy_lacks_rest_broker()
+def test_rest_broker_openapi(
+    local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+):
+    """Test the OpenAPI description served on the root of the REST API."""
+
+    token = setup_rest_broker(
+        local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+    )
+
+    url = f"https://foo.apirest.c-2.local.neon.build:{rest_broker_proxy.wss_port}/postgres/rest/v1/"
+    response = requests.get(url, headers={"Authorization": f"Bearer {token}"}, verify=False)
+    assert response.status_code == 200, response.text
+    assert response.headers["Content-Type"] == "application/openapi+json"
+
+    document = response.json()
+    assert document["openapi"] == "3.0.3"
+    # the authenticated role can only read the items
+    assert list(document["paths"]["/items"].keys()) == ["get"]
+    assert set(document["components"]["schemas"]["items"]["properties"].keys()) == {
+        "id",
+        "name",
+    }
+
+
+@skip_if_proxy_lacks_rest_broker()
+@pytest.mark.parametrize("rest_broker_proxy_extra_args", [["--rest-query-cache-endpoints", "*"]])
+def test_rest_broker_query_cache(
+    local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+):
+    """Test that reads are cached, and that writes through the proxy purge them."""
+
+    token = setup_rest_broker(
+        local_proxy_fixed_port, rest_broker_proxy, vanilla_pg, neon_authorize_jwk, httpserver
+    )
+    vanilla_pg.safe_psql("GRANT INSERT ON test.items TO authenticated")
+    vanilla_pg.safe_psql("GRANT USAGE ON ALL SEQUENCES IN SCHEMA test TO authenticated")
+
+    url = f"https://foo.apirest.c-2.local.neon.build:{rest_broker_proxy.wss_port}/postgres/rest/v1/items"
+    headers = {"Authorization": f"Bearer {token}"}
+
+    def get(**extra_headers):
+        response = requests.get(
+            url,
+            headers=headers | extra_headers,
+            params={"select": "name", "order": "id"},
+            verify=False,
+        )
+        assert response.status_code == 200, response.text
+        return response
+
+    response = get()
+    assert response.headers["Neon-Query-Cache"] == "miss"
+    response = get()
+    assert response.headers["Neon-Query-Cache"] == "hit"
+    assert response.json() == [{"name": "test_item"}]
+
+    # writes behind the back of the proxy are not seen until the entry expires
+    vanilla_pg.safe_psql("INSERT INTO test.items (name) VALUES ('direct_item')")
+    assert get().json() == [{"name": "test_item"}]
+    # unless the client asks to skip the cache
+    response = get(**{"Cache-Control": "no-cache"})
+    assert response.headers["Neon-Query-Cache"] == "miss"
+    assert response.json() == [{"name": "test_item"}, {"name": "direct_item"}]
+
+    # writes through the proxy purge the cached reads of the endpoint
+    response = requests.post(url, headers=headers, json={"name": "rest_item"}, verify=False)
+    assert response.status_code == 201, response.text
+    response = get()
+    assert response.headers["Neon-Query-Cache"] == "miss"
+    assert response.json() == [
+        {"name": "test_item"},
+        {"name": "direct_item"},
+        {"name": "rest_item"},
+    ]
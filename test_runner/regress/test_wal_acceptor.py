@@ -1,6 +1,8 @@
 from __future__ import annotations
 
 import filecmp
+import hashlib
+import json
 import logging
 import os
 import random
@@ -266,6 +268,35 @@ def test_restarts(neon_env_builder: NeonEnvBuilder):
     assert query_scalar(cur, "SELECT sum(key) FROM t") == (n_inserts * (n_inserts + 1)) // 2
 
 
+# Test that commits go through in every commit mode. In remote_apply they are
+# confirmed only after pageserver ingests WAL, so check the data is visible
+# there right away.
+@pytest.mark.parametrize("commit_mode", ["flush", "write", "remote_apply"])
+def test_safekeeper_commit_modes(neon_env_builder: NeonEnvBuilder, commit_mode: str):
+    neon_env_builder.num_safekeepers = 3
+    env = neon_env_builder.init_start()
+
+    config_lines = [
+        "neon.safekeeper_proto_version = 5",
+        f"neon.safekeeper_commit_mode = {commit_mode}",
+    ]
+    endpoint = env.endpoints.create_start("main", config_lines=config_lines)
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    insert_start_lsn = Lsn(endpoint.safe_psql("SELECT pg_current_wal_insert_lsn()")[0][0])
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,10000), 'payload'")
+
+    if commit_mode == "remote_apply":
+        ps_http = env.pageserver.http_client()
+        last_record_lsn = Lsn(
+            ps_http.timeline_detail(env.initial_tenant, env.initial_timeline)["last_record_lsn"]
+        )
+        # commit of the INSERT was confirmed, so pageserver must have it
+        assert last_record_lsn > insert_start_lsn
+
+    endpoint.stop_and_destroy().create_start("main", config_lines=config_lines)
+    assert endpoint.safe_psql("SELECT sum(key) FROM t")[0][0] == 50005000
+
+
 # Test that safekeepers push their info to the broker and learn peer status from it
 def test_broker(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.num_safekeepers = 3
@@ -830,6 +861,38 @@ class DummyConsumer:
         pass
 
 
+# Test reading WAL ranges via HTTP: raw WAL must match the digest and decoded
+# records must cover the range.
+def test_timeline_wal(neon_env_builder: NeonEnvBuilder):
+    env = neon_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    timeline_id = env.initial_timeline
+
+    endpoint = env.endpoints.create_start("main")
+    endpoint.safe_psql("CREATE TABLE t(key int primary key, value text)")
+    endpoint.safe_psql("INSERT INTO t SELECT generate_series(1,1000), 'payload'")
+    endpoint.stop()
+
+    sk = env.safekeepers[0]
+    http_cli = sk.http_client()
+    start_lsn = sk.get_timeline_start_lsn(tenant_id, timeline_id)
+    flush_lsn = http_cli.timeline_status(tenant_id, timeline_id).flush_lsn
+
+    raw = http_cli.timeline_wal(tenant_id, timeline_id, start_lsn, flush_lsn)
+    assert len(raw) == flush_lsn - start_lsn
+    digest = http_cli.timeline_digest(tenant_id, timeline_id, start_lsn, flush_lsn)
+    assert hashlib.sha256(raw).hexdigest() == digest["sha256"]
+
+    records = http_cli.timeline_wal(tenant_id, timeline_id, start_lsn, format="json")
+    records = [json.loads(line) for line in records.decode().splitlines()]
+    assert len(records) > 1000
+    assert Lsn(records[-1]["end_lsn"]) <= flush_lsn
+
+    # range beyond flush_lsn is refused
+    with pytest.raises(requests.exceptions.HTTPError):
+        http_cli.timeline_wal(tenant_id, timeline_id, start_lsn, flush_lsn + 8)
+
+
 def test_start_replication_term(neon_env_builder: NeonEnvBuilder):
     """
     Test START_REPLICATION of uncommitted part specifying leader term. It must
@@ -1793,12 +1856,12 @@ def test_pull_timeline_gc(neon_env_builder: NeonEnvBuilder):
 # to fetch the log up to <last_log_term, flush_lsn>. This is unsafe if term
 # changes during the procedure (unless timeline is locked all the time but we
 # don't want that): recepient might end up with mix of WAL from different
-# histories. Thus the schedule above is expected to fail. Later we'd allow
-# pull_timeline to only initialize timeline to any valid state (up to
-# commit_lsn), holding switch to fully new configuration until it recovers
-# enough, so it won't be affected by term change anymore.
-#
-# Expected to fail while term check is not implemented.
+# histories. Thus the first snapshot attempt is expected to fail; the retry
+# notices that last_log_term changed, drops what was received and pulls the
+# new history from scratch. Later we'd allow pull_timeline to only initialize
+# timeline to any valid state (up to commit_lsn), holding switch to fully new
+# configuration until it recovers enough, so it won't be affected by term
+# change anymore.
 def test_pull_timeline_term_change(neon_env_builder: NeonEnvBuilder):
     neon_env_builder.auth_enabled = True
     neon_env_builder.num_safekeepers = 3
@@ -1843,8 +1906,11 @@ def test_pull_timeline_term_change(neon_env_builder: NeonEnvBuilder):
     assert term_after > term_before, f"term_after={term_after}, term_before={term_before}"
 
     src_http.configure_failpoints(("sk-snapshot-after-list-pausable", "off"))
-    with pytest.raises(requests.exceptions.HTTPError):
-        pt_handle.join()
+    pt_handle.join()
+
+    progress = dst_sk.http_client().pull_timeline_progress(tenant_id, timeline_id)
+    assert progress["status"] == "finished"
+    assert progress["attempts"] > 1, f"progress={progress}"
 
 
 def test_pull_timeline_while_evicted(neon_env_builder: NeonEnvBuilder):